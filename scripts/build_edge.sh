@@ -0,0 +1,25 @@
+# This script builds the node as a single static binary for the farm edge hardware,
+# and fails if the binary is over the size budget
+# To run it you will need the musl target and, when cross-compiling, its linker (see .cargo/config.toml):
+#       $ rustup target add aarch64-unknown-linux-musl
+# libcurl is always linked statically, but OpenSSL must be built for the same target, e.g.:
+#       $ export OPENSSL_STATIC=1 OPENSSL_DIR=/opt/openssl-aarch64-musl
+# Usage:
+#       $ ./scripts/build_edge.sh [target]     (x86_64-unknown-linux-musl by default)
+set -e
+
+TARGET="${1:-x86_64-unknown-linux-musl}"
+# max size of the binary in bytes, 12 MiB by default
+SIZE_BUDGET="${EDGE_SIZE_BUDGET:-12582912}"
+
+# the gossip network is left out, the edge nodes sync through the peer system
+cargo build --profile edge --no-default-features --target "$TARGET"
+
+BINARY="target/$TARGET/edge/agriblock"
+SIZE=$(wc -c < "$BINARY")
+echo "Built $BINARY: $SIZE bytes (budget of $SIZE_BUDGET bytes)"
+
+if [ "$SIZE" -gt "$SIZE_BUDGET" ]; then
+    echo "The binary is over the size budget"
+    exit 1
+fi
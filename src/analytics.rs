@@ -0,0 +1,318 @@
+mod anomaly_scores;
+mod batch_comparison;
+mod batch_status;
+mod picking;
+mod sensor_trend;
+mod sla_compliance;
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Mutex,
+};
+
+use anyhow::Result;
+use chrono::Utc;
+
+use crate::{
+    model::{
+        transaction_hash, Address, Block, Blockchain, ChainEvent, EventBus, EventSubscription,
+        Transaction, TxHash,
+    },
+    util::{
+        execution::{sleep_millis, Runnable},
+        Context,
+    },
+};
+pub use anomaly_scores::{AnomalyScores, Scores};
+pub use batch_comparison::{compare_batches, BatchComparison, BatchStage, StageComparison};
+pub use batch_status::{batch_status, BatchStatus};
+pub use picking::{picking_suggestions, PickingBasis, PickingSuggestion};
+pub use sensor_trend::{
+    sensor_trend, SensorMetric, SensorTrend, TrendBucket, TrendOptions, ViolationWindow,
+};
+pub use sla_compliance::{compliance_reports, PartnerCompliance, SlaViolation};
+
+// With less recent transactions, the share of each address is not meaningful
+const MIN_TRANSACTIONS_FOR_BURSTS: usize = 10;
+
+// Periodically scores the recent traffic of the chain looking for anomalies
+// (sudden bursts from one address, unusual mixes of event types or random-looking payloads)
+// helping operators to spot compromised devices
+pub struct Analytics {
+    window_ms: u64,
+    alert_score: f64,
+    blockchain: Blockchain,
+    scores: AnomalyScores,
+    recent_traffic: Mutex<RecentTraffic>,
+}
+
+// Transactions of the blocks applied recently, collected from the event bus
+struct RecentTraffic {
+    subscription: EventSubscription,
+    // with the timestamp of their block
+    transactions: Vec<(i64, Transaction)>,
+}
+
+impl RecentTraffic {
+    fn new(event_bus: &EventBus) -> RecentTraffic {
+        RecentTraffic {
+            subscription: event_bus.subscribe(),
+            transactions: Vec::new(),
+        }
+    }
+
+    // Returns the transactions of the blocks mined since a timestamp, forgetting the older ones
+    fn since(&mut self, since_timestamp: i64) -> Vec<Transaction> {
+        for event in self.subscription.drain() {
+            match event {
+                ChainEvent::BlockApplied(block) => self.add(block),
+                // the transactions of the rolled back blocks are not traffic of the chain anymore
+                ChainEvent::ReorgOccurred(reorg) => {
+                    let rolled_back: HashSet<TxHash> = reorg
+                        .rolled_back
+                        .iter()
+                        .flat_map(|block| block.body.transactions.iter())
+                        .map(transaction_hash)
+                        .collect();
+                    self.transactions.retain(|(_, transaction)| {
+                        !rolled_back.contains(&transaction_hash(transaction))
+                    });
+                    reorg.applied.into_iter().for_each(|block| self.add(block));
+                }
+                _ => {}
+            }
+        }
+
+        self.transactions
+            .retain(|(timestamp, _)| *timestamp >= since_timestamp);
+        self.transactions
+            .iter()
+            .map(|(_, transaction)| transaction.clone())
+            .collect()
+    }
+
+    fn add(&mut self, block: Block) {
+        let timestamp = block.header.timestamp;
+        let transactions = block.body.transactions.into_iter();
+        self.transactions
+            .extend(transactions.map(|transaction| (timestamp, transaction)));
+    }
+}
+
+impl Runnable for Analytics {
+    fn run(&self) -> Result<()> {
+        self.start()
+    }
+}
+
+impl Analytics {
+    pub fn new(context: &Context) -> Analytics {
+        Analytics {
+            window_ms: context.config.anomaly_window_ms,
+            alert_score: context.config.anomaly_alert_score,
+            blockchain: context.blockchain.clone(),
+            scores: context.anomaly_scores.clone(),
+            recent_traffic: Mutex::new(RecentTraffic::new(&context.blockchain.event_bus())),
+        }
+    }
+
+    pub fn start(&self) -> Result<()> {
+        if self.window_ms == 0 {
+            info!("Anomaly detection disabled, exiting analytics");
+            return Ok(());
+        }
+
+        info!("start scoring the traffic every {} ms", self.window_ms);
+        loop {
+            sleep_millis(self.window_ms);
+            self.update_scores();
+        }
+    }
+
+    // Scores the transactions of the last window and raises alerts for the suspicious ones
+    fn update_scores(&self) {
+        let since = Utc::now().timestamp_millis() - self.window_ms as i64;
+        let transactions = self.recent_traffic.lock().unwrap().since(since);
+        let historical_types = self.blockchain.get_stats_totals().transactions_by_type;
+
+        let (burst, burst_address) = burst_score(&transactions);
+        let scores = Scores {
+            burst,
+            burst_address: burst_address.map(|address| address.to_string()),
+            event_mix: event_mix_score(&transactions, &historical_types),
+            payload_entropy: payload_entropy_score(&transactions),
+        };
+
+        if scores.burst >= self.alert_score {
+            let address = scores.burst_address.as_deref().unwrap_or_default();
+            warn!("Anomaly alert: burst of transactions from {}", address);
+        }
+        if scores.event_mix >= self.alert_score {
+            warn!("Anomaly alert: unusual mix of event types");
+        }
+        if scores.payload_entropy >= self.alert_score {
+            warn!("Anomaly alert: random-looking transaction payloads");
+        }
+
+        self.scores.set(scores);
+    }
+}
+
+// Share of the transactions sent by the busiest address
+fn burst_score(transactions: &[Transaction]) -> (f64, Option<Address>) {
+    if transactions.len() < MIN_TRANSACTIONS_FOR_BURSTS {
+        return (0.0, None);
+    }
+
+    let mut counts: HashMap<&Address, usize> = HashMap::new();
+    for transaction in transactions.iter() {
+        *counts.entry(&transaction.sender).or_insert(0) += 1;
+    }
+
+    match counts.into_iter().max_by_key(|(_, count)| *count) {
+        Some((address, count)) => {
+            let share = count as f64 / transactions.len() as f64;
+            (share, Some(address.clone()))
+        }
+        None => (0.0, None),
+    }
+}
+
+// Total variation distance between the recent and the historical distributions of event types
+fn event_mix_score(transactions: &[Transaction], historical: &BTreeMap<String, u64>) -> f64 {
+    let historical_total: u64 = historical.values().sum();
+    if transactions.is_empty() || historical_total == 0 {
+        return 0.0;
+    }
+
+    let mut recent: BTreeMap<&str, u64> = BTreeMap::new();
+    for transaction in transactions.iter() {
+        *recent.entry(transaction.event_type.as_str()).or_insert(0) += 1;
+    }
+
+    let recent_total = transactions.len() as f64;
+    let historical_total = historical_total as f64;
+    let mut distance = 0.0;
+    for (event_type, count) in historical.iter() {
+        let recent_count = recent.remove(event_type.as_str()).unwrap_or(0);
+        distance += (recent_count as f64 / recent_total - *count as f64 / historical_total).abs();
+    }
+    // event types that never happened before
+    distance += recent
+        .values()
+        .map(|count| *count as f64 / recent_total)
+        .sum::<f64>();
+
+    distance / 2.0
+}
+
+// Highest Shannon entropy of the payloads, in bits per byte and normalized to the max of 8
+fn payload_entropy_score(transactions: &[Transaction]) -> f64 {
+    transactions
+        .iter()
+        .map(|transaction| shannon_entropy(transaction.data.as_json().as_bytes()) / 8.0)
+        .fold(0.0, f64::max)
+}
+
+fn shannon_entropy(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+
+    let mut frequencies = [0usize; 256];
+    for byte in data {
+        frequencies[*byte as usize] += 1;
+    }
+
+    let length = data.len() as f64;
+    frequencies
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let probability = *count as f64 / length;
+            -probability * probability.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        fixtures::{alice, bob, sign},
+        Block,
+    };
+
+    #[test]
+    fn should_score_bursts_from_one_address() {
+        let mut transactions = vec![create_transaction(bob(), "HARVEST", "{}")];
+        assert_eq!(burst_score(&transactions), (0.0, None));
+
+        for _ in 0..9 {
+            transactions.push(create_transaction(alice(), "HARVEST", "{}"));
+        }
+        assert_eq!(burst_score(&transactions), (0.9, Some(alice())));
+    }
+
+    #[test]
+    fn should_score_unusual_event_mixes() {
+        let historical =
+            BTreeMap::from([("HARVEST".to_string(), 50), ("TRANSPORT".to_string(), 50)]);
+
+        // the same mix as usual
+        let usual = vec![
+            create_transaction(alice(), "HARVEST", "{}"),
+            create_transaction(alice(), "TRANSPORT", "{}"),
+        ];
+        assert_eq!(event_mix_score(&usual, &historical), 0.0);
+
+        // only event types that never happened before
+        let unusual = vec![create_transaction(alice(), "RECALL", "{}")];
+        assert_eq!(event_mix_score(&unusual, &historical), 1.0);
+    }
+
+    #[test]
+    fn should_score_payload_entropy() {
+        assert_eq!(shannon_entropy(b"aaaa"), 0.0);
+        assert_eq!(shannon_entropy(b"abab"), 1.0);
+
+        let all_bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(shannon_entropy(&all_bytes), 8.0);
+
+        let transactions = vec![
+            create_transaction(alice(), "HARVEST", "aaaa"),
+            create_transaction(alice(), "HARVEST", "abab"),
+        ];
+        assert_eq!(payload_entropy_score(&transactions), 1.0 / 8.0);
+    }
+
+    #[test]
+    fn should_update_shared_scores() {
+        let blockchain = Blockchain::new(0);
+        let scores = AnomalyScores::default();
+        let analytics = Analytics {
+            window_ms: 60_000,
+            alert_score: 0.9,
+            blockchain: blockchain.clone(),
+            scores: scores.clone(),
+            recent_traffic: Mutex::new(RecentTraffic::new(&blockchain.event_bus())),
+        };
+
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let transactions = vec![create_transaction(alice(), "HARVEST", "abab")];
+        let block = Block::new(1, 0, previous_hash, transactions);
+        blockchain.add_block(block).unwrap();
+
+        analytics.update_scores();
+        assert_eq!(scores.get().payload_entropy, 1.0 / 8.0);
+    }
+
+    fn create_transaction(sender: Address, event_type: &str, data: &str) -> Transaction {
+        sign(Transaction {
+            sender,
+            data: data.into(),
+            event_type: event_type.into(),
+            ..Default::default()
+        })
+    }
+}
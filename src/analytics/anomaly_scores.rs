@@ -0,0 +1,30 @@
+use std::sync::{Arc, Mutex};
+
+// Latest anomaly scores of the traffic, from 0 (normal) to 1 (highly suspicious)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Scores {
+    // share of the recent transactions sent by the busiest address
+    pub burst: f64,
+    pub burst_address: Option<String>,
+    // how much the recent mix of event types differs from the historical one
+    pub event_mix: f64,
+    // highest entropy of the recent payloads, random data is typical of compromised devices
+    pub payload_entropy: f64,
+}
+
+// Shared between the analytics job, that updates them, and the API, that exposes them
+// Multiple threads can read/write concurrently to the scores
+#[derive(Debug, Clone, Default)]
+pub struct AnomalyScores {
+    scores: Arc<Mutex<Scores>>,
+}
+
+impl AnomalyScores {
+    pub fn get(&self) -> Scores {
+        self.scores.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, scores: Scores) {
+        *self.scores.lock().unwrap() = scores;
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use super::sla_compliance::actor_of;
+use crate::model::{Address, Block, CHUNK_EVENT, DOCUMENT_EVENT};
+
+const QUALITY_CHECK_EVENT: &str = "QUALITY_CHECK";
+
+// Durations of the same stage that differ by less than this share are not reported
+const DURATION_TOLERANCE: f64 = 0.25;
+
+const MILLIS_PER_HOUR: f64 = 3_600_000.0;
+
+// An event of the lifecycle of a batch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct BatchStage {
+    pub event_type: String,
+    #[schema(value_type = String)]
+    pub actor: Address,
+    // timestamp in milliseconds of the block of the event
+    pub at: i64,
+    // time until the next event of the batch, none for the latest one
+    pub duration_ms: Option<i64>,
+    // result, grade and score of the quality checks, e.g. "PASS A 92"
+    pub quality: Option<String>,
+}
+
+// The same stage in both batches, e.g. their second STORAGE, or only in one of them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct StageComparison {
+    pub event_type: String,
+    pub a: Option<BatchStage>,
+    pub b: Option<BatchStage>,
+    // what differs between both, empty if nothing
+    pub differences: Vec<String>,
+}
+
+// Side-by-side lifecycles of two batches, e.g. two shipments of the same harvest where only one spoiled
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct BatchComparison {
+    pub batch_a: String,
+    pub batch_b: String,
+    pub stages: Vec<StageComparison>,
+    // time from the first to the latest event of each batch
+    pub duration_a_ms: i64,
+    pub duration_b_ms: i64,
+    // stages with any difference
+    pub divergences: usize,
+}
+
+// Compares the lifecycles of two batches stage by stage, or returns None if one of them has no events
+// The stages are matched by their type and occurrence, so an extra stage of a batch shows up on its own row
+pub fn compare_batches(blocks: &[Block], batch_a: &str, batch_b: &str) -> Option<BatchComparison> {
+    let stages_a = lifecycle(blocks, batch_a);
+    let stages_b = lifecycle(blocks, batch_b);
+    if stages_a.is_empty() || stages_b.is_empty() {
+        return None;
+    }
+
+    // the rows of A are matched with the stages of B of the same type and occurrence
+    let mut rows: Vec<StageComparison> = Vec::new();
+    let mut keys: Vec<Option<(&str, usize)>> = Vec::new();
+    for (stage, occurrence) in stages_a.iter().zip(occurrences(&stages_a)) {
+        rows.push(StageComparison {
+            event_type: stage.event_type.clone(),
+            a: Some(stage.clone()),
+            b: None,
+            differences: Vec::new(),
+        });
+        keys.push(Some((stage.event_type.as_str(), occurrence)));
+    }
+
+    // the extra stages of B go right after the previous stage of B
+    let mut next_row = 0;
+    for (stage, occurrence) in stages_b.iter().zip(occurrences(&stages_b)) {
+        let key = Some((stage.event_type.as_str(), occurrence));
+        match keys.iter().position(|row_key| *row_key == key) {
+            Some(position) => {
+                rows[position].b = Some(stage.clone());
+                next_row = position + 1;
+            }
+            None => {
+                let row = StageComparison {
+                    event_type: stage.event_type.clone(),
+                    a: None,
+                    b: Some(stage.clone()),
+                    differences: Vec::new(),
+                };
+                rows.insert(next_row, row);
+                keys.insert(next_row, None);
+                next_row += 1;
+            }
+        }
+    }
+
+    for row in rows.iter_mut() {
+        row.differences = differences(row.a.as_ref(), row.b.as_ref());
+    }
+
+    let duration = |stages: &[BatchStage]| stages[stages.len() - 1].at - stages[0].at;
+    Some(BatchComparison {
+        batch_a: batch_a.to_string(),
+        batch_b: batch_b.to_string(),
+        divergences: rows
+            .iter()
+            .filter(|row| !row.differences.is_empty())
+            .count(),
+        stages: rows,
+        duration_a_ms: duration(&stages_a),
+        duration_b_ms: duration(&stages_b),
+    })
+}
+
+// The events of a batch in chain order, without the documents attached to it
+fn lifecycle(blocks: &[Block], batch_id: &str) -> Vec<BatchStage> {
+    let mut stages: Vec<BatchStage> = Vec::new();
+    for block in blocks.iter() {
+        for transaction in block.body.transactions.iter() {
+            let event_type = transaction.event_type.as_str();
+            if transaction.batch_id != batch_id
+                || [DOCUMENT_EVENT, CHUNK_EVENT].contains(&event_type)
+            {
+                continue;
+            }
+
+            if let Some(previous) = stages.last_mut() {
+                previous.duration_ms = Some(block.header.timestamp - previous.at);
+            }
+            let quality = match event_type {
+                QUALITY_CHECK_EVENT => read_quality(&transaction.data.as_json()),
+                _ => None,
+            };
+            stages.push(BatchStage {
+                event_type: event_type.to_string(),
+                actor: actor_of(transaction).clone(),
+                at: block.header.timestamp,
+                duration_ms: None,
+                quality,
+            });
+        }
+    }
+
+    stages
+}
+
+// How many stages of the same type came before each stage, e.g. 1 for the second STORAGE
+fn occurrences(stages: &[BatchStage]) -> Vec<usize> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    stages
+        .iter()
+        .map(|stage| {
+            let count = counts.entry(stage.event_type.as_str()).or_default();
+            *count += 1;
+            *count - 1
+        })
+        .collect()
+}
+
+fn read_quality(data: &str) -> Option<String> {
+    let payload: Value = serde_json::from_str(data).ok()?;
+    let parts: Vec<String> = ["result", "grade", "score"]
+        .iter()
+        .filter_map(|key| match payload.get(key)? {
+            Value::String(text) => Some(text.clone()),
+            Value::Null => None,
+            other => Some(other.to_string()),
+        })
+        .collect();
+
+    match parts.is_empty() {
+        true => None,
+        false => Some(parts.join(" ")),
+    }
+}
+
+fn differences(a: Option<&BatchStage>, b: Option<&BatchStage>) -> Vec<String> {
+    let (a, b) = match (a, b) {
+        (Some(a), Some(b)) => (a, b),
+        (Some(_), None) => return vec!["Only in batch A".to_string()],
+        (None, _) => return vec!["Only in batch B".to_string()],
+    };
+
+    let mut differences = Vec::new();
+    if a.actor != b.actor {
+        differences.push(format!(
+            "Handled by {} in A and by {} in B",
+            a.actor, b.actor
+        ));
+    }
+    if let (Some(duration_a), Some(duration_b)) = (a.duration_ms, b.duration_ms) {
+        let longest = duration_a.max(duration_b) as f64;
+        if longest > 0.0 && (duration_a - duration_b).abs() as f64 / longest > DURATION_TOLERANCE {
+            differences.push(format!(
+                "Took {:.1} hours in A and {:.1} hours in B",
+                duration_a as f64 / MILLIS_PER_HOUR,
+                duration_b as f64 / MILLIS_PER_HOUR
+            ));
+        }
+    }
+    if a.quality != b.quality {
+        let quality = |stage: &BatchStage| stage.quality.clone().unwrap_or("-".to_string());
+        differences.push(format!(
+            "Quality {} in A and {} in B",
+            quality(a),
+            quality(b)
+        ));
+    }
+
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        fixtures::{alice, bob},
+        BlockHash, Transaction,
+    };
+
+    const HOUR: i64 = 3_600_000;
+
+    #[test]
+    fn should_compare_the_lifecycles_of_two_batches() {
+        let farm = alice();
+        let warehouse = bob();
+        let event = |timestamp, sender: &Address, event_type: &str, batch_id: &str, data: &str| {
+            let transaction = Transaction {
+                sender: sender.clone(),
+                recipient: sender.clone(),
+                event_type: event_type.into(),
+                batch_id: batch_id.to_string(),
+                data: data.into(),
+                ..Default::default()
+            };
+            let mut block = Block::new(0, 0, BlockHash::zero(), vec![transaction]);
+            block.header.timestamp = timestamp;
+            block
+        };
+        let blocks = vec![
+            event(0, &farm, "HARVEST", "WHEAT-001-A", "{}"),
+            event(0, &farm, "HARVEST", "WHEAT-001-B", "{}"),
+            // the second shipment waited much longer at the farm, in another warehouse
+            event(2 * HOUR, &farm, "TRANSPORT", "WHEAT-001-A", "{}"),
+            event(10 * HOUR, &farm, "TRANSPORT", "WHEAT-001-B", "{}"),
+            event(4 * HOUR, &warehouse, "STORAGE", "WHEAT-001-A", "{}"),
+            event(12 * HOUR, &farm, "STORAGE", "WHEAT-001-B", "{}"),
+            event(12 * HOUR, &farm, "DOCUMENT", "WHEAT-001-B", "{}"),
+            event(13 * HOUR, &farm, "STORAGE", "WHEAT-001-B", "{}"),
+            event(
+                14 * HOUR,
+                &warehouse,
+                "QUALITY_CHECK",
+                "WHEAT-001-A",
+                r#"{"result": "PASS", "grade": "A"}"#,
+            ),
+            event(
+                15 * HOUR,
+                &farm,
+                "QUALITY_CHECK",
+                "WHEAT-001-B",
+                r#"{"result": "FAIL", "score": 41}"#,
+            ),
+        ];
+
+        let comparison = compare_batches(&blocks, "WHEAT-001-A", "WHEAT-001-B").unwrap();
+        let rows: Vec<(&str, bool, bool)> = comparison
+            .stages
+            .iter()
+            .map(|row| (row.event_type.as_str(), row.a.is_some(), row.b.is_some()))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("HARVEST", true, true),
+                ("TRANSPORT", true, true),
+                ("STORAGE", true, true),
+                ("STORAGE", false, true),
+                ("QUALITY_CHECK", true, true),
+            ]
+        );
+
+        assert_eq!(
+            comparison.stages[0].differences,
+            vec!["Took 2.0 hours in A and 10.0 hours in B"]
+        );
+        assert_eq!(comparison.stages[2].differences.len(), 2);
+        assert_eq!(comparison.stages[3].differences, vec!["Only in batch B"]);
+        assert_eq!(
+            comparison.stages[4].differences.last().unwrap(),
+            "Quality PASS A in A and FAIL 41 in B"
+        );
+        assert_eq!(comparison.divergences, 4);
+        assert_eq!(
+            (comparison.duration_a_ms, comparison.duration_b_ms),
+            (14 * HOUR, 15 * HOUR)
+        );
+
+        assert!(compare_batches(&blocks, "WHEAT-001-A", "RICE-001").is_none());
+    }
+}
@@ -0,0 +1,282 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use super::sla_compliance::{actor_of, compliance_reports};
+use crate::model::{
+    Address, Block, Escrow, QuantityLedger, CHUNK_EVENT, DOCUMENT_EVENT, ESCROW_EVENT,
+    PLANNED_EVENT,
+};
+
+// A partner raises a dispute about a batch (e.g. damaged goods), and any party resolves it later
+const DISPUTE_EVENT: &str = "DISPUTE";
+const DISPUTE_RESOLVED_EVENT: &str = "DISPUTE_RESOLVED";
+
+// Event that moves the custody of a batch to its recipient
+const TRANSFER_EVENT: &str = "TRANSPORT";
+
+const DAY_MS: i64 = 86_400_000;
+
+// Where a batch is now, summarized from all its events, so clients don't need its full history
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct BatchStatus {
+    pub batch_id: String,
+    // type of the latest event that moved the batch forward (e.g. "PROCESSING")
+    pub stage: String,
+    // actor holding the batch
+    #[schema(value_type = String)]
+    pub custodian: Address,
+    // latest quantity reported in the payloads, as reported (e.g. "450kg")
+    #[schema(value_type = Option<Object>)]
+    pub quantity: Option<Value>,
+    // kilograms left of the batch, after what went to its children, its merges and its sales
+    pub remaining_kg: Option<f64>,
+    // certifications listed in any of the payloads, in order of appearance
+    pub certifications: Vec<String>,
+    pub open_disputes: u64,
+    // reasons of the SLA violations in the handoffs of the batch,
+    // and of the events that moved more than what was left of it
+    pub violations: Vec<String>,
+    pub events: u64,
+    // timestamp in milliseconds of the block of the latest event
+    pub last_event_at: i64,
+    // timestamp in milliseconds of the block where the current custodian got the batch
+    pub custody_since: i64,
+    // end of the shelf life, from the latest "shelf_life_days" of the payloads counted from the first event
+    pub expires_at: Option<i64>,
+}
+
+// Summarizes the events of a batch, or returns None if the batch has no events
+pub fn batch_status(blocks: &[Block], batch_id: &str) -> Option<BatchStatus> {
+    let mut status = summarize_batch(blocks, batch_id)?;
+    status.violations = compliance_reports(blocks)
+        .into_iter()
+        .flat_map(|report| report.violations)
+        .filter(|violation| violation.batch_id == batch_id)
+        .flat_map(|violation| violation.reasons)
+        .collect();
+    let (violations, quantities) = quantity_violations(blocks, batch_id);
+    status.violations.extend(violations);
+    status.remaining_kg = quantities.balance_of(batch_id).remaining_kg();
+
+    Some(status)
+}
+
+// The events of a batch accepted while the quantities were only flagged, that moved more than what was left of it,
+// and the balances of all the batches after the chain
+fn quantity_violations(blocks: &[Block], batch_id: &str) -> (Vec<String>, QuantityLedger) {
+    let mut quantities = QuantityLedger::default();
+    let mut violations = Vec::new();
+    for block in blocks.iter() {
+        for transaction in block.body.transactions.iter() {
+            if transaction.batch_id == batch_id {
+                if let Err(error) = quantities.check(transaction) {
+                    violations.push(format!("Block {}: {}", block.header.index, error));
+                }
+            }
+            quantities.apply(transaction);
+        }
+    }
+
+    (violations, quantities)
+}
+
+// Same as the status, without the SLA violations that need to go through all the handoffs of the chain
+pub(super) fn summarize_batch(blocks: &[Block], batch_id: &str) -> Option<BatchStatus> {
+    let mut status: Option<BatchStatus> = None;
+    let mut first_event_at = 0;
+    // the custody only goes to the recipient of an escrow when it's released
+    let mut escrow: Option<(Escrow, Address)> = None;
+
+    for block in blocks.iter() {
+        for transaction in block.body.transactions.iter() {
+            // the planned events are not facts about the batch
+            if transaction.batch_id != batch_id || transaction.event_type == PLANNED_EVENT {
+                continue;
+            }
+
+            let actor = actor_of(transaction);
+            let status = status.get_or_insert_with(|| BatchStatus {
+                batch_id: batch_id.to_string(),
+                stage: transaction.event_type.to_string(),
+                custodian: actor.clone(),
+                quantity: None,
+                remaining_kg: None,
+                certifications: Vec::new(),
+                open_disputes: 0,
+                violations: Vec::new(),
+                events: 0,
+                last_event_at: block.header.timestamp,
+                custody_since: block.header.timestamp,
+                expires_at: None,
+            });
+            if status.events == 0 {
+                first_event_at = block.header.timestamp;
+            }
+            status.events += 1;
+            status.last_event_at = block.header.timestamp;
+            let previous_custodian = status.custodian.clone();
+
+            let releases_escrow = escrow.as_ref().is_some_and(|(escrow, _)| {
+                escrow.is_released_by(transaction, block.header.timestamp)
+            });
+            match transaction.event_type.as_str() {
+                _ if releases_escrow => {
+                    status.stage = transaction.event_type.to_string();
+                    status.custodian = escrow.take().unwrap().1;
+                }
+                ESCROW_EVENT => {
+                    status.stage = transaction.event_type.to_string();
+                    escrow = Escrow::parse(&transaction.data.as_json())
+                        .ok()
+                        .map(|parsed| (parsed, transaction.recipient.clone()));
+                }
+                DISPUTE_EVENT => status.open_disputes += 1,
+                DISPUTE_RESOLVED_EVENT => {
+                    status.open_disputes = status.open_disputes.saturating_sub(1)
+                }
+                // documents are attached to the batch, but don't move it
+                DOCUMENT_EVENT | CHUNK_EVENT => {}
+                TRANSFER_EVENT => {
+                    status.stage = transaction.event_type.to_string();
+                    status.custodian = transaction.recipient.clone();
+                }
+                event_type => {
+                    status.stage = event_type.to_string();
+                    status.custodian = actor.clone();
+                }
+            }
+
+            if status.custodian != previous_custodian {
+                status.custody_since = block.header.timestamp;
+            }
+
+            let payload: Value =
+                serde_json::from_str(&transaction.data.as_json()).unwrap_or(Value::Null);
+            if let Some(days) = payload.get("shelf_life_days").and_then(Value::as_f64) {
+                status.expires_at = Some(first_event_at + (days * DAY_MS as f64) as i64);
+            }
+            if let Some(quantity) = payload.get("quantity").filter(|value| !value.is_null()) {
+                status.quantity = Some(quantity.clone());
+            } else if let Some(kg) = payload.get("quantity_kg").and_then(Value::as_f64) {
+                // typed harvests report the amount without the unit
+                status.quantity = Some(Value::String(format!("{}kg", kg)));
+            }
+            let certifications = payload
+                .get("certifications")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str);
+            for certification in certifications {
+                if !status.certifications.iter().any(|c| c == certification) {
+                    status.certifications.push(certification.to_string());
+                }
+            }
+        }
+    }
+
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        fixtures::{alice, bob},
+        BlockHash, Transaction, SLA_EVENT,
+    };
+
+    #[test]
+    fn should_summarize_the_events_of_a_batch() {
+        let farm = alice();
+        let warehouse = bob();
+        let blocks = vec![
+            create_block(
+                0,
+                vec![create_transaction(
+                    &farm,
+                    &warehouse,
+                    SLA_EVENT,
+                    "",
+                    r#"{"max_transit_hours": 1}"#,
+                )],
+            ),
+            create_block(
+                1_000,
+                vec![create_transaction(
+                    &farm,
+                    &farm,
+                    "HARVEST",
+                    "WHEAT-001",
+                    r#"{"quantity": "500kg", "quantity_kg": 500, "certifications": ["EU-Organic"], "shelf_life_days": 2}"#,
+                )],
+            ),
+            create_block(
+                2_000,
+                vec![create_transaction(
+                    &farm,
+                    &warehouse,
+                    "TRANSPORT",
+                    "WHEAT-001",
+                    r#"{"quantity_kg": 600}"#,
+                )],
+            ),
+            // the transit took more than an hour
+            create_block(
+                2_000 + 7_200_000,
+                vec![
+                    create_transaction(
+                        &warehouse,
+                        &warehouse,
+                        "STORAGE",
+                        "WHEAT-001",
+                        r#"{"quantity": "480kg", "certifications": ["EU-Organic", "GlobalG.A.P."]}"#,
+                    ),
+                    create_transaction(&warehouse, &farm, DISPUTE_EVENT, "WHEAT-001", "{}"),
+                    create_transaction(&warehouse, &farm, "HARVEST", "CORN-001", "{}"),
+                ],
+            ),
+        ];
+
+        let status = batch_status(&blocks, "WHEAT-001").unwrap();
+        assert_eq!(status.stage, "STORAGE");
+        assert_eq!(status.custodian, warehouse);
+        assert_eq!(status.quantity, Some(Value::String("480kg".to_string())));
+        assert_eq!(status.remaining_kg, Some(500.0));
+        assert_eq!(status.certifications, vec!["EU-Organic", "GlobalG.A.P."]);
+        assert_eq!(status.open_disputes, 1);
+        // the SLA of the transit, and the transport of more than the harvest
+        assert_eq!(status.violations.len(), 2);
+        assert!(status.violations[1].starts_with("Block 0: The event moves 600kg"));
+        assert_eq!(status.events, 4);
+        assert_eq!(status.last_event_at, 2_000 + 7_200_000);
+        assert_eq!(status.custody_since, 2_000);
+        assert_eq!(status.expires_at, Some(1_000 + 2 * DAY_MS));
+
+        assert_eq!(batch_status(&blocks, "RICE-001"), None);
+    }
+
+    fn create_block(timestamp: i64, transactions: Vec<Transaction>) -> Block {
+        let mut block = Block::new(0, 0, BlockHash::zero(), transactions);
+        block.header.timestamp = timestamp;
+        block
+    }
+
+    fn create_transaction(
+        sender: &Address,
+        recipient: &Address,
+        event_type: &str,
+        batch_id: &str,
+        data: &str,
+    ) -> Transaction {
+        Transaction {
+            sender: sender.clone(),
+            recipient: recipient.clone(),
+            event_type: event_type.into(),
+            batch_id: batch_id.to_string(),
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+}
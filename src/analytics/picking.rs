@@ -0,0 +1,162 @@
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use super::{batch_status::summarize_batch, sla_compliance::actor_of};
+use crate::model::{Address, Block};
+
+// Why a batch is ranked where it is: first expired first out, or first in first out without a shelf life
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PickingBasis {
+    Fefo,
+    Fifo,
+}
+
+// A batch in the custody of an actor, in the order it should be shipped
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct PickingSuggestion {
+    pub rank: usize,
+    pub batch_id: String,
+    pub stage: String,
+    #[schema(value_type = Option<Object>)]
+    pub quantity: Option<Value>,
+    // timestamp in milliseconds of the block where the actor got the batch
+    pub custody_since: i64,
+    pub expires_at: Option<i64>,
+    pub basis: PickingBasis,
+}
+
+// Ranks the batches held by an actor (e.g. a warehouse) to ship the ones that expire first,
+// and then the ones without a shelf life that it holds for the longest time
+// Only depends on the chain, so the ages are left to the clients, which know what time it is
+pub fn picking_suggestions(blocks: &[Block], custodian: &Address) -> Vec<PickingSuggestion> {
+    // only the batches that the actor took part in can be in its custody
+    let mut batch_ids: Vec<&str> = Vec::new();
+    let transactions = blocks
+        .iter()
+        .flat_map(|block| block.body.transactions.iter());
+    for transaction in transactions {
+        let involved = actor_of(transaction) == custodian || transaction.recipient == *custodian;
+        if involved && !batch_ids.contains(&transaction.batch_id.as_str()) {
+            batch_ids.push(&transaction.batch_id);
+        }
+    }
+
+    let mut held: Vec<_> = batch_ids
+        .into_iter()
+        .filter_map(|batch_id| summarize_batch(blocks, batch_id))
+        .filter(|status| status.custodian == *custodian)
+        .collect();
+    held.sort_by(|a, b| {
+        let by_expiry = match (a.expires_at, b.expires_at) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        };
+        by_expiry
+            .then(a.custody_since.cmp(&b.custody_since))
+            .then(a.batch_id.cmp(&b.batch_id))
+    });
+
+    held.into_iter()
+        .enumerate()
+        .map(|(position, status)| PickingSuggestion {
+            rank: position + 1,
+            basis: match status.expires_at {
+                Some(_) => PickingBasis::Fefo,
+                None => PickingBasis::Fifo,
+            },
+            batch_id: status.batch_id,
+            stage: status.stage,
+            quantity: status.quantity,
+            custody_since: status.custody_since,
+            expires_at: status.expires_at,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        fixtures::{alice, bob},
+        BlockHash, Transaction,
+    };
+
+    #[test]
+    fn should_rank_the_batches_by_expiry_and_then_by_age() {
+        let farm = alice();
+        let warehouse = bob();
+        let mut transactions = Vec::new();
+        // (batch, shelf life of the harvest, time of the transport to the warehouse)
+        for (batch_id, shelf_life, received_at) in [
+            ("APPLES-001", "", 3_000),
+            ("MILK-001", r#", "shelf_life_days": 7"#, 4_000),
+            ("WHEAT-001", "", 1_000),
+            ("BERRIES-001", r#", "shelf_life_days": 3"#, 5_000),
+            ("CORN-001", "", 2_000),
+        ] {
+            let harvest = format!(r#"{{"quantity": "10kg"{}}}"#, shelf_life);
+            transactions.push((
+                0,
+                create_transaction(&farm, &farm, "HARVEST", batch_id, &harvest),
+            ));
+            let transport = create_transaction(&farm, &warehouse, "TRANSPORT", batch_id, "{}");
+            transactions.push((received_at, transport));
+        }
+        // the corn already left the warehouse
+        let shipment = create_transaction(&warehouse, &farm, "TRANSPORT", "CORN-001", "{}");
+        transactions.push((6_000, shipment));
+
+        let blocks: Vec<Block> = transactions
+            .into_iter()
+            .map(|(timestamp, transaction)| {
+                let mut block = Block::new(0, 0, BlockHash::zero(), vec![transaction]);
+                block.header.timestamp = timestamp;
+                block
+            })
+            .collect();
+
+        let suggestions = picking_suggestions(&blocks, &warehouse);
+        let ranked: Vec<(&str, PickingBasis)> = suggestions
+            .iter()
+            .map(|suggestion| (suggestion.batch_id.as_str(), suggestion.basis))
+            .collect();
+        assert_eq!(
+            ranked,
+            vec![
+                ("BERRIES-001", PickingBasis::Fefo),
+                ("MILK-001", PickingBasis::Fefo),
+                ("WHEAT-001", PickingBasis::Fifo),
+                ("APPLES-001", PickingBasis::Fifo),
+            ]
+        );
+        assert_eq!(suggestions[0].rank, 1);
+        assert_eq!(suggestions[0].expires_at, Some(3 * 86_400_000));
+        assert_eq!(suggestions[2].custody_since, 1_000);
+
+        // the farm holds the corn again
+        assert_eq!(picking_suggestions(&blocks, &farm)[0].batch_id, "CORN-001");
+    }
+
+    fn create_transaction(
+        sender: &Address,
+        recipient: &Address,
+        event_type: &str,
+        batch_id: &str,
+        data: &str,
+    ) -> Transaction {
+        Transaction {
+            sender: sender.clone(),
+            recipient: recipient.clone(),
+            data: data.into(),
+            batch_id: batch_id.to_string(),
+            event_type: event_type.into(),
+            ..Default::default()
+        }
+    }
+}
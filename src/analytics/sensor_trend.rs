@@ -0,0 +1,350 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use super::sla_compliance::read_measure;
+use crate::model::{Block, SensorReading, SensorReadings, SENSOR_READINGS_EVENT};
+
+// Event of a sensor attached to a batch, e.g. {"temperature": 4.2, "humidity": "81%"}
+// With "recorded_at" (timestamp in milliseconds) when the readings are sent in bulk after being taken
+pub const SENSOR_READING_EVENT: &str = "SENSOR_READING";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SensorMetric {
+    Temperature,
+    Humidity,
+}
+
+impl SensorMetric {
+    // Field of the payload of the readings
+    pub fn as_str(&self) -> &str {
+        match self {
+            SensorMetric::Temperature => "temperature",
+            SensorMetric::Humidity => "humidity",
+        }
+    }
+
+    // The measure of a reading sent in bulk
+    pub fn of(&self, reading: &SensorReading) -> Option<f64> {
+        match self {
+            SensorMetric::Temperature => reading.temperature,
+            SensorMetric::Humidity => reading.humidity,
+        }
+    }
+}
+
+impl FromStr for SensorMetric {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "temperature" => Ok(SensorMetric::Temperature),
+            "humidity" => Ok(SensorMetric::Humidity),
+            _ => Err(()),
+        }
+    }
+}
+
+// How to summarize the readings: the size of the buckets and the acceptable range, if any
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrendOptions {
+    pub metric: SensorMetric,
+    pub bucket_ms: i64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+// The readings taken in an interval of time, e.g. an hour
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TrendBucket {
+    // timestamp in milliseconds, a multiple of the size of the buckets
+    pub start: i64,
+    pub readings: usize,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+}
+
+// Consecutive readings out of the acceptable range
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ViolationWindow {
+    // timestamps in milliseconds of the first and the last readings out of range
+    pub start: i64,
+    pub end: i64,
+    pub readings: usize,
+    // the reading furthest from the range
+    pub peak: f64,
+}
+
+// Summary of the readings of a sensor for a batch, small enough for a dashboard that draws a trend line
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SensorTrend {
+    pub batch_id: String,
+    pub metric: String,
+    pub bucket_ms: i64,
+    pub readings: usize,
+    pub buckets: Vec<TrendBucket>,
+    pub violations: Vec<ViolationWindow>,
+}
+
+// Summarizes the readings of a batch per bucket of time, the oldest first, or returns None without readings
+pub fn sensor_trend(
+    blocks: &[Block],
+    batch_id: &str,
+    options: &TrendOptions,
+) -> Option<SensorTrend> {
+    let readings = readings_of(blocks, batch_id, options.metric);
+    if readings.is_empty() {
+        return None;
+    }
+
+    let mut buckets: Vec<TrendBucket> = Vec::new();
+    for (at, value) in readings.iter() {
+        let start = at - at.rem_euclid(options.bucket_ms);
+        match buckets.last_mut() {
+            Some(bucket) if bucket.start == start => {
+                // the sum is kept in the average until the bucket is complete
+                bucket.readings += 1;
+                bucket.min = bucket.min.min(*value);
+                bucket.max = bucket.max.max(*value);
+                bucket.avg += value;
+            }
+            _ => buckets.push(TrendBucket {
+                start,
+                readings: 1,
+                min: *value,
+                max: *value,
+                avg: *value,
+            }),
+        }
+    }
+    for bucket in buckets.iter_mut() {
+        bucket.avg /= bucket.readings as f64;
+    }
+
+    Some(SensorTrend {
+        batch_id: batch_id.to_string(),
+        metric: options.metric.as_str().to_string(),
+        bucket_ms: options.bucket_ms,
+        readings: readings.len(),
+        buckets,
+        violations: violation_windows(&readings, options),
+    })
+}
+
+// The readings of a metric for a batch as (timestamp, value), sorted by time
+fn readings_of(blocks: &[Block], batch_id: &str, metric: SensorMetric) -> Vec<(i64, f64)> {
+    let mut readings = Vec::new();
+    for block in blocks.iter() {
+        let events = block.body.transactions.iter().filter(|transaction| {
+            transaction.batch_id == batch_id && transaction.event_type == SENSOR_READINGS_EVENT
+        });
+        for transaction in events {
+            if let Ok(bulk) = SensorReadings::parse(&transaction.data.as_json()) {
+                readings.extend(bulk.readings.iter().filter_map(|reading| {
+                    metric.of(reading).map(|value| (reading.recorded_at, value))
+                }));
+            }
+        }
+
+        let events = block.body.transactions.iter().filter(|transaction| {
+            transaction.batch_id == batch_id && transaction.event_type == SENSOR_READING_EVENT
+        });
+        for transaction in events {
+            let payload: Value = match serde_json::from_str(&transaction.data.as_json()) {
+                Ok(payload) => payload,
+                Err(_) => continue,
+            };
+            if let Some(value) = read_measure(&payload, metric.as_str()) {
+                let at = payload
+                    .get("recorded_at")
+                    .and_then(Value::as_i64)
+                    .unwrap_or(block.header.timestamp);
+                readings.push((at, value));
+            }
+        }
+    }
+
+    // stable, so readings with the same timestamp keep their chain order
+    readings.sort_by_key(|(at, _)| *at);
+    readings
+}
+
+fn violation_windows(readings: &[(i64, f64)], options: &TrendOptions) -> Vec<ViolationWindow> {
+    let min = options.min.unwrap_or(f64::MIN);
+    let max = options.max.unwrap_or(f64::MAX);
+    let distance = |value: f64| (min - value).max(value - max);
+
+    let mut windows: Vec<ViolationWindow> = Vec::new();
+    let mut in_window = false;
+    for (at, value) in readings.iter() {
+        if distance(*value) <= 0.0 {
+            in_window = false;
+            continue;
+        }
+
+        match windows.last_mut() {
+            Some(window) if in_window => {
+                window.end = *at;
+                window.readings += 1;
+                if distance(*value) > distance(window.peak) {
+                    window.peak = *value;
+                }
+            }
+            _ => windows.push(ViolationWindow {
+                start: *at,
+                end: *at,
+                readings: 1,
+                peak: *value,
+            }),
+        }
+        in_window = true;
+    }
+
+    windows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{fixtures::alice, BlockHash, Transaction};
+
+    const MINUTE: i64 = 60_000;
+    const HOUR: i64 = 60 * MINUTE;
+
+    #[test]
+    fn should_summarize_the_readings_per_hour() {
+        // a reading every 20 minutes for two hours, in one block sent after the trip
+        let temperatures = [4.0, 5.0, 6.0, 3.0, 3.5, 4.0];
+        let transactions = temperatures
+            .iter()
+            .enumerate()
+            .map(|(position, temperature)| {
+                let data = format!(
+                    r#"{{"temperature": {}, "recorded_at": {}}}"#,
+                    temperature,
+                    position as i64 * 20 * MINUTE
+                );
+                create_reading("WHEAT-1", &data)
+            })
+            .collect();
+        let blocks = vec![create_block(3 * HOUR, transactions)];
+
+        let options = create_options(SensorMetric::Temperature, None, None);
+        let trend = sensor_trend(&blocks, "WHEAT-1", &options).unwrap();
+        assert_eq!(trend.readings, 6);
+        assert_eq!(
+            trend.buckets,
+            vec![
+                TrendBucket {
+                    start: 0,
+                    readings: 3,
+                    min: 4.0,
+                    max: 6.0,
+                    avg: 5.0
+                },
+                TrendBucket {
+                    start: HOUR,
+                    readings: 3,
+                    min: 3.0,
+                    max: 4.0,
+                    avg: 3.5
+                },
+            ]
+        );
+        assert!(trend.violations.is_empty());
+
+        assert!(sensor_trend(&blocks, "WHEAT-2", &options).is_none());
+        let humidity = create_options(SensorMetric::Humidity, None, None);
+        assert!(sensor_trend(&blocks, "WHEAT-1", &humidity).is_none());
+    }
+
+    #[test]
+    fn should_find_the_windows_out_of_range() {
+        // the readings without "recorded_at" are taken when they were mined
+        let blocks: Vec<Block> = ["4C", "9C", "11C", "7C", "-1C", "5C"]
+            .iter()
+            .enumerate()
+            .map(|(position, temperature)| {
+                let data = format!(r#"{{"temperature": "{}"}}"#, temperature);
+                create_block(
+                    position as i64 * MINUTE,
+                    vec![create_reading("WHEAT-1", &data)],
+                )
+            })
+            .collect();
+
+        let options = create_options(SensorMetric::Temperature, Some(2.0), Some(8.0));
+        let trend = sensor_trend(&blocks, "WHEAT-1", &options).unwrap();
+        assert_eq!(trend.buckets.len(), 1);
+        assert_eq!(
+            trend.violations,
+            vec![
+                ViolationWindow {
+                    start: MINUTE,
+                    end: 2 * MINUTE,
+                    readings: 2,
+                    peak: 11.0
+                },
+                ViolationWindow {
+                    start: 4 * MINUTE,
+                    end: 4 * MINUTE,
+                    readings: 1,
+                    peak: -1.0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn should_include_the_readings_sent_in_bulk() {
+        let bulk = Transaction {
+            event_type: SENSOR_READINGS_EVENT.into(),
+            ..create_reading(
+                "WHEAT-1",
+                r#"{"readings": [
+                    {"device_id": "TRUCK-7", "recorded_at": 0, "temperature": 3.0},
+                    {"device_id": "TRUCK-7", "recorded_at": 120000, "humidity": 80},
+                    {"device_id": "TRUCK-7", "recorded_at": 240000, "temperature": 5.0}
+                ]}"#,
+            )
+        };
+        let single = create_reading("WHEAT-1", r#"{"temperature": 10.0, "recorded_at": 60000}"#);
+        let blocks = vec![create_block(HOUR, vec![bulk, single])];
+
+        // sorted with the other readings, and only those that take the measure
+        let options = create_options(SensorMetric::Temperature, None, Some(8.0));
+        let trend = sensor_trend(&blocks, "WHEAT-1", &options).unwrap();
+        assert_eq!(trend.readings, 3);
+        assert_eq!(trend.buckets[0].avg, 6.0);
+        assert_eq!(trend.violations[0].start, MINUTE);
+    }
+
+    fn create_options(metric: SensorMetric, min: Option<f64>, max: Option<f64>) -> TrendOptions {
+        TrendOptions {
+            metric,
+            bucket_ms: HOUR,
+            min,
+            max,
+        }
+    }
+
+    fn create_reading(batch_id: &str, data: &str) -> Transaction {
+        Transaction {
+            sender: alice(),
+            recipient: alice(),
+            data: data.into(),
+            batch_id: batch_id.to_string(),
+            event_type: SENSOR_READING_EVENT.into(),
+            ..Default::default()
+        }
+    }
+
+    fn create_block(timestamp: i64, transactions: Vec<Transaction>) -> Block {
+        let mut block = Block::new(1, 0, BlockHash::zero(), transactions);
+        block.header.timestamp = timestamp;
+        block
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::model::{
+    Address, Block, SensorReadings, Sla, Transaction, SENSOR_READINGS_EVENT, SLA_EVENT,
+};
+
+// Event that starts a handoff: the sender dispatches the batch to the recipient
+const DISPATCH_EVENT: &str = "TRANSPORT";
+
+const MILLIS_PER_HOUR: f64 = 3_600_000.0;
+
+// A handoff that did not comply with the SLA of the partners
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct SlaViolation {
+    pub batch_id: String,
+    pub reasons: Vec<String>,
+}
+
+// How a shipper complied with the SLA agreed with a receiver, over all their completed handoffs
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct PartnerCompliance {
+    #[schema(value_type = String)]
+    pub shipper: Address,
+    #[schema(value_type = String)]
+    pub receiver: Address,
+    pub handoffs: u64,
+    // fraction of compliant handoffs, from 0 to 1
+    pub compliance: f64,
+    pub violations: Vec<SlaViolation>,
+}
+
+// A batch dispatched that the receiver has not acted upon yet
+struct OpenHandoff {
+    shipper: Address,
+    receiver: Address,
+    dispatched_at: i64,
+    // the terms in force when the batch was dispatched
+    sla: Option<Sla>,
+    temperatures: Vec<f64>,
+}
+
+// Scores each completed handoff of the chain against the SLA of the partners
+// A handoff starts with a TRANSPORT event to another actor and completes with the next event of the
+// receiver for the same batch, the temperatures reported in the batch events in between are checked too
+pub fn compliance_reports(blocks: &[Block]) -> Vec<PartnerCompliance> {
+    let mut slas: HashMap<(Address, Address), Sla> = HashMap::new();
+    let mut open_handoffs: HashMap<String, OpenHandoff> = HashMap::new();
+    // keyed by the text of the addresses, to return the reports in a stable order
+    let mut reports: BTreeMap<(String, String), PartnerCompliance> = BTreeMap::new();
+
+    for block in blocks.iter() {
+        for transaction in block.body.transactions.iter() {
+            let actor = actor_of(transaction);
+
+            if transaction.event_type == SLA_EVENT {
+                if let Ok(sla) = Sla::parse(&transaction.data.as_json()) {
+                    slas.insert((actor.clone(), transaction.recipient.clone()), sla);
+                }
+                continue;
+            }
+
+            let temperatures = read_temperatures(transaction);
+            let completes_handoff = open_handoffs
+                .get(&transaction.batch_id)
+                .is_some_and(|handoff| handoff.receiver == *actor);
+            if completes_handoff {
+                let mut handoff = open_handoffs.remove(&transaction.batch_id).unwrap();
+                handoff.temperatures.extend(temperatures.iter());
+                if let Some(sla) = &handoff.sla {
+                    let transit_hours =
+                        (block.header.timestamp - handoff.dispatched_at) as f64 / MILLIS_PER_HOUR;
+                    let reasons = sla.check(transit_hours, &handoff.temperatures);
+                    record_handoff(&mut reports, &handoff, &transaction.batch_id, reasons);
+                }
+            } else if let Some(handoff) = open_handoffs.get_mut(&transaction.batch_id) {
+                // e.g. readings of a sensor in the truck
+                handoff.temperatures.extend(temperatures.iter());
+            }
+
+            if transaction.event_type == DISPATCH_EVENT && transaction.recipient != *actor {
+                let key = (actor.clone(), transaction.recipient.clone());
+                let handoff = OpenHandoff {
+                    sla: slas.get(&key).cloned(),
+                    shipper: key.0,
+                    receiver: key.1,
+                    dispatched_at: block.header.timestamp,
+                    temperatures,
+                };
+                open_handoffs.insert(transaction.batch_id.clone(), handoff);
+            }
+        }
+    }
+
+    reports.into_values().collect()
+}
+
+fn record_handoff(
+    reports: &mut BTreeMap<(String, String), PartnerCompliance>,
+    handoff: &OpenHandoff,
+    batch_id: &str,
+    reasons: Vec<String>,
+) {
+    let key = (handoff.shipper.to_string(), handoff.receiver.to_string());
+    let report = reports.entry(key).or_insert_with(|| PartnerCompliance {
+        shipper: handoff.shipper.clone(),
+        receiver: handoff.receiver.clone(),
+        handoffs: 0,
+        compliance: 0.0,
+        violations: Vec::new(),
+    });
+
+    report.handoffs += 1;
+    if !reasons.is_empty() {
+        report.violations.push(SlaViolation {
+            batch_id: batch_id.to_string(),
+            reasons,
+        });
+    }
+    let compliant = report.handoffs - report.violations.len() as u64;
+    report.compliance = compliant as f64 / report.handoffs as f64;
+}
+
+// Gateways act for other actors, the handoff is between the actors
+pub(super) fn actor_of(transaction: &Transaction) -> &Address {
+    transaction
+        .on_behalf_of
+        .as_ref()
+        .unwrap_or(&transaction.sender)
+}
+
+// Reads the "temperature" of a payload, as a number or a text like "4C" or "-18 C",
+// or all the ones of the readings sent in bulk
+fn read_temperatures(transaction: &Transaction) -> Vec<f64> {
+    let data = transaction.data.as_json();
+    if transaction.event_type == SENSOR_READINGS_EVENT {
+        return SensorReadings::parse(&data)
+            .map(|bulk| {
+                bulk.readings
+                    .iter()
+                    .filter_map(|reading| reading.temperature)
+                    .collect()
+            })
+            .unwrap_or_default();
+    }
+
+    serde_json::from_str::<Value>(&data)
+        .ok()
+        .and_then(|payload| read_measure(&payload, "temperature"))
+        .into_iter()
+        .collect()
+}
+
+// Reads a field of a payload measured by a sensor, as a number or a text with its unit like "65%"
+pub(super) fn read_measure(payload: &Value, field: &str) -> Option<f64> {
+    match payload.get(field)? {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => {
+            let text = text.trim();
+            let end = text
+                .char_indices()
+                .find(|(position, c)| {
+                    !(c.is_ascii_digit() || *c == '.' || (*c == '-' && *position == 0))
+                })
+                .map_or(text.len(), |(position, _)| position);
+            text[..end].parse().ok()
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        fixtures::{alice, bob, carol},
+        BlockHash,
+    };
+
+    const HOUR: i64 = 3_600_000;
+
+    #[test]
+    fn should_read_temperatures() {
+        let read = |event_type: &str, data: &str| {
+            read_temperatures(&create_transaction(&alice(), &bob(), "", event_type, data))
+        };
+        assert_eq!(read("STORAGE", r#"{"temperature": 4.5}"#), vec![4.5]);
+        assert_eq!(read("STORAGE", r#"{"temperature": "4C"}"#), vec![4.0]);
+        assert_eq!(read("STORAGE", r#"{"temperature": "-18 C"}"#), vec![-18.0]);
+        assert!(read("STORAGE", r#"{"humidity": "65%"}"#).is_empty());
+        assert!(read("STORAGE", "cold").is_empty());
+
+        let bulk = r#"{"readings": [
+            {"device_id": "TRUCK-7", "recorded_at": 0, "temperature": 3.5},
+            {"device_id": "TRUCK-7", "recorded_at": 120000, "humidity": 80},
+            {"device_id": "TRUCK-7", "recorded_at": 240000, "temperature": 9}
+        ]}"#;
+        assert_eq!(read(SENSOR_READINGS_EVENT, bulk), vec![3.5, 9.0]);
+    }
+
+    #[test]
+    fn should_score_handoffs_against_the_sla() {
+        let farm = alice();
+        let warehouse = bob();
+        let sla = r#"{"max_transit_hours": 48, "max_temperature": 8}"#;
+
+        let blocks = vec![
+            create_block(
+                0,
+                vec![create_transaction(&farm, &warehouse, "", SLA_EVENT, sla)],
+            ),
+            // a compliant handoff
+            create_block(
+                HOUR,
+                vec![create_transaction(
+                    &farm,
+                    &warehouse,
+                    "WHEAT-1",
+                    "TRANSPORT",
+                    r#"{"temperature": 4}"#,
+                )],
+            ),
+            create_block(
+                10 * HOUR,
+                vec![create_transaction(
+                    &warehouse, &warehouse, "WHEAT-1", "STORAGE", "{}",
+                )],
+            ),
+            // a late handoff, too warm according to a sensor on the way
+            create_block(
+                20 * HOUR,
+                vec![create_transaction(
+                    &farm,
+                    &warehouse,
+                    "WHEAT-2",
+                    "TRANSPORT",
+                    "{}",
+                )],
+            ),
+            create_block(
+                30 * HOUR,
+                vec![create_transaction(
+                    &carol(),
+                    &carol(),
+                    "WHEAT-2",
+                    "READING",
+                    r#"{"temperature": "9C"}"#,
+                )],
+            ),
+            create_block(
+                80 * HOUR,
+                vec![create_transaction(
+                    &warehouse, &warehouse, "WHEAT-2", "STORAGE", "{}",
+                )],
+            ),
+            // a handoff still in transit
+            create_block(
+                81 * HOUR,
+                vec![create_transaction(
+                    &farm,
+                    &warehouse,
+                    "WHEAT-3",
+                    "TRANSPORT",
+                    "{}",
+                )],
+            ),
+        ];
+
+        let reports = compliance_reports(&blocks);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].shipper, farm);
+        assert_eq!(reports[0].receiver, warehouse);
+        assert_eq!(reports[0].handoffs, 2);
+        assert_eq!(reports[0].compliance, 0.5);
+        assert_eq!(
+            reports[0].violations,
+            vec![SlaViolation {
+                batch_id: "WHEAT-2".to_string(),
+                reasons: vec![
+                    "Transit took 60.0 hours, more than 48".to_string(),
+                    "Temperature 9 out of bounds".to_string(),
+                ],
+            }]
+        );
+    }
+
+    #[test]
+    fn should_ignore_handoffs_without_sla() {
+        let blocks = vec![
+            create_block(
+                0,
+                vec![create_transaction(
+                    &alice(),
+                    &bob(),
+                    "CORN-1",
+                    "TRANSPORT",
+                    "{}",
+                )],
+            ),
+            create_block(
+                HOUR,
+                vec![create_transaction(
+                    &bob(),
+                    &bob(),
+                    "CORN-1",
+                    "STORAGE",
+                    "{}",
+                )],
+            ),
+        ];
+
+        assert!(compliance_reports(&blocks).is_empty());
+    }
+
+    fn create_block(timestamp: i64, transactions: Vec<Transaction>) -> Block {
+        let mut block = Block::new(0, 0, BlockHash::zero(), transactions);
+        block.header.timestamp = timestamp;
+        block
+    }
+
+    fn create_transaction(
+        sender: &Address,
+        recipient: &Address,
+        batch_id: &str,
+        event_type: &str,
+        data: &str,
+    ) -> Transaction {
+        Transaction {
+            sender: sender.clone(),
+            recipient: recipient.clone(),
+            data: data.into(),
+            batch_id: batch_id.to_string(),
+            event_type: event_type.into(),
+            ..Default::default()
+        }
+    }
+}
@@ -1,53 +1,368 @@
+mod duplicate_detector;
+mod error;
+mod event_counters;
+mod faucet;
+mod fingerprint_registry;
+mod json_path;
+mod localization;
+mod lot_allocator;
+mod openapi;
+mod pagination;
+mod public_stats;
+mod query_cache;
+mod saved_queries;
+mod subscriptions;
+
 use crate::{
-    model::{Block, Blockchain, Transaction, TransactionPool},
+    analytics::{
+        batch_status, compare_batches, compliance_reports, picking_suggestions, sensor_trend,
+        AnomalyScores, PartnerCompliance, SensorMetric, TrendOptions,
+    },
+    cluster::{
+        LeaderLease, Maintenance, MaintenanceAnnouncement, MaintenanceNotice, MaintenanceReason,
+    },
+    model::{
+        encode, Address, AgriData, AttestationError, BatchState, Block, BlockAttestation,
+        BlockAttestations, BlockHash, BlockHeader, BlockRef, BlockStats, Blockchain, Change,
+        ChangeFeed, ChangeKind, ComplianceReport, ComplianceThresholds, DocumentChunk,
+        DocumentError, DocumentManifest, Encoding, Namespace, NormalizationVersion, OriginChannel,
+        Plan, PlanStatus, PlannedEvent, Profile, ProtocolActivation, QuantityStrictness,
+        RegisteredActor, StatsTotals, Transaction, TransactionOrigins, TransactionPool, TxHash,
+        NORMALIZATION_VERSION, PROTOCOL_VERSION,
+    },
+    peer::{upgrade_advisories, PeerList, UpgradeAdvisory, PROTOCOL_VERSION_HEADER},
+    storage::{self, ChainVerifier, SharedChainStore},
     util::{execution::Runnable, Context},
+    verify::{BatchBundle, BundledEvent, InclusionProof},
+    wallet::Wallet,
 };
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
 use anyhow::Result;
+use chrono::Utc;
+use duplicate_detector::DuplicateDetector;
+use error::{handle_extractor_error, ErrorCode, ErrorResponse};
+use event_counters::{DroppedTransaction, EventCounters};
+use faucet::Faucet;
+use fingerprint_registry::FingerprintRegistry;
+use json_path::{JsonPath, JsonPathError};
+use localization::localize_payload;
+use lot_allocator::LotAllocator;
+use openapi::ApiDoc;
+use pagination::Pagination;
+use public_stats::PublicStats;
+use query_cache::{CachedValue, QueryCache};
+use saved_queries::{SavedQueries, SavedQuery, SavedQueryError};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, str::FromStr, sync::Arc};
+use subscriptions::{Notification, Subscription};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+// Largest body accepted when submitting a document to be split in chunks
+const MAX_DOCUMENT_REQUEST_SIZE: usize = 4 * 1024 * 1024;
+
+// Changes returned when the client doesn't ask for an amount, and the most it can ask for
+const DEFAULT_CHANGES_LIMIT: usize = 100;
+const MAX_CHANGES_LIMIT: usize = 1000;
 
 struct ApiState {
     blockchain: Blockchain,
     pool: TransactionPool,
+    origins: TransactionOrigins,
+    origins_record_ip: bool,
+    attestations: BlockAttestations,
+    changes: ChangeFeed,
+    // the admin queries are disabled without a token
+    admin_token: String,
+    // signs the documents split by the node
+    node_wallet: Arc<Wallet>,
+    peers: PeerList,
+    cache: QueryCache,
+    anomaly_scores: AnomalyScores,
+    leader_lease: LeaderLease,
+    maintenance: Maintenance,
+    verification: ChainVerifier,
+    event_counters: EventCounters,
+    duplicate_detector: DuplicateDetector,
+    fingerprints: FingerprintRegistry,
+    lot_allocator: LotAllocator,
+    public_stats: PublicStats,
+    saved_queries: SavedQueries,
+    // only present on test networks
+    faucet: Option<Faucet>,
+    store: Option<SharedChainStore>,
+    // address of the primary of a read replica, which refuses the writes
+    replica_of: String,
 }
 
 pub struct Api {
     port: u16,
+    origins_record_ip: bool,
+    admin_token: String,
+    query_cache_size: usize,
+    duplicate_window_ms: u64,
+    public_stats_min_contributors: usize,
+    public_stats_epsilon: f64,
+    public_stats_max_contribution: f64,
+    public_stats_epoch_blocks: u64,
+    public_stats_salt: String,
+    testnet: bool,
+    faucet_registrar_key: String,
+    node_wallet: Arc<Wallet>,
     blockchain: Blockchain,
     pool: TransactionPool,
+    origins: TransactionOrigins,
+    attestations: BlockAttestations,
+    changes: ChangeFeed,
+    peers: PeerList,
+    anomaly_scores: AnomalyScores,
+    leader_lease: LeaderLease,
+    maintenance: Maintenance,
+    verification: ChainVerifier,
+    event_counters: EventCounters,
+    saved_queries: SavedQueries,
+    store: Option<SharedChainStore>,
+    replica_of: String,
 }
 
 impl Runnable for Api {
     fn run(&self) -> Result<()> {
-        let api_blockchain = self.blockchain.clone();
-        let api_pool = self.pool.clone();
+        let api_state = ApiState {
+            blockchain: self.blockchain.clone(),
+            pool: self.pool.clone(),
+            origins: self.origins.clone(),
+            origins_record_ip: self.origins_record_ip,
+            attestations: self.attestations.clone(),
+            changes: self.changes.clone(),
+            admin_token: self.admin_token.clone(),
+            node_wallet: self.node_wallet.clone(),
+            peers: self.peers.clone(),
+            cache: QueryCache::new(self.query_cache_size),
+            anomaly_scores: self.anomaly_scores.clone(),
+            leader_lease: self.leader_lease.clone(),
+            maintenance: self.maintenance.clone(),
+            verification: self.verification.clone(),
+            event_counters: self.event_counters.clone(),
+            duplicate_detector: DuplicateDetector::new(
+                self.duplicate_window_ms,
+                self.blockchain.clone(),
+                self.pool.clone(),
+            ),
+            fingerprints: FingerprintRegistry::new(self.blockchain.clone(), self.pool.clone()),
+            lot_allocator: LotAllocator::new(
+                self.blockchain.clone(),
+                self.pool.clone(),
+                self.node_wallet.clone(),
+            ),
+            public_stats: PublicStats::new(
+                self.public_stats_min_contributors,
+                self.public_stats_epsilon,
+                self.public_stats_max_contribution,
+                self.public_stats_epoch_blocks,
+                &self.public_stats_salt,
+            )?,
+            saved_queries: self.saved_queries.clone(),
+            faucet: self
+                .testnet
+                .then(|| Faucet::new(&self.faucet_registrar_key, &self.node_wallet))
+                .transpose()?,
+            store: self.store.clone(),
+            replica_of: self.replica_of.clone(),
+        };
 
-        start_server(self.port, api_blockchain, api_pool)
+        start_server(self.port, api_state)
     }
 }
 
 impl Api {
     pub fn new(context: &Context) -> Api {
+        let saved_queries = SavedQueries::new(
+            context.blockchain.clone(),
+            context.config.saved_queries_file.clone(),
+        );
+        if let Err(error) = saved_queries.load() {
+            error!("Could not load the saved queries: {}", error);
+        }
+
         Api {
             port: context.config.port,
+            origins_record_ip: context.config.origins_record_ip,
+            admin_token: context.config.admin_token.clone(),
+            query_cache_size: context.config.query_cache_size,
+            duplicate_window_ms: context.config.duplicate_window_ms,
+            public_stats_min_contributors: context.config.public_stats_min_contributors,
+            public_stats_epsilon: context.config.public_stats_epsilon,
+            public_stats_max_contribution: context.config.public_stats_max_contribution,
+            public_stats_epoch_blocks: context.config.public_stats_epoch_blocks,
+            public_stats_salt: context.config.public_stats_salt.clone(),
+            testnet: context.config.testnet,
+            faucet_registrar_key: context.config.faucet_registrar_key.clone(),
+            node_wallet: context.node_wallet.clone(),
             blockchain: context.blockchain.clone(),
             pool: context.pool.clone(),
+            origins: context.origins.clone(),
+            attestations: context.attestations.clone(),
+            changes: context.changes.clone(),
+            peers: context.peers.clone(),
+            anomaly_scores: context.anomaly_scores.clone(),
+            leader_lease: context.leader_lease.clone(),
+            maintenance: context.maintenance.clone(),
+            verification: context.verification.clone(),
+            // subscribed before any thread starts, so no event is missed
+            event_counters: EventCounters::new(&context.blockchain.event_bus()),
+            saved_queries,
+            store: context.store.clone(),
+            replica_of: context.config.replica_of.clone(),
         }
     }
 }
 
 #[actix_web::main]
-async fn start_server(port: u16, blockchain: Blockchain, pool: TransactionPool) -> Result<()> {
+async fn start_server(port: u16, api_state: ApiState) -> Result<()> {
     let url = format!("localhost:{}", port);
-    // These variables are really "Arc" pointers to a shared memory value
+    // The state variables are really "Arc" pointers to a shared memory value
     // So when we clone them, we are only cloning the pointers and not the actual data
-    let api_state = web::Data::new(ApiState { blockchain, pool });
+    let api_state = web::Data::new(api_state);
 
     HttpServer::new(move || {
         App::new()
             .app_data(api_state.clone())
+            // malformed requests also get a typed error body
+            .app_data(web::JsonConfig::default().error_handler(|e, _| handle_extractor_error(e)))
+            .app_data(web::QueryConfig::default().error_handler(|e, _| handle_extractor_error(e)))
+            .app_data(web::PathConfig::default().error_handler(|e, _| handle_extractor_error(e)))
             .route("/blocks", web::get().to(get_blocks))
             .route("/blocks", web::post().to(add_block))
+            .route("/blocks/{hash}", web::get().to(get_block))
+            .route(
+                "/blocks/{hash}/attestations",
+                web::get().to(get_block_attestations),
+            )
+            .route(
+                "/blocks/{hash}/attestations",
+                web::post().to(add_block_attestation),
+            )
+            .route("/mine", web::post().to(request_mining))
+            .route(
+                "/blocks/{index}/proofs/{position}",
+                web::get().to(get_inclusion_proof),
+            )
+            .route("/genesis", web::get().to(get_genesis))
+            .route("/normalization", web::get().to(get_normalization))
+            .route("/verification", web::get().to(get_verification))
+            .route("/headers", web::get().to(get_headers))
+            .route("/replication/blocks", web::get().to(get_replication_blocks))
+            .route("/transactions", web::get().to(get_transactions))
             .route("/transactions", web::post().to(add_transaction))
+            .route(
+                "/transactions/pending",
+                web::get().to(get_pending_transactions),
+            )
+            .route(
+                "/transactions/dropped",
+                web::get().to(get_dropped_transactions),
+            )
+            .route(
+                "/transactions/{hash}/thread",
+                web::get().to(get_transaction_thread),
+            )
+            .route(
+                "/batches/{batch_id}/status",
+                web::get().to(get_batch_status),
+            )
+            .route("/batches/{batch_id}/state", web::get().to(get_batch_state))
+            .route(
+                "/batches/{batch_id}/lineage",
+                web::get().to(get_batch_lineage),
+            )
+            .route("/batches/{batch_id}/plans", web::get().to(get_batch_plans))
+            .route("/plans/overdue", web::get().to(get_overdue_plans))
+            .route(
+                "/batches/{batch_id}/history",
+                web::get().to(get_batch_history),
+            )
+            .route(
+                "/batches/{batch_id}/bundle",
+                web::get().to(get_batch_bundle),
+            )
+            .route(
+                "/batches/{batch_id}/sensors",
+                web::get().to(get_sensor_trend),
+            )
+            .route(
+                "/batches/{batch_id}/compliance",
+                web::get().to(get_cold_chain_compliance),
+            )
+            .route(
+                "/batches/{batch_id}/comparison/{other_batch_id}",
+                web::get().to(get_batch_comparison),
+            )
+            .route("/profiles/{address}", web::get().to(get_profile))
+            .route("/registry", web::get().to(get_registry))
+            .route(
+                "/custodians/{address}/picking",
+                web::get().to(get_picking_suggestions),
+            )
+            .route("/lots", web::post().to(allocate_lot))
+            .service(
+                web::resource("/documents")
+                    // documents are larger than the rest of the requests
+                    .app_data(
+                        web::JsonConfig::default()
+                            .limit(MAX_DOCUMENT_REQUEST_SIZE)
+                            .error_handler(|e, _| handle_extractor_error(e)),
+                    )
+                    .route(web::post().to(add_document)),
+            )
+            .route("/documents/{hash}", web::get().to(get_document))
+            .route(
+                "/fingerprints/reused",
+                web::get().to(get_reused_fingerprints),
+            )
+            .route("/fingerprints/{hash}", web::get().to(get_fingerprints))
+            .route("/queries", web::get().to(get_saved_queries))
+            .route("/queries/{name}", web::get().to(get_query_results))
+            .route("/faucet/actors", web::post().to(provision_actor))
+            .route("/peers", web::get().to(get_peers))
+            .route("/forks", web::get().to(get_forks))
+            .route("/forks/{hash}", web::get().to(get_fork))
+            .route("/changes", web::get().to(get_changes))
+            .route("/ws", web::get().to(subscribe_events))
+            .route("/stats", web::get().to(get_stats))
+            .route("/stats/public", web::get().to(get_public_stats))
+            .route("/sla/reports", web::get().to(get_sla_reports))
+            .route("/metrics", web::get().to(get_metrics))
+            .route("/maintenance", web::get().to(get_maintenance))
+            .route(
+                "/maintenance/announcements",
+                web::post().to(receive_maintenance_announcement),
+            )
+            .route("/admin/origins", web::get().to(get_origins))
+            .route("/admin/maintenance", web::post().to(start_maintenance))
+            .route("/admin/maintenance", web::delete().to(end_maintenance))
+            .route(
+                "/admin/verification",
+                web::get().to(get_verification_progress),
+            )
+            .route("/admin/verification", web::post().to(start_verification))
+            .route(
+                "/admin/verification",
+                web::delete().to(interrupt_verification),
+            )
+            .route("/admin/upgrades", web::get().to(get_upgrades))
+            .route("/admin/queries", web::post().to(save_query))
+            .route("/admin/queries/{name}", web::delete().to(remove_query))
+            .route("/openapi.json", web::get().to(get_openapi))
+            // the routes of a consortium on a node shared by several of them
+            .service(
+                web::scope("/ns/{namespace}")
+                    .route("/transactions", web::get().to(get_namespace_transactions))
+                    .route("/transactions", web::post().to(add_namespace_transaction))
+                    .route(
+                        "/batches/{batch_id}/history",
+                        web::get().to(get_namespace_batch_history),
+                    )
+                    .route("/registry", web::get().to(get_namespace_registry)),
+            )
     })
     .bind(url)
     .unwrap()
@@ -57,43 +372,2440 @@ async fn start_server(port: u16, blockchain: Blockchain, pool: TransactionPool)
     Ok(())
 }
 
-// Returns a list of all the blocks in the blockchain
-async fn get_blocks(state: web::Data<ApiState>) -> impl Responder {
+#[derive(Deserialize, IntoParams)]
+struct BlocksQuery {
+    // "binary" or "canonical-json", for external verifiers that need to reproduce the exact bytes
+    encoding: Option<String>,
+}
+
+// Returns a list of all the blocks in the blockchain, or a page of them
+#[utoipa::path(
+    get,
+    path = "/blocks",
+    params(BlocksQuery, Pagination),
+    responses(
+        (status = 200, description = "All the blocks of the chain, from the genesis block", body = [Block]),
+        (status = 400, description = "Unknown encoding", body = ErrorResponse),
+    )
+)]
+async fn get_blocks(
+    state: web::Data<ApiState>,
+    query: web::Query<BlocksQuery>,
+    pagination: web::Query<Pagination>,
+) -> HttpResponse {
     let blockchain = &state.blockchain;
-    let blocks = blockchain.get_all_blocks();
+    // only the blocks of the page are copied out of the chain
+    let total = blockchain.get_last_block().header.index as usize + 1;
+    let range = pagination.range(total);
+    let page = || blockchain.get_blocks(range.start as u64..range.end as u64);
+    if let Some(encoding) = &query.encoding {
+        return encoded_response(&page(), encoding);
+    }
+    if pagination.is_requested() {
+        return Pagination::page_response(&page(), total);
+    }
+
+    compute_cached(&state, "blocks".to_string(), |blocks| {
+        serde_json::to_string(blocks).ok()
+    })
+    .await
+}
+
+// Returns a single block of the chain by its hash
+#[utoipa::path(
+    get,
+    path = "/blocks/{hash}",
+    params(("hash" = String, Path, description = "Hash of the block")),
+    responses(
+        (status = 200, description = "The block", body = Block),
+        (status = 400, description = "Invalid block hash", body = ErrorResponse),
+        (status = 404, description = "No block with that hash in the chain", body = ErrorResponse),
+    )
+)]
+async fn get_block(state: web::Data<ApiState>, hash: web::Path<String>) -> HttpResponse {
+    let hash = match BlockHash::from_str(&hash) {
+        Ok(hash) => hash,
+        Err(_) => {
+            return ErrorResponse::new(ErrorCode::InvalidHash, "Invalid block hash").to_response()
+        }
+    };
+
+    match state.blockchain.get_block_by_hash(&hash) {
+        Some(block) => HttpResponse::Ok().json(&block),
+        None => ErrorResponse::new(ErrorCode::NotFound, "Block not found").to_response(),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct AttestedBlock {
+    header: BlockHeader,
+    // from the oldest, none if no regulator attested the block yet
+    attestations: Vec<BlockAttestation>,
+}
+
+// Returns the header of a block with the attestations of the regulators, which are not part of the chain
+// The blocks rolled back by a reorganization keep their attestations
+#[utoipa::path(
+    get,
+    path = "/blocks/{hash}/attestations",
+    params(("hash" = String, Path, description = "Hash of the block")),
+    responses(
+        (status = 200, description = "The header of the block and its attestations", body = AttestedBlock),
+        (status = 400, description = "Invalid block hash", body = ErrorResponse),
+        (status = 404, description = "No block with that hash", body = ErrorResponse),
+    )
+)]
+async fn get_block_attestations(
+    state: web::Data<ApiState>,
+    hash: web::Path<String>,
+) -> HttpResponse {
+    let hash = match BlockHash::from_str(&hash) {
+        Ok(hash) => hash,
+        Err(_) => {
+            return ErrorResponse::new(ErrorCode::InvalidHash, "Invalid block hash").to_response()
+        }
+    };
+
+    let block = state.blockchain.get_block_by_hash(&hash).or_else(|| {
+        state
+            .blockchain
+            .get_orphaned_block(&hash)
+            .map(|orphaned| orphaned.block)
+    });
+    match block {
+        Some(block) => HttpResponse::Ok().json(AttestedBlock {
+            header: block.header,
+            attestations: state.attestations.of(&hash),
+        }),
+        None => ErrorResponse::new(ErrorCode::NotFound, "Block not found").to_response(),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct AttestationSubmission {
+    // false if the same attestation was already known
+    added: bool,
+}
+
+// Attaches the signed attestation of a regulator to a block of the chain, e.g. after a government inspection
+// Only the keys of REGULATOR_KEYS are accepted, and the block and its hash stay the same
+#[utoipa::path(
+    post,
+    path = "/blocks/{hash}/attestations",
+    params(("hash" = String, Path, description = "Hash of the block")),
+    request_body = BlockAttestation,
+    responses(
+        (status = 200, description = "The attestation was attached to the block", body = AttestationSubmission),
+        (status = 400, description = "Invalid signature or statement, or an unknown regulator", body = ErrorResponse),
+        (status = 404, description = "No block with that hash in the chain", body = ErrorResponse),
+    )
+)]
+async fn add_block_attestation(
+    state: web::Data<ApiState>,
+    hash: web::Path<String>,
+    attestation: web::Json<BlockAttestation>,
+) -> HttpResponse {
+    let attestation = attestation.into_inner();
+    if BlockHash::from_str(&hash).ok() != Some(attestation.block_hash) {
+        let message = "The attestation is about another block";
+        return ErrorResponse::new(ErrorCode::InvalidAttestation, message).to_response();
+    }
+
+    match state.attestations.add(&state.blockchain, attestation) {
+        Ok(added) => HttpResponse::Ok().json(AttestationSubmission { added }),
+        Err(error @ AttestationError::UnknownBlock(_)) => {
+            ErrorResponse::new(ErrorCode::NotFound, error).to_response()
+        }
+        Err(error @ AttestationError::Storage(_)) => {
+            ErrorResponse::new(ErrorCode::Internal, error).to_response()
+        }
+        Err(error) => ErrorResponse::new(ErrorCode::InvalidAttestation, error).to_response(),
+    }
+}
 
-    HttpResponse::Ok().json(&blocks)
+#[derive(Serialize, ToSchema)]
+struct MiningRequest {
+    // transactions that will be included in the next blocks
+    pending_transactions: usize,
+}
+
+// Asks the miner to mine the pending transactions right away, instead of waiting for more of them
+// e.g. for a warehouse that needs its events on chain before a truck leaves
+#[utoipa::path(
+    post,
+    path = "/mine",
+    responses(
+        (status = 202, description = "The pending transactions will be mined next", body = MiningRequest),
+        (status = 409, description = "There are no pending transactions", body = ErrorResponse),
+        (status = 503, description = "The node is in maintenance", body = ErrorResponse),
+    )
+)]
+async fn request_mining(state: web::Data<ApiState>) -> HttpResponse {
+    if let Some(response) = reject_writes(&state) {
+        return response;
+    }
+
+    match state.pool.request_mining() {
+        0 => ErrorResponse::new(ErrorCode::NothingToMine, "No pending transactions to mine")
+            .to_response(),
+        pending_transactions => HttpResponse::Accepted().json(MiningRequest {
+            pending_transactions,
+        }),
+    }
 }
 
 // Adds a new block to the blockchain
-async fn add_block(state: web::Data<ApiState>, block_json: web::Json<Block>) -> HttpResponse {
+#[utoipa::path(
+    post,
+    path = "/blocks",
+    request_body = Block,
+    responses(
+        (status = 200, description = "The block was added to the chain"),
+        (status = 400, description = "Invalid block", body = ErrorResponse),
+        (status = 503, description = "The node is in maintenance", body = ErrorResponse),
+    )
+)]
+async fn add_block(
+    state: web::Data<ApiState>,
+    request: HttpRequest,
+    block_json: web::Json<Block>,
+) -> HttpResponse {
+    if let Some(response) = reject_writes(&state) {
+        return response;
+    }
     let mut block = block_json.into_inner();
 
     // The hash of the block is mandatory and the blockchain checks if it's correct
     // That's a bit unconvenient for manual use of the API
-    // So we ignore the comming hash and recalculate it again before adding to the blockchain
-    block.hash = block.calculate_hash();
+    // So we ignore the comming hash (and merkle root) and recalculate it again before adding to the blockchain
+    block.header.merkle_root = block.calculate_merkle_root();
+    block.header.hash = block.calculate_hash();
 
     let blockchain = &state.blockchain;
     let result = blockchain.add_block(block.clone());
 
     match result {
         Ok(_) => {
-            info!("Received new block {}", block.index);
+            info!("Received new block {}", block.header.index);
+            for transaction in block.body.transactions.iter() {
+                record_origin(&state, &request, transaction, OriginChannel::ApiBlock);
+            }
             HttpResponse::Ok().finish()
         }
-        Err(error) => HttpResponse::BadRequest().body(error.to_string()),
+        Err(error) => ErrorResponse::new(ErrorCode::InvalidBlock, error)
+            .with_details(serde_json::json!({ "index": block.header.index }))
+            .to_response(),
     }
 }
 
+#[derive(Serialize, ToSchema)]
+struct TransactionSubmission {
+    // hash of the transaction as accepted by the node (e.g. after normalizing its payload), to follow it once mined
+    hash: String,
+    // false if the same transaction was already pending, so it will only be mined once
+    added: bool,
+    // probable duplicates of the transaction, documents already attached to other batches,
+    // and quantities larger than what is left of the batch: the transaction is accepted anyway
+    warnings: Vec<String>,
+}
+
 // Adds a new transaction to the pool, to be included on the next block
+#[utoipa::path(
+    post,
+    path = "/transactions",
+    request_body = Transaction,
+    responses(
+        (status = 200, description = "The transaction was added to the pool", body = TransactionSubmission),
+        (status = 400, description = "Invalid transaction", body = ErrorResponse),
+        (status = 503, description = "The node is in maintenance", body = ErrorResponse),
+    )
+)]
 async fn add_transaction(
     state: web::Data<ApiState>,
+    request: HttpRequest,
     transaction_json: web::Json<Transaction>,
-) -> impl Responder {
+) -> HttpResponse {
+    if let Some(response) = reject_writes(&state) {
+        return response;
+    }
     let transaction = transaction_json.into_inner();
+
+    // Invalid transactions would make the whole block invalid, so we don't include them in the pool
+    let pending = state.pool.get_unconfirmed();
+    if let Err(error) = state
+        .blockchain
+        .validate_transaction_after(&transaction, &pending)
+    {
+        return ErrorResponse::new(ErrorCode::InvalidTransaction, error).to_response();
+    }
+
+    // Probable duplicates are still accepted, but the client is warned about them
+    let mut warnings = state.duplicate_detector.check(&transaction);
+    warnings.extend(state.fingerprints.check(&transaction));
+    // rejected by the validation instead when the quantities are a consensus rule
+    if state.blockchain.quantity_strictness() == QuantityStrictness::Flag {
+        let result = state
+            .blockchain
+            .check_quantity_after(&transaction, &pending);
+        warnings.extend(result.err().map(|error| error.to_string()));
+    }
+    for warning in warnings.iter() {
+        warn!("{} (batch {})", warning, transaction.batch_id);
+    }
+
+    record_origin(&state, &request, &transaction, OriginChannel::Api);
+    let hash = format!("{:#x}", transaction.hash());
     let pool = &state.pool;
-    pool.add_transaction(transaction);
+    let added = pool.add_transaction(transaction);
+
+    HttpResponse::Ok().json(TransactionSubmission {
+        hash,
+        added,
+        warnings,
+    })
+}
+
+// Returns what identifies the network of the node, to check that all nodes started from the same genesis
+#[utoipa::path(
+    get,
+    path = "/genesis",
+    responses((status = 200, description = "Genesis hash and initial state root", body = GenesisSummary))
+)]
+async fn get_genesis(state: web::Data<ApiState>) -> impl Responder {
+    HttpResponse::Ok().json(state.blockchain.get_genesis_summary())
+}
+
+#[derive(Serialize, ToSchema)]
+struct NormalizationVersions {
+    // the version applied by "wallet sign --normalize"
+    latest: u32,
+    versions: Vec<NormalizationVersion>,
+}
+
+// Lists the versions of the payload normalization and their transformations, so a client can normalize
+// its transactions before signing them, and anyone can check the `normalization` recorded in a transaction
+#[utoipa::path(
+    get,
+    path = "/normalization",
+    responses((status = 200, description = "The versions of the payload normalization", body = NormalizationVersions))
+)]
+async fn get_normalization() -> impl Responder {
+    HttpResponse::Ok().json(NormalizationVersions {
+        latest: NORMALIZATION_VERSION,
+        versions: NormalizationVersion::all(),
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+struct ChainVerification {
+    blocks: u64,
+    valid: bool,
+    // index of the first invalid block and why, if any
+    invalid_block: Option<u64>,
+    reason: Option<String>,
+}
+
+// Checks again the whole chain of the node, to detect corrupted blocks
+#[utoipa::path(
+    get,
+    path = "/verification",
+    responses((status = 200, description = "Whether all the blocks of the chain are valid", body = ChainVerification))
+)]
+async fn get_verification(state: web::Data<ApiState>) -> HttpResponse {
+    let blockchain = &state.blockchain;
+    let tip = blockchain.get_last_block().header.hash;
+    let verification_json = state.cache.get_or_compute(tip, "verification", || {
+        let invalid = blockchain.validate_chain().err();
+        let verification = ChainVerification {
+            blocks: blockchain.get_last_block().header.index + 1,
+            valid: invalid.is_none(),
+            invalid_block: invalid.as_ref().map(|(index, _)| *index),
+            reason: invalid.map(|(_, error)| error.to_string()),
+        };
+        serde_json::to_string(&verification).ok()
+    });
+
+    cached_json_response(verification_json)
+}
+
+// Returns the proof that the transaction at a position of a block is included in it
+#[utoipa::path(
+    get,
+    path = "/blocks/{index}/proofs/{position}",
+    params(
+        ("index" = u64, Path, description = "Index of the block"),
+        ("position" = usize, Path, description = "Position of the transaction in the block"),
+    ),
+    responses(
+        (status = 200, description = "Header of the block and merkle proof of the transaction", body = InclusionProof),
+        (status = 404, description = "No transaction at that position", body = ErrorResponse),
+    )
+)]
+async fn get_inclusion_proof(
+    state: web::Data<ApiState>,
+    path: web::Path<(u64, usize)>,
+) -> HttpResponse {
+    let (index, position) = path.into_inner();
+    let blockchain = &state.blockchain;
+
+    // the receipts of the transactions of a popular batch are requested over and over by the consumers
+    let tip = blockchain.get_last_block().header.hash;
+    let key = format!("blocks/{}/proofs/{}", index, position);
+    let proof_json = state.cache.get_or_compute(tip, &key, || {
+        let block = blockchain.get_block(index)?;
+        let proof = InclusionProof {
+            header: block.header.clone(),
+            proof: block.proof_for(position)?,
+        };
+        serde_json::to_string(&proof).ok()
+    });
+
+    match proof_json {
+        Some(_) => cached_json_response(proof_json),
+        None => ErrorResponse::new(ErrorCode::NotFound, "Transaction not found").to_response(),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+struct HeadersQuery {
+    from: Option<u64>,
+    // exclusive, defaults to the end of the chain
+    to: Option<u64>,
+}
+
+// Returns the headers of a range of blocks, without their transactions
+#[utoipa::path(
+    get,
+    path = "/headers",
+    params(HeadersQuery),
+    responses((status = 200, description = "Headers of the blocks in the range", body = [BlockHeader]))
+)]
+async fn get_headers(
+    state: web::Data<ApiState>,
+    query: web::Query<HeadersQuery>,
+) -> impl Responder {
+    let range = query.from.unwrap_or(0)..query.to.unwrap_or(u64::MAX);
+    let headers: Vec<BlockHeader> = state.blockchain.iter_headers(range).collect();
+
+    HttpResponse::Ok().json(&headers)
+}
+
+#[derive(Deserialize, IntoParams)]
+struct ReplicationQuery {
+    from: Option<u64>,
+    limit: Option<usize>,
+}
+
+// Returns the next blocks of the replication stream, read from the storage when the node has one
+// so the replicas don't contend with the miner and the peers for the chain
+#[utoipa::path(
+    get,
+    path = "/replication/blocks",
+    params(ReplicationQuery),
+    responses(
+        (status = 200, description = "Bincode list of the blocks from the index, each one in the binary encoding", content_type = "application/octet-stream"),
+    )
+)]
+async fn get_replication_blocks(
+    state: web::Data<ApiState>,
+    query: web::Query<ReplicationQuery>,
+) -> HttpResponse {
+    let limit = query
+        .limit
+        .unwrap_or(storage::DEFAULT_REPLICATION_BATCH)
+        .min(storage::MAX_REPLICATION_BATCH);
+    let store = state.store.as_deref();
+    match storage::replication_batch(store, &state.blockchain, query.from.unwrap_or(0), limit) {
+        Ok(bytes) => HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .body(bytes),
+        Err(error) => ErrorResponse::new(ErrorCode::Internal, error).to_response(),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+struct TransactionsQuery {
+    batch_id: Option<String>,
+    event_type: Option<String>,
+    // actor that sent, received or was concerned by the transactions
+    address: Option<String>,
+    // JSONPath expression to select only a part of each payload
+    path: Option<String>,
+    // language of the localized labels in the payloads
+    lang: Option<String>,
+}
+
+// Part of a payload selected with a JSONPath expression
+#[derive(Serialize, ToSchema)]
+struct PayloadSelection {
+    #[schema(value_type = String)]
+    sender: Address,
+    batch_id: String,
+    event_type: String,
+    value: serde_json::Value,
+}
+
+// Returns the transactions in the chain, optionally filtered by batch, event type and actor
+// With a "path", only the selected part of the (JSON) payload of each transaction is returned
+// With a "lang", the localized labels in the payloads are returned only in that language
+#[utoipa::path(
+    get,
+    path = "/transactions",
+    params(TransactionsQuery, Pagination),
+    responses(
+        (status = 200, description = "Matching transactions, or the selected part of their payloads with a path", body = [Transaction]),
+        (status = 400, description = "Invalid JSONPath expression or address", body = ErrorResponse),
+    )
+)]
+async fn get_transactions(
+    state: web::Data<ApiState>,
+    query: web::Query<TransactionsQuery>,
+    pagination: web::Query<Pagination>,
+) -> HttpResponse {
+    list_transactions(&state, &query, &pagination, None)
+}
+
+// The transactions matching the query, only the ones about the batches of a namespace if any
+fn list_transactions(
+    state: &ApiState,
+    query: &TransactionsQuery,
+    pagination: &Pagination,
+    namespace: Option<&Namespace>,
+) -> HttpResponse {
+    let path = match query.path.as_deref().map(JsonPath::from_str).transpose() {
+        Ok(path) => path,
+        Err(error) => {
+            let JsonPathError::InvalidSyntax(position) = error;
+            return ErrorResponse::new(ErrorCode::InvalidPath, error)
+                .with_details(serde_json::json!({ "position": position }))
+                .to_response();
+        }
+    };
+
+    let address = match query.address.as_deref().map(Address::from_str).transpose() {
+        Ok(address) => address,
+        Err(_) => {
+            return ErrorResponse::new(ErrorCode::InvalidAddress, "Invalid address").to_response()
+        }
+    };
+
+    let matches = |tx: &Transaction| {
+        let batch_matches = query.batch_id.as_ref().is_none_or(|id| *id == tx.batch_id);
+        let type_matches = query
+            .event_type
+            .as_ref()
+            .is_none_or(|t| tx.event_type == *t);
+        let namespace_matches = namespace.is_none_or(|namespace| namespace.contains(&tx.batch_id));
+        batch_matches && type_matches && namespace_matches
+    };
+    // the transactions of an actor are indexed, the other queries go through the whole chain
+    let mut transactions = match &address {
+        Some(address) => state
+            .blockchain
+            .transactions_for_address(address, 0..u64::MAX)
+            .into_iter()
+            .map(|(_, tx)| tx)
+            .filter(|tx| matches(tx))
+            .collect(),
+        None => state.blockchain.find_transactions(matches),
+    };
+
+    if let Some(lang) = &query.lang {
+        for transaction in transactions.iter_mut() {
+            // a typed payload stays typed, unless the localized labels no longer fit in it
+            let localized = localize_payload(&transaction.data.as_json(), lang);
+            transaction.data = match transaction.data {
+                AgriData::Raw(_) => localized.into(),
+                _ => serde_json::from_str(&localized).unwrap_or(AgriData::Raw(localized)),
+            };
+        }
+    }
+
+    let path = match path {
+        Some(path) => path,
+        None => return pagination.response(&transactions),
+    };
+
+    // transactions without a JSON payload or without a match are left out
+    let selections: Vec<PayloadSelection> = transactions
+        .into_iter()
+        .filter_map(|tx| {
+            let payload = serde_json::from_str(&tx.data.as_json()).ok()?;
+            let value = path.evaluate(&payload)?;
+            Some(PayloadSelection {
+                sender: tx.sender,
+                batch_id: tx.batch_id,
+                event_type: tx.event_type.to_string(),
+                value,
+            })
+        })
+        .collect();
+
+    pagination.response(&selections)
+}
+
+#[derive(Deserialize, IntoParams)]
+struct PendingTransactionsQuery {
+    batch_id: Option<String>,
+}
+
+// Returns the transactions waiting in the pool to be mined, in order of arrival
+// e.g. a mobile app can show the events of a batch that are not on chain yet
+#[utoipa::path(
+    get,
+    path = "/transactions/pending",
+    params(PendingTransactionsQuery),
+    responses((status = 200, description = "Pending transactions, the oldest first", body = [Transaction]))
+)]
+async fn get_pending_transactions(
+    state: web::Data<ApiState>,
+    query: web::Query<PendingTransactionsQuery>,
+) -> impl Responder {
+    let pending: Vec<Transaction> = state
+        .pool
+        .get_all()
+        .into_iter()
+        .filter(|transaction| {
+            query
+                .batch_id
+                .as_ref()
+                .is_none_or(|batch_id| transaction.batch_id == *batch_id)
+        })
+        .collect();
+
+    HttpResponse::Ok().json(&pending)
+}
+
+#[derive(Deserialize, IntoParams)]
+struct DroppedTransactionsQuery {
+    sender: Option<String>,
+}
+
+// Returns the pending transactions recently dropped because a new block made them invalid
+// Submitters can check it to find out why their transactions were never mined
+#[utoipa::path(
+    get,
+    path = "/transactions/dropped",
+    params(DroppedTransactionsQuery),
+    responses((status = 200, description = "Recently dropped transactions, the oldest first", body = [DroppedTransaction]))
+)]
+async fn get_dropped_transactions(
+    state: web::Data<ApiState>,
+    query: web::Query<DroppedTransactionsQuery>,
+) -> impl Responder {
+    let dropped: Vec<DroppedTransaction> = state
+        .event_counters
+        .get()
+        .recent_drops
+        .into_iter()
+        .filter(|drop| {
+            query
+                .sender
+                .as_ref()
+                .is_none_or(|sender| drop.transaction.sender.to_string() == *sender)
+        })
+        .collect();
+
+    HttpResponse::Ok().json(&dropped)
+}
+
+// Returns a summary of where a batch is now, computed from all its events
+#[utoipa::path(
+    get,
+    path = "/batches/{batch_id}/status",
+    params(("batch_id" = String, Path, description = "Identifier of the batch")),
+    responses(
+        (status = 200, description = "Current status of the batch", body = BatchStatus),
+        (status = 404, description = "The batch has no events", body = ErrorResponse),
+    )
+)]
+async fn get_batch_status(state: web::Data<ApiState>, batch_id: web::Path<String>) -> HttpResponse {
+    let key = format!("batches/{}/status", batch_id);
+    compute_cached(&state, key, move |blocks| {
+        let status = batch_status(blocks, &batch_id)?;
+        serde_json::to_string(&status).ok()
+    })
+    .await
+}
+
+#[derive(Serialize, ToSchema)]
+struct BatchStateView {
+    batch_id: String,
+    state: BatchState,
+}
+
+// Returns where a batch is in its lifecycle, e.g. for the scanners that only need to know if it was recalled
+#[utoipa::path(
+    get,
+    path = "/batches/{batch_id}/state",
+    params(("batch_id" = String, Path, description = "Identifier of the batch")),
+    responses(
+        (status = 200, description = "State of the batch", body = BatchStateView),
+        (status = 404, description = "The batch has no events", body = ErrorResponse),
+    )
+)]
+async fn get_batch_state(state: web::Data<ApiState>, batch_id: web::Path<String>) -> HttpResponse {
+    let batch_id = batch_id.into_inner();
+    match state.blockchain.batch_state(&batch_id) {
+        Some(state) => HttpResponse::Ok().json(BatchStateView { batch_id, state }),
+        None => ErrorResponse::new(ErrorCode::NotFound, "The batch has no events").to_response(),
+    }
+}
+
+// Returns the batches that a batch comes from, e.g. to trace a contaminated flour back to the harvests of the farms
+#[utoipa::path(
+    get,
+    path = "/batches/{batch_id}/lineage",
+    params(("batch_id" = String, Path, description = "Identifier of the batch")),
+    responses(
+        (status = 200, description = "Ancestors of the batch and the splits and merges between them", body = BatchLineage),
+        (status = 404, description = "The batch has no events", body = ErrorResponse),
+    )
+)]
+async fn get_batch_lineage(
+    state: web::Data<ApiState>,
+    batch_id: web::Path<String>,
+) -> HttpResponse {
+    let blockchain = &state.blockchain;
+    match blockchain.batch_state(&batch_id) {
+        Some(_) => HttpResponse::Ok().json(blockchain.lineage(&batch_id)),
+        None => ErrorResponse::new(ErrorCode::NotFound, "The batch has no events").to_response(),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct PlanView {
+    // hash of the PLANNED transaction
+    #[schema(value_type = String)]
+    hash: TxHash,
+    batch_id: String,
+    #[schema(value_type = String)]
+    planned_by: Address,
+    planned: PlannedEvent,
+    status: PlanStatus,
+    // hash of the actual event, and timestamp in milliseconds of its block
+    #[schema(value_type = Option<String>)]
+    fulfilled_by: Option<TxHash>,
+    fulfilled_at: Option<i64>,
+}
+
+impl PlanView {
+    fn new(plan: Plan, now: i64) -> PlanView {
+        PlanView {
+            status: plan.status(now),
+            hash: plan.hash,
+            batch_id: plan.batch_id,
+            planned_by: plan.planned_by,
+            planned: plan.planned,
+            fulfilled_by: plan.fulfilled_by.map(|(hash, _)| hash),
+            fulfilled_at: plan.fulfilled_by.map(|(_, timestamp)| timestamp),
+        }
+    }
+}
+
+// Returns the events planned for a batch and where they are, e.g. a pickup that is still expected
+#[utoipa::path(
+    get,
+    path = "/batches/{batch_id}/plans",
+    params(("batch_id" = String, Path, description = "Identifier of the batch")),
+    responses((status = 200, description = "Planned events of the batch, in chain order", body = [PlanView]))
+)]
+async fn get_batch_plans(state: web::Data<ApiState>, batch_id: web::Path<String>) -> HttpResponse {
+    let now = Utc::now().timestamp_millis();
+    let plans: Vec<PlanView> = state
+        .blockchain
+        .plans_of(&batch_id)
+        .into_iter()
+        .map(|plan| PlanView::new(plan, now))
+        .collect();
+
+    HttpResponse::Ok().json(plans)
+}
+
+// Returns the planned events of all the batches that did not happen in time, for the logistics teams to follow up
+#[utoipa::path(
+    get,
+    path = "/plans/overdue",
+    responses((status = 200, description = "Overdue planned events, the most overdue first", body = [PlanView]))
+)]
+async fn get_overdue_plans(state: web::Data<ApiState>) -> HttpResponse {
+    let now = Utc::now().timestamp_millis();
+    let plans: Vec<PlanView> = state
+        .blockchain
+        .overdue_plans(now)
+        .into_iter()
+        .map(|plan| PlanView::new(plan, now))
+        .collect();
+
+    HttpResponse::Ok().json(plans)
+}
+
+// Compares the lifecycles of two batches side by side, e.g. to find out why only one of two shipments spoiled
+#[utoipa::path(
+    get,
+    path = "/batches/{batch_id}/comparison/{other_batch_id}",
+    params(
+        ("batch_id" = String, Path, description = "Identifier of the first batch (A)"),
+        ("other_batch_id" = String, Path, description = "Identifier of the second batch (B)"),
+    ),
+    responses(
+        (status = 200, description = "Stages of both batches and what differs between them", body = BatchComparison),
+        (status = 404, description = "One of the batches has no events", body = ErrorResponse),
+    )
+)]
+async fn get_batch_comparison(
+    state: web::Data<ApiState>,
+    path: web::Path<(String, String)>,
+) -> HttpResponse {
+    let (batch_a, batch_b) = path.into_inner();
+    let key = format!("batches/{}/comparison/{}", batch_a, batch_b);
+    compute_cached(&state, key, move |blocks| {
+        let comparison = compare_batches(blocks, &batch_a, &batch_b)?;
+        serde_json::to_string(&comparison).ok()
+    })
+    .await
+}
+
+#[derive(Deserialize, IntoParams)]
+struct SensorTrendQuery {
+    // "temperature" (the default) or "humidity"
+    metric: Option<String>,
+    // size of the buckets, an hour by default
+    bucket_minutes: Option<u64>,
+    // acceptable range, the readings outside of it are reported as violations
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+// Returns the SENSOR_READING events of a batch summarized per bucket of time, with the windows out of range
+// e.g. the hourly trend of a month of readings taken every minute in a cold room
+#[utoipa::path(
+    get,
+    path = "/batches/{batch_id}/sensors",
+    params(("batch_id" = String, Path, description = "Identifier of the batch"), SensorTrendQuery),
+    responses(
+        (status = 200, description = "Minimum, maximum and average of each bucket, and the violation windows", body = SensorTrend),
+        (status = 400, description = "Unknown metric or invalid bucket size", body = ErrorResponse),
+        (status = 404, description = "The batch has no readings of the metric", body = ErrorResponse),
+    )
+)]
+async fn get_sensor_trend(
+    state: web::Data<ApiState>,
+    batch_id: web::Path<String>,
+    query: web::Query<SensorTrendQuery>,
+) -> HttpResponse {
+    let metric = query.metric.as_deref().unwrap_or("temperature");
+    let metric = match SensorMetric::from_str(metric) {
+        Ok(metric) => metric,
+        Err(_) => {
+            let message = format!("Unknown metric {}", metric);
+            return ErrorResponse::new(ErrorCode::InvalidRequest, message).to_response();
+        }
+    };
+    let bucket_minutes = query.bucket_minutes.unwrap_or(60);
+    if bucket_minutes == 0 {
+        let message = "The buckets must last at least a minute";
+        return ErrorResponse::new(ErrorCode::InvalidRequest, message).to_response();
+    }
+    let options = TrendOptions {
+        metric,
+        bucket_ms: bucket_minutes as i64 * 60_000,
+        min: query.min,
+        max: query.max,
+    };
+
+    let key = format!(
+        "batches/{}/sensors/{}/{}/{:?}/{:?}",
+        batch_id,
+        metric.as_str(),
+        bucket_minutes,
+        options.min,
+        options.max
+    );
+    compute_cached(&state, key, move |blocks| {
+        let trend = sensor_trend(blocks, &batch_id, &options)?;
+        serde_json::to_string(&trend).ok()
+    })
+    .await
+}
+
+#[derive(Deserialize, IntoParams)]
+struct ComplianceQuery {
+    // usual thresholds of a kind of product: "dairy", "chilled" or "frozen"
+    profile: Option<String>,
+    // override the ones of the profile
+    min_temperature: Option<f64>,
+    max_temperature: Option<f64>,
+    min_humidity: Option<f64>,
+    max_humidity: Option<f64>,
+}
+
+// Checks the SENSOR_READINGS of a batch against thresholds, e.g. to prove to a buyer that its cold chain was never broken
+// The report can be attached to the batch as it is with a COMPLIANCE_REPORT event
+#[utoipa::path(
+    get,
+    path = "/batches/{batch_id}/compliance",
+    params(("batch_id" = String, Path, description = "Identifier of the batch"), ComplianceQuery),
+    responses(
+        (status = 200, description = "The readings out of the thresholds, up to the last block", body = ComplianceReport),
+        (status = 400, description = "Unknown profile, or no thresholds", body = ErrorResponse),
+    )
+)]
+async fn get_cold_chain_compliance(
+    state: web::Data<ApiState>,
+    batch_id: web::Path<String>,
+    query: web::Query<ComplianceQuery>,
+) -> HttpResponse {
+    let profile = match query.profile.as_deref() {
+        Some(name) => match ComplianceThresholds::profile(name) {
+            Some(thresholds) => thresholds,
+            None => {
+                let message = format!("Unknown profile {}", name);
+                return ErrorResponse::new(ErrorCode::InvalidRequest, message).to_response();
+            }
+        },
+        None => ComplianceThresholds::default(),
+    };
+    let thresholds = ComplianceThresholds {
+        min_temperature: query.min_temperature.or(profile.min_temperature),
+        max_temperature: query.max_temperature.or(profile.max_temperature),
+        min_humidity: query.min_humidity.or(profile.min_humidity),
+        max_humidity: query.max_humidity.or(profile.max_humidity),
+    };
+    if thresholds.is_empty() {
+        let message = "A profile or at least one threshold is required";
+        return ErrorResponse::new(ErrorCode::InvalidRequest, message).to_response();
+    }
+
+    let key = format!("batches/{}/compliance/{:?}", batch_id, thresholds);
+    compute_cached(&state, key, move |blocks| {
+        let report = ComplianceReport::scan(&batch_id, blocks, &thresholds);
+        serde_json::to_string(&report).ok()
+    })
+    .await
+}
+
+#[derive(Serialize, ToSchema)]
+struct BatchEvent {
+    block: BlockRef,
+    transaction: Transaction,
+}
+
+// Returns all the events of a batch in chain order (its provenance), with where each one was mined
+#[utoipa::path(
+    get,
+    path = "/batches/{batch_id}/history",
+    params(("batch_id" = String, Path, description = "Identifier of the batch"), Pagination),
+    responses(
+        (status = 200, description = "Events of the batch, from the oldest", body = [BatchEvent]),
+        (status = 404, description = "The batch has no events", body = ErrorResponse),
+    )
+)]
+async fn get_batch_history(
+    state: web::Data<ApiState>,
+    batch_id: web::Path<String>,
+    pagination: web::Query<Pagination>,
+) -> HttpResponse {
+    let blockchain = &state.blockchain;
+    let history = || -> Vec<BatchEvent> {
+        blockchain
+            .history_for_batch(&batch_id)
+            .into_iter()
+            .map(|(block, transaction)| BatchEvent { block, transaction })
+            .collect()
+    };
+    if pagination.is_requested() {
+        let history = history();
+        if history.is_empty() {
+            return ErrorResponse::new(ErrorCode::NotFound, "Not found").to_response();
+        }
+        return pagination.response(&history);
+    }
+
+    let tip = blockchain.get_last_block().header.hash;
+    let key = format!("batches/{}/history", batch_id);
+    let history_json = state.cache.get_or_compute(tip, &key, || {
+        let history = history();
+        match history.is_empty() {
+            true => None,
+            false => serde_json::to_string(&history).ok(),
+        }
+    });
+
+    cached_json_response(history_json)
+}
+
+// Returns the events of a batch with their inclusion proofs, for the apps that verify it offline
+#[utoipa::path(
+    get,
+    path = "/batches/{batch_id}/bundle",
+    params(("batch_id" = String, Path, description = "Identifier of the batch")),
+    responses(
+        (status = 200, description = "Events of the batch from the oldest, each with the header of its block and its merkle proof", body = BatchBundle),
+        (status = 404, description = "The batch has no events", body = ErrorResponse),
+    )
+)]
+async fn get_batch_bundle(state: web::Data<ApiState>, batch_id: web::Path<String>) -> HttpResponse {
+    let blockchain = &state.blockchain;
+    let tip = blockchain.get_last_block().header.hash;
+    let key = format!("batches/{}/bundle", batch_id);
+    let bundle_json = state.cache.get_or_compute(tip, &key, || {
+        let events: Vec<BundledEvent> = blockchain
+            .history_for_batch(&batch_id)
+            .into_iter()
+            .filter_map(|(block_ref, transaction)| {
+                let block = blockchain.get_block(block_ref.index)?;
+                let inclusion = InclusionProof {
+                    header: block.header.clone(),
+                    proof: block.proof_for(block_ref.position)?,
+                };
+                Some(BundledEvent {
+                    transaction,
+                    inclusion,
+                })
+            })
+            .collect();
+        if events.is_empty() {
+            return None;
+        }
+        let bundle = BatchBundle {
+            batch_id: batch_id.to_string(),
+            events,
+        };
+        serde_json::to_string(&bundle).ok()
+    });
+
+    cached_json_response(bundle_json)
+}
+
+#[derive(Serialize, ToSchema)]
+struct ThreadEvent {
+    block: BlockRef,
+    #[schema(value_type = String)]
+    hash: TxHash,
+    transaction: Transaction,
+}
+
+// Returns the causal thread of a transaction (e.g. an OFFER and its ACCEPT), following the in_response_to links
+#[utoipa::path(
+    get,
+    path = "/transactions/{hash}/thread",
+    params(("hash" = String, Path, description = "Hash of any transaction of the thread")),
+    responses(
+        (status = 200, description = "Transactions of the thread, from the one that started it", body = [ThreadEvent]),
+        (status = 400, description = "Invalid transaction hash", body = ErrorResponse),
+        (status = 404, description = "No transaction with that hash in the chain", body = ErrorResponse),
+    )
+)]
+async fn get_transaction_thread(
+    state: web::Data<ApiState>,
+    hash: web::Path<String>,
+) -> HttpResponse {
+    let hash = match TxHash::from_str(&hash) {
+        Ok(hash) => hash,
+        Err(_) => {
+            return ErrorResponse::new(ErrorCode::InvalidHash, "Invalid transaction hash")
+                .to_response()
+        }
+    };
+
+    let blockchain = &state.blockchain;
+    let tip = blockchain.get_last_block().header.hash;
+    let key = format!("transactions/{:#x}/thread", hash);
+    let thread_json = state.cache.get_or_compute(tip, &key, || {
+        let thread: Vec<ThreadEvent> = blockchain
+            .thread_of(&hash)
+            .into_iter()
+            .map(|(block, hash, transaction)| ThreadEvent {
+                block,
+                hash,
+                transaction,
+            })
+            .collect();
+        match thread.is_empty() {
+            true => None,
+            false => serde_json::to_string(&thread).ok(),
+        }
+    });
+
+    cached_json_response(thread_json)
+}
+
+// Suggests which batches held by an actor (e.g. a warehouse) to ship next
+// The ones that expire first, then the ones without a shelf life that it holds for the longest time
+#[utoipa::path(
+    get,
+    path = "/custodians/{address}/picking",
+    params(("address" = String, Path, description = "Address of the actor holding the batches")),
+    responses(
+        (status = 200, description = "Batches held by the actor, the first one to ship first", body = [PickingSuggestion]),
+        (status = 400, description = "Invalid address", body = ErrorResponse),
+    )
+)]
+async fn get_picking_suggestions(
+    state: web::Data<ApiState>,
+    address: web::Path<String>,
+) -> HttpResponse {
+    let address = match Address::from_str(&address) {
+        Ok(address) => address,
+        Err(_) => {
+            return ErrorResponse::new(ErrorCode::InvalidAddress, "Invalid address").to_response()
+        }
+    };
+
+    let key = format!("custodians/{}/picking", address);
+    compute_cached(&state, key, move |blocks| {
+        let suggestions = picking_suggestions(blocks, &address);
+        serde_json::to_string(&suggestions).ok()
+    })
+    .await
+}
+
+// Returns the latest profile published by an actor
+#[utoipa::path(
+    get,
+    path = "/profiles/{address}",
+    params(("address" = String, Path, description = "Address of the actor")),
+    responses(
+        (status = 200, description = "Latest profile of the actor", body = Profile),
+        (status = 400, description = "Invalid address", body = ErrorResponse),
+        (status = 404, description = "The actor has not published a profile", body = ErrorResponse),
+    )
+)]
+async fn get_profile(state: web::Data<ApiState>, address: web::Path<String>) -> HttpResponse {
+    let address = match Address::from_str(&address) {
+        Ok(address) => address,
+        Err(_) => {
+            return ErrorResponse::new(ErrorCode::InvalidAddress, "Invalid address").to_response()
+        }
+    };
+
+    let blockchain = &state.blockchain;
+    let tip = blockchain.get_last_block().header.hash;
+    let key = format!("profiles/{}", address);
+    let profile_json = state.cache.get_or_compute(tip, &key, || {
+        let profile = blockchain.get_profile(&address)?;
+        serde_json::to_string(&profile).ok()
+    });
+
+    cached_json_response(profile_json)
+}
+
+#[derive(Serialize, ToSchema)]
+struct ActorRegistryListing {
+    // sender of the first registration, none while anyone can emit any event
+    #[schema(value_type = Option<String>)]
+    registrar: Option<Address>,
+    actors: Vec<RegisteredActor>,
+}
+
+// Returns the actors registered on chain with their roles, which restrict the lifecycle events they can emit
+#[utoipa::path(
+    get,
+    path = "/registry",
+    responses(
+        (status = 200, description = "The registrar and the actors with at least one role", body = ActorRegistryListing),
+    )
+)]
+async fn get_registry(state: web::Data<ApiState>) -> HttpResponse {
+    let registry = state.blockchain.get_actor_registry();
+
+    HttpResponse::Ok().json(ActorRegistryListing {
+        registrar: registry.registrar().cloned(),
+        actors: registry.actors(),
+    })
+}
+
+// Adds a transaction about a batch of the namespace to the pool, it's signed so its batch id must already be
+// prefixed with the namespace
+#[utoipa::path(
+    post,
+    path = "/ns/{namespace}/transactions",
+    params(("namespace" = String, Path, description = "Namespace of the consortium")),
+    request_body = Transaction,
+    responses(
+        (status = 200, description = "The transaction was added to the pool", body = TransactionSubmission),
+        (status = 400, description = "Invalid transaction or namespace, or a batch of another namespace", body = ErrorResponse),
+        (status = 503, description = "The node is in maintenance", body = ErrorResponse),
+    )
+)]
+async fn add_namespace_transaction(
+    state: web::Data<ApiState>,
+    request: HttpRequest,
+    namespace: web::Path<String>,
+    transaction_json: web::Json<Transaction>,
+) -> HttpResponse {
+    let namespace = match parse_namespace(&namespace) {
+        Ok(namespace) => namespace,
+        Err(response) => return response,
+    };
+    let transaction = transaction_json.into_inner();
+    if let Some(response) = reject_foreign_batch(&namespace, &transaction.batch_id) {
+        return response;
+    }
+
+    add_transaction(state, request, web::Json(transaction)).await
+}
+
+// Returns the transactions about the batches of a namespace, with the same filters as /transactions
+#[utoipa::path(
+    get,
+    path = "/ns/{namespace}/transactions",
+    params(("namespace" = String, Path, description = "Namespace of the consortium"), TransactionsQuery, Pagination),
+    responses(
+        (status = 200, description = "Matching transactions of the namespace", body = [Transaction]),
+        (status = 400, description = "Invalid JSONPath expression, address or namespace", body = ErrorResponse),
+    )
+)]
+async fn get_namespace_transactions(
+    state: web::Data<ApiState>,
+    namespace: web::Path<String>,
+    query: web::Query<TransactionsQuery>,
+    pagination: web::Query<Pagination>,
+) -> HttpResponse {
+    let namespace = match parse_namespace(&namespace) {
+        Ok(namespace) => namespace,
+        Err(response) => return response,
+    };
+    let mut query = query.into_inner();
+    query.batch_id = query.batch_id.map(|batch_id| namespace.qualify(&batch_id));
+
+    list_transactions(&state, &query, &pagination, Some(&namespace))
+}
+
+// Returns the history of a batch of the namespace, its id can be given without the namespace
+#[utoipa::path(
+    get,
+    path = "/ns/{namespace}/batches/{batch_id}/history",
+    params(
+        ("namespace" = String, Path, description = "Namespace of the consortium"),
+        ("batch_id" = String, Path, description = "Identifier of the batch"),
+        Pagination,
+    ),
+    responses(
+        (status = 200, description = "Events of the batch, from the oldest", body = [BatchEvent]),
+        (status = 400, description = "Invalid namespace, or a batch of another namespace", body = ErrorResponse),
+        (status = 404, description = "The batch has no events", body = ErrorResponse),
+    )
+)]
+async fn get_namespace_batch_history(
+    state: web::Data<ApiState>,
+    path: web::Path<(String, String)>,
+    pagination: web::Query<Pagination>,
+) -> HttpResponse {
+    let (namespace, batch_id) = path.into_inner();
+    let namespace = match parse_namespace(&namespace) {
+        Ok(namespace) => namespace,
+        Err(response) => return response,
+    };
+    let batch_id = namespace.qualify(&batch_id);
+    if let Some(response) = reject_foreign_batch(&namespace, &batch_id) {
+        return response;
+    }
+
+    get_batch_history(state, web::Path::from(batch_id), pagination).await
+}
+
+// Returns the actors registered in a namespace with their roles
+#[utoipa::path(
+    get,
+    path = "/ns/{namespace}/registry",
+    params(("namespace" = String, Path, description = "Namespace of the consortium")),
+    responses(
+        (status = 200, description = "The registrar and the actors of the namespace", body = ActorRegistryListing),
+        (status = 400, description = "Invalid namespace", body = ErrorResponse),
+    )
+)]
+async fn get_namespace_registry(
+    state: web::Data<ApiState>,
+    namespace: web::Path<String>,
+) -> HttpResponse {
+    let namespace = match parse_namespace(&namespace) {
+        Ok(namespace) => namespace,
+        Err(response) => return response,
+    };
+    let registry = state.blockchain.get_actor_registry();
+    let actors = registry
+        .actors()
+        .into_iter()
+        .filter(|actor| actor.namespace.as_ref() == Some(&namespace))
+        .collect();
 
+    HttpResponse::Ok().json(ActorRegistryListing {
+        registrar: registry.registrar().cloned(),
+        actors,
+    })
+}
+
+#[derive(Deserialize, ToSchema)]
+struct LotRequest {
+    // actor that will own the lot, the recipient of the LOT event signed by the node
+    #[schema(value_type = String)]
+    sender: Address,
+    prefix: String,
+    season: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct AllocatedLot {
+    lot_id: String,
+    prefix: String,
+    season: String,
+    sequence: u64,
+}
+
+// Issues the next unique lot identifier for a prefix and season (e.g. "WHEAT-2024-0007")
+// The allocation is recorded on chain, so the lot is guaranteed to be unique once the next block is mined
+#[utoipa::path(
+    post,
+    path = "/lots",
+    request_body = LotRequest,
+    responses(
+        (status = 200, description = "The allocated lot, owned by the requested actor once the next block is mined", body = AllocatedLot),
+        (status = 400, description = "Invalid prefix or season", body = ErrorResponse),
+        (status = 503, description = "The node is in maintenance", body = ErrorResponse),
+    )
+)]
+async fn allocate_lot(state: web::Data<ApiState>, request: web::Json<LotRequest>) -> HttpResponse {
+    if let Some(response) = reject_writes(&state) {
+        return response;
+    }
+
+    let request = request.into_inner();
+    let allocator = &state.lot_allocator;
+    let lot = match allocator.allocate(&request.sender, &request.prefix, &request.season) {
+        Ok(lot) => lot,
+        Err(error) => return ErrorResponse::new(ErrorCode::InvalidLot, error).to_response(),
+    };
+    info!("Allocated lot {}", lot.id());
+
+    HttpResponse::Ok().json(AllocatedLot {
+        lot_id: lot.id(),
+        prefix: lot.prefix,
+        season: lot.season,
+        sequence: lot.sequence,
+    })
+}
+
+#[derive(Deserialize, ToSchema)]
+struct DocumentRequest {
+    #[schema(value_type = String)]
+    recipient: Address,
+    batch_id: String,
+    name: Option<String>,
+    // the whole document, binary documents must be encoded first (e.g. in base64)
+    content: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct SubmittedDocument {
+    hash: String,
+    chunks: usize,
+    // other batches with the same document, e.g. a lab report reused as the certificate of another harvest
+    warnings: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct DeliveredDocument {
+    hash: String,
+    name: Option<String>,
+    batch_id: String,
+    #[schema(value_type = String)]
+    sender: Address,
+    content: String,
+}
+
+// Splits a document too large for a single transaction in a DOCUMENT event and its CHUNK events,
+// sent and signed by the node. The document is delivered once all of them are mined
+#[utoipa::path(
+    post,
+    path = "/documents",
+    request_body = DocumentRequest,
+    responses(
+        (status = 200, description = "The hash that identifies the document and its number of chunks", body = SubmittedDocument),
+        (status = 400, description = "Invalid document", body = ErrorResponse),
+        (status = 503, description = "The node is in maintenance", body = ErrorResponse),
+    )
+)]
+async fn add_document(
+    state: web::Data<ApiState>,
+    request: HttpRequest,
+    document_json: web::Json<DocumentRequest>,
+) -> HttpResponse {
+    if let Some(response) = reject_writes(&state) {
+        return response;
+    }
+
+    let document = document_json.into_inner();
+    let transaction = Transaction {
+        sender: state.node_wallet.address(),
+        recipient: document.recipient,
+        batch_id: document.batch_id,
+        ..Default::default()
+    };
+
+    let mut transactions = DocumentChunk::split(&transaction, &document.content, document.name);
+    for transaction in transactions.iter_mut() {
+        let result = state
+            .node_wallet
+            .sign(transaction)
+            .and_then(|_| state.blockchain.validate_transaction(transaction));
+        if let Err(error) = result {
+            return ErrorResponse::new(ErrorCode::InvalidTransaction, error).to_response();
+        }
+    }
+
+    // the manifest goes first, as the hash identifies the document
+    let manifest = &transactions[0];
+    let submitted = SubmittedDocument {
+        hash: DocumentManifest::parse(&manifest.data.as_json())
+            .unwrap()
+            .hash,
+        chunks: transactions.len() - 1,
+        warnings: state.fingerprints.check(manifest),
+    };
+    info!("Submitted document {}", submitted.hash);
+    for warning in submitted.warnings.iter() {
+        warn!("{} (batch {})", warning, manifest.batch_id);
+    }
+    for transaction in transactions.into_iter() {
+        record_origin(&state, &request, &transaction, OriginChannel::Api);
+        state.pool.add_transaction(transaction);
+    }
+
+    HttpResponse::Ok().json(submitted)
+}
+
+// Returns a document reassembled from its chunks, once all of them are on chain and match its hash
+#[utoipa::path(
+    get,
+    path = "/documents/{hash}",
+    params(("hash" = String, Path, description = "Hex encoded sha256 of the document")),
+    responses(
+        (status = 200, description = "The delivered document", body = DeliveredDocument),
+        (status = 404, description = "Unknown document, or some chunks are missing", body = ErrorResponse),
+        (status = 409, description = "The chunks don't match the hash of the document", body = ErrorResponse),
+    )
+)]
+async fn get_document(state: web::Data<ApiState>, hash: web::Path<String>) -> HttpResponse {
+    let document = match state.blockchain.get_document(&hash) {
+        Some(document) => document,
+        None => return ErrorResponse::new(ErrorCode::NotFound, "Document not found").to_response(),
+    };
+
+    match document.content {
+        Ok(content) => HttpResponse::Ok().json(DeliveredDocument {
+            hash: document.manifest.hash,
+            name: document.manifest.name,
+            batch_id: document.transaction.batch_id,
+            sender: document.transaction.sender,
+            content,
+        }),
+        Err(DocumentError::Incomplete(missing)) => {
+            let message = "The document has not been delivered yet";
+            ErrorResponse::new(ErrorCode::IncompleteDocument, message)
+                .with_details(serde_json::json!({ "missing_chunks": missing }))
+                .to_response()
+        }
+        Err(error @ DocumentError::Corrupted) => {
+            ErrorResponse::new(ErrorCode::CorruptedDocument, error).to_response()
+        }
+    }
+}
+
+// Returns the events of the chain with the hash of a document or attachment, in any batch
+#[utoipa::path(
+    get,
+    path = "/fingerprints/{hash}",
+    params(("hash" = String, Path, description = "Hex encoded hash of the document or attachment")),
+    responses(
+        (status = 200, description = "The events with the hash, the oldest first", body = [fingerprint_registry::Fingerprint]),
+        (status = 404, description = "No event has the hash", body = ErrorResponse),
+    )
+)]
+async fn get_fingerprints(state: web::Data<ApiState>, hash: web::Path<String>) -> HttpResponse {
+    let fingerprints = state.fingerprints.find(&hash);
+    match fingerprints.is_empty() {
+        true => ErrorResponse::new(ErrorCode::NotFound, "Fingerprint not found").to_response(),
+        false => HttpResponse::Ok().json(fingerprints),
+    }
+}
+
+// Returns the hashes attached to more than one batch, to audit the reuse of certificates and lab reports
+#[utoipa::path(
+    get,
+    path = "/fingerprints/reused",
+    responses(
+        (status = 200, description = "The hashes found in several batches", body = [fingerprint_registry::ReusedFingerprint]),
+    )
+)]
+async fn get_reused_fingerprints(state: web::Data<ApiState>) -> HttpResponse {
+    HttpResponse::Ok().json(state.fingerprints.reused())
+}
+
+#[derive(Serialize, ToSchema)]
+struct ProvisionedActor {
+    #[schema(value_type = String)]
+    address: Address,
+    // hex of the ed25519 secret key of the actor, to sign its events
+    secret_key: String,
+    profile: Profile,
+}
+
+// Creates a new actor with the requested profile, registered with its roles by the registrar (test networks only)
+// The events are added to the pool, so the actor will exist once the next block is mined
+// The keypair of the actor is generated by the node and returned, as test actors hold nothing of value
+#[utoipa::path(
+    post,
+    path = "/faucet/actors",
+    request_body = Profile,
+    responses(
+        (status = 200, description = "The new actor, registered once the next block is mined", body = ProvisionedActor),
+        (status = 400, description = "Invalid profile", body = ErrorResponse),
+        (status = 404, description = "The node is not running on a test network", body = ErrorResponse),
+        (status = 503, description = "The node is in maintenance", body = ErrorResponse),
+    )
+)]
+async fn provision_actor(
+    state: web::Data<ApiState>,
+    profile_json: web::Json<Profile>,
+) -> HttpResponse {
+    if let Some(response) = reject_writes(&state) {
+        return response;
+    }
+
+    let faucet = match &state.faucet {
+        Some(faucet) => faucet,
+        None => {
+            let message = "The faucet is only available on testnet";
+            return ErrorResponse::new(ErrorCode::FaucetUnavailable, message).to_response();
+        }
+    };
+
+    let profile = profile_json.into_inner();
+    let (wallet, transactions) = match faucet.provision_actor(&profile) {
+        Ok(provisioned) => provisioned,
+        Err(error) => {
+            return ErrorResponse::new(ErrorCode::InvalidTransaction, error).to_response()
+        }
+    };
+
+    // checked against the chain like any submitted transaction, so they can't make the next block invalid
+    // e.g. the registration is refused when the chain already has another registrar
+    let mut pending = state.pool.get_unconfirmed();
+    for transaction in transactions.iter() {
+        if let Err(error) = state
+            .blockchain
+            .validate_transaction_after(transaction, &pending)
+        {
+            return ErrorResponse::new(ErrorCode::InvalidTransaction, error).to_response();
+        }
+        pending.push(transaction.clone());
+    }
+    for transaction in transactions {
+        state.pool.add_transaction(transaction);
+    }
+
+    HttpResponse::Ok().json(ProvisionedActor {
+        address: wallet.address(),
+        secret_key: wallet.secret_hex(),
+        profile,
+    })
+}
+
+// Returns a list of all the peers known by the node, to let other nodes discover them
+#[utoipa::path(
+    get,
+    path = "/peers",
+    responses((status = 200, description = "Addresses of the known peers", body = [String]))
+)]
+async fn get_peers(state: web::Data<ApiState>) -> impl Responder {
+    let peers = state.peers.get_all_peers();
+
+    // the peers exchange is the handshake of the nodes, where they advertise their version
     HttpResponse::Ok()
+        .insert_header((PROTOCOL_VERSION_HEADER, PROTOCOL_VERSION.to_string()))
+        .json(&peers)
+}
+
+// Returns a list of all the blocks that ended up outside of the main chain
+#[utoipa::path(
+    get,
+    path = "/forks",
+    responses((status = 200, description = "All the orphaned blocks", body = [OrphanedBlock]))
+)]
+async fn get_forks(state: web::Data<ApiState>) -> impl Responder {
+    let orphaned_blocks = state.blockchain.get_orphaned_blocks();
+
+    HttpResponse::Ok().json(&orphaned_blocks)
+}
+
+// Returns a single orphaned block (and the metadata of why it was orphaned) by its hash
+#[utoipa::path(
+    get,
+    path = "/forks/{hash}",
+    params(("hash" = String, Path, description = "Hash of the orphaned block")),
+    responses(
+        (status = 200, description = "The orphaned block", body = OrphanedBlock),
+        (status = 400, description = "Invalid block hash", body = ErrorResponse),
+        (status = 404, description = "No orphaned block with that hash", body = ErrorResponse),
+    )
+)]
+async fn get_fork(state: web::Data<ApiState>, hash: web::Path<String>) -> HttpResponse {
+    let hash = match BlockHash::from_str(&hash) {
+        Ok(hash) => hash,
+        Err(_) => {
+            return ErrorResponse::new(ErrorCode::InvalidHash, "Invalid block hash").to_response()
+        }
+    };
+
+    match state.blockchain.get_orphaned_block(&hash) {
+        Some(orphaned_block) => HttpResponse::Ok().json(&orphaned_block),
+        None => ErrorResponse::new(ErrorCode::NotFound, "Orphaned block not found").to_response(),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+struct ChangesQuery {
+    // cursor of the last change already processed, none to start from the first change
+    cursor: Option<u64>,
+    // max amount of changes to return (100 by default, at most 1000)
+    limit: Option<usize>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ChangeEntry {
+    #[serde(flatten)]
+    change: Change,
+    // the block that was applied, archived or rolled back, none if the node doesn't keep it anymore
+    block: Option<Block>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ChangePage {
+    changes: Vec<ChangeEntry>,
+    // cursor to ask for the next changes, once these ones are processed
+    next_cursor: u64,
+}
+
+// Returns the changes of the chain after a cursor, in the order the node applied them
+// Clients store the next cursor only after processing the changes, so they get each change at least once
+#[utoipa::path(
+    get,
+    path = "/changes",
+    params(ChangesQuery),
+    responses(
+        (status = 200, description = "The next changes of the chain", body = ChangePage),
+        (status = 400, description = "The cursor is after the last change", body = ErrorResponse),
+    )
+)]
+async fn get_changes(state: web::Data<ApiState>, query: web::Query<ChangesQuery>) -> HttpResponse {
+    if let Err(error) = state.changes.sync(&state.blockchain) {
+        return ErrorResponse::new(ErrorCode::Internal, error).to_response();
+    }
+
+    let cursor = query.cursor.unwrap_or(0);
+    let last_cursor = state.changes.last_cursor();
+    if cursor > last_cursor {
+        let message = format!(
+            "The cursor {} is after the last change {}",
+            cursor, last_cursor
+        );
+        return ErrorResponse::new(ErrorCode::InvalidCursor, message).to_response();
+    }
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_CHANGES_LIMIT)
+        .min(MAX_CHANGES_LIMIT);
+    let changes: Vec<ChangeEntry> = state
+        .changes
+        .after(cursor, limit)
+        .into_iter()
+        .map(|change| {
+            let block = match change.kind {
+                ChangeKind::BlockApplied => state
+                    .blockchain
+                    .get_block(change.index)
+                    .filter(|block| block.header.hash == change.hash),
+                ChangeKind::ForkArchived | ChangeKind::BlockRolledBack => state
+                    .blockchain
+                    .get_orphaned_block(&change.hash)
+                    .map(|orphaned| orphaned.block),
+            };
+            ChangeEntry { change, block }
+        })
+        .collect();
+    let next_cursor = changes.last().map_or(cursor, |entry| entry.change.cursor);
+
+    HttpResponse::Ok().json(ChangePage {
+        changes,
+        next_cursor,
+    })
+}
+
+#[derive(Deserialize, IntoParams)]
+struct SubscriptionQuery {
+    // comma-separated batches whose transactions are pushed, more can be followed later with messages
+    batch_id: Option<String>,
+}
+
+// Pushes the new blocks and the transactions of the followed batches through a WebSocket,
+// e.g. to the dashboards of the cold-chain shipments, instead of polling the node
+// The clients change the followed batches with {"action": "subscribe" | "unsubscribe", "batch_id": ...}
+#[utoipa::path(
+    get,
+    path = "/ws",
+    params(SubscriptionQuery),
+    responses(
+        (status = 101, description = "WebSocket of JSON notifications", body = Notification),
+        (status = 400, description = "Not a WebSocket handshake", body = ErrorResponse),
+    )
+)]
+async fn subscribe_events(
+    state: web::Data<ApiState>,
+    request: HttpRequest,
+    body: web::Payload,
+    query: web::Query<SubscriptionQuery>,
+) -> HttpResponse {
+    let (response, session, messages) = match actix_ws::handle(&request, body) {
+        Ok(handshake) => handshake,
+        Err(error) => return ErrorResponse::new(ErrorCode::InvalidRequest, error).to_response(),
+    };
+
+    let batch_ids: Vec<String> = match &query.batch_id {
+        Some(batch_ids) => batch_ids.split(',').map(str::to_string).collect(),
+        None => Vec::new(),
+    };
+    // subscribed before answering the handshake, so the client misses no event after it
+    let events = state.blockchain.event_bus().subscribe();
+    actix_web::rt::spawn(subscriptions::push_notifications(
+        session,
+        messages,
+        events,
+        Subscription::new(&batch_ids).with_saved_queries(state.saved_queries.clone()),
+    ));
+
+    response
+}
+
+#[derive(Deserialize, IntoParams)]
+struct StatsQuery {
+    // index of the first block to include in the time series
+    from: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ChainStatistics {
+    totals: StatsTotals,
+    blocks: Vec<BlockStats>,
+}
+
+// Returns the aggregated statistics of the chain and the time series of per-block statistics
+#[utoipa::path(
+    get,
+    path = "/stats",
+    params(StatsQuery),
+    responses((status = 200, description = "Statistics of the chain", body = ChainStatistics))
+)]
+async fn get_stats(state: web::Data<ApiState>, query: web::Query<StatsQuery>) -> impl Responder {
+    let blockchain = &state.blockchain;
+    let stats = ChainStatistics {
+        totals: blockchain.get_stats_totals(),
+        blocks: blockchain.get_block_stats(query.from.unwrap_or(0)),
+    };
+
+    HttpResponse::Ok().json(stats)
+}
+
+// Returns the harvested volumes per crop and region, protected against revealing the volumes of members
+#[utoipa::path(
+    get,
+    path = "/stats/public",
+    responses(
+        (status = 200, description = "Volumes per crop and region released at the end of the last epoch, with noise", body = [PublicAggregate]),
+        (status = 404, description = "The salt of the consortium is not configured", body = ErrorResponse)
+    )
+)]
+async fn get_public_stats(state: web::Data<ApiState>) -> HttpResponse {
+    if !state.public_stats.is_available() {
+        let message = "The public statistics require the PUBLIC_STATS_SALT of the consortium";
+        return ErrorResponse::new(ErrorCode::NotFound, message).to_response();
+    }
+
+    let stats = state.clone();
+    compute_cached(&state, "stats/public".to_string(), move |blocks| {
+        stats
+            .public_stats
+            .publish(blocks)
+            .and_then(|aggregates| serde_json::to_string(&aggregates).ok())
+    })
+    .await
+}
+
+#[derive(Deserialize, IntoParams)]
+struct SlaReportsQuery {
+    // only the partnerships of this actor, as shipper or receiver
+    address: Option<String>,
+}
+
+// Returns how each pair of partners complied with their SLA over the completed handoffs of the chain
+#[utoipa::path(
+    get,
+    path = "/sla/reports",
+    params(SlaReportsQuery),
+    responses(
+        (status = 200, description = "Compliance of each pair of partners", body = [PartnerCompliance]),
+        (status = 400, description = "Invalid address", body = ErrorResponse),
+    )
+)]
+async fn get_sla_reports(
+    state: web::Data<ApiState>,
+    query: web::Query<SlaReportsQuery>,
+) -> HttpResponse {
+    let address = match query.address.as_deref().map(Address::from_str).transpose() {
+        Ok(address) => address,
+        Err(_) => {
+            return ErrorResponse::new(ErrorCode::InvalidAddress, "Invalid address").to_response()
+        }
+    };
+
+    let key = match &address {
+        Some(address) => format!("sla/reports/{}", address),
+        None => "sla/reports".to_string(),
+    };
+    compute_cached(&state, key, move |blocks| {
+        let reports: Vec<PartnerCompliance> = compliance_reports(blocks)
+            .into_iter()
+            .filter(|report| match &address {
+                Some(address) => report.shipper == *address || report.receiver == *address,
+                None => true,
+            })
+            .collect();
+        serde_json::to_string(&reports).ok()
+    })
+    .await
+}
+
+// Returns operational metrics of the node in the Prometheus text format
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses((status = 200, description = "Metrics in the Prometheus text format", body = String, content_type = "text/plain"))
+)]
+async fn get_metrics(state: web::Data<ApiState>) -> HttpResponse {
+    let cache_stats = state.cache.get_stats();
+    let chain_totals = state.blockchain.get_stats_totals();
+    let anomaly_scores = state.anomaly_scores.get();
+    let event_counts = state.event_counters.get();
+    let metrics = [
+        ("query_cache_hits_total", "counter", cache_stats.hits as f64),
+        (
+            "query_cache_misses_total",
+            "counter",
+            cache_stats.misses as f64,
+        ),
+        ("query_cache_entries", "gauge", cache_stats.entries as f64),
+        ("chain_blocks", "gauge", chain_totals.blocks as f64),
+        ("chain_size_bytes", "gauge", chain_totals.size as f64),
+        (
+            "chain_next_difficulty",
+            "gauge",
+            state.blockchain.next_difficulty() as f64,
+        ),
+        (
+            "chain_last_block_time_ms",
+            "gauge",
+            chain_totals.last_block_time_ms as f64,
+        ),
+        ("anomaly_burst_score", "gauge", anomaly_scores.burst),
+        ("anomaly_event_mix_score", "gauge", anomaly_scores.event_mix),
+        (
+            "anomaly_payload_entropy_score",
+            "gauge",
+            anomaly_scores.payload_entropy,
+        ),
+        (
+            "events_blocks_applied_total",
+            "counter",
+            event_counts.blocks_applied as f64,
+        ),
+        (
+            "events_forks_archived_total",
+            "counter",
+            event_counts.forks_archived as f64,
+        ),
+        (
+            "events_transactions_dropped_total",
+            "counter",
+            event_counts.transactions_dropped as f64,
+        ),
+        (
+            "chain_last_fork_height",
+            "gauge",
+            event_counts.last_fork_height as f64,
+        ),
+        ("events_reorgs_total", "counter", event_counts.reorgs as f64),
+        (
+            "chain_last_reorg_depth",
+            "gauge",
+            event_counts.last_reorg_depth as f64,
+        ),
+        ("protocol_version", "gauge", PROTOCOL_VERSION as f64),
+        // the node must be upgraded when it's not 0
+        (
+            "upgrade_advisories",
+            "gauge",
+            upgrade_advisories(
+                &state.peers.protocol_versions(),
+                state.blockchain.activations(),
+                chain_totals.blocks.saturating_sub(1),
+            )
+            .len() as f64,
+        ),
+        // 1 if the node produces blocks, always the case outside of a cluster
+        (
+            "cluster_leader",
+            "gauge",
+            state.leader_lease.is_leader() as u8 as f64,
+        ),
+        // the term of the cluster that the node leads, 0 if it's not the leader
+        (
+            "cluster_term",
+            "gauge",
+            state.leader_lease.term().unwrap_or_default() as f64,
+        ),
+    ];
+
+    let mut body: String = metrics
+        .iter()
+        .map(|(name, kind, value)| format!("# TYPE {} {}\n{} {}\n", name, kind, name, value))
+        .collect();
+
+    // the transactions are labeled by their event type
+    body.push_str("# TYPE chain_transactions gauge\n");
+    for (event_type, count) in chain_totals.transactions_by_type.iter() {
+        body.push_str(&format!(
+            "chain_transactions{{event_type=\"{}\"}} {}\n",
+            escape_label(event_type),
+            count
+        ));
+    }
+    body.push_str("# TYPE events_transactions_accepted_total counter\n");
+    for (event_type, count) in event_counts.transactions_accepted.iter() {
+        body.push_str(&format!(
+            "events_transactions_accepted_total{{event_type=\"{}\"}} {}\n",
+            escape_label(event_type),
+            count
+        ));
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body)
+}
+
+#[derive(Deserialize, IntoParams)]
+struct OriginsQuery {
+    batch_id: Option<String>,
+    sender: Option<String>,
+}
+
+// Returns where and when this node first saw the transactions, for forensic investigations (admin only)
+#[utoipa::path(
+    get,
+    path = "/admin/origins",
+    params(OriginsQuery),
+    responses(
+        (status = 200, description = "Origins of the transactions, the oldest first", body = [TransactionOrigin]),
+        (status = 401, description = "Missing or wrong admin token", body = ErrorResponse),
+    )
+)]
+async fn get_origins(
+    state: web::Data<ApiState>,
+    request: HttpRequest,
+    query: web::Query<OriginsQuery>,
+) -> HttpResponse {
+    if !is_admin(&state, &request) {
+        let message = "A valid admin token is required";
+        return ErrorResponse::new(ErrorCode::Unauthorized, message).to_response();
+    }
+
+    let origins = state.origins.find(|origin| {
+        let batch_matches = query
+            .batch_id
+            .as_ref()
+            .is_none_or(|batch_id| origin.batch_id == *batch_id);
+        let sender_matches = query
+            .sender
+            .as_ref()
+            .is_none_or(|sender| origin.sender.to_string() == *sender);
+        batch_matches && sender_matches
+    });
+
+    HttpResponse::Ok().json(origins)
+}
+
+#[derive(Serialize, ToSchema)]
+struct MaintenanceStatus {
+    // the maintenance of this node, if it's paused
+    maintenance: Option<MaintenanceNotice>,
+    height: u64,
+    // the longest chain of the peers, the headers are still synced during a maintenance
+    peer_height: Option<u64>,
+    // the peers that announced a maintenance, by address
+    peers: BTreeMap<String, MaintenanceNotice>,
+}
+
+// Returns if the node and its peers are in maintenance, e.g. to follow a consortium-wide upgrade
+#[utoipa::path(
+    get,
+    path = "/maintenance",
+    responses((status = 200, description = "Maintenance of the node and its peers", body = MaintenanceStatus))
+)]
+async fn get_maintenance(state: web::Data<ApiState>) -> HttpResponse {
+    HttpResponse::Ok().json(MaintenanceStatus {
+        maintenance: state.maintenance.current(),
+        height: state.blockchain.get_last_block().header.index,
+        peer_height: state.maintenance.peer_height(),
+        peers: state.maintenance.peers(),
+    })
+}
+
+// Records that a peer entered or left maintenance, only the known peers can announce it
+// It's informational, this node keeps working until its own operator pauses it
+#[utoipa::path(
+    post,
+    path = "/maintenance/announcements",
+    request_body = MaintenanceAnnouncement,
+    responses(
+        (status = 200, description = "The announcement was recorded"),
+        (status = 400, description = "The node is not a known peer", body = ErrorResponse),
+    )
+)]
+async fn receive_maintenance_announcement(
+    state: web::Data<ApiState>,
+    announcement: web::Json<MaintenanceAnnouncement>,
+) -> HttpResponse {
+    let announcement = announcement.into_inner();
+    if !state.peers.get_all_peers().contains(&announcement.node) {
+        let message = format!("{} is not a known peer", announcement.node);
+        return ErrorResponse::new(ErrorCode::InvalidRequest, message).to_response();
+    }
+
+    match &announcement.notice {
+        Some(notice) => info!(
+            "Peer {} is in maintenance ({:?})",
+            announcement.node, notice.reason
+        ),
+        None => info!("Peer {} ended its maintenance", announcement.node),
+    }
+    state.maintenance.record_announcement(announcement);
+
+    HttpResponse::Ok().finish()
+}
+
+#[derive(Deserialize, ToSchema)]
+struct MaintenanceRequest {
+    reason: MaintenanceReason,
+    message: Option<String>,
+}
+
+// Pauses the node: no more blocks or transactions until the maintenance ends (admin only)
+// The peers are told about it on the next sync
+#[utoipa::path(
+    post,
+    path = "/admin/maintenance",
+    request_body = MaintenanceRequest,
+    responses(
+        (status = 200, description = "The node is in maintenance", body = MaintenanceNotice),
+        (status = 401, description = "Missing or wrong admin token", body = ErrorResponse),
+    )
+)]
+async fn start_maintenance(
+    state: web::Data<ApiState>,
+    request: HttpRequest,
+    maintenance: web::Json<MaintenanceRequest>,
+) -> HttpResponse {
+    if !is_admin(&state, &request) {
+        let message = "A valid admin token is required";
+        return ErrorResponse::new(ErrorCode::Unauthorized, message).to_response();
+    }
+
+    let maintenance = maintenance.into_inner();
+    let notice = state
+        .maintenance
+        .start(maintenance.reason, maintenance.message);
+    warn!("Entered maintenance ({:?})", notice.reason);
+
+    HttpResponse::Ok().json(notice)
+}
+
+// Resumes the node after a maintenance (admin only)
+#[utoipa::path(
+    delete,
+    path = "/admin/maintenance",
+    responses(
+        (status = 200, description = "The maintenance that ended", body = MaintenanceNotice),
+        (status = 401, description = "Missing or wrong admin token", body = ErrorResponse),
+        (status = 404, description = "The node is not in maintenance", body = ErrorResponse),
+    )
+)]
+async fn end_maintenance(state: web::Data<ApiState>, request: HttpRequest) -> HttpResponse {
+    if !is_admin(&state, &request) {
+        let message = "A valid admin token is required";
+        return ErrorResponse::new(ErrorCode::Unauthorized, message).to_response();
+    }
+
+    match state.maintenance.end() {
+        Some(notice) => {
+            info!("Ended maintenance ({:?})", notice.reason);
+            HttpResponse::Ok().json(notice)
+        }
+        None => {
+            ErrorResponse::new(ErrorCode::NotFound, "The node is not in maintenance").to_response()
+        }
+    }
+}
+
+// Returns how far the verification of the chain got, and the invalid block that stopped it if any (admin only)
+#[utoipa::path(
+    get,
+    path = "/admin/verification",
+    responses(
+        (status = 200, description = "Progress of the verification of the chain", body = VerificationProgress),
+        (status = 401, description = "Missing or wrong admin token", body = ErrorResponse),
+    )
+)]
+async fn get_verification_progress(
+    state: web::Data<ApiState>,
+    request: HttpRequest,
+) -> HttpResponse {
+    if !is_admin(&state, &request) {
+        let message = "A valid admin token is required";
+        return ErrorResponse::new(ErrorCode::Unauthorized, message).to_response();
+    }
+
+    HttpResponse::Ok().json(state.verification.progress(&state.blockchain))
+}
+
+// Checks again the blocks of the chain in the background, from the last block checked by the previous runs
+// (admin only), unlike `/verification` a long chain is checked a few blocks at a time
+#[utoipa::path(
+    post,
+    path = "/admin/verification",
+    responses(
+        (status = 200, description = "The verification is running", body = VerificationProgress),
+        (status = 401, description = "Missing or wrong admin token", body = ErrorResponse),
+    )
+)]
+async fn start_verification(state: web::Data<ApiState>, request: HttpRequest) -> HttpResponse {
+    if !is_admin(&state, &request) {
+        let message = "A valid admin token is required";
+        return ErrorResponse::new(ErrorCode::Unauthorized, message).to_response();
+    }
+
+    let progress = state.verification.start(&state.blockchain);
+    info!(
+        "Started the verification of the chain from block {}",
+        progress.verified_blocks
+    );
+
+    HttpResponse::Ok().json(progress)
+}
+
+// Stops the verification, a new run resumes it where it stopped (admin only)
+#[utoipa::path(
+    delete,
+    path = "/admin/verification",
+    responses(
+        (status = 200, description = "The verification is stopped", body = VerificationProgress),
+        (status = 401, description = "Missing or wrong admin token", body = ErrorResponse),
+    )
+)]
+async fn interrupt_verification(state: web::Data<ApiState>, request: HttpRequest) -> HttpResponse {
+    if !is_admin(&state, &request) {
+        let message = "A valid admin token is required";
+        return ErrorResponse::new(ErrorCode::Unauthorized, message).to_response();
+    }
+
+    HttpResponse::Ok().json(state.verification.interrupt(&state.blockchain))
+}
+
+#[derive(Serialize, ToSchema)]
+struct PeerVersion {
+    address: String,
+    // none if the peer didn't advertise it yet, or is older than the advertisements
+    protocol_version: Option<u32>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct UpgradeReport {
+    // version of the consensus rules of this build
+    protocol_version: u32,
+    height: u64,
+    // the versions planned for the network in its genesis
+    activations: Vec<ProtocolActivation>,
+    peers: Vec<PeerVersion>,
+    advisories: Vec<UpgradeAdvisory>,
+}
+
+// Tells the operator if the node must be upgraded: the versions of the peers and of the network (admin only)
+#[utoipa::path(
+    get,
+    path = "/admin/upgrades",
+    responses(
+        (status = 200, description = "Versions of the node, its peers and its network, and the advisories", body = UpgradeReport),
+        (status = 401, description = "Missing or wrong admin token", body = ErrorResponse),
+    )
+)]
+async fn get_upgrades(state: web::Data<ApiState>, request: HttpRequest) -> HttpResponse {
+    if !is_admin(&state, &request) {
+        let message = "A valid admin token is required";
+        return ErrorResponse::new(ErrorCode::Unauthorized, message).to_response();
+    }
+
+    let blockchain = &state.blockchain;
+    let height = blockchain.get_last_block().header.index;
+    let activations = blockchain.activations().to_vec();
+    let advisories = upgrade_advisories(&state.peers.protocol_versions(), &activations, height);
+    let peers = state
+        .peers
+        .get_all_records()
+        .into_iter()
+        .map(|record| PeerVersion {
+            address: record.address,
+            protocol_version: record.protocol_version,
+        })
+        .collect();
+
+    HttpResponse::Ok().json(UpgradeReport {
+        protocol_version: PROTOCOL_VERSION,
+        height,
+        activations,
+        peers,
+        advisories,
+    })
+}
+
+// Registers a named query on the node, or replaces the one with the same name (admin only)
+// Its results are computed on the whole chain once, then kept up to date as the blocks are added
+#[utoipa::path(
+    post,
+    path = "/admin/queries",
+    request_body = SavedQuery,
+    responses(
+        (status = 201, description = "The query was saved, with its current results", body = QueryResults),
+        (status = 400, description = "Invalid name, filter or JSONPath expression, or too many queries", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong admin token", body = ErrorResponse),
+    )
+)]
+async fn save_query(
+    state: web::Data<ApiState>,
+    request: HttpRequest,
+    query: web::Json<SavedQuery>,
+) -> HttpResponse {
+    if !is_admin(&state, &request) {
+        let message = "A valid admin token is required";
+        return ErrorResponse::new(ErrorCode::Unauthorized, message).to_response();
+    }
+
+    match state.saved_queries.register(query.into_inner()) {
+        Ok(results) => {
+            info!("Saved the query {}", results.name);
+            HttpResponse::Created().json(results)
+        }
+        Err(error @ SavedQueryError::InvalidPath(_)) => {
+            ErrorResponse::new(ErrorCode::InvalidPath, error).to_response()
+        }
+        Err(error @ SavedQueryError::NotPersisted(_)) => {
+            ErrorResponse::new(ErrorCode::Internal, error).to_response()
+        }
+        Err(error) => ErrorResponse::new(ErrorCode::InvalidRequest, error).to_response(),
+    }
+}
+
+// Forgets a named query (admin only)
+#[utoipa::path(
+    delete,
+    path = "/admin/queries/{name}",
+    responses(
+        (status = 204, description = "The query was removed"),
+        (status = 401, description = "Missing or wrong admin token", body = ErrorResponse),
+        (status = 404, description = "No query with that name", body = ErrorResponse),
+    )
+)]
+async fn remove_query(
+    state: web::Data<ApiState>,
+    request: HttpRequest,
+    name: web::Path<String>,
+) -> HttpResponse {
+    if !is_admin(&state, &request) {
+        let message = "A valid admin token is required";
+        return ErrorResponse::new(ErrorCode::Unauthorized, message).to_response();
+    }
+
+    match state.saved_queries.remove(&name) {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => {
+            ErrorResponse::new(ErrorCode::NotFound, "No saved query with that name").to_response()
+        }
+        Err(error) => ErrorResponse::new(
+            ErrorCode::Internal,
+            format!("The queries could not be persisted: {}", error),
+        )
+        .to_response(),
+    }
+}
+
+// Lists the queries saved on the node
+#[utoipa::path(
+    get,
+    path = "/queries",
+    responses(
+        (status = 200, description = "The definitions of the saved queries", body = [SavedQuery]),
+    )
+)]
+async fn get_saved_queries(state: web::Data<ApiState>) -> HttpResponse {
+    HttpResponse::Ok().json(state.saved_queries.list())
+}
+
+// Returns the current results of a saved query, only the blocks added since the last request are scanned
+#[utoipa::path(
+    get,
+    path = "/queries/{name}",
+    responses(
+        (status = 200, description = "The batches of the query, up to a block", body = QueryResults),
+        (status = 404, description = "No query with that name", body = ErrorResponse),
+    )
+)]
+async fn get_query_results(state: web::Data<ApiState>, name: web::Path<String>) -> HttpResponse {
+    match state.saved_queries.results(&name) {
+        Some(results) => HttpResponse::Ok().json(results),
+        None => {
+            ErrorResponse::new(ErrorCode::NotFound, "No saved query with that name").to_response()
+        }
+    }
+}
+
+// Blocks, transactions and anything else that ends up on chain are refused by a read replica,
+// which only follows its primary, and during a maintenance
+fn reject_writes(state: &ApiState) -> Option<HttpResponse> {
+    if !state.replica_of.is_empty() {
+        let response = ErrorResponse::new(ErrorCode::ReadOnly, "The node is a read replica")
+            .with_details(serde_json::json!({ "primary": state.replica_of }));
+        return Some(response.to_response());
+    }
+
+    state.maintenance.current().map(|notice| {
+        ErrorResponse::new(ErrorCode::Maintenance, "The node is in maintenance")
+            .with_details(serde_json::json!(notice))
+            .to_response()
+    })
+}
+
+// Admin requests carry the token of the node in a "Authorization: Bearer {token}" header
+fn parse_namespace(namespace: &str) -> Result<Namespace, HttpResponse> {
+    Namespace::from_str(namespace)
+        .map_err(|error| ErrorResponse::new(ErrorCode::InvalidNamespace, error).to_response())
+}
+
+// So the integration of a consortium never writes or reads the batches of another one by mistake
+fn reject_foreign_batch(namespace: &Namespace, batch_id: &str) -> Option<HttpResponse> {
+    match namespace.contains(batch_id) {
+        true => None,
+        false => {
+            let message = format!(
+                "The batch `{}` is not in the namespace `{}`",
+                batch_id, namespace
+            );
+            Some(ErrorResponse::new(ErrorCode::ForeignNamespace, message).to_response())
+        }
+    }
+}
+
+fn is_admin(state: &ApiState, request: &HttpRequest) -> bool {
+    let authorization = request
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok());
+
+    !state.admin_token.is_empty()
+        && authorization == Some(format!("Bearer {}", state.admin_token).as_str())
+}
+
+// Remembers how a transaction reached the node, with the address of the client if the operator allows it
+fn record_origin(
+    state: &ApiState,
+    request: &HttpRequest,
+    transaction: &Transaction,
+    channel: OriginChannel,
+) {
+    let ip = match state.origins_record_ip {
+        true => request.peer_addr().map(|address| address.ip().to_string()),
+        false => None,
+    };
+    state
+        .origins
+        .record(transaction, channel, None, ip.as_deref());
+}
+
+// Escapes a value to be used as a label in the Prometheus text format
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Returns the OpenAPI document describing this API, to generate clients from it
+async fn get_openapi() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+// Builds a response with the value in one of the canonical encodings
+fn encoded_response<T: Serialize>(value: &T, encoding: &str) -> HttpResponse {
+    let encoding = match Encoding::from_str(encoding) {
+        Ok(encoding) => encoding,
+        Err(error) => return ErrorResponse::new(ErrorCode::InvalidEncoding, error).to_response(),
+    };
+
+    let content_type = match encoding {
+        Encoding::Binary => "application/octet-stream",
+        Encoding::CanonicalJson => "application/json",
+    };
+    match encode(value, encoding) {
+        Ok(bytes) => HttpResponse::Ok().content_type(content_type).body(bytes),
+        Err(error) => ErrorResponse::new(ErrorCode::Internal, error).to_response(),
+    }
+}
+
+// Returns the cached response of a query, computed on the blocking pool on a miss so the workers keep serving
+// The blocks are read under the lock of the chain instead of being copied
+async fn compute_cached<F>(state: &web::Data<ApiState>, key: String, compute: F) -> HttpResponse
+where
+    F: FnOnce(&[Block]) -> CachedValue + Send + 'static,
+{
+    let cached = state.clone();
+    let value = state
+        .blockchain
+        .run(move |blockchain| {
+            let tip = blockchain.get_last_block().header.hash;
+            cached
+                .cache
+                .get_or_compute(tip, &key, || blockchain.with_blocks(compute))
+        })
+        .await;
+
+    cached_json_response(value)
+}
+
+// Builds the response of a cached query, where "None" means that nothing was found
+fn cached_json_response(value: CachedValue) -> HttpResponse {
+    match value {
+        Some(json) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json),
+        None => ErrorResponse::new(ErrorCode::NotFound, "Not found").to_response(),
+    }
 }
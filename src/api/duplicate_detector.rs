@@ -0,0 +1,177 @@
+use std::collections::HashSet;
+
+use chrono::Utc;
+use serde_json::{Map, Value};
+
+use crate::model::{transaction_hash, Blockchain, Transaction, TransactionPool, TxHash};
+
+// Payloads sharing at least this fraction of their fields are considered near-identical
+const SIMILARITY_THRESHOLD: f64 = 0.8;
+
+// Flags transactions that look like the same event recorded twice by accident
+// (same batch and event type, near-identical payload, within a short window of time)
+// It's only a heuristic, so it produces warnings instead of rejecting the transactions
+pub struct DuplicateDetector {
+    window_ms: i64,
+    blockchain: Blockchain,
+    pool: TransactionPool,
+}
+
+impl DuplicateDetector {
+    // A window of 0 disables the detection
+    pub fn new(window_ms: u64, blockchain: Blockchain, pool: TransactionPool) -> DuplicateDetector {
+        DuplicateDetector {
+            window_ms: window_ms as i64,
+            blockchain,
+            pool,
+        }
+    }
+
+    // Returns a warning for each recent transaction that the new one probably duplicates
+    pub fn check(&self, transaction: &Transaction) -> Vec<String> {
+        if self.window_ms == 0 {
+            return Vec::new();
+        }
+
+        // the pool is read before the chain, so a transaction mined in between is still found once, in its block
+        // (the miner only forgets the transactions in flight once their block is in the chain)
+        let pending = self.pool.get_unconfirmed();
+        let since = Utc::now().timestamp_millis() - self.window_ms;
+        let recorded = self.blockchain.get_recent_transactions(since);
+        let recorded_hashes: HashSet<TxHash> = recorded
+            .iter()
+            .map(|(_, recorded)| transaction_hash(recorded))
+            .collect();
+
+        let mut warnings: Vec<String> = pending
+            .iter()
+            .filter(|pending| !recorded_hashes.contains(&transaction_hash(pending)))
+            .filter(|pending| is_probable_duplicate(transaction, pending))
+            .map(|_| "Probable duplicate of a transaction pending in the pool".to_string())
+            .collect();
+        for (index, recorded) in recorded {
+            if is_probable_duplicate(transaction, &recorded) {
+                let warning = format!("Probable duplicate of a transaction in block {}", index);
+                warnings.push(warning);
+            }
+        }
+
+        warnings
+    }
+}
+
+fn is_probable_duplicate(transaction: &Transaction, other: &Transaction) -> bool {
+    transaction.batch_id == other.batch_id
+        && transaction.event_type == other.event_type
+        && payload_similarity(&transaction.data.as_json(), &other.data.as_json())
+            >= SIMILARITY_THRESHOLD
+}
+
+// Fraction of the fields with the same value in both JSON payloads (from 0 to 1)
+// Payloads that are not JSON objects are only similar if they are equal, ignoring whitespace
+fn payload_similarity(data: &str, other_data: &str) -> f64 {
+    match (parse_object(data), parse_object(other_data)) {
+        (Some(fields), Some(other_fields)) => {
+            let same_fields = fields
+                .iter()
+                .filter(|(key, value)| other_fields.get(*key) == Some(value))
+                .count();
+            let new_fields = other_fields
+                .keys()
+                .filter(|key| !fields.contains_key(*key))
+                .count();
+            let all_fields = fields.len() + new_fields;
+
+            if all_fields == 0 {
+                1.0
+            } else {
+                same_fields as f64 / all_fields as f64
+            }
+        }
+        _ => {
+            let normalize = |s: &str| s.split_whitespace().collect::<String>();
+            if normalize(data) == normalize(other_data) {
+                1.0
+            } else {
+                0.0
+            }
+        }
+    }
+}
+
+fn parse_object(data: &str) -> Option<Map<String, Value>> {
+    match serde_json::from_str(data) {
+        Ok(Value::Object(fields)) => Some(fields),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        fixtures::{alice, sign},
+        Block,
+    };
+
+    #[test]
+    fn should_measure_payload_similarity() {
+        let reading = r#"{"a": 1, "b": 2, "c": 3, "d": 4, "e": 5}"#;
+
+        assert_eq!(payload_similarity(reading, reading), 1.0);
+        // only one field out of five is different
+        let similar = r#"{"a": 1, "b": 2, "c": 3, "d": 4, "e": 6}"#;
+        assert!((payload_similarity(reading, similar) - 0.8).abs() < f64::EPSILON);
+
+        // missing fields also count as different
+        let partial = r#"{"a": 1, "b": 2, "c": 3, "d": 4}"#;
+        assert!((payload_similarity(reading, partial) - 0.8).abs() < f64::EPSILON);
+
+        // plain text payloads must be equal
+        assert_eq!(
+            payload_similarity("wheat  harvested", "wheat harvested"),
+            1.0
+        );
+        assert_eq!(payload_similarity("wheat harvested", "corn harvested"), 0.0);
+    }
+
+    #[test]
+    fn should_warn_about_probable_duplicates() {
+        let blockchain = Blockchain::new(0);
+        let pool = TransactionPool::new(blockchain.event_bus());
+        let detector = DuplicateDetector::new(60_000, blockchain.clone(), pool.clone());
+
+        let transaction = create_transaction(r#"{"crop": "wheat", "quantity": "500kg"}"#);
+        assert!(detector.check(&transaction).is_empty());
+
+        // the same event pending in the pool
+        pool.add_transaction(transaction.clone());
+        assert_eq!(detector.check(&transaction).len(), 1);
+
+        // and then recorded in a recent block, found once even before the miner forgets it
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(1, 0, previous_hash, pool.pop());
+        blockchain.add_block(block).unwrap();
+        let warnings = detector.check(&transaction);
+        assert_eq!(
+            warnings,
+            vec!["Probable duplicate of a transaction in block 1"]
+        );
+        pool.clear_in_flight();
+        assert_eq!(detector.check(&transaction), warnings);
+
+        // different payloads are not duplicates
+        let other = create_transaction(r#"{"crop": "wheat", "quantity": "300kg"}"#);
+        assert!(detector.check(&other).is_empty());
+    }
+
+    fn create_transaction(data: &str) -> Transaction {
+        sign(Transaction {
+            sender: alice(),
+            data: data.into(),
+            batch_id: "WHEAT-2024-001".to_string(),
+            event_type: "HARVEST".into(),
+            ..Default::default()
+        })
+    }
+}
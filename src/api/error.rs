@@ -0,0 +1,121 @@
+use actix_web::{error::InternalError, http::StatusCode, Error, HttpResponse};
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+// Stable identifiers of the errors returned by the API, so clients don't depend on the messages
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    // The request could not be parsed (malformed JSON, wrong query parameters...)
+    InvalidRequest,
+    InvalidBlock,
+    InvalidTransaction,
+    InvalidLot,
+    // Unknown regulator, or a signature that doesn't match the statement of the attestation
+    InvalidAttestation,
+    InvalidAddress,
+    InvalidHash,
+    InvalidPath,
+    InvalidEncoding,
+    // The cursor is after the last change of the feed (e.g. the node lost its changes file)
+    InvalidCursor,
+    InvalidNamespace,
+    // The batch of the request belongs to another namespace than the one of the route
+    ForeignNamespace,
+    NotFound,
+    // Missing or wrong admin token
+    Unauthorized,
+    // Some chunks of the document are not on chain yet
+    IncompleteDocument,
+    // The chunks on chain don't match the hash of the document
+    CorruptedDocument,
+    FaucetUnavailable,
+    // Mining was requested with an empty pool
+    NothingToMine,
+    // The node is paused for a maintenance, only the queries are served
+    Maintenance,
+    // The node is a read replica, the writes go to its primary
+    ReadOnly,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::NotFound | ErrorCode::FaucetUnavailable => StatusCode::NOT_FOUND,
+            ErrorCode::IncompleteDocument => StatusCode::NOT_FOUND,
+            ErrorCode::CorruptedDocument | ErrorCode::NothingToMine => StatusCode::CONFLICT,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::Maintenance | ErrorCode::ReadOnly => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+// Body of all the error responses of the API
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct ErrorResponse {
+    pub code: ErrorCode,
+    // human readable description, it can change between versions
+    pub message: String,
+    // extra information about the error, that depends on the code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+impl ErrorResponse {
+    pub fn new(code: ErrorCode, message: impl ToString) -> ErrorResponse {
+        ErrorResponse {
+            code,
+            message: message.to_string(),
+            details: None,
+        }
+    }
+
+    pub fn with_details(mut self, details: Value) -> ErrorResponse {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn to_response(&self) -> HttpResponse {
+        HttpResponse::build(self.code.status()).json(self)
+    }
+}
+
+// Turns the errors of the actix extractors (JSON bodies, queries and paths) into typed error responses
+pub fn handle_extractor_error<E: std::fmt::Display + std::fmt::Debug + 'static>(error: E) -> Error {
+    let response = ErrorResponse::new(ErrorCode::InvalidRequest, &error).to_response();
+    InternalError::from_response(error, response).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn should_serialize_error_bodies() {
+        let error = ErrorResponse::new(ErrorCode::InvalidPath, "Invalid JSONPath syntax")
+            .with_details(json!({"position": 3}));
+
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            json!({
+                "code": "INVALID_PATH",
+                "message": "Invalid JSONPath syntax",
+                "details": {"position": 3},
+            })
+        );
+
+        // details are left out when there are none
+        let error = ErrorResponse::new(ErrorCode::NotFound, "Profile not found");
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            json!({"code": "NOT_FOUND", "message": "Profile not found"})
+        );
+        assert_eq!(error.to_response().status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::model::{ChainEvent, EventBus, EventSubscription, Transaction};
+
+// Dropped transactions kept for their submitters to find out, the oldest ones are forgotten
+const MAX_RECENT_DROPS: usize = 100;
+
+// A pending transaction that a new block made invalid, and why
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DroppedTransaction {
+    pub transaction: Transaction,
+    pub reason: String,
+}
+
+// Activity of the node since it started
+#[derive(Debug, Clone, Default)]
+pub struct Counts {
+    pub blocks_applied: u64,
+    pub transactions_accepted: BTreeMap<String, u64>,
+    pub forks_archived: u64,
+    pub transactions_dropped: u64,
+    pub recent_drops: VecDeque<DroppedTransaction>,
+    // height of the most recent competing block archived
+    pub last_fork_height: u64,
+    pub reorgs: u64,
+    // blocks rolled back by the most recent reorganization
+    pub last_reorg_depth: u64,
+}
+
+struct CountersState {
+    subscription: EventSubscription,
+    counts: Counts,
+}
+
+// Counts the events of the chain and the pool, to be exposed as metrics
+// The events are consumed from the bus each time the counts are read
+#[derive(Clone)]
+pub struct EventCounters {
+    state: Arc<Mutex<CountersState>>,
+}
+
+impl EventCounters {
+    pub fn new(event_bus: &EventBus) -> EventCounters {
+        let state = CountersState {
+            subscription: event_bus.subscribe(),
+            counts: Counts::default(),
+        };
+
+        EventCounters {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    pub fn get(&self) -> Counts {
+        let mut state = self.state.lock().unwrap();
+
+        for event in state.subscription.drain() {
+            let counts = &mut state.counts;
+            match event {
+                ChainEvent::BlockApplied(_) => counts.blocks_applied += 1,
+                ChainEvent::TxAccepted(transaction) => {
+                    let count = counts
+                        .transactions_accepted
+                        .entry(transaction.event_type.to_string())
+                        .or_insert(0);
+                    *count += 1;
+                }
+                ChainEvent::TxDropped(transaction, reason) => {
+                    counts.transactions_dropped += 1;
+                    if counts.recent_drops.len() == MAX_RECENT_DROPS {
+                        counts.recent_drops.pop_front();
+                    }
+                    let drop = DroppedTransaction {
+                        transaction,
+                        reason,
+                    };
+                    counts.recent_drops.push_back(drop);
+                }
+                ChainEvent::ForkArchived(orphaned) => {
+                    counts.forks_archived += 1;
+                    counts.last_fork_height = orphaned.fork_height;
+                }
+                ChainEvent::ReorgOccurred(reorg) => {
+                    counts.reorgs += 1;
+                    counts.last_reorg_depth = reorg.depth();
+                }
+            }
+        }
+
+        state.counts.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        fixtures::{alice, sign},
+        Block, Blockchain, Transaction, TransactionPool,
+    };
+
+    #[test]
+    fn should_count_published_events() {
+        let blockchain = Blockchain::new(0);
+        let pool = TransactionPool::new(blockchain.event_bus());
+        let counters = EventCounters::new(&blockchain.event_bus());
+
+        let transaction = sign(Transaction {
+            sender: alice(),
+            event_type: "HARVEST".into(),
+            ..Default::default()
+        });
+        pool.add_transaction(transaction.clone());
+        // the same transaction again is not accepted twice
+        pool.add_transaction(transaction.clone());
+        let other_transaction = sign(Transaction {
+            data: "other harvest".into(),
+            ..transaction.clone()
+        });
+        pool.add_transaction(other_transaction);
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(1, 0, previous_hash, pool.pop());
+        blockchain.add_block(block).unwrap();
+
+        let counts = counters.get();
+        assert_eq!(counts.blocks_applied, 1);
+        assert_eq!(counts.transactions_accepted["HARVEST"], 2);
+        assert_eq!(counts.forks_archived, 0);
+        assert_eq!(counts.reorgs, 0);
+
+        // the counts accumulate, the transaction is not pending anymore
+        pool.add_transaction(transaction);
+        assert_eq!(counters.get().transactions_accepted["HARVEST"], 3);
+    }
+}
@@ -0,0 +1,139 @@
+use serde_json::json;
+
+use crate::{
+    model::{
+        Profile, Registration, Transaction, TransactionError, PROFILE_EVENT, REGISTRATION_EVENT,
+    },
+    wallet::{Wallet, WalletError},
+};
+
+// Creates registered actor identities on demand, only available on test networks
+// so QA teams can spin up the actors of their scenarios without managing identities by hand
+// The roles are granted by the registrar of the chain, so the faucet needs its key: the one of the
+// FAUCET_REGISTRAR_KEY setting, or else the key of the node which becomes the registrar with the first registration
+pub struct Faucet {
+    registrar: Wallet,
+}
+
+impl Faucet {
+    pub fn new(registrar_key: &str, node_wallet: &Wallet) -> Result<Faucet, WalletError> {
+        let registrar = match registrar_key.is_empty() {
+            true => node_wallet.clone(),
+            false => Wallet::from_secret_hex(registrar_key)?,
+        };
+        Ok(Faucet { registrar })
+    }
+
+    // Generates a brand new keypair, the PROFILE event of its address and the REGISTRATION event that grants
+    // it the roles of the profile, each signed by its sender as the chain requires
+    pub fn provision_actor(
+        &self,
+        profile: &Profile,
+    ) -> Result<(Wallet, Vec<Transaction>), TransactionError> {
+        let wallet = Wallet::generate();
+        let mut profile_event = Transaction {
+            sender: wallet.address(),
+            recipient: wallet.address(),
+            data: serde_json::to_string(profile)
+                .map_err(|_| TransactionError::InvalidProfile)?
+                .into(),
+            event_type: PROFILE_EVENT.into(),
+            ..Default::default()
+        };
+        wallet.sign(&mut profile_event)?;
+        profile_event.validate()?;
+
+        // only the roles known by the registry can be granted
+        let registration = json!({ "roles": profile.roles }).to_string();
+        Registration::parse(&registration)?;
+        let mut registration_event = Transaction {
+            sender: self.registrar.address(),
+            recipient: wallet.address(),
+            data: registration.into(),
+            event_type: REGISTRATION_EVENT.into(),
+            ..Default::default()
+        };
+        self.registrar.sign(&mut registration_event)?;
+
+        Ok((wallet, vec![profile_event, registration_event]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        fixtures::{farm, mine, registrations},
+        Blockchain, Role,
+    };
+
+    fn profile(roles: &[&str]) -> Profile {
+        Profile {
+            display_name: "QA Farm".to_string(),
+            location: None,
+            contact: None,
+            roles: roles.iter().map(|role| role.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn should_provision_registered_actors() {
+        let node_wallet = Wallet::generate();
+        let faucet = Faucet::new("", &node_wallet).unwrap();
+        let profile = profile(&["FARMER"]);
+
+        let (wallet, transactions) = faucet.provision_actor(&profile).unwrap();
+        assert_eq!(transactions[0].sender, wallet.address());
+        assert_eq!(transactions[0].event_type, PROFILE_EVENT);
+        assert_eq!(
+            Profile::parse(&transactions[0].data.as_json()),
+            Ok(profile.clone())
+        );
+        // signed by the new actor, who gets the keypair to sign its own events
+        assert_eq!(transactions[0].verify(), Ok(()));
+        assert_eq!(transactions[1].verify(), Ok(()));
+
+        // the node becomes the registrar of a chain without one, and grants the roles of the profile
+        let blockchain = Blockchain::new(0);
+        mine(&blockchain, &transactions);
+        let registry = blockchain.get_actor_registry();
+        assert_eq!(registry.registrar(), Some(&node_wallet.address()));
+        assert_eq!(registry.roles_of(&wallet.address()), &[Role::Farmer]);
+
+        // every actor gets its own keypair
+        let (other_wallet, _) = faucet.provision_actor(&profile).unwrap();
+        assert_ne!(wallet.address(), other_wallet.address());
+    }
+
+    #[test]
+    fn should_only_register_with_the_key_of_the_registrar() {
+        let blockchain = Blockchain::new(0);
+        mine(&blockchain, &registrations());
+        let profile = profile(&["FARMER"]);
+
+        let faucet = Faucet::new(&hex::encode([7; 32]), &Wallet::generate()).unwrap();
+        let (_, transactions) = faucet.provision_actor(&profile).unwrap();
+        assert_eq!(
+            blockchain.validate_transaction_after(&transactions[1], &transactions[..1]),
+            Err(TransactionError::NotRegistrar(farm().address))
+        );
+
+        let result = Faucet::new("07", &Wallet::generate());
+        assert_eq!(result.err(), Some(WalletError::InvalidSecretKey));
+    }
+
+    #[test]
+    fn should_reject_invalid_profiles() {
+        let faucet = Faucet::new("", &Wallet::generate()).unwrap();
+
+        let unnamed = Profile {
+            display_name: " ".to_string(),
+            ..profile(&[])
+        };
+        let result = faucet.provision_actor(&unnamed);
+        assert_eq!(result.err(), Some(TransactionError::InvalidProfile));
+
+        let result = faucet.provision_actor(&profile(&["PILOT"]));
+        assert_eq!(result.err(), Some(TransactionError::InvalidRegistration));
+    }
+}
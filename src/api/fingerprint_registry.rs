@@ -0,0 +1,292 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Mutex,
+};
+
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::model::{
+    Address, BlockHash, Blockchain, DocumentManifest, Transaction, TransactionPool, DOCUMENT_EVENT,
+};
+
+// Fields of the payloads that hold the hash of an attachment, e.g. {"report_hash": "9f86d0..."}
+const FINGERPRINT_FIELDS: [&str; 5] = [
+    "report_hash",
+    "certificate_hash",
+    "document_hash",
+    "attachment_hash",
+    "photo_hash",
+];
+
+// An event of the chain that carries the hash of a document or attachment
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct Fingerprint {
+    // hex encoded hash, in lowercase
+    pub hash: String,
+    pub batch_id: String,
+    pub event_type: String,
+    #[schema(value_type = String)]
+    pub actor: Address,
+    pub block_index: u64,
+}
+
+// A hash attached to several batches, e.g. the same lab report reused as the certificate of other harvests
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct ReusedFingerprint {
+    pub hash: String,
+    pub batches: Vec<String>,
+    pub occurrences: Vec<Fingerprint>,
+}
+
+#[derive(Debug, Default)]
+struct FingerprintIndex {
+    fingerprints: BTreeMap<String, Vec<Fingerprint>>,
+    // last indexed block, to detect that the chain was replaced
+    last_block: Option<(u64, BlockHash)>,
+}
+
+// Index of the hashes of documents and attachments across the whole chain, to flag the same hash
+// submitted for unrelated batches (a common fraud, where one certificate covers several harvests)
+// The same hash attached to several events of one batch is expected, e.g. the report of a recheck
+pub struct FingerprintRegistry {
+    blockchain: Blockchain,
+    pool: TransactionPool,
+    index: Mutex<FingerprintIndex>,
+}
+
+impl FingerprintRegistry {
+    pub fn new(blockchain: Blockchain, pool: TransactionPool) -> FingerprintRegistry {
+        FingerprintRegistry {
+            blockchain,
+            pool,
+            index: Mutex::default(),
+        }
+    }
+
+    // Returns a warning for each other batch that already has one of the hashes of the transaction
+    pub fn check(&self, transaction: &Transaction) -> Vec<String> {
+        let hashes = fingerprints_of(transaction);
+        if hashes.is_empty() {
+            return Vec::new();
+        }
+
+        let mut warnings = Vec::new();
+        for hash in hashes.iter() {
+            for fingerprint in self.find(hash) {
+                if fingerprint.batch_id != transaction.batch_id {
+                    warnings.push(format!(
+                        "Document {} was already submitted for batch {} in block {}",
+                        hash, fingerprint.batch_id, fingerprint.block_index
+                    ));
+                }
+            }
+        }
+        for pending in self.pool.get_unconfirmed() {
+            if pending.batch_id == transaction.batch_id {
+                continue;
+            }
+            for hash in fingerprints_of(&pending).intersection(&hashes) {
+                warnings.push(format!(
+                    "Document {} is also submitted for batch {} in a pending transaction",
+                    hash, pending.batch_id
+                ));
+            }
+        }
+
+        warnings
+    }
+
+    // Events of the chain with a hash, the oldest first
+    pub fn find(&self, hash: &str) -> Vec<Fingerprint> {
+        let index = self.sync();
+        index
+            .fingerprints
+            .get(&hash.to_lowercase())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // The hashes found in more than one batch
+    pub fn reused(&self) -> Vec<ReusedFingerprint> {
+        let index = self.sync();
+        index
+            .fingerprints
+            .iter()
+            .filter_map(|(hash, occurrences)| {
+                let batches: BTreeSet<&String> = occurrences
+                    .iter()
+                    .map(|fingerprint| &fingerprint.batch_id)
+                    .collect();
+                (batches.len() > 1).then(|| ReusedFingerprint {
+                    hash: hash.clone(),
+                    batches: batches.into_iter().cloned().collect(),
+                    occurrences: occurrences.clone(),
+                })
+            })
+            .collect()
+    }
+
+    // Indexes the blocks added since the last call, or the whole chain again if it was replaced
+    fn sync(&self) -> std::sync::MutexGuard<'_, FingerprintIndex> {
+        let mut index = self.index.lock().unwrap();
+        if let Some((last_index, last_hash)) = index.last_block {
+            let replaced = self
+                .blockchain
+                .get_block(last_index)
+                .map(|block| block.header.hash != last_hash)
+                .unwrap_or(true);
+            if replaced {
+                *index = FingerprintIndex::default();
+            }
+        }
+
+        let mut next = index.last_block.map(|(last, _)| last + 1).unwrap_or(0);
+        while let Some(block) = self.blockchain.get_block(next) {
+            for transaction in block.body.transactions.iter() {
+                for hash in fingerprints_of(transaction) {
+                    let fingerprint = Fingerprint {
+                        hash: hash.clone(),
+                        batch_id: transaction.batch_id.clone(),
+                        event_type: transaction.event_type.to_string(),
+                        actor: transaction
+                            .on_behalf_of
+                            .clone()
+                            .unwrap_or(transaction.sender.clone()),
+                        block_index: block.header.index,
+                    };
+                    index
+                        .fingerprints
+                        .entry(hash)
+                        .or_default()
+                        .push(fingerprint);
+                }
+            }
+            index.last_block = Some((block.header.index, block.header.hash));
+            next += 1;
+        }
+
+        index
+    }
+}
+
+// Hashes carried by a transaction: the hash of the document it announces, and the hash fields of its payload
+fn fingerprints_of(transaction: &Transaction) -> BTreeSet<String> {
+    let data = transaction.data.as_json();
+    if transaction.event_type == DOCUMENT_EVENT {
+        return DocumentManifest::parse(&data)
+            .map(|manifest| BTreeSet::from([manifest.hash.to_lowercase()]))
+            .unwrap_or_default();
+    }
+
+    let payload: Value = match serde_json::from_str(&data) {
+        Ok(payload @ Value::Object(_)) => payload,
+        _ => return BTreeSet::new(),
+    };
+    FINGERPRINT_FIELDS
+        .iter()
+        .filter_map(|field| payload.get(field)?.as_str())
+        .map(|hash| hash.trim().to_lowercase())
+        .filter(|hash| !hash.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        fixtures::{alice, bob, sign},
+        Block, DocumentChunk,
+    };
+
+    const REPORT: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+    #[test]
+    fn should_read_the_hashes_of_a_transaction() {
+        let report = create_transaction(
+            "WHEAT-001",
+            &format!(
+                r#"{{"result": "PASS", "report_hash": "{}"}}"#,
+                REPORT.to_uppercase()
+            ),
+        );
+        assert_eq!(
+            fingerprints_of(&report),
+            BTreeSet::from([REPORT.to_string()])
+        );
+
+        let document = DocumentChunk::split(&report, "lab report", None).remove(0);
+        let manifest = DocumentManifest::parse(&document.data.as_json()).unwrap();
+        assert_eq!(fingerprints_of(&document), BTreeSet::from([manifest.hash]));
+
+        assert!(fingerprints_of(&create_transaction("WHEAT-001", "plain text")).is_empty());
+        assert!(
+            fingerprints_of(&create_transaction("WHEAT-001", r#"{"report_hash": 7}"#)).is_empty()
+        );
+    }
+
+    #[test]
+    fn should_flag_the_same_report_in_another_batch() {
+        let blockchain = Blockchain::new(0);
+        let pool = TransactionPool::new(blockchain.event_bus());
+        let registry = FingerprintRegistry::new(blockchain.clone(), pool.clone());
+        // e.g. the organic certificate of a harvest
+        let data = format!(r#"{{"certificate_hash": "{}"}}"#, REPORT);
+
+        let original = create_transaction("WHEAT-001", &data);
+        assert!(registry.check(&original).is_empty());
+        let previous_hash = blockchain.get_last_block().header.hash;
+        blockchain
+            .add_block(Block::new(1, 0, previous_hash, vec![original.clone()]))
+            .unwrap();
+
+        // the same document attached again to its batch is fine
+        assert!(registry.check(&original).is_empty());
+
+        let reused = create_transaction("WHEAT-002", &data);
+        assert_eq!(
+            registry.check(&reused),
+            vec![format!(
+                "Document {} was already submitted for batch WHEAT-001 in block 1",
+                REPORT
+            )]
+        );
+
+        // also when the other batch is still pending
+        let pending = sign(Transaction {
+            sender: bob(),
+            ..create_transaction("WHEAT-003", &data)
+        });
+        pool.add_transaction(pending);
+        assert_eq!(registry.check(&reused).len(), 2);
+
+        let previous_hash = blockchain.get_last_block().header.hash;
+        blockchain
+            .add_block(Block::new(2, 0, previous_hash, pool.pop()))
+            .unwrap();
+        pool.clear_in_flight();
+        let found = registry.find(REPORT);
+        assert_eq!(found.len(), 2);
+        assert_eq!(
+            (found[1].batch_id.as_str(), found[1].block_index),
+            ("WHEAT-003", 2)
+        );
+        assert_eq!(found[1].actor, bob());
+
+        let reused = registry.reused();
+        assert_eq!(reused.len(), 1);
+        assert_eq!(reused[0].batches, vec!["WHEAT-001", "WHEAT-003"]);
+    }
+
+    fn create_transaction(batch_id: &str, data: &str) -> Transaction {
+        sign(Transaction {
+            sender: alice(),
+            recipient: alice(),
+            data: data.into(),
+            batch_id: batch_id.to_string(),
+            event_type: "HARVEST".into(),
+            ..Default::default()
+        })
+    }
+}
@@ -0,0 +1,197 @@
+use std::str::FromStr;
+
+use serde_json::Value;
+use thiserror::Error;
+
+#[derive(Error, PartialEq, Debug)]
+pub enum JsonPathError {
+    #[error("Invalid JSONPath expression at position {0}")]
+    InvalidSyntax(usize),
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+    Wildcard,
+}
+
+// Subset of JSONPath to select parts of the transaction payloads
+// Supports the root ("$"), child keys (".name" or "['name']"), array indexes ("[0]") and wildcards ("*")
+#[derive(Debug, PartialEq)]
+pub struct JsonPath {
+    segments: Vec<Segment>,
+}
+
+impl JsonPath {
+    // Returns all the values matched by the expression
+    pub fn select<'a>(&self, value: &'a Value) -> Vec<&'a Value> {
+        let mut current = vec![value];
+        for segment in self.segments.iter() {
+            current = current
+                .into_iter()
+                .flat_map(|value| Self::select_children(segment, value))
+                .collect();
+        }
+
+        current
+    }
+
+    // Returns the result of the expression as a single value
+    // Expressions without wildcards return the matched value itself, the other ones a list of matches
+    pub fn evaluate(&self, value: &Value) -> Option<Value> {
+        let matches = self.select(value);
+        let is_definite = !self.segments.contains(&Segment::Wildcard);
+
+        if is_definite {
+            matches.first().map(|value| (*value).clone())
+        } else {
+            Some(Value::Array(matches.into_iter().cloned().collect()))
+        }
+    }
+
+    fn select_children<'a>(segment: &Segment, value: &'a Value) -> Vec<&'a Value> {
+        match (segment, value) {
+            (Segment::Key(key), Value::Object(map)) => map.get(key).into_iter().collect(),
+            (Segment::Index(index), Value::Array(list)) => list.get(*index).into_iter().collect(),
+            (Segment::Wildcard, Value::Object(map)) => map.values().collect(),
+            (Segment::Wildcard, Value::Array(list)) => list.iter().collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl FromStr for JsonPath {
+    type Err = JsonPathError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let chars: Vec<char> = expression.chars().collect();
+        if chars.first() != Some(&'$') {
+            return Err(JsonPathError::InvalidSyntax(0));
+        }
+
+        let mut segments = Vec::new();
+        let mut position = 1;
+        while position < chars.len() {
+            let (segment, next_position) = match chars[position] {
+                '.' => parse_dot_segment(&chars, position + 1)?,
+                '[' => parse_bracket_segment(&chars, position + 1)?,
+                _ => return Err(JsonPathError::InvalidSyntax(position)),
+            };
+            segments.push(segment);
+            position = next_position;
+        }
+
+        Ok(JsonPath { segments })
+    }
+}
+
+// Parses a ".name" or ".*" segment, starting after the dot
+fn parse_dot_segment(chars: &[char], start: usize) -> Result<(Segment, usize), JsonPathError> {
+    if chars.get(start) == Some(&'*') {
+        return Ok((Segment::Wildcard, start + 1));
+    }
+
+    let end = find_end(chars, start, |c| {
+        c.is_alphanumeric() || c == '_' || c == '-'
+    });
+    if end == start {
+        return Err(JsonPathError::InvalidSyntax(start));
+    }
+
+    let key = chars[start..end].iter().collect();
+    Ok((Segment::Key(key), end))
+}
+
+// Parses a "[0]", "[*]" or "['name']" segment, starting after the opening bracket
+fn parse_bracket_segment(chars: &[char], start: usize) -> Result<(Segment, usize), JsonPathError> {
+    let (segment, end) = match chars.get(start) {
+        Some('*') => (Segment::Wildcard, start + 1),
+        Some(quote @ ('\'' | '"')) => {
+            let end = find_end(chars, start + 1, |c| c != *quote);
+            if end >= chars.len() {
+                return Err(JsonPathError::InvalidSyntax(start));
+            }
+            let key = chars[start + 1..end].iter().collect();
+            (Segment::Key(key), end + 1)
+        }
+        _ => {
+            let end = find_end(chars, start, |c| c.is_ascii_digit());
+            let digits: String = chars[start..end].iter().collect();
+            let index = digits
+                .parse()
+                .map_err(|_| JsonPathError::InvalidSyntax(start))?;
+            (Segment::Index(index), end)
+        }
+    };
+
+    if chars.get(end) != Some(&']') {
+        return Err(JsonPathError::InvalidSyntax(end));
+    }
+
+    Ok((segment, end + 1))
+}
+
+fn find_end<P: Fn(char) -> bool>(chars: &[char], start: usize, predicate: P) -> usize {
+    chars[start.min(chars.len())..]
+        .iter()
+        .position(|c| !predicate(*c))
+        .map_or(chars.len(), |offset| start + offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn should_select_nested_values() {
+        let payload = json!({"sensor": {"temperature": 4, "readings": [10, 20, 30]}});
+
+        let path = JsonPath::from_str("$.sensor.temperature").unwrap();
+        assert_eq!(path.evaluate(&payload), Some(json!(4)));
+
+        let path = JsonPath::from_str("$['sensor'].readings[1]").unwrap();
+        assert_eq!(path.evaluate(&payload), Some(json!(20)));
+
+        // the root returns the whole payload
+        let path = JsonPath::from_str("$").unwrap();
+        assert_eq!(path.evaluate(&payload), Some(payload.clone()));
+
+        // missing values return nothing
+        let path = JsonPath::from_str("$.humidity").unwrap();
+        assert_eq!(path.evaluate(&payload), None);
+    }
+
+    #[test]
+    fn should_select_all_matches_with_wildcards() {
+        let payload = json!({"readings": [{"value": 1}, {"value": 2}, {"other": 3}]});
+
+        let path = JsonPath::from_str("$.readings[*].value").unwrap();
+        assert_eq!(path.evaluate(&payload), Some(json!([1, 2])));
+    }
+
+    #[test]
+    fn should_reject_invalid_expressions() {
+        let invalid_expressions = [
+            ("", 0),
+            ("temperature", 0),
+            ("$.", 2),
+            ("$[abc]", 2),
+            ("$['name", 2),
+            ("$['name'", 8),
+            ("$[0", 3),
+            ("$temperature", 1),
+        ];
+
+        for (expression, position) in invalid_expressions {
+            assert_eq!(
+                JsonPath::from_str(expression),
+                Err(JsonPathError::InvalidSyntax(position)),
+                "{}",
+                expression
+            );
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use serde_json::Value;
+
+// Key of the objects that contain the same label in multiple languages
+// e.g. {"crop": {"i18n": {"en": "Wheat", "fr": "Blé", "ur": "گندم"}}}
+const I18N_KEY: &str = "i18n";
+
+const FALLBACK_LANGUAGE: &str = "en";
+
+// Replaces all the localized labels inside a value by their text in the requested language
+// If the language is not available, it falls back to the base language ("fr" for "fr-CA"),
+// then to english and at last to any of the available languages
+pub fn localize(value: &mut Value, lang: &str) {
+    if let Some(label) = select_label(value, lang) {
+        *value = Value::String(label);
+        return;
+    }
+
+    match value {
+        Value::Object(fields) => fields.values_mut().for_each(|field| localize(field, lang)),
+        Value::Array(items) => items.iter_mut().for_each(|item| localize(item, lang)),
+        _ => {}
+    }
+}
+
+// Localizes the labels of a transaction payload, leaving it untouched if it's not JSON
+pub fn localize_payload(data: &str, lang: &str) -> String {
+    match serde_json::from_str::<Value>(data) {
+        Ok(mut payload) => {
+            localize(&mut payload, lang);
+            payload.to_string()
+        }
+        Err(_) => data.to_string(),
+    }
+}
+
+fn select_label(value: &Value, lang: &str) -> Option<String> {
+    let fields = value.as_object()?;
+    if fields.len() != 1 {
+        return None;
+    }
+    let labels = fields.get(I18N_KEY)?.as_object()?;
+
+    let base_language = lang.split('-').next().unwrap_or(lang);
+    [lang, base_language, FALLBACK_LANGUAGE]
+        .iter()
+        .find_map(|language| labels.get(*language))
+        .or_else(|| labels.values().next())
+        .and_then(Value::as_str)
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn should_select_the_requested_language() {
+        let mut payload = json!({
+            "crop": {"i18n": {"en": "Wheat", "fr": "Blé"}},
+            "quantity": "500kg",
+            "treatments": [{"i18n": {"en": "Organic", "fr": "Biologique"}}],
+        });
+
+        localize(&mut payload, "fr");
+        assert_eq!(
+            payload,
+            json!({"crop": "Blé", "quantity": "500kg", "treatments": ["Biologique"]})
+        );
+    }
+
+    #[test]
+    fn should_fall_back_to_other_languages() {
+        let label = json!({"i18n": {"en": "Wheat", "fr": "Blé"}});
+
+        let mut regional = label.clone();
+        localize(&mut regional, "fr-CA");
+        assert_eq!(regional, json!("Blé"));
+
+        let mut missing = label.clone();
+        localize(&mut missing, "ur");
+        assert_eq!(missing, json!("Wheat"));
+
+        let mut only_other = json!({"i18n": {"es": "Trigo"}});
+        localize(&mut only_other, "ur");
+        assert_eq!(only_other, json!("Trigo"));
+    }
+
+    #[test]
+    fn should_leave_other_payloads_untouched() {
+        assert_eq!(localize_payload("Wheat harvested", "fr"), "Wheat harvested");
+
+        // objects with more fields are not labels
+        let data = r#"{"i18n":{"en":"Wheat"},"other":1}"#;
+        assert_eq!(localize_payload(data, "fr"), data);
+    }
+}
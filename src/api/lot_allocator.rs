@@ -0,0 +1,148 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use std::sync::Arc;
+
+use crate::{
+    model::{Address, Blockchain, Lot, Transaction, TransactionError, TransactionPool, LOT_EVENT},
+    wallet::Wallet,
+};
+
+// Issues the next lot identifier of a prefix and season, by adding a LOT event to the pool
+// Within a node the allocations are serialized, so two requests never get the same sequence
+// Across nodes the chain only accepts the first allocation of an identifier: the lot belongs
+// to the recipient of the LOT event once it's mined, the event is sent and signed by the node
+pub struct LotAllocator {
+    blockchain: Blockchain,
+    pool: TransactionPool,
+    node_wallet: Arc<Wallet>,
+    // last sequence issued by this node for each prefix and season
+    // the allocations being mined are neither in the pool nor in the chain, so we need to remember them
+    issued: Mutex<HashMap<(String, String), u64>>,
+}
+
+impl LotAllocator {
+    pub fn new(
+        blockchain: Blockchain,
+        pool: TransactionPool,
+        node_wallet: Arc<Wallet>,
+    ) -> LotAllocator {
+        LotAllocator {
+            blockchain,
+            pool,
+            node_wallet,
+            issued: Mutex::default(),
+        }
+    }
+
+    pub fn allocate(
+        &self,
+        owner: &Address,
+        prefix: &str,
+        season: &str,
+    ) -> Result<Lot, TransactionError> {
+        let mut issued = self.issued.lock().unwrap();
+        let key = (prefix.to_string(), season.to_string());
+
+        // other nodes may have allocated lots on chain too
+        let last_sequence = self
+            .blockchain
+            .get_last_lot_sequence(prefix, season)
+            .max(issued.get(&key).copied().unwrap_or(0));
+
+        let lot = Lot::new(prefix, season, last_sequence + 1)?;
+        let mut transaction = Transaction {
+            sender: self.node_wallet.address(),
+            recipient: owner.clone(),
+            data: serde_json::to_string(&lot)
+                .map_err(|_| TransactionError::InvalidLot)?
+                .into(),
+            batch_id: lot.id(),
+            event_type: LOT_EVENT.into(),
+            ..Default::default()
+        };
+        self.node_wallet.sign(&mut transaction)?;
+        self.blockchain.validate_transaction(&transaction)?;
+        self.pool.add_transaction(transaction);
+        issued.insert(key, lot.sequence);
+
+        Ok(lot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::model::{
+        fixtures::{alice, bob, carol, sign},
+        Block, EventBus,
+    };
+
+    fn create_allocator(blockchain: Blockchain, pool: TransactionPool) -> LotAllocator {
+        LotAllocator::new(blockchain, pool, Arc::new(Wallet::generate()))
+    }
+
+    #[test]
+    fn should_allocate_consecutive_lots() {
+        let blockchain = Blockchain::new(0);
+        let pool = TransactionPool::new(blockchain.event_bus());
+        let allocator = create_allocator(blockchain.clone(), pool.clone());
+
+        let lot = allocator.allocate(&alice(), "WHEAT", "2024").unwrap();
+        assert_eq!(lot.id(), "WHEAT-2024-0001");
+
+        // allocations being mined are still taken into account
+        let lot = allocator.allocate(&bob(), "WHEAT", "2024").unwrap();
+        assert_eq!(lot.id(), "WHEAT-2024-0002");
+        let transactions = pool.pop();
+        // the node vouches for the allocation to the actor
+        assert_eq!(transactions[0].recipient, alice());
+        assert_eq!(transactions[0].verify(), Ok(()));
+        let lot = allocator.allocate(&alice(), "WHEAT", "2024").unwrap();
+        assert_eq!(lot.id(), "WHEAT-2024-0003");
+
+        // and so are the ones from other nodes
+        let other_node_lot = Lot::new("WHEAT", "2024", 9).unwrap();
+        let other_node_tx = sign(Transaction {
+            sender: carol(),
+            recipient: carol(),
+            data: serde_json::to_string(&other_node_lot).unwrap().into(),
+            batch_id: other_node_lot.id(),
+            event_type: LOT_EVENT.into(),
+            ..Default::default()
+        });
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(1, 0, previous_hash, vec![other_node_tx]);
+        blockchain.add_block(block).unwrap();
+        let lot = allocator.allocate(&alice(), "WHEAT", "2024").unwrap();
+        assert_eq!(lot.id(), "WHEAT-2024-0010");
+
+        // each prefix and season has its own sequence
+        let lot = allocator.allocate(&alice(), "CORN", "2024").unwrap();
+        assert_eq!(lot.id(), "CORN-2024-0001");
+
+        assert_eq!(
+            allocator.allocate(&alice(), "corn", "2024"),
+            Err(TransactionError::InvalidLot)
+        );
+    }
+
+    #[test]
+    fn should_not_allocate_the_same_lot_concurrently() {
+        let allocator = create_allocator(Blockchain::new(0), TransactionPool::new(EventBus::new()));
+
+        let ids: Vec<String> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| scope.spawn(|| allocator.allocate(&alice(), "RICE", "2024").unwrap()))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap().id())
+                .collect()
+        });
+
+        let unique_ids: HashSet<&String> = ids.iter().collect();
+        assert_eq!(unique_ids.len(), 8);
+    }
+}
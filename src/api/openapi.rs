@@ -0,0 +1,283 @@
+use utoipa::OpenApi;
+
+use super::error::{ErrorCode, ErrorResponse};
+use crate::{
+    analytics::{
+        BatchComparison, BatchStage, BatchStatus, PartnerCompliance, PickingBasis,
+        PickingSuggestion, SensorTrend, SlaViolation, StageComparison, TrendBucket,
+        ViolationWindow,
+    },
+    cluster::{MaintenanceAnnouncement, MaintenanceNotice, MaintenanceReason},
+    model::{
+        BatchLineage, BatchPortion, Block, BlockAttestation, BlockBody, BlockHeader, BlockRef,
+        BlockStats, Change, ChangeKind, ComplianceReport, ComplianceThresholds, ComplianceWindow,
+        GenesisSummary, GpsPosition, LineageLink, Merge, MerkleProof, NormalizationStep,
+        NormalizationVersion, OriginChannel, OrphanReason, OrphanedBlock, PlanStatus, PlannedEvent,
+        Profile, ProofSide, ProofStep, ProtocolActivation, RegisteredActor, ReorgSummary, Role,
+        SensorReading, SensorReadings, Split, StatsTotals, Transaction, TransactionOrigin,
+        ValidityWindow,
+    },
+    peer::UpgradeAdvisory,
+    storage::VerificationProgress,
+};
+
+// OpenAPI 3 document of the REST API, generated from the handlers and the types they use
+// Partners can generate their clients from it, so any change here is a change of the contract
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "AgriBlock API"),
+    paths(
+        super::get_blocks,
+        super::add_block,
+        super::get_block,
+        super::get_block_attestations,
+        super::add_block_attestation,
+        super::request_mining,
+        super::get_inclusion_proof,
+        super::get_genesis,
+        super::get_normalization,
+        super::get_verification,
+        super::get_headers,
+        super::get_replication_blocks,
+        super::get_transactions,
+        super::add_transaction,
+        super::get_pending_transactions,
+        super::get_dropped_transactions,
+        super::get_transaction_thread,
+        super::get_batch_status,
+        super::get_batch_state,
+        super::get_batch_lineage,
+        super::get_batch_plans,
+        super::get_overdue_plans,
+        super::get_batch_history,
+        super::get_batch_bundle,
+        super::get_batch_comparison,
+        super::get_sensor_trend,
+        super::get_cold_chain_compliance,
+        super::get_profile,
+        super::get_registry,
+        super::add_namespace_transaction,
+        super::get_namespace_transactions,
+        super::get_namespace_batch_history,
+        super::get_namespace_registry,
+        super::get_picking_suggestions,
+        super::allocate_lot,
+        super::add_document,
+        super::get_document,
+        super::get_fingerprints,
+        super::get_reused_fingerprints,
+        super::get_saved_queries,
+        super::get_query_results,
+        super::provision_actor,
+        super::get_peers,
+        super::get_forks,
+        super::get_fork,
+        super::get_changes,
+        super::subscribe_events,
+        super::get_stats,
+        super::get_public_stats,
+        super::get_sla_reports,
+        super::get_metrics,
+        super::get_origins,
+        super::get_maintenance,
+        super::receive_maintenance_announcement,
+        super::start_maintenance,
+        super::end_maintenance,
+        super::get_verification_progress,
+        super::start_verification,
+        super::interrupt_verification,
+        super::get_upgrades,
+        super::save_query,
+        super::remove_query,
+    ),
+    components(schemas(
+        Block,
+        BlockHeader,
+        BlockBody,
+        BlockRef,
+        BlockAttestation,
+        super::AttestedBlock,
+        super::AttestationSubmission,
+        MerkleProof,
+        ProofStep,
+        ProofSide,
+        BlockStats,
+        GenesisSummary,
+        super::NormalizationVersions,
+        NormalizationVersion,
+        NormalizationStep,
+        StatsTotals,
+        OrphanReason,
+        OrphanedBlock,
+        ReorgSummary,
+        Change,
+        ChangeKind,
+        Profile,
+        RegisteredActor,
+        Role,
+        Transaction,
+        ValidityWindow,
+        BatchStatus,
+        super::BatchStateView,
+        crate::model::BatchState,
+        BatchLineage,
+        LineageLink,
+        BatchPortion,
+        Split,
+        Merge,
+        super::PlanView,
+        PlannedEvent,
+        PlanStatus,
+        super::UpgradeReport,
+        super::PeerVersion,
+        ProtocolActivation,
+        UpgradeAdvisory,
+        BatchComparison,
+        StageComparison,
+        BatchStage,
+        SensorTrend,
+        SensorReadings,
+        SensorReading,
+        GpsPosition,
+        ComplianceReport,
+        ComplianceThresholds,
+        ComplianceWindow,
+        TrendBucket,
+        ViolationWindow,
+        PickingSuggestion,
+        PickingBasis,
+        PartnerCompliance,
+        SlaViolation,
+        OriginChannel,
+        TransactionOrigin,
+        ErrorCode,
+        ErrorResponse,
+        super::TransactionSubmission,
+        super::event_counters::DroppedTransaction,
+        super::ChainVerification,
+        super::BatchEvent,
+        super::ThreadEvent,
+        super::ChangeEntry,
+        super::ChangePage,
+        super::Notification,
+        super::MiningRequest,
+        crate::verify::InclusionProof,
+        crate::verify::BundledEvent,
+        crate::verify::BatchBundle,
+        super::PayloadSelection,
+        super::ActorRegistryListing,
+        super::LotRequest,
+        super::AllocatedLot,
+        super::DocumentRequest,
+        super::SubmittedDocument,
+        super::DeliveredDocument,
+        super::fingerprint_registry::Fingerprint,
+        super::fingerprint_registry::ReusedFingerprint,
+        super::saved_queries::SavedQuery,
+        super::saved_queries::QueryFilter,
+        super::saved_queries::QueryResults,
+        super::ProvisionedActor,
+        super::ChainStatistics,
+        super::public_stats::PublicAggregate,
+        MaintenanceNotice,
+        MaintenanceReason,
+        VerificationProgress,
+        MaintenanceAnnouncement,
+        super::MaintenanceStatus,
+        super::MaintenanceRequest,
+    ))
+)]
+pub struct ApiDoc;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_document_all_routes() {
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+
+        assert!(document["openapi"].as_str().unwrap().starts_with("3."));
+        let paths = document["paths"].as_object().unwrap();
+        for (path, method) in [
+            ("/blocks", "get"),
+            ("/blocks", "post"),
+            ("/blocks/{hash}", "get"),
+            ("/blocks/{hash}/attestations", "get"),
+            ("/blocks/{hash}/attestations", "post"),
+            ("/mine", "post"),
+            ("/blocks/{index}/proofs/{position}", "get"),
+            ("/genesis", "get"),
+            ("/normalization", "get"),
+            ("/verification", "get"),
+            ("/headers", "get"),
+            ("/replication/blocks", "get"),
+            ("/transactions", "get"),
+            ("/transactions", "post"),
+            ("/transactions/pending", "get"),
+            ("/transactions/dropped", "get"),
+            ("/transactions/{hash}/thread", "get"),
+            ("/batches/{batch_id}/status", "get"),
+            ("/batches/{batch_id}/state", "get"),
+            ("/batches/{batch_id}/lineage", "get"),
+            ("/batches/{batch_id}/plans", "get"),
+            ("/plans/overdue", "get"),
+            ("/batches/{batch_id}/history", "get"),
+            ("/batches/{batch_id}/bundle", "get"),
+            ("/batches/{batch_id}/comparison/{other_batch_id}", "get"),
+            ("/batches/{batch_id}/sensors", "get"),
+            ("/batches/{batch_id}/compliance", "get"),
+            ("/profiles/{address}", "get"),
+            ("/registry", "get"),
+            ("/ns/{namespace}/transactions", "post"),
+            ("/ns/{namespace}/transactions", "get"),
+            ("/ns/{namespace}/batches/{batch_id}/history", "get"),
+            ("/ns/{namespace}/registry", "get"),
+            ("/custodians/{address}/picking", "get"),
+            ("/lots", "post"),
+            ("/documents", "post"),
+            ("/documents/{hash}", "get"),
+            ("/fingerprints/{hash}", "get"),
+            ("/fingerprints/reused", "get"),
+            ("/queries", "get"),
+            ("/queries/{name}", "get"),
+            ("/faucet/actors", "post"),
+            ("/peers", "get"),
+            ("/forks", "get"),
+            ("/forks/{hash}", "get"),
+            ("/changes", "get"),
+            ("/ws", "get"),
+            ("/stats", "get"),
+            ("/stats/public", "get"),
+            ("/sla/reports", "get"),
+            ("/metrics", "get"),
+            ("/admin/origins", "get"),
+            ("/maintenance", "get"),
+            ("/maintenance/announcements", "post"),
+            ("/admin/maintenance", "post"),
+            ("/admin/maintenance", "delete"),
+            ("/admin/verification", "get"),
+            ("/admin/verification", "post"),
+            ("/admin/verification", "delete"),
+            ("/admin/upgrades", "get"),
+            ("/admin/queries", "post"),
+            ("/admin/queries/{name}", "delete"),
+        ] {
+            assert!(paths[path].get(method).is_some(), "{} {}", method, path);
+        }
+    }
+
+    #[test]
+    fn should_document_error_bodies() {
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+
+        let error_schema = &document["components"]["schemas"]["ErrorResponse"];
+        assert!(error_schema["properties"]["code"].is_object());
+        assert!(error_schema["properties"]["message"].is_object());
+        assert!(error_schema["properties"]["details"].is_object());
+
+        let invalid_transaction = &document["paths"]["/transactions"]["post"]["responses"]["400"];
+        let schema = &invalid_transaction["content"]["application/json"]["schema"]["$ref"];
+        assert_eq!(schema, "#/components/schemas/ErrorResponse");
+    }
+}
@@ -0,0 +1,72 @@
+use std::ops::Range;
+
+use actix_web::HttpResponse;
+use serde::{Deserialize, Serialize};
+use utoipa::IntoParams;
+
+// Header with the amount of items of the whole list, so clients know when to stop asking for pages
+pub const TOTAL_COUNT_HEADER: &str = "X-Total-Count";
+
+// Page of a list returned by the API, from the oldest item
+// Without any of them the whole list is returned, as before the lists were paginated
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct Pagination {
+    // amount of items to skip
+    offset: Option<usize>,
+    // max amount of items to return
+    limit: Option<usize>,
+}
+
+impl Pagination {
+    pub fn is_requested(&self) -> bool {
+        self.offset.is_some() || self.limit.is_some()
+    }
+
+    // Returns the positions of the items of the page in a list of a length, empty if it starts after its end
+    pub fn range(&self, len: usize) -> Range<usize> {
+        let start = self.offset.unwrap_or(0).min(len);
+        let end = match self.limit {
+            Some(limit) => start.saturating_add(limit).min(len),
+            None => len,
+        };
+
+        start..end
+    }
+
+    // Returns the items of the page, empty if it starts after the end of the list
+    pub fn page<'a, T>(&self, items: &'a [T]) -> &'a [T] {
+        &items[self.range(items.len())]
+    }
+
+    // JSON response with the items of the page and the total amount of items
+    pub fn response<T: Serialize>(&self, items: &[T]) -> HttpResponse {
+        Pagination::page_response(self.page(items), items.len())
+    }
+
+    // Same, with the items of the page already read from the list (see range), e.g. without copying the others
+    pub fn page_response<T: Serialize>(page: &[T], total: usize) -> HttpResponse {
+        HttpResponse::Ok()
+            .insert_header((TOTAL_COUNT_HEADER, total))
+            .json(page)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_return_the_items_of_the_page() {
+        let items = [1, 2, 3, 4, 5];
+        let pagination = |offset, limit| Pagination { offset, limit };
+
+        assert_eq!(pagination(None, None).page(&items), &items);
+        assert_eq!(pagination(Some(1), Some(2)).page(&items), &[2, 3]);
+        assert_eq!(pagination(Some(3), None).page(&items), &[4, 5]);
+        assert_eq!(pagination(None, Some(10)).page(&items), &items);
+        assert!(pagination(Some(10), Some(2)).page(&items).is_empty());
+        assert_eq!(pagination(Some(1), Some(2)).range(items.len()), 1..3);
+        assert_eq!(pagination(Some(10), Some(2)).range(items.len()), 5..5);
+        assert!(!pagination(None, None).is_requested());
+    }
+}
@@ -0,0 +1,367 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{anyhow, Result};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use super::localization::localize;
+use crate::model::{to_kg, Address, Block, Transaction};
+
+// Only the harvested volumes are aggregated, the other events would count the same goods again
+const VOLUME_EVENT: &str = "HARVEST";
+
+// Language of the crop and region names, when they are localized labels
+const AGGREGATE_LANGUAGE: &str = "en";
+
+// Public aggregate of the harvested volume of a crop in a region
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct PublicAggregate {
+    pub crop: String,
+    pub region: String,
+    // sum of the quantities of the payloads, with noise
+    pub volume: f64,
+    // privacy budget spent by all the volumes of the group released so far, this one included
+    pub epsilon_spent: f64,
+}
+
+// Volume of each member in each crop and region
+type MemberVolumes<'a> = BTreeMap<(String, String), HashMap<&'a Address, f64>>;
+
+// Computes public aggregates that don't reveal the volumes of individual consortium members
+// Groups with too few members are left out (thresholding), each member's contribution to a group
+// is capped, and Laplace noise calibrated to that cap is added to every volume (differential privacy)
+// Every new volume of a group spends its privacy budget again, so the volumes are only released once per
+// epoch of blocks, with the budget spent by each group since the genesis
+pub struct PublicStats {
+    min_contributors: usize,
+    // privacy budget of each volume, lower is more private (0 disables the noise)
+    epsilon: f64,
+    max_contribution: f64,
+    // blocks between two releases of the volumes
+    epoch_blocks: u64,
+    // secret shared by the nodes of the consortium, so the noise can neither be recomputed and subtracted
+    // by others nor averaged out over the answers of several nodes
+    salt: Option<[u8; 32]>,
+}
+
+impl PublicStats {
+    pub fn new(
+        min_contributors: usize,
+        epsilon: f64,
+        max_contribution: f64,
+        epoch_blocks: u64,
+        salt: &str,
+    ) -> Result<PublicStats> {
+        let salt = match salt.is_empty() {
+            true => None,
+            false => Some(
+                hex::decode(salt)
+                    .ok()
+                    .and_then(|salt| salt.try_into().ok())
+                    .ok_or_else(|| {
+                        anyhow!("The salt of the public statistics must be 32 bytes in hex")
+                    })?,
+            ),
+        };
+
+        Ok(PublicStats {
+            min_contributors,
+            epsilon,
+            max_contribution,
+            epoch_blocks: epoch_blocks.max(1),
+            salt,
+        })
+    }
+
+    // The noise needs the salt of the consortium, so there are no volumes without it (unless the noise is disabled)
+    pub fn is_available(&self) -> bool {
+        self.epsilon <= 0.0 || self.salt.is_some()
+    }
+
+    // The volumes released at the end of the last complete epoch of the chain, none before the first one
+    pub fn publish(&self, blocks: &[Block]) -> Option<Vec<PublicAggregate>> {
+        if !self.is_available() {
+            return None;
+        }
+
+        let mut members = MemberVolumes::new();
+        // the last volume released of each group, and how many different volumes it had
+        let mut releases: HashMap<(String, String), (f64, u64)> = HashMap::new();
+        let mut aggregates = Vec::new();
+        for block in blocks.iter() {
+            add_harvests(&mut members, &block.body.transactions);
+            if block.header.index == 0 || block.header.index % self.epoch_blocks != 0 {
+                continue;
+            }
+
+            aggregates = self.volumes(&members);
+            for (group, volume) in aggregates.iter() {
+                let release = releases.entry(group.clone()).or_insert((f64::NAN, 0));
+                if release.0 != *volume {
+                    *release = (*volume, release.1 + 1);
+                }
+            }
+        }
+
+        let aggregates = aggregates
+            .into_iter()
+            .map(|((crop, region), volume)| {
+                let noise = self.laplace_noise(&crop, &region, volume);
+                let released = releases[&(crop.clone(), region.clone())].1;
+                PublicAggregate {
+                    volume: (volume + noise).max(0.0).round(),
+                    epsilon_spent: self.epsilon * released as f64,
+                    crop,
+                    region,
+                }
+            })
+            .collect();
+        Some(aggregates)
+    }
+
+    // The exact volumes of the groups with enough members, each member capped
+    fn volumes(&self, members: &MemberVolumes) -> Vec<((String, String), f64)> {
+        members
+            .iter()
+            .filter(|(_, volumes)| volumes.len() >= self.min_contributors)
+            .map(|(group, volumes)| {
+                let volume: f64 = volumes
+                    .values()
+                    .map(|volume| volume.min(self.max_contribution))
+                    .sum();
+                (group.clone(), volume)
+            })
+            .collect()
+    }
+
+    // Seeded by the contents of the aggregate, its exact volume included
+    // The noise only changes when the aggregate does, so repeating a query doesn't cancel it out
+    fn laplace_noise(&self, crop: &str, region: &str, volume: f64) -> f64 {
+        let salt = match self.salt {
+            Some(salt) if self.epsilon > 0.0 => salt,
+            _ => return 0.0,
+        };
+
+        let mut hasher = Sha256::new();
+        hasher.update(salt);
+        hasher.update(crop.as_bytes());
+        hasher.update([0]);
+        hasher.update(region.as_bytes());
+        hasher.update([0]);
+        hasher.update(volume.to_bits().to_be_bytes());
+        let mut rng = StdRng::from_seed(hasher.finalize().into());
+
+        // inverse of the cumulative distribution of Laplace(0, scale)
+        let scale = self.max_contribution / self.epsilon;
+        let uniform: f64 = rng.gen_range(-0.5..0.5);
+        -scale * uniform.signum() * (1.0 - 2.0 * uniform.abs()).ln()
+    }
+}
+
+// Adds the harvested volumes of some transactions to the volumes of their members
+fn add_harvests<'a>(members: &mut MemberVolumes<'a>, transactions: &'a [Transaction]) {
+    for transaction in transactions.iter() {
+        if transaction.event_type != VOLUME_EVENT {
+            continue;
+        }
+        if let Some((crop, region, quantity)) = parse_harvest(&transaction.data.as_json()) {
+            // gateways are not members, the actors they act for are
+            let member = transaction
+                .on_behalf_of
+                .as_ref()
+                .unwrap_or(&transaction.sender);
+            let volumes = members.entry((crop, region)).or_default();
+            *volumes.entry(member).or_insert(0.0) += quantity;
+        }
+    }
+}
+
+// Reads the crop, region and quantity of a harvest payload
+// e.g. {"crop": "wheat", "region": "Punjab", "quantity": "500kg"}, the quantities are converted to kilograms
+// (kilograms without a unit) and the ones in an unknown unit are left out
+// Typed harvests have a "quantity_kg" instead, and the region among their other fields
+fn parse_harvest(data: &str) -> Option<(String, String, f64)> {
+    let mut payload: Value = serde_json::from_str(data).ok()?;
+    localize(&mut payload, AGGREGATE_LANGUAGE);
+
+    let crop = payload.get("crop")?.as_str()?.trim().to_lowercase();
+    let region = payload.get("region")?.as_str()?.trim().to_string();
+    let quantity = match payload
+        .get("quantity")
+        .or_else(|| payload.get("quantity_kg"))?
+    {
+        Value::Number(number) => number.as_f64()?,
+        Value::String(text) => to_kg(text, Some("kg"))?,
+        _ => return None,
+    };
+
+    (quantity >= 0.0).then_some((crop, region, quantity))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::fixtures::{alice, bob, carol};
+
+    #[test]
+    fn should_parse_harvest_payloads() {
+        let data = r#"{"crop": "Wheat", "region": "Punjab", "quantity": "500.5kg"}"#;
+        assert_eq!(
+            parse_harvest(data),
+            Some(("wheat".to_string(), "Punjab".to_string(), 500.5))
+        );
+
+        let data = r#"{"crop": {"i18n": {"fr": "Blé", "en": "Wheat"}}, "region": "Punjab", "quantity": 20}"#;
+        assert_eq!(
+            parse_harvest(data),
+            Some(("wheat".to_string(), "Punjab".to_string(), 20.0))
+        );
+
+        // a ton weighs as much as 1000 kilograms
+        let data = r#"{"crop": "wheat", "region": "Punjab", "quantity": "1.5t"}"#;
+        assert_eq!(parse_harvest(data).unwrap().2, 1500.0);
+        let data = r#"{"crop": "wheat", "region": "Punjab", "quantity": "20 bushels"}"#;
+        assert_eq!(parse_harvest(data), None);
+
+        assert_eq!(parse_harvest(r#"{"crop": "wheat", "quantity": 20}"#), None);
+        assert_eq!(parse_harvest("500kg of wheat"), None);
+    }
+
+    #[test]
+    fn should_hide_groups_with_few_members() {
+        let stats = PublicStats::new(3, 0.0, 10_000.0, 1, "").unwrap();
+        let mut transactions = vec![
+            create_harvest(alice(), "wheat", 100),
+            create_harvest(bob(), "wheat", 200),
+            create_harvest(carol(), "wheat", 300),
+            create_harvest(alice(), "wheat", 50),
+            // only two members harvested rice
+            create_harvest(alice(), "rice", 100),
+            create_harvest(bob(), "rice", 100),
+        ];
+        transactions.push(Transaction {
+            event_type: "TRANSPORT".into(),
+            ..create_harvest(carol(), "rice", 100)
+        });
+
+        let aggregates = stats.publish(&create_chain(vec![transactions])).unwrap();
+        assert_eq!(
+            aggregates,
+            vec![PublicAggregate {
+                crop: "wheat".to_string(),
+                region: "Punjab".to_string(),
+                volume: 650.0,
+                epsilon_spent: 0.0,
+            }]
+        );
+    }
+
+    #[test]
+    fn should_cap_contributions_and_add_noise() {
+        let transactions = vec![
+            create_harvest(alice(), "wheat", 100),
+            create_harvest(bob(), "wheat", 100),
+            create_harvest(carol(), "wheat", 1_000_000),
+        ];
+
+        // a single large member cannot dominate the volume
+        let stats = PublicStats::new(3, 0.0, 1000.0, 1, "").unwrap();
+        let aggregates = stats.publish(&create_chain(vec![transactions.clone()]));
+        assert_eq!(aggregates.unwrap()[0].volume, 1200.0);
+
+        // the noise needs the salt of the consortium
+        let stats = PublicStats::new(3, 0.5, 1000.0, 1, "").unwrap();
+        assert_eq!(
+            stats.publish(&create_chain(vec![transactions.clone()])),
+            None
+        );
+        assert!(PublicStats::new(3, 0.5, 1000.0, 1, "abcd").is_err());
+
+        // with noise the volume changes with the aggregate, but not between queries, nodes nor with unrelated events
+        let salt = "07".repeat(32);
+        let stats = PublicStats::new(3, 0.5, 1000.0, 1, &salt).unwrap();
+        let noisy = stats.publish(&create_chain(vec![transactions.clone()]));
+        let other_node = PublicStats::new(3, 0.5, 1000.0, 1, &salt).unwrap();
+        assert_eq!(
+            other_node.publish(&create_chain(vec![transactions.clone()])),
+            noisy
+        );
+        let mut more_transactions = transactions.clone();
+        more_transactions.push(create_harvest(alice(), "rice", 100));
+        assert_eq!(stats.publish(&create_chain(vec![more_transactions])), noisy);
+        let volumes: Vec<f64> = (0..20)
+            .map(|quantity| {
+                let mut transactions = transactions.clone();
+                transactions.push(create_harvest(alice(), "wheat", quantity));
+                stats.publish(&create_chain(vec![transactions])).unwrap()[0].volume
+            })
+            .collect();
+        assert!(volumes
+            .iter()
+            .zip(0..)
+            .any(|(volume, quantity)| *volume != 1200.0 + quantity as f64));
+    }
+
+    #[test]
+    fn should_only_release_the_volumes_once_per_epoch() {
+        let stats = PublicStats::new(2, 0.5, 1000.0, 2, &"07".repeat(32)).unwrap();
+        let harvests = vec![
+            create_harvest(alice(), "wheat", 100),
+            create_harvest(bob(), "wheat", 100),
+        ];
+
+        // nothing is released before the end of the first epoch
+        let chain = create_chain(vec![harvests.clone()]);
+        assert_eq!(stats.publish(&chain), Some(Vec::new()));
+
+        // the harvests of the next epoch are left out until it ends
+        let mut chain = create_chain(vec![
+            harvests,
+            Vec::new(),
+            vec![create_harvest(alice(), "wheat", 1)],
+        ]);
+        let released = stats.publish(&chain).unwrap();
+        assert_eq!(released[0].epsilon_spent, 0.5);
+
+        // a new volume spends the budget again, the same volume doesn't
+        let next_block = |chain: &[Block]| {
+            let last = chain.last().unwrap();
+            Block::new(last.header.index + 1, 0, last.header.hash, Vec::new())
+        };
+        chain.push(next_block(&chain));
+        let next_release = stats.publish(&chain).unwrap();
+        assert_ne!(next_release, released);
+        assert_eq!(next_release[0].epsilon_spent, 1.0);
+        chain.push(next_block(&chain));
+        chain.push(next_block(&chain));
+        assert_eq!(stats.publish(&chain).unwrap(), next_release);
+    }
+
+    // A chain with a block for each list of transactions after the genesis block
+    fn create_chain(blocks: Vec<Vec<Transaction>>) -> Vec<Block> {
+        let mut chain = vec![Block::new(0, 0, Default::default(), Vec::new())];
+        for transactions in blocks {
+            let last = chain.last().unwrap();
+            let block = Block::new(last.header.index + 1, 0, last.header.hash, transactions);
+            chain.push(block);
+        }
+        chain
+    }
+
+    fn create_harvest(sender: Address, crop: &str, quantity: u64) -> Transaction {
+        Transaction {
+            sender,
+            data: format!(
+                r#"{{"crop": "{}", "region": "Punjab", "quantity": "{}kg"}}"#,
+                crop, quantity
+            )
+            .into(),
+            batch_id: "WHEAT-2024-001".to_string(),
+            event_type: VOLUME_EVENT.into(),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use lru::LruCache;
+
+use crate::model::BlockHash;
+
+// Cached responses, "None" represents a query that had no results
+pub type CachedValue = Option<String>;
+
+// The response of a query, calculated once by the first request while the other requests of the query wait for it
+type CacheSlot = Arc<OnceLock<CachedValue>>;
+
+struct CacheState {
+    // all the entries were calculated when this block was the last one of the chain
+    tip: BlockHash,
+    entries: LruCache<String, CacheSlot>,
+    hits: u64,
+    misses: u64,
+}
+
+// Least recently used cache for the responses of frequently requested queries
+// All entries are discarded as soon as the last block of the chain changes (new block or reorg)
+// Multiple threads can read/write concurrently to the cache, the responses are calculated without locking it
+#[derive(Clone)]
+pub struct QueryCache {
+    // none when the cache is disabled
+    capacity: Option<NonZeroUsize>,
+    state: Arc<Mutex<CacheState>>,
+}
+
+// Snapshot of the cache usage, to be exposed as metrics
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+impl QueryCache {
+    // Creates an empty cache, a capacity of 0 disables it
+    pub fn new(capacity: usize) -> QueryCache {
+        let capacity = NonZeroUsize::new(capacity);
+        let state = CacheState {
+            tip: BlockHash::default(),
+            entries: LruCache::new(capacity.unwrap_or(NonZeroUsize::MIN)),
+            hits: 0,
+            misses: 0,
+        };
+
+        QueryCache {
+            capacity,
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    // Returns the cached value of a query, calculating and storing it if it's not present
+    pub fn get_or_compute<F>(&self, tip: BlockHash, key: &str, compute: F) -> CachedValue
+    where
+        F: FnOnce() -> CachedValue,
+    {
+        let slot = {
+            let mut state = self.state.lock().unwrap();
+
+            // the chain has changed, so all previous results may be stale
+            if state.tip != tip {
+                state.tip = tip;
+                state.entries.clear();
+            }
+
+            match (self.capacity, state.entries.get(key).cloned()) {
+                (None, _) => {
+                    state.misses += 1;
+                    None
+                }
+                (Some(_), Some(slot)) => {
+                    state.hits += 1;
+                    Some(slot)
+                }
+                // the least recently used entry is evicted when the cache is full
+                (Some(_), None) => {
+                    state.misses += 1;
+                    let slot = CacheSlot::default();
+                    state.entries.put(key.to_string(), slot.clone());
+                    Some(slot)
+                }
+            }
+        };
+
+        // a slow query only holds up the requests of the same query
+        match slot {
+            Some(slot) => slot.get_or_init(compute).clone(),
+            None => compute(),
+        }
+    }
+
+    pub fn get_stats(&self) -> CacheStats {
+        let state = self.state.lock().unwrap();
+
+        CacheStats {
+            hits: state.hits,
+            misses: state.misses,
+            entries: state.entries.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_return_cached_values() {
+        let cache = QueryCache::new(10);
+        let tip = BlockHash::from(1);
+
+        let value = cache.get_or_compute(tip, "blocks", || Some("first".to_string()));
+        assert_eq!(value, Some("first".to_string()));
+
+        // the second time the value is not calculated again
+        let value = cache.get_or_compute(tip, "blocks", || Some("second".to_string()));
+        assert_eq!(value, Some("first".to_string()));
+
+        let stats = cache.get_stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.entries, 1);
+    }
+
+    #[test]
+    fn should_invalidate_when_tip_changes() {
+        let cache = QueryCache::new(10);
+
+        cache.get_or_compute(BlockHash::from(1), "blocks", || Some("old".to_string()));
+
+        // a new block was added, so the value must be calculated again
+        let value = cache.get_or_compute(BlockHash::from(2), "blocks", || Some("new".to_string()));
+        assert_eq!(value, Some("new".to_string()));
+        assert_eq!(cache.get_stats().misses, 2);
+    }
+
+    #[test]
+    fn should_evict_least_recently_used_entries() {
+        let cache = QueryCache::new(2);
+        let tip = BlockHash::from(1);
+
+        cache.get_or_compute(tip, "a", || Some("a".to_string()));
+        cache.get_or_compute(tip, "b", || Some("b".to_string()));
+
+        // "a" is used again, so "b" becomes the least recently used
+        cache.get_or_compute(tip, "a", || None);
+        cache.get_or_compute(tip, "c", || Some("c".to_string()));
+
+        assert_eq!(cache.get_stats().entries, 2);
+        assert_eq!(
+            cache.get_or_compute(tip, "a", || None),
+            Some("a".to_string())
+        );
+        assert_eq!(cache.get_or_compute(tip, "b", || None), None);
+    }
+
+    #[test]
+    fn should_compute_the_queries_without_locking_the_cache() {
+        let cache = QueryCache::new(10);
+        let tip = BlockHash::from(1);
+        let (started_sender, started) = std::sync::mpsc::channel();
+        let (release, released) = std::sync::mpsc::channel::<()>();
+
+        let cache = &cache;
+        std::thread::scope(|scope| {
+            let slow = scope.spawn(move || {
+                cache.get_or_compute(tip, "slow", || {
+                    started_sender.send(()).unwrap();
+                    released.recv().unwrap();
+                    Some("slow".to_string())
+                })
+            });
+            started.recv().unwrap();
+
+            // other queries are answered meanwhile...
+            let value = cache.get_or_compute(tip, "fast", || Some("fast".to_string()));
+            assert_eq!(value, Some("fast".to_string()));
+
+            // ...and the same query waits for the first computation instead of repeating it
+            let waiting = scope.spawn(move || cache.get_or_compute(tip, "slow", || None));
+            release.send(()).unwrap();
+            assert_eq!(slow.join().unwrap(), Some("slow".to_string()));
+            assert_eq!(waiting.join().unwrap(), Some("slow".to_string()));
+        });
+
+        let stats = cache.get_stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 2);
+    }
+
+    #[test]
+    fn should_not_store_anything_when_disabled() {
+        let cache = QueryCache::new(0);
+        let tip = BlockHash::from(1);
+
+        cache.get_or_compute(tip, "blocks", || Some("first".to_string()));
+        let value = cache.get_or_compute(tip, "blocks", || Some("second".to_string()));
+
+        assert_eq!(value, Some("second".to_string()));
+        assert_eq!(cache.get_stats().entries, 0);
+    }
+}
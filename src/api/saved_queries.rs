@@ -0,0 +1,494 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use super::json_path::{JsonPath, JsonPathError};
+use crate::model::{Address, BlockHash, Blockchain, Transaction};
+
+// Queries registered on a node, each one is evaluated against every new block
+const MAX_SAVED_QUERIES: usize = 50;
+
+#[derive(Error, PartialEq, Debug)]
+pub enum SavedQueryError {
+    #[error("The name of a query must be 1 to 64 letters, digits, '-' or '_'")]
+    InvalidName,
+    #[error("The filter of a query needs an event_type, an actor or a path")]
+    EmptyFilter,
+    #[error("A value to compare needs a path in the payload")]
+    ValueWithoutPath,
+    #[error(transparent)]
+    InvalidPath(#[from] JsonPathError),
+    #[error("At most {0} queries can be saved")]
+    TooManyQueries(usize),
+    #[error("The queries could not be persisted: {0}")]
+    NotPersisted(String),
+}
+
+// The events selected by a query, all the given criteria must match
+// e.g. {"event_type": "HARVEST", "path": "$.organic", "equals": true} for the harvests claimed organic
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct QueryFilter {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_type: Option<String>,
+    // the actor of the event, on behalf of whom it was sent if it was delegated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub actor: Option<Address>,
+    // JSONPath expression in the (JSON) payload, which must select a value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    // the value that the path must select, any value if none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub equals: Option<Value>,
+}
+
+// A filter ready to be matched against the transactions, with its path parsed once
+struct Matcher {
+    filter: QueryFilter,
+    path: Option<JsonPath>,
+}
+
+impl Matcher {
+    fn new(filter: &QueryFilter) -> Result<Matcher, SavedQueryError> {
+        if filter.event_type.is_none() && filter.actor.is_none() && filter.path.is_none() {
+            return Err(SavedQueryError::EmptyFilter);
+        }
+        if filter.path.is_none() && filter.equals.is_some() {
+            return Err(SavedQueryError::ValueWithoutPath);
+        }
+
+        Ok(Matcher {
+            filter: filter.clone(),
+            path: filter.path.as_deref().map(JsonPath::from_str).transpose()?,
+        })
+    }
+
+    fn matches(&self, transaction: &Transaction) -> bool {
+        let type_matches = self
+            .filter
+            .event_type
+            .as_ref()
+            .is_none_or(|event_type| transaction.event_type == event_type.as_str());
+        let actor = transaction
+            .on_behalf_of
+            .as_ref()
+            .unwrap_or(&transaction.sender);
+        let actor_matches = self
+            .filter
+            .actor
+            .as_ref()
+            .is_none_or(|expected| expected == actor);
+        if !type_matches || !actor_matches {
+            return false;
+        }
+
+        let path = match &self.path {
+            Some(path) => path,
+            None => return true,
+        };
+        let payload: Value = match serde_json::from_str(&transaction.data.as_json()) {
+            Ok(payload) => payload,
+            Err(_) => return false,
+        };
+        match (path.evaluate(&payload), &self.filter.equals) {
+            (None, _) | (Some(Value::Null), None) => false,
+            (Some(_), None) => true,
+            (Some(value), Some(expected)) => value == *expected,
+        }
+    }
+}
+
+// A standing question about the batches of the chain, e.g. the harvests claimed organic without a certification:
+// the batches with an event matching the filter, but none matching the exception
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SavedQuery {
+    pub name: String,
+    pub filter: QueryFilter,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub except: Option<QueryFilter>,
+}
+
+impl SavedQuery {
+    fn is_valid_name(name: &str) -> bool {
+        (1..=64).contains(&name.len())
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    }
+}
+
+// The batches of a query, up to a block of the chain
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct QueryResults {
+    pub name: String,
+    // index of the last block applied to the results
+    pub until_block: u64,
+    pub batch_ids: Vec<String>,
+}
+
+struct QueryState {
+    query: SavedQuery,
+    filter: Matcher,
+    except: Option<Matcher>,
+    matched: BTreeSet<String>,
+    excluded: BTreeSet<String>,
+}
+
+impl QueryState {
+    fn new(query: SavedQuery) -> Result<QueryState, SavedQueryError> {
+        if !SavedQuery::is_valid_name(&query.name) {
+            return Err(SavedQueryError::InvalidName);
+        }
+
+        Ok(QueryState {
+            filter: Matcher::new(&query.filter)?,
+            except: query.except.as_ref().map(Matcher::new).transpose()?,
+            query,
+            matched: BTreeSet::new(),
+            excluded: BTreeSet::new(),
+        })
+    }
+
+    fn apply(&mut self, transaction: &Transaction) {
+        if self.filter.matches(transaction) {
+            self.matched.insert(transaction.batch_id.clone());
+        }
+        if let Some(except) = &self.except {
+            if except.matches(transaction) {
+                self.excluded.insert(transaction.batch_id.clone());
+            }
+        }
+    }
+
+    fn batch_ids(&self) -> Vec<String> {
+        self.matched.difference(&self.excluded).cloned().collect()
+    }
+}
+
+#[derive(Default)]
+struct QueryIndex {
+    queries: BTreeMap<String, QueryState>,
+    // last applied block, to detect that the chain was replaced
+    last_block: Option<(u64, BlockHash)>,
+}
+
+// Named queries registered by the operator, whose results are kept up to date block after block,
+// so the standing questions are answered without scanning the whole chain each time
+// The definitions are stored in a file (if set) to be registered again after a restart
+#[derive(Clone)]
+pub struct SavedQueries {
+    blockchain: Blockchain,
+    index: Arc<Mutex<QueryIndex>>,
+    file_path: String,
+}
+
+impl SavedQueries {
+    pub fn new(blockchain: Blockchain, file_path: String) -> SavedQueries {
+        SavedQueries {
+            blockchain,
+            index: Arc::default(),
+            file_path,
+        }
+    }
+
+    // Registers a query, or replaces the one with the same name, and returns its results on the whole chain
+    pub fn register(&self, query: SavedQuery) -> Result<QueryResults, SavedQueryError> {
+        let mut state = QueryState::new(query)?;
+        let mut index = self.sync();
+        let is_new = !index.queries.contains_key(&state.query.name);
+        if is_new && index.queries.len() >= MAX_SAVED_QUERIES {
+            return Err(SavedQueryError::TooManyQueries(MAX_SAVED_QUERIES));
+        }
+
+        // the other queries are up to date, only the new one has to scan the chain
+        let until_block = index.last_block.map(|(last, _)| last);
+        for block_index in until_block.map(|last| 0..=last).into_iter().flatten() {
+            let block = match self.blockchain.get_block(block_index) {
+                Some(block) => block,
+                None => break,
+            };
+            for transaction in block.body.transactions.iter() {
+                if !transaction.batch_id.is_empty() {
+                    state.apply(transaction);
+                }
+            }
+        }
+
+        let name = state.query.name.clone();
+        let previous = index.queries.insert(name.clone(), state);
+        if let Err(error) = self.save(&index) {
+            match previous {
+                Some(previous) => index.queries.insert(name, previous),
+                None => index.queries.remove(&name),
+            };
+            return Err(SavedQueryError::NotPersisted(error.to_string()));
+        }
+
+        Ok(Self::results_of(&index, &name).unwrap())
+    }
+
+    // Forgets a query, returns false if there was none with that name
+    pub fn remove(&self, name: &str) -> Result<bool> {
+        let mut index = self.index.lock().unwrap();
+        let removed = index.queries.remove(name);
+        if let Err(error) = self.save(&index) {
+            if let Some(removed) = removed {
+                index.queries.insert(name.to_string(), removed);
+            }
+            return Err(error);
+        }
+
+        Ok(removed.is_some())
+    }
+
+    pub fn list(&self) -> Vec<SavedQuery> {
+        let index = self.index.lock().unwrap();
+        index
+            .queries
+            .values()
+            .map(|state| state.query.clone())
+            .collect()
+    }
+
+    // The current results of a query, none if there is no query with that name
+    pub fn results(&self, name: &str) -> Option<QueryResults> {
+        let index = self.sync();
+        Self::results_of(&index, name)
+    }
+
+    // Registers again the queries persisted in previous runs
+    pub fn load(&self) -> Result<()> {
+        if self.file_path.is_empty() || fs::metadata(&self.file_path).is_err() {
+            return Ok(());
+        }
+
+        let raw_queries = fs::read_to_string(&self.file_path)?;
+        let queries: Vec<SavedQuery> = serde_json::from_str(&raw_queries)?;
+        let mut index = self.index.lock().unwrap();
+        *index = QueryIndex::default();
+        for query in queries.into_iter() {
+            let state = QueryState::new(query)?;
+            index.queries.insert(state.query.name.clone(), state);
+        }
+
+        Ok(())
+    }
+
+    fn save(&self, index: &QueryIndex) -> Result<()> {
+        if self.file_path.is_empty() {
+            return Ok(());
+        }
+
+        let queries: Vec<&SavedQuery> = index.queries.values().map(|state| &state.query).collect();
+        fs::write(&self.file_path, serde_json::to_string_pretty(&queries)?)?;
+
+        Ok(())
+    }
+
+    fn results_of(index: &QueryIndex, name: &str) -> Option<QueryResults> {
+        let state = index.queries.get(name)?;
+        Some(QueryResults {
+            name: name.to_string(),
+            until_block: index.last_block.map(|(last, _)| last).unwrap_or(0),
+            batch_ids: state.batch_ids(),
+        })
+    }
+
+    // Applies the blocks added since the last call to all the queries, or the whole chain again if it was replaced
+    fn sync(&self) -> MutexGuard<'_, QueryIndex> {
+        let mut index = self.index.lock().unwrap();
+        if let Some((last_index, last_hash)) = index.last_block {
+            let replaced = self
+                .blockchain
+                .get_block(last_index)
+                .map(|block| block.header.hash != last_hash)
+                .unwrap_or(true);
+            if replaced {
+                index.last_block = None;
+                for state in index.queries.values_mut() {
+                    state.matched.clear();
+                    state.excluded.clear();
+                }
+            }
+        }
+
+        let mut next = index.last_block.map(|(last, _)| last + 1).unwrap_or(0);
+        while let Some(block) = self.blockchain.get_block(next) {
+            let transactions = block
+                .body
+                .transactions
+                .iter()
+                .filter(|transaction| !transaction.batch_id.is_empty());
+            for transaction in transactions {
+                for state in index.queries.values_mut() {
+                    state.apply(transaction);
+                }
+            }
+            index.last_block = Some((block.header.index, block.header.hash));
+            next += 1;
+        }
+
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        fixtures::{alice, bob, sign},
+        Block,
+    };
+
+    #[test]
+    fn should_keep_the_results_up_to_date() {
+        let blockchain = Blockchain::new(0);
+        let queries = SavedQueries::new(blockchain.clone(), String::new());
+        add_block(
+            &blockchain,
+            vec![
+                event("WHEAT-1", "HARVEST", alice(), r#"{"organic": true}"#),
+                event("WHEAT-2", "HARVEST", alice(), r#"{"organic": false}"#),
+                event("WHEAT-3", "HARVEST", bob(), r#"{"organic": true}"#),
+            ],
+        );
+
+        // the chain is scanned once when the query is registered
+        let results = queries.register(uncertified_organic()).unwrap();
+        assert_eq!(results.until_block, 1);
+        assert_eq!(results.batch_ids, vec!["WHEAT-1", "WHEAT-3"]);
+
+        // then only the new blocks are applied
+        add_block(
+            &blockchain,
+            vec![
+                event("WHEAT-1", "CERTIFICATION", bob(), "{}"),
+                event("WHEAT-4", "HARVEST", alice(), r#"{"organic": true}"#),
+            ],
+        );
+        let results = queries.results("uncertified-organic").unwrap();
+        assert_eq!(results.until_block, 2);
+        assert_eq!(results.batch_ids, vec!["WHEAT-3", "WHEAT-4"]);
+
+        // a query can be replaced, and filter on the actor
+        let mut query = uncertified_organic();
+        query.filter.actor = Some(bob());
+        let results = queries.register(query).unwrap();
+        assert_eq!(results.batch_ids, vec!["WHEAT-3"]);
+        assert_eq!(queries.list().len(), 1);
+
+        assert!(queries.remove("uncertified-organic").unwrap());
+        assert!(!queries.remove("uncertified-organic").unwrap());
+        assert_eq!(queries.results("uncertified-organic"), None);
+    }
+
+    #[test]
+    fn should_not_register_invalid_queries() {
+        let queries = SavedQueries::new(Blockchain::new(0), String::new());
+
+        let mut query = uncertified_organic();
+        query.name = "uncertified organic".to_string();
+        assert_eq!(queries.register(query), Err(SavedQueryError::InvalidName));
+
+        let mut query = uncertified_organic();
+        query.filter = QueryFilter::default();
+        assert_eq!(queries.register(query), Err(SavedQueryError::EmptyFilter));
+
+        let mut query = uncertified_organic();
+        query.filter.path = None;
+        assert_eq!(
+            queries.register(query),
+            Err(SavedQueryError::ValueWithoutPath)
+        );
+
+        let mut query = uncertified_organic();
+        query.filter.path = Some("$.organic[".to_string());
+        assert!(matches!(
+            queries.register(query),
+            Err(SavedQueryError::InvalidPath(_))
+        ));
+
+        for position in 0..MAX_SAVED_QUERIES {
+            let mut query = uncertified_organic();
+            query.name = format!("query-{}", position);
+            queries.register(query).unwrap();
+        }
+        assert_eq!(
+            queries.register(uncertified_organic()),
+            Err(SavedQueryError::TooManyQueries(MAX_SAVED_QUERIES))
+        );
+    }
+
+    #[test]
+    fn should_persist_the_queries() {
+        let file_path = std::env::temp_dir().join("rust_blockchain_saved_queries_test.json");
+        let file_path = file_path.to_str().unwrap().to_string();
+        let blockchain = Blockchain::new(0);
+        add_block(
+            &blockchain,
+            vec![event("WHEAT-1", "HARVEST", alice(), r#"{"organic": true}"#)],
+        );
+
+        let queries = SavedQueries::new(blockchain.clone(), file_path.clone());
+        queries.register(uncertified_organic()).unwrap();
+
+        let loaded = SavedQueries::new(blockchain, file_path.clone());
+        loaded.load().unwrap();
+        assert_eq!(loaded.list(), vec![uncertified_organic()]);
+        assert_eq!(
+            loaded.results("uncertified-organic").unwrap().batch_ids,
+            vec!["WHEAT-1"]
+        );
+
+        fs::remove_file(file_path).unwrap();
+    }
+
+    fn uncertified_organic() -> SavedQuery {
+        SavedQuery {
+            name: "uncertified-organic".to_string(),
+            filter: QueryFilter {
+                event_type: Some("HARVEST".to_string()),
+                path: Some("$.organic".to_string()),
+                equals: Some(Value::Bool(true)),
+                ..Default::default()
+            },
+            except: Some(QueryFilter {
+                event_type: Some("CERTIFICATION".to_string()),
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn event(batch_id: &str, event_type: &str, sender: Address, data: &str) -> Transaction {
+        sign(Transaction {
+            sender,
+            batch_id: batch_id.to_string(),
+            event_type: event_type.into(),
+            data: data.into(),
+            ..Default::default()
+        })
+    }
+
+    fn add_block(blockchain: &Blockchain, transactions: Vec<Transaction>) {
+        let last_block = blockchain.get_last_block();
+        let block = Block::new(
+            last_block.header.index + 1,
+            0,
+            last_block.header.hash,
+            transactions,
+        );
+        blockchain.add_block(block).unwrap();
+    }
+}
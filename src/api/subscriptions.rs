@@ -0,0 +1,447 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use actix_ws::{Message, MessageStream, Session};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::saved_queries::SavedQueries;
+use crate::model::{
+    transaction_hash, Block, BlockHeader, BlockRef, ChainEvent, EventSubscription, Transaction,
+    TxHash,
+};
+
+// Most batches followed by a single connection, a dashboard follows the shipments of a few trucks
+const MAX_SUBSCRIBED_BATCHES: usize = 100;
+
+// Messages of the clients to change the batches they follow, e.g. {"action": "subscribe", "batch_id": "WHEAT-1"},
+// or the saved queries whose results they follow, e.g. {"action": "subscribe_query", "name": "uncertified-organic"}
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum SubscriptionRequest {
+    Subscribe { batch_id: String },
+    Unsubscribe { batch_id: String },
+    SubscribeQuery { name: String },
+    UnsubscribeQuery { name: String },
+}
+
+// Messages pushed to the clients, as JSON text frames
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Notification {
+    // A block was added to the chain, mined by the node or received from a peer
+    BlockAdded {
+        header: BlockHeader,
+    },
+    // A transaction of a followed batch was accepted in the pool
+    TransactionPending {
+        #[schema(value_type = String)]
+        hash: TxHash,
+        transaction: Transaction,
+    },
+    // A transaction of a followed batch was added to the chain in a block
+    TransactionMined {
+        #[schema(value_type = String)]
+        hash: TxHash,
+        transaction: Transaction,
+        block: BlockRef,
+    },
+    // The blocks after the ancestor were replaced by the ones of a longer fork, with these headers
+    // The transactions of the followed batches in the new blocks are notified again as mined
+    ChainReorganized {
+        ancestor: u64,
+        rolled_back: u64,
+        headers: Vec<BlockHeader>,
+    },
+    // The batches followed after a request of the client
+    Subscribed {
+        batch_ids: Vec<String>,
+    },
+    // The current results of a saved query that the client started to follow
+    QuerySubscribed {
+        name: String,
+        until_block: u64,
+        batch_ids: Vec<String>,
+    },
+    // The batches that entered or left the results of a followed query with the new blocks
+    QueryChanged {
+        name: String,
+        until_block: u64,
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+    // A request of the client that could not be applied
+    Error {
+        message: String,
+    },
+}
+
+// The batches and the saved queries followed by a connection
+#[derive(Default)]
+pub struct Subscription {
+    batch_ids: BTreeSet<String>,
+    saved_queries: Option<SavedQueries>,
+    // the results last sent of each followed query
+    queries: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Subscription {
+    pub fn new(batch_ids: &[String]) -> Subscription {
+        let batch_ids = batch_ids
+            .iter()
+            .filter(|batch_id| !batch_id.is_empty())
+            .take(MAX_SUBSCRIBED_BATCHES)
+            .cloned()
+            .collect();
+
+        Subscription {
+            batch_ids,
+            ..Default::default()
+        }
+    }
+
+    // Lets the client follow the results of the queries saved on the node
+    pub fn with_saved_queries(mut self, saved_queries: SavedQueries) -> Subscription {
+        self.saved_queries = Some(saved_queries);
+        self
+    }
+
+    // Applies a message of the client, and returns the answer to send back
+    pub fn handle(&mut self, message: &str) -> Notification {
+        let request = match serde_json::from_str::<SubscriptionRequest>(message) {
+            Ok(request) => request,
+            Err(error) => {
+                let message = format!("Invalid subscription request: {}", error);
+                return Notification::Error { message };
+            }
+        };
+
+        match request {
+            SubscriptionRequest::Subscribe { batch_id } => {
+                if self.batch_ids.len() >= MAX_SUBSCRIBED_BATCHES {
+                    let message =
+                        format!("At most {} batches can be followed", MAX_SUBSCRIBED_BATCHES);
+                    return Notification::Error { message };
+                }
+                self.batch_ids.insert(batch_id);
+            }
+            SubscriptionRequest::Unsubscribe { batch_id } => {
+                self.batch_ids.remove(&batch_id);
+            }
+            SubscriptionRequest::SubscribeQuery { name } => {
+                let results = self
+                    .saved_queries
+                    .as_ref()
+                    .and_then(|saved_queries| saved_queries.results(&name));
+                return match results {
+                    Some(results) => {
+                        let batch_ids = results.batch_ids.iter().cloned().collect();
+                        self.queries.insert(name.clone(), batch_ids);
+                        Notification::QuerySubscribed {
+                            name,
+                            until_block: results.until_block,
+                            batch_ids: results.batch_ids,
+                        }
+                    }
+                    None => Notification::Error {
+                        message: format!("No saved query named {}", name),
+                    },
+                };
+            }
+            SubscriptionRequest::UnsubscribeQuery { name } => {
+                self.queries.remove(&name);
+            }
+        }
+
+        Notification::Subscribed {
+            batch_ids: self.batch_ids.iter().cloned().collect(),
+        }
+    }
+
+    // What the client must be told about an event: every new block, the transactions of its batches
+    // and the changes of the results of its queries
+    pub fn notifications(&mut self, event: &ChainEvent) -> Vec<Notification> {
+        match event {
+            ChainEvent::BlockApplied(block) => {
+                let header = block.header.clone();
+                let mut notifications = vec![Notification::BlockAdded { header }];
+                notifications.extend(self.mined(block));
+                notifications.extend(self.query_changes());
+                notifications
+            }
+            ChainEvent::ReorgOccurred(reorg) => {
+                let mut notifications = vec![Notification::ChainReorganized {
+                    ancestor: reorg.ancestor,
+                    rolled_back: reorg.depth(),
+                    headers: reorg
+                        .applied
+                        .iter()
+                        .map(|block| block.header.clone())
+                        .collect(),
+                }];
+                for block in reorg.applied.iter() {
+                    notifications.extend(self.mined(block));
+                }
+                notifications.extend(self.query_changes());
+                notifications
+            }
+            ChainEvent::TxAccepted(transaction) if self.follows(transaction) => {
+                vec![Notification::TransactionPending {
+                    hash: transaction_hash(transaction),
+                    transaction: transaction.clone(),
+                }]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // The transactions of the followed batches in a block
+    fn mined(&self, block: &Block) -> Vec<Notification> {
+        block
+            .body
+            .transactions
+            .iter()
+            .enumerate()
+            .filter(|(_, transaction)| self.follows(transaction))
+            .map(|(position, transaction)| Notification::TransactionMined {
+                hash: transaction_hash(transaction),
+                transaction: transaction.clone(),
+                block: BlockRef {
+                    index: block.header.index,
+                    hash: block.header.hash,
+                    timestamp: block.header.timestamp,
+                    position,
+                },
+            })
+            .collect()
+    }
+
+    fn follows(&self, transaction: &Transaction) -> bool {
+        self.batch_ids.contains(&transaction.batch_id)
+    }
+
+    // The batches that entered or left the results of the followed queries since they were last sent
+    // A query removed by the operator is no longer followed
+    fn query_changes(&mut self) -> Vec<Notification> {
+        let saved_queries = match &self.saved_queries {
+            Some(saved_queries) => saved_queries,
+            None => return Vec::new(),
+        };
+
+        let mut notifications = Vec::new();
+        let mut removed_queries = Vec::new();
+        for (name, sent) in self.queries.iter_mut() {
+            let results = match saved_queries.results(name) {
+                Some(results) => results,
+                None => {
+                    removed_queries.push(name.clone());
+                    notifications.push(Notification::Error {
+                        message: format!("The saved query {} was removed", name),
+                    });
+                    continue;
+                }
+            };
+
+            let batch_ids: BTreeSet<String> = results.batch_ids.into_iter().collect();
+            let added: Vec<String> = batch_ids.difference(sent).cloned().collect();
+            let removed: Vec<String> = sent.difference(&batch_ids).cloned().collect();
+            if !added.is_empty() || !removed.is_empty() {
+                notifications.push(Notification::QueryChanged {
+                    name: name.clone(),
+                    until_block: results.until_block,
+                    added,
+                    removed,
+                });
+                *sent = batch_ids;
+            }
+        }
+        for name in removed_queries.iter() {
+            self.queries.remove(name);
+        }
+
+        notifications
+    }
+}
+
+// Pushes the events of the chain to a client until it disconnects, while applying its subscription requests
+pub async fn push_notifications(
+    mut session: Session,
+    mut messages: MessageStream,
+    mut events: EventSubscription,
+    mut subscription: Subscription,
+) {
+    loop {
+        let notifications = tokio::select! {
+            event = events.recv() => match event {
+                Some(event) => subscription.notifications(&event),
+                None => break,
+            },
+            message = messages.recv() => match message {
+                Some(Ok(Message::Text(text))) => vec![subscription.handle(&text)],
+                Some(Ok(Message::Ping(bytes))) => match session.pong(&bytes).await {
+                    Ok(_) => continue,
+                    Err(_) => return,
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+
+        for notification in notifications.iter() {
+            // every notification can be serialized
+            let text = serde_json::to_string(notification).unwrap();
+            if session.text(text).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    let _ = session.close(None).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::saved_queries::{QueryFilter, SavedQuery};
+    use crate::model::{
+        fixtures::{alice, sign},
+        BlockHash, Blockchain, ReorgEvent,
+    };
+
+    #[test]
+    fn should_notify_the_followed_batches() {
+        let mut subscription = Subscription::new(&["WHEAT-1".to_string()]);
+        let wheat = create_transaction("WHEAT-1");
+        let corn = create_transaction("CORN-1");
+
+        let pending = subscription.notifications(&ChainEvent::TxAccepted(wheat.clone()));
+        assert!(matches!(
+            &pending[..],
+            [Notification::TransactionPending { transaction, .. }] if transaction.batch_id == "WHEAT-1"
+        ));
+        assert!(subscription
+            .notifications(&ChainEvent::TxAccepted(corn.clone()))
+            .is_empty());
+
+        // every block is notified, but only the transactions of the followed batches
+        let block = Block::new(1, 0, BlockHash::zero(), vec![corn, wheat]);
+        let notifications = subscription.notifications(&ChainEvent::BlockApplied(block));
+        assert_eq!(notifications.len(), 2);
+        assert!(matches!(
+            &notifications[0],
+            Notification::BlockAdded { header } if header.index == 1
+        ));
+        assert!(matches!(
+            &notifications[1],
+            Notification::TransactionMined { block, .. } if block.position == 1
+        ));
+    }
+
+    #[test]
+    fn should_notify_the_reorganizations() {
+        let mut subscription = Subscription::new(&["WHEAT-1".to_string()]);
+        let wheat = create_transaction("WHEAT-1");
+        let rolled_back = Block::new(1, 0, BlockHash::zero(), vec![wheat.clone()]);
+        let first = Block::new(1, 1, BlockHash::zero(), Vec::new());
+        let second = Block::new(2, 1, first.header.hash, vec![wheat]);
+        let reorg = ReorgEvent {
+            ancestor: 0,
+            rolled_back: vec![rolled_back],
+            applied: vec![first, second],
+        };
+
+        // the transactions of the followed batches are mined again in the new blocks
+        let notifications = subscription.notifications(&ChainEvent::ReorgOccurred(reorg));
+        assert_eq!(notifications.len(), 2);
+        assert!(matches!(
+            &notifications[0],
+            Notification::ChainReorganized { ancestor: 0, rolled_back: 1, headers } if headers.len() == 2
+        ));
+        assert!(matches!(
+            &notifications[1],
+            Notification::TransactionMined { block, .. } if block.index == 2
+        ));
+    }
+
+    #[test]
+    fn should_change_the_followed_batches() {
+        let mut subscription = Subscription::default();
+        let subscribe = r#"{"action": "subscribe", "batch_id": "WHEAT-1"}"#;
+        assert!(matches!(
+            subscription.handle(subscribe),
+            Notification::Subscribed { batch_ids } if batch_ids == vec!["WHEAT-1".to_string()]
+        ));
+        let wheat = ChainEvent::TxAccepted(create_transaction("WHEAT-1"));
+        assert_eq!(subscription.notifications(&wheat).len(), 1);
+
+        let unsubscribe = r#"{"action": "unsubscribe", "batch_id": "WHEAT-1"}"#;
+        assert!(matches!(
+            subscription.handle(unsubscribe),
+            Notification::Subscribed { batch_ids } if batch_ids.is_empty()
+        ));
+        assert!(subscription.notifications(&wheat).is_empty());
+
+        assert!(matches!(
+            subscription.handle(r#"{"action": "follow"}"#),
+            Notification::Error { .. }
+        ));
+    }
+
+    #[test]
+    fn should_notify_the_changes_of_the_followed_queries() {
+        let blockchain = Blockchain::new(0);
+        let saved_queries = SavedQueries::new(blockchain.clone(), String::new());
+        let certified = SavedQuery {
+            name: "certified".to_string(),
+            filter: QueryFilter {
+                event_type: Some("CERTIFICATION".to_string()),
+                ..Default::default()
+            },
+            except: None,
+        };
+        saved_queries.register(certified).unwrap();
+        let mut subscription = Subscription::default().with_saved_queries(saved_queries.clone());
+
+        assert!(matches!(
+            subscription.handle(r#"{"action": "subscribe_query", "name": "certified"}"#),
+            Notification::QuerySubscribed { batch_ids, .. } if batch_ids.is_empty()
+        ));
+        assert!(matches!(
+            subscription.handle(r#"{"action": "subscribe_query", "name": "organic"}"#),
+            Notification::Error { .. }
+        ));
+
+        let certification = sign(Transaction {
+            event_type: "CERTIFICATION".into(),
+            ..create_transaction("WHEAT-1")
+        });
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(1, 0, previous_hash, vec![certification]);
+        blockchain.add_block(block.clone()).unwrap();
+        let notifications = subscription.notifications(&ChainEvent::BlockApplied(block.clone()));
+        assert!(matches!(
+            &notifications[..],
+            [Notification::BlockAdded { .. }, Notification::QueryChanged { added, removed, until_block: 1, .. }]
+                if *added == vec!["WHEAT-1".to_string()] && removed.is_empty()
+        ));
+        // the results didn't change since
+        let notifications = subscription.notifications(&ChainEvent::BlockApplied(block.clone()));
+        assert_eq!(notifications.len(), 1);
+
+        // a removed query is no longer followed
+        saved_queries.remove("certified").unwrap();
+        let notifications = subscription.notifications(&ChainEvent::BlockApplied(block.clone()));
+        assert!(matches!(&notifications[1], Notification::Error { .. }));
+        let notifications = subscription.notifications(&ChainEvent::BlockApplied(block));
+        assert_eq!(notifications.len(), 1);
+    }
+
+    fn create_transaction(batch_id: &str) -> Transaction {
+        sign(Transaction {
+            sender: alice(),
+            recipient: alice(),
+            batch_id: batch_id.to_string(),
+            event_type: "STORAGE".into(),
+            ..Default::default()
+        })
+    }
+}
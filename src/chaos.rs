@@ -0,0 +1,98 @@
+// Fault injection for the chaos tests of the node, to check that it recovers from the conditions of a farm site:
+// storage writes that fail, network messages to the peers that are delayed or lost, and a clock that is off
+// The hooks are called where the faults would happen, and only inject them in the builds with the `chaos`
+// feature, configured by the CHAOS_* variables (see .env.example); in the other builds they never fail nor wait
+use anyhow::Result;
+use chrono::Utc;
+
+// The time of the node in milliseconds, for everything that depends on its clock agreeing with the other nodes
+// (the timestamps of the blocks, how far ahead a block can be, the deadlines of the escrows and the leases)
+pub fn now_millis() -> i64 {
+    Utc::now().timestamp_millis() + faults::clock_skew_ms()
+}
+
+// Called before writing to the storage, fails like a full or broken disk would
+pub fn storage_write() -> Result<()> {
+    faults::storage_write()
+}
+
+// Called before a message is exchanged with a peer, delays it and fails like a lost message would
+pub fn network_message(address: &str) -> Result<()> {
+    faults::network_message(address)
+}
+
+#[cfg(feature = "chaos")]
+mod faults {
+    use std::{env, str::FromStr, sync::OnceLock};
+
+    use anyhow::{bail, Result};
+
+    use crate::util::execution::sleep_millis;
+
+    struct Faults {
+        // probabilities between 0 and 1
+        storage_failure_rate: f64,
+        network_drop_rate: f64,
+        network_delay_ms: u64,
+        // added to every reading of the clock, negative when the node is behind
+        clock_skew_ms: i64,
+    }
+
+    pub fn clock_skew_ms() -> i64 {
+        get().clock_skew_ms
+    }
+
+    pub fn storage_write() -> Result<()> {
+        if happens(get().storage_failure_rate) {
+            bail!("Injected storage write failure");
+        }
+        Ok(())
+    }
+
+    pub fn network_message(address: &str) -> Result<()> {
+        sleep_millis(get().network_delay_ms);
+        if happens(get().network_drop_rate) {
+            bail!("Injected loss of a message with {}", address);
+        }
+        Ok(())
+    }
+
+    // Read once, the faults are the same for the whole life of the node
+    fn get() -> &'static Faults {
+        static FAULTS: OnceLock<Faults> = OnceLock::new();
+        FAULTS.get_or_init(|| Faults {
+            storage_failure_rate: read("CHAOS_STORAGE_FAILURE_RATE", 0.0),
+            network_drop_rate: read("CHAOS_NETWORK_DROP_RATE", 0.0),
+            network_delay_ms: read("CHAOS_NETWORK_DELAY_MS", 0),
+            clock_skew_ms: read("CHAOS_CLOCK_SKEW_MS", 0),
+        })
+    }
+
+    fn read<T: FromStr>(key: &str, default_value: T) -> T {
+        env::var(key)
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(default_value)
+    }
+
+    fn happens(rate: f64) -> bool {
+        rate > 0.0 && rand::random::<f64>() < rate
+    }
+}
+
+#[cfg(not(feature = "chaos"))]
+mod faults {
+    use anyhow::Result;
+
+    pub fn clock_skew_ms() -> i64 {
+        0
+    }
+
+    pub fn storage_write() -> Result<()> {
+        Ok(())
+    }
+
+    pub fn network_message(_address: &str) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+mod leader_lease;
+mod maintenance;
+
+use anyhow::{bail, Result};
+
+use crate::{
+    model::Blockchain,
+    storage::{self, SharedChainStore},
+    util::{
+        execution::{sleep_millis, Runnable},
+        Context,
+    },
+};
+pub use leader_lease::LeaderLease;
+pub use maintenance::{Maintenance, MaintenanceAnnouncement, MaintenanceNotice, MaintenanceReason};
+
+// Periodically acquires or renews the leadership of the node in its cluster
+// so only one node of a consortium member produces blocks, and another one takes over if it crashes
+// The leader is the only node that syncs, mines and writes the storage shared by the cluster, the others wait
+// on standby. A node that takes over opens the storage and catches up with the blocks of the previous leader
+// before acting as the leader, and closes it when it loses the leadership
+pub struct Cluster {
+    lease: LeaderLease,
+    blockchain: Blockchain,
+    store: Option<SharedChainStore>,
+}
+
+impl Runnable for Cluster {
+    fn run(&self) -> Result<()> {
+        self.start()
+    }
+}
+
+impl Cluster {
+    pub fn new(context: &Context) -> Cluster {
+        Cluster {
+            lease: context.leader_lease.clone(),
+            blockchain: context.blockchain.clone(),
+            store: context.store.clone(),
+        }
+    }
+
+    pub fn start(&self) -> Result<()> {
+        if !self.lease.is_enabled() {
+            info!("No cluster lease configured, exiting cluster system");
+            return Ok(());
+        }
+
+        // renewing several times per lease tolerates a slow renewal without losing the leadership
+        let renew_ms = self.lease.duration_ms() / 3;
+        let mut was_leader = false;
+        loop {
+            let is_leader = match self.lease.try_acquire(|term| self.take_over(term)) {
+                Ok(is_leader) => is_leader,
+                Err(error) => {
+                    error!("Could not access the cluster lease: {}", error);
+                    false
+                }
+            };
+
+            if is_leader != was_leader {
+                match is_leader {
+                    true => info!(
+                        "Became the cluster leader for term {}",
+                        self.lease.term().unwrap_or_default()
+                    ),
+                    false => {
+                        warn!("Lost the cluster leadership");
+                        if let Some(store) = &self.store {
+                            store.close();
+                        }
+                    }
+                }
+                was_leader = is_leader;
+            }
+            sleep_millis(renew_ms);
+        }
+    }
+
+    // A node that could not take over closes the storage again, sled locks its folder for a single process
+    // and the next leader could never open it
+    fn take_over(&self, term: u64) -> Result<()> {
+        let store = match &self.store {
+            Some(store) => store,
+            None => return Ok(()),
+        };
+        let result = self.catch_up(store, term);
+        if result.is_err() {
+            store.close();
+        }
+
+        result
+    }
+
+    fn catch_up(&self, store: &SharedChainStore, term: u64) -> Result<()> {
+        store.open()?;
+        let restored = storage::restore_chain(store.as_ref(), &self.blockchain)?;
+        // catching up can take longer than the lease, the index and the statistics may be written again after it
+        if !self.lease.within_deadline() {
+            bail!("The lease of term {} expired while taking over", term);
+        }
+        storage::check_batch_index(store.as_ref(), &self.blockchain)?;
+        storage::load_block_stats(store.as_ref(), &self.blockchain)?;
+        info!(
+            "Restored {} blocks from the storage of the cluster for term {}",
+            restored, term
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::*;
+    use crate::{model::Block, storage::ClusterStore};
+
+    #[test]
+    fn should_release_the_storage_when_it_cannot_take_over() {
+        let dir = std::env::temp_dir().join(format!("agriblock-cluster-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let storage_path = dir.join("storage").to_string_lossy().to_string();
+        let lease_path = dir.join("leader.lease").to_string_lossy().to_string();
+        let node = |node_id: &str, blockchain: Blockchain, store: SharedChainStore| Cluster {
+            lease: LeaderLease::new(&lease_path, node_id, 100),
+            blockchain,
+            store: Some(store),
+        };
+
+        // the shared storage has a block that the chain of the first node refuses (a too low difficulty)
+        let network = Blockchain::new(0);
+        let genesis_hash = network.get_last_block().header.hash;
+        network
+            .add_block(Block::new(1, 0, genesis_hash, Vec::new()))
+            .unwrap();
+        let store: SharedChainStore = Arc::new(ClusterStore::new(&storage_path, None));
+        store.open().unwrap();
+        for index in 0..2 {
+            let (block, stats) = network.get_block_with_stats(index).unwrap();
+            store.append_block(&block, &stats).unwrap();
+        }
+        let first = node("node-1", Blockchain::new(20), store);
+        assert!(first
+            .lease
+            .try_acquire(|term| first.take_over(term))
+            .is_err());
+        assert!(!first.lease.is_leader());
+
+        // so the next node opens it once the lease of the first one expired
+        let store = Arc::new(ClusterStore::new(&storage_path, None));
+        let second = node("node-2", Blockchain::new(0), store);
+        assert!(!second
+            .lease
+            .try_acquire(|term| second.take_over(term))
+            .unwrap());
+        std::thread::sleep(Duration::from_millis(150));
+        // sled releases the lock of its folder from a background thread, shortly after being closed
+        let taken_over = (0..50).any(|_| {
+            let result = second.lease.try_acquire(|term| second.take_over(term));
+            if !matches!(result, Ok(true)) {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            matches!(result, Ok(true))
+        });
+        assert!(taken_over);
+        assert!(second.lease.term().unwrap() >= 2);
+
+        second.store.as_ref().unwrap().close();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
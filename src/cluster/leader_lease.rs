@@ -0,0 +1,462 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+// A node without a lease file is the leader of a single term
+const STANDALONE_TERM: u64 = 1;
+
+// Content of the file of a term
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct LeaseRecord {
+    holder: String,
+    term: u64,
+    // incremented by the holder on each renewal, so the other nodes see that it's alive
+    renewals: u64,
+}
+
+// The latest term that a node saw, and since when it hasn't changed by its own monotonic clock
+#[derive(Debug)]
+struct Observation {
+    term: u64,
+    content: String,
+    since: Instant,
+}
+
+#[derive(Debug, Default)]
+struct LeaseState {
+    renewals: u64,
+    observation: Option<Observation>,
+    // the last term claimed by this process, 0 if none
+    claimed: u64,
+}
+
+// Leadership of a node among the nodes of a cluster, decided by the lease files in a storage shared by all of them
+// Each leadership is a term, numbered from 1, whose file ("<lease file>.<term>") is created by the node that
+// takes it. The creation fails if the file exists, so a single node wins each term even when several try at once,
+// and the term is a fencing token: a node only acts as the leader of the latest term
+// The leader renews its term periodically, when the others see no renewal for the duration of the lease
+// (e.g. its VM crashed) one of them takes the next term. The durations are measured by each node with its own
+// monotonic clock, so the clocks of the nodes don't need to be synchronized
+// Without a lease file the node works standalone and it's always the leader
+// Shared between the cluster job, that renews the lease, and the processes that only run on the leader
+#[derive(Debug, Clone)]
+pub struct LeaderLease {
+    path: Option<PathBuf>,
+    node_id: String,
+    duration_ms: u64,
+    // term held by the node, 0 when it's not the leader
+    term: Arc<AtomicU64>,
+    // until when the term is surely not taken by another node, a lease after the last renewal started
+    // (apart from the state, whose lock is held while the lease files are written)
+    deadline: Arc<Mutex<Option<Instant>>>,
+    state: Arc<Mutex<LeaseState>>,
+}
+
+impl LeaderLease {
+    pub fn new(path: &str, node_id: &str, duration_ms: u64) -> LeaderLease {
+        let path = (!path.is_empty()).then(|| PathBuf::from(path));
+        let term = match path {
+            Some(_) => 0,
+            None => STANDALONE_TERM,
+        };
+
+        LeaderLease {
+            term: Arc::new(AtomicU64::new(term)),
+            deadline: Arc::default(),
+            state: Arc::default(),
+            path,
+            node_id: node_id.to_string(),
+            duration_ms,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.path.is_some()
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.term().is_some()
+    }
+
+    // The term that the node leads, if it's the leader
+    pub fn term(&self) -> Option<u64> {
+        match self.term.load(Ordering::SeqCst) {
+            0 => None,
+            term => Some(term),
+        }
+    }
+
+    pub fn duration_ms(&self) -> u64 {
+        self.duration_ms
+    }
+
+    // Takes the next term if the latest one is not renewed anymore, or renews it if the node holds it
+    // Returns if the node is the leader, a node that can't reach the lease files stops being the leader
+    // A node that takes a new term does what it must before acting as the leader (e.g. catching up with the
+    // storage written by the previous leader), it's not the leader until it's done and tries again if it failed
+    pub fn try_acquire(&self, take_over: impl FnOnce(u64) -> Result<()>) -> Result<bool> {
+        // the other nodes see the renewal once written, so the lease is counted from before writing it
+        let started = Instant::now();
+        let result = self.acquire().and_then(|term| {
+            *self.deadline.lock().unwrap() =
+                (term != 0).then(|| started + Duration::from_millis(self.duration_ms));
+            let is_new = term != 0 && term != self.term.load(Ordering::SeqCst);
+            match is_new {
+                true => take_over(term).map(|_| term),
+                false => Ok(term),
+            }
+        });
+        self.term
+            .store(*result.as_ref().unwrap_or(&0), Ordering::SeqCst);
+
+        result.map(|term| term != 0)
+    }
+
+    // Whether the lease of the node was renewed less than a lease ago, by its monotonic clock
+    // Always true without a lease file
+    pub fn within_deadline(&self) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+
+        self.deadline
+            .lock()
+            .unwrap()
+            .is_some_and(|deadline| Instant::now() < deadline)
+    }
+
+    // Whether the node still leads the cluster, checked before each write to the storage shared by its nodes
+    // Another node takes the next term when the lease is not renewed for its duration, so a leader that could not
+    // renew it in time (e.g. a paused VM or a stalled shared disk) steps down at the deadline instead of writing
+    // along with the next leader. It takes a new term on its next renewal if no other node did
+    pub fn check_deadline(&self) -> bool {
+        let term = match self.term() {
+            Some(term) => term,
+            None => return false,
+        };
+        if self.within_deadline() {
+            return true;
+        }
+
+        warn!(
+            "The lease of term {} expired before being renewed, stepping down",
+            term
+        );
+        let _ = self
+            .term
+            .compare_exchange(term, 0, Ordering::SeqCst, Ordering::SeqCst);
+        false
+    }
+
+    // Returns the term held by the node after the attempt, 0 if it's not the leader
+    fn acquire(&self) -> Result<u64> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(STANDALONE_TERM),
+        };
+        let mut state = self.state.lock().unwrap();
+
+        let (term, content) = match self.latest_term(path)? {
+            Some(latest) => latest,
+            // nobody ever led the cluster
+            None => return self.claim(path, 1, &mut state),
+        };
+        if term == self.term.load(Ordering::SeqCst) {
+            state.renewals += 1;
+            self.renew(path, term, state.renewals)?;
+            return Ok(term);
+        }
+
+        // the holder renews the term more than once per lease, so it's expired when it didn't change for a lease
+        let now = Instant::now();
+        let since = match &state.observation {
+            Some(observation) if observation.term == term && observation.content == content => {
+                observation.since
+            }
+            _ => now,
+        };
+        let expired = now.duration_since(since) >= Duration::from_millis(self.duration_ms);
+        // a term claimed by this process that it doesn't hold anymore (it could not take over, or stepped down)
+        // is taken again right away, nothing else writes for it. A node restarted after leading the cluster
+        // waits out the lease instead, its previous process may still be writing (e.g. paused and not dead)
+        let own_term = term == state.claimed;
+        state.observation = Some(Observation {
+            term,
+            content,
+            since,
+        });
+
+        match expired || own_term {
+            true => self.claim(path, term + 1, &mut state),
+            false => Ok(0),
+        }
+    }
+
+    fn claim(&self, path: &Path, term: u64, state: &mut LeaseState) -> Result<u64> {
+        let record = LeaseRecord {
+            holder: self.node_id.clone(),
+            term,
+            renewals: 0,
+        };
+        // the creation is exclusive, so the nodes that try to take the same term at once don't both win
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(term_path(path, term));
+        match file {
+            Ok(mut file) => file.write_all(serde_json::to_string(&record)?.as_bytes())?,
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => return Ok(0),
+            Err(error) => return Err(error.into()),
+        }
+        state.renewals = 0;
+        state.claimed = term;
+
+        // the files of the previous terms are not needed anymore
+        for (previous, previous_path) in list_terms(path)? {
+            if previous < term {
+                let _ = fs::remove_file(previous_path);
+            }
+        }
+
+        Ok(term)
+    }
+
+    fn renew(&self, path: &Path, term: u64, renewals: u64) -> Result<()> {
+        let record = LeaseRecord {
+            holder: self.node_id.clone(),
+            term,
+            renewals,
+        };
+        // only the holder writes its term, and the rename is atomic so the other nodes never read half-written records
+        // (the name of the temporary file is not the one of a term)
+        let temp_path =
+            term_path(path, term).with_extension(format!("{}.tmp", rand::random::<u64>()));
+        fs::write(&temp_path, serde_json::to_string(&record)?)?;
+        fs::rename(&temp_path, term_path(path, term))?;
+
+        Ok(())
+    }
+
+    // The latest term and the content of its file
+    fn latest_term(&self, path: &Path) -> Result<Option<(u64, String)>> {
+        let latest = list_terms(path)?.into_iter().max_by_key(|(term, _)| *term);
+        let (term, term_path) = match latest {
+            Some(latest) => latest,
+            None => return Ok(None),
+        };
+
+        match fs::read_to_string(term_path) {
+            Ok(content) => Ok(Some((term, content))),
+            // removed by the leader of a newer term in the meantime
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(Some((term, String::new()))),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+fn term_path(path: &Path, term: u64) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(format!(".{}", term));
+    path.with_file_name(file_name)
+}
+
+// The terms whose files are next to the lease file
+fn list_terms(path: &Path) -> Result<Vec<(u64, PathBuf)>> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let prefix = format!(
+        "{}.",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    );
+
+    let mut terms = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let term = file_name
+            .to_str()
+            .and_then(|name| name.strip_prefix(&prefix))
+            .and_then(|term| term.parse::<u64>().ok());
+        if let Some(term) = term {
+            terms.push((term, entry.path()));
+        }
+    }
+
+    Ok(terms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_always_lead_without_lease_file() {
+        let lease = LeaderLease::new("", "node-1", 1000);
+
+        assert!(!lease.is_enabled());
+        assert!(lease.is_leader());
+        assert!(lease.try_acquire(no_take_over).unwrap());
+        assert_eq!(lease.term(), Some(STANDALONE_TERM));
+    }
+
+    #[test]
+    fn should_fail_over_when_the_lease_is_not_renewed() {
+        let path = create_lease_dir("rust_blockchain_lease_test");
+        let path = path.to_str().unwrap();
+
+        let first_node = LeaderLease::new(path, "node-1", 200);
+        let second_node = LeaderLease::new(path, "node-2", 200);
+        assert!(!first_node.is_leader());
+
+        assert!(first_node.try_acquire(no_take_over).unwrap());
+        assert_eq!(first_node.term(), Some(1));
+        assert!(!second_node.try_acquire(no_take_over).unwrap());
+        // the leader keeps the lease while it renews it
+        std::thread::sleep(std::time::Duration::from_millis(150));
+        assert!(first_node.try_acquire(no_take_over).unwrap());
+        std::thread::sleep(std::time::Duration::from_millis(150));
+        assert!(!second_node.try_acquire(no_take_over).unwrap());
+        assert_eq!(second_node.term(), None);
+
+        std::thread::sleep(std::time::Duration::from_millis(250));
+        assert!(second_node.try_acquire(no_take_over).unwrap());
+        assert_eq!(second_node.term(), Some(2));
+        // the previous leader sees the newer term
+        assert!(!first_node.try_acquire(no_take_over).unwrap());
+        assert!(!first_node.is_leader());
+        assert!(second_node.try_acquire(no_take_over).unwrap());
+
+        fs::remove_dir_all(Path::new(path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn should_give_each_term_to_a_single_node() {
+        let path = create_lease_dir("rust_blockchain_lease_race_test");
+        let path = path.to_str().unwrap();
+        let leader = LeaderLease::new(path, "node-0", 100);
+        assert!(leader.try_acquire(no_take_over).unwrap());
+
+        // all the followers see the term of the leader expire, and try to take the next one at once
+        let followers: Vec<_> = (1..=8)
+            .map(|node| LeaderLease::new(path, &format!("node-{}", node), 100))
+            .collect();
+        for follower in followers.iter() {
+            assert!(!follower.try_acquire(no_take_over).unwrap());
+        }
+        std::thread::sleep(std::time::Duration::from_millis(150));
+        let winners = std::thread::scope(|scope| {
+            let attempts: Vec<_> = followers
+                .iter()
+                .map(|follower| scope.spawn(|| follower.try_acquire(no_take_over).unwrap()))
+                .collect();
+            attempts
+                .into_iter()
+                .map(|attempt| attempt.join().unwrap())
+                .filter(|won| *won)
+                .count()
+        });
+
+        assert_eq!(winners, 1);
+        let terms: Vec<_> = followers.iter().filter_map(|node| node.term()).collect();
+        assert_eq!(terms, vec![2]);
+
+        fs::remove_dir_all(Path::new(path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn should_wait_out_the_lease_after_a_restart() {
+        let path = create_lease_dir("rust_blockchain_lease_restart_test");
+        let path = path.to_str().unwrap();
+        assert!(LeaderLease::new(path, "node-1", 100)
+            .try_acquire(no_take_over)
+            .unwrap());
+
+        // the previous process may still be writing
+        let restarted = LeaderLease::new(path, "node-1", 100);
+        assert!(!restarted.try_acquire(no_take_over).unwrap());
+        std::thread::sleep(std::time::Duration::from_millis(150));
+        assert!(restarted.try_acquire(no_take_over).unwrap());
+        assert_eq!(restarted.term(), Some(2));
+        assert!(!LeaderLease::new(path, "node-2", 100)
+            .try_acquire(no_take_over)
+            .unwrap());
+
+        fs::remove_dir_all(Path::new(path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn should_only_lead_once_it_took_over() {
+        let path = create_lease_dir("rust_blockchain_lease_take_over_test");
+        let path = path.to_str().unwrap();
+        let lease = LeaderLease::new(path, "node-1", 60_000);
+
+        assert!(lease
+            .try_acquire(|_| Err(anyhow::anyhow!("storage unavailable")))
+            .is_err());
+        assert!(!lease.is_leader());
+        // it takes the next term on the next attempt, and doesn't take over again while it holds it
+        let mut taken_over = Vec::new();
+        for _ in 0..2 {
+            let take_over = |term| {
+                taken_over.push(term);
+                Ok(())
+            };
+            assert!(lease.try_acquire(take_over).unwrap());
+        }
+        assert_eq!(taken_over, vec![2]);
+        assert_eq!(lease.term(), Some(2));
+
+        fs::remove_dir_all(Path::new(path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn should_step_down_when_the_lease_was_not_renewed_in_time() {
+        let path = create_lease_dir("rust_blockchain_lease_deadline_test");
+        let path = path.to_str().unwrap();
+        let lease = LeaderLease::new(path, "node-1", 100);
+        assert!(!lease.check_deadline());
+
+        assert!(lease.try_acquire(no_take_over).unwrap());
+        assert!(lease.check_deadline());
+        // the renewals stalled for longer than the lease
+        std::thread::sleep(std::time::Duration::from_millis(150));
+        assert!(!lease.within_deadline());
+        assert!(!lease.check_deadline());
+        assert!(!lease.is_leader());
+
+        // nobody took the lead in the meantime, so it takes the next term
+        assert!(lease.try_acquire(no_take_over).unwrap());
+        assert_eq!(lease.term(), Some(2));
+        assert!(lease.check_deadline());
+
+        // the deadline doesn't apply without a lease file
+        let standalone = LeaderLease::new("", "node-1", 0);
+        assert!(standalone.within_deadline());
+        assert!(standalone.check_deadline());
+
+        fs::remove_dir_all(Path::new(path).parent().unwrap()).unwrap();
+    }
+
+    fn no_take_over(_term: u64) -> Result<()> {
+        Ok(())
+    }
+
+    fn create_lease_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("leader.lease")
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// Why a node was paused, so the operators of the other nodes know what to expect
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum MaintenanceReason {
+    // a new version of the node, usually rolled out by all the members at once
+    Upgrade,
+    // e.g. moving the storage to another disk
+    Migration,
+    Incident,
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct MaintenanceNotice {
+    pub reason: MaintenanceReason,
+    pub message: Option<String>,
+    // timestamp in milliseconds
+    pub since: i64,
+}
+
+// Sent by a node to its peers when it enters or leaves maintenance, without notice once it's over
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct MaintenanceAnnouncement {
+    // public address of the node, as known by its peers
+    pub node: String,
+    pub notice: Option<MaintenanceNotice>,
+}
+
+#[derive(Debug, Default)]
+struct MaintenanceState {
+    notice: Option<MaintenanceNotice>,
+    peers: BTreeMap<String, MaintenanceNotice>,
+    // the longest chain announced by the peers, synced as headers only during maintenance
+    peer_height: Option<u64>,
+}
+
+// Maintenance mode of the node, started by an operator before a planned upgrade of the consortium
+// A node in maintenance stops producing blocks and accepting transactions or blocks,
+// but keeps serving the queries and following the headers of its peers
+// Shared between the api, that starts and ends it, and the processes that pause during it
+#[derive(Debug, Clone, Default)]
+pub struct Maintenance {
+    state: Arc<RwLock<MaintenanceState>>,
+}
+
+impl Maintenance {
+    // Starts the maintenance, or updates its reason if it was already started
+    pub fn start(&self, reason: MaintenanceReason, message: Option<String>) -> MaintenanceNotice {
+        let mut state = self.state.write().unwrap();
+        let since = match &state.notice {
+            Some(notice) => notice.since,
+            None => Utc::now().timestamp_millis(),
+        };
+        let notice = MaintenanceNotice {
+            reason,
+            message,
+            since,
+        };
+        state.notice = Some(notice.clone());
+
+        notice
+    }
+
+    // Returns the notice of the maintenance that was ended, if any
+    pub fn end(&self) -> Option<MaintenanceNotice> {
+        self.state.write().unwrap().notice.take()
+    }
+
+    pub fn current(&self) -> Option<MaintenanceNotice> {
+        self.state.read().unwrap().notice.clone()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.state.read().unwrap().notice.is_some()
+    }
+
+    // Remembers which peers are in maintenance, it doesn't pause this node
+    pub fn record_announcement(&self, announcement: MaintenanceAnnouncement) {
+        let mut state = self.state.write().unwrap();
+        match announcement.notice {
+            Some(notice) => state.peers.insert(announcement.node, notice),
+            None => state.peers.remove(&announcement.node),
+        };
+    }
+
+    // The peers in maintenance, by address
+    pub fn peers(&self) -> BTreeMap<String, MaintenanceNotice> {
+        self.state.read().unwrap().peers.clone()
+    }
+
+    pub fn record_peer_height(&self, height: u64) {
+        let mut state = self.state.write().unwrap();
+        state.peer_height = Some(state.peer_height.unwrap_or(0).max(height));
+    }
+
+    pub fn peer_height(&self) -> Option<u64> {
+        self.state.read().unwrap().peer_height
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_keep_the_start_of_the_maintenance() {
+        let maintenance = Maintenance::default();
+        assert!(!maintenance.is_active());
+        assert_eq!(maintenance.end(), None);
+
+        let notice = maintenance.start(MaintenanceReason::Upgrade, None);
+        assert!(maintenance.is_active());
+
+        // a new reason for the same maintenance
+        let message = Some("Rolling out v2.1".to_string());
+        let updated = maintenance.start(MaintenanceReason::Incident, message.clone());
+        assert_eq!(updated.since, notice.since);
+        assert_eq!(updated.message, message);
+        assert_eq!(maintenance.current(), Some(updated.clone()));
+
+        assert_eq!(maintenance.end(), Some(updated));
+        assert!(!maintenance.is_active());
+    }
+
+    #[test]
+    fn should_track_the_peers_in_maintenance() {
+        let maintenance = Maintenance::default();
+        let notice = MaintenanceNotice {
+            reason: MaintenanceReason::Migration,
+            message: None,
+            since: 1_700_000_000_000,
+        };
+        let announce = |notice: Option<MaintenanceNotice>| MaintenanceAnnouncement {
+            node: "http://coop-a:8000".to_string(),
+            notice,
+        };
+
+        maintenance.record_announcement(announce(Some(notice.clone())));
+        assert_eq!(
+            maintenance.peers(),
+            BTreeMap::from([("http://coop-a:8000".to_string(), notice)])
+        );
+        // the peers don't pause this node
+        assert!(!maintenance.is_active());
+
+        maintenance.record_announcement(announce(None));
+        assert!(maintenance.peers().is_empty());
+
+        maintenance.record_peer_height(12);
+        maintenance.record_peer_height(7);
+        assert_eq!(maintenance.peer_height(), Some(12));
+    }
+}
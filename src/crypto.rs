@@ -0,0 +1,135 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use thiserror::Error;
+
+#[derive(Error, PartialEq, Debug)]
+pub enum CryptoError {
+    #[error("Invalid signature")]
+    InvalidSignature,
+}
+
+// Purposes for which a key can sign a message
+// Each one prefixes the message with its own context before signing,
+// so a signature produced for one purpose can never be replayed as another one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SigningDomain {
+    Transaction,
+    Block,
+    Checkpoint,
+    ApiResponse,
+    QualityAttestation,
+    BlockAttestation,
+}
+
+impl SigningDomain {
+    // Contexts must never be a prefix of each other, the trailing separator guarantees it
+    fn context(&self) -> &'static [u8] {
+        match self {
+            SigningDomain::Transaction => b"agriblock/transaction/v1\0",
+            SigningDomain::Block => b"agriblock/block/v1\0",
+            SigningDomain::Checkpoint => b"agriblock/checkpoint/v1\0",
+            SigningDomain::ApiResponse => b"agriblock/api-response/v1\0",
+            SigningDomain::QualityAttestation => b"agriblock/quality-attestation/v1\0",
+            SigningDomain::BlockAttestation => b"agriblock/block-attestation/v1\0",
+        }
+    }
+}
+
+// Signs a message for a specific purpose
+pub fn sign(key: &SigningKey, domain: SigningDomain, message: &[u8]) -> Signature {
+    key.sign(&domain_message(domain, message))
+}
+
+// Checks that a message was signed by the key for the same purpose
+pub fn verify(
+    key: &VerifyingKey,
+    domain: SigningDomain,
+    message: &[u8],
+    signature: &Signature,
+) -> Result<(), CryptoError> {
+    key.verify(&domain_message(domain, message), signature)
+        .map_err(|_| CryptoError::InvalidSignature)
+}
+
+fn domain_message(domain: SigningDomain, message: &[u8]) -> Vec<u8> {
+    let mut domain_message = domain.context().to_vec();
+    domain_message.extend_from_slice(message);
+    domain_message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_DOMAINS: [SigningDomain; 6] = [
+        SigningDomain::Transaction,
+        SigningDomain::Block,
+        SigningDomain::Checkpoint,
+        SigningDomain::ApiResponse,
+        SigningDomain::QualityAttestation,
+        SigningDomain::BlockAttestation,
+    ];
+
+    #[test]
+    fn should_verify_signatures_of_the_same_domain() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let message = b"WHEAT-2024-001 harvested";
+
+        for domain in ALL_DOMAINS {
+            let signature = sign(&key, domain, message);
+            assert_eq!(
+                verify(&key.verifying_key(), domain, message, &signature),
+                Ok(())
+            );
+        }
+    }
+
+    #[test]
+    fn should_reject_signatures_replayed_in_other_domains() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let message = b"WHEAT-2024-001 harvested";
+
+        for signed_domain in ALL_DOMAINS {
+            let signature = sign(&key, signed_domain, message);
+            for other_domain in ALL_DOMAINS.iter().filter(|d| **d != signed_domain) {
+                let result = verify(&key.verifying_key(), *other_domain, message, &signature);
+                assert_eq!(result, Err(CryptoError::InvalidSignature));
+            }
+        }
+    }
+
+    #[test]
+    fn should_reject_signatures_without_domain() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let message = b"WHEAT-2024-001 harvested";
+
+        // a plain signature of the message is not valid in any domain
+        let plain_signature = key.sign(message);
+        for domain in ALL_DOMAINS {
+            let result = verify(&key.verifying_key(), domain, message, &plain_signature);
+            assert_eq!(result, Err(CryptoError::InvalidSignature));
+        }
+    }
+
+    #[test]
+    fn should_reject_tampered_messages() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let signature = sign(&key, SigningDomain::Transaction, b"500kg");
+
+        let result = verify(
+            &key.verifying_key(),
+            SigningDomain::Transaction,
+            b"900kg",
+            &signature,
+        );
+        assert_eq!(result, Err(CryptoError::InvalidSignature));
+    }
+
+    #[test]
+    fn should_use_unambiguous_contexts() {
+        for domain in ALL_DOMAINS {
+            for other_domain in ALL_DOMAINS.iter().filter(|d| **d != domain) {
+                assert!(!other_domain.context().starts_with(domain.context()));
+            }
+        }
+    }
+}
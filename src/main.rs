@@ -1,19 +1,59 @@
 #[macro_use]
 extern crate log;
 
+mod analytics;
 mod api;
+mod chaos;
+mod cluster;
+// only transactions are signed yet, blocks, checkpoints and API responses will use it
+#[allow(dead_code)]
+mod crypto;
 mod miner;
 mod model;
+#[cfg(feature = "gossip")]
+mod network;
+mod notary;
 mod peer;
+mod storage;
+mod tools;
 mod util;
+mod verify;
+mod wallet;
 
+use analytics::{Analytics, AnomalyScores};
 use api::Api;
+use cluster::{Cluster, LeaderLease, Maintenance};
 use miner::Miner;
-use model::{Blockchain, TransactionPool};
-use peer::Peer;
-use util::{execution, initialize_logger, termination, Config, Context};
+use model::{
+    Address, BlockAttestations, Blockchain, ChangeFeed, Difficulty, GenesisConfig, GenesisError,
+    IssuanceLimit, TransactionOrigins, TransactionPool,
+};
+#[cfg(feature = "gossip")]
+use network::Network;
+use notary::Notary;
+use peer::{Peer, PeerList};
+use std::{str::FromStr, sync::Arc};
+use storage::{
+    ChainVerifier, ClusterStore, Replica, SharedChainStore, SledStore, Storage, StorageCipher,
+};
+use util::{
+    execution::{self, Runnable},
+    initialize_logger, termination, Config, Context,
+};
+use wallet::Wallet;
 
 fn main() {
+    // the other commands run a tool instead of the node
+    let args: Vec<String> = std::env::args().collect();
+    let minimal = match tools::run(&args) {
+        Ok(Some(options)) => options.minimal,
+        Ok(None) => return,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+
     initialize_logger();
     info!("starting up");
 
@@ -21,20 +61,215 @@ fn main() {
     termination::set_ctrlc_handler();
 
     // initialize shared data values
-    let config = Config::read();
-    let difficulty = config.difficulty;
+    let config = match Config::read() {
+        Ok(config) => config,
+        Err(error) => {
+            error!("Invalid configuration: {}", error);
+            std::process::exit(1);
+        }
+    };
+    // the lease files record which node holds each term, for the operators to tell the nodes apart
+    if !config.cluster_lease_file.is_empty() && config.cluster_node_id.is_empty() {
+        error!("A unique CLUSTER_NODE_ID is required in a cluster (CLUSTER_LEASE_FILE)");
+        std::process::exit(1);
+    }
+    let peers = PeerList::new(
+        config.peer_allowlist.clone(),
+        config.public_address.clone(),
+        config.peers_file.clone(),
+    );
+    let leader_lease = LeaderLease::new(
+        &config.cluster_lease_file,
+        &config.cluster_node_id,
+        config.cluster_lease_ms,
+    );
+    let blockchain = match create_blockchain(&config) {
+        Ok(blockchain) => blockchain,
+        Err(error) => {
+            error!("{}", error);
+            std::process::exit(1);
+        }
+    };
+    // the pool publishes the accepted transactions in the same bus as the chain
+    let pool = TransactionPool::new(blockchain.event_bus())
+        .with_limits(config.max_transactions_per_block, config.pool_max_age_ms);
+    // the stored blocks are added before any process starts, as if they were just received
+    let store = open_store(&config, &blockchain);
+    let origins = TransactionOrigins::new(config.origins_file.clone());
+    if let Err(error) = origins.load() {
+        error!(
+            "Could not load the persisted transaction origins: {}",
+            error
+        );
+    }
+    let attestations = create_attestations(&config);
+    // the cursors given to the integrators must survive restarts
+    let changes = ChangeFeed::new(config.changes_file.clone());
+    if let Err(error) = changes.load() {
+        error!("Could not load the persisted change feed: {}", error);
+        std::process::exit(1);
+    }
+    // the verification of the chain resumes after the last block checked before the restart
+    let verification = ChainVerifier::new(store.clone());
+    if let Err(error) = verification.load() {
+        error!("Could not load the progress of the verification: {}", error);
+    }
+    let node_wallet = match open_node_wallet(&config) {
+        Ok(wallet) => Arc::new(wallet),
+        Err(error) => {
+            error!("Could not open the key of the node: {}", error);
+            std::process::exit(1);
+        }
+    };
+    info!(
+        "The transactions of this node are signed by {}",
+        node_wallet.address()
+    );
     let context = Context {
         config,
-        blockchain: Blockchain::new(difficulty),
-        pool: TransactionPool::new(),
+        blockchain,
+        pool,
+        origins,
+        attestations,
+        changes,
+        peers,
+        anomaly_scores: AnomalyScores::default(),
+        leader_lease,
+        maintenance: Maintenance::default(),
+        store,
+        verification,
+        node_wallet,
     };
 
     // initialize the processes
     let miner = Miner::new(&context);
     let api = Api::new(&context);
     let peer = Peer::new(&context);
+    #[cfg(feature = "gossip")]
+    let network = Network::new(&context);
+    let notary = Notary::new(&context);
+    let analytics = Analytics::new(&context);
+    let cluster = Cluster::new(&context);
+    let storage = Storage::new(&context);
+    let replica = Replica::new(&context);
 
-    // miner, api and peer system run in separate threads
+    // miner, api, peer system, gossip network, notary, analytics, cluster and storage run in separate threads
     // because mining is very cpu intensive
-    execution::run_in_parallel(vec![&miner, &api, &peer]);
+    let mut processes: Vec<&dyn Runnable> = vec![&api, &storage];
+    match (context.config.replica_of.is_empty(), minimal) {
+        (false, _) => {
+            info!("Read replica of {}, the miner, peer system, gossip network, notary and cluster are disabled", context.config.replica_of);
+            processes.extend([&replica as &dyn Runnable, &analytics]);
+        }
+        (true, true) => {
+            info!("Minimal mode, the gossip network, notary, analytics and cluster are disabled");
+            processes.extend([&miner as &dyn Runnable, &peer]);
+        }
+        (true, false) => {
+            processes.extend([&miner as &dyn Runnable, &peer]);
+            #[cfg(feature = "gossip")]
+            processes.push(&network);
+            processes.extend([&notary as &dyn Runnable, &analytics, &cluster]);
+        }
+    }
+    #[cfg(not(feature = "gossip"))]
+    if !context.config.p2p_listen_address.is_empty() {
+        warn!("Built without the gossip network, P2P_LISTEN_ADDRESS is ignored");
+    }
+    execution::run_in_parallel(processes);
+}
+
+// The network of the genesis file if any, where the genesis defines the difficulty, or the default network
+// The consensus rules of the configuration are applied, so the tools validate like the node does
+fn create_blockchain(config: &Config) -> Result<Blockchain, GenesisError> {
+    let issuance_limit =
+        IssuanceLimit::new(config.max_custody_events, config.custody_events_window_ms);
+    if config.genesis_file.is_empty() {
+        let difficulty = Difficulty::new(
+            config.difficulty,
+            config.difficulty_adjustment_blocks,
+            config.target_block_time_ms,
+        );
+        return Ok(Blockchain::with_difficulty(difficulty)
+            .with_issuance_limit(issuance_limit)
+            .with_quantity_strictness(config.quantity_strictness)
+            .with_orphan_limit(config.orphan_archive_limit));
+    }
+
+    let genesis = GenesisConfig::load(&config.genesis_file)?;
+    info!(
+        "Joining the network {} defined in {}",
+        genesis.chain_id, config.genesis_file
+    );
+    Ok(Blockchain::from_genesis(&genesis)
+        .with_issuance_limit(issuance_limit)
+        .with_quantity_strictness(config.quantity_strictness)
+        .with_orphan_limit(config.orphan_archive_limit))
+}
+
+// The key of NODE_KEY_FILE, created on the first start, or else a new key that only lasts until the node stops
+fn open_node_wallet(config: &Config) -> anyhow::Result<Wallet> {
+    if config.node_key_file.is_empty() {
+        warn!("No NODE_KEY_FILE, the transactions of this node are signed with a new key on every start");
+        return Ok(Wallet::generate());
+    }
+    Wallet::open_secret_file(std::path::Path::new(&config.node_key_file))
+}
+
+// The attestations of the configured regulators, a key that is not an address is left out
+fn create_attestations(config: &Config) -> BlockAttestations {
+    let regulators = config
+        .regulator_keys
+        .iter()
+        .filter_map(|key| match Address::from_str(key) {
+            Ok(address) => Some(address),
+            Err(_) => {
+                error!("Ignoring the invalid regulator key {}", key);
+                None
+            }
+        })
+        .collect();
+    let attestations = BlockAttestations::new(regulators, config.attestations_file.clone());
+    if let Err(error) = attestations.load() {
+        error!("Could not load the persisted block attestations: {}", error);
+    }
+    attestations
+}
+
+// A node that cannot restore its chain must not start, it would fork from its own past blocks
+fn open_store(config: &Config, blockchain: &Blockchain) -> Option<SharedChainStore> {
+    let path = &config.storage_path;
+    if path.is_empty() {
+        return None;
+    }
+
+    let cipher =
+        StorageCipher::from_config(&config.storage_encryption_key, &config.storage_key_command);
+    // in a cluster the storage is opened and restored by the node that takes the lead
+    if !config.cluster_lease_file.is_empty() {
+        return match cipher {
+            Ok(cipher) => Some(Arc::new(ClusterStore::new(path, cipher))),
+            Err(error) => {
+                error!("Could not open the storage at {}: {}", path, error);
+                std::process::exit(1);
+            }
+        };
+    }
+    let restored = cipher.and_then(|cipher| {
+        let store = SledStore::open(path, cipher)?;
+        let count = storage::restore_chain(&store, blockchain)?;
+        storage::check_batch_index(&store, blockchain)?;
+        storage::load_block_stats(&store, blockchain)?;
+        Ok((store, count))
+    });
+    match restored {
+        Ok((store, count)) => {
+            info!("Restored {} blocks from the storage at {}", count, path);
+            Some(Arc::new(store))
+        }
+        Err(error) => {
+            error!("Could not restore the chain from {}: {}", path, error);
+            std::process::exit(1);
+        }
+    }
 }
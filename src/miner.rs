@@ -1,6 +1,10 @@
+mod hash_meter;
+
 use crate::{
+    cluster::{LeaderLease, Maintenance},
     model::{
-        Address, Block, BlockHash, Blockchain, Transaction, TransactionPool, TransactionVec,
+        Address, Block, BlockHash, Blockchain, ChainEvent, EventSubscription, Transaction,
+        TransactionPool, TransactionVec,
     },
     util::{
         execution::{sleep_millis, Runnable},
@@ -8,8 +12,13 @@ use crate::{
     },
 };
 use anyhow::Result;
+use hash_meter::HashMeter;
+use std::{sync::Mutex, time::Instant};
 use thiserror::Error;
 
+// How often the miner checks if a client asked to mine right away while it waits for more transactions
+const MINING_REQUEST_CHECK_MS: u64 = 10;
+
 #[derive(Error, Debug)]
 pub enum MinerError {
     #[error("No valid block was mined at index `{0}`")]
@@ -20,10 +29,18 @@ pub struct Miner {
     miner_address: Address,
     max_blocks: u64,
     max_nonce: u64,
+    max_hash_rate: u64,
+    mining_duty_cycle: u64,
     tx_waiting_ms: u64,
+    block_interval_min_ms: u64,
+    block_interval_max_ms: u64,
+    block_interval_full_pool: u64,
     blockchain: Blockchain,
     pool: TransactionPool,
-    target: BlockHash,
+    // the blocks applied to the chain, by this node or by peers, to revalidate the pool with them
+    applied_blocks: Mutex<EventSubscription>,
+    leader_lease: LeaderLease,
+    maintenance: Maintenance,
 }
 
 impl Runnable for Miner {
@@ -34,16 +51,21 @@ impl Runnable for Miner {
 
 impl Miner {
     pub fn new(context: &Context) -> Miner {
-        let target = Self::create_target(context.config.difficulty);
-
         Miner {
             miner_address: context.config.miner_address.clone(),
             max_blocks: context.config.max_blocks,
             max_nonce: context.config.max_nonce,
+            max_hash_rate: context.config.max_hash_rate,
+            mining_duty_cycle: context.config.mining_duty_cycle,
             tx_waiting_ms: context.config.tx_waiting_ms,
+            block_interval_min_ms: context.config.block_interval_min_ms,
+            block_interval_max_ms: context.config.block_interval_max_ms,
+            block_interval_full_pool: context.config.block_interval_full_pool,
             blockchain: context.blockchain.clone(),
             pool: context.pool.clone(),
-            target,
+            applied_blocks: Mutex::new(context.blockchain.event_bus().subscribe()),
+            leader_lease: context.leader_lease.clone(),
+            maintenance: context.maintenance.clone(),
         }
     }
 
@@ -52,7 +74,7 @@ impl Miner {
     pub fn start(&self) -> Result<()> {
         info!(
             "start minining with difficulty {}",
-            self.blockchain.difficulty
+            self.blockchain.next_difficulty()
         );
 
         // In each loop it tries to find the next valid block and append it to the blockchain
@@ -63,26 +85,91 @@ impl Miner {
                 return Ok(());
             }
 
-            // Empty all transactions from the pool, they will be included in the new block
-            let transactions = self.pool.pop();
+            self.revalidate_pool();
+            self.pool.evict_stale();
+
+            // In a cluster only the leader produces blocks, the other nodes catch up from the storage when they take over
+            let term = match self.leader_lease.term() {
+                Some(term) => term,
+                None => {
+                    sleep_millis(self.tx_waiting_ms);
+                    continue;
+                }
+            };
+
+            // No blocks during a maintenance, the pending transactions wait for it to end
+            if self.maintenance.is_active() {
+                sleep_millis(self.tx_waiting_ms);
+                continue;
+            }
 
             // Do not try to mine a block if there are no transactions in the pool
-            if transactions.is_empty() {
+            let pending_transactions = self.pool.len();
+            if pending_transactions == 0 {
                 sleep_millis(self.tx_waiting_ms);
                 continue;
             }
 
+            // Give some time for more transactions to arrive, less the busier the pool is
+            self.wait_block_interval(self.block_interval(pending_transactions));
+
+            // Take the oldest transactions from the pool, they will be included in the new block
+            // Delegations may have expired since the transactions were submitted
+            let mut transactions = TransactionVec::new();
+            for transaction in self.pool.pop() {
+                let validation = self
+                    .blockchain
+                    .validate_transaction_after(&transaction, &transactions);
+                match validation {
+                    Ok(()) => transactions.push(transaction),
+                    Err(error) => warn!("Discarding transaction from the pool: {}", error),
+                }
+            }
+            if transactions.is_empty() {
+                self.pool.clear_in_flight();
+                continue;
+            }
+
             // try to find a valid next block of the blockchain
+            // the difficulty may have been adjusted by the last block
             let last_block = self.blockchain.get_last_block();
-            let mining_result = self.mine_block(&last_block, &transactions.clone());
+            let difficulty = self.blockchain.next_difficulty();
+            let mining_result = self.mine_block(&last_block, &transactions.clone(), difficulty);
             match mining_result {
                 Some(block) => {
-                    info!("valid block found for index {}", block.index);
-                    self.blockchain.add_block(block.clone())?;
+                    info!("valid block found for index {}", block.header.index);
+                    // the term is the fencing token of the leader, another node may have taken over while mining
+                    if self.leader_lease.term() != Some(term) {
+                        warn!(
+                            "Discarding the mined block {}: not the cluster leader of term {} anymore",
+                            block.header.index, term
+                        );
+                        for transaction in transactions {
+                            self.pool.add_transaction(transaction);
+                        }
+                        self.pool.clear_in_flight();
+                        continue;
+                    }
+                    // the chain may have changed while mining, e.g. with the blocks of a longer fork of the peers
+                    if let Err(error) = self.blockchain.add_block(block.clone()) {
+                        if self.blockchain.get_last_block().header.hash == last_block.header.hash {
+                            return Err(error);
+                        }
+                        warn!(
+                            "Discarding the mined block {}: {}",
+                            block.header.index, error
+                        );
+                        for transaction in transactions {
+                            self.pool.add_transaction(transaction);
+                        }
+                        self.pool.clear_in_flight();
+                        continue;
+                    }
+                    self.pool.clear_in_flight();
                     block_counter += 1;
                 }
                 None => {
-                    let index = last_block.index + 1;
+                    let index = last_block.header.index + 1;
                     error!("no valid block was foun for index {}", index);
                     return Err(MinerError::BlockNotMined(index).into());
                 }
@@ -90,12 +177,57 @@ impl Miner {
         }
     }
 
+    // Drops the pending transactions that the blocks applied since the last check made invalid,
+    // and puts back the ones of the blocks rolled back by a reorganization
+    fn revalidate_pool(&self) {
+        let events = self.applied_blocks.lock().unwrap().drain();
+        for event in events {
+            match event {
+                ChainEvent::BlockApplied(block) => {
+                    self.pool.revalidate(&self.blockchain, &block);
+                }
+                ChainEvent::ReorgOccurred(reorg) => {
+                    let returned = self.pool.return_transactions(&self.blockchain, &reorg);
+                    info!(
+                        "{} transactions of the rolled back blocks returned to the pool",
+                        returned
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+
     // Creates binary data mask with the amount of left padding zeroes indicated by the "difficulty" value
     // Used to easily compare if a newly created block has a hash that matches the difficulty
     fn create_target(difficulty: u32) -> BlockHash {
         BlockHash::MAX >> difficulty
     }
 
+    // Sleeps for the block interval, unless a client asks to mine the pending transactions right away
+    fn wait_block_interval(&self, interval_ms: u64) {
+        let started = Instant::now();
+        while !self.pool.is_mining_requested() {
+            let elapsed_ms = started.elapsed().as_millis() as u64;
+            if elapsed_ms >= interval_ms {
+                return;
+            }
+            sleep_millis(MINING_REQUEST_CHECK_MS.min(interval_ms - elapsed_ms));
+        }
+    }
+
+    // Calculates how long to wait before mining a block, depending on the amount of pending transactions
+    // An idle pool waits the max interval, and it linearly shortens until the pool is considered full
+    fn block_interval(&self, pending_transactions: usize) -> u64 {
+        let full_pool = self.block_interval_full_pool.max(1);
+        let depth = (pending_transactions as u64).min(full_pool);
+        let range = self
+            .block_interval_max_ms
+            .saturating_sub(self.block_interval_min_ms);
+
+        self.block_interval_max_ms - range * depth / full_pool
+    }
+
     // check if we have hit the limit of mined blocks (if the limit is set)
     fn must_stop_mining(&self, block_counter: u64) -> bool {
         self.max_blocks > 0 && block_counter >= self.max_blocks
@@ -104,18 +236,31 @@ impl Miner {
     // Tries to find the next valid block of the blockchain
     // It will create blocks with different "nonce" values until one has a hash that matches the difficulty
     // Returns either a valid block (that satisfies the difficulty) or "None" if no block was found
-    fn mine_block(&self, last_block: &Block, transactions: &TransactionVec) -> Option<Block> {
+    fn mine_block(
+        &self,
+        last_block: &Block,
+        transactions: &TransactionVec,
+        difficulty: u32,
+    ) -> Option<Block> {
+        let target = Self::create_target(difficulty);
+
         // Add the coinbase transaction as the first transaction in the block
-        let coinbase = self.create_coinbase_transaction();
+        let coinbase = Transaction::coinbase(self.miner_address.clone());
         let mut block_transactions = transactions.clone();
         block_transactions.insert(0, coinbase);
 
+        // The transactions don't change between attempts, only the nonce and thus the hash
+        let mut next_block = self.create_next_block(last_block, block_transactions, 0);
+        let mut meter = HashMeter::new(self.max_hash_rate, self.mining_duty_cycle);
+        let header = &mut next_block.header;
         for nonce in 0..self.max_nonce {
-            let next_block = self.create_next_block(last_block, block_transactions.clone(), nonce);
+            header.nonce = nonce;
+            header.hash = header.calculate_hash();
+            meter.record_hash();
 
             // A valid block must have a hash with enough starting zeroes
             // To check that, we simply compare against a binary data mask
-            if next_block.hash < self.target {
+            if header.hash < target {
                 return Some(next_block);
             }
         }
@@ -131,29 +276,19 @@ impl Miner {
         transactions: TransactionVec,
         nonce: u64,
     ) -> Block {
-        let index = (last_block.index + 1) as u64;
-        let previous_hash = last_block.hash;
+        let index = last_block.header.index + 1;
+        let previous_hash = last_block.header.hash;
 
         // hash of the new block is automatically calculated on creation
         Block::new(index, nonce, previous_hash, transactions)
     }
-
-    fn create_coinbase_transaction(&self) -> Transaction {
-        Transaction {
-            sender: Address::default(),
-            recipient: self.miner_address.clone(),
-            data: "Block Mined by NUST Node - Validation Complete".to_string(),
-            batch_id: "SYSTEM_LOG".to_string(),
-            event_type: "BLOCK_VALIDATION".to_string(),
-        }
-    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::model::{
-        test_util::{alice, bob},
+        fixtures::{alice, bob, sign},
         Transaction,
     };
 
@@ -168,8 +303,8 @@ mod tests {
         let next_block = miner.create_next_block(&block, Vec::new(), 0);
 
         // the next block must follow the previous one
-        assert_eq!(next_block.index, block.index + 1);
-        assert_eq!(next_block.previous_hash, block.hash);
+        assert_eq!(next_block.header.index, block.header.index + 1);
+        assert_eq!(next_block.header.previous_hash, block.header.hash);
     }
 
     #[test]
@@ -190,6 +325,33 @@ mod tests {
         assert_eq!(target.leading_zeros(), MAX_DIFFICULTY);
     }
 
+    #[test]
+    fn test_block_interval_depends_on_pool_depth() {
+        let mut miner = create_default_miner();
+        miner.block_interval_min_ms = 1_000;
+        miner.block_interval_max_ms = 11_000;
+        miner.block_interval_full_pool = 100;
+
+        // a nearly idle pool waits almost the max interval
+        assert_eq!(miner.block_interval(1), 10_900);
+
+        // the interval shortens as the pool gets deeper
+        assert_eq!(miner.block_interval(50), 6_000);
+
+        // and never goes below the min interval
+        assert_eq!(miner.block_interval(100), 1_000);
+        assert_eq!(miner.block_interval(1_000), 1_000);
+    }
+
+    #[test]
+    fn test_block_interval_disabled_by_default() {
+        let miner = create_default_miner();
+
+        // with no bounds configured blocks are mined as soon as there are transactions
+        assert_eq!(miner.block_interval(1), 0);
+        assert_eq!(miner.block_interval(1_000), 0);
+    }
+
     #[test]
     fn test_mine_block_found() {
         // let's use a small difficulty target for fast testing
@@ -201,7 +363,7 @@ mod tests {
         // check that the block is mined
         let miner = create_miner(difficulty, max_nonce);
         let last_block = create_empty_block();
-        let result = miner.mine_block(&last_block, &Vec::new());
+        let result = miner.mine_block(&last_block, &Vec::new(), difficulty);
         assert!(result.is_some());
 
         // check that the block is valid
@@ -221,7 +383,7 @@ mod tests {
         // check that the block is not mined
         let miner = create_miner(difficulty, max_nonce);
         let last_block = create_empty_block();
-        let result = miner.mine_block(&last_block, &Vec::new());
+        let result = miner.mine_block(&last_block, &Vec::new(), difficulty);
         assert!(result.is_none());
     }
 
@@ -249,10 +411,10 @@ mod tests {
         let mined_block = &blocks[1];
 
         // the mined block must be valid
-        assert_mined_block_is_valid(mined_block, genesis_block, blockchain.difficulty);
+        assert_mined_block_is_valid(mined_block, genesis_block, difficulty);
 
         // the mined block must include the transaction added previously plus the coinbase
-        let mined_transactions = &mined_block.transactions;
+        let mined_transactions = &mined_block.body.transactions;
         assert_eq!(mined_transactions.len(), 2);
 
         // the transaction pool must be empty
@@ -291,42 +453,50 @@ mod tests {
         let miner_address = miner_address();
         let max_blocks = 1;
         let tx_waiting_ms = 1;
-        let target = Miner::create_target(difficulty);
 
         let blockchain = Blockchain::new(difficulty);
-        let pool = TransactionPool::new();
+        let pool = TransactionPool::new(blockchain.event_bus());
+        let applied_blocks = Mutex::new(blockchain.event_bus().subscribe());
 
         Miner {
             miner_address,
             max_blocks,
             max_nonce,
+            max_hash_rate: 0,
+            mining_duty_cycle: 100,
             tx_waiting_ms,
+            block_interval_min_ms: 0,
+            block_interval_max_ms: 0,
+            block_interval_full_pool: 100,
             blockchain,
             pool,
-            target,
+            applied_blocks,
+            leader_lease: LeaderLease::new("", "", 0),
+            maintenance: Maintenance::default(),
         }
     }
 
     fn create_empty_block() -> Block {
-        return Block::new(0, 0, BlockHash::default(), Vec::new());
+        Block::new(0, 0, BlockHash::default(), Vec::new())
     }
 
     fn add_mock_transaction(pool: &TransactionPool) {
         // the transaction is valid because the genesis block gives rewards to the miner address
         // so that address can be a sender of funds to other addresses
-        let transaction = Transaction {
+        let transaction = sign(Transaction {
             sender: miner_address(),
             recipient: bob(),
-            data: "Mock transaction data".to_string(),
+            data: "Mock transaction data".into(),
             batch_id: "TEST_BATCH".to_string(),
-            event_type: "TEST_EVENT".to_string(),
-        };
+            event_type: "TEST_EVENT".into(),
+            ..Default::default()
+        });
         pool.add_transaction(transaction.clone());
     }
 
     fn assert_mined_block_is_valid(mined_block: &Block, previous_block: &Block, difficulty: u32) {
-        assert_eq!(mined_block.index, previous_block.index + 1);
-        assert_eq!(mined_block.previous_hash, previous_block.hash);
-        assert!(mined_block.hash.leading_zeros() >= difficulty as u32);
+        assert_eq!(mined_block.header.index, previous_block.header.index + 1);
+        assert_eq!(mined_block.header.previous_hash, previous_block.header.hash);
+        assert!(mined_block.header.hash.leading_zeros() >= difficulty);
     }
 }
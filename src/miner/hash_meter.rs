@@ -0,0 +1,118 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+// Hashes between two checks of the limits, short enough to keep the pauses small and frequent
+// (a few milliseconds of hashing on a Raspberry Pi) so the CPU never runs at full speed for long
+const METERING_CHUNK: u64 = 256;
+
+// Limits the hashing work of the miner, so low-power devices (e.g. a gateway in a field cabinet)
+// can take part in a low-difficulty chain without running their CPU at 100% and overheating
+// Mining pauses after every chunk of hashes for as long as the strictest of the limits requires
+pub struct HashMeter {
+    // max hashes per second (0 for unlimited)
+    max_hash_rate: u64,
+    // percentage of the time spent hashing, the rest is spent sleeping (100 for always)
+    duty_cycle: u64,
+    chunk_started: Instant,
+    chunk_hashes: u64,
+}
+
+impl HashMeter {
+    pub fn new(max_hash_rate: u64, duty_cycle: u64) -> HashMeter {
+        HashMeter {
+            max_hash_rate,
+            duty_cycle: duty_cycle.clamp(1, 100),
+            chunk_started: Instant::now(),
+            chunk_hashes: 0,
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.max_hash_rate == 0 && self.duty_cycle == 100
+    }
+
+    // Counts a hash, and pauses the thread if a chunk of hashes went faster than the limits allow
+    pub fn record_hash(&mut self) {
+        if self.is_unlimited() {
+            return;
+        }
+
+        self.chunk_hashes += 1;
+        if self.chunk_hashes < METERING_CHUNK {
+            return;
+        }
+
+        let pause = self.pause_after(self.chunk_hashes, self.chunk_started.elapsed());
+        if !pause.is_zero() {
+            thread::sleep(pause);
+        }
+        self.chunk_started = Instant::now();
+        self.chunk_hashes = 0;
+    }
+
+    // How long to rest after hashing for some time
+    fn pause_after(&self, hashes: u64, hashing: Duration) -> Duration {
+        let for_hash_rate = match self.max_hash_rate {
+            0 => Duration::ZERO,
+            rate => Duration::from_secs_f64(hashes as f64 / rate as f64).saturating_sub(hashing),
+        };
+        let idle_share = (100 - self.duty_cycle) as f64 / self.duty_cycle as f64;
+        let for_duty_cycle = hashing.mul_f64(idle_share);
+
+        for_hash_rate.max(for_duty_cycle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_pause_for_the_strictest_limit() {
+        // 1000 hashes at 10000 hashes per second take at least 100ms
+        let meter = HashMeter::new(10_000, 100);
+        let pause = meter.pause_after(1_000, Duration::from_millis(20));
+        assert_eq!(pause, Duration::from_millis(80));
+        assert_eq!(
+            meter.pause_after(1_000, Duration::from_millis(150)),
+            Duration::ZERO
+        );
+
+        // hashing only a quarter of the time rests three times as long as it hashed
+        let meter = HashMeter::new(0, 25);
+        assert_eq!(
+            meter.pause_after(1_000, Duration::from_millis(20)),
+            Duration::from_millis(60)
+        );
+
+        // with both limits, the longest pause wins
+        let meter = HashMeter::new(10_000, 50);
+        assert_eq!(
+            meter.pause_after(1_000, Duration::from_millis(20)),
+            Duration::from_millis(80)
+        );
+        assert_eq!(
+            meter.pause_after(1_000, Duration::from_millis(90)),
+            Duration::from_millis(90)
+        );
+
+        assert!(HashMeter::new(0, 100).is_unlimited());
+        // an invalid duty cycle is kept in bounds instead of never hashing
+        assert!(HashMeter::new(0, 0).pause_after(1, Duration::from_millis(1)) > Duration::ZERO);
+        assert!(HashMeter::new(0, 250).is_unlimited());
+    }
+
+    #[test]
+    fn should_limit_the_hash_rate() {
+        let mut meter = HashMeter::new(20_000, 100);
+        let started = Instant::now();
+        for _ in 0..2_000 {
+            meter.record_hash();
+        }
+
+        // 7 full chunks (1792 hashes) at 20000 hashes per second
+        assert!(started.elapsed() >= Duration::from_millis(89));
+    }
+}
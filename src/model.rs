@@ -1,17 +1,100 @@
-mod account_balance_map;
+mod actor_registry;
 mod address;
+mod address_index;
+mod agri_data;
+mod batch_index;
+mod batch_state;
 mod block;
+mod block_attestation;
+mod block_stats;
 mod blockchain;
+mod change_feed;
+mod compliance;
+mod delegation;
+mod difficulty;
+mod document;
+mod encoding;
+mod escrow;
+mod event_bus;
+mod event_type;
+mod genesis;
+mod hashable;
+mod issuance_limit;
+mod lineage;
+mod lot;
+mod merkle_tree;
+mod namespace;
+mod orphaned_block;
+mod payload_normalization;
+mod profile;
+mod quality_consensus;
+mod quantity_balance;
+mod reorg;
+mod schedule;
+mod sensor_reading;
+mod sla;
 mod transaction;
+mod transaction_origins;
 mod transaction_pool;
+mod validation;
+mod validity_window;
 
 // Explicitly controlling which individual identifiers we export
 // It also avoids verbose module imports from other files
+pub use actor_registry::{ActorRegistry, RegisteredActor, Registration, Role, REGISTRATION_EVENT};
 pub use address::Address;
-pub use block::{Block, BlockHash};
-pub use blockchain::{Blockchain, BlockchainError};
-pub use transaction::Transaction;
+pub use address_index::AddressIndex;
+pub use agri_data::AgriData;
+pub use batch_index::{BatchIndex, TxPosition};
+pub use batch_state::BatchState;
+pub use block::{Block, BlockBody, BlockHash, BlockHeader, BlockRef};
+pub use block_attestation::{AttestationError, BlockAttestation, BlockAttestations};
+pub use block_stats::{BlockStats, StatsTotals};
+pub use blockchain::Blockchain;
+#[cfg(feature = "gossip")]
+pub use blockchain::BlockchainError;
+pub use change_feed::{Change, ChangeFeed, ChangeKind};
+pub use compliance::{
+    ComplianceReport, ComplianceThresholds, ComplianceWindow, COMPLIANCE_REPORT_EVENT,
+};
+pub use delegation::{Delegation, DELEGATION_EVENT};
+pub use difficulty::{Difficulty, DifficultyPeriods};
+pub use document::{
+    Document, DocumentChunk, DocumentError, DocumentManifest, CHUNK_EVENT, DOCUMENT_EVENT,
+};
+pub use encoding::{decode, encode, Encoding, EncodingError};
+pub use escrow::{Escrow, EscrowState, ESCROW_EVENT};
+pub use event_bus::{ChainEvent, EventBus, EventSubscription};
+pub use event_type::EventType;
+pub use genesis::{
+    GenesisConfig, GenesisError, GenesisSummary, ProtocolActivation, PROTOCOL_VERSION,
+};
+pub use hashable::{CanonicalWriter, Hashable};
+pub use issuance_limit::IssuanceLimit;
+pub use lineage::{
+    custodians_of, BatchLineage, BatchPortion, LineageLink, Merge, Split, MERGE_EVENT, SPLIT_EVENT,
+};
+pub use lot::{Lot, LOT_EVENT};
+pub use merkle_tree::{transaction_hash, MerkleProof, MerkleTree, ProofSide, ProofStep, TxHash};
+pub use namespace::Namespace;
+pub use orphaned_block::{OrphanReason, OrphanedBlock, ReorgSummary};
+pub use payload_normalization::{
+    check_normalized, normalize, to_kg, NormalizationStep, NormalizationVersion,
+    NORMALIZATION_VERSION,
+};
+pub use profile::{Profile, PROFILE_EVENT};
+pub use quality_consensus::{QualityAttestation, QualityConsensus};
+pub use quantity_balance::{QuantityLedger, QuantityStrictness};
+pub use reorg::ReorgEvent;
+pub use schedule::{Plan, PlanStatus, PlannedEvent, Schedule, PLANNED_EVENT};
+pub use sensor_reading::{GpsPosition, SensorReading, SensorReadings, SENSOR_READINGS_EVENT};
+pub use sla::{Sla, SLA_EVENT};
+pub use transaction::{Transaction, TransactionError, COINBASE_BATCH_ID};
+pub use transaction_origins::{OriginChannel, TransactionOrigin, TransactionOrigins};
 pub use transaction_pool::{TransactionPool, TransactionVec};
+pub use validation::{BlockValidator, ValidationError, MAX_TRANSACTIONS_PER_BLOCK};
+pub use validity_window::ValidityWindow;
 
+// The fixtures of the unit tests, which only the unit tests can reach (there is no library target to export them)
 #[cfg(test)]
-pub use address::test_util;
+pub mod fixtures;
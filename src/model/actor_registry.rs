@@ -0,0 +1,428 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{Address, AgriData, EventType, Namespace, Transaction, TransactionError};
+
+pub const REGISTRATION_EVENT: &str = "REGISTRATION";
+
+// What an actor of the supply chain does, each lifecycle event can only be emitted by one role
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Role {
+    Farmer,
+    Transporter,
+    Warehouse,
+    Processor,
+    Retailer,
+    Inspector,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Role::Farmer => "FARMER",
+            Role::Transporter => "TRANSPORTER",
+            Role::Warehouse => "WAREHOUSE",
+            Role::Processor => "PROCESSOR",
+            Role::Retailer => "RETAILER",
+            Role::Inspector => "INSPECTOR",
+        }
+    }
+
+    // The role required to emit an event, the custom events are open to everyone
+    pub fn required_for(event_type: &EventType) -> Option<Role> {
+        match event_type {
+            EventType::Harvest => Some(Role::Farmer),
+            EventType::Transport => Some(Role::Transporter),
+            EventType::Storage => Some(Role::Warehouse),
+            EventType::Processing => Some(Role::Processor),
+            EventType::QualityCheck => Some(Role::Inspector),
+            EventType::Sale => Some(Role::Retailer),
+            // the food safety authority, or the laboratory that found the contamination
+            EventType::Recall => Some(Role::Inspector),
+            EventType::Custom(_) => None,
+        }
+    }
+}
+
+// Roles granted to the recipient of a REGISTRATION event
+// A newer registration of the same actor replaces the previous one, so an empty list revokes all its roles
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Registration {
+    pub roles: Vec<Role>,
+    // consortium of the actor on a node shared by several of them, which confines it to its batches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<Namespace>,
+}
+
+impl Registration {
+    // Parses the registration contained in the data of a REGISTRATION event
+    pub fn parse(data: &str) -> Result<Registration, TransactionError> {
+        serde_json::from_str(data).map_err(|_| TransactionError::InvalidRegistration)
+    }
+}
+
+// An actor and its current roles
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct RegisteredActor {
+    #[schema(value_type = String)]
+    pub address: Address,
+    pub roles: Vec<Role>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub namespace: Option<Namespace>,
+}
+
+// The actors registered on chain and their roles
+// The sender of the first registration of the chain becomes its registrar, the only one who can register actors
+// Until then the chain is open and anyone can emit any event, afterwards each lifecycle event requires its role
+// The actors registered in a namespace only emit events about its batches, and the batches of a namespace
+// only get events from its actors
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ActorRegistry {
+    registrar: Option<Address>,
+    roles: HashMap<Address, Vec<Role>>,
+    namespaces: HashMap<Address, Namespace>,
+}
+
+impl ActorRegistry {
+    pub fn registrar(&self) -> Option<&Address> {
+        self.registrar.as_ref()
+    }
+
+    pub fn roles_of(&self, address: &Address) -> &[Role] {
+        self.roles.get(address).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    pub fn namespace_of(&self, address: &Address) -> Option<&Namespace> {
+        self.namespaces.get(address)
+    }
+
+    // The actors with at least one role, sorted by address
+    pub fn actors(&self) -> Vec<RegisteredActor> {
+        let mut actors: Vec<RegisteredActor> = self
+            .roles
+            .iter()
+            .filter(|(_, roles)| !roles.is_empty())
+            .map(|(address, roles)| RegisteredActor {
+                address: address.clone(),
+                roles: roles.clone(),
+                namespace: self.namespace_of(address).cloned(),
+            })
+            .collect();
+        actors.sort_by_key(|actor| actor.address.to_string());
+        actors
+    }
+
+    // Checks that the sender of a registration is the registrar, and that the actor of an event has its role
+    // The actor is the one a gateway acts on behalf of, as it's who emits the event
+    // The transactions allowed by the identity of their sender must be signed by it, as anyone could use its
+    // address otherwise (the signatures present were already verified with the transaction)
+    pub fn check(&self, transaction: &Transaction) -> Result<(), TransactionError> {
+        let registrar = match &self.registrar {
+            Some(registrar) => registrar,
+            None => return Ok(()),
+        };
+        let signed = || match transaction.signature.is_some() {
+            true => Ok(()),
+            false => Err(TransactionError::MissingSignature),
+        };
+
+        if transaction.event_type == REGISTRATION_EVENT {
+            return match transaction.sender == *registrar {
+                true => signed(),
+                false => Err(TransactionError::NotRegistrar(registrar.clone())),
+            };
+        }
+
+        let actor = transaction
+            .on_behalf_of
+            .as_ref()
+            .unwrap_or(&transaction.sender);
+        // the namespace of the batch was already validated with the transaction
+        let batch_namespace = Namespace::of(&transaction.batch_id).ok().flatten();
+        if self.namespace_of(actor) != batch_namespace.as_ref() {
+            return Err(TransactionError::ForeignNamespace(
+                actor.clone(),
+                transaction.batch_id.clone(),
+            ));
+        }
+
+        let role = match Role::required_for(&transaction.event_type) {
+            Some(role) => role,
+            None => return Ok(()),
+        };
+        let missing_role = |actor: &Address| {
+            TransactionError::MissingRole(
+                actor.clone(),
+                role.as_str().to_string(),
+                transaction.event_type.to_string(),
+            )
+        };
+        if !self.roles_of(actor).contains(&role) {
+            return Err(missing_role(actor));
+        }
+        signed()?;
+
+        // every inspector of a check graded by several of them needs the role too
+        if let AgriData::QualityCheck(check) = &transaction.data {
+            let unregistered = check
+                .attestations
+                .iter()
+                .find(|attestation| !self.roles_of(&attestation.inspector).contains(&role));
+            if let Some(attestation) = unregistered {
+                return Err(missing_role(&attestation.inspector));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Records a registration, any other transaction is ignored
+    // The transaction must have been checked before, as the registrar is not verified again
+    pub fn apply(&mut self, transaction: &Transaction) {
+        if transaction.event_type != REGISTRATION_EVENT {
+            return;
+        }
+        let registration = match Registration::parse(&transaction.data.as_json()) {
+            Ok(registration) => registration,
+            Err(_) => return,
+        };
+
+        self.registrar
+            .get_or_insert_with(|| transaction.sender.clone());
+        self.roles
+            .insert(transaction.recipient.clone(), registration.roles);
+        match registration.namespace {
+            Some(namespace) => self
+                .namespaces
+                .insert(transaction.recipient.clone(), namespace),
+            None => self.namespaces.remove(&transaction.recipient),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::SigningKey;
+
+    use super::*;
+    use crate::model::{
+        fixtures::{farm, inspector, mill, Persona},
+        QualityAttestation,
+    };
+
+    #[test]
+    fn should_parse_registrations() {
+        let registration = Registration::parse(r#"{"roles": ["FARMER", "RETAILER"]}"#).unwrap();
+        assert_eq!(registration.roles, vec![Role::Farmer, Role::Retailer]);
+
+        for data in [r#"{"roles": ["PILOT"]}"#, r#"{}"#, "FARMER"] {
+            assert_eq!(
+                Registration::parse(data),
+                Err(TransactionError::InvalidRegistration)
+            );
+        }
+    }
+
+    #[test]
+    fn should_require_the_role_of_each_lifecycle_event() {
+        let mut registry = ActorRegistry::default();
+        let farm = farm();
+        let inspector = inspector();
+
+        // nobody is registered yet, so the chain is still open
+        assert_eq!(registry.check(&event(&inspector, "HARVEST")), Ok(()));
+        assert_eq!(
+            registry.check(&unsigned(event(&inspector, "HARVEST"))),
+            Ok(())
+        );
+
+        let registration = create_registration(&farm, &farm.address, r#"{"roles": ["FARMER"]}"#);
+        assert_eq!(registry.check(&registration), Ok(()));
+        registry.apply(&registration);
+        assert_eq!(registry.registrar(), Some(&farm.address));
+
+        assert_eq!(registry.check(&event(&farm, "HARVEST")), Ok(()));
+        assert_eq!(
+            registry.check(&event(&farm, "QUALITY_CHECK")),
+            Err(TransactionError::MissingRole(
+                farm.address.clone(),
+                "INSPECTOR".to_string(),
+                "QUALITY_CHECK".to_string()
+            ))
+        );
+        // custom events are open to everyone
+        assert_eq!(registry.check(&event(&inspector, "DISPUTE")), Ok(()));
+        assert_eq!(
+            registry.check(&unsigned(event(&inspector, "DISPUTE"))),
+            Ok(())
+        );
+        // but the role of an actor is only granted to its key
+        assert_eq!(
+            registry.check(&unsigned(event(&farm, "HARVEST"))),
+            Err(TransactionError::MissingSignature)
+        );
+
+        // only the registrar can register the other actors
+        let registration = create_registration(
+            &inspector,
+            &inspector.address,
+            r#"{"roles": ["INSPECTOR"]}"#,
+        );
+        assert_eq!(
+            registry.check(&registration),
+            Err(TransactionError::NotRegistrar(farm.address.clone()))
+        );
+        let registration =
+            create_registration(&farm, &inspector.address, r#"{"roles": ["INSPECTOR"]}"#);
+        assert_eq!(
+            registry.check(&unsigned(registration.clone())),
+            Err(TransactionError::MissingSignature)
+        );
+        assert_eq!(registry.check(&registration), Ok(()));
+        registry.apply(&registration);
+        assert_eq!(registry.check(&event(&inspector, "QUALITY_CHECK")), Ok(()));
+
+        // a gateway needs the role of the actor it acts for
+        let delegated = farm.sign(Transaction {
+            on_behalf_of: Some(inspector.address.clone()),
+            ..event(&farm, "QUALITY_CHECK")
+        });
+        assert_eq!(registry.check(&delegated), Ok(()));
+
+        // and the roles can be revoked
+        registry.apply(&create_registration(
+            &farm,
+            &inspector.address,
+            r#"{"roles": []}"#,
+        ));
+        assert!(registry.check(&event(&inspector, "QUALITY_CHECK")).is_err());
+        assert_eq!(registry.actors().len(), 1);
+    }
+
+    #[test]
+    fn should_require_the_role_of_every_inspector() {
+        let laboratory = SigningKey::from_bytes(&[1; 32]);
+        let buyer = SigningKey::from_bytes(&[2; 32]);
+        let attestations: Vec<QualityAttestation> = [&laboratory, &buyer]
+            .iter()
+            .map(|key| QualityAttestation::sign(key, "WHEAT-1", "A", 50, 100))
+            .collect();
+        let data = serde_json::json!({
+            "type": "QUALITY_CHECK",
+            "result": "PASS",
+            "grade": "A",
+            "threshold": 100,
+            "attestations": attestations,
+        });
+        let inspector = inspector();
+        let check = inspector.sign(Transaction {
+            batch_id: "WHEAT-1".to_string(),
+            data: serde_json::from_value(data).unwrap(),
+            ..event(&inspector, "QUALITY_CHECK")
+        });
+        assert_eq!(check.validate(), Ok(()));
+
+        let mut registry = ActorRegistry::default();
+        for actor in [&inspector.address, &attestations[0].inspector] {
+            registry.apply(&create_registration(
+                &inspector,
+                actor,
+                r#"{"roles": ["INSPECTOR"]}"#,
+            ));
+        }
+        assert_eq!(
+            registry.check(&check),
+            Err(TransactionError::MissingRole(
+                attestations[1].inspector.clone(),
+                "INSPECTOR".to_string(),
+                "QUALITY_CHECK".to_string()
+            ))
+        );
+        registry.apply(&create_registration(
+            &inspector,
+            &attestations[1].inspector,
+            r#"{"roles": ["INSPECTOR"]}"#,
+        ));
+        assert_eq!(registry.check(&check), Ok(()));
+    }
+
+    #[test]
+    fn should_confine_the_actors_to_their_namespace() {
+        let mut registry = ActorRegistry::default();
+        let acme_farm = farm();
+        let other_farm = mill();
+        registry.apply(&create_registration(
+            &acme_farm,
+            &acme_farm.address,
+            r#"{"roles": ["FARMER"], "namespace": "acme"}"#,
+        ));
+        registry.apply(&create_registration(
+            &acme_farm,
+            &other_farm.address,
+            r#"{"roles": ["FARMER"]}"#,
+        ));
+        assert_eq!(
+            registry.namespace_of(&acme_farm.address),
+            Some(&"acme".parse().unwrap())
+        );
+
+        let harvest = |sender: &Persona, batch_id: &str| {
+            sender.sign(Transaction {
+                batch_id: batch_id.to_string(),
+                ..event(sender, "HARVEST")
+            })
+        };
+        assert_eq!(registry.check(&harvest(&acme_farm, "acme:WHEAT-1")), Ok(()));
+        assert_eq!(registry.check(&harvest(&other_farm, "WHEAT-1")), Ok(()));
+        // neither in the batches of the other consortia, nor in the batches without a namespace
+        for (actor, batch_id) in [
+            (&acme_farm, "globex:WHEAT-1"),
+            (&acme_farm, "WHEAT-1"),
+            (&other_farm, "acme:WHEAT-1"),
+        ] {
+            assert_eq!(
+                registry.check(&harvest(actor, batch_id)),
+                Err(TransactionError::ForeignNamespace(
+                    actor.address.clone(),
+                    batch_id.to_string()
+                ))
+            );
+        }
+        assert_eq!(
+            harvest(&acme_farm, "Acme:WHEAT-1").validate(),
+            Err(TransactionError::InvalidNamespace("Acme".to_string()))
+        );
+    }
+
+    // Signed by its sender, like the events of the registered actors must be
+    fn event(sender: &Persona, event_type: &str) -> Transaction {
+        sender.sign(Transaction {
+            sender: sender.address.clone(),
+            recipient: sender.address.clone(),
+            event_type: event_type.into(),
+            ..Default::default()
+        })
+    }
+
+    fn unsigned(transaction: Transaction) -> Transaction {
+        Transaction {
+            signature: None,
+            ..transaction
+        }
+    }
+
+    fn create_registration(sender: &Persona, recipient: &Address, data: &str) -> Transaction {
+        sender.sign(Transaction {
+            sender: sender.address.clone(),
+            recipient: recipient.clone(),
+            data: data.into(),
+            event_type: REGISTRATION_EVENT.into(),
+            ..Default::default()
+        })
+    }
+}
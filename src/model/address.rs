@@ -37,6 +37,19 @@ impl TryFrom<Vec<Byte>> for Address {
     }
 }
 
+// Addresses are ed25519 public keys
+impl From<[Byte; LEN]> for Address {
+    fn from(bytes: [Byte; LEN]) -> Self {
+        Address(bytes)
+    }
+}
+
+impl Address {
+    pub fn as_bytes(&self) -> &[Byte; LEN] {
+        &self.0
+    }
+}
+
 impl TryFrom<String> for Address {
     type Error = AddressError;
 
@@ -68,36 +81,6 @@ impl fmt::Display for Address {
     }
 }
 
-// Some sample addresses to be used in tests all over the project
-// We export functions to workaround constant value restrictions in Rust
-#[cfg(test)]
-pub mod test_util {
-    use std::convert::TryFrom;
-
-    use super::Address;
-
-    pub fn alice() -> Address {
-        Address::try_from(
-            "f780b958227ff0bf5795ede8f9f7eaac67e7e06666b043a400026cbd421ce28e".to_string(),
-        )
-        .unwrap()
-    }
-
-    pub fn bob() -> Address {
-        Address::try_from(
-            "51df097c03c0a6e64e54a6fce90cb6968adebd85955917ed438e3d3c05f2f00f".to_string(),
-        )
-        .unwrap()
-    }
-
-    pub fn carol() -> Address {
-        Address::try_from(
-            "b4f8293fb123ef3ff9ad49e923f4afc732774ee2bfdc3b278a359b54473c2277".to_string(),
-        )
-        .unwrap()
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use std::{convert::TryFrom, str::FromStr};
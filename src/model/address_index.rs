@@ -0,0 +1,93 @@
+use std::{collections::HashMap, ops::Range};
+
+use super::{Address, Block, TxPosition};
+
+// Where the transactions of each actor are in the chain, so everything an actor ever touched is found
+// without going through every block
+// An actor is involved as the sender, the recipient, the actor a gateway acts for or one of the extra recipients
+// (see Transaction::actors), and a transaction is only indexed once for each of them
+#[derive(Debug, Clone, Default)]
+pub struct AddressIndex {
+    positions: HashMap<Address, Vec<TxPosition>>,
+}
+
+impl AddressIndex {
+    // The index of the blocks of a chain, from its genesis
+    pub fn of<'a>(blocks: impl Iterator<Item = &'a Block>) -> AddressIndex {
+        let mut index = AddressIndex::default();
+        for block in blocks {
+            index.apply(block);
+        }
+        index
+    }
+
+    // Adds the transactions of the next block of the chain
+    // The reward of the miner is left out, it's in every block the miner mined
+    pub fn apply(&mut self, block: &Block) {
+        for (position, transaction) in block.body.transactions.iter().enumerate() {
+            if transaction.is_coinbase() {
+                continue;
+            }
+            for actor in transaction.actors() {
+                self.positions
+                    .entry(actor.clone())
+                    .or_default()
+                    .push((block.header.index, position));
+            }
+        }
+    }
+
+    // The positions of the transactions of an actor in a range of blocks, in chain order
+    pub fn positions(&self, address: &Address, blocks: Range<u64>) -> &[TxPosition] {
+        let positions = self
+            .positions
+            .get(address)
+            .map(|positions| positions.as_slice())
+            .unwrap_or_default();
+        // the positions are sorted by block, as the blocks are applied in order
+        let start = positions.partition_point(|(index, _)| *index < blocks.start);
+        let end = positions.partition_point(|(index, _)| *index < blocks.end);
+
+        &positions[start..end.max(start)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        fixtures::{alice, bob, carol},
+        BlockHash, Transaction,
+    };
+
+    #[test]
+    fn should_find_the_transactions_of_an_actor() {
+        let event = |sender: Address, recipient: Address| Transaction {
+            sender,
+            recipient,
+            batch_id: "WHEAT-1".to_string(),
+            event_type: "STORAGE".into(),
+            ..Default::default()
+        };
+        let recall = Transaction {
+            recipients: vec![carol()],
+            ..event(bob(), alice())
+        };
+        let first = Block::new(1, 0, BlockHash::zero(), vec![event(alice(), alice())]);
+        let second = Block::new(2, 0, first.header.hash, vec![event(alice(), bob()), recall]);
+        let index = AddressIndex::of([first, second].iter());
+
+        // once even when the actor is both the sender and the recipient
+        assert_eq!(
+            index.positions(&alice(), 0..u64::MAX),
+            &[(1, 0), (2, 0), (2, 1)]
+        );
+        assert_eq!(index.positions(&bob(), 0..u64::MAX), &[(2, 0), (2, 1)]);
+        assert_eq!(index.positions(&carol(), 0..u64::MAX), &[(2, 1)]);
+
+        // only the blocks of the range
+        assert_eq!(index.positions(&alice(), 2..3), &[(2, 0), (2, 1)]);
+        assert_eq!(index.positions(&alice(), 0..2), &[(1, 0)]);
+        assert!(index.positions(&alice(), 3..10).is_empty());
+    }
+}
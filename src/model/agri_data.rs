@@ -0,0 +1,247 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::{quality_consensus, EventType, QualityAttestation, QualityConsensus, TransactionError};
+
+// Payload of a transaction, either typed for the lifecycle events or the raw text of the older transactions
+// The typed payloads are JSON objects tagged with their event type, e.g. {"type": "HARVEST", "crop": "wheat", ...},
+// while the raw ones are still a JSON string, so the transactions recorded before keep the same hash
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AgriData {
+    Harvest(HarvestData),
+    Transport(TransportData),
+    Storage(StorageData),
+    Processing(ProcessingData),
+    QualityCheck(QualityCheckData),
+    Sale(SaleData),
+    // free-form text (usually JSON), also used by the system events (e.g. PROFILE or LOT)
+    #[serde(untagged)]
+    Raw(String),
+}
+
+// The other fields of a typed payload (e.g. "shelf_life_days" or "certifications") are kept as they are
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HarvestData {
+    pub crop: String,
+    pub quantity_kg: f64,
+    pub field: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransportData {
+    pub vehicle: String,
+    pub driver: String,
+    // ISO 8601 date and time, e.g. "2024-12-22T08:00:00Z"
+    pub departure: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StorageData {
+    pub facility: String,
+    // degrees celsius
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProcessingData {
+    pub process: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_kg: Option<f64>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QualityCheckData {
+    // e.g. "PASS" or "FAIL"
+    pub result: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grade: Option<String>,
+    // for the checks graded by several inspectors: the weight that their grades must reach together,
+    // and their signed grades, the grade of the check being the agreed one (see quality_consensus)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attestations: Vec<QualityAttestation>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SaleData {
+    pub buyer: String,
+    pub price: f64,
+    pub currency: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl QualityCheckData {
+    // The grade agreed by the inspectors of a check graded by several of them, none for a single inspector
+    // A threshold goes with the attestations, and the grade of the check must be the agreed one
+    pub fn consensus(&self, batch_id: &str) -> Result<Option<QualityConsensus>, TransactionError> {
+        match (self.threshold, self.attestations.is_empty()) {
+            (None, true) => Ok(None),
+            (Some(threshold), false) => quality_consensus::check_consensus(
+                batch_id,
+                self.grade.as_ref(),
+                threshold,
+                &self.attestations,
+            )
+            .map(Some),
+            _ => Err(TransactionError::InvalidPayload),
+        }
+    }
+}
+
+impl AgriData {
+    // Returns the event that a typed payload is for, none for the raw ones
+    pub fn event_type(&self) -> Option<EventType> {
+        match self {
+            AgriData::Harvest(_) => Some(EventType::Harvest),
+            AgriData::Transport(_) => Some(EventType::Transport),
+            AgriData::Storage(_) => Some(EventType::Storage),
+            AgriData::Processing(_) => Some(EventType::Processing),
+            AgriData::QualityCheck(_) => Some(EventType::QualityCheck),
+            AgriData::Sale(_) => Some(EventType::Sale),
+            AgriData::Raw(_) => None,
+        }
+    }
+
+    // Returns the payload as JSON text, the way it is read by the queries and reports
+    // Raw payloads are returned as they are, even if they are not valid JSON
+    pub fn as_json(&self) -> Cow<'_, str> {
+        match self {
+            AgriData::Raw(text) => Cow::Borrowed(text),
+            typed => Cow::Owned(serde_json::to_string(typed).unwrap_or_default()),
+        }
+    }
+
+    // Checks that a typed payload belongs to the event of the transaction, and that its amounts make sense
+    pub fn validate(&self, event_type: &EventType) -> Result<(), TransactionError> {
+        let payload_type = match self.event_type() {
+            Some(payload_type) => payload_type,
+            None => return Ok(()),
+        };
+        if payload_type != *event_type {
+            return Err(TransactionError::PayloadMismatch(
+                event_type.to_string(),
+                payload_type.to_string(),
+            ));
+        }
+
+        let amounts = match self {
+            AgriData::Harvest(harvest) => vec![harvest.quantity_kg],
+            AgriData::Processing(processing) => processing.output_kg.into_iter().collect(),
+            AgriData::Sale(sale) => vec![sale.price],
+            _ => Vec::new(),
+        };
+        if amounts
+            .iter()
+            .any(|amount| !amount.is_finite() || *amount < 0.0)
+        {
+            return Err(TransactionError::InvalidPayload);
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for AgriData {
+    fn default() -> Self {
+        AgriData::Raw(String::new())
+    }
+}
+
+impl From<&str> for AgriData {
+    fn from(text: &str) -> Self {
+        AgriData::Raw(text.to_string())
+    }
+}
+
+impl From<String> for AgriData {
+    fn from(text: String) -> Self {
+        AgriData::Raw(text)
+    }
+}
+
+impl PartialEq<&str> for AgriData {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_json() == *other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_serialize_typed_and_raw_payloads() {
+        let json = r#"{"type": "HARVEST", "crop": "wheat", "quantity_kg": 500, "field": "Field-7", "shelf_life_days": 90}"#;
+        let data: AgriData = serde_json::from_str(json).unwrap();
+        match &data {
+            AgriData::Harvest(harvest) => {
+                assert_eq!(harvest.crop, "wheat");
+                assert_eq!(harvest.quantity_kg, 500.0);
+                assert_eq!(harvest.extra["shelf_life_days"], 90);
+            }
+            other => panic!("unexpected payload {:?}", other),
+        }
+        assert!(data
+            .as_json()
+            .starts_with(r#"{"type":"HARVEST","crop":"wheat""#));
+
+        // the older payloads are still plain strings
+        let raw: AgriData = serde_json::from_str(r#""{\"crop\": \"wheat\"}""#).unwrap();
+        assert_eq!(raw, AgriData::from(r#"{"crop": "wheat"}"#));
+        assert_eq!(
+            serde_json::to_string(&raw).unwrap(),
+            r#""{\"crop\": \"wheat\"}""#
+        );
+
+        // but a typed payload must have all of its fields
+        let missing_field = r#"{"type": "TRANSPORT", "vehicle": "TRUCK-15"}"#;
+        assert!(serde_json::from_str::<AgriData>(missing_field).is_err());
+    }
+
+    #[test]
+    fn should_validate_typed_payloads() {
+        let sale = AgriData::Sale(SaleData {
+            buyer: "Fresh Market".to_string(),
+            price: 120.5,
+            currency: "USD".to_string(),
+            extra: Map::new(),
+        });
+        assert_eq!(sale.validate(&EventType::Sale), Ok(()));
+        assert_eq!(
+            sale.validate(&EventType::Harvest),
+            Err(TransactionError::PayloadMismatch(
+                "HARVEST".to_string(),
+                "SALE".to_string()
+            ))
+        );
+
+        let processing = AgriData::Processing(ProcessingData {
+            process: "milling".to_string(),
+            output_kg: Some(-1.0),
+            extra: Map::new(),
+        });
+        assert_eq!(
+            processing.validate(&EventType::Processing),
+            Err(TransactionError::InvalidPayload)
+        );
+
+        // raw payloads go with any event
+        assert_eq!(AgriData::from("{}").validate(&EventType::Sale), Ok(()));
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+
+use super::{Block, LineageLink};
+
+// Where a transaction is in the chain: the index of its block and its position in the block
+pub type TxPosition = (u64, usize);
+
+// Where the events of each batch are in the chain, so its history is found without going through every block
+// Updated with each block appended to the main chain, and built again from scratch with the rest of the state
+// of the chain when a reorganization replaces its last blocks
+#[derive(Debug, Clone, Default)]
+pub struct BatchIndex {
+    positions: HashMap<String, Vec<TxPosition>>,
+    // the splits and merges of each batch, as the parent or the child, so its lineage is found the same way
+    links: HashMap<String, Vec<TxPosition>>,
+}
+
+impl BatchIndex {
+    // The index of the blocks of a chain, from its genesis
+    pub fn of<'a>(blocks: impl Iterator<Item = &'a Block>) -> BatchIndex {
+        let mut index = BatchIndex::default();
+        for block in blocks {
+            index.apply(block);
+        }
+        index
+    }
+
+    // Adds the transactions of the next block of the chain
+    // The reward of the miner is left out, it's not an event of any batch
+    pub fn apply(&mut self, block: &Block) {
+        for (position, transaction) in block.body.transactions.iter().enumerate() {
+            if transaction.is_coinbase() {
+                continue;
+            }
+            self.positions
+                .entry(transaction.batch_id.clone())
+                .or_default()
+                .push((block.header.index, position));
+            for link in LineageLink::of(transaction) {
+                for batch_id in [link.parent, link.child] {
+                    let links = self.links.entry(batch_id).or_default();
+                    // a merge of several parents links its batch more than once
+                    if links.last() != Some(&(block.header.index, position)) {
+                        links.push((block.header.index, position));
+                    }
+                }
+            }
+        }
+    }
+
+    // The positions of the transactions of a batch, in chain order
+    pub fn positions(&self, batch_id: &str) -> &[TxPosition] {
+        self.positions
+            .get(batch_id)
+            .map(|positions| positions.as_slice())
+            .unwrap_or_default()
+    }
+
+    // The positions of the splits and merges that a batch is the parent or the child of, in chain order
+    pub fn links(&self, batch_id: &str) -> &[TxPosition] {
+        self.links
+            .get(batch_id)
+            .map(|positions| positions.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn batch_ids(&self) -> impl Iterator<Item = &String> {
+        self.positions.keys()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{BlockHash, Transaction};
+
+    #[test]
+    fn should_find_the_transactions_of_a_batch() {
+        let event = |batch_id: &str| Transaction {
+            batch_id: batch_id.to_string(),
+            event_type: "STORAGE".into(),
+            ..Default::default()
+        };
+        let first = Block::new(
+            1,
+            0,
+            BlockHash::zero(),
+            vec![event("WHEAT-1"), event("CORN-1")],
+        );
+        let second = Block::new(2, 0, first.header.hash, vec![event("WHEAT-1")]);
+        let mut index = BatchIndex::of([first].iter());
+        assert_eq!(index.positions("WHEAT-1"), &[(1, 0)]);
+
+        index.apply(&second);
+        assert_eq!(index.positions("WHEAT-1"), &[(1, 0), (2, 0)]);
+        assert_eq!(index.positions("CORN-1"), &[(1, 1)]);
+        assert!(index.positions("RICE-1").is_empty());
+        assert_eq!(index.batch_ids().count(), 2);
+    }
+
+    #[test]
+    fn should_find_the_splits_and_merges_of_a_batch() {
+        let merge = Transaction {
+            batch_id: "FLOUR-1".to_string(),
+            event_type: "MERGE".into(),
+            data: r#"{"parents": [{"batch_id": "WHEAT-1", "quantity_kg": 10}, {"batch_id": "WHEAT-2", "quantity_kg": 5}]}"#.into(),
+            ..Default::default()
+        };
+        let block = Block::new(1, 0, BlockHash::zero(), vec![merge]);
+        let index = BatchIndex::of([block].iter());
+
+        // the merge is an event of the child only, but links the parents too
+        assert_eq!(index.positions("FLOUR-1"), &[(1, 0)]);
+        assert!(index.positions("WHEAT-1").is_empty());
+        assert_eq!(index.links("FLOUR-1"), &[(1, 0)]);
+        assert_eq!(index.links("WHEAT-1"), &[(1, 0)]);
+        assert_eq!(index.links("WHEAT-2"), &[(1, 0)]);
+        assert!(index.links("CORN-1").is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{EventType, LineageLink, Transaction, PLANNED_EVENT};
+
+// Where a batch is in the supply chain, derived from its lifecycle events
+// Created → Harvested → (InTransit | Stored | Processed, in any order and any number of times) → Sold,
+// and a batch can be Recalled at any point after its harvest, even once sold
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum BatchState {
+    // The batch has events (e.g. its lot or documents), but it was not harvested yet
+    Created,
+    Harvested,
+    InTransit,
+    Stored,
+    Processed,
+    Sold,
+    Recalled,
+}
+
+impl BatchState {
+    pub fn as_str(&self) -> &str {
+        match self {
+            BatchState::Created => "CREATED",
+            BatchState::Harvested => "HARVESTED",
+            BatchState::InTransit => "IN_TRANSIT",
+            BatchState::Stored => "STORED",
+            BatchState::Processed => "PROCESSED",
+            BatchState::Sold => "SOLD",
+            BatchState::Recalled => "RECALLED",
+        }
+    }
+
+    // The state after an event, none if the batch cannot go through it (e.g. a STORAGE after the sale)
+    // The custom events and the quality checks don't move the batch
+    pub fn next(self, event_type: &EventType) -> Option<BatchState> {
+        match (self, event_type) {
+            (_, EventType::Custom(_)) => Some(self),
+            (BatchState::Created, EventType::Harvest) => Some(BatchState::Harvested),
+            (BatchState::Created, _) | (_, EventType::Harvest) => None,
+            (BatchState::Recalled, _) => None,
+            (BatchState::Sold, EventType::Recall) => Some(BatchState::Recalled),
+            (BatchState::Sold, _) => None,
+            (_, EventType::Transport) => Some(BatchState::InTransit),
+            (_, EventType::Storage) => Some(BatchState::Stored),
+            (_, EventType::Processing) => Some(BatchState::Processed),
+            (_, EventType::QualityCheck) => Some(self),
+            (_, EventType::Sale) => Some(BatchState::Sold),
+            (_, EventType::Recall) => Some(BatchState::Recalled),
+        }
+    }
+
+    // Whether the batch can still be split, or merged into another one
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self,
+            BatchState::Harvested
+                | BatchState::InTransit
+                | BatchState::Stored
+                | BatchState::Processed
+        )
+    }
+
+    // The state of a batch after the transactions of the chain in order, none if it has no events
+    // A batch split or merged from others was harvested with them, so it starts harvested
+    // The planned events are ignored, they're not facts
+    // The chain never contains an impossible transition, so one could only come from another version of the
+    // rules and is ignored
+    pub fn of<'a, I>(batch_id: &str, transactions: I) -> Option<BatchState>
+    where
+        I: IntoIterator<Item = &'a Transaction>,
+    {
+        transactions.into_iter().fold(None, |state, transaction| {
+            let is_born = state.is_none()
+                && LineageLink::of(transaction)
+                    .iter()
+                    .any(|link| link.child == batch_id);
+            let state = match is_born {
+                true => Some(BatchState::Harvested),
+                false => state,
+            };
+            // a plan is not a fact about the batch
+            if transaction.batch_id != batch_id || transaction.event_type == PLANNED_EVENT {
+                return state;
+            }
+
+            let state = state.unwrap_or(BatchState::Created);
+            Some(state.next(&transaction.event_type).unwrap_or(state))
+        })
+    }
+}
+
+impl fmt::Display for BatchState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_follow_the_lifecycle_of_a_batch() {
+        let created = BatchState::Created;
+        assert_eq!(
+            created.next(&EventType::Harvest),
+            Some(BatchState::Harvested)
+        );
+        assert_eq!(created.next(&EventType::Processing), None);
+        assert_eq!(created.next(&EventType::Recall), None);
+        assert_eq!(created.next(&"LOT".into()), Some(created));
+
+        // the steps after the harvest repeat in any order, the quality checks keep the state
+        let processed = BatchState::Processed;
+        assert_eq!(processed.next(&EventType::Harvest), None);
+        assert_eq!(
+            processed.next(&EventType::Storage),
+            Some(BatchState::Stored)
+        );
+        assert_eq!(
+            BatchState::Stored.next(&EventType::Transport),
+            Some(BatchState::InTransit)
+        );
+        assert_eq!(processed.next(&EventType::QualityCheck), Some(processed));
+
+        // a sold batch can still be recalled, and nothing happens to a recalled one
+        let sold = BatchState::Sold;
+        assert_eq!(sold.next(&EventType::Transport), None);
+        assert_eq!(sold.next(&"DISPUTE".into()), Some(sold));
+        assert_eq!(sold.next(&EventType::Recall), Some(BatchState::Recalled));
+        assert_eq!(BatchState::Recalled.next(&EventType::Sale), None);
+        assert_eq!(BatchState::Recalled.next(&EventType::Recall), None);
+    }
+
+    #[test]
+    fn should_derive_the_state_from_the_events() {
+        let mut events: Vec<Transaction> = ["LOT", "HARVEST", "TRANSPORT", "QUALITY_CHECK"]
+            .into_iter()
+            .map(|event_type| Transaction {
+                batch_id: "WHEAT-1".to_string(),
+                event_type: event_type.into(),
+                ..Default::default()
+            })
+            .collect();
+
+        assert_eq!(BatchState::of("WHEAT-1", &[]), None);
+        assert_eq!(
+            BatchState::of("WHEAT-1", &events[..1]),
+            Some(BatchState::Created)
+        );
+        assert_eq!(
+            BatchState::of("WHEAT-1", &events),
+            Some(BatchState::InTransit)
+        );
+
+        // the children of a split start harvested, even without events of their own
+        assert_eq!(BatchState::of("WHEAT-1-A", &events), None);
+        events.push(Transaction {
+            batch_id: "WHEAT-1".to_string(),
+            event_type: "SPLIT".into(),
+            data: r#"{"children": [{"batch_id": "WHEAT-1-A", "quantity_kg": 200}]}"#.into(),
+            ..Default::default()
+        });
+        assert_eq!(
+            BatchState::of("WHEAT-1-A", &events),
+            Some(BatchState::Harvested)
+        );
+        assert_eq!(
+            BatchState::of("WHEAT-1", &events),
+            Some(BatchState::InTransit)
+        );
+    }
+}
@@ -1,22 +1,72 @@
-use chrono::prelude::*;
 use ethereum_types::U256;
 use serde::{Deserialize, Serialize};
-use sha2::{Sha256, Digest};
+use utoipa::ToSchema;
 
-use super::Transaction;
+use super::{
+    decode, encode, CanonicalWriter, Encoding, EncodingError, Hashable, MerkleProof, MerkleTree,
+    Transaction,
+};
+use crate::chaos;
 
 pub type BlockHash = U256;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// A block is its header, which is all that the hash covers, and its body with the transactions
+// The JSON of the API is flat, with the fields of the header next to the transactions
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Block {
+    #[serde(flatten)]
+    pub header: BlockHeader,
+    #[serde(flatten)]
+    pub body: BlockBody,
+}
+
+// The fields of a block that its hash is calculated from, so a chain of headers can be synced and checked
+// (e.g. by a light client) without any transaction, and mining only hashes them again for each nonce
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct BlockHeader {
     pub index: u64,
     pub timestamp: i64,
     pub nonce: u64,
+    #[schema(value_type = String)]
     pub previous_hash: BlockHash,
+    // root of the merkle tree of the transactions, the hash only commits to them through it
+    // optional in the API, which calculates it for the blocks submitted manually
+    #[serde(default)]
+    #[schema(value_type = String)]
+    pub merkle_root: BlockHash,
+    #[schema(value_type = String)]
     pub hash: BlockHash,
+}
+
+// The transactions of a block, committed to by the merkle root of its header
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct BlockBody {
     pub transactions: Vec<Transaction>,
 }
 
+// A block in the binary format of the storage and the gossip network, JSON is only for the API
+// The payloads of the transactions are free-form JSON, so each transaction is in the canonical binary encoding
+#[derive(Serialize, Deserialize)]
+struct BinaryBlock {
+    index: u64,
+    timestamp: i64,
+    nonce: u64,
+    previous_hash: [u8; 32],
+    merkle_root: [u8; 32],
+    hash: [u8; 32],
+    transactions: Vec<Vec<u8>>,
+}
+
+// Where a transaction was mined: its block and its position in it, enough to request its inclusion proof
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct BlockRef {
+    pub index: u64,
+    #[schema(value_type = String)]
+    pub hash: BlockHash,
+    pub timestamp: i64,
+    pub position: usize,
+}
+
 impl Block {
     pub fn new(
         index: u64,
@@ -24,37 +74,115 @@ impl Block {
         previous_hash: BlockHash,
         transactions: Vec<Transaction>,
     ) -> Block {
-        let mut block = Block {
+        let mut header = BlockHeader {
             index,
-            timestamp: Utc::now().timestamp_millis(),
+            timestamp: chaos::now_millis(),
             nonce,
             previous_hash,
+            merkle_root: MerkleTree::new(&transactions).root(),
             hash: BlockHash::default(),
+        };
+        header.hash = header.calculate_hash();
+        Block {
+            header,
+            body: BlockBody { transactions },
+        }
+    }
+
+    pub fn calculate_hash(&self) -> BlockHash {
+        self.header.calculate_hash()
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, EncodingError> {
+        let transactions = self
+            .body
+            .transactions
+            .iter()
+            .map(|transaction| encode(transaction, Encoding::Binary))
+            .collect::<Result<_, _>>()?;
+        let header = &self.header;
+        let binary = BinaryBlock {
+            index: header.index,
+            timestamp: header.timestamp,
+            nonce: header.nonce,
+            previous_hash: hash_bytes(&header.previous_hash),
+            merkle_root: hash_bytes(&header.merkle_root),
+            hash: hash_bytes(&header.hash),
             transactions,
         };
-        block.hash = block.calculate_hash();
-        block
+
+        bincode::serialize(&binary).map_err(|_| EncodingError::InvalidValue)
+    }
+
+    // The hash is not checked, the blocks read from a peer are validated by the chain like any other
+    pub fn from_bytes(bytes: &[u8]) -> Result<Block, EncodingError> {
+        let binary: BinaryBlock =
+            bincode::deserialize(bytes).map_err(|_| EncodingError::InvalidData)?;
+        let transactions = binary
+            .transactions
+            .iter()
+            .map(|transaction| {
+                let value = decode(transaction, Encoding::Binary)?;
+                serde_json::from_value(value).map_err(|_| EncodingError::InvalidData)
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Block {
+            header: BlockHeader {
+                index: binary.index,
+                timestamp: binary.timestamp,
+                nonce: binary.nonce,
+                previous_hash: U256::from_big_endian(&binary.previous_hash),
+                merkle_root: U256::from_big_endian(&binary.merkle_root),
+                hash: U256::from_big_endian(&binary.hash),
+            },
+            body: BlockBody { transactions },
+        })
+    }
+
+    pub fn calculate_merkle_root(&self) -> BlockHash {
+        MerkleTree::new(&self.body.transactions).root()
+    }
+
+    // Returns the proof that the transaction at a position is included in the block
+    pub fn proof_for(&self, position: usize) -> Option<MerkleProof> {
+        MerkleTree::new(&self.body.transactions).proof(position)
     }
+}
 
+impl BlockHeader {
+    // The transactions only take part in the hash through the merkle root,
+    // so recalculating the hash (e.g. for each nonce while mining) doesn't go through them
     pub fn calculate_hash(&self) -> BlockHash {
-        let mut hashable_data = self.clone();
-        hashable_data.hash = BlockHash::default();
-        let serialized = serde_json::to_string(&hashable_data).unwrap();
+        self.canonical_hash()
+    }
+}
 
-        // SHA-256 using sha2 crate
-        let mut hasher = Sha256::new();
-        hasher.update(serialized.as_bytes());
-        let result = hasher.finalize();
+// The fields covered by the hash, the transactions only through the merkle root
+impl Hashable for BlockHeader {
+    const DOMAIN: &'static [u8] = &[];
 
-        // Convert to U256 - using from_big_endian
-        U256::from_big_endian(result.as_slice())
+    fn write_canonical(&self, writer: &mut CanonicalWriter) {
+        writer.u64(self.index);
+        writer.i64(self.timestamp);
+        writer.u64(self.nonce);
+        writer.hash(&self.previous_hash);
+        writer.hash(&self.merkle_root);
     }
 }
 
+fn hash_bytes(hash: &BlockHash) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    hash.to_big_endian(&mut bytes);
+    bytes
+}
+
 #[cfg(test)]
 mod tests {
+    use sha2::{Digest, Sha256};
+
     use super::*;
-    use crate::model::test_util::{alice, bob};
+    use crate::model::fixtures::{alice, bob};
 
     #[test]
     fn should_create_block_with_transactions() {
@@ -64,21 +192,21 @@ mod tests {
 
         let block = Block::new(1, 0, previous_hash, transactions.clone());
 
-        assert_eq!(block.index, 1);
-        assert_eq!(block.nonce, 0);
-        assert_eq!(block.previous_hash, previous_hash);
-        assert_eq!(block.transactions.len(), 1);
-        assert_eq!(block.transactions[0].batch_id, tx.batch_id);
+        assert_eq!(block.header.index, 1);
+        assert_eq!(block.header.nonce, 0);
+        assert_eq!(block.header.previous_hash, previous_hash);
+        assert_eq!(block.body.transactions.len(), 1);
+        assert_eq!(block.body.transactions[0].batch_id, tx.batch_id);
     }
 
     #[test]
     fn should_create_block_without_transactions() {
         let block = Block::new(0, 0, BlockHash::default(), Vec::new());
 
-        assert_eq!(block.index, 0);
-        assert_eq!(block.nonce, 0);
-        assert_eq!(block.previous_hash, BlockHash::default());
-        assert!(block.transactions.is_empty());
+        assert_eq!(block.header.index, 0);
+        assert_eq!(block.header.nonce, 0);
+        assert_eq!(block.header.previous_hash, BlockHash::default());
+        assert!(block.body.transactions.is_empty());
     }
 
     #[test]
@@ -87,7 +215,19 @@ mod tests {
         let calculated_hash = block.calculate_hash();
 
         assert_ne!(calculated_hash, BlockHash::default());
-        assert_eq!(block.hash, calculated_hash);
+        assert_eq!(block.header.hash, calculated_hash);
+    }
+
+    #[test]
+    fn should_calculate_the_same_hash_from_the_header() {
+        let block = Block::new(
+            1,
+            100,
+            BlockHash::from(999),
+            vec![create_test_transaction()],
+        );
+
+        assert_eq!(block.header.calculate_hash(), block.header.hash);
     }
 
     #[test]
@@ -97,7 +237,7 @@ mod tests {
         let block1 = Block::new(1, 0, previous_hash, Vec::new());
         let block2 = Block::new(1, 1, previous_hash, Vec::new());
 
-        assert_ne!(block1.hash, block2.hash);
+        assert_ne!(block1.header.hash, block2.header.hash);
     }
 
     #[test]
@@ -107,7 +247,7 @@ mod tests {
         let block1 = Block::new(1, 0, previous_hash, Vec::new());
         let block2 = Block::new(2, 0, previous_hash, Vec::new());
 
-        assert_ne!(block1.hash, block2.hash);
+        assert_ne!(block1.header.hash, block2.header.hash);
     }
 
     #[test]
@@ -115,7 +255,7 @@ mod tests {
         let block1 = Block::new(1, 0, BlockHash::from(111), Vec::new());
         let block2 = Block::new(1, 0, BlockHash::from(222), Vec::new());
 
-        assert_ne!(block1.hash, block2.hash);
+        assert_ne!(block1.header.hash, block2.header.hash);
     }
 
     #[test]
@@ -128,16 +268,16 @@ mod tests {
         let block1 = Block::new(1, 0, previous_hash, vec![tx1]);
         let block2 = Block::new(1, 0, previous_hash, vec![tx2]);
 
-        assert_ne!(block1.hash, block2.hash);
+        assert_ne!(block1.header.hash, block2.header.hash);
     }
 
     #[test]
     fn should_recalculate_hash_correctly() {
         let mut block = Block::new(1, 0, BlockHash::from(999), Vec::new());
-        let original_hash = block.hash;
+        let original_hash = block.header.hash;
 
         // Manually change the hash to something invalid
-        block.hash = BlockHash::from(111);
+        block.header.hash = BlockHash::from(111);
 
         // Recalculate should return the original hash
         let recalculated_hash = block.calculate_hash();
@@ -150,12 +290,15 @@ mod tests {
         let block1 = Block::new(1, 100, BlockHash::from(999), vec![tx]);
         let block2 = block1.clone();
 
-        assert_eq!(block1.index, block2.index);
-        assert_eq!(block1.nonce, block2.nonce);
-        assert_eq!(block1.hash, block2.hash);
-        assert_eq!(block1.previous_hash, block2.previous_hash);
-        assert_eq!(block1.timestamp, block2.timestamp);
-        assert_eq!(block1.transactions.len(), block2.transactions.len());
+        assert_eq!(block1.header.index, block2.header.index);
+        assert_eq!(block1.header.nonce, block2.header.nonce);
+        assert_eq!(block1.header.hash, block2.header.hash);
+        assert_eq!(block1.header.previous_hash, block2.header.previous_hash);
+        assert_eq!(block1.header.timestamp, block2.header.timestamp);
+        assert_eq!(
+            block1.body.transactions.len(),
+            block2.body.transactions.len()
+        );
     }
 
     #[test]
@@ -176,10 +319,13 @@ mod tests {
         let json = serde_json::to_string(&original_block).unwrap();
         let deserialized_block: Block = serde_json::from_str(&json).unwrap();
 
-        assert_eq!(deserialized_block.index, original_block.index);
-        assert_eq!(deserialized_block.nonce, original_block.nonce);
-        assert_eq!(deserialized_block.hash, original_block.hash);
-        assert_eq!(deserialized_block.previous_hash, original_block.previous_hash);
+        assert_eq!(deserialized_block.header.index, original_block.header.index);
+        assert_eq!(deserialized_block.header.nonce, original_block.header.nonce);
+        assert_eq!(deserialized_block.header.hash, original_block.header.hash);
+        assert_eq!(
+            deserialized_block.header.previous_hash,
+            original_block.header.previous_hash
+        );
     }
 
     #[test]
@@ -191,10 +337,50 @@ mod tests {
         let block2: Block = serde_json::from_str(&json).unwrap();
 
         // Hash should be the same
-        assert_eq!(block1.hash, block2.hash);
+        assert_eq!(block1.header.hash, block2.header.hash);
 
         // Recalculating hash should give the same result
-        assert_eq!(block2.calculate_hash(), block1.hash);
+        assert_eq!(block2.calculate_hash(), block1.header.hash);
+    }
+
+    #[test]
+    fn should_hash_a_fixed_binary_layout() {
+        let mut block = Block::new(1, 42, BlockHash::from(999), Vec::new());
+        block.header.timestamp = 1_700_000_000_000;
+
+        // a change of the layout would change the hash of every block of every chain
+        let mut preimage = Vec::new();
+        preimage.extend(1u64.to_le_bytes());
+        preimage.extend(1_700_000_000_000i64.to_le_bytes());
+        preimage.extend(42u64.to_le_bytes());
+        preimage.extend(hash_bytes(&BlockHash::from(999)));
+        preimage.extend(hash_bytes(&block.header.merkle_root));
+        let expected = U256::from_big_endian(Sha256::digest(preimage).as_slice());
+        assert_eq!(block.calculate_hash(), expected);
+    }
+
+    #[test]
+    fn should_convert_to_bytes_and_back() {
+        let mut tx = create_test_transaction();
+        tx.data = r#"{"crop": "wheat", "temperature": 4.5}"#.into();
+        let block = Block::new(1, 42, BlockHash::from(999), vec![tx.clone(), tx]);
+
+        let bytes = block.to_bytes().unwrap();
+        assert!(bytes.len() < serde_json::to_vec(&block).unwrap().len());
+        let decoded = Block::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.header.hash, block.header.hash);
+        assert_eq!(decoded.calculate_hash(), block.header.hash);
+        assert_eq!(decoded.calculate_merkle_root(), block.header.merkle_root);
+        assert_eq!(
+            serde_json::to_value(&decoded.body.transactions).unwrap(),
+            serde_json::to_value(&block.body.transactions).unwrap()
+        );
+
+        assert_eq!(
+            Block::from_bytes(&bytes[..bytes.len() - 1]).err(),
+            Some(EncodingError::InvalidData)
+        );
+        assert!(Block::from_bytes(b"not a block").is_err());
     }
 
     #[test]
@@ -207,19 +393,20 @@ mod tests {
 
         let block = Block::new(1, 0, BlockHash::default(), vec![tx1, tx2, tx3]);
 
-        assert_eq!(block.transactions.len(), 3);
-        assert_eq!(block.transactions[0].batch_id, "WHEAT-001");
-        assert_eq!(block.transactions[1].batch_id, "WHEAT-002");
-        assert_eq!(block.transactions[2].batch_id, "WHEAT-003");
+        assert_eq!(block.body.transactions.len(), 3);
+        assert_eq!(block.body.transactions[0].batch_id, "WHEAT-001");
+        assert_eq!(block.body.transactions[1].batch_id, "WHEAT-002");
+        assert_eq!(block.body.transactions[2].batch_id, "WHEAT-003");
     }
 
     fn create_test_transaction() -> Transaction {
         Transaction {
             sender: alice(),
             recipient: bob(),
-            data: "Test harvest data".to_string(),
+            data: "Test harvest data".into(),
             batch_id: "WHEAT-001".to_string(),
-            event_type: "HARVEST".to_string(),
+            event_type: "HARVEST".into(),
+            ..Default::default()
         }
     }
 }
@@ -0,0 +1,245 @@
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use super::{Address, BlockHash, Blockchain, CanonicalWriter};
+use crate::crypto::{self, SigningDomain};
+
+// The statement refers to the report of the inspection, it doesn't contain it
+pub const MAX_STATEMENT_LENGTH: usize = 1024;
+
+#[derive(Error, PartialEq, Debug)]
+pub enum AttestationError {
+    #[error("`{0}` is not the key of a regulator known by this node")]
+    UnknownRegulator(Address),
+
+    #[error("Invalid signature of the attestation")]
+    InvalidSignature,
+
+    #[error("Invalid statement, it must have between 1 and {MAX_STATEMENT_LENGTH} bytes")]
+    InvalidStatement,
+
+    #[error("The block `{0:#x}` is not in the chain")]
+    UnknownBlock(BlockHash),
+
+    #[error("Could not store the attestation: {0}")]
+    Storage(String),
+}
+
+// The mark left on a block by a regulator, e.g. by a government inspection of the events of the block
+// It's signed with the key of the regulator so anyone can verify it, but kept apart from the chain:
+// attesting a block afterwards doesn't change its hash nor the consensus history
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct BlockAttestation {
+    #[schema(value_type = String)]
+    pub block_hash: BlockHash,
+    #[schema(value_type = String)]
+    pub regulator: Address,
+    // what the regulator attests, e.g. the reference and the outcome of the inspection
+    pub statement: String,
+    // when the regulator signed it, in milliseconds
+    pub signed_at: i64,
+    pub signature: String,
+}
+
+impl BlockAttestation {
+    pub fn sign(
+        key: &SigningKey,
+        block_hash: BlockHash,
+        statement: &str,
+        signed_at: i64,
+    ) -> BlockAttestation {
+        let message = signed_bytes(&block_hash, statement, signed_at);
+        let signature = crypto::sign(key, SigningDomain::BlockAttestation, &message);
+        BlockAttestation {
+            block_hash,
+            regulator: Address::from(key.verifying_key().to_bytes()),
+            statement: statement.to_string(),
+            signed_at,
+            signature: hex::encode(signature.to_bytes()),
+        }
+    }
+
+    // Checks that the regulator signed this statement about this block
+    pub fn verify(&self) -> Result<(), AttestationError> {
+        if self.statement.is_empty() || self.statement.len() > MAX_STATEMENT_LENGTH {
+            return Err(AttestationError::InvalidStatement);
+        }
+
+        let signature_bytes: [u8; 64] = hex::decode(&self.signature)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(AttestationError::InvalidSignature)?;
+        let key = VerifyingKey::from_bytes(self.regulator.as_bytes())
+            .map_err(|_| AttestationError::InvalidSignature)?;
+
+        let message = signed_bytes(&self.block_hash, &self.statement, self.signed_at);
+        crypto::verify(
+            &key,
+            SigningDomain::BlockAttestation,
+            &message,
+            &Signature::from_bytes(&signature_bytes),
+        )
+        .map_err(|_| AttestationError::InvalidSignature)
+    }
+}
+
+fn signed_bytes(block_hash: &BlockHash, statement: &str, signed_at: i64) -> Vec<u8> {
+    let mut writer = CanonicalWriter::default();
+    writer.hash(block_hash);
+    writer.str(statement);
+    writer.i64(signed_at);
+    writer.into_bytes()
+}
+
+// We don't need to export this type because concurrency is encapsulated in this file
+type SyncedAttestationMap = Arc<Mutex<HashMap<BlockHash, Vec<BlockAttestation>>>>;
+
+// The attestations of the blocks, in a sidecar of the chain referenced by the block hashes
+// Only the regulators configured in the node can attest, any other key is refused
+// The attestations are appended to a file (if set), so they survive restarts
+#[derive(Debug, Clone)]
+pub struct BlockAttestations {
+    attestations: SyncedAttestationMap,
+    regulators: Vec<Address>,
+    file_path: String,
+}
+
+impl BlockAttestations {
+    pub fn new(regulators: Vec<Address>, file_path: String) -> BlockAttestations {
+        BlockAttestations {
+            attestations: SyncedAttestationMap::default(),
+            regulators,
+            file_path,
+        }
+    }
+
+    // Adds the attestation of a block of the main chain, returns false if it was already known
+    pub fn add(
+        &self,
+        blockchain: &Blockchain,
+        attestation: BlockAttestation,
+    ) -> Result<bool, AttestationError> {
+        if !self.regulators.contains(&attestation.regulator) {
+            return Err(AttestationError::UnknownRegulator(attestation.regulator));
+        }
+        attestation.verify()?;
+        if blockchain
+            .get_block_by_hash(&attestation.block_hash)
+            .is_none()
+        {
+            return Err(AttestationError::UnknownBlock(attestation.block_hash));
+        }
+
+        let mut attestations = self.attestations.lock().unwrap();
+        let of_block = attestations.entry(attestation.block_hash).or_default();
+        if of_block.contains(&attestation) {
+            return Ok(false);
+        }
+        self.append(&attestation)
+            .map_err(|error| AttestationError::Storage(error.to_string()))?;
+        of_block.push(attestation);
+
+        Ok(true)
+    }
+
+    // The attestations of a block, in the order they were added
+    pub fn of(&self, block_hash: &BlockHash) -> Vec<BlockAttestation> {
+        let attestations = self.attestations.lock().unwrap();
+
+        attestations.get(block_hash).cloned().unwrap_or_default()
+    }
+
+    // Loads the attestations persisted in previous runs
+    pub fn load(&self) -> Result<()> {
+        if self.file_path.is_empty() || fs::metadata(&self.file_path).is_err() {
+            return Ok(());
+        }
+
+        let raw_attestations = fs::read_to_string(&self.file_path)?;
+        let mut attestations = self.attestations.lock().unwrap();
+        for line in raw_attestations
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+        {
+            let attestation: BlockAttestation = serde_json::from_str(line)?;
+            attestations
+                .entry(attestation.block_hash)
+                .or_default()
+                .push(attestation);
+        }
+
+        Ok(())
+    }
+
+    // One JSON document per line, so adding an attestation doesn't rewrite the whole file
+    fn append(&self, attestation: &BlockAttestation) -> Result<()> {
+        if self.file_path.is_empty() {
+            return Ok(());
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.file_path)?;
+        writeln!(file, "{}", serde_json::to_string(attestation)?)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_only_keep_the_attestations_of_the_regulators() {
+        let blockchain = Blockchain::new(0);
+        let block_hash = blockchain.get_last_block().header.hash;
+        let regulator = SigningKey::from_bytes(&[3; 32]);
+        let stranger = SigningKey::from_bytes(&[4; 32]);
+        let attestations = BlockAttestations::new(
+            vec![Address::from(regulator.verifying_key().to_bytes())],
+            String::new(),
+        );
+
+        let attestation =
+            BlockAttestation::sign(&regulator, block_hash, "Inspection 2024-117: compliant", 1);
+        assert_eq!(attestations.add(&blockchain, attestation.clone()), Ok(true));
+        assert_eq!(
+            attestations.add(&blockchain, attestation.clone()),
+            Ok(false)
+        );
+        assert_eq!(attestations.of(&block_hash), vec![attestation.clone()]);
+
+        // the statement cannot be changed after signing
+        let altered = BlockAttestation {
+            statement: "Inspection 2024-117: not compliant".to_string(),
+            ..attestation.clone()
+        };
+        assert_eq!(
+            attestations.add(&blockchain, altered),
+            Err(AttestationError::InvalidSignature)
+        );
+        let unknown = BlockAttestation::sign(&stranger, block_hash, "Compliant", 1);
+        assert_eq!(
+            attestations.add(&blockchain, unknown.clone()),
+            Err(AttestationError::UnknownRegulator(unknown.regulator))
+        );
+        let missing_block = BlockAttestation::sign(&regulator, BlockHash::zero(), "Compliant", 1);
+        assert_eq!(
+            attestations.add(&blockchain, missing_block),
+            Err(AttestationError::UnknownBlock(BlockHash::zero()))
+        );
+        assert_eq!(attestations.of(&block_hash).len(), 1);
+    }
+}
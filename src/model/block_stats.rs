@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::Block;
+
+// Statistics of a single block, computed only once when the block is appended to the chain
+// and stored with it, so they're loaded instead of computed again on startup
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct BlockStats {
+    pub index: u64,
+    pub timestamp: i64,
+    pub transaction_count: usize,
+    pub transactions_by_type: BTreeMap<String, usize>,
+    // size in bytes of the block in its binary format, as stored
+    pub size: usize,
+    // milliseconds since the previous block was mined
+    pub block_time_ms: i64,
+}
+
+impl BlockStats {
+    pub fn new(block: &Block, previous: Option<&Block>) -> BlockStats {
+        let mut transactions_by_type = BTreeMap::new();
+        for transaction in block.body.transactions.iter() {
+            *transactions_by_type
+                .entry(transaction.event_type.to_string())
+                .or_insert(0) += 1;
+        }
+
+        BlockStats {
+            index: block.header.index,
+            timestamp: block.header.timestamp,
+            transaction_count: block.body.transactions.len(),
+            transactions_by_type,
+            size: block.to_bytes().map_or(0, |bytes| bytes.len()),
+            block_time_ms: previous.map_or(0, |previous| {
+                block.header.timestamp - previous.header.timestamp
+            }),
+        }
+    }
+}
+
+// Aggregated statistics of the whole chain, updated every time a block is appended
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub struct StatsTotals {
+    pub blocks: u64,
+    pub transactions_by_type: BTreeMap<String, u64>,
+    pub size: u64,
+    pub last_block_time_ms: i64,
+}
+
+// Time series with the statistics of every block in the chain, plus running totals
+// so the exporters don't need to go through all the blocks on every request
+#[derive(Debug, Default)]
+pub struct ChainStats {
+    series: Vec<BlockStats>,
+    totals: StatsTotals,
+}
+
+impl ChainStats {
+    pub fn of(series: impl IntoIterator<Item = BlockStats>) -> ChainStats {
+        let mut stats = ChainStats::default();
+        for block_stats in series {
+            stats.record(block_stats);
+        }
+        stats
+    }
+
+    pub fn record(&mut self, stats: BlockStats) {
+        let totals = &mut self.totals;
+        for (event_type, count) in stats.transactions_by_type.iter() {
+            *totals
+                .transactions_by_type
+                .entry(event_type.clone())
+                .or_insert(0) += *count as u64;
+        }
+        totals.blocks += 1;
+        totals.size += stats.size as u64;
+        totals.last_block_time_ms = stats.block_time_ms;
+
+        self.series.push(stats);
+    }
+
+    // Returns the statistics of all the blocks starting from an index
+    pub fn get_series(&self, from_index: u64) -> Vec<BlockStats> {
+        let start = (from_index as usize).min(self.series.len());
+
+        self.series[start..].to_vec()
+    }
+
+    pub fn get(&self, index: u64) -> Option<&BlockStats> {
+        self.series.get(index as usize)
+    }
+
+    pub fn get_totals(&self) -> StatsTotals {
+        self.totals.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{BlockHash, Transaction};
+
+    #[test]
+    fn should_compute_block_stats() {
+        let mut previous = Block::new(0, 0, BlockHash::default(), Vec::new());
+        previous.header.timestamp = 1000;
+        let transactions = vec![
+            create_transaction("HARVEST"),
+            create_transaction("HARVEST"),
+            create_transaction("TRANSPORT"),
+        ];
+        let mut block = Block::new(1, 0, previous.header.hash, transactions);
+        block.header.timestamp = 1500;
+
+        let stats = BlockStats::new(&block, Some(&previous));
+        assert_eq!(stats.index, 1);
+        assert_eq!(stats.transaction_count, 3);
+        assert_eq!(stats.transactions_by_type["HARVEST"], 2);
+        assert_eq!(stats.transactions_by_type["TRANSPORT"], 1);
+        assert_eq!(stats.size, block.to_bytes().unwrap().len());
+        assert_eq!(stats.block_time_ms, 500);
+    }
+
+    #[test]
+    fn should_keep_running_totals() {
+        let mut chain_stats = ChainStats::default();
+        let genesis = Block::new(0, 0, BlockHash::default(), Vec::new());
+        let block = Block::new(
+            1,
+            0,
+            genesis.header.hash,
+            vec![create_transaction("HARVEST")],
+        );
+        chain_stats.record(BlockStats::new(&genesis, None));
+        chain_stats.record(BlockStats::new(&block, Some(&genesis)));
+
+        let totals = chain_stats.get_totals();
+        assert_eq!(totals.blocks, 2);
+        assert_eq!(totals.transactions_by_type["HARVEST"], 1);
+        assert_eq!(
+            totals.last_block_time_ms,
+            block.header.timestamp - genesis.header.timestamp
+        );
+
+        let series = chain_stats.get_series(0);
+        let size: usize = series.iter().map(|stats| stats.size).sum();
+        assert_eq!(totals.size, size as u64);
+        assert_eq!(chain_stats.get_series(1).len(), 1);
+        assert!(chain_stats.get_series(5).is_empty());
+    }
+
+    fn create_transaction(event_type: &str) -> Transaction {
+        Transaction {
+            event_type: event_type.into(),
+            ..Default::default()
+        }
+    }
+}
@@ -1,58 +1,366 @@
 use anyhow::Result;
 use std::{
-    sync::{Arc, Mutex},
+    collections::{BTreeSet, HashMap, VecDeque},
+    ops::Range,
+    sync::{Arc, RwLock},
 };
 use thiserror::Error;
 
-use super::{Block, BlockHash};
+use super::{
+    block_stats::ChainStats, custodians_of, transaction_hash, ActorRegistry, Address, AddressIndex,
+    BatchIndex, BatchLineage, BatchState, Block, BlockHash, BlockHeader, BlockRef, BlockStats,
+    BlockValidator, ChainEvent, ComplianceReport, Delegation, Difficulty, DifficultyPeriods,
+    Document, DocumentChunk, DocumentManifest, Escrow, EscrowState, EventBus, GenesisConfig,
+    GenesisSummary, IssuanceLimit, LineageLink, Lot, OrphanReason, OrphanedBlock, Plan,
+    PlannedEvent, Profile, ProtocolActivation, QuantityLedger, QuantityStrictness, ReorgEvent,
+    ReorgSummary, Schedule, SensorReadings, StatsTotals, Transaction, TransactionError, TxHash,
+    TxPosition, ValidationError, CHUNK_EVENT, COMPLIANCE_REPORT_EVENT, DELEGATION_EVENT,
+    DOCUMENT_EVENT, ESCROW_EVENT, LOT_EVENT, PLANNED_EVENT, PROFILE_EVENT, SENSOR_READINGS_EVENT,
+};
+use crate::chaos;
 
 pub type BlockVec = Vec<Block>;
+pub type BlockHeaderVec = Vec<BlockHeader>;
+pub type OrphanedBlockVec = Vec<OrphanedBlock>;
+
+// Orphaned blocks kept by default, any peer can send competing blocks so the archive cannot grow forever
+const DEFAULT_ORPHAN_LIMIT: usize = 1000;
+
+// All the data derived from the main chain, protected by a single lock to keep it consistent
+#[derive(Debug)]
+struct ChainState {
+    blocks: BlockVec,
+    headers: BlockHeaderVec,
+    stats: ChainStats,
+    // roles of the actors, updated with the registrations of each block
+    registry: ActorRegistry,
+    // planned events, apart from the blocks so they're never mistaken for the history of the batches
+    schedule: Schedule,
+    // kilograms left of each batch, moved between the batches by the splits and merges
+    quantities: QuantityLedger,
+    // where the transactions of the chain are by hash, so none of them is mined twice and the references are followed
+    included: HashMap<TxHash, TxPosition>,
+    // hashes of the responses to each transaction of the chain (see in_response_to), so its thread is followed forward
+    responses: HashMap<TxHash, Vec<TxHash>>,
+    // where the events of each batch are, so their history is found without going through the whole chain
+    batches: BatchIndex,
+    // where the transactions of each actor are, e.g. for an inspector to find everything it ever touched
+    addresses: AddressIndex,
+    // difficulty of each period, so the difficulty of a block doesn't depend on the whole chain before it
+    difficulties: DifficultyPeriods,
+    // latest delegation of each actor to each gateway, by (actor, gateway), so they are checked without a scan
+    delegations: HashMap<(Address, Address), Delegation>,
+    // latest profile published by each actor, the newer ones replacing it
+    profiles: HashMap<Address, Profile>,
+    // where the manifest and the chunks of each document are, by the hash of its content
+    documents: HashMap<String, Vec<TxPosition>>,
+    // highest sequence allocated to the lots of each prefix and season
+    lot_sequences: HashMap<(String, String), u64>,
+}
+
+impl ChainState {
+    // The state of a chain with only its genesis block
+    fn new(genesis_block: Block, difficulty: Difficulty) -> ChainState {
+        // the statistics of each block are calculated only once, when it's added
+        let mut stats = ChainStats::default();
+        stats.record(BlockStats::new(&genesis_block, None));
+
+        // the actors registered and the events planned by the genesis block
+        let mut registry = ActorRegistry::default();
+        let mut schedule = Schedule::default();
+        for transaction in genesis_block.body.transactions.iter() {
+            registry.apply(transaction);
+            schedule.apply(transaction, genesis_block.header.timestamp);
+        }
+        let quantities = QuantityLedger::of(genesis_block.body.transactions.iter());
+        let batches = BatchIndex::of([&genesis_block].into_iter());
+        let addresses = AddressIndex::of([&genesis_block].into_iter());
+
+        let mut state = ChainState {
+            headers: vec![genesis_block.header.clone()],
+            blocks: vec![],
+            stats,
+            registry,
+            schedule,
+            quantities,
+            included: HashMap::new(),
+            responses: HashMap::new(),
+            batches,
+            addresses,
+            difficulties: DifficultyPeriods::new(difficulty),
+            delegations: HashMap::new(),
+            profiles: HashMap::new(),
+            documents: HashMap::new(),
+            lot_sequences: HashMap::new(),
+        };
+        state.apply_lookups(&genesis_block);
+        state.blocks.push(genesis_block);
+        state
+    }
+
+    // Appends a block that was already validated, with the data derived from it
+    fn push(&mut self, block: Block) {
+        let block_stats = BlockStats::new(&block, self.blocks.last());
+        self.headers.push(block.header.clone());
+        self.difficulties.apply(block.header.index, |index| {
+            self.headers[index as usize].timestamp
+        });
+        self.stats.record(block_stats);
+        for transaction in block.body.transactions.iter() {
+            self.registry.apply(transaction);
+            self.quantities.apply(transaction);
+            self.schedule.apply(transaction, block.header.timestamp);
+        }
+        self.batches.apply(&block);
+        self.addresses.apply(&block);
+        self.apply_lookups(&block);
+        self.blocks.push(block);
+    }
+
+    // Updates the lookups of the state that are kept by key, e.g. the transactions by hash
+    fn apply_lookups(&mut self, block: &Block) {
+        for (position, transaction) in block.body.transactions.iter().enumerate() {
+            let hash = transaction_hash(transaction);
+            // the coinbase of a miner is the same in each of its blocks, only the first one is kept
+            self.included
+                .entry(hash)
+                .or_insert((block.header.index, position));
+            if let Some(reference) = transaction.in_response_to {
+                self.responses.entry(reference).or_default().push(hash);
+            }
+            if transaction.event_type == DELEGATION_EVENT {
+                // the delegations in the chain were all parsed when they were validated
+                if let Ok(delegation) = Delegation::parse(&transaction.data.as_json()) {
+                    let key = (transaction.sender.clone(), transaction.recipient.clone());
+                    self.delegations.insert(key, delegation);
+                }
+            }
+            if transaction.event_type == PROFILE_EVENT {
+                if let Ok(profile) = Profile::parse(&transaction.data.as_json()) {
+                    self.profiles.insert(transaction.sender.clone(), profile);
+                }
+            }
+            if transaction.event_type == LOT_EVENT {
+                if let Ok(lot) = Lot::parse(&transaction.data.as_json()) {
+                    let last = self
+                        .lot_sequences
+                        .entry((lot.prefix, lot.season))
+                        .or_default();
+                    *last = lot.sequence.max(*last);
+                }
+            }
+            let document = match transaction.event_type.as_str() {
+                DOCUMENT_EVENT => DocumentManifest::parse(&transaction.data.as_json())
+                    .ok()
+                    .map(|manifest| manifest.hash),
+                CHUNK_EVENT => DocumentChunk::parse(&transaction.data.as_json())
+                    .ok()
+                    .map(|chunk| chunk.document),
+                _ => None,
+            };
+            if let Some(hash) = document {
+                self.documents
+                    .entry(hash)
+                    .or_default()
+                    .push((block.header.index, position));
+            }
+        }
+    }
+
+    // The transaction at a position of the chain
+    fn transaction_at(&self, (index, position): TxPosition) -> &Transaction {
+        &self.blocks[index as usize].body.transactions[position]
+    }
+
+    // Where a transaction was mined
+    fn block_ref(&self, (index, position): TxPosition) -> BlockRef {
+        let header = &self.headers[index as usize];
+        BlockRef {
+            index,
+            hash: header.hash,
+            timestamp: header.timestamp,
+            position,
+        }
+    }
+
+    // The transactions at positions of the chain, in chain order and each one once
+    fn transactions_at(&self, mut positions: Vec<TxPosition>) -> Vec<&Transaction> {
+        positions.sort_unstable();
+        positions.dedup();
+        positions
+            .into_iter()
+            .map(|position| self.transaction_at(position))
+            .collect()
+    }
+
+    // The events of a batch with the splits and merges that link it, enough for its state
+    fn batch_transactions(&self, batch_id: &str) -> Vec<&Transaction> {
+        let positions = self.batches.positions(batch_id).iter();
+        self.transactions_at(
+            positions
+                .chain(self.batches.links(batch_id))
+                .copied()
+                .collect(),
+        )
+    }
+
+    // The splits and merges from a batch to all the batches it comes from, enough for its lineage
+    fn lineage_transactions(&self, batch_id: &str) -> Vec<&Transaction> {
+        let (_, links) = self.walk_links(batch_id, true);
+        self.transactions_at(links)
+    }
+
+    // The events of a batch and of all the batches that come from it, with the splits and merges between them
+    fn family_transactions(&self, batch_id: &str) -> Vec<&Transaction> {
+        let (family, mut positions) = self.walk_links(batch_id, false);
+        for batch_id in family.iter() {
+            positions.extend(self.batches.positions(batch_id));
+        }
+        self.transactions_at(positions)
+    }
+
+    // Follows the splits and merges of a batch towards its ancestors or its descendants
+    // Returns the batches reached, the batch included, and the positions of the links followed
+    fn walk_links(
+        &self,
+        batch_id: &str,
+        to_ancestors: bool,
+    ) -> (BTreeSet<String>, Vec<TxPosition>) {
+        let mut reached = BTreeSet::from([batch_id.to_string()]);
+        let mut queue = VecDeque::from([batch_id.to_string()]);
+        let mut followed = Vec::new();
+        while let Some(current) = queue.pop_front() {
+            for &(index, position) in self.batches.links(&current) {
+                let transaction = &self.blocks[index as usize].body.transactions[position];
+                for link in LineageLink::of(transaction) {
+                    let (from, to) = match to_ancestors {
+                        true => (link.child, link.parent),
+                        false => (link.parent, link.child),
+                    };
+                    if from != current {
+                        continue;
+                    }
+                    followed.push((index, position));
+                    if reached.insert(to.clone()) {
+                        queue.push_back(to);
+                    }
+                }
+            }
+        }
+
+        (reached, followed)
+    }
+}
 
 // We don't need to export this because concurrency is encapsulated in this file
-type SyncedBlockVec = Arc<Mutex<BlockVec>>;
+type SyncedChainState = Arc<RwLock<ChainState>>;
+type SyncedOrphanedBlocks = Arc<RwLock<VecDeque<OrphanedBlock>>>;
 
 // Error types to return when trying to add blocks with invalid fields
 #[derive(Error, PartialEq, Debug)]
 #[allow(clippy::enum_variant_names)]
 pub enum BlockchainError {
-    #[error("Invalid index")]
-    InvalidIndex,
+    #[error(transparent)]
+    InvalidBlock(#[from] ValidationError),
 
-    #[error("Invalid previous_hash")]
-    InvalidPreviousHash,
+    #[error("Invalid transaction: {0}")]
+    InvalidTransaction(TransactionError),
 
-    #[error("Invalid hash")]
-    InvalidHash,
+    #[error("Block {0} is not before the last block of the chain, there is nothing to roll back")]
+    NotAFork(u64),
 
-    #[error("Invalid difficulty")]
-    InvalidDifficulty,
+    #[error("The fork up to block {0} is not longer than the chain up to block {1}")]
+    ShorterFork(u64, u64),
 }
 
 // Struct that holds all the blocks in the blockhain
-// Multiple threads can read/write concurrently to the list of blocks
+// It's a cheap handle to clone and share between threads (miner, API, peers...)
+// Any number of threads can read at the same time, while writes (new blocks) are exclusive
 #[derive(Debug, Clone)]
 pub struct Blockchain {
-    pub difficulty: u32,
-    blocks: SyncedBlockVec,
+    difficulty: Difficulty,
+    // to detect a corrupted genesis block, it's never added like the others
+    genesis_hash: BlockHash,
+    issuance_limit: IssuanceLimit,
+    quantity_strictness: QuantityStrictness,
+    // the versions of the consensus rules planned in the genesis
+    activations: Vec<ProtocolActivation>,
+    state: SyncedChainState,
+    orphaned_blocks: SyncedOrphanedBlocks,
+    // the oldest orphaned blocks are dropped past this amount
+    orphan_limit: usize,
+    event_bus: EventBus,
 }
 
 // Basic operations in the blockchain are encapsulated in the implementation
 // Encapsulates concurrency concerns, so external callers do not need to know how it's handled
 impl Blockchain {
-    // Creates a brand new blockchain with a genesis block
+    // Creates a brand new blockchain with a genesis block, whose blocks always need the same difficulty
     pub fn new(difficulty: u32) -> Blockchain {
-        let genesis_block = Blockchain::create_genesis_block();
+        Blockchain::with_difficulty(Difficulty::fixed(difficulty))
+    }
+
+    // Creates a brand new blockchain with a genesis block, whose difficulty can be adjusted
+    pub fn with_difficulty(difficulty: Difficulty) -> Blockchain {
+        Blockchain::with_genesis(Blockchain::create_genesis_block(), difficulty)
+    }
+
+    // Creates the blockchain of another network than the default one, with its own genesis block and difficulty
+    pub fn from_genesis(config: &GenesisConfig) -> Blockchain {
+        let mut blockchain = Blockchain::with_genesis(config.create_block(), config.difficulty());
+        blockchain.activations = config.activations.clone();
+        blockchain
+    }
 
-        // add the genesis block to the synced vec of blocks
-        let blocks = vec![genesis_block];
-        let synced_blocks = Arc::new(Mutex::new(blocks));
+    fn with_genesis(genesis_block: Block, difficulty: Difficulty) -> Blockchain {
+        // add the genesis block to the synced chain state
+        let genesis_hash = genesis_block.header.hash;
+        let state = ChainState::new(genesis_block, difficulty);
 
         Blockchain {
             difficulty,
-            blocks: synced_blocks,
+            genesis_hash,
+            issuance_limit: IssuanceLimit::unlimited(),
+            quantity_strictness: QuantityStrictness::default(),
+            activations: Vec::new(),
+            state: Arc::new(RwLock::new(state)),
+            orphaned_blocks: SyncedOrphanedBlocks::default(),
+            orphan_limit: DEFAULT_ORPHAN_LIMIT,
+            event_bus: EventBus::new(),
         }
     }
 
+    // Limits the custody events of each actor per batch, before the chain is shared with other threads
+    pub fn with_issuance_limit(mut self, issuance_limit: IssuanceLimit) -> Blockchain {
+        self.issuance_limit = issuance_limit;
+        self
+    }
+
+    // Chooses how the quantities of the batches are enforced, before the chain is shared with other threads
+    pub fn with_quantity_strictness(mut self, strictness: QuantityStrictness) -> Blockchain {
+        self.quantity_strictness = strictness;
+        self
+    }
+
+    // Limits the orphaned blocks kept in the archive, before the chain is shared with other threads
+    pub fn with_orphan_limit(mut self, orphan_limit: usize) -> Blockchain {
+        self.orphan_limit = orphan_limit;
+        self
+    }
+
+    // The difficulty rules of the network, e.g. for a light client to check the headers of its blocks
+    pub fn difficulty(&self) -> Difficulty {
+        self.difficulty
+    }
+
+    pub fn activations(&self) -> &[ProtocolActivation] {
+        &self.activations
+    }
+
+    pub fn quantity_strictness(&self) -> QuantityStrictness {
+        self.quantity_strictness
+    }
+
     fn create_genesis_block() -> Block {
         let index = 0;
         let nonce = 0;
@@ -63,192 +371,2607 @@ impl Blockchain {
 
         // to easily sync multiple nodes in a network, the genesis blocks must match
         // so we clear the timestamp so the hash of the genesis block is predictable
-        block.timestamp = 0;
-        block.hash = block.calculate_hash();
+        block.header.timestamp = 0;
+        block.header.hash = block.calculate_hash();
 
         block
     }
 
+    // Runs an operation on the chain from async code, on the blocking threads of tokio, so a long operation
+    // (e.g. a query of the API going through the whole chain) never stalls the tasks of the executor
+    // The panics of the operation are raised again in the caller
+    pub async fn run<T, F>(&self, operation: F) -> T
+    where
+        F: FnOnce(&Blockchain) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let blockchain = self.clone();
+        match tokio::task::spawn_blocking(move || operation(&blockchain)).await {
+            Ok(result) => result,
+            Err(error) => std::panic::resume_unwind(error.into_panic()),
+        }
+    }
+
+    // Returns the bus where the changes of the chain are published
+    pub fn event_bus(&self) -> EventBus {
+        self.event_bus.clone()
+    }
+
+    // Returns what identifies the network of this chain, derived from its genesis block
+    pub fn get_genesis_summary(&self) -> GenesisSummary {
+        let state = self.state.read().unwrap();
+
+        GenesisSummary::new(&state.blocks[0])
+    }
+
     // Returns a copy of the most recent block in the blockchain
     pub fn get_last_block(&self) -> Block {
-        let blocks = self.blocks.lock().unwrap();
+        let state = self.state.read().unwrap();
+
+        state.blocks[state.blocks.len() - 1].clone()
+    }
+
+    // Returns a copy of the block at an index of the chain, if any
+    pub fn get_block(&self, index: u64) -> Option<Block> {
+        let state = self.state.read().unwrap();
+
+        state.blocks.get(index as usize).cloned()
+    }
+
+    // Returns a copy of the block of the chain at an index with its statistics, read together
+    pub fn get_block_with_stats(&self, index: u64) -> Option<(Block, BlockStats)> {
+        let state = self.state.read().unwrap();
+
+        let block = state.blocks.get(index as usize)?.clone();
+        let stats = state.stats.get(index)?.clone();
+        Some((block, stats))
+    }
+
+    // Returns a copy of the block of the chain with a hash, if any
+    pub fn get_block_by_hash(&self, hash: &BlockHash) -> Option<Block> {
+        let state = self.state.read().unwrap();
+
+        state
+            .blocks
+            .iter()
+            .find(|block| block.header.hash == *hash)
+            .cloned()
+    }
+
+    // Returns the difficulty that the next block must meet
+    pub fn next_difficulty(&self) -> u32 {
+        let state = self.state.read().unwrap();
+
+        state.difficulties.at(state.blocks.len() as u64)
+    }
+
+    // Checks again every block of the chain, from the genesis: indexes, links, timestamps, hashes and difficulty
+    // The blocks were already checked when added, so an invalid chain means that the data was corrupted
+    // Returns the index of the first invalid block and why
+    pub fn validate_chain(&self) -> Result<(), (u64, ValidationError)> {
+        let height = self.get_last_block().header.index;
+        for index in 0..=height {
+            self.validate_block_at(index)
+                .map_err(|error| (index, error))?;
+        }
+
+        Ok(())
+    }
+
+    // Checks again a single block of the chain against the ones before it, so a long chain can be checked
+    // a few blocks at a time without holding the chain for the whole verification
+    pub fn validate_block_at(&self, index: u64) -> Result<(), ValidationError> {
+        let state = self.state.read().unwrap();
+        let blocks = &state.blocks;
+        let index = index as usize;
 
-        blocks[blocks.len() - 1].clone()
+        match index {
+            0 if blocks[0].header.hash != self.genesis_hash
+                || blocks[0].calculate_hash() != self.genesis_hash =>
+            {
+                Err(ValidationError::GenesisMismatch)
+            }
+            0 => Ok(()),
+            _ => match blocks.get(index) {
+                Some(block) => Self::check_link(&state.difficulties, &blocks[..index], block),
+                None => Err(ValidationError::InvalidIndex {
+                    expected: blocks.len() as u64,
+                    found: index as u64,
+                }),
+            },
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        match self.validate_chain() {
+            Ok(()) => true,
+            Err((index, error)) => {
+                warn!("Block {} is not valid: {}", index, error);
+                false
+            }
+        }
     }
 
     // Returns a copy of the whole list of blocks
     pub fn get_all_blocks(&self) -> BlockVec {
-        let blocks = self.blocks.lock().unwrap();
+        let state = self.state.read().unwrap();
+
+        state.blocks.clone()
+    }
+
+    // Runs a computation over all the blocks under the read lock, without copying them
+    // The new blocks wait for it, so it's only meant for the queries that the API caches
+    pub fn with_blocks<T>(&self, compute: impl FnOnce(&[Block]) -> T) -> T {
+        let state = self.state.read().unwrap();
+
+        compute(&state.blocks)
+    }
+
+    // Returns a copy of the blocks in a range of indexes, without copying the rest of the chain
+    // Indexes outside of the chain are ignored
+    pub fn get_blocks(&self, range: Range<u64>) -> BlockVec {
+        let blocks = &self.state.read().unwrap().blocks;
+        let end = (range.end as usize).min(blocks.len());
+        let start = (range.start as usize).min(end);
+
+        blocks[start..end].to_vec()
+    }
+
+    // Iterates over the headers of the blocks in a range of indexes, without loading the transactions
+    // Indexes outside of the chain are ignored
+    pub fn iter_headers(&self, range: Range<u64>) -> impl Iterator<Item = BlockHeader> {
+        let headers = &self.state.read().unwrap().headers;
+        let end = (range.end as usize).min(headers.len());
+        let start = (range.start as usize).min(end);
+
+        // the headers are copied, so the lock is not held while iterating
+        let headers_in_range: BlockHeaderVec = headers[start..end].to_vec();
+        headers_in_range.into_iter()
+    }
+
+    // Checks the headers of a chain received from a peer, before downloading its blocks
+    // They must continue from one of our blocks, link to each other and have valid hashes for the difficulty
+    // The merkle roots can only be checked once the transactions are downloaded
+    pub fn validate_headers(&self, headers: &[BlockHeader]) -> Result<(), BlockchainError> {
+        let first_index = match headers.first() {
+            Some(first) => first.index,
+            None => return Ok(()),
+        };
+        let state = self.state.read().unwrap();
+        let parent = first_index
+            .checked_sub(1)
+            .and_then(|index| state.headers.get(index as usize))
+            .ok_or(ValidationError::InvalidIndex {
+                expected: state.headers.len() as u64,
+                found: first_index,
+            })?;
+
+        // the difficulty depends on the timestamps of our blocks, then on the ones of the headers
+        let timestamp_at = |index: u64| match index < first_index {
+            true => state.headers.get(index as usize).map_or(0, |h| h.timestamp),
+            false => headers
+                .get((index - first_index) as usize)
+                .map_or(0, |h| h.timestamp),
+        };
+        let mut difficulties = state.difficulties.until(parent.index);
+
+        let validator = BlockValidator::at(chaos::now_millis());
+        let mut previous = parent;
+        for header in headers {
+            validator.validate_header(previous, header, difficulties.at(header.index))?;
+            difficulties.apply(header.index, timestamp_at);
+            previous = header;
+        }
 
-        blocks.clone()
+        Ok(())
     }
 
     // Tries to append a new block into the blockchain
     // It will validate that the values of the new block are consistend with the blockchain state
     // This operation is safe to be called concurrently from multiple threads
     pub fn add_block(&self, block: Block) -> Result<()> {
-        // the "blocks" attribute is protected by a Mutex
-        // so only one thread at a time can access the value when the lock is held
+        // the chain state is protected by a RwLock
+        // so only one thread at a time can write to it when the write lock is held, and nobody can read it
         // that prevents adding multiple valid blocks at the same time
         // preserving the correct order of indexes and hashes of the blockchain
-        let mut blocks = self.blocks.lock().unwrap();
+        let mut state = self.state.write().unwrap();
+        let blocks = &state.blocks;
         let last = &blocks[blocks.len() - 1];
 
         // check that the index is valid
-        if block.index != last.index + 1 {
-            return Err(BlockchainError::InvalidIndex.into());
+        if block.header.index != last.header.index + 1 {
+            let error = ValidationError::InvalidIndex {
+                expected: last.header.index + 1,
+                found: block.header.index,
+            };
+            // the block could still be a valid competitor of one of our blocks
+            self.try_archive_stale_fork(&state, block);
+            return Err(BlockchainError::from(error).into());
         }
 
-        // check that the previous_hash is valid
-        if block.previous_hash != last.hash {
-            return Err(BlockchainError::InvalidPreviousHash.into());
+        self.check_block(&state, &block)?;
+
+        // published while the lock is held, so the subscribers receive the blocks in order
+        self.event_bus
+            .publish(ChainEvent::BlockApplied(block.clone()));
+        state.push(block);
+
+        Ok(())
+    }
+
+    // Replaces the blocks after an ancestor by the blocks of a longer fork, e.g. the chain that the rest of the
+    // network built while this node was cut off from it
+    // The fork is validated whole before anything changes, so the chain is never left half replaced
+    // The blocks rolled back are archived, and their transactions that the fork doesn't include are returned
+    // to the pool by the miner when it receives the event
+    pub fn reorganize(&self, ancestor: u64, fork: Vec<Block>) -> Result<ReorgEvent> {
+        let mut state = self.state.write().unwrap();
+        let height = state.blocks.len() as u64 - 1;
+        if ancestor >= height {
+            return Err(BlockchainError::NotAFork(ancestor).into());
+        }
+        let fork_height = ancestor + fork.len() as u64;
+        if fork_height <= height {
+            return Err(BlockchainError::ShorterFork(fork_height, height).into());
         }
 
-        // check that the hash matches the data
-        if block.hash != block.calculate_hash() {
-            return Err(BlockchainError::InvalidHash.into());
+        // the data derived from the blocks cannot be rolled back, so it's built again up to the ancestor
+        let mut forked = ChainState::new(state.blocks[0].clone(), self.difficulty);
+        for block in state.blocks[1..=ancestor as usize].iter() {
+            forked.push(block.clone());
+        }
+        for block in fork.iter() {
+            self.check_block(&forked, block)?;
+            forked.push(block.clone());
         }
 
-        // check that the difficulty is correct
-        if block.hash.leading_zeros() < self.difficulty {
-            return Err(BlockchainError::InvalidDifficulty.into());
+        let rolled_back = state.blocks[ancestor as usize + 1..].to_vec();
+        *state = forked;
+        let reorg = ReorgSummary {
+            ancestor,
+            depth: rolled_back.len() as u64,
+        };
+        let mut orphaned_blocks = self.orphaned_blocks.write().unwrap();
+        for (block, canonical) in rolled_back.iter().zip(fork.iter()) {
+            let orphaned = OrphanedBlock::new(
+                block.clone(),
+                OrphanReason::RolledBack,
+                canonical.header.hash,
+            )
+            .with_reorg(reorg.clone());
+            self.archive(&mut orphaned_blocks, orphaned);
         }
+        warn!(
+            "Reorganized the chain after block {}: {} blocks rolled back, {} applied",
+            ancestor,
+            rolled_back.len(),
+            fork.len()
+        );
+
+        let event = ReorgEvent {
+            ancestor,
+            rolled_back,
+            applied: fork,
+        };
+        self.event_bus
+            .publish(ChainEvent::ReorgOccurred(event.clone()));
+        Ok(event)
+    }
+
+    // Checks that a block can follow the chain of a state, with all of its transactions
+    fn check_block(&self, state: &ChainState, block: &Block) -> Result<(), BlockchainError> {
+        let blocks = &state.blocks;
+        Self::check_link(&state.difficulties, blocks, block)?;
 
-        // append the block to the end
-        blocks.push(block);
+        // check that all the transactions are valid, considering the previous ones in the block
+        let mut registry = state.registry.clone();
+        let mut quantities = state.quantities.clone();
+        for (position, transaction) in block.body.transactions.iter().enumerate() {
+            let preceding = &block.body.transactions[..position];
+            let result = transaction
+                .validate()
+                .and_then(|_| Self::check_replay(&state.included, preceding, transaction))
+                .and_then(|_| {
+                    Self::check_validity(transaction, block.header.index, block.header.timestamp)
+                })
+                .and_then(|_| {
+                    Self::check_delegation(state, preceding, transaction, block.header.timestamp)
+                })
+                .and_then(|_| Self::check_lot_allocation(state, preceding, transaction))
+                .and_then(|_| {
+                    Self::check_escrow(state, preceding, transaction, block.header.timestamp)
+                })
+                .and_then(|_| Self::check_transition(state, preceding, transaction))
+                .and_then(|_| Self::check_lineage(state, preceding, transaction))
+                .and_then(|_| Self::check_recipients(state, preceding, transaction))
+                .and_then(|_| Self::check_plan(transaction, block.header.timestamp))
+                .and_then(|_| Self::check_readings(transaction, block.header.timestamp))
+                .and_then(|_| Self::check_compliance_report(state, transaction))
+                .and_then(|_| Self::check_reference(state, preceding, transaction))
+                .and_then(|_| {
+                    self.issuance_limit.check(
+                        blocks,
+                        preceding,
+                        transaction,
+                        block.header.timestamp,
+                    )
+                })
+                .and_then(|_| match self.quantity_strictness {
+                    QuantityStrictness::Reject => quantities.check(transaction),
+                    _ => Ok(()),
+                })
+                .and_then(|_| registry.check(transaction));
+            if let Err(error) = result {
+                return Err(BlockchainError::InvalidTransaction(error));
+            }
+            registry.apply(transaction);
+            quantities.apply(transaction);
+        }
 
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::model::{
-        test_util::{alice, bob},
-        Address, Transaction,
-    };
+    // Checks that a transaction would be valid if it was added now to the end of the chain
+    pub fn validate_transaction(&self, transaction: &Transaction) -> Result<(), TransactionError> {
+        self.validate_transaction_after(transaction, &[])
+    }
 
-    use super::*;
+    // Same, when other transactions not mined yet will go before it (e.g. the ones pending in the pool)
+    // They only count for the lifecycle of the batches, so a batch can be moved before its harvest is mined
+    pub fn validate_transaction_after(
+        &self,
+        transaction: &Transaction,
+        pending: &[Transaction],
+    ) -> Result<(), TransactionError> {
+        // only the miner adds its reward, to the blocks it mines
+        if transaction.is_coinbase() {
+            return Err(TransactionError::MissingSignature);
+        }
+        transaction.validate()?;
 
-    const NO_DIFFICULTY: u32 = 0;
+        let state = self.state.read().unwrap();
+        let now = chaos::now_millis();
+        // most likely included by the next block
+        let height = state.blocks.len() as u64;
+        // the same transaction pending in the pool is not an error, the pool only keeps it once
+        Self::check_replay(&state.included, &[], transaction)?;
+        Self::check_validity(transaction, height, now)?;
+        Self::check_delegation(&state, &[], transaction, now)?;
+        Self::check_lot_allocation(&state, &[], transaction)?;
+        Self::check_escrow(&state, &[], transaction, now)?;
+        Self::check_transition(&state, pending, transaction)?;
+        Self::check_lineage(&state, pending, transaction)?;
+        Self::check_recipients(&state, pending, transaction)?;
+        Self::check_plan(transaction, now)?;
+        Self::check_readings(transaction, now)?;
+        Self::check_compliance_report(&state, transaction)?;
+        Self::check_reference(&state, pending, transaction)?;
+        // the pending transactions will be mined after the chain, most likely in the next block
+        self.issuance_limit
+            .check(&state.blocks, pending, transaction, now)?;
+        if self.quantity_strictness == QuantityStrictness::Reject {
+            Self::check_quantity(&state.quantities, pending, transaction)?;
+        }
+        state.registry.check(transaction)
+    }
 
-    fn farm_address() -> Address {
-        alice()
+    // Checks that a transaction doesn't move more than what is left of its batch, whatever the strictness,
+    // e.g. to warn the submitters when the quantities are only flagged
+    pub fn check_quantity_after(
+        &self,
+        transaction: &Transaction,
+        pending: &[Transaction],
+    ) -> Result<(), TransactionError> {
+        let state = self.state.read().unwrap();
+        Self::check_quantity(&state.quantities, pending, transaction)
     }
 
-    fn warehouse_address() -> Address {
-        bob()
+    // Returns the highest sequence allocated on chain for the lots of a prefix and season (0 if none)
+    pub fn get_last_lot_sequence(&self, prefix: &str, season: &str) -> u64 {
+        let state = self.state.read().unwrap();
+
+        state
+            .lot_sequences
+            .get(&(prefix.to_string(), season.to_string()))
+            .copied()
+            .unwrap_or(0)
     }
 
-    #[test]
-    fn should_have_valid_genesis_block() {
-        let blockchain = Blockchain::new(NO_DIFFICULTY);
+    // Returns all the transactions in the chain that satisfy a condition, in chain order
+    pub fn find_transactions<P>(&self, predicate: P) -> Vec<Transaction>
+    where
+        P: Fn(&Transaction) -> bool,
+    {
+        let state = self.state.read().unwrap();
 
-        // check that a new blockchain has one and only one block
-        let blocks = blockchain.get_all_blocks();
-        assert_eq!(blocks.len(), 1);
+        state
+            .blocks
+            .iter()
+            .flat_map(|block| block.body.transactions.iter())
+            .filter(|tx| predicate(tx))
+            .cloned()
+            .collect()
+    }
 
-        // check that the last block is in the blockchain
-        let block = blockchain.get_last_block();
-        assert_eq!(block.hash, blocks[0].hash);
+    // Returns where a batch is in the supply chain, none if it has no events
+    pub fn batch_state(&self, batch_id: &str) -> Option<BatchState> {
+        let state = self.state.read().unwrap();
 
-        // check that the genesis block has valid values
-        assert_eq!(block.index, 0);
-        assert_eq!(block.nonce, 0);
-        assert_eq!(block.previous_hash, BlockHash::default());
-        assert!(block.transactions.is_empty());
+        BatchState::of(batch_id, state.batch_transactions(batch_id))
     }
 
-    #[test]
-    fn should_let_adding_valid_blocks() {
-        let blockchain = Blockchain::new(NO_DIFFICULTY);
+    // Returns all the batches that a batch comes from through the splits and merges, with the links between them
+    pub fn lineage(&self, batch_id: &str) -> BatchLineage {
+        let state = self.state.read().unwrap();
 
-        // create a valid block with agricultural transactions
-        let previous_hash = blockchain.get_last_block().hash;
-        let tx1 = Transaction {
-            sender: farm_address(),
-            recipient: warehouse_address(),
-            data: r#"{"crop": "wheat", "quantity": "500kg", "quality": "Grade A"}"#.to_string(),
-            batch_id: "WHEAT-2024-001".to_string(),
-            event_type: "HARVEST".to_string(),
+        BatchLineage::of(batch_id, state.lineage_transactions(batch_id))
+    }
+
+    // Returns the events planned for a batch in chain order, with the actual events that fulfilled them
+    pub fn plans_of(&self, batch_id: &str) -> Vec<Plan> {
+        let state = self.state.read().unwrap();
+        state.schedule.plans_of(batch_id)
+    }
+
+    // Returns the planned events of all the batches that are late at a given time, the most overdue first
+    pub fn overdue_plans(&self, now: i64) -> Vec<Plan> {
+        let state = self.state.read().unwrap();
+        state.schedule.overdue(now)
+    }
+
+    // Returns all the events of a batch in chain order, with where each one was mined
+    // The planned events are not part of it, they're only returned with the plans of the batch
+    pub fn history_for_batch(&self, batch_id: &str) -> Vec<(BlockRef, Transaction)> {
+        let state = self.state.read().unwrap();
+
+        state
+            .batches
+            .positions(batch_id)
+            .iter()
+            .map(|&(index, position)| {
+                let block = &state.blocks[index as usize];
+                let block_ref = BlockRef {
+                    index,
+                    hash: block.header.hash,
+                    timestamp: block.header.timestamp,
+                    position,
+                };
+                (block_ref, &block.body.transactions[position])
+            })
+            .filter(|(_, tx)| tx.event_type != PLANNED_EVENT)
+            .map(|(block_ref, tx)| (block_ref, tx.clone()))
+            .collect()
+    }
+
+    // Returns the transactions that involve an actor in a range of blocks, in chain order, with where each one was mined
+    pub fn transactions_for_address(
+        &self,
+        address: &Address,
+        blocks: Range<u64>,
+    ) -> Vec<(BlockRef, Transaction)> {
+        let state = self.state.read().unwrap();
+
+        state
+            .addresses
+            .positions(address, blocks)
+            .iter()
+            .map(|&(index, position)| {
+                let block = &state.blocks[index as usize];
+                let block_ref = BlockRef {
+                    index,
+                    hash: block.header.hash,
+                    timestamp: block.header.timestamp,
+                    position,
+                };
+                (block_ref, block.body.transactions[position].clone())
+            })
+            .collect()
+    }
+
+    // Returns the causal thread of a transaction in chain order: the transaction it ultimately responds to,
+    // and all the ones that respond to it, directly or through other responses (empty if not in the chain)
+    pub fn thread_of(&self, hash: &TxHash) -> Vec<(BlockRef, TxHash, Transaction)> {
+        let state = self.state.read().unwrap();
+
+        // a response always goes after what it responds to, so the root is found going back
+        let mut root = match state.included.get(hash) {
+            Some(&position) => (*hash, position),
+            None => return Vec::new(),
         };
-        let tx2 = Transaction {
-            sender: warehouse_address(),
-            recipient: farm_address(),
-            data: r#"{"vehicle": "TRUCK-42", "distance": "50km", "departure_time": "08:00"}"#.to_string(),
-            batch_id: "WHEAT-2024-001".to_string(),
-            event_type: "TRANSPORT".to_string(),
+        while let Some(reference) = state.transaction_at(root.1).in_response_to {
+            match state.included.get(&reference) {
+                Some(&position) => root = (reference, position),
+                None => break,
+            }
+        }
+
+        // and the responses are found going forward, through the index of the responses
+        let mut thread = vec![root];
+        let mut next = 0;
+        while let Some(&(tx_hash, _)) = thread.get(next) {
+            for response in state.responses.get(&tx_hash).into_iter().flatten() {
+                thread.push((*response, state.included[response]));
+            }
+            next += 1;
+        }
+        thread.sort_unstable_by_key(|&(_, position)| position);
+
+        thread
+            .into_iter()
+            .map(|(tx_hash, position)| {
+                let tx = state.transaction_at(position).clone();
+                (state.block_ref(position), tx_hash, tx)
+            })
+            .collect()
+    }
+
+    // Returns the transactions (and the index of their block) of the blocks mined since a timestamp
+    pub fn get_recent_transactions(&self, since_timestamp: i64) -> Vec<(u64, Transaction)> {
+        let state = self.state.read().unwrap();
+
+        state
+            .blocks
+            .iter()
+            .rev()
+            .take_while(|block| block.header.timestamp >= since_timestamp)
+            .flat_map(|block| {
+                let index = block.header.index;
+                block
+                    .body
+                    .transactions
+                    .iter()
+                    .map(move |tx| (index, tx.clone()))
+            })
+            .collect()
+    }
+
+    // Returns the statistics of each block in the chain, starting from an index
+    pub fn get_block_stats(&self, from_index: u64) -> Vec<BlockStats> {
+        let state = self.state.read().unwrap();
+
+        state.stats.get_series(from_index)
+    }
+
+    // Replaces the statistics of the first blocks of the chain with the stored ones, which were computed when the
+    // blocks were appended, as long as they're the statistics of the same blocks
+    // Returns the amount of statistics loaded
+    pub fn load_block_stats(&self, stored: Vec<BlockStats>) -> usize {
+        let mut state = self.state.write().unwrap();
+
+        let matches = stored.len() <= state.headers.len()
+            && stored
+                .iter()
+                .zip(state.headers.iter())
+                .all(|(stats, header)| {
+                    stats.index == header.index && stats.timestamp == header.timestamp
+                });
+        if !matches {
+            return 0;
+        }
+        let count = stored.len();
+        let computed = state.stats.get_series(count as u64);
+        state.stats = ChainStats::of(stored.into_iter().chain(computed));
+        count
+    }
+
+    // Returns the aggregated statistics of the whole chain
+    pub fn get_stats_totals(&self) -> StatsTotals {
+        let state = self.state.read().unwrap();
+
+        state.stats.get_totals()
+    }
+
+    // Returns the most recent profile published by an actor, if any
+    pub fn get_profile(&self, address: &Address) -> Option<Profile> {
+        let state = self.state.read().unwrap();
+
+        state.profiles.get(address).cloned()
+    }
+
+    // Returns a copy of the actors registered on chain and their roles
+    pub fn get_actor_registry(&self) -> ActorRegistry {
+        let state = self.state.read().unwrap();
+
+        state.registry.clone()
+    }
+
+    // Returns where the events of each batch are in the main chain, derived from its blocks
+    pub fn get_batch_index(&self) -> BatchIndex {
+        let state = self.state.read().unwrap();
+
+        state.batches.clone()
+    }
+
+    // Returns the first document announced on chain with a hash, reassembled from the chunks of the same actor
+    pub fn get_document(&self, hash: &str) -> Option<Document> {
+        let state = self.state.read().unwrap();
+        let transactions = || {
+            let positions = state.documents.get(hash).into_iter().flatten();
+            positions.map(|&position| state.transaction_at(position))
         };
-        let block = Block::new(1, 0, previous_hash, vec![tx1, tx2]);
 
-        // add it to the blockchain and check it was really added
-        let result = blockchain.add_block(block.clone());
-        assert!(result.is_ok());
+        let (transaction, manifest) = transactions()
+            .filter(|tx| tx.event_type == DOCUMENT_EVENT)
+            .find_map(|tx| Some((tx, DocumentManifest::parse(&tx.data.as_json()).ok()?)))?;
 
-        let blocks = blockchain.get_all_blocks();
-        assert_eq!(blocks.len(), 2);
+        // nobody else can complete or corrupt the document
+        let actor = transaction
+            .on_behalf_of
+            .as_ref()
+            .unwrap_or(&transaction.sender);
+        let chunks: Vec<DocumentChunk> = transactions()
+            .filter(|tx| tx.event_type == CHUNK_EVENT)
+            .filter(|tx| tx.on_behalf_of.as_ref().unwrap_or(&tx.sender) == actor)
+            .filter_map(|tx| DocumentChunk::parse(&tx.data.as_json()).ok())
+            .collect();
 
-        let last_block = blockchain.get_last_block();
-        assert_eq!(last_block.hash, block.hash);
+        Some(Document {
+            content: manifest.assemble(&chunks),
+            manifest,
+            transaction: transaction.clone(),
+        })
     }
 
-    #[test]
-    fn should_not_let_adding_block_with_invalid_index() {
-        let blockchain = Blockchain::new(NO_DIFFICULTY);
+    // Returns a copy of all the blocks that are not part of the main chain anymore, oldest first
+    pub fn get_orphaned_blocks(&self) -> OrphanedBlockVec {
+        let orphaned_blocks = self.orphaned_blocks.read().unwrap();
 
-        // create a block with invalid index
-        let invalid_index = 2;
-        let previous_hash = blockchain.get_last_block().hash;
-        let block = Block::new(invalid_index, 0, previous_hash, Vec::new());
+        orphaned_blocks.iter().cloned().collect()
+    }
 
-        // try adding the invalid block, it should return an error
-        let result = blockchain.add_block(block.clone());
-        assert_err(result, BlockchainError::InvalidIndex);
+    // Puts back the orphaned blocks archived in the storage (e.g. before a restart), oldest first
+    pub fn restore_orphaned_blocks(&self, restored: OrphanedBlockVec) {
+        let mut orphaned_blocks = self.orphaned_blocks.write().unwrap();
+        for orphaned in restored {
+            let already_archived = orphaned_blocks
+                .iter()
+                .any(|archived| archived.block.header.hash == orphaned.block.header.hash);
+            if !already_archived {
+                self.archive(&mut orphaned_blocks, orphaned);
+            }
+        }
     }
 
-    #[test]
-    fn should_not_let_adding_block_with_invalid_previous_hash() {
-        let blockchain = Blockchain::new(NO_DIFFICULTY);
+    pub fn orphan_limit(&self) -> usize {
+        self.orphan_limit
+    }
 
-        // create a block with invalid previous hash
-        let invalid_previous_hash = BlockHash::default();
-        let block = Block::new(1, 0, invalid_previous_hash, Vec::new());
+    // Returns a copy of the orphaned block with the indicated hash, if any
+    pub fn get_orphaned_block(&self, hash: &BlockHash) -> Option<OrphanedBlock> {
+        let orphaned_blocks = self.orphaned_blocks.read().unwrap();
 
-        // try adding the invalid block, it should return an error
-        let result = blockchain.add_block(block.clone());
-        assert_err(result, BlockchainError::InvalidPreviousHash);
+        orphaned_blocks
+            .iter()
+            .find(|orphaned| orphaned.block.header.hash == *hash)
+            .cloned()
     }
 
-    #[test]
-    fn should_not_let_adding_block_with_invalid_hash() {
-        let blockchain = Blockchain::new(NO_DIFFICULTY);
+    // Checks that a transaction submitted on behalf of another actor is covered by a delegation
+    // Only the most recent delegation from the actor to the sender is taken into account
+    fn check_delegation(
+        state: &ChainState,
+        preceding: &[Transaction],
+        transaction: &Transaction,
+        timestamp: i64,
+    ) -> Result<(), TransactionError> {
+        let actor = match &transaction.on_behalf_of {
+            Some(actor) if *actor != transaction.sender => actor,
+            _ => return Ok(()),
+        };
 
-        // create a block with invalid hash
-        let previous_hash = blockchain.get_last_block().hash;
-        let mut block = Block::new(1, 0, previous_hash, Vec::new());
-        block.hash = BlockHash::default();
+        // the delegations earlier in the block replace the ones of the chain
+        let delegation = match preceding.iter().rev().find(|tx| {
+            tx.event_type == DELEGATION_EVENT
+                && tx.sender == *actor
+                && tx.recipient == transaction.sender
+        }) {
+            Some(tx) => Delegation::parse(&tx.data.as_json()).ok(),
+            None => state
+                .delegations
+                .get(&(actor.clone(), transaction.sender.clone()))
+                .cloned(),
+        };
 
-        // try adding the invalid block, it should return an error
-        let result = blockchain.add_block(block.clone());
-        assert_err(result, BlockchainError::InvalidHash);
+        match delegation {
+            Some(delegation) if delegation.authorizes(transaction, timestamp) => Ok(()),
+            _ => Err(TransactionError::NotDelegated(actor.clone())),
+        }
     }
 
-    #[test]
-    fn should_not_let_adding_block_with_invalid_difficulty() {
-        // set up a blockchain with an insane difficulty
-        let difficulty: u32 = 30;
-        let blockchain = Blockchain::new(difficulty);
+    // Checks that a lot identifier is allocated only once, by the first LOT event that claims it
+    fn check_lot_allocation(
+        state: &ChainState,
+        preceding: &[Transaction],
+        transaction: &Transaction,
+    ) -> Result<(), TransactionError> {
+        if transaction.event_type != LOT_EVENT {
+            return Ok(());
+        }
 
-        // create a valid block
-        let previous_hash = blockchain.get_last_block().hash;
-        let block = Block::new(1, 0, previous_hash, Vec::new());
+        let already_allocated = state
+            .batch_transactions(&transaction.batch_id)
+            .into_iter()
+            .chain(preceding.iter())
+            .any(|tx| tx.event_type == LOT_EVENT && tx.batch_id == transaction.batch_id);
+        if already_allocated {
+            return Err(TransactionError::LotAlreadyAllocated(
+                transaction.batch_id.clone(),
+            ));
+        }
 
-        // ensure that the hash actually does NOT meet the difficulty
-        assert!(block.hash.leading_zeros() < difficulty);
+        Ok(())
+    }
 
-        // try adding the invalid block, it should return an error
-        let result = blockchain.add_block(block.clone());
-        assert_err(result, BlockchainError::InvalidDifficulty);
+    // Checks the transitions of a batch in escrow, considering only the most recent escrow of the batch:
+    // it can't be escrowed again while pending, and its recipient can't act on it unless it was released
+    fn check_escrow(
+        state: &ChainState,
+        preceding: &[Transaction],
+        transaction: &Transaction,
+        timestamp: i64,
+    ) -> Result<(), TransactionError> {
+        if transaction.batch_id.is_empty() {
+            return Ok(());
+        }
+
+        if transaction.event_type == ESCROW_EVENT {
+            let escrow = Escrow::parse(&transaction.data.as_json())?;
+            if escrow.deadline <= timestamp {
+                return Err(TransactionError::InvalidEscrow);
+            }
+        }
+
+        // the events of the batch with the time they were recorded
+        let events: Vec<(i64, &Transaction)> = state
+            .batches
+            .positions(&transaction.batch_id)
+            .iter()
+            .map(|&(index, position)| {
+                let block = &state.blocks[index as usize];
+                (block.header.timestamp, &block.body.transactions[position])
+            })
+            .chain(preceding.iter().map(|tx| (timestamp, tx)))
+            .filter(|(_, tx)| tx.batch_id == transaction.batch_id)
+            .collect();
+        let position = match events
+            .iter()
+            .rposition(|(_, tx)| tx.event_type == ESCROW_EVENT)
+        {
+            Some(position) => position,
+            None => return Ok(()),
+        };
+        let escrow_tx = events[position].1;
+        let escrow = match Escrow::parse(&escrow_tx.data.as_json()) {
+            Ok(escrow) => escrow,
+            Err(_) => return Ok(()),
+        };
+        let state = escrow.state(events[position + 1..].iter().copied(), timestamp);
+
+        let batch_id = transaction.batch_id.clone();
+        if transaction.event_type == ESCROW_EVENT && state == EscrowState::Pending {
+            return Err(TransactionError::EscrowPending(batch_id));
+        }
+        let actor = transaction
+            .on_behalf_of
+            .as_ref()
+            .unwrap_or(&transaction.sender);
+        let is_recipient = *actor == escrow_tx.recipient && *actor != escrow.inspector;
+        if is_recipient && state != EscrowState::Released {
+            return Err(TransactionError::EscrowNotReleased(batch_id));
+        }
+
+        Ok(())
+    }
+
+    // Checks that a lifecycle event is a possible transition from the state of its batch
+    // (e.g. no PROCESSING before the HARVEST, nor a STORAGE after the SALE)
+    fn check_transition(
+        state: &ChainState,
+        preceding: &[Transaction],
+        transaction: &Transaction,
+    ) -> Result<(), TransactionError> {
+        if !transaction.event_type.is_lifecycle() || transaction.batch_id.is_empty() {
+            return Ok(());
+        }
+
+        let transactions = state
+            .batch_transactions(&transaction.batch_id)
+            .into_iter()
+            .chain(preceding.iter());
+        let state =
+            BatchState::of(&transaction.batch_id, transactions).unwrap_or(BatchState::Created);
+        match state.next(&transaction.event_type) {
+            Some(_) => Ok(()),
+            None => Err(TransactionError::InvalidTransition(
+                transaction.batch_id.clone(),
+                state.to_string(),
+                transaction.event_type.to_string(),
+            )),
+        }
+    }
+
+    // Checks that a split or merge creates new batches, from batches still in the supply chain
+    // (e.g. no split of a sold batch, nor a merge into a batch that already has events)
+    fn check_lineage(
+        state: &ChainState,
+        preceding: &[Transaction],
+        transaction: &Transaction,
+    ) -> Result<(), TransactionError> {
+        let links = LineageLink::of(transaction);
+        if links.is_empty() {
+            return Ok(());
+        }
+
+        let transactions = |batch_id: &str| {
+            state
+                .batch_transactions(batch_id)
+                .into_iter()
+                .chain(preceding.iter())
+        };
+        let new_batches: BTreeSet<&String> = links.iter().map(|link| &link.child).collect();
+        if let Some(existing) = new_batches
+            .into_iter()
+            .find(|batch_id| BatchState::of(batch_id, transactions(batch_id)).is_some())
+        {
+            return Err(TransactionError::BatchAlreadyExists(existing.clone()));
+        }
+
+        let parents: BTreeSet<&String> = links.iter().map(|link| &link.parent).collect();
+        for parent in parents.into_iter() {
+            let state = BatchState::of(parent, transactions(parent)).unwrap_or(BatchState::Created);
+            if !state.is_active() {
+                return Err(TransactionError::InvalidTransition(
+                    parent.clone(),
+                    state.to_string(),
+                    transaction.event_type.to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Checks that the other recipients of an event held its batch or a batch that comes from it
+    fn check_recipients(
+        state: &ChainState,
+        preceding: &[Transaction],
+        transaction: &Transaction,
+    ) -> Result<(), TransactionError> {
+        if transaction.recipients.is_empty() {
+            return Ok(());
+        }
+
+        let transactions = state
+            .family_transactions(&transaction.batch_id)
+            .into_iter()
+            .chain(preceding.iter());
+        let custodians = custodians_of(&transaction.batch_id, transactions);
+        match transaction
+            .recipients
+            .iter()
+            .find(|recipient| !custodians.contains(*recipient))
+        {
+            Some(recipient) => Err(TransactionError::NotACustodian(
+                recipient.clone(),
+                transaction.batch_id.clone(),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    // Checks that a transaction signed in advance is within its validity window in the block that includes it
+    fn check_validity(
+        transaction: &Transaction,
+        height: u64,
+        timestamp: i64,
+    ) -> Result<(), TransactionError> {
+        match &transaction.validity {
+            Some(validity) => validity.check(height, timestamp),
+            None => Ok(()),
+        }
+    }
+
+    // Checks that a planned event is expected after the block that records it
+    fn check_plan(transaction: &Transaction, timestamp: i64) -> Result<(), TransactionError> {
+        if transaction.event_type != PLANNED_EVENT {
+            return Ok(());
+        }
+
+        let planned = PlannedEvent::parse(&transaction.data.as_json())?;
+        match planned.due > timestamp {
+            true => Ok(()),
+            false => Err(TransactionError::InvalidPlan),
+        }
+    }
+
+    // Checks that a compliance report is the one that the blocks it covers give
+    // Only the events of the batch up to the last block covered are read again, through the index of the batches
+    fn check_compliance_report(
+        state: &ChainState,
+        transaction: &Transaction,
+    ) -> Result<(), TransactionError> {
+        if transaction.event_type != COMPLIANCE_REPORT_EVENT {
+            return Ok(());
+        }
+
+        let report = ComplianceReport::parse(&transaction.data.as_json())?;
+        if report.until_block >= state.blocks.len() as u64 {
+            return Err(TransactionError::InvalidComplianceReport);
+        }
+        let positions = state
+            .batches
+            .positions(&report.batch_id)
+            .iter()
+            .filter(|(index, _)| *index <= report.until_block)
+            .copied()
+            .collect();
+        let scanned = ComplianceReport::of(
+            &report.batch_id,
+            state.transactions_at(positions),
+            report.until_block,
+            &report.thresholds,
+        );
+        match scanned == report {
+            true => Ok(()),
+            false => Err(TransactionError::InvalidComplianceReport),
+        }
+    }
+
+    // Checks that the readings of a device were taken before the block that records them
+    fn check_readings(transaction: &Transaction, timestamp: i64) -> Result<(), TransactionError> {
+        if transaction.event_type != SENSOR_READINGS_EVENT {
+            return Ok(());
+        }
+
+        let readings = SensorReadings::parse(&transaction.data.as_json())?;
+        match readings
+            .readings
+            .iter()
+            .all(|reading| reading.recorded_at <= timestamp)
+        {
+            true => Ok(()),
+            false => Err(TransactionError::InvalidSensorReadings),
+        }
+    }
+
+    // Checks that an event doesn't move more than what is left of the batches after the chain and the pending events
+    fn check_quantity(
+        quantities: &QuantityLedger,
+        pending: &[Transaction],
+        transaction: &Transaction,
+    ) -> Result<(), TransactionError> {
+        if transaction.batch_id.is_empty() {
+            return Ok(());
+        }
+
+        let mut quantities = quantities.clone();
+        for pending_transaction in pending.iter() {
+            quantities.apply(pending_transaction);
+        }
+        quantities.check(transaction)
+    }
+
+    // Checks that a transaction is neither in the chain nor before it in its block, so it's only mined once
+    // e.g. a signed harvest submitted again cannot count twice
+    // The coinbase transactions are left out, a miner gets the same one in each of its blocks
+    fn check_replay(
+        included: &HashMap<TxHash, TxPosition>,
+        preceding: &[Transaction],
+        transaction: &Transaction,
+    ) -> Result<(), TransactionError> {
+        if transaction.is_coinbase() {
+            return Ok(());
+        }
+
+        let hash = transaction_hash(transaction);
+        match included.contains_key(&hash)
+            || preceding.iter().any(|tx| transaction_hash(tx) == hash)
+        {
+            true => Err(TransactionError::Replayed(hash)),
+            false => Ok(()),
+        }
+    }
+
+    // Checks that the transaction that another one responds to goes before it
+    // The hashes of the chain are already indexed to refuse the replays, so the blocks are not scanned
+    fn check_reference(
+        state: &ChainState,
+        preceding: &[Transaction],
+        transaction: &Transaction,
+    ) -> Result<(), TransactionError> {
+        let reference = match transaction.in_response_to {
+            Some(reference) => reference,
+            None => return Ok(()),
+        };
+
+        let found = state.included.contains_key(&reference)
+            || preceding.iter().any(|tx| transaction_hash(tx) == reference);
+        match found {
+            true => Ok(()),
+            false => Err(TransactionError::UnknownReference(reference)),
+        }
+    }
+
+    // Checks that a block can follow the previous blocks of the chain, the state only matters for its transactions
+    // The previous blocks are the ones of the chain of the difficulties, or the first ones of it
+    fn check_link(
+        difficulties: &DifficultyPeriods,
+        previous_blocks: &[Block],
+        block: &Block,
+    ) -> Result<(), ValidationError> {
+        let last = &previous_blocks[previous_blocks.len() - 1];
+
+        // the difficulty depends on the timestamps of the previous blocks
+        let required = difficulties.at(block.header.index);
+        BlockValidator::at(chaos::now_millis()).validate_block(&last.header, block, required)
+    }
+
+    // Archives the block if it's a valid block for a height that we already have
+    // That happens when another node mined a block at the same time as us, and our block won
+    fn try_archive_stale_fork(&self, state: &ChainState, block: Block) {
+        let blocks = &state.blocks;
+        let index = block.header.index as usize;
+        if index == 0 || index >= blocks.len() {
+            return;
+        }
+
+        // it must be a valid successor of our block at the previous height
+        let canonical = &blocks[index];
+        let is_stale_fork = block.header.hash != canonical.header.hash
+            && Self::check_link(&state.difficulties, &blocks[..index], &block).is_ok();
+        if !is_stale_fork {
+            return;
+        }
+
+        let mut orphaned_blocks = self.orphaned_blocks.write().unwrap();
+        let already_archived = orphaned_blocks
+            .iter()
+            .any(|orphaned| orphaned.block.header.hash == block.header.hash);
+        if !already_archived {
+            info!("Archived stale fork block {}", block.header.index);
+            let orphaned =
+                OrphanedBlock::new(block, OrphanReason::StaleFork, canonical.header.hash);
+            self.event_bus
+                .publish(ChainEvent::ForkArchived(orphaned.clone()));
+            self.archive(&mut orphaned_blocks, orphaned);
+        }
+    }
+
+    // Adds a block to the archive, dropping the oldest ones past the limit
+    fn archive(&self, orphaned_blocks: &mut VecDeque<OrphanedBlock>, orphaned: OrphanedBlock) {
+        orphaned_blocks.push_back(orphaned);
+        while orphaned_blocks.len() > self.orphan_limit {
+            orphaned_blocks.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::{
+        fixtures::{self, alice, bob, carol, sign, Persona},
+        Address, ComplianceThresholds, Role, Transaction, ValidityWindow, COINBASE_BATCH_ID,
+        REGISTRATION_EVENT,
+    };
+
+    use ed25519_dalek::SigningKey;
+
+    use super::*;
+
+    const NO_DIFFICULTY: u32 = 0;
+
+    fn farm_address() -> Address {
+        alice()
+    }
+
+    fn warehouse_address() -> Address {
+        bob()
+    }
+
+    #[test]
+    fn should_have_valid_genesis_block() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+
+        // check that a new blockchain has one and only one block
+        let blocks = blockchain.get_all_blocks();
+        assert_eq!(blocks.len(), 1);
+
+        // check that the last block is in the blockchain
+        let block = blockchain.get_last_block();
+        assert_eq!(block.header.hash, blocks[0].header.hash);
+
+        // check that the genesis block has valid values
+        assert_eq!(block.header.index, 0);
+        assert_eq!(block.header.nonce, 0);
+        assert_eq!(block.header.previous_hash, BlockHash::default());
+        assert!(block.body.transactions.is_empty());
+    }
+
+    #[test]
+    fn should_let_adding_valid_blocks() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+
+        // create a valid block with agricultural transactions
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let tx1 = Transaction {
+            sender: farm_address(),
+            recipient: warehouse_address(),
+            data: r#"{"crop": "wheat", "quantity": "500kg", "quality": "Grade A"}"#.into(),
+            batch_id: "WHEAT-2024-001".to_string(),
+            event_type: "HARVEST".into(),
+            ..Default::default()
+        };
+        let tx2 = Transaction {
+            sender: warehouse_address(),
+            recipient: farm_address(),
+            data: r#"{"vehicle": "TRUCK-42", "distance": "50km", "departure_time": "08:00"}"#
+                .into(),
+            batch_id: "WHEAT-2024-001".to_string(),
+            event_type: "TRANSPORT".into(),
+            ..Default::default()
+        };
+        let block = Block::new(1, 0, previous_hash, vec![sign(tx1), sign(tx2)]);
+
+        // add it to the blockchain and check it was really added
+        let result = blockchain.add_block(block.clone());
+        assert!(result.is_ok());
+
+        let blocks = blockchain.get_all_blocks();
+        assert_eq!(blocks.len(), 2);
+
+        // the header is available on its own
+        let headers: Vec<BlockHeader> = blockchain.iter_headers(1..10).collect();
+        assert_eq!(headers, vec![block.header.clone()]);
+        // and so is a range of blocks
+        let range = blockchain.get_blocks(1..10);
+        assert_eq!(range.len(), 1);
+        assert_eq!(range[0].header.hash, block.header.hash);
+        assert!(blockchain.get_blocks(5..10).is_empty());
+
+        let last_block = blockchain.get_last_block();
+        assert_eq!(last_block.header.hash, block.header.hash);
+
+        // the transactions can be queried
+        let harvests = blockchain.find_transactions(|tx| tx.event_type == "HARVEST");
+        assert_eq!(harvests.len(), 1);
+        assert_eq!(harvests[0].batch_id, "WHEAT-2024-001");
+
+        // and the history of a batch tells where each of its events was mined
+        let history = blockchain.history_for_batch("WHEAT-2024-001");
+        let positions: Vec<(u64, usize)> = history
+            .iter()
+            .map(|(block_ref, _)| (block_ref.index, block_ref.position))
+            .collect();
+        assert_eq!(positions, vec![(1, 0), (1, 1)]);
+        assert_eq!(history[1].1.event_type, "TRANSPORT");
+        assert!(blockchain.history_for_batch("CORN-2024-001").is_empty());
+
+        // the statistics of the new block are recorded
+        let block_stats = blockchain.get_block_stats(1);
+        assert_eq!(block_stats.len(), 1);
+        assert_eq!(block_stats[0].transaction_count, 2);
+        assert_eq!(blockchain.get_stats_totals().blocks, 2);
+    }
+
+    #[test]
+    fn should_not_let_adding_block_with_invalid_index() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+
+        // create a block with invalid index
+        let invalid_index = 2;
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(invalid_index, 0, previous_hash, Vec::new());
+
+        // try adding the invalid block, it should return an error
+        let result = blockchain.add_block(block.clone());
+        let error = ValidationError::InvalidIndex {
+            expected: 1,
+            found: 2,
+        };
+        assert_err(result, BlockchainError::InvalidBlock(error));
+    }
+
+    #[test]
+    fn should_not_let_adding_block_with_invalid_previous_hash() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+
+        // create a block with invalid previous hash
+        let invalid_previous_hash = BlockHash::default();
+        let block = Block::new(1, 0, invalid_previous_hash, Vec::new());
+
+        // try adding the invalid block, it should return an error
+        let result = blockchain.add_block(block.clone());
+        assert_err(
+            result,
+            BlockchainError::InvalidBlock(ValidationError::InvalidPreviousHash),
+        );
+    }
+
+    #[test]
+    fn should_not_let_adding_block_with_invalid_hash() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+
+        // create a block with invalid hash
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let mut block = Block::new(1, 0, previous_hash, Vec::new());
+        block.header.hash = BlockHash::default();
+
+        // try adding the invalid block, it should return an error
+        let result = blockchain.add_block(block.clone());
+        assert_err(
+            result,
+            BlockchainError::InvalidBlock(ValidationError::InvalidHash),
+        );
+    }
+
+    #[test]
+    fn should_not_let_adding_block_with_invalid_merkle_root() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+
+        // transactions swapped after hashing the block
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let mut block = Block::new(1, 0, previous_hash, Vec::new());
+        block.body.transactions = vec![Transaction::default()];
+
+        let result = blockchain.add_block(block.clone());
+        assert_err(
+            result,
+            BlockchainError::InvalidBlock(ValidationError::InvalidMerkleRoot),
+        );
+    }
+
+    #[test]
+    fn should_not_let_adding_block_with_invalid_difficulty() {
+        // set up a blockchain with an insane difficulty
+        let difficulty: u32 = 30;
+        let blockchain = Blockchain::new(difficulty);
+
+        // create a valid block
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(1, 0, previous_hash, Vec::new());
+
+        // ensure that the hash actually does NOT meet the difficulty
+        assert!(block.header.hash.leading_zeros() < difficulty);
+
+        // try adding the invalid block, it should return an error
+        let result = blockchain.add_block(block.clone());
+        let error = ValidationError::InvalidDifficulty {
+            required: difficulty,
+            found: block.header.hash.leading_zeros(),
+        };
+        assert_err(result, BlockchainError::InvalidBlock(error));
+    }
+
+    #[test]
+    fn should_adjust_the_difficulty_to_the_block_times() {
+        // periods of 2 blocks, aiming for a block per second
+        let blockchain = Blockchain::with_difficulty(Difficulty::new(0, 2, 1000));
+
+        // the first period takes a millisecond, so much more work is needed
+        for timestamp in [1000, 1001] {
+            let block = create_block_at(&blockchain, timestamp, |_| true);
+            blockchain.add_block(block).unwrap();
+        }
+        assert_eq!(blockchain.next_difficulty(), 2);
+
+        let easy_block = create_block_at(&blockchain, 2000, |hash| hash.leading_zeros() < 2);
+        let error = ValidationError::InvalidDifficulty {
+            required: 2,
+            found: easy_block.header.hash.leading_zeros(),
+        };
+        let result = blockchain.add_block(easy_block);
+        assert_err(result, BlockchainError::InvalidBlock(error));
+
+        let block = create_block_at(&blockchain, 2000, |hash| hash.leading_zeros() >= 2);
+        assert!(blockchain.add_block(block).is_ok());
+    }
+
+    #[test]
+    fn should_validate_the_headers_of_a_chain() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let genesis_hash = blockchain.get_last_block().header.hash;
+        let first = Block::new(1, 0, genesis_hash, Vec::new());
+        let second = Block::new(2, 0, first.header.hash, Vec::new());
+        let headers = vec![first.header.clone(), second.header.clone()];
+        assert_eq!(blockchain.validate_headers(&headers), Ok(()));
+
+        // the headers must continue our chain
+        assert_eq!(
+            blockchain.validate_headers(&headers[1..]),
+            Err(BlockchainError::InvalidBlock(
+                ValidationError::InvalidIndex {
+                    expected: 1,
+                    found: 2
+                }
+            ))
+        );
+
+        let mut tampered = headers.clone();
+        tampered[1].nonce += 1;
+        assert_eq!(
+            blockchain.validate_headers(&tampered),
+            Err(BlockchainError::InvalidBlock(ValidationError::InvalidHash))
+        );
+
+        let unlinked = Block::new(2, 0, BlockHash::from(1), Vec::new());
+        assert_eq!(
+            blockchain.validate_headers(&[first.header, unlinked.header.clone()]),
+            Err(BlockchainError::InvalidBlock(
+                ValidationError::InvalidPreviousHash
+            ))
+        );
+
+        // and meet the difficulty
+        let hard_blockchain = Blockchain::new(30);
+        let easy_header = Block::new(
+            1,
+            0,
+            hard_blockchain.get_last_block().header.hash,
+            Vec::new(),
+        )
+        .header
+        .clone();
+        let error = ValidationError::InvalidDifficulty {
+            required: 30,
+            found: easy_header.hash.leading_zeros(),
+        };
+        assert_eq!(
+            hard_blockchain.validate_headers(&[easy_header]),
+            Err(BlockchainError::InvalidBlock(error))
+        );
+    }
+
+    #[test]
+    fn should_not_let_adding_block_with_invalid_transactions() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+
+        // a farm cannot publish the profile of a warehouse
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let tx = create_profile_transaction(&SigningKey::from_bytes(&[7; 32]), "Warehouse A");
+        let mut forged_tx = tx.clone();
+        forged_tx.recipient = warehouse_address();
+        forged_tx.sign(&SigningKey::from_bytes(&[7; 32])).unwrap();
+        let block = Block::new(1, 0, previous_hash, vec![forged_tx]);
+
+        let result = blockchain.add_block(block);
+        assert_err(
+            result,
+            BlockchainError::InvalidTransaction(TransactionError::ProfileNotSelfPublished),
+        );
+    }
+
+    #[test]
+    fn should_not_mine_a_transaction_twice() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let dispute = sign(Transaction {
+            sender: farm_address(),
+            recipient: warehouse_address(),
+            data: r#"{"reason": "short delivery"}"#.into(),
+            batch_id: "WHEAT-001".to_string(),
+            event_type: "DISPUTE".into(),
+            ..Default::default()
+        });
+        let coinbase = Transaction::coinbase(farm_address());
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(1, 0, previous_hash, vec![coinbase.clone(), dispute.clone()]);
+        blockchain.add_block(block).unwrap();
+
+        // neither submitted again nor included in another block, only the coinbase is repeated
+        let hash = transaction_hash(&dispute);
+        assert_eq!(
+            blockchain.validate_transaction(&dispute),
+            Err(TransactionError::Replayed(hash))
+        );
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let replay = Block::new(2, 0, previous_hash, vec![coinbase.clone(), dispute.clone()]);
+        assert_err(
+            blockchain.add_block(replay),
+            BlockchainError::InvalidTransaction(TransactionError::Replayed(hash)),
+        );
+
+        // the same dispute again is another transaction with another nonce, but only once per block
+        let again = sign(Transaction {
+            nonce: Some(1),
+            ..dispute
+        });
+        assert_eq!(blockchain.validate_transaction(&again), Ok(()));
+        let twice = Block::new(2, 0, previous_hash, vec![again.clone(), again.clone()]);
+        assert_err(
+            blockchain.add_block(twice),
+            BlockchainError::InvalidTransaction(TransactionError::Replayed(transaction_hash(
+                &again,
+            ))),
+        );
+        let block = Block::new(2, 0, previous_hash, vec![coinbase, again]);
+        blockchain.add_block(block).unwrap();
+    }
+
+    #[test]
+    fn should_keep_the_coinbase_out_of_the_histories() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let dispute = sign(Transaction {
+            sender: farm_address(),
+            recipient: warehouse_address(),
+            data: r#"{"reason": "short delivery"}"#.into(),
+            batch_id: "WHEAT-001".to_string(),
+            event_type: "DISPUTE".into(),
+            ..Default::default()
+        });
+
+        // the reward of the miner carries nothing but the miner
+        let coinbase = Transaction::coinbase(warehouse_address());
+        let loaded = Transaction {
+            data: "Block mined by the warehouse".into(),
+            ..coinbase.clone()
+        };
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(1, 0, previous_hash, vec![loaded, dispute.clone()]);
+        assert_err(
+            blockchain.add_block(block),
+            BlockchainError::InvalidTransaction(TransactionError::InvalidCoinbase),
+        );
+
+        // and is neither an event of a batch nor of the miner
+        let block = Block::new(1, 0, previous_hash, vec![coinbase, dispute]);
+        blockchain.add_block(block).unwrap();
+        assert!(blockchain.history_for_batch(COINBASE_BATCH_ID).is_empty());
+        assert_eq!(blockchain.history_for_batch("WHEAT-001").len(), 1);
+        let positions: Vec<usize> = blockchain
+            .transactions_for_address(&warehouse_address(), 0..u64::MAX)
+            .into_iter()
+            .map(|(block_ref, _)| block_ref.position)
+            .collect();
+        assert_eq!(positions, vec![1]);
+    }
+
+    #[test]
+    fn should_not_mine_a_signed_transaction_twice() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let mut transfer = Transaction {
+            sender: Address::from(key.verifying_key().to_bytes()),
+            recipient: warehouse_address(),
+            batch_id: "WHEAT-001".to_string(),
+            event_type: "TRANSFER".into(),
+            ..Default::default()
+        };
+        transfer.sign(&key).unwrap();
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(1, 0, previous_hash, vec![transfer.clone()]);
+        blockchain.add_block(block).unwrap();
+
+        // the same signature spelled in upper case would otherwise be another transaction
+        let upper_cased = Transaction {
+            signature: transfer.signature.as_ref().map(|s| s.to_uppercase()),
+            ..transfer.clone()
+        };
+        assert_ne!(transaction_hash(&upper_cased), transaction_hash(&transfer));
+        assert_eq!(
+            blockchain.validate_transaction(&upper_cased),
+            Err(TransactionError::InvalidSignature)
+        );
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let replay = Block::new(2, 0, previous_hash, vec![upper_cased]);
+        assert_err(
+            blockchain.add_block(replay),
+            BlockchainError::InvalidBlock(ValidationError::InvalidSignature {
+                position: 0,
+                error: TransactionError::InvalidSignature,
+            }),
+        );
+    }
+
+    #[test]
+    fn should_return_latest_profile() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let farm = Address::from(key.verifying_key().to_bytes());
+        assert!(blockchain.get_profile(&farm).is_none());
+
+        // publish a profile and then update it in a later block
+        for (index, name) in ["Green Valley", "Green Valley Farm"].iter().enumerate() {
+            let previous_hash = blockchain.get_last_block().header.hash;
+            let tx = create_profile_transaction(&key, name);
+            let block = Block::new(index as u64 + 1, 0, previous_hash, vec![tx]);
+            blockchain.add_block(block).unwrap();
+        }
+
+        // but nobody else can overwrite it
+        let mut forged_tx = create_profile_transaction(&key, "Stolen Farm");
+        forged_tx.signature = None;
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let result = blockchain.add_block(Block::new(3, 0, previous_hash, vec![forged_tx]));
+        assert_err(result, missing_signature(0));
+
+        let profile = blockchain.get_profile(&farm).unwrap();
+        assert_eq!(profile.display_name, "Green Valley Farm");
+        assert!(blockchain.get_profile(&warehouse_address()).is_none());
+
+        // the update is undone when its block is rolled back
+        let ancestor_hash = blockchain.get_block(1).unwrap().header.hash;
+        let second = Block::new(2, 1, ancestor_hash, Vec::new());
+        let third = Block::new(3, 1, second.header.hash, Vec::new());
+        blockchain.reorganize(1, vec![second, third]).unwrap();
+        let profile = blockchain.get_profile(&farm).unwrap();
+        assert_eq!(profile.display_name, "Green Valley");
+    }
+
+    #[test]
+    fn should_archive_stale_fork_blocks() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let genesis_hash = blockchain.get_last_block().header.hash;
+
+        // add a valid block at height 1
+        let block = Block::new(1, 0, genesis_hash, Vec::new());
+        blockchain.add_block(block.clone()).unwrap();
+
+        // a competing block for the same height is rejected...
+        let competing_block = Block::new(1, 1, genesis_hash, Vec::new());
+        let result = blockchain.add_block(competing_block.clone());
+        let error = ValidationError::InvalidIndex {
+            expected: 2,
+            found: 1,
+        };
+        assert_err(result, BlockchainError::InvalidBlock(error));
+
+        // ...but kept in the archive, only once
+        blockchain.add_block(competing_block.clone()).unwrap_err();
+        let orphaned_blocks = blockchain.get_orphaned_blocks();
+        assert_eq!(orphaned_blocks.len(), 1);
+
+        let orphaned = blockchain
+            .get_orphaned_block(&competing_block.header.hash)
+            .unwrap();
+        assert_eq!(orphaned.reason, OrphanReason::StaleFork);
+        assert_eq!(orphaned.fork_height, 1);
+        assert_eq!(orphaned.canonical_hash, block.header.hash);
+        assert_eq!(orphaned.reorg, None);
+    }
+
+    #[test]
+    fn should_keep_only_the_most_recent_orphaned_blocks() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY).with_orphan_limit(2);
+        let genesis_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(1, 0, genesis_hash, Vec::new());
+        blockchain.add_block(block).unwrap();
+
+        let competing_blocks: Vec<_> = (1..=3)
+            .map(|nonce| Block::new(1, nonce, genesis_hash, Vec::new()))
+            .collect();
+        for competing_block in competing_blocks.iter() {
+            blockchain.add_block(competing_block.clone()).unwrap_err();
+        }
+
+        let archived: Vec<_> = blockchain
+            .get_orphaned_blocks()
+            .iter()
+            .map(|orphaned| orphaned.block.header.hash)
+            .collect();
+        let expected: Vec<_> = competing_blocks[1..]
+            .iter()
+            .map(|block| block.header.hash)
+            .collect();
+        assert_eq!(archived, expected);
+    }
+
+    #[test]
+    fn should_not_archive_unrelated_invalid_blocks() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let genesis_hash = blockchain.get_last_block().header.hash;
+        blockchain
+            .add_block(Block::new(1, 0, genesis_hash, Vec::new()))
+            .unwrap();
+
+        // a block that does not follow our chain is not a fork of it
+        let unrelated_block = Block::new(1, 1, BlockHash::default(), Vec::new());
+        blockchain.add_block(unrelated_block.clone()).unwrap_err();
+
+        // a block with a tampered hash is not valid at all
+        let mut tampered_block = Block::new(1, 2, genesis_hash, Vec::new());
+        tampered_block.header.hash = BlockHash::from(1);
+        blockchain.add_block(tampered_block).unwrap_err();
+
+        assert!(blockchain.get_orphaned_blocks().is_empty());
+        assert!(blockchain
+            .get_orphaned_block(&unrelated_block.header.hash)
+            .is_none());
+    }
+
+    #[test]
+    fn should_validate_delegated_transactions() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let farm_key = SigningKey::from_bytes(&[1; 32]);
+        let farm = Address::from(farm_key.verifying_key().to_bytes());
+        let gateway_key = SigningKey::from_bytes(&[2; 32]);
+        let gateway_address = Address::from(gateway_key.verifying_key().to_bytes());
+        let signed = |mut transaction: Transaction, key: &SigningKey| {
+            transaction.sign(key).unwrap();
+            transaction
+        };
+
+        // the gateway cannot act for the farm without a delegation
+        let harvest_tx = Transaction {
+            sender: gateway_address.clone(),
+            recipient: warehouse_address(),
+            data: r#"{"crop": "wheat", "quantity": "500kg"}"#.into(),
+            batch_id: "WHEAT-2024-001".to_string(),
+            event_type: "HARVEST".into(),
+            on_behalf_of: Some(farm.clone()),
+            ..Default::default()
+        };
+        assert_eq!(
+            blockchain.validate_transaction(&signed(harvest_tx.clone(), &gateway_key)),
+            Err(TransactionError::NotDelegated(farm.clone()))
+        );
+
+        // nor anyone pretend to be the gateway or the farm, both sign their part
+        let delegation_tx = Transaction {
+            sender: farm.clone(),
+            recipient: gateway_address,
+            data: r#"{"event_types": ["HARVEST"], "expires_at": 4102444800000}"#.into(),
+            batch_id: String::new(),
+            event_type: DELEGATION_EVENT.into(),
+            ..Default::default()
+        };
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let forged = Block::new(
+            1,
+            0,
+            previous_hash,
+            vec![
+                delegation_tx.clone(),
+                signed(harvest_tx.clone(), &gateway_key),
+            ],
+        );
+        assert_err(blockchain.add_block(forged), missing_signature(0));
+        let forged = Block::new(
+            1,
+            0,
+            previous_hash,
+            vec![signed(delegation_tx.clone(), &farm_key), harvest_tx.clone()],
+        );
+        assert_err(blockchain.add_block(forged), missing_signature(1));
+
+        // the delegation can be in the same block, before the delegated transaction
+        let block = Block::new(
+            1,
+            0,
+            previous_hash,
+            vec![
+                signed(delegation_tx.clone(), &farm_key),
+                signed(harvest_tx.clone(), &gateway_key),
+            ],
+        );
+        blockchain.add_block(block).unwrap();
+        let next_harvest_tx = Transaction {
+            batch_id: "WHEAT-2024-002".to_string(),
+            ..harvest_tx.clone()
+        };
+        assert_eq!(
+            blockchain.validate_transaction(&signed(next_harvest_tx, &gateway_key)),
+            Ok(())
+        );
+
+        // but it only covers the delegated event types
+        let sale_tx = Transaction {
+            event_type: "SALE".into(),
+            ..harvest_tx.clone()
+        };
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(2, 0, previous_hash, vec![signed(sale_tx, &gateway_key)]);
+        assert_err(
+            blockchain.add_block(block),
+            BlockchainError::InvalidTransaction(TransactionError::NotDelegated(farm.clone())),
+        );
+
+        // and a newer delegation replaces it, here revoking it
+        let revocation_tx = Transaction {
+            data: r#"{"event_types": [], "expires_at": 4102444800000}"#.into(),
+            ..delegation_tx
+        };
+        let block = Block::new(2, 0, previous_hash, vec![signed(revocation_tx, &farm_key)]);
+        blockchain.add_block(block).unwrap();
+        let last_harvest_tx = Transaction {
+            batch_id: "WHEAT-2024-003".to_string(),
+            ..harvest_tx
+        };
+        assert_eq!(
+            blockchain.validate_transaction(&signed(last_harvest_tx, &gateway_key)),
+            Err(TransactionError::NotDelegated(farm))
+        );
+    }
+
+    #[test]
+    fn should_follow_the_lifecycle_of_the_batches() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let event = |event_type: &str| {
+            sign(Transaction {
+                sender: farm_address(),
+                recipient: warehouse_address(),
+                data: "{}".into(),
+                batch_id: "WHEAT-2024-001".to_string(),
+                event_type: event_type.into(),
+                ..Default::default()
+            })
+        };
+
+        // nothing can happen to a batch before its harvest
+        assert_eq!(
+            blockchain.validate_transaction(&event("PROCESSING")),
+            Err(TransactionError::InvalidTransition(
+                "WHEAT-2024-001".to_string(),
+                "CREATED".to_string(),
+                "PROCESSING".to_string()
+            ))
+        );
+        assert_eq!(
+            blockchain.validate_transaction(&event("HARVSET")),
+            Err(TransactionError::UnknownEventType(
+                "HARVSET".to_string(),
+                "HARVEST".to_string()
+            ))
+        );
+
+        // the harvest can still be pending, e.g. in the pool
+        let harvest_tx = event("HARVEST");
+        assert_eq!(
+            blockchain.validate_transaction_after(
+                &event("PROCESSING"),
+                std::slice::from_ref(&harvest_tx)
+            ),
+            Ok(())
+        );
+
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(1, 0, previous_hash, vec![harvest_tx, event("SALE")]);
+        blockchain.add_block(block).unwrap();
+
+        // the batch is gone after its sale, only custom events and its recall can follow it
+        assert_eq!(
+            blockchain.batch_state("WHEAT-2024-001"),
+            Some(BatchState::Sold)
+        );
+        assert_eq!(
+            blockchain.validate_transaction(&event("STORAGE")),
+            Err(TransactionError::InvalidTransition(
+                "WHEAT-2024-001".to_string(),
+                "SOLD".to_string(),
+                "STORAGE".to_string()
+            ))
+        );
+        assert_eq!(blockchain.validate_transaction(&event("DISPUTE")), Ok(()));
+        assert_eq!(blockchain.validate_transaction(&event("RECALL")), Ok(()));
+        assert_eq!(blockchain.batch_state("WHEAT-2024-002"), None);
+    }
+
+    #[test]
+    fn should_split_and_merge_the_batches() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let event = |batch_id: &str, event_type: &str, data: &str| {
+            sign(Transaction {
+                sender: farm_address(),
+                recipient: warehouse_address(),
+                data: data.into(),
+                batch_id: batch_id.to_string(),
+                event_type: event_type.into(),
+                ..Default::default()
+            })
+        };
+        let split = event(
+            "WHEAT-1",
+            "SPLIT",
+            r#"{"children": [{"batch_id": "WHEAT-1-A", "quantity_kg": 200}, {"batch_id": "WHEAT-1-B", "quantity_kg": 300}]}"#,
+        );
+
+        // a batch can only be split once harvested
+        assert_eq!(
+            blockchain.validate_transaction(&split),
+            Err(TransactionError::InvalidTransition(
+                "WHEAT-1".to_string(),
+                "CREATED".to_string(),
+                "SPLIT".to_string()
+            ))
+        );
+
+        let harvests = vec![
+            event("WHEAT-1", "HARVEST", "{}"),
+            event("CORN-1", "HARVEST", "{}"),
+            split,
+        ];
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(1, 0, previous_hash, harvests);
+        blockchain.add_block(block).unwrap();
+
+        // the children go on in the supply chain, but a split can't reuse an existing batch
+        assert_eq!(
+            blockchain.batch_state("WHEAT-1-A"),
+            Some(BatchState::Harvested)
+        );
+        assert_eq!(
+            blockchain.validate_transaction(&event("WHEAT-1-A", "TRANSPORT", "{}")),
+            Ok(())
+        );
+        let reused = event(
+            "WHEAT-1",
+            "SPLIT",
+            r#"{"children": [{"batch_id": "CORN-1", "quantity_kg": 10}]}"#,
+        );
+        assert_eq!(
+            blockchain.validate_transaction(&reused),
+            Err(TransactionError::BatchAlreadyExists("CORN-1".to_string()))
+        );
+
+        let merge = event(
+            "MIX-1",
+            "MERGE",
+            r#"{"parents": [{"batch_id": "WHEAT-1-A", "quantity_kg": 200}, {"batch_id": "CORN-1", "quantity_kg": 100}]}"#,
+        );
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(2, 0, previous_hash, vec![merge]);
+        blockchain.add_block(block).unwrap();
+
+        let lineage = blockchain.lineage("MIX-1");
+        assert_eq!(lineage.ancestors, vec!["WHEAT-1-A", "CORN-1", "WHEAT-1"]);
+        assert_eq!(lineage.links.len(), 3);
+        assert_eq!(blockchain.batch_state("MIX-1"), Some(BatchState::Harvested));
+    }
+
+    #[test]
+    fn should_find_everything_an_actor_touched() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        fixtures::mine(&blockchain, &fixtures::happy_path("WHEAT-1"));
+        fixtures::mine(&blockchain, &fixtures::recall("WHEAT-2"));
+
+        // the trucker carried both batches twice, and is among the recipients of the recall
+        let trucker = fixtures::trucker().address;
+        let touched = blockchain.transactions_for_address(&trucker, 0..u64::MAX);
+        let events: Vec<(u64, String)> = touched
+            .iter()
+            .map(|(block_ref, tx)| (block_ref.index, tx.event_type.to_string()))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (2, "TRANSPORT".to_string()),
+                (5, "TRANSPORT".to_string()),
+                (8, "TRANSPORT".to_string()),
+                (11, "TRANSPORT".to_string()),
+                (13, "RECALL".to_string()),
+            ]
+        );
+        let (block_ref, tx) = &touched[4];
+        let block = blockchain.get_block(block_ref.index).unwrap();
+        assert_eq!(block_ref.hash, block.header.hash);
+        assert_eq!(
+            transaction_hash(tx),
+            transaction_hash(&block.body.transactions[block_ref.position])
+        );
+
+        // only in the range of blocks
+        let touched = blockchain.transactions_for_address(&trucker, 6..12);
+        assert_eq!(touched.len(), 2);
+        assert_eq!(touched[0].1.batch_id, "WHEAT-2");
+        let consumer = fixtures::consumer().address;
+        assert_eq!(
+            blockchain
+                .transactions_for_address(&consumer, 0..u64::MAX)
+                .len(),
+            2
+        );
+        assert!(blockchain
+            .transactions_for_address(&alice(), 0..u64::MAX)
+            .is_empty());
+
+        // the transactions rolled back by a reorganization are forgotten
+        let ancestor = blockchain.get_block(6).unwrap();
+        let fork = Block::new(7, 1, ancestor.header.hash, Vec::new());
+        let fork = vec![fork.clone(), Block::new(8, 1, fork.header.hash, Vec::new())];
+        let fork: Vec<Block> = (9..=14).fold(fork, |mut fork, index| {
+            let previous_hash = fork.last().unwrap().header.hash;
+            fork.push(Block::new(index, 1, previous_hash, Vec::new()));
+            fork
+        });
+        blockchain.reorganize(6, fork).unwrap();
+        assert_eq!(
+            blockchain
+                .transactions_for_address(&trucker, 0..u64::MAX)
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn should_keep_the_plans_apart_from_the_history() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let event = |event_type: &str, data: &str| {
+            sign(Transaction {
+                sender: farm_address(),
+                recipient: warehouse_address(),
+                data: data.into(),
+                batch_id: "WHEAT-2024-001".to_string(),
+                event_type: event_type.into(),
+                ..Default::default()
+            })
+        };
+        let plan = |due: i64| {
+            let data = format!(r#"{{"event_type": "TRANSPORT", "due": {}}}"#, due);
+            event("PLANNED", &data)
+        };
+
+        // a plan must be due after the block that records it
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(1, 0, previous_hash, vec![plan(0)]);
+        assert_err(
+            blockchain.add_block(block),
+            BlockchainError::InvalidTransaction(TransactionError::InvalidPlan),
+        );
+
+        let block = Block::new(1, 0, previous_hash, vec![plan(i64::MAX)]);
+        blockchain.add_block(block).unwrap();
+        assert_eq!(blockchain.batch_state("WHEAT-2024-001"), None);
+        assert!(blockchain.history_for_batch("WHEAT-2024-001").is_empty());
+        let plans = blockchain.plans_of("WHEAT-2024-001");
+        assert_eq!(plans.len(), 1);
+        assert_eq!(plans[0].fulfilled_by, None);
+        assert_eq!(blockchain.overdue_plans(i64::MAX).len(), 0);
+
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let events = vec![event("HARVEST", "{}"), event("TRANSPORT", "{}")];
+        let block = Block::new(2, 0, previous_hash, events);
+        blockchain.add_block(block).unwrap();
+        assert_eq!(blockchain.history_for_batch("WHEAT-2024-001").len(), 2);
+        let plans = blockchain.plans_of("WHEAT-2024-001");
+        assert!(plans[0].fulfilled_by.is_some());
+    }
+
+    #[test]
+    fn should_include_the_transactions_within_their_validity_window() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let harvest = |validity: ValidityWindow| {
+            sign(Transaction {
+                sender: farm_address(),
+                recipient: warehouse_address(),
+                batch_id: "WHEAT-2024-001".to_string(),
+                event_type: "HARVEST".into(),
+                validity: Some(validity),
+                ..Default::default()
+            })
+        };
+        let from_block_2 = harvest(ValidityWindow {
+            not_before_height: Some(2),
+            ..Default::default()
+        });
+
+        // too early for the next block, but not for the one after it
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(1, 0, previous_hash, vec![from_block_2.clone()]);
+        assert_err(
+            blockchain.add_block(block),
+            BlockchainError::InvalidTransaction(TransactionError::NotYetValid(
+                "block 2".to_string(),
+            )),
+        );
+        assert_eq!(
+            blockchain.validate_transaction(&from_block_2),
+            Err(TransactionError::NotYetValid("block 2".to_string()))
+        );
+        let block = Block::new(1, 0, previous_hash, Vec::new());
+        blockchain.add_block(block).unwrap();
+        assert_eq!(blockchain.validate_transaction(&from_block_2), Ok(()));
+
+        // the window is checked against the timestamp of the block, not the time it is added
+        let expired = harvest(ValidityWindow {
+            not_after: Some(chaos::now_millis() - 1_000),
+            ..Default::default()
+        });
+        assert!(matches!(
+            blockchain.validate_transaction(&expired),
+            Err(TransactionError::ValidityExpired(_))
+        ));
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(2, 0, previous_hash, vec![expired]);
+        assert!(blockchain.add_block(block).is_err());
+        let block = Block::new(2, 0, previous_hash, vec![from_block_2]);
+        blockchain.add_block(block).unwrap();
+    }
+
+    #[test]
+    fn should_only_notify_the_custodians_of_a_batch() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let event = |event_type: &str, recipient: Address| {
+            sign(Transaction {
+                sender: farm_address(),
+                recipient,
+                batch_id: "WHEAT-2024-001".to_string(),
+                event_type: event_type.into(),
+                ..Default::default()
+            })
+        };
+        let recall = sign(Transaction {
+            recipients: vec![warehouse_address()],
+            ..event("RECALL", farm_address())
+        });
+
+        // the warehouse never held the batch
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(1, 0, previous_hash, vec![event("HARVEST", farm_address())]);
+        blockchain.add_block(block).unwrap();
+        assert_eq!(
+            blockchain.validate_transaction(&recall),
+            Err(TransactionError::NotACustodian(
+                warehouse_address(),
+                "WHEAT-2024-001".to_string()
+            ))
+        );
+
+        let transport = event("TRANSPORT", warehouse_address());
+        assert_eq!(
+            blockchain.validate_transaction_after(&recall, &[transport]),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn should_only_accept_the_compliance_reports_of_the_chain() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let event = |event_type: &str, data: &str| {
+            sign(Transaction {
+                sender: farm_address(),
+                recipient: warehouse_address(),
+                data: data.into(),
+                batch_id: "MILK-1".to_string(),
+                event_type: event_type.into(),
+                ..Default::default()
+            })
+        };
+        let readings =
+            r#"{"readings": [{"device_id": "TRUCK-7", "recorded_at": 0, "temperature": 12}]}"#;
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(
+            1,
+            0,
+            previous_hash,
+            vec![event("SENSOR_READINGS", readings)],
+        );
+        blockchain.add_block(block).unwrap();
+
+        let dairy = ComplianceThresholds::profile("dairy").unwrap();
+        let mut report = ComplianceReport::scan("MILK-1", &blockchain.get_all_blocks(), &dairy);
+        assert!(!report.compliant);
+        let attach = |report: &ComplianceReport| {
+            event(
+                COMPLIANCE_REPORT_EVENT,
+                &serde_json::to_string(report).unwrap(),
+            )
+        };
+        assert_eq!(blockchain.validate_transaction(&attach(&report)), Ok(()));
+
+        // the blocks covered must exist, and give the same report
+        report.until_block = 2;
+        assert_eq!(
+            blockchain.validate_transaction(&attach(&report)),
+            Err(TransactionError::InvalidComplianceReport)
+        );
+        report.until_block = 0;
+        assert_eq!(
+            blockchain.validate_transaction(&attach(&report)),
+            Err(TransactionError::InvalidComplianceReport)
+        );
+    }
+
+    #[test]
+    fn should_record_the_readings_taken_before_the_block() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let readings = |recorded_at: i64| {
+            sign(Transaction {
+            sender: farm_address(),
+            recipient: farm_address(),
+            data: format!(
+                r#"{{"readings": [{{"device_id": "TRUCK-7", "recorded_at": {}, "temperature": 4}}]}}"#,
+                recorded_at
+            )
+            .as_str()
+            .into(),
+            batch_id: "WHEAT-2024-001".to_string(),
+            event_type: SENSOR_READINGS_EVENT.into(),
+            ..Default::default()
+        })
+        };
+
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(1, 0, previous_hash, vec![readings(i64::MAX)]);
+        assert_err(
+            blockchain.add_block(block),
+            BlockchainError::InvalidTransaction(TransactionError::InvalidSensorReadings),
+        );
+        assert!(blockchain
+            .validate_transaction(&readings(i64::MAX))
+            .is_err());
+
+        let block = Block::new(1, 0, previous_hash, vec![readings(0)]);
+        blockchain.add_block(block).unwrap();
+        assert_eq!(blockchain.validate_transaction(&readings(1)), Ok(()));
+    }
+
+    #[test]
+    fn should_limit_the_custody_events_of_an_actor() {
+        let blockchain =
+            Blockchain::new(NO_DIFFICULTY).with_issuance_limit(IssuanceLimit::new(2, 60_000));
+        let event = |event_type: &str| {
+            sign(Transaction {
+                sender: farm_address(),
+                recipient: warehouse_address(),
+                data: "{}".into(),
+                batch_id: "WHEAT-2024-001".to_string(),
+                event_type: event_type.into(),
+                ..Default::default()
+            })
+        };
+
+        // a flood of events in a single block is rejected
+        let another_storage = sign(Transaction {
+            nonce: Some(1),
+            ..event("STORAGE")
+        });
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let flood = vec![event("HARVEST"), event("STORAGE"), another_storage.clone()];
+        assert_err(
+            blockchain.add_block(Block::new(1, 0, previous_hash, flood)),
+            BlockchainError::InvalidTransaction(TransactionError::TooManyEvents(
+                farm_address(),
+                "WHEAT-2024-001".to_string(),
+                2,
+            )),
+        );
+
+        let block = Block::new(
+            1,
+            0,
+            previous_hash,
+            vec![event("HARVEST"), event("STORAGE")],
+        );
+        blockchain.add_block(block).unwrap();
+        // also in the next blocks of the window, and in the pool
+        assert!(blockchain.validate_transaction(&another_storage).is_err());
+        assert_eq!(blockchain.validate_transaction(&event("DISPUTE")), Ok(()));
+    }
+
+    #[test]
+    fn should_conserve_the_quantity_of_the_batches() {
+        let blockchain =
+            Blockchain::new(NO_DIFFICULTY).with_quantity_strictness(QuantityStrictness::Reject);
+        let event = |event_type: &str, quantity_kg: f64| {
+            sign(Transaction {
+                sender: farm_address(),
+                recipient: warehouse_address(),
+                data: format!(r#"{{"quantity_kg": {}}}"#, quantity_kg)
+                    .as_str()
+                    .into(),
+                batch_id: "WHEAT-2024-001".to_string(),
+                event_type: event_type.into(),
+                ..Default::default()
+            })
+        };
+
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let overloaded = vec![event("HARVEST", 500.0), event("TRANSPORT", 600.0)];
+        assert_err(
+            blockchain.add_block(Block::new(1, 0, previous_hash, overloaded)),
+            BlockchainError::InvalidTransaction(TransactionError::QuantityExceeded(
+                "WHEAT-2024-001".to_string(),
+                600.0,
+                500.0,
+            )),
+        );
+
+        let block = Block::new(1, 0, previous_hash, vec![event("HARVEST", 500.0)]);
+        blockchain.add_block(block).unwrap();
+        let split = sign(Transaction {
+            data: r#"{"children": [{"batch_id": "WHEAT-2024-001-A", "quantity_kg": 200}]}"#.into(),
+            ..event("SPLIT", 0.0)
+        });
+        let pending = vec![split];
+        assert_eq!(
+            blockchain.validate_transaction_after(&event("SALE", 400.0), &pending),
+            Err(TransactionError::QuantityExceeded(
+                "WHEAT-2024-001".to_string(),
+                400.0,
+                300.0
+            ))
+        );
+
+        // the children of a split start with their portion
+        let child_sale = sign(Transaction {
+            batch_id: "WHEAT-2024-001-A".to_string(),
+            ..event("SALE", 250.0)
+        });
+        assert!(matches!(
+            blockchain.validate_transaction_after(&child_sale, &pending),
+            Err(TransactionError::QuantityExceeded(batch_id, _, _)) if batch_id == "WHEAT-2024-001-A"
+        ));
+
+        // only flagged by default, for the submitters to be warned
+        let flagged = Blockchain::new(NO_DIFFICULTY);
+        let block = Block::new(1, 0, previous_hash, vec![event("HARVEST", 500.0)]);
+        flagged.add_block(block).unwrap();
+        let sale = event("SALE", 600.0);
+        assert_eq!(flagged.validate_transaction(&sale), Ok(()));
+        assert!(flagged.check_quantity_after(&sale, &[]).is_err());
+    }
+
+    #[test]
+    fn should_build_the_threads_of_responses() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let offer_tx = sign(Transaction {
+            sender: farm_address(),
+            recipient: warehouse_address(),
+            data: r#"{"price": 120}"#.into(),
+            batch_id: "WHEAT-2024-001".to_string(),
+            event_type: "OFFER".into(),
+            ..Default::default()
+        });
+        let offer_hash = transaction_hash(&offer_tx);
+        let counter_offer_tx = sign(Transaction {
+            sender: warehouse_address(),
+            recipient: farm_address(),
+            data: r#"{"price": 110}"#.into(),
+            event_type: "OFFER".into(),
+            in_response_to: Some(offer_hash),
+            ..offer_tx.clone()
+        });
+
+        // the transaction it responds to must go before it
+        assert_eq!(
+            blockchain.validate_transaction(&counter_offer_tx),
+            Err(TransactionError::UnknownReference(offer_hash))
+        );
+        let unrelated_tx = sign(Transaction {
+            batch_id: "CORN-2024-001".to_string(),
+            ..offer_tx.clone()
+        });
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(
+            1,
+            0,
+            previous_hash,
+            vec![offer_tx, unrelated_tx, counter_offer_tx.clone()],
+        );
+        blockchain.add_block(block).unwrap();
+
+        let accept_tx = sign(Transaction {
+            sender: farm_address(),
+            recipient: warehouse_address(),
+            data: "{}".into(),
+            event_type: "ACCEPT".into(),
+            in_response_to: Some(transaction_hash(&counter_offer_tx)),
+            ..counter_offer_tx
+        });
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(2, 0, previous_hash, vec![accept_tx.clone()]);
+        blockchain.add_block(block).unwrap();
+
+        // the thread is the same from any of its transactions, without the unrelated ones
+        let thread = blockchain.thread_of(&transaction_hash(&accept_tx));
+        let positions: Vec<(u64, usize)> = thread
+            .iter()
+            .map(|(block, _, _)| (block.index, block.position))
+            .collect();
+        assert_eq!(positions, vec![(1, 0), (1, 2), (2, 0)]);
+        assert_eq!(thread[0].1, offer_hash);
+        assert_eq!(blockchain.thread_of(&offer_hash).len(), 3);
+        assert!(blockchain.thread_of(&TxHash::from(1)).is_empty());
+    }
+
+    #[test]
+    fn should_allocate_lots_only_once() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        assert_eq!(blockchain.get_last_lot_sequence("WHEAT", "2024"), 0);
+
+        // two packhouses claiming the same lot in the same block
+        let lot = Lot::new("WHEAT", "2024", 1).unwrap();
+        let first_claim = create_lot_transaction(farm_address(), &lot);
+        let second_claim = create_lot_transaction(warehouse_address(), &lot);
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(
+            1,
+            0,
+            previous_hash,
+            vec![first_claim.clone(), second_claim.clone()],
+        );
+        assert_err(
+            blockchain.add_block(block),
+            BlockchainError::InvalidTransaction(TransactionError::LotAlreadyAllocated(
+                "WHEAT-2024-0001".to_string(),
+            )),
+        );
+
+        // only the first one is recorded
+        let block = Block::new(1, 0, previous_hash, vec![first_claim]);
+        blockchain.add_block(block).unwrap();
+        assert_eq!(blockchain.get_last_lot_sequence("WHEAT", "2024"), 1);
+        assert_eq!(blockchain.get_last_lot_sequence("WHEAT", "2025"), 0);
+        assert_eq!(
+            blockchain.validate_transaction(&second_claim),
+            Err(TransactionError::LotAlreadyAllocated(
+                "WHEAT-2024-0001".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn should_enforce_the_roles_once_actors_are_registered() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let (farm, warehouse) = (fixtures::farm(), fixtures::retailer());
+        let registration = |sender: &Persona, recipient: &Persona, roles: &str| {
+            sender.sign(Transaction {
+                sender: sender.address.clone(),
+                recipient: recipient.address.clone(),
+                data: format!(r#"{{"roles": {}}}"#, roles).into(),
+                event_type: REGISTRATION_EVENT.into(),
+                ..Default::default()
+            })
+        };
+        let harvest = |sender: &Persona| {
+            sender.sign(Transaction {
+                sender: sender.address.clone(),
+                recipient: sender.address.clone(),
+                batch_id: "WHEAT-001".to_string(),
+                event_type: "HARVEST".into(),
+                ..Default::default()
+            })
+        };
+        // nobody is registered, so anyone can emit any event
+        assert_eq!(
+            blockchain.validate_transaction(&harvest(&warehouse)),
+            Ok(())
+        );
+
+        // the farm registers itself and becomes the registrar, the roles apply right after
+        let farm_registration = registration(&farm, &farm, r#"["FARMER"]"#);
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(
+            1,
+            0,
+            previous_hash,
+            vec![farm_registration.clone(), harvest(&warehouse)],
+        );
+        assert_err(
+            blockchain.add_block(block),
+            BlockchainError::InvalidTransaction(TransactionError::MissingRole(
+                warehouse.address.clone(),
+                "FARMER".to_string(),
+                "HARVEST".to_string(),
+            )),
+        );
+        assert_eq!(blockchain.get_actor_registry().registrar(), None);
+
+        let block = Block::new(1, 0, previous_hash, vec![farm_registration, harvest(&farm)]);
+        blockchain.add_block(block).unwrap();
+        let registry = blockchain.get_actor_registry();
+        assert_eq!(registry.registrar(), Some(&farm.address));
+        assert_eq!(registry.roles_of(&farm.address), [Role::Farmer]);
+
+        // only the registrar can grant roles
+        let self_registration = registration(&warehouse, &warehouse, r#"["WAREHOUSE"]"#);
+        assert_eq!(
+            blockchain.validate_transaction(&self_registration),
+            Err(TransactionError::NotRegistrar(farm.address.clone()))
+        );
+        let registration = registration(&farm, &warehouse, r#"["WAREHOUSE"]"#);
+        assert_eq!(blockchain.validate_transaction(&registration), Ok(()));
+
+        // both must be signed, anyone could name the registrar or the farm as the sender otherwise
+        let forged_registration = Transaction {
+            signature: None,
+            ..registration
+        };
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(2, 0, previous_hash, vec![forged_registration]);
+        assert_err(blockchain.add_block(block), missing_signature(0));
+        let forged_harvest = Transaction {
+            signature: None,
+            batch_id: "WHEAT-002".to_string(),
+            ..harvest(&farm)
+        };
+        let block = Block::new(2, 0, previous_hash, vec![forged_harvest]);
+        assert_err(blockchain.add_block(block), missing_signature(0));
+    }
+
+    #[test]
+    fn should_transfer_escrowed_batches_only_when_released() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let inspector = carol();
+        let escrow_tx = sign(Transaction {
+            sender: farm_address(),
+            recipient: warehouse_address(),
+            data: format!(
+                r#"{{"condition_event": "QUALITY_CHECK", "inspector": "{}", "expected": {{"result": "PASS"}}, "deadline": 4102444800000}}"#,
+                inspector
+            )
+            .into(),
+            batch_id: "WHEAT-2024-001".to_string(),
+            event_type: ESCROW_EVENT.into(),
+            ..Default::default()
+        });
+        let storage_tx = sign(Transaction {
+            sender: warehouse_address(),
+            recipient: warehouse_address(),
+            data: "{}".into(),
+            batch_id: "WHEAT-2024-001".to_string(),
+            event_type: "STORAGE".into(),
+            ..Default::default()
+        });
+        let harvest_tx = sign(Transaction {
+            sender: farm_address(),
+            recipient: farm_address(),
+            data: "{}".into(),
+            batch_id: "WHEAT-2024-001".to_string(),
+            event_type: "HARVEST".into(),
+            ..Default::default()
+        });
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(1, 0, previous_hash, vec![harvest_tx, escrow_tx.clone()]);
+        blockchain.add_block(block).unwrap();
+
+        // the batch can't be escrowed twice, and the recipient can't act on it yet
+        let another_escrow_tx = sign(Transaction {
+            nonce: Some(1),
+            ..escrow_tx
+        });
+        assert_eq!(
+            blockchain.validate_transaction(&another_escrow_tx),
+            Err(TransactionError::EscrowPending(
+                "WHEAT-2024-001".to_string()
+            ))
+        );
+        assert_eq!(
+            blockchain.validate_transaction(&storage_tx),
+            Err(TransactionError::EscrowNotReleased(
+                "WHEAT-2024-001".to_string()
+            ))
+        );
+
+        // a passed quality check by the inspector releases it
+        let check_tx = sign(Transaction {
+            sender: inspector.clone(),
+            recipient: inspector,
+            data: r#"{"result": "PASS"}"#.into(),
+            batch_id: "WHEAT-2024-001".to_string(),
+            event_type: "QUALITY_CHECK".into(),
+            ..Default::default()
+        });
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(2, 0, previous_hash, vec![check_tx, storage_tx]);
+        blockchain.add_block(block).unwrap();
+    }
+
+    #[test]
+    fn should_detect_corrupted_blocks() {
+        let blockchain = Blockchain::new(0);
+        for timestamp in [1000, 2000] {
+            let block = create_block_at(&blockchain, timestamp, |_| true);
+            blockchain.add_block(block).unwrap();
+        }
+        assert!(blockchain.is_valid());
+
+        // the contents of a block change after it was added
+        blockchain.state.write().unwrap().blocks[1].header.nonce += 1;
+        assert!(!blockchain.is_valid());
+        assert_eq!(
+            blockchain.validate_chain(),
+            Err((1, ValidationError::InvalidHash))
+        );
+    }
+
+    #[test]
+    fn should_let_reading_while_adding_blocks() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+
+        std::thread::scope(|scope| {
+            // readers must always see the blocks, headers and stats in sync
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..100 {
+                        let last_block = blockchain.get_last_block();
+                        let totals = blockchain.get_stats_totals();
+                        assert!(totals.blocks > last_block.header.index);
+                        assert!(
+                            blockchain.iter_headers(0..u64::MAX).count() as u64
+                                > last_block.header.index
+                        );
+                    }
+                });
+            }
+
+            scope.spawn(|| {
+                for index in 1..=20 {
+                    let previous_hash = blockchain.get_last_block().header.hash;
+                    let block = Block::new(index, 0, previous_hash, Vec::new());
+                    blockchain.add_block(block).unwrap();
+                }
+            });
+        });
+
+        assert_eq!(blockchain.get_last_block().header.index, 20);
+        assert_eq!(blockchain.get_stats_totals().blocks, 21);
+    }
+
+    #[test]
+    fn should_run_operations_from_async_code() {
+        let blockchain = Blockchain::new(NO_DIFFICULTY);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        // a writer and a reader on the same executor, neither of them blocks it
+        let (added, height) = runtime.block_on(async {
+            let writer = blockchain.run(|blockchain| {
+                let previous_hash = blockchain.get_last_block().header.hash;
+                blockchain.add_block(Block::new(1, 0, previous_hash, Vec::new()))
+            });
+            let reader = blockchain.run(|blockchain| blockchain.get_last_block().header.index);
+            futures::join!(writer, reader)
+        });
+        assert!(added.is_ok());
+        assert!(height <= 1);
+        assert_eq!(blockchain.get_last_block().header.index, 1);
+
+        // the panics of an operation are raised in the caller
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            runtime.block_on(blockchain.run(|_| panic!("the operation failed")))
+        }));
+        assert!(panicked.is_err());
+    }
+
+    // Creates the next block of the chain, with the first nonce whose hash is accepted
+    fn create_block_at<F>(blockchain: &Blockchain, timestamp: i64, accept: F) -> Block
+    where
+        F: Fn(&BlockHash) -> bool,
+    {
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let index = blockchain.get_last_block().header.index + 1;
+        let mut block = Block::new(index, 0, previous_hash, Vec::new());
+        block.header.timestamp = timestamp;
+        block.header.hash = block.calculate_hash();
+        while !accept(&block.header.hash) {
+            block.header.nonce += 1;
+            block.header.hash = block.calculate_hash();
+        }
+        block
+    }
+
+    // Signed by the key, as only an actor can publish its profile
+    fn create_profile_transaction(key: &SigningKey, display_name: &str) -> Transaction {
+        let address = Address::from(key.verifying_key().to_bytes());
+        let mut transaction = Transaction {
+            sender: address.clone(),
+            recipient: address,
+            data: format!(r#"{{"display_name": "{}"}}"#, display_name).into(),
+            batch_id: String::new(),
+            event_type: PROFILE_EVENT.into(),
+            ..Default::default()
+        };
+        transaction.sign(key).unwrap();
+        transaction
+    }
+
+    fn create_lot_transaction(address: Address, lot: &Lot) -> Transaction {
+        sign(Transaction {
+            sender: address.clone(),
+            recipient: address,
+            data: serde_json::to_string(lot).unwrap().into(),
+            batch_id: lot.id(),
+            event_type: LOT_EVENT.into(),
+            ..Default::default()
+        })
+    }
+
+    // The error of a block with an unsigned transaction, refused before any other rule is checked
+    fn missing_signature(position: usize) -> BlockchainError {
+        BlockchainError::InvalidBlock(ValidationError::InvalidSignature {
+            position,
+            error: TransactionError::MissingSignature,
+        })
     }
 
     fn assert_err(result: Result<(), anyhow::Error>, error_type: BlockchainError) {
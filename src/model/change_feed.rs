@@ -0,0 +1,291 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, OpenOptions},
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{BlockHash, Blockchain, OrphanReason};
+
+// What happened to the chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ChangeKind {
+    // A block was appended to the main chain
+    BlockApplied,
+    // A valid block competing with one of the main chain was archived
+    ForkArchived,
+    // A block of the main chain was replaced by the block of a longer fork, applied right after
+    BlockRolledBack,
+}
+
+// A mutation of the chain, in the order this node recorded them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Change {
+    // position in the feed, starting at 1 and never reused
+    pub cursor: u64,
+    pub kind: ChangeKind,
+    pub index: u64,
+    #[schema(value_type = String)]
+    pub hash: BlockHash,
+    // timestamp in milliseconds
+    pub recorded_at: i64,
+}
+
+#[derive(Debug, Default)]
+struct ChangeLog {
+    changes: Vec<Change>,
+    // so the same fork is never recorded twice, e.g. when the stored chain is restored
+    archived: HashSet<BlockHash>,
+    // the main chain as recorded by the changes, to find the blocks rolled back since
+    applied: BTreeMap<u64, BlockHash>,
+}
+
+impl ChangeLog {
+    fn push(&mut self, change: Change) {
+        match change.kind {
+            ChangeKind::BlockApplied => {
+                self.applied.insert(change.index, change.hash);
+            }
+            ChangeKind::ForkArchived => {
+                self.archived.insert(change.hash);
+            }
+            ChangeKind::BlockRolledBack => {
+                self.applied.remove(&change.index);
+            }
+        }
+        self.changes.push(change);
+    }
+}
+
+// Ordered and replayable feed of the mutations of the chain, for integrators that ingest it
+// Clients keep the cursor of the last change they processed and ask for the next ones, so a crash of a client
+// only replays the changes after its cursor (at-least-once delivery)
+// The changes are appended to a file (if set), so the cursors stay valid after a restart of the node
+#[derive(Debug, Clone)]
+pub struct ChangeFeed {
+    log: Arc<Mutex<ChangeLog>>,
+    file_path: String,
+}
+
+impl ChangeFeed {
+    pub fn new(file_path: String) -> ChangeFeed {
+        ChangeFeed {
+            log: Arc::default(),
+            file_path,
+        }
+    }
+
+    // Records the changes of the chain since the last call
+    // They are read from the chain instead of its events, so none is missed while nobody reads the feed
+    pub fn sync(&self, blockchain: &Blockchain) -> Result<()> {
+        let mut log = self.log.lock().unwrap();
+
+        // the recorded blocks that are not in the chain anymore, the last ones first
+        let mut found: Vec<_> = log
+            .applied
+            .iter()
+            .rev()
+            .take_while(|(index, hash)| {
+                blockchain.get_block(**index).map(|block| block.header.hash) != Some(**hash)
+            })
+            .map(|(index, hash)| (ChangeKind::BlockRolledBack, *index, *hash))
+            .collect();
+        let mut index = match found.last() {
+            Some((_, index, _)) => *index,
+            None => log.applied.keys().next_back().map_or(1, |index| index + 1),
+        };
+        while let Some(block) = blockchain.get_block(index) {
+            found.push((
+                ChangeKind::BlockApplied,
+                block.header.index,
+                block.header.hash,
+            ));
+            index += 1;
+        }
+        // the rolled back blocks are archived too, they are already recorded as such
+        for orphaned in blockchain
+            .get_orphaned_blocks()
+            .into_iter()
+            .filter(|orphaned| orphaned.reason != OrphanReason::RolledBack)
+        {
+            found.push((
+                ChangeKind::ForkArchived,
+                orphaned.block.header.index,
+                orphaned.block.header.hash,
+            ));
+        }
+
+        for (kind, index, hash) in found {
+            if kind == ChangeKind::ForkArchived && log.archived.contains(&hash) {
+                continue;
+            }
+            let change = Change {
+                cursor: log.changes.len() as u64 + 1,
+                kind,
+                index,
+                hash,
+                recorded_at: Utc::now().timestamp_millis(),
+            };
+            // a change that cannot be persisted is not served, its cursor could change after a restart
+            self.append(&change)?;
+            log.push(change);
+        }
+
+        Ok(())
+    }
+
+    // Returns the changes after a cursor (0 for all of them), the oldest first
+    pub fn after(&self, cursor: u64, limit: usize) -> Vec<Change> {
+        let log = self.log.lock().unwrap();
+        let start = (cursor as usize).min(log.changes.len());
+        let end = start.saturating_add(limit).min(log.changes.len());
+
+        log.changes[start..end].to_vec()
+    }
+
+    // Cursor of the last recorded change, 0 if there are none
+    pub fn last_cursor(&self) -> u64 {
+        self.log.lock().unwrap().changes.len() as u64
+    }
+
+    // Loads the changes persisted in previous runs
+    pub fn load(&self) -> Result<()> {
+        if self.file_path.is_empty() || fs::metadata(&self.file_path).is_err() {
+            return Ok(());
+        }
+
+        let raw_changes = fs::read_to_string(&self.file_path)?;
+        let mut log = self.log.lock().unwrap();
+        for line in raw_changes.lines().filter(|line| !line.trim().is_empty()) {
+            let change: Change = serde_json::from_str(line)?;
+            log.push(change);
+        }
+
+        Ok(())
+    }
+
+    // One JSON document per line, so recording a change doesn't rewrite the whole file
+    fn append(&self, change: &Change) -> Result<()> {
+        if self.file_path.is_empty() {
+            return Ok(());
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.file_path)?;
+        writeln!(file, "{}", serde_json::to_string(change)?)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{fixtures::alice, Block, Transaction};
+
+    #[test]
+    fn should_record_the_changes_of_the_chain_once() {
+        let blockchain = Blockchain::new(0);
+        let feed = ChangeFeed::new(String::new());
+        let first = add_block(&blockchain);
+        feed.sync(&blockchain).unwrap();
+        let second = add_block(&blockchain);
+        feed.sync(&blockchain).unwrap();
+        feed.sync(&blockchain).unwrap();
+
+        let changes = feed.after(0, 10);
+        assert_eq!(changes.len(), 2);
+        assert_eq!((changes[0].cursor, changes[0].hash), (1, first));
+        assert_eq!((changes[1].cursor, changes[1].hash), (2, second));
+        assert_eq!(feed.after(1, 10), vec![changes[1].clone()]);
+        assert!(feed.after(2, 10).is_empty());
+        assert_eq!(feed.after(0, 1).len(), 1);
+        assert_eq!(feed.last_cursor(), 2);
+
+        // a competitor of the first block
+        let genesis_hash = blockchain.get_block(0).unwrap().header.hash;
+        let fork = Block::new(1, 1, genesis_hash, vec![]);
+        assert!(blockchain.add_block(fork.clone()).is_err());
+        feed.sync(&blockchain).unwrap();
+        let change = &feed.after(2, 10)[0];
+        assert_eq!(change.kind, ChangeKind::ForkArchived);
+        assert_eq!((change.index, change.hash), (1, fork.header.hash));
+    }
+
+    #[test]
+    fn should_record_the_blocks_rolled_back_by_a_reorganization() {
+        let blockchain = Blockchain::new(0);
+        let feed = ChangeFeed::new(String::new());
+        let replaced = [add_block(&blockchain), add_block(&blockchain)];
+        feed.sync(&blockchain).unwrap();
+
+        // a longer fork after the genesis
+        let mut fork = vec![blockchain.get_block(0).unwrap()];
+        for nonce in 0..3 {
+            let last = &fork.last().unwrap().header;
+            fork.push(Block::new(last.index + 1, nonce + 1, last.hash, vec![]));
+        }
+        fork.remove(0);
+        blockchain.reorganize(0, fork.clone()).unwrap();
+        feed.sync(&blockchain).unwrap();
+
+        let changes: Vec<_> = feed
+            .after(2, 10)
+            .into_iter()
+            .map(|change| (change.kind, change.index, change.hash))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (ChangeKind::BlockRolledBack, 2, replaced[1]),
+                (ChangeKind::BlockRolledBack, 1, replaced[0]),
+                (ChangeKind::BlockApplied, 1, fork[0].header.hash),
+                (ChangeKind::BlockApplied, 2, fork[1].header.hash),
+                (ChangeKind::BlockApplied, 3, fork[2].header.hash),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_keep_the_cursors_after_a_restart() {
+        let path = std::env::temp_dir().join(format!("agriblock-changes-{}", std::process::id()));
+        let file_path = path.to_str().unwrap().to_string();
+        let blockchain = Blockchain::new(0);
+        let feed = ChangeFeed::new(file_path.clone());
+        add_block(&blockchain);
+        feed.sync(&blockchain).unwrap();
+
+        // the restored chain has the same blocks, they are not recorded again
+        let restarted = ChangeFeed::new(file_path);
+        restarted.load().unwrap();
+        restarted.sync(&blockchain).unwrap();
+        assert_eq!(restarted.after(0, 10), feed.after(0, 10));
+
+        add_block(&blockchain);
+        restarted.sync(&blockchain).unwrap();
+        assert_eq!(restarted.after(1, 10)[0].cursor, 2);
+        fs::remove_file(path).unwrap();
+    }
+
+    fn add_block(blockchain: &Blockchain) -> BlockHash {
+        let last = blockchain.get_last_block();
+        let transaction = Transaction::coinbase(alice());
+        let block = Block::new(
+            last.header.index + 1,
+            0,
+            last.header.hash,
+            vec![transaction],
+        );
+        let hash = block.header.hash;
+        blockchain.add_block(block).unwrap();
+        hash
+    }
+}
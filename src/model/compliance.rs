@@ -0,0 +1,280 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{
+    Block, SensorReading, SensorReadings, Transaction, TransactionError, SENSOR_READINGS_EVENT,
+};
+
+// Event that attaches to a batch the proof that its cold chain was never broken (or where it was)
+pub const COMPLIANCE_REPORT_EVENT: &str = "COMPLIANCE_REPORT";
+
+// The acceptable range of the measures of a product, e.g. at most 8°C for dairy
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ComplianceThresholds {
+    // degrees celsius
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_temperature: Option<f64>,
+    // relative humidity in percent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_humidity: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_humidity: Option<f64>,
+}
+
+impl ComplianceThresholds {
+    // The usual thresholds of a kind of product
+    pub fn profile(name: &str) -> Option<ComplianceThresholds> {
+        let temperature = |min_temperature: Option<f64>, max_temperature: f64| {
+            Some(ComplianceThresholds {
+                min_temperature,
+                max_temperature: Some(max_temperature),
+                ..Default::default()
+            })
+        };
+        match name {
+            "dairy" => temperature(None, 8.0),
+            "chilled" => temperature(Some(0.0), 4.0),
+            "frozen" => temperature(None, -18.0),
+            _ => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == ComplianceThresholds::default()
+    }
+
+    // The bounds of a measure of the readings, by name
+    fn bounds(&self) -> [(&'static str, Option<f64>, Option<f64>); 2] {
+        [
+            ("temperature", self.min_temperature, self.max_temperature),
+            ("humidity", self.min_humidity, self.max_humidity),
+        ]
+    }
+}
+
+// Consecutive readings of a measure out of the thresholds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ComplianceWindow {
+    // "temperature" or "humidity"
+    pub metric: String,
+    // timestamps in milliseconds of the first and the last readings out of range
+    pub start: i64,
+    pub end: i64,
+    pub readings: usize,
+    // the reading furthest from the range
+    pub peak: f64,
+}
+
+// The readings of a batch checked against thresholds, up to a block of the chain
+// so anyone can scan the same blocks again and get the same report
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ComplianceReport {
+    pub batch_id: String,
+    // index of the last block scanned
+    pub until_block: u64,
+    pub thresholds: ComplianceThresholds,
+    pub readings: usize,
+    // the batch has readings, and none of them is out of range
+    pub compliant: bool,
+    pub violations: Vec<ComplianceWindow>,
+}
+
+impl ComplianceReport {
+    // Checks the SENSOR_READINGS of a batch in the blocks against thresholds
+    pub fn scan(
+        batch_id: &str,
+        blocks: &[Block],
+        thresholds: &ComplianceThresholds,
+    ) -> ComplianceReport {
+        let until_block = blocks.last().map(|block| block.header.index).unwrap_or(0);
+        let transactions = blocks
+            .iter()
+            .flat_map(|block| block.body.transactions.iter());
+        ComplianceReport::of(batch_id, transactions, until_block, thresholds)
+    }
+
+    // Same, from the transactions of the blocks up to a block in chain order, e.g. the events of the batch only
+    pub fn of<'a>(
+        batch_id: &str,
+        transactions: impl IntoIterator<Item = &'a Transaction>,
+        until_block: u64,
+        thresholds: &ComplianceThresholds,
+    ) -> ComplianceReport {
+        let mut readings: Vec<SensorReading> = transactions
+            .into_iter()
+            .filter(|transaction| {
+                transaction.batch_id == batch_id && transaction.event_type == SENSOR_READINGS_EVENT
+            })
+            .filter_map(|transaction| SensorReadings::parse(&transaction.data.as_json()).ok())
+            .flat_map(|bulk| bulk.readings)
+            .collect();
+        // stable, so readings with the same timestamp keep their chain order
+        readings.sort_by_key(|reading| reading.recorded_at);
+
+        let mut violations = Vec::new();
+        for (metric, min, max) in thresholds.bounds() {
+            let measures: Vec<(i64, f64)> = readings
+                .iter()
+                .filter_map(|reading| {
+                    let measure = match metric {
+                        "temperature" => reading.temperature,
+                        _ => reading.humidity,
+                    };
+                    measure.map(|measure| (reading.recorded_at, measure))
+                })
+                .collect();
+            violations.extend(out_of_range(metric, &measures, min, max));
+        }
+
+        ComplianceReport {
+            batch_id: batch_id.to_string(),
+            until_block,
+            thresholds: *thresholds,
+            readings: readings.len(),
+            compliant: !readings.is_empty() && violations.is_empty(),
+            violations,
+        }
+    }
+
+    // Parses the report contained in the data of a COMPLIANCE_REPORT event
+    pub fn parse(data: &str) -> Result<ComplianceReport, TransactionError> {
+        let report: ComplianceReport =
+            serde_json::from_str(data).map_err(|_| TransactionError::InvalidComplianceReport)?;
+        match report.thresholds.is_empty() {
+            true => Err(TransactionError::InvalidComplianceReport),
+            false => Ok(report),
+        }
+    }
+}
+
+// The windows of consecutive measures of a metric out of a range
+fn out_of_range(
+    metric: &str,
+    measures: &[(i64, f64)],
+    min: Option<f64>,
+    max: Option<f64>,
+) -> Vec<ComplianceWindow> {
+    let min = min.unwrap_or(f64::MIN);
+    let max = max.unwrap_or(f64::MAX);
+    let distance = |value: f64| (min - value).max(value - max);
+
+    let mut windows: Vec<ComplianceWindow> = Vec::new();
+    let mut in_window = false;
+    for (at, value) in measures.iter() {
+        if distance(*value) <= 0.0 {
+            in_window = false;
+            continue;
+        }
+
+        match windows.last_mut() {
+            Some(window) if in_window => {
+                window.end = *at;
+                window.readings += 1;
+                if distance(*value) > distance(window.peak) {
+                    window.peak = *value;
+                }
+            }
+            _ => windows.push(ComplianceWindow {
+                metric: metric.to_string(),
+                start: *at,
+                end: *at,
+                readings: 1,
+                peak: *value,
+            }),
+        }
+        in_window = true;
+    }
+
+    windows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{BlockHash, Transaction};
+
+    #[test]
+    fn should_find_where_the_cold_chain_was_broken() {
+        let blocks = vec![
+            create_block(0, vec![]),
+            create_block(
+                1,
+                vec![
+                    create_readings("MILK-1", &[(0, 4.0), (60_000, 9.5), (120_000, 11.0)]),
+                    create_readings("MILK-2", &[(0, 20.0)]),
+                ],
+            ),
+            create_block(2, vec![create_readings("MILK-1", &[(180_000, 5.0)])]),
+        ];
+
+        let dairy = ComplianceThresholds::profile("dairy").unwrap();
+        let report = ComplianceReport::scan("MILK-1", &blocks, &dairy);
+        assert_eq!(report.until_block, 2);
+        assert_eq!(report.readings, 4);
+        assert!(!report.compliant);
+        assert_eq!(
+            report.violations,
+            vec![ComplianceWindow {
+                metric: "temperature".to_string(),
+                start: 60_000,
+                end: 120_000,
+                readings: 2,
+                peak: 11.0
+            }]
+        );
+
+        // without any reading, there is nothing to prove
+        let report = ComplianceReport::scan("MILK-3", &blocks, &dairy);
+        assert!(!report.compliant && report.violations.is_empty());
+        let report = ComplianceReport::scan("MILK-1", &blocks[..1], &dairy);
+        assert_eq!(report.readings, 0);
+
+        let chilled = ComplianceThresholds::profile("chilled").unwrap();
+        let report = ComplianceReport::scan("MILK-1", &blocks[2..], &chilled);
+        assert_eq!(report.violations[0].peak, 5.0);
+        assert_eq!(ComplianceThresholds::profile("wine"), None);
+    }
+
+    #[test]
+    fn should_parse_the_reports() {
+        let dairy = ComplianceThresholds::profile("dairy").unwrap();
+        let report = ComplianceReport::scan("MILK-1", &[], &dairy);
+        let data = serde_json::to_string(&report).unwrap();
+        assert_eq!(ComplianceReport::parse(&data), Ok(report));
+
+        let without_thresholds = r#"{"batch_id": "MILK-1", "until_block": 0, "thresholds": {}, "readings": 0, "compliant": false, "violations": []}"#;
+        assert_eq!(
+            ComplianceReport::parse(without_thresholds),
+            Err(TransactionError::InvalidComplianceReport)
+        );
+    }
+
+    fn create_readings(batch_id: &str, temperatures: &[(i64, f64)]) -> Transaction {
+        let readings = temperatures
+            .iter()
+            .map(|(recorded_at, temperature)| SensorReading {
+                device_id: "TRUCK-7".to_string(),
+                recorded_at: *recorded_at,
+                temperature: Some(*temperature),
+                humidity: None,
+                gps: None,
+            })
+            .collect();
+        let data = serde_json::to_string(&SensorReadings { readings }).unwrap();
+        Transaction {
+            batch_id: batch_id.to_string(),
+            event_type: SENSOR_READINGS_EVENT.into(),
+            data: data.as_str().into(),
+            ..Default::default()
+        }
+    }
+
+    fn create_block(index: u64, transactions: Vec<Transaction>) -> Block {
+        Block::new(index, 0, BlockHash::zero(), transactions)
+    }
+}
@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+
+use super::{EventType, Transaction, TransactionError};
+
+pub const DELEGATION_EVENT: &str = "DELEGATION";
+
+// Authorization that an actor (sender of the DELEGATION event) grants to a gateway (recipient)
+// to submit transactions on its behalf, for some event types and batches until an expiration date
+// Publishing a newer delegation to the same gateway replaces the previous one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Delegation {
+    pub event_types: Vec<EventType>,
+    // an empty list authorizes all batches
+    #[serde(default)]
+    pub batch_ids: Vec<String>,
+    // timestamp in milliseconds
+    pub expires_at: i64,
+}
+
+impl Delegation {
+    // Parses the delegation contained in the data of a DELEGATION event
+    pub fn parse(data: &str) -> Result<Delegation, TransactionError> {
+        serde_json::from_str(data).map_err(|_| TransactionError::InvalidDelegation)
+    }
+
+    // Checks if the delegation authorizes a transaction at a given time
+    pub fn authorizes(&self, transaction: &Transaction, timestamp: i64) -> bool {
+        let event_type_allowed = self.event_types.contains(&transaction.event_type);
+        let batch_allowed =
+            self.batch_ids.is_empty() || self.batch_ids.contains(&transaction.batch_id);
+
+        event_type_allowed && batch_allowed && timestamp < self.expires_at
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_authorize_delegated_transactions() {
+        let delegation = Delegation::parse(
+            r#"{"event_types": ["HARVEST"], "batch_ids": ["WHEAT-001"], "expires_at": 1000}"#,
+        )
+        .unwrap();
+
+        let transaction = create_transaction("HARVEST", "WHEAT-001");
+        assert!(delegation.authorizes(&transaction, 999));
+
+        // not after the expiration date
+        assert!(!delegation.authorizes(&transaction, 1000));
+
+        // and not for other event types or batches
+        assert!(!delegation.authorizes(&create_transaction("SALE", "WHEAT-001"), 0));
+        assert!(!delegation.authorizes(&create_transaction("HARVEST", "CORN-001"), 0));
+    }
+
+    #[test]
+    fn should_authorize_all_batches_by_default() {
+        let delegation =
+            Delegation::parse(r#"{"event_types": ["HARVEST"], "expires_at": 1000}"#).unwrap();
+
+        assert!(delegation.authorizes(&create_transaction("HARVEST", "CORN-001"), 0));
+    }
+
+    #[test]
+    fn should_reject_invalid_delegations() {
+        let result = Delegation::parse(r#"{"batch_ids": ["WHEAT-001"]}"#);
+        assert_eq!(result, Err(TransactionError::InvalidDelegation));
+    }
+
+    fn create_transaction(event_type: &str, batch_id: &str) -> Transaction {
+        Transaction {
+            event_type: event_type.into(),
+            batch_id: batch_id.to_string(),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,202 @@
+// Max change of the difficulty in a single adjustment (in bits, so 4 times more or less work)
+// It limits how fast a sudden change of the hash rate (or fake timestamps) can move the difficulty
+const MAX_ADJUSTMENT_BITS: i64 = 2;
+
+// Max difficulty of a sha256 hash
+const MAX_DIFFICULTY: i64 = 256;
+
+// Amount of leading zero bits that the hash of each block must have
+// It's adjusted every "adjustment_interval" blocks, so the blocks are mined every "target_block_time_ms" on average
+// whatever the hash rate of the network. Every node derives the same value from the timestamps of the chain
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Difficulty {
+    initial: u32,
+    // blocks between adjustments, 0 keeps the initial difficulty forever
+    adjustment_interval: u64,
+    target_block_time_ms: u64,
+}
+
+impl Difficulty {
+    pub fn new(initial: u32, adjustment_interval: u64, target_block_time_ms: u64) -> Difficulty {
+        Difficulty {
+            initial,
+            // a single block has no interval to measure
+            adjustment_interval: match adjustment_interval {
+                1 => 2,
+                interval => interval,
+            },
+            target_block_time_ms,
+        }
+    }
+
+    pub fn fixed(difficulty: u32) -> Difficulty {
+        Difficulty::new(difficulty, 0, 0)
+    }
+
+    // Difficulty of the first blocks, and of all of them if it's fixed
+    pub fn initial(&self) -> u32 {
+        self.initial
+    }
+
+    pub fn is_adjustable(&self) -> bool {
+        self.adjustment_interval > 0 && self.target_block_time_ms > 0
+    }
+
+    // Returns the difficulty of the block at an index, from the timestamps of the blocks before it
+    // The genesis block has a fixed timestamp, so the periods are counted from the block 1:
+    // the difficulty of each period is adjusted with the time that the previous period took
+    // It adjusts again every period before the block, the chain keeps them instead (see DifficultyPeriods)
+    pub fn at<F>(&self, index: u64, timestamp_of: F) -> u32
+    where
+        F: Fn(u64) -> i64,
+    {
+        if !self.is_adjustable() || index == 0 {
+            return self.initial;
+        }
+
+        let interval = self.adjustment_interval;
+        let period = (index - 1) / interval;
+        (1..=period).fold(self.initial, |difficulty, period| {
+            let first = (period - 1) * interval + 1;
+            let last = period * interval;
+            let elapsed_ms = timestamp_of(last) - timestamp_of(first);
+            self.adjust(difficulty, elapsed_ms)
+        })
+    }
+
+    // Returns the difficulty for the next period, knowing how long the last one took
+    pub fn adjust(&self, difficulty: u32, elapsed_ms: i64) -> u32 {
+        let expected_ms = ((self.adjustment_interval - 1) * self.target_block_time_ms) as f64;
+        // at least a millisecond, to not divide by zero
+        let elapsed_ms = elapsed_ms.max(1) as f64;
+
+        // each bit doubles the expected amount of hashes
+        let bits = (expected_ms / elapsed_ms).log2().round() as i64;
+        let bits = bits.clamp(-MAX_ADJUSTMENT_BITS, MAX_ADJUSTMENT_BITS);
+        (difficulty as i64 + bits).clamp(0, MAX_DIFFICULTY) as u32
+    }
+}
+
+// The difficulty of each period of a chain, adjusted once when the period before it ends, so the difficulty of
+// a block is found without adjusting again every period since the genesis
+// Updated with each block appended to the main chain, and built again with the rest of the state of the chain
+// when a reorganization replaces its last blocks
+#[derive(Debug, Clone)]
+pub struct DifficultyPeriods {
+    difficulty: Difficulty,
+    // difficulty of each period, from the first one
+    periods: Vec<u32>,
+}
+
+impl DifficultyPeriods {
+    pub fn new(difficulty: Difficulty) -> DifficultyPeriods {
+        DifficultyPeriods {
+            difficulty,
+            periods: vec![difficulty.initial],
+        }
+    }
+
+    // Adds the next block of the chain, the difficulty of the next period is adjusted after the last block of a period
+    // "timestamp_of" reads the timestamps of the blocks until this one
+    pub fn apply<F>(&mut self, index: u64, timestamp_of: F)
+    where
+        F: Fn(u64) -> i64,
+    {
+        let interval = self.difficulty.adjustment_interval;
+        if !self.difficulty.is_adjustable() || index == 0 || !index.is_multiple_of(interval) {
+            return;
+        }
+        // a period already adjusted (or one missed) is not adjusted again
+        if self.periods.len() as u64 != index / interval {
+            return;
+        }
+
+        let elapsed_ms = timestamp_of(index) - timestamp_of(index + 1 - interval);
+        let last = self.periods[self.periods.len() - 1];
+        self.periods.push(self.difficulty.adjust(last, elapsed_ms));
+    }
+
+    // Returns the difficulty of the block at an index, up to the block after the last one applied
+    pub fn at(&self, index: u64) -> u32 {
+        if !self.difficulty.is_adjustable() || index == 0 {
+            return self.difficulty.initial;
+        }
+
+        let period = ((index - 1) / self.difficulty.adjustment_interval) as usize;
+        self.periods[period.min(self.periods.len() - 1)]
+    }
+
+    // The periods of the chain until a block, to go on with the blocks of a fork after it
+    pub fn until(&self, index: u64) -> DifficultyPeriods {
+        let kept = match self.difficulty.is_adjustable() {
+            true => (index / self.difficulty.adjustment_interval) as usize + 1,
+            false => 1,
+        };
+
+        DifficultyPeriods {
+            difficulty: self.difficulty,
+            periods: self.periods[..kept.min(self.periods.len())].to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_keep_a_fixed_difficulty() {
+        let difficulty = Difficulty::fixed(10);
+
+        assert!(!difficulty.is_adjustable());
+        assert_eq!(difficulty.at(1000, |index| index as i64), 10);
+    }
+
+    #[test]
+    fn should_adjust_to_the_block_time_of_the_previous_period() {
+        // periods of 10 blocks, aiming for a block per second
+        let difficulty = Difficulty::new(10, 10, 1000);
+
+        // blocks 4 times faster than the target
+        let fast = |index: u64| index as i64 * 250;
+        assert_eq!(difficulty.at(10, fast), 10);
+        assert_eq!(difficulty.at(11, fast), 12);
+        assert_eq!(difficulty.at(21, fast), 14);
+
+        // on target, a bit slower or a bit faster
+        assert_eq!(difficulty.at(11, |index| index as i64 * 1000), 10);
+        assert_eq!(difficulty.at(11, |index| index as i64 * 1200), 10);
+        assert_eq!(difficulty.at(11, |index| index as i64 * 800), 10);
+
+        // much slower, but limited by the max adjustment
+        assert_eq!(difficulty.at(11, |index| index as i64 * 100_000), 8);
+        assert_eq!(difficulty.adjust(1, 1_000_000), 0);
+    }
+
+    #[test]
+    fn should_keep_the_difficulty_of_each_period() {
+        let difficulty = Difficulty::new(10, 10, 1000);
+        // blocks 4 times faster than the target during the first 2 periods, then 4 times slower
+        let timestamp_of = |index: u64| match index <= 20 {
+            true => index as i64 * 250,
+            false => 5000 + (index as i64 - 20) * 4000,
+        };
+
+        let mut periods = DifficultyPeriods::new(difficulty);
+        for index in 1..=40 {
+            // the difficulty of each block is the one adjusted again from the genesis
+            assert_eq!(
+                periods.at(index),
+                difficulty.at(index, timestamp_of),
+                "{}",
+                index
+            );
+            periods.apply(index, timestamp_of);
+        }
+        assert_eq!(periods.at(41), 10);
+
+        // a fork after a block goes on from the periods until it
+        assert_eq!(periods.until(15).at(16), 12);
+        assert_eq!(periods.until(20).at(21), 14);
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use super::{Transaction, TransactionError};
+
+pub const DOCUMENT_EVENT: &str = "DOCUMENT";
+pub const CHUNK_EVENT: &str = "CHUNK";
+
+// Documents bigger than this (e.g. certificates) must be split in chunks
+pub const MAX_CHUNK_SIZE: usize = 16 * 1024;
+
+// Announces a document (e.g. a certificate) that is too large for a single transaction
+// The content is sent afterwards in CHUNK events of the same sender, and readers reassemble it and verify its hash
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DocumentManifest {
+    // hex encoded sha256 of the whole content, which also identifies the document
+    pub hash: String,
+    // bytes of the whole content
+    pub size: usize,
+    pub chunks: usize,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+// Part of the content of a document, the chunks are concatenated by their index
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DocumentChunk {
+    // hash of the document in its manifest
+    pub document: String,
+    pub index: usize,
+    pub content: String,
+}
+
+// A document announced on chain, with its content if it has been delivered
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub manifest: DocumentManifest,
+    // the DOCUMENT event
+    pub transaction: Transaction,
+    pub content: Result<String, DocumentError>,
+}
+
+// Why a document has not been delivered yet
+#[derive(Error, Clone, PartialEq, Debug)]
+pub enum DocumentError {
+    #[error("Missing chunks {0:?}")]
+    Incomplete(Vec<usize>),
+    #[error("The content does not match the hash of the document")]
+    Corrupted,
+}
+
+impl DocumentManifest {
+    // Parses and validates the manifest contained in the data of a DOCUMENT event
+    pub fn parse(data: &str) -> Result<DocumentManifest, TransactionError> {
+        let manifest: DocumentManifest =
+            serde_json::from_str(data).map_err(|_| TransactionError::InvalidDocument)?;
+
+        let enough_chunks = manifest.chunks * MAX_CHUNK_SIZE >= manifest.size;
+        if !is_valid_hash(&manifest.hash) || manifest.chunks == 0 || !enough_chunks {
+            return Err(TransactionError::InvalidDocument);
+        }
+
+        Ok(manifest)
+    }
+
+    // Reassembles the document from the chunks of the chain, in any order
+    // Only the first chunk of each index counts, and the document is not delivered until all of them are present
+    pub fn assemble<'a, I>(&self, chunks: I) -> Result<String, DocumentError>
+    where
+        I: IntoIterator<Item = &'a DocumentChunk>,
+    {
+        let mut contents: BTreeMap<usize, &str> = BTreeMap::new();
+        for chunk in chunks
+            .into_iter()
+            .filter(|chunk| chunk.document == self.hash)
+        {
+            contents.entry(chunk.index).or_insert(&chunk.content);
+        }
+
+        let missing: Vec<usize> = (0..self.chunks)
+            .filter(|index| !contents.contains_key(index))
+            .collect();
+        if !missing.is_empty() {
+            return Err(DocumentError::Incomplete(missing));
+        }
+
+        let content: String = contents.into_values().collect();
+        if content.len() != self.size || hash_content(&content) != self.hash {
+            return Err(DocumentError::Corrupted);
+        }
+
+        Ok(content)
+    }
+}
+
+impl DocumentChunk {
+    // Parses and validates the chunk contained in the data of a CHUNK event
+    pub fn parse(data: &str) -> Result<DocumentChunk, TransactionError> {
+        let chunk: DocumentChunk =
+            serde_json::from_str(data).map_err(|_| TransactionError::InvalidDocument)?;
+
+        let valid_size = !chunk.content.is_empty() && chunk.content.len() <= MAX_CHUNK_SIZE;
+        if !is_valid_hash(&chunk.document) || !valid_size {
+            return Err(TransactionError::InvalidDocument);
+        }
+
+        Ok(chunk)
+    }
+
+    // Splits a content in the transactions needed to record it on chain, the manifest first
+    pub fn split(
+        transaction: &Transaction,
+        content: &str,
+        name: Option<String>,
+    ) -> Vec<Transaction> {
+        let hash = hash_content(content);
+        let mut parts: Vec<String> = Vec::new();
+        let mut part = String::new();
+        // a character is never split between two chunks
+        for c in content.chars() {
+            if part.len() + c.len_utf8() > MAX_CHUNK_SIZE {
+                parts.push(std::mem::take(&mut part));
+            }
+            part.push(c);
+        }
+        parts.push(part);
+
+        let manifest = DocumentManifest {
+            hash: hash.clone(),
+            size: content.len(),
+            chunks: parts.len(),
+            name,
+        };
+        let mut transactions = vec![Transaction {
+            data: serde_json::to_string(&manifest).unwrap().into(),
+            event_type: DOCUMENT_EVENT.into(),
+            ..transaction.clone()
+        }];
+        for (index, content) in parts.into_iter().enumerate() {
+            let chunk = DocumentChunk {
+                document: hash.clone(),
+                index,
+                content,
+            };
+            transactions.push(Transaction {
+                data: serde_json::to_string(&chunk).unwrap().into(),
+                event_type: CHUNK_EVENT.into(),
+                ..transaction.clone()
+            });
+        }
+
+        transactions
+    }
+}
+
+pub fn hash_content(content: &str) -> String {
+    hex::encode(Sha256::digest(content.as_bytes()))
+}
+
+fn is_valid_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::fixtures::{alice, signed};
+
+    #[test]
+    fn should_validate_manifests_and_chunks() {
+        let hash = hash_content("certificate");
+        let data = format!(r#"{{"hash": "{}", "size": 11, "chunks": 1}}"#, hash);
+        assert!(DocumentManifest::parse(&data).is_ok());
+
+        // a single chunk can't hold the whole document
+        let data = format!(r#"{{"hash": "{}", "size": 20000, "chunks": 1}}"#, hash);
+        let error = DocumentManifest::parse(&data).unwrap_err();
+        assert_eq!(error, TransactionError::InvalidDocument);
+
+        let data = r#"{"hash": "1234", "size": 11, "chunks": 1}"#;
+        let error = DocumentManifest::parse(data).unwrap_err();
+        assert_eq!(error, TransactionError::InvalidDocument);
+
+        let too_large = "a".repeat(MAX_CHUNK_SIZE + 1);
+        let data = format!(
+            r#"{{"document": "{}", "index": 0, "content": "{}"}}"#,
+            hash, too_large
+        );
+        let error = DocumentChunk::parse(&data).unwrap_err();
+        assert_eq!(error, TransactionError::InvalidDocument);
+    }
+
+    #[test]
+    fn should_reassemble_split_documents() {
+        let content = "é".repeat(MAX_CHUNK_SIZE);
+        let transaction = Transaction {
+            sender: alice(),
+            recipient: alice(),
+            batch_id: "WHEAT-001".to_string(),
+            ..Default::default()
+        };
+
+        let transactions = signed(DocumentChunk::split(&transaction, &content, None));
+        assert_eq!(transactions.len(), 3);
+        assert!(transactions.iter().all(|tx| tx.validate().is_ok()));
+
+        let manifest = DocumentManifest::parse(&transactions[0].data.as_json()).unwrap();
+        let mut chunks: Vec<DocumentChunk> = transactions[1..]
+            .iter()
+            .map(|tx| DocumentChunk::parse(&tx.data.as_json()).unwrap())
+            .collect();
+        chunks.reverse();
+        assert_eq!(manifest.assemble(&chunks), Ok(content));
+
+        // incomplete sets are not delivered
+        assert_eq!(
+            manifest.assemble(&chunks[..1]),
+            Err(DocumentError::Incomplete(vec![0]))
+        );
+
+        chunks[0].content.push('!');
+        assert_eq!(manifest.assemble(&chunks), Err(DocumentError::Corrupted));
+    }
+}
@@ -0,0 +1,310 @@
+use std::str::FromStr;
+
+use serde::Serialize;
+use serde_json::{Map, Number, Value};
+use thiserror::Error;
+
+#[derive(Error, PartialEq, Debug)]
+pub enum EncodingError {
+    #[error("Unknown encoding")]
+    UnknownEncoding,
+
+    #[error("Value cannot be encoded")]
+    InvalidValue,
+
+    #[error("Invalid encoded data")]
+    InvalidData,
+}
+
+// Deterministic representations of the chain data, so external verifiers can reproduce them byte by byte
+// "Binary" is compact, "CanonicalJson" can be reproduced by verifiers that only understand JSON (e.g. browsers)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Binary,
+    CanonicalJson,
+}
+
+impl FromStr for Encoding {
+    type Err = EncodingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "binary" => Ok(Encoding::Binary),
+            "canonical-json" => Ok(Encoding::CanonicalJson),
+            _ => Err(EncodingError::UnknownEncoding),
+        }
+    }
+}
+
+// Type tags of the binary encoding
+const NULL: u8 = 0x00;
+const FALSE: u8 = 0x01;
+const TRUE: u8 = 0x02;
+const UNSIGNED: u8 = 0x03;
+const NEGATIVE: u8 = 0x04;
+const FLOAT: u8 = 0x05;
+const STRING: u8 = 0x06;
+const ARRAY: u8 = 0x07;
+const OBJECT: u8 = 0x08;
+
+// Encodes any serializable value in the chosen encoding
+pub fn encode<T: Serialize>(value: &T, encoding: Encoding) -> Result<Vec<u8>, EncodingError> {
+    let value = serde_json::to_value(value).map_err(|_| EncodingError::InvalidValue)?;
+
+    let mut output = Vec::new();
+    match encoding {
+        Encoding::Binary => write_binary(&value, &mut output),
+        Encoding::CanonicalJson => output.extend(canonical_json(&value).into_bytes()),
+    }
+
+    Ok(output)
+}
+
+// Decodes data in any of the encodings, also the plain JSON of the API
+pub fn decode(data: &[u8], encoding: Encoding) -> Result<Value, EncodingError> {
+    match encoding {
+        Encoding::CanonicalJson => {
+            serde_json::from_slice(data).map_err(|_| EncodingError::InvalidData)
+        }
+        Encoding::Binary => {
+            let mut input = data;
+            let value = read_binary(&mut input)?;
+            // trailing bytes mean that the data is not what we think it is
+            if !input.is_empty() {
+                return Err(EncodingError::InvalidData);
+            }
+            Ok(value)
+        }
+    }
+}
+
+// JSON with the keys of all objects sorted, no whitespace and a fixed number format:
+// integers (and floats without decimals) as plain integers, other floats never use exponents
+fn canonical_json(value: &Value) -> String {
+    match value {
+        Value::Number(number) => canonical_number(number),
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(canonical_json).collect();
+            format!("[{}]", items.join(","))
+        }
+        Value::Object(fields) => {
+            let fields: Vec<String> = sorted_fields(fields)
+                .into_iter()
+                .map(|(key, value)| {
+                    format!("{}:{}", Value::from(key.as_str()), canonical_json(value))
+                })
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        }
+        // null, booleans and strings already have a single representation
+        _ => value.to_string(),
+    }
+}
+
+fn canonical_number(number: &Number) -> String {
+    match number.as_f64() {
+        Some(float) if number.is_f64() && float.fract() == 0.0 && float.abs() < 1e15 => {
+            format!("{}", float as i64)
+        }
+        Some(float) if number.is_f64() => format!("{}", float),
+        _ => number.to_string(),
+    }
+}
+
+// Tag, then fixed size big-endian numbers, or a 4-byte length followed by the contents
+fn write_binary(value: &Value, output: &mut Vec<u8>) {
+    match value {
+        Value::Null => output.push(NULL),
+        Value::Bool(false) => output.push(FALSE),
+        Value::Bool(true) => output.push(TRUE),
+        Value::Number(number) => {
+            if let Some(unsigned) = number.as_u64() {
+                output.push(UNSIGNED);
+                output.extend(unsigned.to_be_bytes());
+            } else if let Some(negative) = number.as_i64() {
+                output.push(NEGATIVE);
+                output.extend(negative.to_be_bytes());
+            } else {
+                output.push(FLOAT);
+                output.extend(number.as_f64().unwrap_or_default().to_be_bytes());
+            }
+        }
+        Value::String(string) => {
+            output.push(STRING);
+            write_bytes(string.as_bytes(), output);
+        }
+        Value::Array(items) => {
+            output.push(ARRAY);
+            output.extend((items.len() as u32).to_be_bytes());
+            items.iter().for_each(|item| write_binary(item, output));
+        }
+        Value::Object(fields) => {
+            output.push(OBJECT);
+            output.extend((fields.len() as u32).to_be_bytes());
+            for (key, value) in sorted_fields(fields) {
+                write_bytes(key.as_bytes(), output);
+                write_binary(value, output);
+            }
+        }
+    }
+}
+
+fn write_bytes(bytes: &[u8], output: &mut Vec<u8>) {
+    output.extend((bytes.len() as u32).to_be_bytes());
+    output.extend(bytes);
+}
+
+fn read_binary(input: &mut &[u8]) -> Result<Value, EncodingError> {
+    let tag = take(input, 1)?[0];
+    let value = match tag {
+        NULL => Value::Null,
+        FALSE => Value::Bool(false),
+        TRUE => Value::Bool(true),
+        UNSIGNED => Value::from(u64::from_be_bytes(take_array(input)?)),
+        NEGATIVE => Value::from(i64::from_be_bytes(take_array(input)?)),
+        FLOAT => Value::from(f64::from_be_bytes(take_array(input)?)),
+        STRING => Value::String(read_string(input)?),
+        ARRAY => {
+            let count = read_length(input)?;
+            let items = (0..count).map(|_| read_binary(input));
+            Value::Array(items.collect::<Result<_, _>>()?)
+        }
+        OBJECT => {
+            let count = read_length(input)?;
+            let mut fields = Map::new();
+            for _ in 0..count {
+                let key = read_string(input)?;
+                fields.insert(key, read_binary(input)?);
+            }
+            Value::Object(fields)
+        }
+        _ => return Err(EncodingError::InvalidData),
+    };
+
+    Ok(value)
+}
+
+fn read_string(input: &mut &[u8]) -> Result<String, EncodingError> {
+    let length = read_length(input)?;
+    String::from_utf8(take(input, length)?.to_vec()).map_err(|_| EncodingError::InvalidData)
+}
+
+fn read_length(input: &mut &[u8]) -> Result<usize, EncodingError> {
+    Ok(u32::from_be_bytes(take_array(input)?) as usize)
+}
+
+fn take_array<const N: usize>(input: &mut &[u8]) -> Result<[u8; N], EncodingError> {
+    let bytes = take(input, N)?;
+    bytes.try_into().map_err(|_| EncodingError::InvalidData)
+}
+
+fn take<'a>(input: &mut &'a [u8], amount: usize) -> Result<&'a [u8], EncodingError> {
+    if input.len() < amount {
+        return Err(EncodingError::InvalidData);
+    }
+    let (taken, rest) = input.split_at(amount);
+    *input = rest;
+    Ok(taken)
+}
+
+// Keys are sorted by their UTF-8 bytes, regardless of how the map is implemented
+fn sorted_fields(fields: &Map<String, Value>) -> Vec<(&String, &Value)> {
+    let mut sorted: Vec<(&String, &Value)> = fields.iter().collect();
+    sorted.sort_by(|(a, _), (b, _)| a.as_bytes().cmp(b.as_bytes()));
+    sorted
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    // Value, its canonical JSON and its binary encoding (in hex)
+    fn test_vectors() -> Vec<(Value, &'static str, &'static str)> {
+        vec![
+            (json!(null), "null", "00"),
+            (json!(true), "true", "02"),
+            (json!(42), "42", "03000000000000002a"),
+            (json!(-1), "-1", "04ffffffffffffffff"),
+            (json!(2.5), "2.5", "054004000000000000"),
+            (json!(1e21), "1000000000000000000000", "05444b1ae4d6e2ef50"),
+            (json!("Blé"), "\"Blé\"", "0600000004426cc3a9"),
+            (
+                json!({"quantity": 500, "crop": "wheat"}),
+                r#"{"crop":"wheat","quantity":500}"#,
+                "08000000020000000463726f7006000000057768656174\
+                 000000087175616e746974790300000000000001f4",
+            ),
+            (
+                json!([{"b": [], "a": null}]),
+                r#"[{"a":null,"b":[]}]"#,
+                "0700000001080000000200000001610000000001620700000000",
+            ),
+        ]
+    }
+
+    #[test]
+    fn should_match_test_vectors() {
+        for (value, expected_json, expected_binary) in test_vectors() {
+            let json = encode(&value, Encoding::CanonicalJson).unwrap();
+            assert_eq!(String::from_utf8(json).unwrap(), expected_json);
+
+            let binary = encode(&value, Encoding::Binary).unwrap();
+            assert_eq!(hex::encode(binary), expected_binary, "{}", expected_json);
+        }
+    }
+
+    #[test]
+    fn should_cross_verify_both_encodings() {
+        for (value, _, _) in test_vectors() {
+            let binary = encode(&value, Encoding::Binary).unwrap();
+            let decoded = decode(&binary, Encoding::Binary).unwrap();
+
+            // the binary encoding keeps the same value that the canonical JSON represents
+            let json = encode(&value, Encoding::CanonicalJson).unwrap();
+            let decoded_json = encode(&decoded, Encoding::CanonicalJson).unwrap();
+            assert_eq!(decoded_json, json);
+
+            // and the canonical JSON is stable when parsed and encoded again
+            let parsed: Value = serde_json::from_slice(&json).unwrap();
+            assert_eq!(encode(&parsed, Encoding::CanonicalJson).unwrap(), json);
+        }
+    }
+
+    #[test]
+    fn should_reject_invalid_binary_data() {
+        let binary = encode(&json!({"crop": "wheat"}), Encoding::Binary).unwrap();
+
+        let truncated = &binary[..binary.len() - 1];
+        assert_eq!(
+            decode(truncated, Encoding::Binary),
+            Err(EncodingError::InvalidData)
+        );
+
+        let mut trailing = binary.clone();
+        trailing.push(NULL);
+        assert_eq!(
+            decode(&trailing, Encoding::Binary),
+            Err(EncodingError::InvalidData)
+        );
+
+        assert_eq!(
+            decode(&[0xff], Encoding::Binary),
+            Err(EncodingError::InvalidData)
+        );
+    }
+
+    #[test]
+    fn should_parse_encoding_names() {
+        assert_eq!(Encoding::from_str("binary"), Ok(Encoding::Binary));
+        assert_eq!(
+            Encoding::from_str("canonical-json"),
+            Ok(Encoding::CanonicalJson)
+        );
+        assert_eq!(
+            Encoding::from_str("xml"),
+            Err(EncodingError::UnknownEncoding)
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::{Address, Transaction, TransactionError};
+
+pub const ESCROW_EVENT: &str = "ESCROW";
+
+// Custody transfer of a batch from the sender of the ESCROW event to the recipient, that only becomes
+// effective if a condition event (e.g. a QUALITY_CHECK by a named inspector) appears before the deadline
+// e.g. payment-against-quality: the buyer only takes the batch if the inspector passes it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Escrow {
+    // type of the event that releases the batch
+    pub condition_event: String,
+    // the only actor that can release the batch
+    pub inspector: Address,
+    // fields that the payload of the condition event must have (e.g. {"result": "PASS"})
+    #[serde(default)]
+    pub expected: Map<String, Value>,
+    // timestamp in milliseconds
+    pub deadline: i64,
+}
+
+// Whether the custody of an escrowed batch was transferred
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EscrowState {
+    // still waiting for the condition event
+    Pending,
+    // the condition event appeared in time, the recipient is the new custodian
+    Released,
+    // the deadline passed without the condition event, the sender keeps the batch
+    Expired,
+}
+
+impl Escrow {
+    // Parses the escrow contained in the data of an ESCROW event
+    pub fn parse(data: &str) -> Result<Escrow, TransactionError> {
+        let escrow: Escrow =
+            serde_json::from_str(data).map_err(|_| TransactionError::InvalidEscrow)?;
+        if escrow.condition_event.is_empty() || escrow.condition_event == ESCROW_EVENT {
+            return Err(TransactionError::InvalidEscrow);
+        }
+
+        Ok(escrow)
+    }
+
+    // Checks if an event of the escrowed batch, recorded at a given time, releases the batch
+    pub fn is_released_by(&self, transaction: &Transaction, timestamp: i64) -> bool {
+        let actor = transaction
+            .on_behalf_of
+            .as_ref()
+            .unwrap_or(&transaction.sender);
+        if transaction.event_type != self.condition_event
+            || *actor != self.inspector
+            || timestamp >= self.deadline
+        {
+            return false;
+        }
+
+        let payload: Value =
+            serde_json::from_str(&transaction.data.as_json()).unwrap_or(Value::Null);
+        self.expected
+            .iter()
+            .all(|(field, value)| payload.get(field) == Some(value))
+    }
+
+    // Returns the state of the escrow at a given time, from the events of the batch recorded after it
+    pub fn state<'a, I>(&self, later_events: I, now: i64) -> EscrowState
+    where
+        I: IntoIterator<Item = (i64, &'a Transaction)>,
+    {
+        let released = later_events
+            .into_iter()
+            .any(|(timestamp, transaction)| self.is_released_by(transaction, timestamp));
+
+        match released {
+            true => EscrowState::Released,
+            false if now >= self.deadline => EscrowState::Expired,
+            false => EscrowState::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::fixtures::{alice, carol};
+
+    #[test]
+    fn should_release_with_the_condition_event_before_the_deadline() {
+        let inspector = carol();
+        let escrow = create_escrow(&inspector);
+        let pass = create_check(&inspector, r#"{"result": "PASS", "grade": "A"}"#);
+        let fail = create_check(&inspector, r#"{"result": "FAIL"}"#);
+        let impostor = create_check(&alice(), r#"{"result": "PASS"}"#);
+
+        assert!(escrow.is_released_by(&pass, 999));
+        assert!(!escrow.is_released_by(&pass, 1000));
+        assert!(!escrow.is_released_by(&fail, 0));
+        assert!(!escrow.is_released_by(&impostor, 0));
+
+        assert_eq!(escrow.state([(10, &fail)], 500), EscrowState::Pending);
+        assert_eq!(escrow.state([(10, &fail)], 1000), EscrowState::Expired);
+        assert_eq!(
+            escrow.state([(10, &fail), (20, &pass)], 1000),
+            EscrowState::Released
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_escrows() {
+        let result = Escrow::parse(r#"{"condition_event": "QUALITY_CHECK", "deadline": 1000}"#);
+        assert_eq!(result, Err(TransactionError::InvalidEscrow));
+    }
+
+    fn create_escrow(inspector: &Address) -> Escrow {
+        let data = format!(
+            r#"{{"condition_event": "QUALITY_CHECK", "inspector": "{}", "expected": {{"result": "PASS"}}, "deadline": 1000}}"#,
+            inspector
+        );
+        Escrow::parse(&data).unwrap()
+    }
+
+    fn create_check(sender: &Address, data: &str) -> Transaction {
+        Transaction {
+            sender: sender.clone(),
+            recipient: sender.clone(),
+            data: data.into(),
+            batch_id: "WHEAT-001".to_string(),
+            event_type: "QUALITY_CHECK".into(),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::panic::RefUnwindSafe;
+
+use tokio::sync::broadcast::{
+    self,
+    error::{RecvError, TryRecvError},
+};
+
+use super::{Block, OrphanedBlock, ReorgEvent, Transaction};
+
+// Enough to not lose events between two polls of a subscriber in normal conditions
+const EVENT_BUS_CAPACITY: usize = 4096;
+
+// Changes of the chain and the pool that other modules can react to
+#[derive(Debug, Clone)]
+pub enum ChainEvent {
+    // A block was appended to the main chain
+    BlockApplied(Block),
+    // A transaction was accepted in the pool, it's not mined yet
+    TxAccepted(Transaction),
+    // A valid block competing with one of the main chain was archived
+    ForkArchived(OrphanedBlock),
+    // A pending transaction was removed from the pool because a new block made it invalid
+    TxDropped(Transaction, String),
+    // The last blocks of the main chain were replaced by the ones of a longer fork
+    ReorgOccurred(ReorgEvent),
+}
+
+// Publishes the chain events to any number of subscribers, without knowing who they are
+// The chain and the pool publish, so new consumers only need to subscribe instead of being called by them
+// Cloning it is cheap, all the clones publish to the same subscribers
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<ChainEvent>,
+}
+
+// The channel keeps its state behind its own locks, so a panic never leaves it inconsistent
+// (e.g. the peer system catches the panics of unreachable peers while using the chain)
+impl RefUnwindSafe for EventBus {}
+
+// Events received by a subscriber since it subscribed
+pub struct EventSubscription {
+    receiver: broadcast::Receiver<ChainEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        let (sender, _) = broadcast::channel(EVENT_BUS_CAPACITY);
+
+        EventBus { sender }
+    }
+
+    pub fn publish(&self, event: ChainEvent) {
+        // it only fails when there are no subscribers, so nobody misses the event
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> EventSubscription {
+        EventSubscription {
+            receiver: self.sender.subscribe(),
+        }
+    }
+}
+
+impl EventSubscription {
+    // Returns the events published since the last call, without blocking
+    // A subscriber too slow to keep up with the bus loses the oldest events
+    pub fn drain(&mut self) -> Vec<ChainEvent> {
+        let mut events = Vec::new();
+        loop {
+            match self.receiver.try_recv() {
+                Ok(event) => events.push(event),
+                Err(TryRecvError::Lagged(skipped)) => {
+                    warn!("Event subscriber lagging behind, {} events lost", skipped)
+                }
+                Err(TryRecvError::Empty) | Err(TryRecvError::Closed) => return events,
+            }
+        }
+    }
+
+    // Waits for the next event, for the subscribers that push the events as soon as they happen
+    // Returns none when the bus is gone
+    pub async fn recv(&mut self) -> Option<ChainEvent> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) => return Some(event),
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Event subscriber lagging behind, {} events lost", skipped)
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        fixtures::{alice, sign},
+        Blockchain, TransactionPool,
+    };
+
+    #[test]
+    fn should_publish_chain_and_pool_events() {
+        let blockchain = Blockchain::new(0);
+        let pool = TransactionPool::new(blockchain.event_bus());
+        let mut subscription = blockchain.event_bus().subscribe();
+        let mut other_subscription = blockchain.event_bus().subscribe();
+
+        let transaction = sign(Transaction {
+            sender: alice(),
+            ..Default::default()
+        });
+        pool.add_transaction(transaction.clone());
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(1, 0, previous_hash, vec![transaction]);
+        blockchain.add_block(block.clone()).unwrap();
+
+        let events = subscription.drain();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[0], ChainEvent::TxAccepted(_)));
+        assert!(
+            matches!(&events[1], ChainEvent::BlockApplied(applied) if applied.header.hash == block.header.hash)
+        );
+        assert!(subscription.drain().is_empty());
+
+        // each subscriber receives all the events
+        assert_eq!(other_subscription.drain().len(), 2);
+    }
+}
@@ -0,0 +1,173 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+// What happened to a batch, the steps of its lifecycle are known so typos can't go unnoticed
+// Other events (e.g. "PROFILE", "DISPUTE" or consortium-specific ones) are custom, and serialized as they are,
+// so the transactions keep the same hash as when the event type was a plain string
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum EventType {
+    Harvest,
+    Transport,
+    Storage,
+    Processing,
+    QualityCheck,
+    Sale,
+    // The batch is withdrawn from the supply chain, e.g. after a contamination
+    Recall,
+    Custom(String),
+}
+
+const LIFECYCLE: [EventType; 7] = [
+    EventType::Harvest,
+    EventType::Transport,
+    EventType::Storage,
+    EventType::Processing,
+    EventType::QualityCheck,
+    EventType::Sale,
+    EventType::Recall,
+];
+
+impl EventType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            EventType::Harvest => "HARVEST",
+            EventType::Transport => "TRANSPORT",
+            EventType::Storage => "STORAGE",
+            EventType::Processing => "PROCESSING",
+            EventType::QualityCheck => "QUALITY_CHECK",
+            EventType::Sale => "SALE",
+            EventType::Recall => "RECALL",
+            EventType::Custom(name) => name,
+        }
+    }
+
+    // Whether the event is a step of the lifecycle of a batch, which moves it between the states of BatchState
+    pub fn is_lifecycle(&self) -> bool {
+        !matches!(self, EventType::Custom(_))
+    }
+
+    // Returns the lifecycle event that a custom one was probably meant to be (e.g. "HARVSET" or "harvest")
+    pub fn probable_typo(&self) -> Option<EventType> {
+        let name = match self {
+            EventType::Custom(name) if !name.is_empty() => name.to_uppercase(),
+            _ => return None,
+        };
+
+        LIFECYCLE.into_iter().find(|known| {
+            // short names are easily one edit away from an unrelated one
+            let max_distance = if known.as_str().len() <= 4 { 1 } else { 2 };
+            edit_distance(&name, known.as_str()) <= max_distance
+        })
+    }
+}
+
+impl Default for EventType {
+    fn default() -> Self {
+        EventType::Custom(String::new())
+    }
+}
+
+impl From<&str> for EventType {
+    fn from(name: &str) -> Self {
+        LIFECYCLE
+            .into_iter()
+            .find(|known| known.as_str() == name)
+            .unwrap_or_else(|| EventType::Custom(name.to_string()))
+    }
+}
+
+impl From<String> for EventType {
+    fn from(name: String) -> Self {
+        EventType::from(name.as_str())
+    }
+}
+
+impl From<EventType> for String {
+    fn from(event_type: EventType) -> Self {
+        event_type.to_string()
+    }
+}
+
+impl fmt::Display for EventType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// The system events are still identified by their names (e.g. PROFILE_EVENT)
+impl PartialEq<str> for EventType {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for EventType {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<String> for EventType {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == other
+    }
+}
+
+// Amount of single-character insertions, deletions or substitutions to go from a text to another one
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_serialize_as_the_plain_name() {
+        let parsed: Vec<EventType> =
+            serde_json::from_str(r#"["QUALITY_CHECK", "PROFILE", "harvest"]"#).unwrap();
+        assert_eq!(parsed[0], EventType::QualityCheck);
+        assert_eq!(parsed[1], EventType::Custom("PROFILE".to_string()));
+        // only the exact names are lifecycle events, other spellings are kept as they are
+        assert_eq!(parsed[2], EventType::Custom("harvest".to_string()));
+
+        let serialized = serde_json::to_string(&parsed).unwrap();
+        assert_eq!(serialized, r#"["QUALITY_CHECK","PROFILE","harvest"]"#);
+        assert!(EventType::Sale == "SALE");
+    }
+
+    #[test]
+    fn should_detect_probable_typos() {
+        let typo = |name: &str| EventType::from(name).probable_typo();
+        assert_eq!(typo("HARVSET"), Some(EventType::Harvest));
+        assert_eq!(typo("harvest"), Some(EventType::Harvest));
+        assert_eq!(typo("QUALITYCHECK"), Some(EventType::QualityCheck));
+        assert_eq!(typo("SALES"), Some(EventType::Sale));
+
+        for name in [
+            "PROFILE",
+            "SLA",
+            "LOT",
+            "DISPUTE",
+            "MAINTENANCE",
+            "BLOCK_VALIDATION",
+            "",
+        ] {
+            assert_eq!(typo(name), None, "{}", name);
+        }
+        assert_eq!(EventType::Harvest.probable_typo(), None);
+    }
+}
@@ -0,0 +1,319 @@
+// Fixtures to be used in the unit tests all over the project: sample addresses, the cast of a supply chain,
+// realistic payloads of each event type and whole scenarios of the life of a batch
+// They are not reusable outside of the node yet: the crate is only a binary, so neither the integration tests
+// nor other crates can import them until the model is moved to a library target (e.g. behind a testing feature)
+// We export functions to workaround constant value restrictions in Rust
+use ed25519_dalek::SigningKey;
+use serde_json::json;
+
+use super::{
+    Address, AgriData, Block, Blockchain, EventType, Role, Transaction, REGISTRATION_EVENT,
+    SENSOR_READINGS_EVENT,
+};
+
+// The sample addresses have a key too, every transaction of the chain must be signed by its sender
+pub fn alice() -> Address {
+    address_of(&alice_key())
+}
+
+pub fn bob() -> Address {
+    address_of(&bob_key())
+}
+
+pub fn carol() -> Address {
+    address_of(&carol_key())
+}
+
+pub fn alice_key() -> SigningKey {
+    SigningKey::from_bytes(&[1; 32])
+}
+
+pub fn bob_key() -> SigningKey {
+    SigningKey::from_bytes(&[2; 32])
+}
+
+pub fn carol_key() -> SigningKey {
+    SigningKey::from_bytes(&[3; 32])
+}
+
+fn address_of(key: &SigningKey) -> Address {
+    Address::from(key.verifying_key().to_bytes())
+}
+
+// An actor of the supply chain with its key, and the roles it's registered with (none for the consumers)
+#[derive(Debug, Clone)]
+pub struct Persona {
+    pub name: &'static str,
+    pub key: SigningKey,
+    pub address: Address,
+    pub roles: Vec<Role>,
+}
+
+impl Persona {
+    // The transaction signed by the persona, which must be its sender
+    pub fn sign(&self, mut transaction: Transaction) -> Transaction {
+        transaction.sign(&self.key).unwrap();
+        transaction
+    }
+}
+
+// The key of each persona comes from its own seed, so its address never changes
+fn persona(name: &'static str, seed: u8, roles: Vec<Role>) -> Persona {
+    let key = SigningKey::from_bytes(&[seed; 32]);
+    Persona {
+        name,
+        address: address_of(&key),
+        key,
+        roles,
+    }
+}
+
+pub fn farm() -> Persona {
+    persona("Green Valley Farm", 101, vec![Role::Farmer])
+}
+
+pub fn mill() -> Persona {
+    persona("Riverside Mill", 102, vec![Role::Processor])
+}
+
+pub fn trucker() -> Persona {
+    persona("Northline Logistics", 103, vec![Role::Transporter])
+}
+
+pub fn inspector() -> Persona {
+    persona("Food Safety Agency", 104, vec![Role::Inspector])
+}
+
+pub fn retailer() -> Persona {
+    persona(
+        "Corner Grocery",
+        105,
+        // the cold room of the shop stores the chilled products
+        vec![Role::Retailer, Role::Warehouse],
+    )
+}
+
+pub fn consumer() -> Persona {
+    persona("Consumer", 106, Vec::new())
+}
+
+pub fn cast() -> Vec<Persona> {
+    vec![
+        farm(),
+        mill(),
+        trucker(),
+        inspector(),
+        retailer(),
+        consumer(),
+    ]
+}
+
+// Signs a transaction with the key of its sender, one of the sample addresses or of the cast
+pub fn sign(mut transaction: Transaction) -> Transaction {
+    let key = [alice_key(), bob_key(), carol_key()]
+        .into_iter()
+        .chain(cast().into_iter().map(|persona| persona.key))
+        .find(|key| address_of(key) == transaction.sender)
+        .unwrap();
+    transaction.sign(&key).unwrap();
+    transaction
+}
+
+// Same for each transaction
+pub fn signed(transactions: Vec<Transaction>) -> Vec<Transaction> {
+    transactions.into_iter().map(sign).collect()
+}
+
+// The registrations of the cast by the farm, which becomes the registrar of the chain, so the roles are enforced
+// From then on the events are only accepted when signed, like the ones of the scenarios below
+pub fn registrations() -> Vec<Transaction> {
+    let registrations = cast()
+        .into_iter()
+        .filter(|persona| !persona.roles.is_empty())
+        .map(|persona| Transaction {
+            sender: farm().address,
+            recipient: persona.address,
+            data: json!({ "roles": persona.roles }).to_string().into(),
+            event_type: REGISTRATION_EVENT.into(),
+            ..Default::default()
+        })
+        .collect();
+    signed(registrations)
+}
+
+// A payload like the ones sent by the actors for each lifecycle event, raw for the other ones
+pub fn payload(event_type: &EventType) -> AgriData {
+    let payload = match event_type {
+        EventType::Harvest => json!({
+            "type": "HARVEST", "crop": "wheat", "quantity_kg": 1200.0, "field": "Field-7",
+            "moisture_percent": 13.5
+        }),
+        EventType::Transport => json!({
+            "type": "TRANSPORT", "vehicle": "TRUCK-7", "driver": "J. Smith",
+            "departure": "2024-07-02T06:30:00Z", "refrigerated": true
+        }),
+        EventType::Storage => json!({
+            "type": "STORAGE", "facility": "SILO-3", "temperature": 4.0
+        }),
+        EventType::Processing => json!({
+            "type": "PROCESSING", "process": "milling", "output_kg": 1000.0
+        }),
+        EventType::QualityCheck => json!({
+            "type": "QUALITY_CHECK", "result": "PASS", "grade": "A"
+        }),
+        EventType::Sale => json!({
+            "type": "SALE", "buyer": "Corner Grocery", "price": 850.0, "currency": "EUR",
+            "quantity_kg": 1000.0
+        }),
+        EventType::Recall => json!({ "reason": "aflatoxin above the legal limit" }),
+        EventType::Custom(_) => json!({}),
+    };
+
+    match event_type.is_lifecycle() && *event_type != EventType::Recall {
+        true => serde_json::from_value(payload).unwrap(),
+        false => payload.to_string().into(),
+    }
+}
+
+// A lifecycle event of a batch with its usual payload
+pub fn event(
+    sender: &Persona,
+    recipient: &Persona,
+    event_type: &str,
+    batch_id: &str,
+) -> Transaction {
+    let event_type = EventType::from(event_type);
+    Transaction {
+        sender: sender.address.clone(),
+        recipient: recipient.address.clone(),
+        data: payload(&event_type),
+        batch_id: batch_id.to_string(),
+        event_type,
+        ..Default::default()
+    }
+}
+
+// Readings of the logger of the truck every 10 minutes from the 2nd of July 2024, at 4°C but the ones out of range
+pub fn cold_chain_readings(batch_id: &str, breached: bool) -> Transaction {
+    let start = 1_719_900_000_000_i64;
+    let readings: Vec<_> = (0..6)
+        .map(|minute| {
+            let temperature = match breached && (2..4).contains(&minute) {
+                true => 11.5,
+                false => 4.0,
+            };
+            json!({
+                "device_id": "TRUCK-7-LOGGER",
+                "recorded_at": start + minute * 600_000,
+                "temperature": temperature
+            })
+        })
+        .collect();
+
+    Transaction {
+        data: json!({ "readings": readings }).to_string().into(),
+        event_type: SENSOR_READINGS_EVENT.into(),
+        ..event(&trucker(), &trucker(), SENSOR_READINGS_EVENT, batch_id)
+    }
+}
+
+// A batch from the field to the consumer: harvested, carried to the mill, milled, checked, carried to the shop
+// and sold
+pub fn happy_path(batch_id: &str) -> Vec<Transaction> {
+    signed(vec![
+        event(&farm(), &farm(), "HARVEST", batch_id),
+        event(&trucker(), &mill(), "TRANSPORT", batch_id),
+        event(&mill(), &mill(), "PROCESSING", batch_id),
+        event(&inspector(), &mill(), "QUALITY_CHECK", batch_id),
+        event(&trucker(), &retailer(), "TRANSPORT", batch_id),
+        event(&retailer(), &consumer(), "SALE", batch_id),
+    ])
+}
+
+// A refrigerated trip where the temperature went above the range of the chilled products for 20 minutes,
+// before the batch is stored
+pub fn cold_chain_breach(batch_id: &str) -> Vec<Transaction> {
+    signed(vec![
+        event(&farm(), &farm(), "HARVEST", batch_id),
+        event(&trucker(), &retailer(), "TRANSPORT", batch_id),
+        cold_chain_readings(batch_id, true),
+        event(&retailer(), &retailer(), "STORAGE", batch_id),
+    ])
+}
+
+// The happy path until the sale, then the inspector recalls the batch from everyone who held it
+pub fn recall(batch_id: &str) -> Vec<Transaction> {
+    let mut transactions = happy_path(batch_id);
+    transactions.push(inspector().sign(Transaction {
+        recipients: vec![trucker().address, retailer().address],
+        ..event(&inspector(), &mill(), "RECALL", batch_id)
+    }));
+    transactions
+}
+
+// Mines the transactions one per block, after the last block of the chain
+pub fn mine(blockchain: &Blockchain, transactions: &[Transaction]) {
+    for transaction in transactions {
+        let last = blockchain.get_last_block().header;
+        let block = Block::new(last.index + 1, 0, last.hash, vec![transaction.clone()]);
+        blockchain.add_block(block).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{BatchState, ComplianceReport, ComplianceThresholds};
+
+    #[test]
+    fn should_play_the_scenarios_with_the_roles_enforced() {
+        let blockchain = Blockchain::new(0);
+        mine(&blockchain, &registrations());
+        let registry = blockchain.get_actor_registry();
+        assert_eq!(registry.registrar(), Some(&farm().address));
+        for persona in cast() {
+            assert_eq!(
+                registry.roles_of(&persona.address),
+                persona.roles,
+                "{}",
+                persona.name
+            );
+        }
+
+        mine(&blockchain, &happy_path("WHEAT-1"));
+        assert_eq!(blockchain.batch_state("WHEAT-1"), Some(BatchState::Sold));
+
+        mine(&blockchain, &recall("WHEAT-2"));
+        assert_eq!(
+            blockchain.batch_state("WHEAT-2"),
+            Some(BatchState::Recalled)
+        );
+
+        mine(&blockchain, &cold_chain_breach("MILK-1"));
+        assert_eq!(blockchain.batch_state("MILK-1"), Some(BatchState::Stored));
+        let chilled = ComplianceThresholds::profile("chilled").unwrap();
+        let report = ComplianceReport::scan("MILK-1", &blockchain.get_all_blocks(), &chilled);
+        assert!(!report.compliant);
+        assert_eq!(report.violations.len(), 1);
+        assert_eq!(report.violations[0].readings, 2);
+        assert_eq!(report.violations[0].peak, 11.5);
+    }
+
+    #[test]
+    fn should_build_a_valid_payload_for_each_event_type() {
+        for event_type in [
+            "HARVEST",
+            "TRANSPORT",
+            "STORAGE",
+            "PROCESSING",
+            "QUALITY_CHECK",
+            "SALE",
+        ] {
+            let event_type = EventType::from(event_type);
+            let payload = payload(&event_type);
+            assert_eq!(payload.event_type(), Some(event_type.clone()));
+            assert_eq!(payload.validate(&event_type), Ok(()));
+        }
+        assert_eq!(payload(&EventType::Recall).event_type(), None);
+    }
+}
@@ -0,0 +1,304 @@
+use std::{fs, path::Path};
+
+use ethereum_types::U256;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use super::{
+    encode, Address, Block, BlockHash, Difficulty, Encoding, RegisteredActor, Registration,
+    Transaction, REGISTRATION_EVENT,
+};
+
+// Event of the genesis block with the parameters of the network, so they take part in the genesis hash
+pub const GENESIS_EVENT: &str = "GENESIS";
+
+// Version of the consensus rules implemented by this build, advertised to the peers
+// A network only moves to a new version from its activation height, so all its nodes switch at the same block
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// Height of the chain from which a network follows a version of the consensus rules
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ProtocolActivation {
+    pub version: u32,
+    pub height: u64,
+}
+
+#[derive(Error, Debug)]
+pub enum GenesisError {
+    #[error("Cannot read the genesis file: {0}")]
+    Unreadable(#[from] std::io::Error),
+
+    #[error("Invalid genesis file: {0}")]
+    Invalid(String),
+}
+
+// Parameters of a network fixed by its genesis block, e.g. to run a test network next to the production one
+// Read from a JSON or TOML file (by its extension), all the nodes of the network must use the same file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GenesisConfig {
+    // e.g. "agriblock-testnet"
+    pub chain_id: String,
+    #[serde(default)]
+    pub difficulty: u32,
+    // 0 keeps the initial difficulty forever
+    #[serde(default)]
+    pub difficulty_adjustment_blocks: u64,
+    #[serde(default = "default_target_block_time_ms")]
+    pub target_block_time_ms: u64,
+    // timestamp in milliseconds of the genesis block, no block of the chain can be older
+    #[serde(default)]
+    pub timestamp: i64,
+    // sender of the registrations of the actors, who can register the next ones (the first actor by default)
+    #[serde(default)]
+    pub registrar: Option<Address>,
+    // actors registered with their roles from the start
+    #[serde(default)]
+    pub actors: Vec<RegisteredActor>,
+    // the versions of the consensus rules planned for the network, announced long before their height
+    #[serde(default)]
+    pub activations: Vec<ProtocolActivation>,
+}
+
+// The consensus parameters recorded in the GENESIS event
+#[derive(Serialize)]
+struct NetworkParameters<'a> {
+    chain_id: &'a str,
+    difficulty: u32,
+    difficulty_adjustment_blocks: u64,
+    target_block_time_ms: u64,
+    // left out when empty, so the networks without activations keep their genesis hash
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    activations: &'a [ProtocolActivation],
+}
+
+fn default_target_block_time_ms() -> u64 {
+    10_000
+}
+
+impl GenesisConfig {
+    pub fn load(path: &str) -> Result<GenesisConfig, GenesisError> {
+        let content = fs::read_to_string(path)?;
+        let is_toml = Path::new(path)
+            .extension()
+            .is_some_and(|extension| extension == "toml");
+        let config: GenesisConfig = match is_toml {
+            true => toml::from_str(&content).map_err(|error| error.to_string()),
+            false => serde_json::from_str(&content).map_err(|error| error.to_string()),
+        }
+        .map_err(GenesisError::Invalid)?;
+
+        if config.chain_id.trim().is_empty() {
+            return Err(GenesisError::Invalid("The chain_id is empty".to_string()));
+        }
+        Ok(config)
+    }
+
+    pub fn difficulty(&self) -> Difficulty {
+        Difficulty::new(
+            self.difficulty,
+            self.difficulty_adjustment_blocks,
+            self.target_block_time_ms,
+        )
+    }
+
+    // The genesis block of the network: the GENESIS event and the registration of each actor
+    pub fn create_block(&self) -> Block {
+        let parameters = NetworkParameters {
+            chain_id: &self.chain_id,
+            difficulty: self.difficulty,
+            difficulty_adjustment_blocks: self.difficulty_adjustment_blocks,
+            target_block_time_ms: self.target_block_time_ms,
+            activations: &self.activations,
+        };
+        let mut transactions = vec![Transaction {
+            data: String::from_utf8(encode(&parameters, Encoding::CanonicalJson).unwrap())
+                .unwrap()
+                .into(),
+            event_type: GENESIS_EVENT.into(),
+            ..Default::default()
+        }];
+
+        let registrar = self
+            .registrar
+            .clone()
+            .or_else(|| self.actors.first().map(|actor| actor.address.clone()));
+        for actor in self.actors.iter() {
+            let registration = Registration {
+                roles: actor.roles.clone(),
+                namespace: actor.namespace.clone(),
+            };
+            transactions.push(Transaction {
+                sender: registrar.clone().unwrap_or_default(),
+                recipient: actor.address.clone(),
+                data: serde_json::to_string(&registration).unwrap().into(),
+                event_type: REGISTRATION_EVENT.into(),
+                ..Default::default()
+            });
+        }
+
+        let mut block = Block::new(0, 0, BlockHash::default(), transactions);
+        block.header.timestamp = self.timestamp;
+        block.header.hash = block.calculate_hash();
+        block
+    }
+}
+
+// Identifies the network of a node, so consortium members can check that they all started from the same genesis
+// Both values are derived deterministically from the genesis block, independently of the machine and the build
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct GenesisSummary {
+    #[schema(value_type = String)]
+    pub hash: BlockHash,
+    // hash of the canonical JSON of the genesis transactions, which the whole initial state derives from
+    #[schema(value_type = String)]
+    pub state_root: BlockHash,
+}
+
+impl GenesisSummary {
+    pub fn new(genesis_block: &Block) -> GenesisSummary {
+        // the canonical JSON doesn't depend on the field order or the formatting of serde
+        let state =
+            encode(&genesis_block.body.transactions, Encoding::CanonicalJson).unwrap_or_default();
+        let state_root = U256::from_big_endian(Sha256::digest(state).as_slice());
+
+        GenesisSummary {
+            hash: genesis_block.header.hash,
+            state_root,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        fixtures::{alice, bob},
+        Blockchain, Role,
+    };
+
+    #[test]
+    fn should_derive_the_same_summary_on_every_node() {
+        let summary = Blockchain::new(0).get_genesis_summary();
+        let other_summary = Blockchain::new(10).get_genesis_summary();
+        assert_eq!(summary, other_summary);
+
+        // sha256 of "[]", as the genesis block has no transactions
+        assert_eq!(
+            format!("{:x}", summary.state_root),
+            "4f53cda18c2baa0c0354bb5f9a3ecbe5ed12ab4d8e11ba873c2f11161202b945"
+        );
+    }
+
+    #[test]
+    fn should_read_a_genesis_file() {
+        let path =
+            std::env::temp_dir().join(format!("agriblock-genesis-{}.toml", std::process::id()));
+        let toml = format!(
+            r#"
+chain_id = "agriblock-testnet"
+difficulty = 4
+timestamp = 1700000000000
+
+[[actors]]
+address = "{}"
+roles = ["FARMER"]
+
+[[actors]]
+address = "{}"
+roles = ["TRANSPORTER", "WAREHOUSE"]
+"#,
+            alice(),
+            bob()
+        );
+        fs::write(&path, toml).unwrap();
+        let config = GenesisConfig::load(path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.chain_id, "agriblock-testnet");
+        assert_eq!(config.difficulty().initial(), 4);
+        assert_eq!(config.target_block_time_ms, 10_000);
+        assert_eq!(
+            config.actors[1].roles,
+            vec![Role::Transporter, Role::Warehouse]
+        );
+
+        // the same parameters in JSON give the same genesis
+        let path =
+            std::env::temp_dir().join(format!("agriblock-genesis-{}.json", std::process::id()));
+        fs::write(&path, serde_json::to_string(&config).unwrap()).unwrap();
+        let from_json = GenesisConfig::load(path.to_str().unwrap()).unwrap();
+        fs::write(&path, r#"{"chain_id": " "}"#).unwrap();
+        assert!(GenesisConfig::load(path.to_str().unwrap()).is_err());
+        fs::write(&path, r#"{"chain_id": "test", "dificulty": 4}"#).unwrap();
+        assert!(GenesisConfig::load(path.to_str().unwrap()).is_err());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            from_json.create_block().header.hash,
+            config.create_block().header.hash
+        );
+    }
+
+    #[test]
+    fn should_separate_the_networks() {
+        let config = GenesisConfig {
+            chain_id: "agriblock-testnet".to_string(),
+            difficulty: 0,
+            difficulty_adjustment_blocks: 0,
+            target_block_time_ms: 10_000,
+            timestamp: 1_700_000_000_000,
+            registrar: None,
+            actors: vec![RegisteredActor {
+                address: alice(),
+                roles: vec![Role::Farmer],
+                namespace: None,
+            }],
+            activations: Vec::new(),
+        };
+        let genesis = config.create_block();
+        assert_eq!(genesis.header.timestamp, 1_700_000_000_000);
+        assert_eq!(genesis.body.transactions.len(), 2);
+        // the first actor registers itself, so it becomes the registrar
+        assert_eq!(genesis.body.transactions[1].sender, alice());
+
+        let production = GenesisConfig {
+            chain_id: "agriblock".to_string(),
+            ..config.clone()
+        };
+        assert_ne!(production.create_block().header.hash, genesis.header.hash);
+        let harder = GenesisConfig {
+            difficulty: 12,
+            ..config.clone()
+        };
+        assert_ne!(harder.create_block().header.hash, genesis.header.hash);
+        let upgraded = GenesisConfig {
+            activations: vec![ProtocolActivation {
+                version: PROTOCOL_VERSION + 1,
+                height: 1_000,
+            }],
+            ..config.clone()
+        };
+        assert_ne!(upgraded.create_block().header.hash, genesis.header.hash);
+        assert_eq!(
+            Blockchain::from_genesis(&upgraded).activations(),
+            upgraded.activations
+        );
+
+        // the actors are registered from the start
+        let blockchain = Blockchain::from_genesis(&config);
+        assert_eq!(blockchain.get_last_block().header.hash, genesis.header.hash);
+        assert_eq!(blockchain.get_actor_registry().registrar(), Some(&alice()));
+        assert_eq!(
+            blockchain.get_actor_registry().roles_of(&alice()),
+            &[Role::Farmer]
+        );
+        assert!(blockchain.is_valid());
+        assert_ne!(
+            blockchain.get_genesis_summary(),
+            Blockchain::new(0).get_genesis_summary()
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use ethereum_types::U256;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use super::{encode, Address, Encoding};
+
+// Types whose hash is the sha256 of a byte encoding written by hand, field by field
+// Unlike the serde formats, it cannot change when a field is renamed, reordered or gets a serde attribute,
+// so every version of the node computes the same hashes and agrees on the same chain
+pub trait Hashable {
+    // Written before the fields, so the hashes of different types never collide
+    const DOMAIN: &'static [u8];
+
+    fn write_canonical(&self, writer: &mut CanonicalWriter);
+
+    fn canonical_bytes(&self) -> Vec<u8> {
+        let mut writer = CanonicalWriter::default();
+        self.write_canonical(&mut writer);
+        writer.bytes
+    }
+
+    fn canonical_hash(&self) -> U256 {
+        let mut hasher = Sha256::new();
+        hasher.update(Self::DOMAIN);
+        hasher.update(self.canonical_bytes());
+        U256::from_big_endian(hasher.finalize().as_slice())
+    }
+}
+
+// The primitives of the canonical encoding, with the same conventions as bincode:
+// integers in 8 little-endian bytes, variable-length values after their length,
+// and optional values after a 0x00 (none) or 0x01 (some) byte
+// Hashes and addresses are their 32 big-endian bytes, and free-form JSON is in the canonical binary encoding
+#[derive(Debug, Default)]
+pub struct CanonicalWriter {
+    bytes: Vec<u8>,
+}
+
+impl CanonicalWriter {
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    pub fn i64(&mut self, value: i64) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    pub fn hash(&mut self, hash: &U256) {
+        let mut bytes = [0; 32];
+        hash.to_big_endian(&mut bytes);
+        self.bytes.extend(bytes);
+    }
+
+    pub fn address(&mut self, address: &Address) {
+        self.bytes.extend(address.as_bytes());
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.u64(bytes.len() as u64);
+        self.bytes.extend(bytes);
+    }
+
+    pub fn str(&mut self, value: &str) {
+        self.bytes(value.as_bytes());
+    }
+
+    pub fn json(&mut self, value: &Value) {
+        // any JSON value can be encoded
+        self.bytes(&encode(value, Encoding::Binary).unwrap());
+    }
+
+    pub fn option<T, F>(&mut self, value: Option<&T>, write: F)
+    where
+        T: ?Sized,
+        F: FnOnce(&mut CanonicalWriter, &T),
+    {
+        match value {
+            Some(value) => {
+                self.bytes.push(0x01);
+                write(self, value);
+            }
+            None => self.bytes.push(0x00),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::fixtures::alice;
+
+    #[test]
+    fn should_write_the_primitives() {
+        let mut writer = CanonicalWriter::default();
+        writer.u64(1);
+        writer.i64(-1);
+        writer.str("ab");
+        writer.option(None::<&str>, CanonicalWriter::str);
+        writer.option(Some("c"), CanonicalWriter::str);
+        writer.hash(&U256::from(2));
+
+        let mut expected = vec![1, 0, 0, 0, 0, 0, 0, 0];
+        expected.extend([0xff; 8]);
+        expected.extend([2, 0, 0, 0, 0, 0, 0, 0, b'a', b'b']);
+        expected.push(0x00);
+        expected.extend([0x01, 1, 0, 0, 0, 0, 0, 0, 0, b'c']);
+        expected.extend([0; 31]);
+        expected.push(2);
+        assert_eq!(writer.bytes, expected);
+
+        let mut writer = CanonicalWriter::default();
+        writer.address(&alice());
+        assert_eq!(writer.bytes, alice().as_bytes().to_vec());
+    }
+}
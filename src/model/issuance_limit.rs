@@ -0,0 +1,162 @@
+use super::{Address, Block, Transaction, TransactionError, ESCROW_EVENT};
+
+// Most custody events that a single actor can emit for the same batch in a window of time
+// A consensus rule, so a compromised key cannot bury the real events of a batch under a flood of fake ones
+// All the nodes of a network must use the same values, or they will reject each other's blocks
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct IssuanceLimit {
+    // 0 doesn't limit the events
+    max_events: usize,
+    // 0 only counts the events of the same block
+    window_ms: u64,
+}
+
+impl IssuanceLimit {
+    pub fn new(max_events: usize, window_ms: u64) -> IssuanceLimit {
+        IssuanceLimit {
+            max_events,
+            window_ms,
+        }
+    }
+
+    pub fn unlimited() -> IssuanceLimit {
+        IssuanceLimit::default()
+    }
+
+    pub fn is_limited(&self) -> bool {
+        self.max_events > 0
+    }
+
+    // Checks that the actor of a custody event has not emitted too many of them for its batch,
+    // counting the preceding transactions (e.g. of the same block) and the blocks of the window
+    // before the timestamp
+    pub fn check(
+        &self,
+        blocks: &[Block],
+        preceding: &[Transaction],
+        transaction: &Transaction,
+        timestamp: i64,
+    ) -> Result<(), TransactionError> {
+        if !self.is_limited() || !changes_custody(transaction) || transaction.batch_id.is_empty() {
+            return Ok(());
+        }
+
+        let window_start = timestamp - self.window_ms as i64;
+        let recent = blocks
+            .iter()
+            .rev()
+            .take_while(|block| self.window_ms > 0 && block.header.timestamp > window_start)
+            .flat_map(|block| block.body.transactions.iter());
+        let actor = actor_of(transaction);
+        let emitted = preceding
+            .iter()
+            .chain(recent)
+            .filter(|tx| {
+                tx.batch_id == transaction.batch_id && changes_custody(tx) && actor_of(tx) == actor
+            })
+            .count();
+
+        match emitted < self.max_events {
+            true => Ok(()),
+            false => Err(TransactionError::TooManyEvents(
+                actor.clone(),
+                transaction.batch_id.clone(),
+                self.max_events,
+            )),
+        }
+    }
+}
+
+// The events that move a batch along its custody chain: its lifecycle and its escrows
+fn changes_custody(transaction: &Transaction) -> bool {
+    transaction.event_type.is_lifecycle() || transaction.event_type == ESCROW_EVENT
+}
+
+// The actor that a gateway acts for is the one who emits the event
+fn actor_of(transaction: &Transaction) -> &Address {
+    transaction
+        .on_behalf_of
+        .as_ref()
+        .unwrap_or(&transaction.sender)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        fixtures::{alice, bob},
+        BlockHash,
+    };
+
+    const MINUTE: i64 = 60_000;
+
+    #[test]
+    fn should_limit_the_events_of_an_actor_per_batch() {
+        let limit = IssuanceLimit::new(2, 10 * MINUTE as u64);
+        let blocks = vec![
+            create_block(0, vec![event(alice(), "WHEAT-1", "STORAGE")]),
+            create_block(15 * MINUTE, vec![event(alice(), "WHEAT-1", "STORAGE")]),
+            create_block(20 * MINUTE, vec![event(alice(), "WHEAT-1", "TRANSPORT")]),
+        ];
+        let now = 21 * MINUTE;
+
+        // the first block is out of the window
+        let storage = event(alice(), "WHEAT-1", "STORAGE");
+        assert_eq!(
+            limit.check(&blocks, &[], &storage, now),
+            Err(TransactionError::TooManyEvents(
+                alice(),
+                "WHEAT-1".to_string(),
+                2
+            ))
+        );
+        assert_eq!(limit.check(&blocks, &[], &storage, 26 * MINUTE), Ok(()));
+
+        // other batches, other actors and the events that don't change the custody are not limited
+        let other_batch = event(alice(), "WHEAT-2", "STORAGE");
+        assert_eq!(limit.check(&blocks, &[], &other_batch, now), Ok(()));
+        let other_actor = event(bob(), "WHEAT-1", "STORAGE");
+        assert_eq!(limit.check(&blocks, &[], &other_actor, now), Ok(()));
+        let note = event(alice(), "WHEAT-1", "NOTE");
+        assert_eq!(limit.check(&blocks, &[], &note, now), Ok(()));
+
+        // a gateway counts as the actor it acts for
+        let mut delegated = event(bob(), "WHEAT-1", "STORAGE");
+        delegated.on_behalf_of = Some(alice());
+        assert!(limit.check(&blocks, &[], &delegated, now).is_err());
+
+        assert_eq!(
+            IssuanceLimit::unlimited().check(&blocks, &[], &storage, now),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn should_count_the_events_of_the_same_block() {
+        let limit = IssuanceLimit::new(1, 0);
+        let blocks = vec![create_block(0, vec![event(alice(), "WHEAT-1", "STORAGE")])];
+        let storage = event(alice(), "WHEAT-1", "STORAGE");
+
+        // without a window, only the same block counts
+        assert_eq!(limit.check(&blocks, &[], &storage, 0), Ok(()));
+        assert!(limit
+            .check(&blocks, std::slice::from_ref(&storage), &storage, 0)
+            .is_err());
+    }
+
+    fn event(sender: Address, batch_id: &str, event_type: &str) -> Transaction {
+        Transaction {
+            sender: sender.clone(),
+            recipient: sender,
+            batch_id: batch_id.to_string(),
+            event_type: event_type.into(),
+            ..Default::default()
+        }
+    }
+
+    fn create_block(timestamp: i64, transactions: Vec<Transaction>) -> Block {
+        let mut block = Block::new(1, 0, BlockHash::zero(), transactions);
+        block.header.timestamp = timestamp;
+        block
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::{BTreeSet, HashSet, VecDeque};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{Address, EventType, Transaction, TransactionError, PLANNED_EVENT};
+
+pub const SPLIT_EVENT: &str = "SPLIT";
+pub const MERGE_EVENT: &str = "MERGE";
+
+// A quantity of a batch that goes to (or comes from) another batch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct BatchPortion {
+    pub batch_id: String,
+    pub quantity_kg: f64,
+}
+
+// Division of the batch of a SPLIT event into new batches, e.g. 500kg of wheat divided at the mill
+// into a 200kg and a 300kg batch sold to different buyers; what goes to no child stays in the batch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Split {
+    pub children: Vec<BatchPortion>,
+}
+
+impl Split {
+    // Parses the split contained in the data of a SPLIT event
+    pub fn parse(data: &str) -> Result<Split, TransactionError> {
+        let split: Split =
+            serde_json::from_str(data).map_err(|_| TransactionError::InvalidSplit)?;
+        match are_valid(&split.children, 1) {
+            true => Ok(split),
+            false => Err(TransactionError::InvalidSplit),
+        }
+    }
+}
+
+// Several batches combined into the new batch of a MERGE event, e.g. the harvests of two farms in one silo
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Merge {
+    pub parents: Vec<BatchPortion>,
+}
+
+impl Merge {
+    // Parses the merge contained in the data of a MERGE event
+    pub fn parse(data: &str) -> Result<Merge, TransactionError> {
+        let merge: Merge =
+            serde_json::from_str(data).map_err(|_| TransactionError::InvalidMerge)?;
+        match are_valid(&merge.parents, 2) {
+            true => Ok(merge),
+            false => Err(TransactionError::InvalidMerge),
+        }
+    }
+}
+
+// Distinct batches, with a quantity that is never negative
+fn are_valid(portions: &[BatchPortion], min_count: usize) -> bool {
+    let batch_ids: BTreeSet<&String> = portions.iter().map(|portion| &portion.batch_id).collect();
+
+    portions.len() >= min_count
+        && batch_ids.len() == portions.len()
+        && portions
+            .iter()
+            .all(|portion| !portion.batch_id.is_empty() && portion.quantity_kg >= 0.0)
+}
+
+// A batch that comes from another one, through a split or a merge
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct LineageLink {
+    pub parent: String,
+    pub child: String,
+    pub quantity_kg: f64,
+    // SPLIT or MERGE
+    pub event_type: String,
+}
+
+impl LineageLink {
+    // The links recorded by a SPLIT or MERGE event, none for the other events or an invalid payload
+    pub fn of(transaction: &Transaction) -> Vec<LineageLink> {
+        let link = |parent: &str, child: &str, quantity_kg: f64| LineageLink {
+            parent: parent.to_string(),
+            child: child.to_string(),
+            quantity_kg,
+            event_type: transaction.event_type.to_string(),
+        };
+
+        let data = transaction.data.as_json();
+        match transaction.event_type.as_str() {
+            SPLIT_EVENT => Split::parse(&data)
+                .map(|split| {
+                    split
+                        .children
+                        .iter()
+                        .map(|child| {
+                            link(&transaction.batch_id, &child.batch_id, child.quantity_kg)
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            MERGE_EVENT => Merge::parse(&data)
+                .map(|merge| {
+                    merge
+                        .parents
+                        .iter()
+                        .map(|parent| {
+                            link(&parent.batch_id, &transaction.batch_id, parent.quantity_kg)
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+// The ancestry of a batch: all the batches it comes from, through every split and merge before it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct BatchLineage {
+    pub batch_id: String,
+    // closest first, e.g. the batch it was split from before the harvests merged into that one
+    pub ancestors: Vec<String>,
+    // the links between the batch and its ancestors, in chain order
+    pub links: Vec<LineageLink>,
+}
+
+impl BatchLineage {
+    // The lineage of a batch from the transactions of the chain
+    pub fn of<'a, I>(batch_id: &str, transactions: I) -> BatchLineage
+    where
+        I: IntoIterator<Item = &'a Transaction>,
+    {
+        let all_links: Vec<LineageLink> =
+            transactions.into_iter().flat_map(LineageLink::of).collect();
+
+        // walks the links backwards from the batch, a batch reached twice (split then merged again) is kept once
+        let mut ancestors = Vec::new();
+        let mut visited = BTreeSet::from([batch_id.to_string()]);
+        let mut queue = VecDeque::from([batch_id.to_string()]);
+        while let Some(child) = queue.pop_front() {
+            for link in all_links.iter().filter(|link| link.child == child) {
+                if visited.insert(link.parent.clone()) {
+                    ancestors.push(link.parent.clone());
+                    queue.push_back(link.parent.clone());
+                }
+            }
+        }
+
+        let links = all_links
+            .into_iter()
+            .filter(|link| link.child == batch_id || ancestors.contains(&link.child))
+            .collect();
+
+        BatchLineage {
+            batch_id: batch_id.to_string(),
+            ancestors,
+            links,
+        }
+    }
+}
+
+// The actors that held a batch or a batch that comes from it, from the transactions of the chain:
+// the actors of their events and the recipients of their transports
+pub fn custodians_of<'a, I>(batch_id: &str, transactions: I) -> HashSet<Address>
+where
+    I: IntoIterator<Item = &'a Transaction>,
+{
+    let mut family = HashSet::from([batch_id.to_string()]);
+    let mut custodians = HashSet::new();
+    for transaction in transactions {
+        // the children always come after their parents in the chain
+        for link in LineageLink::of(transaction) {
+            if family.contains(&link.parent) {
+                family.insert(link.child);
+            }
+        }
+        if !family.contains(&transaction.batch_id) || transaction.event_type == PLANNED_EVENT {
+            continue;
+        }
+
+        let actor = transaction
+            .on_behalf_of
+            .as_ref()
+            .unwrap_or(&transaction.sender);
+        custodians.insert(actor.clone());
+        if transaction.event_type == EventType::Transport {
+            custodians.insert(transaction.recipient.clone());
+        }
+    }
+
+    custodians
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::fixtures::{alice, bob, carol};
+
+    #[test]
+    fn should_parse_the_splits_and_merges() {
+        let split = Split::parse(
+            r#"{"children": [{"batch_id": "WHEAT-1-A", "quantity_kg": 200}, {"batch_id": "WHEAT-1-B", "quantity_kg": 300}]}"#,
+        )
+        .unwrap();
+        assert_eq!(split.children.len(), 2);
+        assert_eq!(split.children[1].quantity_kg, 300.0);
+
+        // no children, the same child twice, or a negative quantity
+        let invalid = [
+            r#"{"children": []}"#,
+            r#"{"children": [{"batch_id": "WHEAT-1-A", "quantity_kg": 1}, {"batch_id": "WHEAT-1-A", "quantity_kg": 2}]}"#,
+            r#"{"children": [{"batch_id": "WHEAT-1-A", "quantity_kg": -1}]}"#,
+            r#"{"children": [{"batch_id": "", "quantity_kg": 1}]}"#,
+        ];
+        for data in invalid.iter() {
+            assert_eq!(Split::parse(data), Err(TransactionError::InvalidSplit));
+        }
+
+        // a merge combines at least two batches
+        let single = r#"{"parents": [{"batch_id": "WHEAT-1", "quantity_kg": 200}]}"#;
+        assert_eq!(Merge::parse(single), Err(TransactionError::InvalidMerge));
+    }
+
+    #[test]
+    fn should_walk_the_ancestry_of_a_batch() {
+        let transactions = vec![
+            event(
+                "WHEAT-1",
+                SPLIT_EVENT,
+                r#"{"children": [{"batch_id": "WHEAT-1-A", "quantity_kg": 200}, {"batch_id": "WHEAT-1-B", "quantity_kg": 300}]}"#,
+            ),
+            event("CORN-1", "HARVEST", r#"{"quantity_kg": 100}"#),
+            event(
+                "MIX-1",
+                MERGE_EVENT,
+                r#"{"parents": [{"batch_id": "WHEAT-1-A", "quantity_kg": 200}, {"batch_id": "CORN-1", "quantity_kg": 100}]}"#,
+            ),
+        ];
+
+        let lineage = BatchLineage::of("MIX-1", &transactions);
+        assert_eq!(lineage.ancestors, vec!["WHEAT-1-A", "CORN-1", "WHEAT-1"]);
+        let links: Vec<(&str, &str)> = lineage
+            .links
+            .iter()
+            .map(|link| (link.parent.as_str(), link.child.as_str()))
+            .collect();
+        assert_eq!(
+            links,
+            vec![
+                ("WHEAT-1", "WHEAT-1-A"),
+                ("WHEAT-1-A", "MIX-1"),
+                ("CORN-1", "MIX-1")
+            ]
+        );
+
+        // the other child of the split is not an ancestor
+        let lineage = BatchLineage::of("WHEAT-1-B", &transactions);
+        assert_eq!(lineage.ancestors, vec!["WHEAT-1"]);
+        assert_eq!(lineage.links.len(), 1);
+        assert!(BatchLineage::of("CORN-1", &transactions)
+            .ancestors
+            .is_empty());
+    }
+
+    #[test]
+    fn should_find_the_custodians_downstream() {
+        let transport = |batch_id: &str, sender: Address, recipient: Address| Transaction {
+            sender,
+            recipient,
+            ..event(batch_id, "TRANSPORT", "{}")
+        };
+        let transactions = vec![
+            transport("WHEAT-1", alice(), bob()),
+            Transaction {
+                sender: bob(),
+                ..event(
+                    "WHEAT-1",
+                    SPLIT_EVENT,
+                    r#"{"children": [{"batch_id": "WHEAT-1-A", "quantity_kg": 200}]}"#,
+                )
+            },
+            transport("WHEAT-1-A", bob(), carol()),
+            transport("CORN-1", carol(), Address::default()),
+        ];
+
+        let custodians = custodians_of("WHEAT-1", &transactions);
+        assert_eq!(custodians, HashSet::from([alice(), bob(), carol()]));
+        // the parent of a batch is not downstream
+        let custodians = custodians_of("WHEAT-1-A", &transactions);
+        assert_eq!(custodians, HashSet::from([bob(), carol()]));
+    }
+
+    fn event(batch_id: &str, event_type: &str, data: &str) -> Transaction {
+        Transaction {
+            batch_id: batch_id.to_string(),
+            event_type: event_type.into(),
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+}
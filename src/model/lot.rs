@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::TransactionError;
+
+pub const LOT_EVENT: &str = "LOT";
+
+// Prefixes and seasons are short, so the identifiers stay easy to print on labels
+const MAX_PART_LENGTH: usize = 16;
+
+// Allocation of a human-readable batch/lot identifier, recorded on chain in a LOT event
+// The identifier ("{prefix}-{season}-{sequence}", e.g. "WHEAT-2024-0007") is also the batch id of the event,
+// and the chain rejects any other allocation of the same identifier
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Lot {
+    pub prefix: String,
+    pub season: String,
+    pub sequence: u64,
+}
+
+impl Lot {
+    pub fn new(prefix: &str, season: &str, sequence: u64) -> Result<Lot, TransactionError> {
+        let lot = Lot {
+            prefix: prefix.to_string(),
+            season: season.to_string(),
+            sequence,
+        };
+        lot.validate()?;
+
+        Ok(lot)
+    }
+
+    // Parses and validates the lot contained in the data of a LOT event
+    pub fn parse(data: &str) -> Result<Lot, TransactionError> {
+        let lot: Lot = serde_json::from_str(data).map_err(|_| TransactionError::InvalidLot)?;
+        lot.validate()?;
+
+        Ok(lot)
+    }
+
+    pub fn id(&self) -> String {
+        format!("{}-{}-{:04}", self.prefix, self.season, self.sequence)
+    }
+
+    // Uppercase letters and digits only, so the parts never contain the separator
+    fn validate(&self) -> Result<(), TransactionError> {
+        let is_valid_part = |part: &str| {
+            !part.is_empty()
+                && part.len() <= MAX_PART_LENGTH
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+        };
+
+        if !is_valid_part(&self.prefix) || !is_valid_part(&self.season) || self.sequence == 0 {
+            return Err(TransactionError::InvalidLot);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_format_lot_ids() {
+        let lot = Lot::new("WHEAT", "2024", 7).unwrap();
+        assert_eq!(lot.id(), "WHEAT-2024-0007");
+
+        let lot = Lot::new("RICE", "2024S2", 12345).unwrap();
+        assert_eq!(lot.id(), "RICE-2024S2-12345");
+    }
+
+    #[test]
+    fn should_reject_invalid_lots() {
+        assert_eq!(Lot::new("", "2024", 1), Err(TransactionError::InvalidLot));
+        assert_eq!(
+            Lot::new("wheat", "2024", 1),
+            Err(TransactionError::InvalidLot)
+        );
+        assert_eq!(
+            Lot::new("WHEAT-A", "2024", 1),
+            Err(TransactionError::InvalidLot)
+        );
+        assert_eq!(
+            Lot::new("WHEAT", "2024", 0),
+            Err(TransactionError::InvalidLot)
+        );
+        assert_eq!(
+            Lot::new("WHEATWHEATWHEATWHEAT", "2024", 1),
+            Err(TransactionError::InvalidLot)
+        );
+    }
+
+    #[test]
+    fn should_parse_lots() {
+        let data = r#"{"prefix": "WHEAT", "season": "2024", "sequence": 3}"#;
+        assert_eq!(Lot::parse(data), Lot::new("WHEAT", "2024", 3));
+
+        let data = r#"{"prefix": "WHEAT", "season": "2024", "sequence": 3, "extra": 1}"#;
+        assert_eq!(Lot::parse(data), Err(TransactionError::InvalidLot));
+    }
+}
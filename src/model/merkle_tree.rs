@@ -0,0 +1,204 @@
+use ethereum_types::U256;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use super::{BlockHash, Transaction};
+
+// Domain separation of leaves and inner nodes, so a pair of hashes can't pass as a transaction
+pub(super) const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+// Side of the sibling hash to combine with, at each level of a proof
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ProofSide {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ProofStep {
+    #[schema(value_type = String)]
+    pub hash: BlockHash,
+    pub side: ProofSide,
+}
+
+// Path from a transaction to the merkle root of its block
+// Anyone with the header of the block can check the inclusion of the transaction without the other transactions
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct MerkleProof {
+    #[schema(value_type = String)]
+    pub transaction_hash: BlockHash,
+    pub path: Vec<ProofStep>,
+}
+
+impl MerkleProof {
+    // Checks that a path leads from the hash of a transaction to the merkle root of a block
+    pub fn verify(root: &BlockHash, transaction_hash: &TxHash, path: &[ProofStep]) -> bool {
+        root_of(transaction_hash, path) == *root
+    }
+}
+
+// Binary hash tree over the transactions of a block, whose root commits to all of them
+// A node without a sibling (odd levels) is promoted to the next level as is,
+// so no two different lists of transactions have the same root
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    // from the leaves (hashes of the transactions) to the root
+    levels: Vec<Vec<BlockHash>>,
+}
+
+impl MerkleTree {
+    pub fn new(transactions: &[Transaction]) -> MerkleTree {
+        let leaves: Vec<BlockHash> = transactions.iter().map(transaction_hash).collect();
+
+        let mut levels = vec![leaves];
+        while levels.last().unwrap().len() > 1 {
+            let next_level = levels
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => hash_pair(left, right),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next_level);
+        }
+
+        MerkleTree { levels }
+    }
+
+    // The root of a block without transactions is zero
+    pub fn root(&self) -> BlockHash {
+        self.levels
+            .last()
+            .and_then(|level| level.first())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    // Returns the inclusion proof of the transaction at a position of the block
+    pub fn proof(&self, position: usize) -> Option<MerkleProof> {
+        let transaction_hash = *self.levels[0].get(position)?;
+
+        let mut path = Vec::new();
+        let mut index = position;
+        for level in self.levels.iter().take(self.levels.len() - 1) {
+            let sibling = match index % 2 {
+                0 => level.get(index + 1).map(|hash| (*hash, ProofSide::Right)),
+                _ => Some((level[index - 1], ProofSide::Left)),
+            };
+            if let Some((hash, side)) = sibling {
+                path.push(ProofStep { hash, side });
+            }
+            index /= 2;
+        }
+
+        Some(MerkleProof {
+            transaction_hash,
+            path,
+        })
+    }
+}
+
+// Identifier of a transaction, e.g. for other transactions to reference it
+pub type TxHash = BlockHash;
+
+// Hash of a transaction as a leaf of the tree, from its canonical bytes
+pub fn transaction_hash(transaction: &Transaction) -> TxHash {
+    transaction.hash()
+}
+
+// Combines the hash of a transaction with the hashes of its path, up to the root that it leads to
+fn root_of(transaction_hash: &TxHash, path: &[ProofStep]) -> BlockHash {
+    path.iter()
+        .fold(*transaction_hash, |hash, step| match step.side {
+            ProofSide::Left => hash_pair(&step.hash, &hash),
+            ProofSide::Right => hash_pair(&hash, &step.hash),
+        })
+}
+
+fn hash_pair(left: &BlockHash, right: &BlockHash) -> BlockHash {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(to_bytes(left));
+    hasher.update(to_bytes(right));
+    U256::from_big_endian(hasher.finalize().as_slice())
+}
+
+fn to_bytes(hash: &BlockHash) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    hash.to_big_endian(&mut bytes);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_commit_to_every_transaction() {
+        let transactions = create_transactions(5);
+        let root = MerkleTree::new(&transactions).root();
+
+        let mut changed = transactions.clone();
+        changed[4].data = "changed".into();
+        assert_ne!(MerkleTree::new(&changed).root(), root);
+
+        // the order matters too
+        let mut reordered = transactions.clone();
+        reordered.swap(0, 1);
+        assert_ne!(MerkleTree::new(&reordered).root(), root);
+
+        // duplicating the last transaction doesn't give the same root
+        let mut duplicated = transactions.clone();
+        duplicated.push(transactions[4].clone());
+        assert_ne!(MerkleTree::new(&duplicated).root(), root);
+
+        assert_eq!(MerkleTree::new(&[]).root(), BlockHash::zero());
+    }
+
+    #[test]
+    fn should_build_proofs_up_to_the_root() {
+        let transactions = create_transactions(5);
+        let tree = MerkleTree::new(&transactions);
+
+        for (position, transaction) in transactions.iter().enumerate() {
+            let proof = tree.proof(position).unwrap();
+            assert_eq!(proof.transaction_hash, transaction_hash(transaction));
+
+            assert!(MerkleProof::verify(
+                &tree.root(),
+                &proof.transaction_hash,
+                &proof.path
+            ));
+        }
+
+        // a path only leads to the root from its own transaction
+        let proof = tree.proof(0).unwrap();
+        let other = transaction_hash(&transactions[1]);
+        assert!(!MerkleProof::verify(&tree.root(), &other, &proof.path));
+        assert!(!MerkleProof::verify(
+            &tree.root(),
+            &proof.transaction_hash,
+            &proof.path[1..]
+        ));
+
+        // the last transaction has no sibling in the first two levels
+        assert_eq!(tree.proof(4).unwrap().path.len(), 1);
+        assert_eq!(tree.proof(5), None);
+    }
+
+    fn create_transactions(count: usize) -> Vec<Transaction> {
+        (0..count)
+            .map(|position| Transaction {
+                batch_id: format!("WHEAT-{:03}", position),
+                event_type: "HARVEST".into(),
+                ..Default::default()
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,100 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use super::TransactionError;
+
+// Between the namespace and the rest of a namespaced identifier, e.g. "acme:WHEAT-2024-001"
+pub const NAMESPACE_SEPARATOR: char = ':';
+const MAX_NAMESPACE_LENGTH: usize = 32;
+
+// Identifier of a consortium on a node shared by several of them, so the integration of one company
+// cannot query or write into the batches of another one by mistake
+// The batches of a namespace have its prefix in their ids, and the actors registered in a namespace
+// can only emit events about its batches (see the actor registry)
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Namespace(String);
+
+impl Namespace {
+    // The namespace of an identifier, none if it's not namespaced
+    pub fn of(id: &str) -> Result<Option<Namespace>, TransactionError> {
+        match id.split_once(NAMESPACE_SEPARATOR) {
+            Some((_, "")) => Err(TransactionError::InvalidNamespace(id.to_string())),
+            Some((namespace, _)) => namespace.parse().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // Whether an identifier belongs to the namespace
+    pub fn contains(&self, id: &str) -> bool {
+        Namespace::of(id).ok().flatten().as_ref() == Some(self)
+    }
+
+    // Prefixes an identifier with the namespace, unless it's already namespaced
+    pub fn qualify(&self, id: &str) -> String {
+        match id.contains(NAMESPACE_SEPARATOR) {
+            true => id.to_string(),
+            false => format!("{}{}{}", self.0, NAMESPACE_SEPARATOR, id),
+        }
+    }
+}
+
+// Lowercase letters, digits and dashes
+impl FromStr for Namespace {
+    type Err = TransactionError;
+
+    fn from_str(namespace: &str) -> Result<Self, Self::Err> {
+        let is_valid_char = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-';
+        match !namespace.is_empty()
+            && namespace.len() <= MAX_NAMESPACE_LENGTH
+            && namespace.chars().all(is_valid_char)
+        {
+            true => Ok(Namespace(namespace.to_string())),
+            false => Err(TransactionError::InvalidNamespace(namespace.to_string())),
+        }
+    }
+}
+
+impl TryFrom<String> for Namespace {
+    type Error = TransactionError;
+
+    fn try_from(namespace: String) -> Result<Self, Self::Error> {
+        namespace.parse()
+    }
+}
+
+impl From<Namespace> for String {
+    fn from(namespace: Namespace) -> Self {
+        namespace.0
+    }
+}
+
+impl fmt::Display for Namespace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_find_the_namespace_of_the_identifiers() {
+        let acme: Namespace = "acme".parse().unwrap();
+        assert_eq!(Namespace::of("acme:WHEAT-1"), Ok(Some(acme.clone())));
+        assert_eq!(Namespace::of("WHEAT-1"), Ok(None));
+        assert!(acme.contains("acme:WHEAT-1"));
+        assert!(!acme.contains("globex:WHEAT-1"));
+        assert!(!acme.contains("WHEAT-1"));
+
+        assert_eq!(acme.qualify("WHEAT-1"), "acme:WHEAT-1");
+        assert_eq!(acme.qualify("acme:WHEAT-1"), "acme:WHEAT-1");
+        assert_eq!(acme.qualify("globex:WHEAT-1"), "globex:WHEAT-1");
+
+        for id in ["Acme:WHEAT-1", ":WHEAT-1", "acme:", "acme corp:WHEAT-1"] {
+            assert!(Namespace::of(id).is_err(), "{}", id);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use chrono::prelude::*;
+use ethereum_types::U256;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{Block, BlockHash, EncodingError};
+
+// Why a block ended up outside of the main chain
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum OrphanReason {
+    // A valid block competing for a height that was already taken by another block
+    StaleFork,
+    // A block of the main chain replaced by the block of a longer fork
+    RolledBack,
+}
+
+// The reorganization that rolled a block back, to find the other blocks that left the chain with it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ReorgSummary {
+    // index of the last block in common with the fork
+    pub ancestor: u64,
+    // amount of blocks rolled back
+    pub depth: u64,
+}
+
+// A block that is not part of the main chain anymore, kept for forensic investigations
+// It records the block that won the height in the main chain at the time of archiving
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct OrphanedBlock {
+    pub block: Block,
+    pub reason: OrphanReason,
+    pub fork_height: u64,
+    #[schema(value_type = String)]
+    pub canonical_hash: BlockHash,
+    pub archived_at: i64,
+    // only for the blocks rolled back
+    pub reorg: Option<ReorgSummary>,
+}
+
+// An orphaned block in the binary format of the storage, with the block in its own binary format
+#[derive(Serialize, Deserialize)]
+struct BinaryOrphanedBlock {
+    block: Vec<u8>,
+    reason: OrphanReason,
+    canonical_hash: [u8; 32],
+    archived_at: i64,
+    reorg: Option<ReorgSummary>,
+}
+
+impl OrphanedBlock {
+    pub fn new(block: Block, reason: OrphanReason, canonical_hash: BlockHash) -> OrphanedBlock {
+        OrphanedBlock {
+            fork_height: block.header.index,
+            block,
+            reason,
+            canonical_hash,
+            archived_at: Utc::now().timestamp_millis(),
+            reorg: None,
+        }
+    }
+
+    pub fn with_reorg(mut self, reorg: ReorgSummary) -> OrphanedBlock {
+        self.reorg = Some(reorg);
+        self
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, EncodingError> {
+        let mut canonical_hash = [0; 32];
+        self.canonical_hash.to_big_endian(&mut canonical_hash);
+        let binary = BinaryOrphanedBlock {
+            block: self.block.to_bytes()?,
+            reason: self.reason.clone(),
+            canonical_hash,
+            archived_at: self.archived_at,
+            reorg: self.reorg.clone(),
+        };
+
+        bincode::serialize(&binary).map_err(|_| EncodingError::InvalidValue)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<OrphanedBlock, EncodingError> {
+        let binary: BinaryOrphanedBlock =
+            bincode::deserialize(bytes).map_err(|_| EncodingError::InvalidData)?;
+        let block = Block::from_bytes(&binary.block)?;
+
+        Ok(OrphanedBlock {
+            fork_height: block.header.index,
+            block,
+            reason: binary.reason,
+            canonical_hash: U256::from_big_endian(&binary.canonical_hash),
+            archived_at: binary.archived_at,
+            reorg: binary.reorg,
+        })
+    }
+}
@@ -0,0 +1,274 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+use utoipa::ToSchema;
+
+use super::{AgriData, TransactionError};
+
+// Version of the normalization that the nodes apply to the payloads submitted to them
+pub const NORMALIZATION_VERSION: u32 = 1;
+
+// A transformation of the payloads, e.g. "500kg" to 500 in `quantity_kg`
+// The transformations only see the fields of the payload (no clock, no configuration, no chain), so a payload
+// is normalized the same way by any node and at any time
+// A transformation is never changed once released: the next version of the normalization retires it
+// and includes the new one instead, so the payloads normalized before can still be checked
+struct PayloadTransform {
+    name: &'static str,
+    description: &'static str,
+    // first version of the normalization that includes it, and the first one that doesn't anymore
+    since: u32,
+    until: Option<u32>,
+    apply: fn(&mut Map<String, Value>),
+}
+
+impl PayloadTransform {
+    fn is_in(&self, version: u32) -> bool {
+        self.since <= version && self.until.is_none_or(|until| version < until)
+    }
+}
+
+// All the transformations released, in the order they are applied
+const PAYLOAD_TRANSFORMS: [PayloadTransform; 2] = [
+    PayloadTransform {
+        name: "field_aliases",
+        description: "Renames the usual aliases of the fields, e.g. `qty` to `quantity` or `temp` to `temperature`",
+        since: 1,
+        until: None,
+        apply: rename_aliases,
+    },
+    PayloadTransform {
+        name: "units",
+        description: "Converts the quantities to kilograms in `quantity_kg`, the temperatures to degrees celsius and the humidities to percents",
+        since: 1,
+        until: None,
+        apply: normalize_units,
+    },
+];
+
+// A version of the normalization and the transformations it applies, as listed by GET /normalization
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct NormalizationVersion {
+    pub version: u32,
+    pub transforms: Vec<NormalizationStep>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct NormalizationStep {
+    pub name: String,
+    pub description: String,
+}
+
+impl NormalizationVersion {
+    pub fn all() -> Vec<NormalizationVersion> {
+        (1..=NORMALIZATION_VERSION)
+            .map(|version| NormalizationVersion {
+                version,
+                transforms: transforms_of(version)
+                    .map(|transform| NormalizationStep {
+                        name: transform.name.to_string(),
+                        description: transform.description.to_string(),
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+fn transforms_of(version: u32) -> impl Iterator<Item = &'static PayloadTransform> {
+    PAYLOAD_TRANSFORMS
+        .iter()
+        .filter(move |transform| transform.is_in(version))
+}
+
+// Applies a version of the normalization to a payload
+// Only the JSON objects are normalized, and a payload that doesn't change is returned as it is (e.g. with its spaces)
+pub fn normalize(data: &AgriData, version: u32) -> Result<AgriData, TransactionError> {
+    if version == 0 || version > NORMALIZATION_VERSION {
+        return Err(TransactionError::UnknownNormalization(version));
+    }
+    let mut fields = match serde_json::from_str(&data.as_json()) {
+        Ok(Value::Object(fields)) => fields,
+        _ => return Ok(data.clone()),
+    };
+
+    let original = fields.clone();
+    for transform in transforms_of(version) {
+        (transform.apply)(&mut fields);
+    }
+    if fields == original {
+        return Ok(data.clone());
+    }
+
+    // the typed payloads keep their type tag, the raw ones stay JSON text
+    match data {
+        AgriData::Raw(_) => Ok(AgriData::Raw(Value::Object(fields).to_string())),
+        _ => serde_json::from_value(Value::Object(fields))
+            .map_err(|_| TransactionError::InvalidPayload),
+    }
+}
+
+// Checks that a payload is what the version of the normalization recorded with it makes of it
+pub fn check_normalized(data: &AgriData, version: u32) -> Result<(), TransactionError> {
+    let normalized = normalize(data, version)?;
+    match normalized.as_json() == data.as_json() {
+        true => Ok(()),
+        false => Err(TransactionError::NotNormalized(version)),
+    }
+}
+
+// The field is renamed only if the payload doesn't have it already
+fn rename_aliases(fields: &mut Map<String, Value>) {
+    let aliases = [
+        ("qty", "quantity"),
+        ("weight", "quantity"),
+        ("weight_kg", "quantity_kg"),
+        ("temp", "temperature"),
+        ("rh", "humidity"),
+    ];
+    for (alias, name) in aliases {
+        if fields.contains_key(name) {
+            continue;
+        }
+        if let Some(value) = fields.remove(alias) {
+            fields.insert(name.to_string(), value);
+        }
+    }
+}
+
+fn normalize_units(fields: &mut Map<String, Value>) {
+    // a quantity with its unit, e.g. "1.5t", goes to `quantity_kg` unless the payload already has it
+    if !fields.contains_key("quantity_kg") {
+        let quantity_kg = fields
+            .get("quantity")
+            .and_then(Value::as_str)
+            .and_then(|text| to_kg(text, None));
+        if let Some(quantity_kg) = quantity_kg {
+            fields.remove("quantity");
+            fields.insert("quantity_kg".to_string(), Value::from(quantity_kg));
+        }
+    }
+
+    let conversions: [(&str, Conversion); 3] = [
+        ("quantity_kg", |text| to_kg(text, Some("kg"))),
+        ("temperature", to_celsius),
+        ("humidity", to_percent),
+    ];
+    for (name, convert) in conversions {
+        let converted = fields.get(name).and_then(Value::as_str).and_then(convert);
+        if let Some(value) = converted {
+            fields.insert(name.to_string(), Value::from(value));
+        }
+    }
+}
+
+// Number of a measure given as text, in the unit of its field
+type Conversion = fn(&str) -> Option<f64>;
+
+// Kilograms of a quantity, rounded to the gram
+pub fn to_kg(text: &str, default_unit: Option<&str>) -> Option<f64> {
+    let (value, unit) = parse_measure(text)?;
+    let unit = match (unit, default_unit) {
+        ("", Some(default_unit)) => default_unit.to_string(),
+        (unit, _) => unit.to_lowercase(),
+    };
+    let factor = match unit.as_str() {
+        "kg" | "kgs" => 1.0,
+        "g" => 0.001,
+        "t" | "ton" | "tons" | "tonne" | "tonnes" => 1000.0,
+        "lb" | "lbs" => 0.453_592_37,
+        _ => return None,
+    };
+    Some((value * factor * 1000.0).round() / 1000.0)
+}
+
+// Degrees celsius of a temperature, rounded to the hundredth
+fn to_celsius(text: &str) -> Option<f64> {
+    let (value, unit) = parse_measure(text)?;
+    let celsius = match unit.trim_start_matches('°').to_uppercase().as_str() {
+        "" | "C" => value,
+        "F" => (value - 32.0) * 5.0 / 9.0,
+        _ => return None,
+    };
+    Some((celsius * 100.0).round() / 100.0)
+}
+
+fn to_percent(text: &str) -> Option<f64> {
+    let (value, unit) = parse_measure(text)?;
+    match unit {
+        "" | "%" => Some(value),
+        _ => None,
+    }
+}
+
+// Splits a measure like "500 kg" or "-18°C" into its value and its unit
+fn parse_measure(text: &str) -> Option<(f64, &str)> {
+    let text = text.trim();
+    let end = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+'))
+        .unwrap_or(text.len());
+    let value: f64 = text[..end].parse().ok()?;
+    match value.is_finite() {
+        true => Some((value, text[end..].trim())),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_normalize_the_units_and_the_aliases() {
+        let data: AgriData =
+            r#"{"crop": "wheat", "qty": "1.5t", "temp": "39.2°F", "rh": "65%"}"#.into();
+        let normalized = normalize(&data, 1).unwrap();
+        let fields: Value = serde_json::from_str(&normalized.as_json()).unwrap();
+        assert_eq!(
+            fields,
+            serde_json::json!({"crop": "wheat", "quantity_kg": 1500.0, "temperature": 4.0, "humidity": 65.0})
+        );
+        assert_eq!(check_normalized(&normalized, 1), Ok(()));
+        assert_eq!(
+            check_normalized(&data, 1),
+            Err(TransactionError::NotNormalized(1))
+        );
+
+        // the normalized payloads, and the ones that are not JSON objects, are kept as they are
+        for text in [r#"{"quantity_kg":  500}"#, "500kg of wheat", r#""500kg""#] {
+            let data: AgriData = text.into();
+            assert_eq!(normalize(&data, 1), Ok(data));
+        }
+        assert_eq!(
+            normalize(&data, NORMALIZATION_VERSION + 1),
+            Err(TransactionError::UnknownNormalization(
+                NORMALIZATION_VERSION + 1
+            ))
+        );
+    }
+
+    #[test]
+    fn should_keep_the_fields_that_cannot_be_normalized() {
+        // already in the payload, or in an unknown unit
+        let data: AgriData =
+            r#"{"quantity": "300lb", "quantity_kg": "136", "qty": 2, "temperature": "4K"}"#.into();
+        let normalized = normalize(&data, 1).unwrap();
+        let fields: Value = serde_json::from_str(&normalized.as_json()).unwrap();
+        assert_eq!(
+            fields,
+            serde_json::json!({"quantity": "300lb", "quantity_kg": 136.0, "qty": 2, "temperature": "4K"})
+        );
+
+        // the typed payloads stay typed
+        let data: AgriData = serde_json::from_str(
+            r#"{"type": "STORAGE", "facility": "Silo-2", "temperature": -18, "weight": "2 tons"}"#,
+        )
+        .unwrap();
+        match normalize(&data, 1).unwrap() {
+            AgriData::Storage(storage) => {
+                assert_eq!(storage.temperature, Some(-18.0));
+                assert_eq!(storage.extra["quantity_kg"], 2000.0);
+            }
+            other => panic!("unexpected payload {:?}", other),
+        }
+    }
+}
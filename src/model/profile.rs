@@ -0,0 +1,94 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::TransactionError;
+
+pub const PROFILE_EVENT: &str = "PROFILE";
+
+// Profiles are stored on chain, so we keep them small
+const MAX_PROFILE_SIZE: usize = 1024;
+
+// Self-described metadata that an actor publishes about itself in a PROFILE event
+// A newer profile from the same actor replaces the previous one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub display_name: String,
+    #[serde(default)]
+    pub location: Option<String>,
+    #[serde(default)]
+    pub contact: Option<String>,
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+impl Profile {
+    // Parses and validates the profile contained in the data of a PROFILE event
+    pub fn parse(data: &str) -> Result<Profile, TransactionError> {
+        if data.len() > MAX_PROFILE_SIZE {
+            return Err(TransactionError::ProfileTooLarge);
+        }
+
+        let profile: Profile =
+            serde_json::from_str(data).map_err(|_| TransactionError::InvalidProfile)?;
+        if profile.display_name.trim().is_empty() {
+            return Err(TransactionError::InvalidProfile);
+        }
+
+        Ok(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_valid_profile() {
+        let data = r#"{
+            "display_name": "Green Valley Farm",
+            "location": "Punjab",
+            "contact": "info@greenvalley.example",
+            "roles": ["FARMER"]
+        }"#;
+
+        let profile = Profile::parse(data).unwrap();
+        assert_eq!(profile.display_name, "Green Valley Farm");
+        assert_eq!(profile.location, Some("Punjab".to_string()));
+        assert_eq!(profile.roles, vec!["FARMER"]);
+    }
+
+    #[test]
+    fn should_parse_profile_with_only_display_name() {
+        let profile = Profile::parse(r#"{"display_name": "Warehouse A"}"#).unwrap();
+
+        assert_eq!(profile.location, None);
+        assert_eq!(profile.contact, None);
+        assert!(profile.roles.is_empty());
+    }
+
+    #[test]
+    fn should_reject_invalid_profiles() {
+        let invalid_profiles = [
+            "not json",
+            r#"{"location": "Punjab"}"#,
+            r#"{"display_name": " "}"#,
+            r#"{"display_name": "Farm", "unknown": "field"}"#,
+        ];
+
+        for data in invalid_profiles.iter() {
+            assert_eq!(Profile::parse(data), Err(TransactionError::InvalidProfile));
+        }
+    }
+
+    #[test]
+    fn should_reject_too_large_profiles() {
+        let display_name = "a".repeat(MAX_PROFILE_SIZE);
+        let data = format!(r#"{{"display_name": "{}"}}"#, display_name);
+
+        assert_eq!(
+            Profile::parse(&data),
+            Err(TransactionError::ProfileTooLarge)
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+use super::{Address, CanonicalWriter, TransactionError};
+use crate::crypto::{self, SigningDomain};
+
+// The grade given by one of the inspectors of a quality check graded by several of them,
+// e.g. for the markets that only accept a grade agreed by the laboratory and the buyer's inspector
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QualityAttestation {
+    pub inspector: Address,
+    pub grade: String,
+    // share of the inspector in the decision, e.g. more for an accredited laboratory
+    pub weight: u64,
+    // hex ed25519 signature by the inspector of the batch, its grade and weight, and the threshold of the check,
+    // so the party that submits the check can change none of them
+    pub signature: String,
+}
+
+impl QualityAttestation {
+    pub fn sign(
+        key: &SigningKey,
+        batch_id: &str,
+        grade: &str,
+        weight: u64,
+        threshold: u64,
+    ) -> QualityAttestation {
+        let message = signed_bytes(batch_id, grade, weight, threshold);
+        let signature = crypto::sign(key, SigningDomain::QualityAttestation, &message);
+        QualityAttestation {
+            inspector: Address::from(key.verifying_key().to_bytes()),
+            grade: grade.to_string(),
+            weight,
+            signature: hex::encode(signature.to_bytes()),
+        }
+    }
+
+    // Checks that the inspector gave this grade to the batch, for a check with this threshold
+    pub fn verify(&self, batch_id: &str, threshold: u64) -> Result<(), TransactionError> {
+        let invalid = || TransactionError::InvalidAttestation(self.inspector.clone());
+        let signature_bytes: [u8; 64] = hex::decode(&self.signature)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(invalid)?;
+        let key = VerifyingKey::from_bytes(self.inspector.as_bytes()).map_err(|_| invalid())?;
+
+        let message = signed_bytes(batch_id, &self.grade, self.weight, threshold);
+        crypto::verify(
+            &key,
+            SigningDomain::QualityAttestation,
+            &message,
+            &Signature::from_bytes(&signature_bytes),
+        )
+        .map_err(|_| invalid())
+    }
+}
+
+fn signed_bytes(batch_id: &str, grade: &str, weight: u64, threshold: u64) -> Vec<u8> {
+    let mut writer = CanonicalWriter::default();
+    writer.str(batch_id);
+    writer.str(grade);
+    writer.u64(weight);
+    writer.u64(threshold);
+    writer.into_bytes()
+}
+
+// The grade that the inspectors of a check agreed on, if any, and the weight given to each grade
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QualityConsensus {
+    pub grade: Option<String>,
+    pub threshold: u64,
+    pub weights: BTreeMap<String, u64>,
+}
+
+impl QualityConsensus {
+    // The grade with the most weight is agreed when its weight reaches the threshold,
+    // a tie with another grade is not an agreement
+    // The attestations must have been verified before
+    pub fn compute(attestations: &[QualityAttestation], threshold: u64) -> QualityConsensus {
+        let mut weights: BTreeMap<String, u64> = BTreeMap::new();
+        for attestation in attestations.iter() {
+            let weight = weights.entry(attestation.grade.clone()).or_default();
+            *weight = weight.saturating_add(attestation.weight);
+        }
+
+        let heaviest = weights.values().copied().max().unwrap_or(0);
+        let mut candidates = weights.iter().filter(|(_, weight)| **weight == heaviest);
+        let grade = match (candidates.next(), candidates.next()) {
+            (Some((grade, weight)), None) if *weight >= threshold => Some(grade.clone()),
+            _ => None,
+        };
+
+        QualityConsensus {
+            grade,
+            threshold,
+            weights,
+        }
+    }
+}
+
+// Checks the attestations of a quality check: signed for its batch and threshold, one per inspector,
+// and that the grade of the check is the agreed one, or that there is no grade without an agreement
+pub fn check_consensus(
+    batch_id: &str,
+    grade: Option<&String>,
+    threshold: u64,
+    attestations: &[QualityAttestation],
+) -> Result<QualityConsensus, TransactionError> {
+    if threshold == 0 {
+        return Err(TransactionError::InvalidPayload);
+    }
+    for (position, attestation) in attestations.iter().enumerate() {
+        let repeated = attestations[..position]
+            .iter()
+            .any(|previous| previous.inspector == attestation.inspector);
+        if repeated || attestation.weight == 0 {
+            return Err(TransactionError::InvalidAttestation(
+                attestation.inspector.clone(),
+            ));
+        }
+        attestation.verify(batch_id, threshold)?;
+    }
+
+    let consensus = QualityConsensus::compute(attestations, threshold);
+    match grade == consensus.grade.as_ref() {
+        true => Ok(consensus),
+        false => Err(TransactionError::GradeNotAgreed(
+            consensus.grade.unwrap_or_else(|| "none".to_string()),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLD: u64 = 60;
+
+    #[test]
+    fn should_agree_on_the_grade_with_enough_weight() {
+        let laboratory = attest(1, "A", 50);
+        let buyer = attest(2, "A", 20);
+        let farm = attest(3, "B", 30);
+
+        let consensus =
+            QualityConsensus::compute(&[laboratory.clone(), buyer, farm.clone()], THRESHOLD);
+        assert_eq!(consensus.grade, Some("A".to_string()));
+        assert_eq!(
+            consensus.weights,
+            BTreeMap::from([("A".to_string(), 70), ("B".to_string(), 30)])
+        );
+
+        // not enough weight, and a tie
+        let consensus = QualityConsensus::compute(&[laboratory.clone(), farm.clone()], THRESHOLD);
+        assert_eq!(consensus.grade, None);
+        let consensus = QualityConsensus::compute(&[laboratory, farm], 50);
+        assert_eq!(consensus.grade, Some("A".to_string()));
+        let tie = [attest(1, "A", 30), attest(2, "B", 30)];
+        assert_eq!(QualityConsensus::compute(&tie, 10).grade, None);
+    }
+
+    #[test]
+    fn should_check_the_attestations() {
+        let attestations = vec![attest(1, "A", 50), attest(2, "A", 20)];
+        let grade = Some("A".to_string());
+        assert!(check_consensus("WHEAT-1", grade.as_ref(), THRESHOLD, &attestations).is_ok());
+
+        // the grade must be the agreed one, and there is no grade without an agreement
+        assert_eq!(
+            check_consensus("WHEAT-1", Some(&"B".to_string()), THRESHOLD, &attestations),
+            Err(TransactionError::GradeNotAgreed("A".to_string()))
+        );
+        assert_eq!(
+            check_consensus("WHEAT-1", grade.as_ref(), THRESHOLD, &attestations[..1]),
+            Err(TransactionError::GradeNotAgreed("none".to_string()))
+        );
+        assert!(check_consensus("WHEAT-1", None, THRESHOLD, &attestations[..1]).is_ok());
+
+        // signed for another batch or another threshold
+        let inspector = attestations[0].inspector.clone();
+        assert_eq!(
+            check_consensus("WHEAT-2", grade.as_ref(), THRESHOLD, &attestations),
+            Err(TransactionError::InvalidAttestation(inspector.clone()))
+        );
+        assert_eq!(
+            check_consensus("WHEAT-1", grade.as_ref(), 40, &attestations),
+            Err(TransactionError::InvalidAttestation(inspector.clone()))
+        );
+
+        // a weight raised after signing, and an inspector counted twice
+        let mut raised = attestations.clone();
+        raised[1].weight = 60;
+        assert!(check_consensus("WHEAT-1", grade.as_ref(), THRESHOLD, &raised).is_err());
+        let repeated = vec![attestations[0].clone(), attestations[0].clone()];
+        assert_eq!(
+            check_consensus("WHEAT-1", grade.as_ref(), THRESHOLD, &repeated),
+            Err(TransactionError::InvalidAttestation(inspector))
+        );
+    }
+
+    fn attest(key: u8, grade: &str, weight: u64) -> QualityAttestation {
+        let key = SigningKey::from_bytes(&[key; 32]);
+        QualityAttestation::sign(&key, "WHEAT-1", grade, weight, THRESHOLD)
+    }
+}
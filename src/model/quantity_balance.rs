@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use serde_json::Value;
+
+use super::{
+    BatchPortion, EventType, Merge, Split, Transaction, TransactionError, MERGE_EVENT, SPLIT_EVENT,
+};
+
+// Rounding of the quantities reported as decimals, e.g. 0.1 + 0.2 kg
+const TOLERANCE_KG: f64 = 1e-6;
+
+// How the node enforces that no event moves more of a batch than it has left
+// Only "reject" is a consensus rule, so all the nodes of a network that use it must use it
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum QuantityStrictness {
+    Off,
+    // the events are accepted, and the submitters warned
+    #[default]
+    Flag,
+    // the events are rejected, in the pool and in the blocks
+    Reject,
+}
+
+impl FromStr for QuantityStrictness {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(QuantityStrictness::Off),
+            "flag" => Ok(QuantityStrictness::Flag),
+            "reject" => Ok(QuantityStrictness::Reject),
+            _ => Err(format!("Unknown quantity strictness `{}`", s)),
+        }
+    }
+}
+
+// The kilograms left of a batch, from the quantities in the payloads of its events:
+// set by the harvest, split or merge that created it, replaced by the output of a processing,
+// and reduced by the splits (what goes to the children), the merges it goes into and the sales
+// Transports move the batch as a whole, so they can't carry more than it has left, but don't reduce it
+// Nothing is checked for the batches harvested without a quantity
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct QuantityBalance {
+    remaining_kg: Option<f64>,
+}
+
+impl QuantityBalance {
+    pub fn remaining_kg(&self) -> Option<f64> {
+        self.remaining_kg
+    }
+
+    // Checks that an event doesn't move more than what is left of its batch,
+    // and that the processing of a batch with a known quantity records its yield
+    pub fn check(&self, transaction: &Transaction) -> Result<(), TransactionError> {
+        let moved = moved_kg(transaction);
+        if transaction.event_type == EventType::Processing
+            && self.remaining_kg.is_some()
+            && moved.is_none()
+        {
+            return Err(TransactionError::YieldNotRecorded(
+                transaction.batch_id.clone(),
+            ));
+        }
+
+        match moved {
+            Some(moved) => self.check_taken(&transaction.batch_id, moved),
+            None => Ok(()),
+        }
+    }
+
+    // Checks that a quantity taken from a batch is not more than what it has left
+    fn check_taken(&self, batch_id: &str, taken_kg: f64) -> Result<(), TransactionError> {
+        match self.remaining_kg {
+            Some(remaining) if taken_kg > remaining + TOLERANCE_KG => Err(
+                TransactionError::QuantityExceeded(batch_id.to_string(), taken_kg, remaining),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn apply(&mut self, transaction: &Transaction) {
+        let quantity = moved_kg(transaction);
+        match &transaction.event_type {
+            EventType::Harvest | EventType::Processing => {
+                self.remaining_kg = quantity.or(self.remaining_kg)
+            }
+            event_type if *event_type == MERGE_EVENT => self.remaining_kg = quantity,
+            EventType::Sale => self.subtract(quantity),
+            event_type if *event_type == SPLIT_EVENT => self.subtract(quantity),
+            _ => {}
+        }
+    }
+
+    fn subtract(&mut self, quantity: Option<f64>) {
+        if let (Some(remaining), Some(quantity)) = (self.remaining_kg, quantity) {
+            self.remaining_kg = Some((remaining - quantity).max(0.0));
+        }
+    }
+}
+
+// The balances of all the batches of the chain, where the splits and merges move the quantities between them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QuantityLedger {
+    balances: HashMap<String, QuantityBalance>,
+}
+
+impl QuantityLedger {
+    // The balances after the transactions of the chain in order
+    pub fn of<'a, I>(transactions: I) -> QuantityLedger
+    where
+        I: IntoIterator<Item = &'a Transaction>,
+    {
+        let mut ledger = QuantityLedger::default();
+        for transaction in transactions {
+            ledger.apply(transaction);
+        }
+        ledger
+    }
+
+    pub fn balance_of(&self, batch_id: &str) -> QuantityBalance {
+        self.balances.get(batch_id).copied().unwrap_or_default()
+    }
+
+    // Checks an event against the balance of its batch, and a merge against the balances of its parents
+    pub fn check(&self, transaction: &Transaction) -> Result<(), TransactionError> {
+        if transaction.batch_id.is_empty() {
+            return Ok(());
+        }
+        self.balance_of(&transaction.batch_id).check(transaction)?;
+
+        if transaction.event_type == MERGE_EVENT {
+            if let Ok(merge) = Merge::parse(&transaction.data.as_json()) {
+                for parent in merge.parents.iter() {
+                    self.balance_of(&parent.batch_id)
+                        .check_taken(&parent.batch_id, parent.quantity_kg)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn apply(&mut self, transaction: &Transaction) {
+        if transaction.batch_id.is_empty() {
+            return;
+        }
+        self.balances
+            .entry(transaction.batch_id.clone())
+            .or_default()
+            .apply(transaction);
+
+        // the children of a split start with their portion, and the parents of a merge give theirs
+        let data = transaction.data.as_json();
+        match transaction.event_type.as_str() {
+            SPLIT_EVENT => {
+                for child in Split::parse(&data)
+                    .map(|split| split.children)
+                    .unwrap_or_default()
+                {
+                    let balance = self.balances.entry(child.batch_id).or_default();
+                    balance.remaining_kg = Some(child.quantity_kg);
+                }
+            }
+            MERGE_EVENT => {
+                for parent in Merge::parse(&data)
+                    .map(|merge| merge.parents)
+                    .unwrap_or_default()
+                {
+                    let balance = self.balances.entry(parent.batch_id).or_default();
+                    balance.subtract(Some(parent.quantity_kg));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// The kilograms that an event harvests, produces or takes from its batch, if it reports them
+fn moved_kg(transaction: &Transaction) -> Option<f64> {
+    let data = transaction.data.as_json();
+    let field = match &transaction.event_type {
+        EventType::Processing => "output_kg",
+        EventType::Harvest | EventType::Transport | EventType::Sale => "quantity_kg",
+        event_type if *event_type == SPLIT_EVENT => {
+            return Split::parse(&data)
+                .ok()
+                .map(|split| total_kg(&split.children))
+        }
+        event_type if *event_type == MERGE_EVENT => {
+            return Merge::parse(&data)
+                .ok()
+                .map(|merge| total_kg(&merge.parents))
+        }
+        _ => return None,
+    };
+
+    let payload: Value = serde_json::from_str(&data).ok()?;
+    payload.get(field)?.as_f64().filter(|kg| *kg >= 0.0)
+}
+
+fn total_kg(portions: &[BatchPortion]) -> f64 {
+    portions.iter().map(|portion| portion.quantity_kg).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_track_the_quantity_left_of_a_batch() {
+        let events = vec![
+            event("HARVEST", r#"{"quantity_kg": 500}"#),
+            event("TRANSPORT", r#"{"quantity_kg": 500}"#),
+            event(
+                "SPLIT",
+                r#"{"children": [{"batch_id": "WHEAT-1-A", "quantity_kg": 150}, {"batch_id": "WHEAT-1-B", "quantity_kg": 50}]}"#,
+            ),
+            event("STORAGE", r#"{"quantity_kg": 1000}"#),
+        ];
+        let balance = QuantityLedger::of(&events).balance_of("WHEAT-1");
+        assert_eq!(balance.remaining_kg, Some(300.0));
+
+        // a transport or a sale can't carry more than what is left, nor a processing produce more
+        let too_much = [
+            event("TRANSPORT", r#"{"quantity_kg": 300.5}"#),
+            event("SALE", r#"{"quantity_kg": 400}"#),
+            event("PROCESSING", r#"{"output_kg": 350}"#),
+            event(
+                "SPLIT",
+                r#"{"children": [{"batch_id": "WHEAT-1-C", "quantity_kg": 301}]}"#,
+            ),
+        ];
+        for transaction in too_much.iter() {
+            assert!(matches!(
+                balance.check(transaction),
+                Err(TransactionError::QuantityExceeded(_, _, remaining)) if remaining == 300.0
+            ));
+        }
+        assert_eq!(
+            balance.check(&event("SALE", r#"{"quantity_kg": 300}"#)),
+            Ok(())
+        );
+
+        // the yield of a processing is what is left afterwards
+        let mut processed = balance;
+        processed.apply(&event("PROCESSING", r#"{"output_kg": 250}"#));
+        assert_eq!(processed.remaining_kg, Some(250.0));
+
+        // a merged batch holds what was taken from its parents
+        let merge = event(
+            "MERGE",
+            r#"{"parents": [{"batch_id": "WHEAT-2", "quantity_kg": 250}, {"batch_id": "WHEAT-3", "quantity_kg": 100}]}"#,
+        );
+        let merged = QuantityLedger::of(&[merge]).balance_of("WHEAT-1");
+        assert_eq!(merged.remaining_kg, Some(350.0));
+
+        // the processing of a batch with a known quantity records its yield
+        assert_eq!(
+            balance.check(&event("PROCESSING", r#"{"process": "milling"}"#)),
+            Err(TransactionError::YieldNotRecorded("WHEAT-1".to_string()))
+        );
+    }
+
+    #[test]
+    fn should_move_the_quantities_between_the_batches() {
+        let mut ledger = QuantityLedger::of(&[
+            event("HARVEST", r#"{"quantity_kg": 500}"#),
+            event(
+                "SPLIT",
+                r#"{"children": [{"batch_id": "WHEAT-1-A", "quantity_kg": 200}]}"#,
+            ),
+        ]);
+        assert_eq!(ledger.balance_of("WHEAT-1").remaining_kg, Some(300.0));
+        assert_eq!(ledger.balance_of("WHEAT-1-A").remaining_kg, Some(200.0));
+
+        // a child can't give more than its portion, even if its parent has more left
+        let sale = Transaction {
+            batch_id: "WHEAT-1-A".to_string(),
+            ..event("SALE", r#"{"quantity_kg": 250}"#)
+        };
+        assert!(ledger.check(&sale).is_err());
+
+        // the parents of a merge give their portions, which they must have left
+        let merge = |wheat_kg: f64| {
+            Transaction {
+            batch_id: "MIX-1".to_string(),
+            data: format!(
+                r#"{{"parents": [{{"batch_id": "WHEAT-1", "quantity_kg": {}}}, {{"batch_id": "WHEAT-1-A", "quantity_kg": 200}}]}}"#,
+                wheat_kg
+            )
+            .as_str()
+            .into(),
+            ..event("MERGE", "")
+        }
+        };
+        assert_eq!(
+            ledger.check(&merge(350.0)),
+            Err(TransactionError::QuantityExceeded(
+                "WHEAT-1".to_string(),
+                350.0,
+                300.0
+            ))
+        );
+        assert_eq!(ledger.check(&merge(100.0)), Ok(()));
+        ledger.apply(&merge(100.0));
+        assert_eq!(ledger.balance_of("MIX-1").remaining_kg, Some(300.0));
+        assert_eq!(ledger.balance_of("WHEAT-1").remaining_kg, Some(200.0));
+        assert_eq!(ledger.balance_of("WHEAT-1-A").remaining_kg, Some(0.0));
+    }
+
+    #[test]
+    fn should_not_check_the_batches_without_a_quantity() {
+        let ledger = QuantityLedger::of(&[event("HARVEST", r#"{"crop": "wheat"}"#)]);
+        assert_eq!(ledger.balance_of("WHEAT-1").remaining_kg, None);
+        assert_eq!(
+            ledger.check(&event("SALE", r#"{"quantity_kg": 400}"#)),
+            Ok(())
+        );
+        assert_eq!(
+            ledger.check(&event("PROCESSING", r#"{"process": "milling"}"#)),
+            Ok(())
+        );
+    }
+
+    fn event(event_type: &str, data: &str) -> Transaction {
+        Transaction {
+            batch_id: "WHEAT-1".to_string(),
+            event_type: event_type.into(),
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::HashSet;
+
+use super::{transaction_hash, Block, Transaction};
+
+// A replacement of the last blocks of the main chain by the blocks of a longer fork, received from the peers
+// The modules that keep an index of the chain from its events rebuild it from there: the blocks rolled back
+// are not in the chain anymore, and the applied ones are not published as applied blocks
+#[derive(Debug, Clone)]
+pub struct ReorgEvent {
+    // index of the last block in common with the fork, it's kept
+    pub ancestor: u64,
+    // the blocks removed from the main chain, in chain order
+    pub rolled_back: Vec<Block>,
+    // the blocks of the fork appended after the ancestor, in chain order
+    pub applied: Vec<Block>,
+}
+
+impl ReorgEvent {
+    // Amount of blocks rolled back
+    pub fn depth(&self) -> u64 {
+        self.rolled_back.len() as u64
+    }
+
+    // The transactions of the rolled back blocks that the fork doesn't include, to be mined again
+    // The coinbase transactions of the miners are left out, they only make sense in their own block
+    pub fn returned_transactions(&self) -> Vec<Transaction> {
+        let included: HashSet<_> = self
+            .applied
+            .iter()
+            .flat_map(|block| block.body.transactions.iter())
+            .map(transaction_hash)
+            .collect();
+
+        self.rolled_back
+            .iter()
+            .flat_map(|block| block.body.transactions.iter())
+            .filter(|transaction| !transaction.is_coinbase())
+            .filter(|transaction| !included.contains(&transaction_hash(transaction)))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        fixtures::{alice, bob},
+        BlockHash,
+    };
+
+    #[test]
+    fn should_return_the_transactions_left_out_of_the_fork() {
+        let event = |batch_id: &str| Transaction {
+            sender: alice(),
+            recipient: bob(),
+            batch_id: batch_id.to_string(),
+            event_type: "HARVEST".into(),
+            ..Default::default()
+        };
+        let coinbase = Transaction::coinbase(alice());
+        let rolled_back = Block::new(
+            1,
+            0,
+            BlockHash::zero(),
+            vec![coinbase.clone(), event("WHEAT-1"), event("WHEAT-2")],
+        );
+        let applied = Block::new(1, 1, BlockHash::zero(), vec![coinbase, event("WHEAT-2")]);
+        let reorg = ReorgEvent {
+            ancestor: 0,
+            rolled_back: vec![rolled_back],
+            applied: vec![applied.clone(), applied],
+        };
+
+        assert_eq!(reorg.depth(), 1);
+        let returned: Vec<_> = reorg
+            .returned_transactions()
+            .iter()
+            .map(transaction_hash)
+            .collect();
+        assert_eq!(returned, vec![transaction_hash(&event("WHEAT-1"))]);
+    }
+}
@@ -0,0 +1,215 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{transaction_hash, Address, Transaction, TransactionError, TxHash};
+
+pub const PLANNED_EVENT: &str = "PLANNED";
+
+// An event planned for a batch, e.g. the pickup of a harvest by a transporter before a given day
+// Plans are kept apart from the facts: they don't move the batch, and are not part of its history
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PlannedEvent {
+    // type of the expected event (e.g. "TRANSPORT")
+    pub event_type: String,
+    // timestamp in milliseconds by which the event is expected
+    pub due: i64,
+    // the only actor whose event fulfills the plan, any actor if none
+    #[serde(default)]
+    #[schema(value_type = Option<String>)]
+    pub actor: Option<Address>,
+}
+
+impl PlannedEvent {
+    // Parses the plan contained in the data of a PLANNED event
+    pub fn parse(data: &str) -> Result<PlannedEvent, TransactionError> {
+        let planned: PlannedEvent =
+            serde_json::from_str(data).map_err(|_| TransactionError::InvalidPlan)?;
+        if planned.event_type.is_empty() || planned.event_type == PLANNED_EVENT {
+            return Err(TransactionError::InvalidPlan);
+        }
+
+        Ok(planned)
+    }
+
+    // Checks if an event of the planned batch is the one that was expected
+    pub fn is_fulfilled_by(&self, transaction: &Transaction) -> bool {
+        let actor = transaction
+            .on_behalf_of
+            .as_ref()
+            .unwrap_or(&transaction.sender);
+
+        transaction.event_type == self.event_type.as_str()
+            && self.actor.as_ref().is_none_or(|expected| expected == actor)
+    }
+}
+
+// Where a plan is at a given time
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PlanStatus {
+    // the event is still expected before its due time
+    Scheduled,
+    // the due time passed without the event
+    Overdue,
+    Fulfilled,
+    // the event happened, but after its due time
+    FulfilledLate,
+}
+
+// A plan recorded on chain, and the actual event that fulfilled it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    // hash of the PLANNED transaction
+    pub hash: TxHash,
+    pub batch_id: String,
+    pub planned_by: Address,
+    pub planned: PlannedEvent,
+    // hash of the event and timestamp in milliseconds of its block
+    pub fulfilled_by: Option<(TxHash, i64)>,
+}
+
+impl Plan {
+    pub fn status(&self, now: i64) -> PlanStatus {
+        match self.fulfilled_by {
+            Some((_, timestamp)) if timestamp <= self.planned.due => PlanStatus::Fulfilled,
+            Some(_) => PlanStatus::FulfilledLate,
+            None if now > self.planned.due => PlanStatus::Overdue,
+            None => PlanStatus::Scheduled,
+        }
+    }
+}
+
+// The plans of all the batches, in chain order
+// Each event fulfills the open plan of its batch that expected it with the earliest due time
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schedule {
+    plans: Vec<Plan>,
+}
+
+impl Schedule {
+    // Records a plan, or fulfills one with an actual event, recorded in a block at a given time
+    pub fn apply(&mut self, transaction: &Transaction, timestamp: i64) {
+        if transaction.batch_id.is_empty() {
+            return;
+        }
+
+        if transaction.event_type == PLANNED_EVENT {
+            if let Ok(planned) = PlannedEvent::parse(&transaction.data.as_json()) {
+                self.plans.push(Plan {
+                    hash: transaction_hash(transaction),
+                    batch_id: transaction.batch_id.clone(),
+                    planned_by: transaction.sender.clone(),
+                    planned,
+                    fulfilled_by: None,
+                });
+            }
+            return;
+        }
+
+        let fulfilled = self
+            .plans
+            .iter_mut()
+            .filter(|plan| {
+                plan.fulfilled_by.is_none()
+                    && plan.batch_id == transaction.batch_id
+                    && plan.planned.is_fulfilled_by(transaction)
+            })
+            .min_by_key(|plan| plan.planned.due);
+        if let Some(plan) = fulfilled {
+            plan.fulfilled_by = Some((transaction_hash(transaction), timestamp));
+        }
+    }
+
+    pub fn plans_of(&self, batch_id: &str) -> Vec<Plan> {
+        self.plans
+            .iter()
+            .filter(|plan| plan.batch_id == batch_id)
+            .cloned()
+            .collect()
+    }
+
+    // The plans of all the batches whose event is late at a given time, the most overdue first
+    pub fn overdue(&self, now: i64) -> Vec<Plan> {
+        let mut overdue: Vec<Plan> = self
+            .plans
+            .iter()
+            .filter(|plan| plan.status(now) == PlanStatus::Overdue)
+            .cloned()
+            .collect();
+        overdue.sort_by_key(|plan| plan.planned.due);
+        overdue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::fixtures::{alice, bob};
+
+    #[test]
+    fn should_fulfill_the_plans_with_the_actual_events() {
+        let mut schedule = Schedule::default();
+        schedule.apply(&plan("WHEAT-1", "TRANSPORT", 2_000, None), 0);
+        schedule.apply(&plan("WHEAT-1", "TRANSPORT", 1_000, Some(bob())), 0);
+        schedule.apply(&plan("WHEAT-2", "STORAGE", 1_000, None), 0);
+
+        // only the expected actor fulfills a plan, then the open one due first
+        schedule.apply(&event("WHEAT-1", "TRANSPORT", alice()), 500);
+        let plans = schedule.plans_of("WHEAT-1");
+        assert_eq!(plans[0].status(500), PlanStatus::Fulfilled);
+        assert_eq!(plans[1].status(500), PlanStatus::Scheduled);
+        schedule.apply(&event("WHEAT-1", "TRANSPORT", bob()), 1_500);
+        let plans = schedule.plans_of("WHEAT-1");
+        assert_eq!(plans[1].status(1_500), PlanStatus::FulfilledLate);
+
+        // an event of another type or batch fulfills nothing
+        schedule.apply(&event("WHEAT-1", "STORAGE", alice()), 1_500);
+        let overdue = schedule.overdue(1_500);
+        assert_eq!(overdue.len(), 1);
+        assert_eq!(overdue[0].batch_id, "WHEAT-2");
+        assert!(schedule.overdue(500).is_empty());
+    }
+
+    #[test]
+    fn should_parse_the_planned_events() {
+        let planned = PlannedEvent::parse(r#"{"event_type": "TRANSPORT", "due": 1000}"#).unwrap();
+        assert_eq!(planned.actor, None);
+
+        let invalid = [
+            r#"{"event_type": "", "due": 1000}"#,
+            r#"{"event_type": "PLANNED", "due": 1000}"#,
+            r#"{"event_type": "TRANSPORT"}"#,
+        ];
+        for data in invalid.iter() {
+            assert_eq!(
+                PlannedEvent::parse(data),
+                Err(TransactionError::InvalidPlan)
+            );
+        }
+    }
+
+    fn plan(batch_id: &str, event_type: &str, due: i64, actor: Option<Address>) -> Transaction {
+        let planned = PlannedEvent {
+            event_type: event_type.to_string(),
+            due,
+            actor,
+        };
+        Transaction {
+            sender: alice(),
+            batch_id: batch_id.to_string(),
+            event_type: PLANNED_EVENT.into(),
+            data: serde_json::to_string(&planned).unwrap().as_str().into(),
+            ..Default::default()
+        }
+    }
+
+    fn event(batch_id: &str, event_type: &str, sender: Address) -> Transaction {
+        Transaction {
+            sender,
+            batch_id: batch_id.to_string(),
+            event_type: event_type.into(),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::TransactionError;
+
+// Event of the telemetry of a batch sent in bulk, e.g. the readings of the loggers of a truck every few minutes,
+// so a trip takes a few transactions instead of one per reading
+pub const SENSOR_READINGS_EVENT: &str = "SENSOR_READINGS";
+
+// Readings of one event, about a day of readings every 2 minutes
+pub const MAX_READINGS_PER_EVENT: usize = 720;
+
+// Position reported by the GPS of a device, in decimal degrees
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct GpsPosition {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+// A reading of a device, with the measures it takes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SensorReading {
+    pub device_id: String,
+    // timestamp in milliseconds when the reading was taken, before the block
+    pub recorded_at: i64,
+    // degrees celsius
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    // relative humidity in percent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub humidity: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gps: Option<GpsPosition>,
+}
+
+impl SensorReading {
+    // At least one measure, and only plausible ones
+    fn is_valid(&self) -> bool {
+        let humidity_valid = self
+            .humidity
+            .is_none_or(|humidity| (0.0..=100.0).contains(&humidity));
+        let temperature_valid = self
+            .temperature
+            .is_none_or(|temperature| temperature.is_finite());
+        let gps_valid = self.gps.is_none_or(|gps| {
+            (-90.0..=90.0).contains(&gps.lat) && (-180.0..=180.0).contains(&gps.lon)
+        });
+        let has_measure =
+            self.temperature.is_some() || self.humidity.is_some() || self.gps.is_some();
+
+        !self.device_id.is_empty()
+            && has_measure
+            && humidity_valid
+            && temperature_valid
+            && gps_valid
+    }
+}
+
+// The readings carried by a SENSOR_READINGS event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SensorReadings {
+    pub readings: Vec<SensorReading>,
+}
+
+impl SensorReadings {
+    // Parses the readings contained in the data of a SENSOR_READINGS event
+    pub fn parse(data: &str) -> Result<SensorReadings, TransactionError> {
+        let readings: SensorReadings =
+            serde_json::from_str(data).map_err(|_| TransactionError::InvalidSensorReadings)?;
+
+        // a device takes one reading at a time
+        let taken: BTreeSet<(&str, i64)> = readings
+            .readings
+            .iter()
+            .map(|reading| (reading.device_id.as_str(), reading.recorded_at))
+            .collect();
+        let is_valid = !readings.readings.is_empty()
+            && readings.readings.len() <= MAX_READINGS_PER_EVENT
+            && taken.len() == readings.readings.len()
+            && readings.readings.iter().all(SensorReading::is_valid);
+
+        match is_valid {
+            true => Ok(readings),
+            false => Err(TransactionError::InvalidSensorReadings),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_the_readings_of_the_devices() {
+        let readings = SensorReadings::parse(
+            r#"{"readings": [
+                {"device_id": "TRUCK-7", "recorded_at": 1000, "temperature": 4.2, "humidity": 81, "gps": {"lat": 31.52, "lon": 74.35}},
+                {"device_id": "TRUCK-7", "recorded_at": 121000, "temperature": 4.5}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(readings.readings.len(), 2);
+        assert_eq!(readings.readings[1].humidity, None);
+        assert_eq!(
+            readings.readings[0].gps,
+            Some(GpsPosition {
+                lat: 31.52,
+                lon: 74.35
+            })
+        );
+    }
+
+    #[test]
+    fn should_not_parse_invalid_readings() {
+        let reading = |fields: &str| format!(r#"{{"device_id": "TRUCK-7", {}}}"#, fields);
+        let invalid = [
+            r#"{"readings": []}"#.to_string(),
+            format!(r#"{{"readings": [{}]}}"#, reading(r#""recorded_at": 1000"#)),
+            format!(
+                r#"{{"readings": [{}]}}"#,
+                reading(r#""recorded_at": 1000, "humidity": 120"#)
+            ),
+            format!(
+                r#"{{"readings": [{}]}}"#,
+                reading(r#""recorded_at": 1000, "gps": {"lat": 91, "lon": 0}"#)
+            ),
+            format!(
+                r#"{{"readings": [{}]}}"#,
+                reading(r#""recorded_at": 1000, "temperature": 4, "pressure": 1"#)
+            ),
+            // the same reading twice
+            format!(
+                r#"{{"readings": [{0}, {0}]}}"#,
+                reading(r#""recorded_at": 1000, "temperature": 4"#)
+            ),
+        ];
+        for data in invalid.iter() {
+            assert_eq!(
+                SensorReadings::parse(data),
+                Err(TransactionError::InvalidSensorReadings)
+            );
+        }
+
+        let too_many: Vec<String> = (0..=MAX_READINGS_PER_EVENT)
+            .map(|at| reading(&format!(r#""recorded_at": {}, "temperature": 4"#, at)))
+            .collect();
+        let data = format!(r#"{{"readings": [{}]}}"#, too_many.join(","));
+        assert!(SensorReadings::parse(&data).is_err());
+    }
+}
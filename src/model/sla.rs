@@ -0,0 +1,94 @@
+use serde::{Deserialize, Serialize};
+
+use super::TransactionError;
+
+pub const SLA_EVENT: &str = "SLA";
+
+// Terms that a shipper (sender of the SLA event) agrees with a receiver (recipient) for their handoffs
+// Publishing newer terms for the same pair replaces the previous ones for the next handoffs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sla {
+    #[serde(default)]
+    pub max_transit_hours: Option<f64>,
+    // degrees celsius
+    #[serde(default)]
+    pub min_temperature: Option<f64>,
+    #[serde(default)]
+    pub max_temperature: Option<f64>,
+}
+
+impl Sla {
+    // Parses and validates the terms contained in the data of a SLA event
+    pub fn parse(data: &str) -> Result<Sla, TransactionError> {
+        let sla: Sla = serde_json::from_str(data).map_err(|_| TransactionError::InvalidSla)?;
+
+        let has_terms = sla.max_transit_hours.is_some()
+            || sla.min_temperature.is_some()
+            || sla.max_temperature.is_some();
+        let valid_bounds = match (sla.min_temperature, sla.max_temperature) {
+            (Some(min), Some(max)) => min <= max,
+            _ => true,
+        };
+        if !has_terms || !valid_bounds || sla.max_transit_hours.is_some_and(|hours| hours <= 0.0) {
+            return Err(TransactionError::InvalidSla);
+        }
+
+        Ok(sla)
+    }
+
+    // Returns why a handoff did not comply with the terms, if it didn't
+    pub fn check(&self, transit_hours: f64, temperatures: &[f64]) -> Vec<String> {
+        let mut violations = Vec::new();
+
+        if let Some(max_hours) = self.max_transit_hours {
+            if transit_hours > max_hours {
+                violations.push(format!(
+                    "Transit took {:.1} hours, more than {}",
+                    transit_hours, max_hours
+                ));
+            }
+        }
+
+        let min = self.min_temperature.unwrap_or(f64::MIN);
+        let max = self.max_temperature.unwrap_or(f64::MAX);
+        for temperature in temperatures.iter().filter(|t| **t < min || **t > max) {
+            violations.push(format!("Temperature {} out of bounds", temperature));
+        }
+
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_sla_terms() {
+        let data = r#"{"max_transit_hours": 48, "min_temperature": 2, "max_temperature": 8}"#;
+        let sla = Sla::parse(data).unwrap();
+        assert_eq!(sla.max_transit_hours, Some(48.0));
+
+        // at least one term is needed
+        assert_eq!(Sla::parse("{}"), Err(TransactionError::InvalidSla));
+        let data = r#"{"min_temperature": 8, "max_temperature": 2}"#;
+        assert_eq!(Sla::parse(data), Err(TransactionError::InvalidSla));
+        let data = r#"{"max_transit_hours": 48, "penalty": 100}"#;
+        assert_eq!(Sla::parse(data), Err(TransactionError::InvalidSla));
+    }
+
+    #[test]
+    fn should_check_handoffs() {
+        let sla = Sla::parse(r#"{"max_transit_hours": 48, "max_temperature": 8}"#).unwrap();
+
+        assert!(sla.check(24.0, &[4.0, 7.5]).is_empty());
+        assert_eq!(
+            sla.check(50.0, &[4.0, 9.5]),
+            vec![
+                "Transit took 50.0 hours, more than 48",
+                "Temperature 9.5 out of bounds"
+            ]
+        );
+    }
+}
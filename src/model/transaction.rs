@@ -1,20 +1,541 @@
+use std::collections::{BTreeMap, HashSet};
+
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use super::{
+    check_normalized, encode, merkle_tree::LEAF_PREFIX, normalize, transaction_hash, Address,
+    AgriData, BatchPortion, CanonicalWriter, ComplianceReport, Delegation, DocumentChunk,
+    DocumentManifest, Encoding, Escrow, EventType, Hashable, Lot, Merge, Namespace, PlannedEvent,
+    Profile, Registration, SensorReadings, Sla, Split, TxHash, ValidityWindow, CHUNK_EVENT,
+    COMPLIANCE_REPORT_EVENT, DELEGATION_EVENT, DOCUMENT_EVENT, ESCROW_EVENT, LOT_EVENT,
+    MERGE_EVENT, PLANNED_EVENT, PROFILE_EVENT, REGISTRATION_EVENT, SENSOR_READINGS_EVENT,
+    SLA_EVENT, SPLIT_EVENT,
+};
+use crate::crypto::{self, SigningDomain};
+
+// The reward of the miner of a block, logged in a batch of its own that no other event can use
+pub const COINBASE_EVENT: &str = "BLOCK_VALIDATION";
+pub const COINBASE_BATCH_ID: &str = "SYSTEM_LOG";
+
+// Limits of the extensions of a transaction, so they can't be used to bloat the blocks
+const MAX_EXTENSIONS_SIZE: usize = 4 * 1024;
+const MAX_EXTENSION_NAME_LENGTH: usize = 64;
+
+// Parties that an event can concern at once, e.g. the custodians of a recalled batch
+pub const MAX_RECIPIENTS: usize = 100;
+
+// Error types to return when a transaction is not valid
+#[derive(Error, PartialEq, Debug)]
+pub enum TransactionError {
+    #[error("Profiles can only be published by the actor itself")]
+    ProfileNotSelfPublished,
+
+    #[error("Invalid profile")]
+    InvalidProfile,
+
+    #[error("Profile too large")]
+    ProfileTooLarge,
+
+    #[error("Invalid delegation")]
+    InvalidDelegation,
+
+    #[error("The sender is not authorized to act on behalf of `{0}`")]
+    NotDelegated(Address),
+
+    #[error("Invalid lot")]
+    InvalidLot,
+
+    #[error("The lot `{0}` was already allocated")]
+    LotAlreadyAllocated(String),
+
+    #[error("Invalid SLA")]
+    InvalidSla,
+
+    #[error("Invalid document")]
+    InvalidDocument,
+
+    #[error("Invalid escrow")]
+    InvalidEscrow,
+
+    #[error("The batch `{0}` is already in escrow")]
+    EscrowPending(String),
+
+    #[error("The batch `{0}` was not released from escrow")]
+    EscrowNotReleased(String),
+
+    #[error("Invalid extension name `{0}`")]
+    InvalidExtensionName(String),
+
+    #[error("Extensions too large")]
+    ExtensionsTooLarge,
+
+    #[error("The transaction is not signed")]
+    MissingSignature,
+
+    #[error("Invalid coinbase, the reward of a miner only names the miner")]
+    InvalidCoinbase,
+
+    #[error("The batch `{0}` is reserved to the rewards of the miners")]
+    ReservedBatch(String),
+
+    #[error("Invalid signature")]
+    InvalidSignature,
+
+    #[error("The key does not belong to the sender, its address is `{0}`")]
+    SenderKeyMismatch(Address),
+
+    #[error("Unknown event type `{0}`, did you mean `{1}`?")]
+    UnknownEventType(String, String),
+
+    #[error("The batch `{0}` cannot go from `{1}` to `{2}`")]
+    InvalidTransition(String, String, String),
+
+    #[error("A `{0}` event cannot have a `{1}` payload")]
+    PayloadMismatch(String, String),
+
+    #[error("Invalid payload")]
+    InvalidPayload,
+
+    #[error("The transaction `{0:#x}` it responds to is not in the chain")]
+    UnknownReference(TxHash),
+
+    #[error("Invalid registration")]
+    InvalidRegistration,
+
+    #[error("Only the registrar `{0}` can register actors")]
+    NotRegistrar(Address),
+
+    #[error("The actor `{0}` needs the role `{1}` to emit `{2}` events")]
+    MissingRole(Address, String, String),
+
+    #[error(
+        "The actor `{0}` already emitted the most custody events allowed for the batch `{1}` ({2})"
+    )]
+    TooManyEvents(Address, String, usize),
+
+    #[error("Invalid quality attestation of `{0}`")]
+    InvalidAttestation(Address),
+
+    #[error("The grade of the check must be the one agreed by its inspectors: {0}")]
+    GradeNotAgreed(String),
+
+    #[error("The event moves {1}kg of the batch `{0}`, which only has {2}kg left")]
+    QuantityExceeded(String, f64, f64),
+
+    #[error("The processing of the batch `{0}` must record its yield in `output_kg`")]
+    YieldNotRecorded(String),
+
+    #[error("Invalid split")]
+    InvalidSplit,
+
+    #[error("Invalid merge")]
+    InvalidMerge,
+
+    #[error("The batch `{0}` already exists")]
+    BatchAlreadyExists(String),
+
+    #[error("Invalid plan")]
+    InvalidPlan,
+
+    #[error("Invalid sensor readings")]
+    InvalidSensorReadings,
+
+    #[error("Invalid recipients")]
+    InvalidRecipients,
+
+    #[error("Invalid compliance report")]
+    InvalidComplianceReport,
+
+    #[error("`{0}` never held the batch `{1}` or the batches that come from it")]
+    NotACustodian(Address, String),
+
+    #[error("Unknown version {0} of the payload normalization")]
+    UnknownNormalization(u32),
 
-use super::Address;
+    #[error("The payload is not normalized by the version {0} of the payload normalization")]
+    NotNormalized(u32),
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+    #[error("Invalid validity window")]
+    InvalidValidityWindow,
+
+    #[error("The transaction is not valid before {0}")]
+    NotYetValid(String),
+
+    #[error("The transaction is not valid after {0}")]
+    ValidityExpired(String),
+
+    #[error("Invalid namespace in `{0}`")]
+    InvalidNamespace(String),
+
+    #[error(
+        "The transaction `{0:#x}` is already in the chain, an identical event needs another nonce"
+    )]
+    Replayed(TxHash),
+
+    #[error("The actor `{0}` cannot emit events about the batch `{1}` of another namespace")]
+    ForeignNamespace(Address, String),
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Transaction {
-    pub sender: Address,    // Represents "Batch ID" (e.g., WHEAT-001)
+    #[schema(value_type = String)]
+    pub sender: Address, // Represents "Batch ID" (e.g., WHEAT-001)
+    #[schema(value_type = String)]
     pub recipient: Address, // Represents "Location/Actor" (e.g., WAREHOUSE-A)
-    pub data: String,       // NEW: Represents "Agri Details" (JSON String)
+    // Represents "Agri Details", typed for the lifecycle events or a JSON string
+    #[schema(value_type = Value)]
+    pub data: AgriData,
     pub batch_id: String,
-    pub event_type: String,
+    #[schema(value_type = String)]
+    pub event_type: EventType,
+    // Actor that the sender (e.g. a gateway) is acting for, it requires a valid delegation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub on_behalf_of: Option<Address>,
+    // Transaction that triggered this one (e.g. the DISPUTE that a DISPUTE_RESOLVED settles), it must be in the chain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub in_response_to: Option<TxHash>,
+    // Consortium-specific fields (e.g. "acme.contract_id"), hashed with the rest of the transaction
+    // Nodes don't need to understand them, so any extension is accepted within the limits
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[schema(value_type = Object)]
+    pub extensions: BTreeMap<String, Value>,
+    // Hex ed25519 signature of the rest of the transaction by the sender, whose address is its public key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub signature: Option<String>,
+    // Other parties that the event concerns besides the recipient (e.g. a RECALL to every custodian of the batch),
+    // they must have held the batch or a batch that comes from it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schema(value_type = Vec<String>)]
+    pub recipients: Vec<Address>,
+    // Version of the normalization applied to the payload before it was hashed (e.g. "500kg" to 500 in `quantity_kg`),
+    // the payload must be what that version makes of it, so anyone can normalize it again the same way
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normalization: Option<u32>,
+    // Blocks that can include the transaction when it is signed in advance and submitted later by someone else
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validity: Option<ValidityWindow>,
+    // Chosen by the sender to tell apart the events that are otherwise identical (e.g. two equal readings
+    // of a sensor), so each one has its own hash, signed and hashed with the rest of the transaction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<u64>,
+}
+
+impl Transaction {
+    // Unique identifier of the transaction, e.g. to reference it from another one, to prove that a block
+    // includes it or to follow it once submitted; it's also its leaf in the merkle tree of the block
+    pub fn hash(&self) -> TxHash {
+        self.canonical_hash()
+    }
+
+    // Checks that the contents of the transaction are consistent with its event type
+    pub fn validate(&self) -> Result<(), TransactionError> {
+        self.validate_extensions()?;
+        self.validate_recipients()?;
+        if let Some(version) = self.normalization {
+            check_normalized(&self.data, version)?;
+        }
+        if let Some(validity) = &self.validity {
+            validity.validate()?;
+        }
+        Namespace::of(&self.batch_id)?;
+
+        if let Some(known) = self.event_type.probable_typo() {
+            return Err(TransactionError::UnknownEventType(
+                self.event_type.to_string(),
+                known.to_string(),
+            ));
+        }
+        self.data.validate(&self.event_type)?;
+        if let AgriData::QualityCheck(check) = &self.data {
+            check.consensus(&self.batch_id)?;
+        }
+
+        // only the key of the sender can vouch for an event, the reward of the miner has no sender
+        // and carries nothing else than the miner, so no data can be slipped into a block without a signature
+        if self.is_coinbase() {
+            if transaction_hash(self)
+                != transaction_hash(&Transaction::coinbase(self.recipient.clone()))
+            {
+                return Err(TransactionError::InvalidCoinbase);
+            }
+        } else {
+            if self.batch_id == COINBASE_BATCH_ID {
+                return Err(TransactionError::ReservedBatch(self.batch_id.clone()));
+            }
+            self.verify()?;
+        }
+
+        match self.event_type.as_str() {
+            PROFILE_EVENT => {
+                // an actor can only describe itself
+                if self.sender != self.recipient {
+                    return Err(TransactionError::ProfileNotSelfPublished);
+                }
+                Profile::parse(&self.data.as_json())?;
+            }
+            DELEGATION_EVENT => {
+                if self.sender == self.recipient {
+                    return Err(TransactionError::InvalidDelegation);
+                }
+                Delegation::parse(&self.data.as_json())?;
+            }
+            SLA_EVENT => {
+                // terms are agreed with another actor
+                if self.sender == self.recipient {
+                    return Err(TransactionError::InvalidSla);
+                }
+                Sla::parse(&self.data.as_json())?;
+            }
+            ESCROW_EVENT => {
+                // the custody of a batch goes to another actor
+                if self.sender == self.recipient || self.batch_id.is_empty() {
+                    return Err(TransactionError::InvalidEscrow);
+                }
+                Escrow::parse(&self.data.as_json())?;
+            }
+            DOCUMENT_EVENT => {
+                DocumentManifest::parse(&self.data.as_json())?;
+            }
+            CHUNK_EVENT => {
+                DocumentChunk::parse(&self.data.as_json())?;
+            }
+            REGISTRATION_EVENT => {
+                Registration::parse(&self.data.as_json())?;
+            }
+            SPLIT_EVENT => {
+                // the children are new batches
+                let split = Split::parse(&self.data.as_json())?;
+                let is_own_child = |child: &BatchPortion| child.batch_id == self.batch_id;
+                if self.batch_id.is_empty() || split.children.iter().any(is_own_child) {
+                    return Err(TransactionError::InvalidSplit);
+                }
+            }
+            MERGE_EVENT => {
+                // the merged batch is a new one
+                let merge = Merge::parse(&self.data.as_json())?;
+                let is_own_parent = |parent: &BatchPortion| parent.batch_id == self.batch_id;
+                if self.batch_id.is_empty() || merge.parents.iter().any(is_own_parent) {
+                    return Err(TransactionError::InvalidMerge);
+                }
+            }
+            PLANNED_EVENT => {
+                // the plan of a batch
+                if self.batch_id.is_empty() {
+                    return Err(TransactionError::InvalidPlan);
+                }
+                PlannedEvent::parse(&self.data.as_json())?;
+            }
+            COMPLIANCE_REPORT_EVENT => {
+                // the report of the batch of the event
+                let report = ComplianceReport::parse(&self.data.as_json())?;
+                if self.batch_id.is_empty() || report.batch_id != self.batch_id {
+                    return Err(TransactionError::InvalidComplianceReport);
+                }
+            }
+            SENSOR_READINGS_EVENT => {
+                // the telemetry of a batch
+                if self.batch_id.is_empty() {
+                    return Err(TransactionError::InvalidSensorReadings);
+                }
+                SensorReadings::parse(&self.data.as_json())?;
+            }
+            LOT_EVENT => {
+                // the allocated identifier is the batch of the event
+                let lot = Lot::parse(&self.data.as_json())?;
+                if self.batch_id != lot.id() {
+                    return Err(TransactionError::InvalidLot);
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    // Normalizes the payload with a version of the normalization and records it, before signing
+    pub fn normalize(&mut self, version: u32) -> Result<(), TransactionError> {
+        self.data = normalize(&self.data, version)?;
+        self.normalization = Some(version);
+        Ok(())
+    }
+
+    // Signs the transaction with the key of the sender, replacing any previous signature
+    pub fn sign(&mut self, key: &SigningKey) -> Result<(), TransactionError> {
+        let signer = Address::from(key.verifying_key().to_bytes());
+        if signer != self.sender {
+            return Err(TransactionError::SenderKeyMismatch(signer));
+        }
+
+        let signature = crypto::sign(key, SigningDomain::Transaction, &self.signed_bytes()?);
+        self.signature = Some(hex::encode(signature.to_bytes()));
+        Ok(())
+    }
+
+    // Checks that the transaction was signed by its sender, and not modified after that
+    pub fn verify(&self) -> Result<(), TransactionError> {
+        let signature = self
+            .signature
+            .as_ref()
+            .ok_or(TransactionError::MissingSignature)?;
+        let signature_bytes: [u8; 64] = hex::decode(signature)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(TransactionError::InvalidSignature)?;
+        // only the lowercase hex of the signature, which is hashed as it is, so another spelling of the same
+        // signature (e.g. upper-cased) can't give the same transaction another hash and replay it
+        if hex::encode(signature_bytes) != *signature {
+            return Err(TransactionError::InvalidSignature);
+        }
+        let key = VerifyingKey::from_bytes(self.sender.as_bytes())
+            .map_err(|_| TransactionError::InvalidSignature)?;
+
+        crypto::verify(
+            &key,
+            SigningDomain::Transaction,
+            &self.signed_bytes()?,
+            &Signature::from_bytes(&signature_bytes),
+        )
+        .map_err(|_| TransactionError::InvalidSignature)
+    }
+
+    // The canonical JSON of everything but the signature itself
+    fn signed_bytes(&self) -> Result<Vec<u8>, TransactionError> {
+        let unsigned = Transaction {
+            signature: None,
+            ..self.clone()
+        };
+        encode(&unsigned, Encoding::CanonicalJson).map_err(|_| TransactionError::InvalidSignature)
+    }
+
+    // The reward of a miner, the same in each of its blocks
+    pub fn coinbase(miner: Address) -> Transaction {
+        Transaction {
+            recipient: miner,
+            batch_id: COINBASE_BATCH_ID.to_string(),
+            event_type: COINBASE_EVENT.into(),
+            ..Default::default()
+        }
+    }
+
+    // The reward of the miner of a block, the same in each of its blocks
+    pub fn is_coinbase(&self) -> bool {
+        self.sender == Address::default() && self.event_type == COINBASE_EVENT
+    }
+
+    // The actors that the transaction concerns: its sender, the actor it acts for and its recipients
+    pub fn actors(&self) -> HashSet<&Address> {
+        [&self.sender, &self.recipient]
+            .into_iter()
+            .chain(self.on_behalf_of.as_ref())
+            .chain(self.recipients.iter())
+            .collect()
+    }
+
+    // The extra recipients concern a batch, and are distinct from each other and from the recipient
+    fn validate_recipients(&self) -> Result<(), TransactionError> {
+        if self.recipients.is_empty() {
+            return Ok(());
+        }
+
+        let distinct: HashSet<&Address> = self
+            .recipients
+            .iter()
+            .chain(std::iter::once(&self.recipient))
+            .collect();
+        match !self.batch_id.is_empty()
+            && self.recipients.len() <= MAX_RECIPIENTS
+            && distinct.len() == self.recipients.len() + 1
+        {
+            true => Ok(()),
+            false => Err(TransactionError::InvalidRecipients),
+        }
+    }
+
+    fn validate_extensions(&self) -> Result<(), TransactionError> {
+        if self.extensions.is_empty() {
+            return Ok(());
+        }
+
+        let is_valid_char =
+            |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || "._-".contains(c);
+        if let Some(name) = self.extensions.keys().find(|name| {
+            name.is_empty()
+                || name.len() > MAX_EXTENSION_NAME_LENGTH
+                || !name.chars().all(is_valid_char)
+        }) {
+            return Err(TransactionError::InvalidExtensionName(name.clone()));
+        }
+
+        let size = serde_json::to_string(&self.extensions)
+            .map(|json| json.len())
+            .unwrap_or(usize::MAX);
+        if size > MAX_EXTENSIONS_SIZE {
+            return Err(TransactionError::ExtensionsTooLarge);
+        }
+
+        Ok(())
+    }
+}
+
+// Every field in the order of the struct, the payload and the extensions as canonical binary JSON
+// The recipients are only written when there are some, so the transactions before them keep their hash
+// A transaction is hashed as a leaf of the merkle tree of its block
+impl Hashable for Transaction {
+    const DOMAIN: &'static [u8] = &[LEAF_PREFIX];
+
+    fn write_canonical(&self, writer: &mut CanonicalWriter) {
+        writer.address(&self.sender);
+        writer.address(&self.recipient);
+        // a raw payload is a JSON string, a typed one an object
+        writer.json(&serde_json::to_value(&self.data).unwrap_or_default());
+        writer.str(&self.batch_id);
+        writer.str(&self.event_type.to_string());
+        writer.option(self.on_behalf_of.as_ref(), CanonicalWriter::address);
+        writer.option(self.in_response_to.as_ref(), CanonicalWriter::hash);
+        writer.u64(self.extensions.len() as u64);
+        for (name, value) in self.extensions.iter() {
+            writer.str(name);
+            writer.json(value);
+        }
+        writer.option(self.signature.as_deref(), CanonicalWriter::str);
+        if !self.recipients.is_empty() {
+            writer.u64(self.recipients.len() as u64);
+            for recipient in self.recipients.iter() {
+                writer.address(recipient);
+            }
+        }
+        // only hashed when recorded too, its 9 bytes cannot be confused with the recipients
+        if self.normalization.is_some() {
+            writer.option(self.normalization.as_ref(), |writer, version| {
+                writer.u64(*version as u64)
+            });
+        }
+        // same for the validity window, the lengths of the combinations of these three never collide
+        if let Some(validity) = &self.validity {
+            validity.write_canonical(writer);
+        }
+        // tagged, so its bytes cannot be read as the optional fields before it
+        if let Some(nonce) = self.nonce {
+            writer.str("nonce");
+            writer.u64(nonce);
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::model::test_util::{alice, bob};
+    use crate::model::{
+        fixtures::{alice, bob, carol, sign},
+        transaction_hash, Block, BlockHash,
+    };
 
     fn farm_address() -> Address {
         alice()
@@ -29,9 +550,10 @@ mod tests {
         let tx = Transaction {
             sender: farm_address(),
             recipient: warehouse_address(),
-            data: r#"{"quantity": "100kg", "quality": "Grade A"}"#.to_string(),
+            data: r#"{"quantity": "100kg", "quality": "Grade A"}"#.into(),
             batch_id: "WHEAT-001".to_string(),
-            event_type: "HARVEST".to_string(),
+            event_type: "HARVEST".into(),
+            ..Default::default()
         };
 
         assert_eq!(tx.sender, farm_address());
@@ -45,9 +567,10 @@ mod tests {
         let tx1 = Transaction {
             sender: farm_address(),
             recipient: warehouse_address(),
-            data: r#"{"temperature": "4C", "humidity": "65%"}"#.to_string(),
+            data: r#"{"temperature": "4C", "humidity": "65%"}"#.into(),
             batch_id: "CORN-042".to_string(),
-            event_type: "STORAGE".to_string(),
+            event_type: "STORAGE".into(),
+            ..Default::default()
         };
 
         let tx2 = tx1.clone();
@@ -64,9 +587,10 @@ mod tests {
         let tx = Transaction {
             sender: farm_address(),
             recipient: warehouse_address(),
-            data: r#"{"location": "Warehouse-A", "inspector": "John Doe"}"#.to_string(),
+            data: r#"{"location": "Warehouse-A", "inspector": "John Doe"}"#.into(),
             batch_id: "RICE-999".to_string(),
-            event_type: "QUALITY_CHECK".to_string(),
+            event_type: "QUALITY_CHECK".into(),
+            ..Default::default()
         };
 
         let json = serde_json::to_string(&tx).unwrap();
@@ -74,6 +598,33 @@ mod tests {
         assert!(json.contains("QUALITY_CHECK"));
     }
 
+    #[test]
+    fn should_deserialize_typed_payloads() {
+        let json = r#"{
+            "sender": "f780b958227ff0bf5795ede8f9f7eaac67e7e06666b043a400026cbd421ce28e",
+            "recipient": "51df097c03c0a6e64e54a6fce90cb6968adebd85955917ed438e3d3c05f2f00f",
+            "data": {"type": "TRANSPORT", "vehicle": "TRUCK-42", "driver": "Jane Smith", "departure": "2024-12-22T08:00:00Z"},
+            "batch_id": "WHEAT-123",
+            "event_type": "TRANSPORT"
+        }"#;
+
+        let mut tx: Transaction = serde_json::from_str(json).unwrap();
+        assert!(matches!(tx.data, AgriData::Transport(_)));
+        tx.sender = farm_address();
+        assert_eq!(sign(tx.clone()).validate(), Ok(()));
+
+        // the payload must be the one of the event
+        tx.event_type = "STORAGE".into();
+        let tx = sign(tx);
+        assert_eq!(
+            tx.validate(),
+            Err(TransactionError::PayloadMismatch(
+                "STORAGE".to_string(),
+                "TRANSPORT".to_string()
+            ))
+        );
+    }
+
     #[test]
     fn should_deserialize_from_json() {
         let json = r#"{
@@ -87,7 +638,7 @@ mod tests {
         let tx: Transaction = serde_json::from_str(json).unwrap();
         assert_eq!(tx.batch_id, "WHEAT-123");
         assert_eq!(tx.event_type, "TRANSPORT");
-        assert!(tx.data.contains("TRUCK-42"));
+        assert!(tx.data.as_json().contains("TRUCK-42"));
     }
 
     #[test]
@@ -95,13 +646,14 @@ mod tests {
         let tx = Transaction {
             sender: farm_address(),
             recipient: farm_address(),
-            data: r#"{"crop": "wheat", "quantity": "500kg", "field": "Field-7"}"#.to_string(),
+            data: r#"{"crop": "wheat", "quantity": "500kg", "field": "Field-7"}"#.into(),
             batch_id: "WHEAT-2024-001".to_string(),
-            event_type: "HARVEST".to_string(),
+            event_type: "HARVEST".into(),
+            ..Default::default()
         };
 
         assert_eq!(tx.event_type, "HARVEST");
-        assert!(tx.data.contains("wheat"));
+        assert!(tx.data.as_json().contains("wheat"));
     }
 
     #[test]
@@ -109,13 +661,14 @@ mod tests {
         let tx = Transaction {
             sender: warehouse_address(),
             recipient: farm_address(),
-            data: r#"{"process": "milling", "output": "450kg flour"}"#.to_string(),
+            data: r#"{"process": "milling", "output": "450kg flour"}"#.into(),
             batch_id: "WHEAT-2024-001".to_string(),
-            event_type: "PROCESSING".to_string(),
+            event_type: "PROCESSING".into(),
+            ..Default::default()
         };
 
         assert_eq!(tx.event_type, "PROCESSING");
-        assert!(tx.data.contains("milling"));
+        assert!(tx.data.as_json().contains("milling"));
     }
 
     #[test]
@@ -123,13 +676,348 @@ mod tests {
         let tx = Transaction {
             sender: farm_address(),
             recipient: warehouse_address(),
-            data: r#"{"driver": "Jane Smith", "vehicle": "TRUCK-15", "departure": "2024-12-22T08:00:00Z"}"#.to_string(),
+            data: r#"{"driver": "Jane Smith", "vehicle": "TRUCK-15", "departure": "2024-12-22T08:00:00Z"}"#.into(),
             batch_id: "CORN-042".to_string(),
-            event_type: "TRANSPORT".to_string(),
+            event_type: "TRANSPORT".into(),
+            ..Default::default()
         };
 
         assert_eq!(tx.event_type, "TRANSPORT");
-        assert!(tx.data.contains("TRUCK-15"));
+        assert!(tx.data.as_json().contains("TRUCK-15"));
+    }
+
+    #[test]
+    fn should_validate_profile_events() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let address = Address::from(key.verifying_key().to_bytes());
+        let mut tx = Transaction {
+            sender: address.clone(),
+            recipient: address.clone(),
+            data: r#"{"display_name": "Green Valley Farm", "roles": ["FARMER"]}"#.into(),
+            batch_id: String::new(),
+            event_type: "PROFILE".into(),
+            ..Default::default()
+        };
+        // anyone could overwrite the profile of an actor without its signature
+        assert_eq!(tx.validate(), Err(TransactionError::MissingSignature));
+        tx.sign(&key).unwrap();
+        assert_eq!(tx.validate(), Ok(()));
+
+        // the profile must be published by the actor itself
+        tx.recipient = warehouse_address();
+        tx.sign(&key).unwrap();
+        assert_eq!(
+            tx.validate(),
+            Err(TransactionError::ProfileNotSelfPublished)
+        );
+
+        // and must contain a valid profile
+        tx.recipient = address;
+        tx.data = "Green Valley Farm".into();
+        tx.sign(&key).unwrap();
+        assert_eq!(tx.validate(), Err(TransactionError::InvalidProfile));
+    }
+
+    #[test]
+    fn should_validate_lot_events() {
+        let mut tx = Transaction {
+            sender: farm_address(),
+            recipient: farm_address(),
+            data: r#"{"prefix": "WHEAT", "season": "2024", "sequence": 1}"#.into(),
+            batch_id: "WHEAT-2024-0001".to_string(),
+            event_type: LOT_EVENT.into(),
+            ..Default::default()
+        };
+        assert_eq!(sign(tx.clone()).validate(), Ok(()));
+
+        // the batch must be the allocated lot
+        tx.batch_id = "WHEAT-2024-0002".to_string();
+        assert_eq!(sign(tx).validate(), Err(TransactionError::InvalidLot));
+    }
+
+    #[test]
+    fn should_validate_sla_events() {
+        let mut tx = Transaction {
+            sender: farm_address(),
+            recipient: warehouse_address(),
+            data: r#"{"max_transit_hours": 48}"#.into(),
+            event_type: SLA_EVENT.into(),
+            ..Default::default()
+        };
+        assert_eq!(sign(tx.clone()).validate(), Ok(()));
+
+        // terms are agreed between two actors
+        tx.recipient = farm_address();
+        assert_eq!(sign(tx).validate(), Err(TransactionError::InvalidSla));
+    }
+
+    #[test]
+    fn should_validate_extensions() {
+        let mut tx = Transaction {
+            sender: farm_address(),
+            recipient: warehouse_address(),
+            event_type: "TRANSPORT".into(),
+            ..Default::default()
+        };
+        let hash_without_extensions = Block::new(1, 0, BlockHash::zero(), vec![tx.clone()])
+            .header
+            .hash;
+
+        // unknown extensions are accepted, and they are part of the hash
+        tx.extensions.insert(
+            "acme.contract_id".to_string(),
+            serde_json::json!({"id": "C-42", "version": 2}),
+        );
+        assert_eq!(sign(tx.clone()).validate(), Ok(()));
+        let hash_with_extensions = Block::new(1, 0, BlockHash::zero(), vec![tx.clone()])
+            .header
+            .hash;
+        assert_ne!(hash_without_extensions, hash_with_extensions);
+
+        tx.extensions
+            .insert("Not Valid".to_string(), Value::Bool(true));
+        assert_eq!(
+            sign(tx.clone()).validate(),
+            Err(TransactionError::InvalidExtensionName(
+                "Not Valid".to_string()
+            ))
+        );
+
+        tx.extensions.remove("Not Valid");
+        tx.extensions
+            .insert("acme.notes".to_string(), Value::String("x".repeat(5000)));
+        assert_eq!(
+            sign(tx).validate(),
+            Err(TransactionError::ExtensionsTooLarge)
+        );
+    }
+
+    #[test]
+    fn should_validate_recipients() {
+        let mut tx = Transaction {
+            sender: farm_address(),
+            recipient: warehouse_address(),
+            batch_id: "WHEAT-001".to_string(),
+            event_type: "RECALL".into(),
+            ..Default::default()
+        };
+        let hash_without_recipients = transaction_hash(&tx);
+
+        // the other recipients are part of the hash, and the transaction concerns each of them
+        tx.recipients = vec![carol()];
+        assert_eq!(sign(tx.clone()).validate(), Ok(()));
+        assert_ne!(transaction_hash(&tx), hash_without_recipients);
+        assert!(tx.actors().contains(&carol()) && tx.actors().contains(&farm_address()));
+        assert!(!tx.actors().contains(&Address::default()));
+
+        // distinct from each other and from the recipient, and about a batch
+        let invalid = [
+            vec![carol(), carol()],
+            vec![warehouse_address()],
+            vec![Address::default(); MAX_RECIPIENTS + 1],
+        ];
+        for recipients in invalid {
+            let invalid_tx = sign(Transaction {
+                recipients,
+                ..tx.clone()
+            });
+            assert_eq!(
+                invalid_tx.validate(),
+                Err(TransactionError::InvalidRecipients)
+            );
+        }
+        tx.batch_id = String::new();
+        assert_eq!(
+            sign(tx).validate(),
+            Err(TransactionError::InvalidRecipients)
+        );
+    }
+
+    #[test]
+    fn should_identify_each_transaction_by_its_hash() {
+        let reading = Transaction {
+            sender: farm_address(),
+            recipient: warehouse_address(),
+            data: r#"{"temperature_c": 4}"#.into(),
+            batch_id: "MILK-001".to_string(),
+            event_type: "STORAGE".into(),
+            ..Default::default()
+        };
+        assert_eq!(reading.hash(), transaction_hash(&reading));
+        assert_eq!(reading.hash(), reading.clone().hash());
+
+        // the same reading taken twice is told apart by its nonce
+        let first = Transaction {
+            nonce: Some(1),
+            ..reading.clone()
+        };
+        let second = Transaction {
+            nonce: Some(2),
+            ..reading.clone()
+        };
+        assert_ne!(first.hash(), reading.hash());
+        assert_ne!(first.hash(), second.hash());
+
+        // and cannot be taken for a normalization version
+        let normalized = Transaction {
+            normalization: Some(1),
+            ..reading
+        };
+        assert_ne!(first.hash(), normalized.hash());
+    }
+
+    #[test]
+    fn should_validate_the_normalization() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let mut tx = Transaction {
+            sender: Address::from(key.verifying_key().to_bytes()),
+            recipient: warehouse_address(),
+            data: r#"{"crop": "wheat", "qty": "2t"}"#.into(),
+            batch_id: "WHEAT-001".to_string(),
+            event_type: "HARVEST".into(),
+            ..Default::default()
+        };
+        tx.normalize(1).unwrap();
+        tx.sign(&key).unwrap();
+        assert_eq!(tx.data, r#"{"crop":"wheat","quantity_kg":2000.0}"#);
+        assert_eq!(tx.normalization, Some(1));
+        assert_eq!(tx.validate(), Ok(()));
+
+        // the version is signed with the payload, which must be normalized by it
+        let mut unrecorded = tx.clone();
+        unrecorded.normalization = None;
+        assert_eq!(
+            unrecorded.validate(),
+            Err(TransactionError::InvalidSignature)
+        );
+        let mut not_normalized = tx.clone();
+        not_normalized.data = r#"{"crop": "wheat", "qty": "2t"}"#.into();
+        not_normalized.sign(&key).unwrap();
+        assert_eq!(
+            not_normalized.validate(),
+            Err(TransactionError::NotNormalized(1))
+        );
+        let mut unknown = tx.clone();
+        unknown.normalization = Some(9);
+        assert_eq!(
+            unknown.validate(),
+            Err(TransactionError::UnknownNormalization(9))
+        );
+    }
+
+    #[test]
+    fn should_hash_a_hand_written_layout() {
+        let tx = Transaction {
+            sender: farm_address(),
+            recipient: warehouse_address(),
+            data: "ok".into(),
+            batch_id: "W-1".to_string(),
+            event_type: "HARVEST".into(),
+            ..Default::default()
+        };
+
+        // a change of the layout would change the merkle root of every block of every chain
+        let mut expected = farm_address().as_bytes().to_vec();
+        expected.extend(warehouse_address().as_bytes());
+        // the payload as a binary JSON string
+        expected.extend([7, 0, 0, 0, 0, 0, 0, 0, 0x06, 0, 0, 0, 2, b'o', b'k']);
+        expected.extend([3, 0, 0, 0, 0, 0, 0, 0, b'W', b'-', b'1']);
+        expected.extend([7, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend(b"HARVEST");
+        expected.extend([0x00, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0x00]);
+        assert_eq!(tx.canonical_bytes(), expected);
+
+        // every field is covered, also the optional ones
+        let mut changed = vec![tx.clone(); 6];
+        changed[0].on_behalf_of = Some(farm_address());
+        changed[1].in_response_to = Some(TxHash::zero());
+        changed[2]
+            .extensions
+            .insert("acme.contract_id".to_string(), "C-42".into());
+        changed[3].data = r#""ok""#.into();
+        changed[4].normalization = Some(1);
+        changed[5].validity = Some(ValidityWindow {
+            not_after_height: Some(10),
+            ..Default::default()
+        });
+        for other in changed.iter() {
+            assert_ne!(other.canonical_hash(), tx.canonical_hash());
+        }
+
+        // the same transaction from JSON with another field order has the same hash
+        let json = r#"{"event_type": "HARVEST", "batch_id": "W-1", "data": "ok",
+            "recipient": "RECIPIENT", "sender": "SENDER"}"#
+            .replace("RECIPIENT", &warehouse_address().to_string())
+            .replace("SENDER", &farm_address().to_string());
+        let parsed: Transaction = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.canonical_hash(), tx.canonical_hash());
+    }
+
+    #[test]
+    fn should_sign_with_the_key_of_the_sender() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let mut tx = Transaction {
+            sender: Address::from(key.verifying_key().to_bytes()),
+            recipient: warehouse_address(),
+            data: r#"{"quantity": "100kg"}"#.into(),
+            batch_id: "WHEAT-001".to_string(),
+            event_type: "HARVEST".into(),
+            ..Default::default()
+        };
+        assert_eq!(tx.verify(), Err(TransactionError::MissingSignature));
+        // nobody can send an event under the name of another actor, only the reward of the miner has no key
+        assert_eq!(tx.validate(), Err(TransactionError::MissingSignature));
+        let coinbase = Transaction::coinbase(warehouse_address());
+        assert_eq!(coinbase.validate(), Ok(()));
+        // which names the miner and nothing else
+        let loaded = Transaction {
+            sender: Address::default(),
+            event_type: COINBASE_EVENT.into(),
+            ..tx.clone()
+        };
+        assert_eq!(loaded.validate(), Err(TransactionError::InvalidCoinbase));
+        // and has a batch of its own
+        let mut system_log = Transaction {
+            batch_id: COINBASE_BATCH_ID.to_string(),
+            ..tx.clone()
+        };
+        system_log.sign(&key).unwrap();
+        assert_eq!(
+            system_log.validate(),
+            Err(TransactionError::ReservedBatch(
+                COINBASE_BATCH_ID.to_string()
+            ))
+        );
+
+        tx.sign(&key).unwrap();
+        assert_eq!(tx.verify(), Ok(()));
+        assert_eq!(tx.validate(), Ok(()));
+
+        // any change after signing breaks the signature
+        let mut tampered = tx.clone();
+        tampered.data = r#"{"quantity": "900kg"}"#.into();
+        assert_eq!(tampered.validate(), Err(TransactionError::InvalidSignature));
+
+        let mut garbage = tx.clone();
+        garbage.signature = Some("not hex".to_string());
+        assert_eq!(garbage.validate(), Err(TransactionError::InvalidSignature));
+        let mut upper_cased = tx.clone();
+        upper_cased.signature = tx
+            .signature
+            .as_ref()
+            .map(|signature| signature.to_uppercase());
+        assert_eq!(
+            upper_cased.validate(),
+            Err(TransactionError::InvalidSignature)
+        );
+
+        // nobody can sign for another actor
+        let other_key = SigningKey::from_bytes(&[8; 32]);
+        let other_address = Address::from(other_key.verifying_key().to_bytes());
+        assert_eq!(
+            tx.sign(&other_key),
+            Err(TransactionError::SenderKeyMismatch(other_address))
+        );
     }
 
     #[test]
@@ -146,17 +1034,17 @@ mod tests {
         let tx = Transaction {
             sender: warehouse_address(),
             recipient: warehouse_address(),
-            data: complex_data.to_string(),
+            data: complex_data.into(),
             batch_id: "ORGANIC-WHEAT-001".to_string(),
-            event_type: "QUALITY_CHECK".to_string(),
+            event_type: "QUALITY_CHECK".into(),
+            ..Default::default()
         };
 
         assert_eq!(tx.event_type, "QUALITY_CHECK");
 
         // Verify the data field contains valid JSON
-        let parsed: serde_json::Value = serde_json::from_str(&tx.data).unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&tx.data.as_json()).unwrap();
         assert_eq!(parsed["temperature"], 25);
         assert_eq!(parsed["organic"], true);
     }
 }
-
@@ -0,0 +1,212 @@
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{Address, Transaction, TxHash};
+
+// How a transaction reached this node
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum OriginChannel {
+    // submitted by a client to the API
+    Api,
+    // inside a block pushed by a peer to the API
+    ApiBlock,
+    // inside a block pulled from a peer
+    PeerSync,
+    // broadcast by a peer of the gossip network, alone or inside a block
+    Gossip,
+}
+
+// Where and when this node saw a transaction for the first time
+// It's only known by this node and it's not part of the chain, so it's not covered by the consensus
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TransactionOrigin {
+    // the hash that identifies the transaction everywhere else, e.g. in the response of POST /transactions
+    #[schema(value_type = String)]
+    pub hash: TxHash,
+    pub batch_id: String,
+    pub event_type: String,
+    #[schema(value_type = String)]
+    pub sender: Address,
+    pub channel: OriginChannel,
+    // address of the peer, for blocks pulled from peers
+    pub peer: Option<String>,
+    // address of the client, only when the operator allows recording it
+    pub ip: Option<String>,
+    // timestamp in milliseconds
+    pub first_seen: i64,
+}
+
+// We don't need to export this type because concurrency is encapsulated in this file
+type SyncedOriginMap = Arc<Mutex<HashMap<TxHash, TransactionOrigin>>>;
+
+// Origins of the transactions seen by the node, for forensic investigations of suspicious events
+// The origins are appended to a file (if set), so they survive restarts
+// Multiple threads can read/write concurrently to the origins
+#[derive(Debug, Clone)]
+pub struct TransactionOrigins {
+    origins: SyncedOriginMap,
+    file_path: String,
+}
+
+impl TransactionOrigins {
+    pub fn new(file_path: String) -> TransactionOrigins {
+        TransactionOrigins {
+            origins: SyncedOriginMap::default(),
+            file_path,
+        }
+    }
+
+    // Records the origin of a transaction, unless it was already seen
+    pub fn record(
+        &self,
+        transaction: &Transaction,
+        channel: OriginChannel,
+        peer: Option<&str>,
+        ip: Option<&str>,
+    ) {
+        let hash = transaction.hash();
+        let mut origins = self.origins.lock().unwrap();
+        if origins.contains_key(&hash) {
+            return;
+        }
+
+        let origin = TransactionOrigin {
+            hash,
+            batch_id: transaction.batch_id.clone(),
+            event_type: transaction.event_type.to_string(),
+            sender: transaction.sender.clone(),
+            channel,
+            peer: peer.map(str::to_string),
+            ip: ip.map(str::to_string),
+            first_seen: Utc::now().timestamp_millis(),
+        };
+        if let Err(error) = self.append(&origin) {
+            error!("Could not persist the origin of a transaction: {}", error);
+        }
+        origins.insert(hash, origin);
+    }
+
+    // Returns the origins that satisfy a condition, the oldest first
+    pub fn find<P>(&self, predicate: P) -> Vec<TransactionOrigin>
+    where
+        P: Fn(&TransactionOrigin) -> bool,
+    {
+        let origins = self.origins.lock().unwrap();
+
+        let mut found: Vec<TransactionOrigin> = origins
+            .values()
+            .filter(|origin| predicate(origin))
+            .cloned()
+            .collect();
+        found.sort_by_key(|origin| (origin.first_seen, origin.hash));
+        found
+    }
+
+    // Loads the origins persisted in previous runs
+    pub fn load(&self) -> Result<()> {
+        if self.file_path.is_empty() || fs::metadata(&self.file_path).is_err() {
+            return Ok(());
+        }
+
+        let raw_origins = fs::read_to_string(&self.file_path)?;
+        let mut origins = self.origins.lock().unwrap();
+        for line in raw_origins.lines().filter(|line| !line.trim().is_empty()) {
+            let origin: TransactionOrigin = serde_json::from_str(line)?;
+            origins.entry(origin.hash).or_insert(origin);
+        }
+
+        Ok(())
+    }
+
+    // One JSON document per line, so recording an origin doesn't rewrite the whole file
+    fn append(&self, origin: &TransactionOrigin) -> Result<()> {
+        if self.file_path.is_empty() {
+            return Ok(());
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.file_path)?;
+        writeln!(file, "{}", serde_json::to_string(origin)?)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_keep_the_first_origin_of_each_transaction() {
+        let origins = TransactionOrigins::new(String::default());
+        let transaction = create_transaction("WHEAT-001");
+
+        origins.record(&transaction, OriginChannel::Api, None, Some("10.0.0.1"));
+        origins.record(
+            &transaction,
+            OriginChannel::PeerSync,
+            Some("http://peer"),
+            None,
+        );
+        origins.record(
+            &create_transaction("CORN-001"),
+            OriginChannel::PeerSync,
+            Some("http://peer"),
+            None,
+        );
+
+        let found = origins.find(|origin| origin.batch_id == "WHEAT-001");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].channel, OriginChannel::Api);
+        assert_eq!(found[0].ip.as_deref(), Some("10.0.0.1"));
+        assert_eq!(found[0].hash, transaction.hash());
+    }
+
+    #[test]
+    fn should_persist_origins() {
+        let dir = std::env::temp_dir().join("rust_blockchain_origins_test");
+        fs::create_dir_all(&dir).unwrap();
+        let file_path = dir.join("origins.jsonl").to_str().unwrap().to_string();
+        let _ = fs::remove_file(&file_path);
+
+        let origins = TransactionOrigins::new(file_path.clone());
+        origins.record(
+            &create_transaction("WHEAT-001"),
+            OriginChannel::Api,
+            None,
+            None,
+        );
+        origins.record(
+            &create_transaction("CORN-001"),
+            OriginChannel::Api,
+            None,
+            None,
+        );
+
+        let restarted_origins = TransactionOrigins::new(file_path);
+        restarted_origins.load().unwrap();
+        assert_eq!(restarted_origins.find(|_| true), origins.find(|_| true));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn create_transaction(batch_id: &str) -> Transaction {
+        Transaction {
+            batch_id: batch_id.to_string(),
+            event_type: "HARVEST".into(),
+            ..Default::default()
+        }
+    }
+}
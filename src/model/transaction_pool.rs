@@ -1,67 +1,332 @@
-use super::Transaction;
-use std::sync::{Arc, Mutex};
+use super::{
+    merkle_tree::transaction_hash, Block, BlockHash, Blockchain, ChainEvent, EventBus, ReorgEvent,
+    Transaction, MAX_TRANSACTIONS_PER_BLOCK,
+};
+use chrono::Utc;
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
 
 pub type TransactionVec = Vec<Transaction>;
 
+// A transaction waiting to be mined
+#[derive(Debug, Clone)]
+struct PendingTransaction {
+    transaction: Transaction,
+    // to recognize the same transaction submitted again
+    hash: BlockHash,
+    // timestamp in milliseconds
+    added_at: i64,
+}
+
 // We don't need to export this type because concurrency is encapsulated in this file
-type SyncedTransactionVec = Arc<Mutex<TransactionVec>>;
+// The transactions are kept in order of arrival
+type SyncedPendingTransactionVec = Arc<Mutex<Vec<PendingTransaction>>>;
 
 // Represents a pool of unrealized transactions
 // Multiple threads can read/write concurrently to the pool
 #[derive(Debug, Clone)]
 pub struct TransactionPool {
-    transactions: SyncedTransactionVec,
+    transactions: SyncedPendingTransactionVec,
+    // the last transactions handed to the miner, until their block is added to the chain
+    in_flight: Arc<Mutex<TransactionVec>>,
+    // set when a client asks to mine the pending transactions without waiting for more of them
+    mining_requested: Arc<AtomicBool>,
+    // max transactions handed to the miner for a single block (0 for all of them)
+    max_batch: usize,
+    // max time a transaction can wait to be mined (0 to wait forever)
+    max_age_ms: u64,
+    event_bus: EventBus,
 }
 
 // Basic operations in the transaction pool are encapsulated in the implementation
 // Encapsulates concurrency concerns, so external callers do not need to know how it's handled
 impl TransactionPool {
-    // Creates a empty transaction pool
-    pub fn new() -> TransactionPool {
+    // Creates a empty transaction pool, that publishes the accepted transactions in a bus
+    // By default it hands all the transactions to the miner and keeps them until they are mined
+    pub fn new(event_bus: EventBus) -> TransactionPool {
         TransactionPool {
-            transactions: SyncedTransactionVec::default(),
+            transactions: SyncedPendingTransactionVec::default(),
+            in_flight: Arc::default(),
+            mining_requested: Arc::default(),
+            max_batch: 0,
+            max_age_ms: 0,
+            event_bus,
         }
     }
 
-    // Adds a new transaction to the pool
-    pub fn add_transaction(&self, transaction: Transaction) {
-        // TODO: transactions should be validated before being included in the pool
+    // Hands at most "max_batch" transactions to the miner at once,
+    // and evicts the transactions that wait more than "max_age_ms" to be mined
+    pub fn with_limits(mut self, max_batch: usize, max_age_ms: u64) -> TransactionPool {
+        self.max_batch = max_batch;
+        self.max_age_ms = max_age_ms;
+        self
+    }
+
+    // Adds a new transaction to the pool, unless the same transaction is already pending
+    // Submitting a transaction again (e.g. a client retrying after a timeout) doesn't mine it twice
+    // Returns if the transaction was added
+    pub fn add_transaction(&self, transaction: Transaction) -> bool {
+        let hash = transaction_hash(&transaction);
         let mut transactions = self.transactions.lock().unwrap();
-        transactions.push(transaction);
+        if transactions.iter().any(|pending| pending.hash == hash) {
+            info!("transaction already pending");
+            return false;
+        }
+
+        self.event_bus
+            .publish(ChainEvent::TxAccepted(transaction.clone()));
+        transactions.push(PendingTransaction {
+            transaction,
+            hash,
+            added_at: Utc::now().timestamp_millis(),
+        });
         info!("transaction added");
+        true
+    }
+
+    // Returns the amount of transactions waiting in the pool
+    pub fn len(&self) -> usize {
+        self.transactions.lock().unwrap().len()
+    }
+
+    // Returns a copy of all transactions in order of arrival, leaving them in the pool
+    pub fn get_all(&self) -> TransactionVec {
+        let transactions = self.transactions.lock().unwrap();
+
+        transactions
+            .iter()
+            .map(|pending| pending.transaction.clone())
+            .collect()
+    }
+
+    // Returns the transactions that are not in the chain yet, the ones being mined before the waiting ones
+    // New transactions are validated after them, e.g. the transport of a batch whose harvest is being mined
+    pub fn get_unconfirmed(&self) -> TransactionVec {
+        let in_flight = self.in_flight.lock().unwrap();
+        let mut unconfirmed = in_flight.clone();
+        unconfirmed.extend(self.get_all());
+        unconfirmed
     }
 
-    // Returns a copy of all transactions and empties the pool
+    // Forgets the transactions handed to the miner, once their block is in the chain (or they were discarded)
+    pub fn clear_in_flight(&self) {
+        self.in_flight.lock().unwrap().clear();
+    }
+
+    // Asks the miner to mine the pending transactions right away, until it takes them out of the pool
+    // Returns the amount of pending transactions, the request is ignored if there are none
+    pub fn request_mining(&self) -> usize {
+        let transactions = self.transactions.lock().unwrap();
+        if !transactions.is_empty() {
+            self.mining_requested.store(true, Ordering::SeqCst);
+        }
+        transactions.len()
+    }
+
+    pub fn is_mining_requested(&self) -> bool {
+        self.mining_requested.load(Ordering::SeqCst)
+    }
+
+    // Drops the transactions that have been waiting too long, e.g. because the network was down
+    // Returns the amount of evicted transactions, each one is published in the bus with the reason
+    pub fn evict_stale(&self) -> usize {
+        if self.max_age_ms == 0 {
+            return 0;
+        }
+
+        let oldest_allowed = Utc::now().timestamp_millis() - self.max_age_ms as i64;
+        let mut transactions = self.transactions.lock().unwrap();
+        let before = transactions.len();
+        transactions.retain(|pending| {
+            if pending.added_at >= oldest_allowed {
+                return true;
+            }
+            let reason = format!("Not mined after {} ms in the pool", self.max_age_ms);
+            warn!("Evicting transaction from the pool: {}", reason);
+            let event = ChainEvent::TxDropped(pending.transaction.clone(), reason);
+            self.event_bus.publish(event);
+            false
+        });
+
+        before - transactions.len()
+    }
+
+    // Drops the pending transactions that became invalid with a new block of the chain
+    // (e.g. the custody of their batch changed or the delegation they rely on was replaced)
+    // Only the transactions about the same batches or actors as the block are validated again
+    // The ones that the block includes (e.g. also received by its miner) are removed, they cannot be mined again
+    // Returns the amount of dropped transactions, each one is published in the bus with the reason
+    pub fn revalidate(&self, blockchain: &Blockchain, block: &Block) -> usize {
+        let included: HashSet<BlockHash> = block
+            .body
+            .transactions
+            .iter()
+            .map(transaction_hash)
+            .collect();
+        let batch_ids: HashSet<&String> = block
+            .body
+            .transactions
+            .iter()
+            .map(|tx| &tx.batch_id)
+            .collect();
+        let actors: HashSet<_> = block
+            .body
+            .transactions
+            .iter()
+            .map(|tx| &tx.sender)
+            .collect();
+        let is_affected = |transaction: &Transaction| {
+            batch_ids.contains(&transaction.batch_id)
+                || transaction
+                    .on_behalf_of
+                    .as_ref()
+                    .is_some_and(|actor| actors.contains(actor))
+        };
+
+        let mut transactions = self.transactions.lock().unwrap();
+        transactions.retain(|pending| !included.contains(&pending.hash));
+        let before = transactions.len();
+        // the transactions kept so far will be mined before the next ones
+        let mut kept: TransactionVec = Vec::new();
+        transactions.retain(|pending| {
+            let transaction = &pending.transaction;
+            let result = match is_affected(transaction) {
+                true => blockchain.validate_transaction_after(transaction, &kept),
+                false => Ok(()),
+            };
+            match result {
+                Ok(()) => {
+                    kept.push(transaction.clone());
+                    true
+                }
+                Err(error) => {
+                    warn!("Dropping transaction from the pool: {}", error);
+                    let event = ChainEvent::TxDropped(transaction.clone(), error.to_string());
+                    self.event_bus.publish(event);
+                    false
+                }
+            }
+        });
+
+        before - transactions.len()
+    }
+
+    // Puts back the transactions of the blocks that a reorganization of the chain rolled back, before the pending
+    // ones as they were submitted first, and validates all of them again against the new blocks
+    // The pending transactions that the new blocks include are removed, the invalid ones are dropped
+    // Returns the amount of transactions put back in the pool
+    pub fn return_transactions(&self, blockchain: &Blockchain, reorg: &ReorgEvent) -> usize {
+        let included: HashSet<BlockHash> = reorg
+            .applied
+            .iter()
+            .flat_map(|block| block.body.transactions.iter())
+            .map(transaction_hash)
+            .collect();
+        let now = Utc::now().timestamp_millis();
+        let returned: Vec<PendingTransaction> = reorg
+            .returned_transactions()
+            .into_iter()
+            .map(|transaction| PendingTransaction {
+                hash: transaction_hash(&transaction),
+                transaction,
+                added_at: now,
+            })
+            .collect();
+        let returned_hashes: HashSet<BlockHash> =
+            returned.iter().map(|pending| pending.hash).collect();
+
+        let mut transactions = self.transactions.lock().unwrap();
+        let candidates: Vec<PendingTransaction> =
+            returned.into_iter().chain(transactions.drain(..)).collect();
+        let mut kept: TransactionVec = Vec::new();
+        let mut seen = HashSet::new();
+        for pending in candidates {
+            if included.contains(&pending.hash) || !seen.insert(pending.hash) {
+                continue;
+            }
+            match blockchain.validate_transaction_after(&pending.transaction, &kept) {
+                Ok(()) => {
+                    kept.push(pending.transaction.clone());
+                    transactions.push(pending);
+                }
+                Err(error) => {
+                    warn!("Dropping transaction from the pool: {}", error);
+                    let event = ChainEvent::TxDropped(pending.transaction, error.to_string());
+                    self.event_bus.publish(event);
+                }
+            }
+        }
+
+        transactions
+            .iter()
+            .filter(|pending| returned_hashes.contains(&pending.hash))
+            .count()
+    }
+
+    // Takes the oldest transactions out of the pool, as many as fit in a block
     // This operation is safe to be called concurrently from multiple threads
     pub fn pop(&self) -> TransactionVec {
         // the "transactions" attribute is protected by a Mutex
         // so only one thread at a time can access the value when the lock is held
         // preventing inconsitencies when adding new transactions while a pop is in course
+        // the transactions in flight are locked first, like when getting the unconfirmed ones
+        let mut in_flight = self.in_flight.lock().unwrap();
         let mut transactions = self.transactions.lock().unwrap();
-        let transactions_clone = transactions.clone();
-        transactions.clear();
+        // never more than the chain accepts in a block
+        let batch_size = match self.max_batch {
+            0 => MAX_TRANSACTIONS_PER_BLOCK,
+            max_batch => max_batch.min(MAX_TRANSACTIONS_PER_BLOCK),
+        }
+        .min(transactions.len());
 
-        transactions_clone
+        self.mining_requested.store(false, Ordering::SeqCst);
+        let popped: TransactionVec = transactions
+            .drain(..batch_size)
+            .map(|pending| pending.transaction)
+            .collect();
+        in_flight.clone_from(&popped);
+        popped
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::model::test_util::{alice, bob};
+    use crate::model::{
+        fixtures::{alice, bob, sign},
+        LOT_EVENT,
+    };
 
     use super::*;
 
     #[test]
     fn should_be_empty_after_creation() {
-        let transaction_pool = TransactionPool::new();
+        let transaction_pool = TransactionPool::new(EventBus::new());
 
         let transactions = transaction_pool.pop();
         assert!(transactions.is_empty());
     }
 
+    #[test]
+    fn should_count_pending_transactions() {
+        let transaction_pool = TransactionPool::new(EventBus::new());
+
+        transaction_pool.add_transaction(create_mock_transaction(1));
+        transaction_pool.add_transaction(create_mock_transaction(2));
+        assert_eq!(transaction_pool.len(), 2);
+
+        // popping the transactions empties the pool
+        transaction_pool.pop();
+        assert_eq!(transaction_pool.len(), 0);
+    }
+
     #[test]
     fn should_pop_single_value() {
-        let transaction_pool = TransactionPool::new();
+        let transaction_pool = TransactionPool::new(EventBus::new());
 
         // add a new transaction to the pool
         let transaction = create_mock_transaction(1);
@@ -79,7 +344,7 @@ mod tests {
 
     #[test]
     fn should_pop_multiple_values() {
-        let transaction_pool = TransactionPool::new();
+        let transaction_pool = TransactionPool::new(EventBus::new());
 
         // add a new transaction to the pool
         let transaction_a = create_mock_transaction(1);
@@ -98,13 +363,176 @@ mod tests {
         assert!(transactions.is_empty());
     }
 
+    #[test]
+    fn should_keep_the_popped_transactions_unconfirmed_until_mined() {
+        let transaction_pool = TransactionPool::new(EventBus::new());
+        transaction_pool.add_transaction(create_mock_transaction(1));
+        transaction_pool.pop();
+        transaction_pool.add_transaction(create_mock_transaction(2));
+
+        // the popped transaction goes first, it will be mined before the waiting one
+        let unconfirmed = transaction_pool.get_unconfirmed();
+        assert_eq!(unconfirmed.len(), 2);
+        assert_eq!(unconfirmed[0].data, "Mock data 1");
+        assert_eq!(transaction_pool.len(), 1);
+
+        transaction_pool.clear_in_flight();
+        assert_eq!(transaction_pool.get_unconfirmed()[0].data, "Mock data 2");
+    }
+
+    #[test]
+    fn should_not_add_the_same_transaction_twice() {
+        let transaction_pool = TransactionPool::new(EventBus::new());
+
+        assert!(transaction_pool.add_transaction(create_mock_transaction(1)));
+        assert!(!transaction_pool.add_transaction(create_mock_transaction(1)));
+        assert!(transaction_pool.add_transaction(create_mock_transaction(2)));
+        assert_eq!(transaction_pool.len(), 2);
+
+        // once mined, the same event can happen again
+        transaction_pool.pop();
+        assert!(transaction_pool.add_transaction(create_mock_transaction(1)));
+    }
+
+    #[test]
+    fn should_request_mining_only_with_pending_transactions() {
+        let transaction_pool = TransactionPool::new(EventBus::new());
+        assert_eq!(transaction_pool.request_mining(), 0);
+        assert!(!transaction_pool.is_mining_requested());
+
+        transaction_pool.add_transaction(create_mock_transaction(1));
+        assert_eq!(transaction_pool.request_mining(), 1);
+        assert!(transaction_pool.is_mining_requested());
+
+        // the request is fulfilled once the miner takes the transactions
+        transaction_pool.pop();
+        assert!(!transaction_pool.is_mining_requested());
+    }
+
+    #[test]
+    fn should_pop_the_oldest_transactions_up_to_the_limit() {
+        let transaction_pool = TransactionPool::new(EventBus::new()).with_limits(2, 0);
+        for id in 1..=3 {
+            transaction_pool.add_transaction(create_mock_transaction(id));
+        }
+
+        let transactions = transaction_pool.pop();
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].data, "Mock data 1");
+        assert_eq!(transactions[1].data, "Mock data 2");
+        assert_eq!(transaction_pool.pop()[0].data, "Mock data 3");
+    }
+
+    #[test]
+    fn should_evict_stale_transactions() {
+        let event_bus = EventBus::new();
+        let mut subscription = event_bus.subscribe();
+        let transaction_pool = TransactionPool::new(event_bus).with_limits(0, 60_000);
+        transaction_pool.add_transaction(create_mock_transaction(1));
+        transaction_pool.add_transaction(create_mock_transaction(2));
+
+        // the first transaction arrived long ago
+        transaction_pool.transactions.lock().unwrap()[0].added_at -= 120_000;
+        assert_eq!(transaction_pool.evict_stale(), 1);
+        assert_eq!(transaction_pool.get_all()[0].data, "Mock data 2");
+
+        let dropped = subscription
+            .drain()
+            .into_iter()
+            .filter(|event| matches!(event, ChainEvent::TxDropped(..)))
+            .count();
+        assert_eq!(dropped, 1);
+    }
+
+    #[test]
+    fn should_drop_transactions_invalidated_by_a_block() {
+        let blockchain = Blockchain::new(0);
+        let transaction_pool = TransactionPool::new(blockchain.event_bus());
+        let mut subscription = blockchain.event_bus().subscribe();
+
+        // two nodes allocating the same lot, the other one wins
+        let lot_claim = sign(Transaction {
+            sender: alice(),
+            recipient: alice(),
+            data: r#"{"prefix": "WHEAT", "season": "2024", "sequence": 1}"#.into(),
+            batch_id: "WHEAT-2024-0001".to_string(),
+            event_type: LOT_EVENT.into(),
+            ..Default::default()
+        });
+        transaction_pool.add_transaction(lot_claim.clone());
+        transaction_pool.add_transaction(create_mock_transaction(1));
+        transaction_pool.add_transaction(create_mock_transaction(2));
+        let other_claim = sign(Transaction {
+            sender: bob(),
+            recipient: bob(),
+            ..lot_claim
+        });
+        // the block also includes a pending transaction, e.g. received by its miner too
+        let previous_hash = blockchain.get_last_block().header.hash;
+        let block = Block::new(
+            1,
+            0,
+            previous_hash,
+            vec![other_claim, create_mock_transaction(2)],
+        );
+        blockchain.add_block(block.clone()).unwrap();
+
+        // it's removed from the pool without being dropped
+        assert_eq!(transaction_pool.revalidate(&blockchain, &block), 1);
+        assert_eq!(transaction_pool.len(), 1);
+        assert_eq!(transaction_pool.get_all()[0].data, "Mock data 1");
+        let dropped = subscription
+            .drain()
+            .into_iter()
+            .filter(|event| matches!(event, ChainEvent::TxDropped(..)))
+            .count();
+        assert_eq!(dropped, 1);
+    }
+
+    #[test]
+    fn should_return_the_transactions_of_the_rolled_back_blocks() {
+        let blockchain = Blockchain::new(0);
+        let transaction_pool = TransactionPool::new(blockchain.event_bus());
+        let lot_claim = sign(Transaction {
+            sender: alice(),
+            recipient: alice(),
+            data: r#"{"prefix": "WHEAT", "season": "2024", "sequence": 1}"#.into(),
+            batch_id: "WHEAT-2024-0001".to_string(),
+            event_type: LOT_EVENT.into(),
+            ..Default::default()
+        });
+        let genesis_hash = blockchain.get_last_block().header.hash;
+        let mined = vec![create_mock_transaction(1), lot_claim.clone()];
+        let block = Block::new(1, 0, genesis_hash, mined);
+        blockchain.add_block(block).unwrap();
+        transaction_pool.add_transaction(create_mock_transaction(2));
+        transaction_pool.add_transaction(create_mock_transaction(3));
+
+        // the longer fork includes a pending transaction, and allocated the lot to another node
+        let other_claim = sign(Transaction {
+            sender: bob(),
+            recipient: bob(),
+            ..lot_claim
+        });
+        let first = Block::new(1, 1, genesis_hash, vec![create_mock_transaction(2)]);
+        let second = Block::new(2, 1, first.header.hash, vec![other_claim]);
+        let reorg = blockchain.reorganize(0, vec![first, second]).unwrap();
+
+        assert_eq!(transaction_pool.return_transactions(&blockchain, &reorg), 1);
+        let pending = transaction_pool.get_all();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0].data, "Mock data 1");
+        assert_eq!(pending[1].data, "Mock data 3");
+    }
+
     fn create_mock_transaction(id: u64) -> Transaction {
-        Transaction {
+        sign(Transaction {
             sender: alice(),
             recipient: bob(),
-            data: format!("Mock data {}", id),
+            data: format!("Mock data {}", id).into(),
             batch_id: "TEST_BATCH".to_string(),
-            event_type: "TEST_EVENT".to_string(),
-        }
+            event_type: "TEST_EVENT".into(),
+            ..Default::default()
+        })
     }
 }
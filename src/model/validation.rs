@@ -0,0 +1,291 @@
+use thiserror::Error;
+
+use super::{Block, BlockHeader, TransactionError};
+
+// Most transactions in a block, the same for every node so the miners never produce blocks the others reject
+pub const MAX_TRANSACTIONS_PER_BLOCK: usize = 10_000;
+
+// How far in the future the timestamp of a block can be, to tolerate the clock differences between nodes
+// Without a limit, a miner could lower the difficulty of the next blocks with fake timestamps
+const MAX_CLOCK_DRIFT_MS: i64 = 5 * 60 * 1000;
+
+// Why a block (or its header) cannot follow the previous one
+#[derive(Error, PartialEq, Debug)]
+pub enum ValidationError {
+    #[error("The genesis block does not match")]
+    GenesisMismatch,
+
+    #[error("Invalid index {found}, expected {expected}")]
+    InvalidIndex { expected: u64, found: u64 },
+
+    #[error("Invalid previous_hash")]
+    InvalidPreviousHash,
+
+    #[error("Invalid hash")]
+    InvalidHash,
+
+    #[error("Invalid merkle_root")]
+    InvalidMerkleRoot,
+
+    #[error(
+        "Invalid difficulty, the hash has {found} leading zero bits and {required} are required"
+    )]
+    InvalidDifficulty { required: u32, found: u32 },
+
+    #[error("The timestamp {timestamp} is older than the one of the previous block ({previous})")]
+    TimestampBeforeParent { timestamp: i64, previous: i64 },
+
+    #[error("The timestamp {timestamp} is in the future (now is {now})")]
+    TimestampInFuture { timestamp: i64, now: i64 },
+
+    #[error("Too many transactions ({count}), a block can have {max} at most")]
+    TooManyTransactions { count: usize, max: usize },
+
+    #[error("Transaction {position} has an invalid signature: {error}")]
+    InvalidSignature {
+        position: usize,
+        error: TransactionError,
+    },
+}
+
+// Checks the rules of the blocks that don't depend on the state of the chain, only on the previous block
+// The transactions are checked against the state by the chain itself (lifecycle, delegations...)
+#[derive(Debug, Clone, Copy)]
+pub struct BlockValidator {
+    // timestamp in milliseconds that the blocks cannot be ahead of
+    now: i64,
+}
+
+impl BlockValidator {
+    pub fn at(now: i64) -> BlockValidator {
+        BlockValidator { now }
+    }
+
+    // Checks that a header follows the previous one: index, link, timestamp, hash and difficulty
+    // The difficulty depends on the timestamps of the blocks before it, so it's given by the chain
+    pub fn validate_header(
+        &self,
+        previous: &BlockHeader,
+        header: &BlockHeader,
+        required: u32,
+    ) -> Result<(), ValidationError> {
+        if header.index != previous.index + 1 {
+            return Err(ValidationError::InvalidIndex {
+                expected: previous.index + 1,
+                found: header.index,
+            });
+        }
+
+        if header.previous_hash != previous.hash {
+            return Err(ValidationError::InvalidPreviousHash);
+        }
+
+        // blocks mined in the same millisecond have the same timestamp
+        if header.timestamp < previous.timestamp {
+            return Err(ValidationError::TimestampBeforeParent {
+                timestamp: header.timestamp,
+                previous: previous.timestamp,
+            });
+        }
+        if header.timestamp > self.now + MAX_CLOCK_DRIFT_MS {
+            return Err(ValidationError::TimestampInFuture {
+                timestamp: header.timestamp,
+                now: self.now,
+            });
+        }
+
+        if header.hash != header.calculate_hash() {
+            return Err(ValidationError::InvalidHash);
+        }
+
+        let found = header.hash.leading_zeros();
+        if found < required {
+            return Err(ValidationError::InvalidDifficulty { required, found });
+        }
+
+        Ok(())
+    }
+
+    // Checks a whole block: its header, the amount of transactions, the merkle root and the signatures
+    pub fn validate_block(
+        &self,
+        previous: &BlockHeader,
+        block: &Block,
+        required: u32,
+    ) -> Result<(), ValidationError> {
+        // the hash only covers the transactions through the merkle root
+        if block.header.merkle_root != block.calculate_merkle_root() {
+            return Err(ValidationError::InvalidMerkleRoot);
+        }
+
+        self.validate_header(previous, &block.header, required)?;
+
+        if block.body.transactions.len() > MAX_TRANSACTIONS_PER_BLOCK {
+            return Err(ValidationError::TooManyTransactions {
+                count: block.body.transactions.len(),
+                max: MAX_TRANSACTIONS_PER_BLOCK,
+            });
+        }
+
+        // every transaction is signed by its sender, but the reward of the miner that goes first
+        for (position, transaction) in block.body.transactions.iter().enumerate() {
+            if position > 0 || !transaction.is_coinbase() {
+                transaction
+                    .verify()
+                    .map_err(|error| ValidationError::InvalidSignature { position, error })?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{fixtures::alice, BlockHash, Transaction};
+
+    const NOW: i64 = 1_700_000_000_000;
+
+    #[test]
+    fn should_check_the_header_against_the_previous_one() {
+        let validator = BlockValidator::at(NOW);
+        let previous = create_block(0, BlockHash::zero(), NOW - 1000, Vec::new());
+        let block = create_block(1, previous.header.hash, NOW, Vec::new());
+        let header = block.header.clone();
+        let validate =
+            |header: &BlockHeader| validator.validate_header(&previous.header, header, 0);
+        assert_eq!(validate(&header), Ok(()));
+
+        let mut skipped = create_block(2, previous.header.hash, NOW, Vec::new())
+            .header
+            .clone();
+        skipped.hash = skipped.calculate_hash();
+        assert_eq!(
+            validate(&skipped),
+            Err(ValidationError::InvalidIndex {
+                expected: 1,
+                found: 2
+            })
+        );
+
+        let older = create_block(1, previous.header.hash, NOW - 2000, Vec::new());
+        assert_eq!(
+            validate(&older.header),
+            Err(ValidationError::TimestampBeforeParent {
+                timestamp: NOW - 2000,
+                previous: NOW - 1000
+            })
+        );
+
+        // a few minutes ahead is tolerated, but not more
+        let ahead = create_block(1, previous.header.hash, NOW + 60_000, Vec::new());
+        assert_eq!(validate(&ahead.header), Ok(()));
+        let future = create_block(1, previous.header.hash, NOW + 3_600_000, Vec::new());
+        assert_eq!(
+            validate(&future.header),
+            Err(ValidationError::TimestampInFuture {
+                timestamp: NOW + 3_600_000,
+                now: NOW
+            })
+        );
+
+        let mut tampered = header.clone();
+        tampered.nonce += 1;
+        assert_eq!(validate(&tampered), Err(ValidationError::InvalidHash));
+
+        let result = validator.validate_header(&previous.header, &header, 255);
+        assert!(matches!(
+            result,
+            Err(ValidationError::InvalidDifficulty { required: 255, .. })
+        ));
+    }
+
+    #[test]
+    fn should_check_the_transactions_of_the_block() {
+        let validator = BlockValidator::at(NOW);
+        let previous = create_block(0, BlockHash::zero(), NOW, Vec::new());
+
+        let too_many = vec![Transaction::default(); MAX_TRANSACTIONS_PER_BLOCK + 1];
+        let block = create_block(1, previous.header.hash, NOW, too_many);
+        assert_eq!(
+            validator.validate_block(&previous.header, &block, 0),
+            Err(ValidationError::TooManyTransactions {
+                count: MAX_TRANSACTIONS_PER_BLOCK + 1,
+                max: MAX_TRANSACTIONS_PER_BLOCK
+            })
+        );
+
+        // a signature that doesn't match the sender
+        let coinbase = Transaction {
+            event_type: "BLOCK_VALIDATION".into(),
+            ..Default::default()
+        };
+        let forged = Transaction {
+            sender: alice(),
+            recipient: alice(),
+            signature: Some("00".repeat(64)),
+            ..Default::default()
+        };
+        let block = create_block(
+            1,
+            previous.header.hash,
+            NOW,
+            vec![coinbase.clone(), forged.clone()],
+        );
+        let result = validator.validate_block(&previous.header, &block, 0);
+        assert!(matches!(
+            result,
+            Err(ValidationError::InvalidSignature { position: 1, .. })
+        ));
+
+        // or no signature at all, only the reward of the miner has none
+        let unsigned = Transaction {
+            signature: None,
+            ..forged
+        };
+        let block = create_block(
+            1,
+            previous.header.hash,
+            NOW,
+            vec![coinbase.clone(), unsigned],
+        );
+        assert_eq!(
+            validator.validate_block(&previous.header, &block, 0),
+            Err(ValidationError::InvalidSignature {
+                position: 1,
+                error: TransactionError::MissingSignature
+            })
+        );
+        // which goes first
+        let block = create_block(
+            1,
+            previous.header.hash,
+            NOW,
+            vec![coinbase.clone(), coinbase],
+        );
+        assert!(matches!(
+            validator.validate_block(&previous.header, &block, 0),
+            Err(ValidationError::InvalidSignature { position: 1, .. })
+        ));
+
+        let mut block = create_block(1, previous.header.hash, NOW, vec![Transaction::default()]);
+        block.body.transactions.push(Transaction::default());
+        assert_eq!(
+            validator.validate_block(&previous.header, &block, 0),
+            Err(ValidationError::InvalidMerkleRoot)
+        );
+    }
+
+    fn create_block(
+        index: u64,
+        previous_hash: BlockHash,
+        timestamp: i64,
+        transactions: Vec<Transaction>,
+    ) -> Block {
+        let mut block = Block::new(index, 0, previous_hash, transactions);
+        block.header.timestamp = timestamp;
+        block.header.hash = block.calculate_hash();
+        block
+    }
+}
@@ -0,0 +1,133 @@
+use chrono::{TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{CanonicalWriter, TransactionError};
+
+// Bounds of the blocks that can include a transaction signed in advance, e.g. by the device of a seasonal
+// worker that pre-authorizes its events, which a coordinator submits later on its behalf
+// The window is signed with the rest of the transaction, so whoever submits it cannot move it
+// The heights are the indexes of the blocks and the times are in milliseconds, all of them inclusive,
+// and they are checked against the block that includes the transaction (the next one when it is submitted)
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ValidityWindow {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before_height: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_after_height: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_after: Option<i64>,
+}
+
+impl ValidityWindow {
+    // At least one bound, and none of them ends before it starts
+    pub fn validate(&self) -> Result<(), TransactionError> {
+        let is_empty = self.not_before_height.is_none()
+            && self.not_after_height.is_none()
+            && self.not_before.is_none()
+            && self.not_after.is_none();
+        let is_inverted = self
+            .not_before_height
+            .zip(self.not_after_height)
+            .is_some_and(|(from, to)| from > to)
+            || self
+                .not_before
+                .zip(self.not_after)
+                .is_some_and(|(from, to)| from > to);
+        match is_empty || is_inverted {
+            true => Err(TransactionError::InvalidValidityWindow),
+            false => Ok(()),
+        }
+    }
+
+    // Checks that a block with that index and timestamp can include the transaction
+    pub fn check(&self, height: u64, timestamp: i64) -> Result<(), TransactionError> {
+        if let Some(from) = self.not_before_height.filter(|from| height < *from) {
+            return Err(TransactionError::NotYetValid(format!("block {}", from)));
+        }
+        if let Some(from) = self.not_before.filter(|from| timestamp < *from) {
+            return Err(TransactionError::NotYetValid(format_time(from)));
+        }
+        if let Some(to) = self.not_after_height.filter(|to| height > *to) {
+            return Err(TransactionError::ValidityExpired(format!("block {}", to)));
+        }
+        if let Some(to) = self.not_after.filter(|to| timestamp > *to) {
+            return Err(TransactionError::ValidityExpired(format_time(to)));
+        }
+
+        Ok(())
+    }
+
+    // The four bounds, each one present or not, so a window takes from 5 to 37 bytes
+    pub fn write_canonical(&self, writer: &mut CanonicalWriter) {
+        writer.option(self.not_before_height.as_ref(), |writer, height| {
+            writer.u64(*height)
+        });
+        writer.option(self.not_after_height.as_ref(), |writer, height| {
+            writer.u64(*height)
+        });
+        writer.option(self.not_before.as_ref(), |writer, time| writer.i64(*time));
+        writer.option(self.not_after.as_ref(), |writer, time| writer.i64(*time));
+    }
+}
+
+fn format_time(timestamp: i64) -> String {
+    Utc.timestamp_millis_opt(timestamp)
+        .single()
+        .map_or_else(|| timestamp.to_string(), |time| time.to_rfc3339())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_check_the_bounds_of_the_window() {
+        let window = ValidityWindow {
+            not_before_height: Some(10),
+            not_after_height: Some(20),
+            not_after: Some(5_000),
+            ..Default::default()
+        };
+        assert_eq!(window.validate(), Ok(()));
+        assert_eq!(window.check(10, 5_000), Ok(()));
+        assert_eq!(window.check(20, 0), Ok(()));
+        assert_eq!(
+            window.check(9, 0),
+            Err(TransactionError::NotYetValid("block 10".to_string()))
+        );
+        assert_eq!(
+            window.check(21, 0),
+            Err(TransactionError::ValidityExpired("block 20".to_string()))
+        );
+        assert_eq!(
+            window.check(15, 5_001),
+            Err(TransactionError::ValidityExpired(
+                "1970-01-01T00:00:05+00:00".to_string()
+            ))
+        );
+
+        // a window without bounds, or that ends before it starts
+        let invalid = [
+            ValidityWindow::default(),
+            ValidityWindow {
+                not_before_height: Some(3),
+                not_after_height: Some(2),
+                ..Default::default()
+            },
+            ValidityWindow {
+                not_before: Some(2_000),
+                not_after: Some(1_000),
+                ..Default::default()
+            },
+        ];
+        for window in invalid {
+            assert_eq!(
+                window.validate(),
+                Err(TransactionError::InvalidValidityWindow)
+            );
+        }
+    }
+}
@@ -0,0 +1,476 @@
+use std::{io, time::Duration};
+
+use anyhow::Result;
+use futures::StreamExt;
+use libp2p::{
+    gossipsub::{self, IdentTopic, MessageAcceptance, MessageId, PublishError, TopicHash},
+    mdns, noise,
+    swarm::{behaviour::toggle::Toggle, NetworkBehaviour, SwarmEvent},
+    tcp, yamux, Multiaddr, PeerId, Swarm,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    chaos,
+    cluster::{LeaderLease, Maintenance},
+    model::{
+        transaction_hash, Block, Blockchain, BlockchainError, ChainEvent, EventSubscription,
+        OriginChannel, Transaction, TransactionOrigins, TransactionPool, ValidationError,
+    },
+    util::{execution::Runnable, Context},
+};
+
+// Period to check the chain events to broadcast (new transactions and blocks)
+const EVENT_POLL_MS: u64 = 50;
+
+// Big enough for a block full of transactions
+const MAX_MESSAGE_BYTES: usize = 8 * 1024 * 1024;
+
+// Connections without gossip for this long are closed
+const IDLE_CONNECTION_SECS: u64 = 60;
+
+// Gossip network between the nodes, over libp2p
+// New transactions and mined blocks are broadcast as soon as they are accepted by this node,
+// and the ones received from other nodes are validated before being added and relayed
+// Nodes find each other with mDNS on the local network, or through the bootstrap addresses
+pub struct Network {
+    listen_address: String,
+    bootstrap: Vec<String>,
+    mdns: bool,
+    handler: GossipHandler,
+}
+
+#[derive(NetworkBehaviour)]
+struct NodeBehaviour {
+    gossipsub: gossipsub::Behaviour,
+    mdns: Toggle<mdns::tokio::Behaviour>,
+}
+
+// Topics of a network, named after its genesis so nodes of different networks never mix their messages
+struct Topics {
+    blocks: IdentTopic,
+    transactions: IdentTopic,
+}
+
+impl Topics {
+    fn new(blockchain: &Blockchain) -> Topics {
+        let genesis = blockchain.get_genesis_summary().hash;
+
+        Topics {
+            blocks: IdentTopic::new(format!("agriblock/{:#x}/blocks", genesis)),
+            transactions: IdentTopic::new(format!("agriblock/{:#x}/transactions", genesis)),
+        }
+    }
+}
+
+// Validates the messages received from the other nodes and applies the valid ones
+struct GossipHandler {
+    blockchain: Blockchain,
+    pool: TransactionPool,
+    origins: TransactionOrigins,
+    maintenance: Maintenance,
+    leader_lease: LeaderLease,
+    topics: Topics,
+}
+
+impl Runnable for Network {
+    fn run(&self) -> Result<()> {
+        self.start()
+    }
+}
+
+impl Network {
+    pub fn new(context: &Context) -> Network {
+        let handler = GossipHandler {
+            blockchain: context.blockchain.clone(),
+            pool: context.pool.clone(),
+            origins: context.origins.clone(),
+            maintenance: context.maintenance.clone(),
+            leader_lease: context.leader_lease.clone(),
+            topics: Topics::new(&context.blockchain),
+        };
+
+        Network {
+            listen_address: context.config.p2p_listen_address.clone(),
+            bootstrap: context.config.p2p_bootstrap.clone(),
+            mdns: context.config.p2p_mdns,
+            handler,
+        }
+    }
+
+    pub fn start(&self) -> Result<()> {
+        if self.listen_address.is_empty() {
+            info!("No P2P listen address configured, exiting gossip network");
+            return Ok(());
+        }
+
+        // libp2p is asynchronous, so the network runs its own runtime in its thread
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        runtime.block_on(self.run_swarm())
+    }
+
+    async fn run_swarm(&self) -> Result<()> {
+        // subscribed before listening, so no event is missed
+        let mut subscription = self.handler.blockchain.event_bus().subscribe();
+        let mut swarm = self.build_swarm()?;
+
+        let topics = &self.handler.topics;
+        swarm.behaviour_mut().gossipsub.subscribe(&topics.blocks)?;
+        swarm
+            .behaviour_mut()
+            .gossipsub
+            .subscribe(&topics.transactions)?;
+
+        swarm.listen_on(self.listen_address.parse()?)?;
+        for address in self.bootstrap.iter() {
+            let result = address
+                .parse::<Multiaddr>()
+                .map_err(anyhow::Error::from)
+                .and_then(|address| Ok(swarm.dial(address)?));
+            if let Err(error) = result {
+                error!("Could not dial the bootstrap node {}: {}", address, error);
+            }
+        }
+        info!(
+            "start gossip network as {} on {}",
+            swarm.local_peer_id(),
+            self.listen_address
+        );
+
+        let mut events = tokio::time::interval(Duration::from_millis(EVENT_POLL_MS));
+        loop {
+            tokio::select! {
+                event = swarm.select_next_some() => self.handle_swarm_event(&mut swarm, event),
+                _ = events.tick() => self.broadcast(&mut swarm, &mut subscription),
+            }
+        }
+    }
+
+    fn build_swarm(&self) -> Result<Swarm<NodeBehaviour>> {
+        let mdns = self.mdns;
+        let swarm = libp2p::SwarmBuilder::with_new_identity()
+            .with_tokio()
+            .with_tcp(
+                tcp::Config::default(),
+                noise::Config::new,
+                yamux::Config::default,
+            )?
+            .with_behaviour(|key| {
+                // the same content is the same message, so a node never relays it twice
+                let config = gossipsub::ConfigBuilder::default()
+                    .validation_mode(gossipsub::ValidationMode::Strict)
+                    .validate_messages()
+                    .message_id_fn(content_id)
+                    .max_transmit_size(MAX_MESSAGE_BYTES)
+                    .build()
+                    .map_err(io::Error::other)?;
+                let gossipsub = gossipsub::Behaviour::new(
+                    gossipsub::MessageAuthenticity::Signed(key.clone()),
+                    config,
+                )?;
+                let mdns = match mdns {
+                    true => Some(mdns::tokio::Behaviour::new(
+                        mdns::Config::default(),
+                        key.public().to_peer_id(),
+                    )?),
+                    false => None,
+                };
+
+                Ok(NodeBehaviour {
+                    gossipsub,
+                    mdns: Toggle::from(mdns),
+                })
+            })?
+            .with_swarm_config(|config| {
+                config.with_idle_connection_timeout(Duration::from_secs(IDLE_CONNECTION_SECS))
+            })
+            .build();
+
+        Ok(swarm)
+    }
+
+    fn handle_swarm_event(
+        &self,
+        swarm: &mut Swarm<NodeBehaviour>,
+        event: SwarmEvent<NodeBehaviourEvent>,
+    ) {
+        let gossipsub = &mut swarm.behaviour_mut().gossipsub;
+        match event {
+            SwarmEvent::Behaviour(NodeBehaviourEvent::Mdns(mdns::Event::Discovered(peers))) => {
+                for (peer_id, address) in peers {
+                    info!("Discovered gossip peer {} at {}", peer_id, address);
+                    gossipsub.add_explicit_peer(&peer_id);
+                }
+            }
+            SwarmEvent::Behaviour(NodeBehaviourEvent::Mdns(mdns::Event::Expired(peers))) => {
+                for (peer_id, _) in peers {
+                    gossipsub.remove_explicit_peer(&peer_id);
+                }
+            }
+            SwarmEvent::Behaviour(NodeBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                propagation_source,
+                message_id,
+                message,
+            })) => {
+                // a lost message is left to the relays of the other peers, or to the peer sync
+                let acceptance = match chaos::network_message(&propagation_source.to_string()) {
+                    Ok(()) => {
+                        self.handler
+                            .accept(&message.topic, &message.data, &propagation_source)
+                    }
+                    Err(error) => {
+                        warn!("{}", error);
+                        MessageAcceptance::Ignore
+                    }
+                };
+                // only the accepted messages are relayed to the other nodes
+                let _ = gossipsub.report_message_validation_result(
+                    &message_id,
+                    &propagation_source,
+                    acceptance,
+                );
+            }
+            SwarmEvent::Behaviour(NodeBehaviourEvent::Gossipsub(
+                gossipsub::Event::Subscribed { peer_id, topic },
+            )) if topic == self.handler.topics.blocks.hash() => {
+                info!("Gossip peer {} subscribed to the blocks", peer_id);
+            }
+            SwarmEvent::NewListenAddr { address, .. } => {
+                info!("Gossip network listening on {}", address);
+            }
+            _ => {}
+        }
+    }
+
+    // Broadcasts the transactions and blocks accepted by this node since the last call
+    fn broadcast(&self, swarm: &mut Swarm<NodeBehaviour>, subscription: &mut EventSubscription) {
+        let topics = &self.handler.topics;
+        for event in subscription.drain() {
+            let (topic, data) = match event {
+                ChainEvent::BlockApplied(block) => (
+                    &topics.blocks,
+                    block.to_bytes().map_err(anyhow::Error::from),
+                ),
+                ChainEvent::TxAccepted(transaction) => (
+                    &topics.transactions,
+                    serde_json::to_vec(&transaction).map_err(anyhow::Error::from),
+                ),
+                _ => continue,
+            };
+            let data = match data {
+                Ok(data) => data,
+                Err(error) => {
+                    error!("Could not serialize a gossip message: {}", error);
+                    continue;
+                }
+            };
+
+            match swarm.behaviour_mut().gossipsub.publish(topic.clone(), data) {
+                Ok(_) => {}
+                // nobody to send it to, or it came from the network in the first place
+                Err(PublishError::InsufficientPeers) | Err(PublishError::Duplicate) => {}
+                Err(error) => error!("Could not publish to {}: {}", topic, error),
+            }
+        }
+    }
+}
+
+impl GossipHandler {
+    // Applies a message received from a peer, telling gossipsub whether to relay it
+    // Messages that this node cannot apply (e.g. a block it already has) are ignored, and only invalid ones rejected
+    fn accept(&self, topic: &TopicHash, data: &[u8], source: &PeerId) -> MessageAcceptance {
+        // nothing is applied during a maintenance, the peer sync catches up once it ends
+        if self.maintenance.is_active() {
+            return MessageAcceptance::Ignore;
+        }
+
+        // in a cluster only the leader adds blocks, the others catch up from the storage when they take over
+        if *topic == self.topics.blocks.hash() && !self.leader_lease.is_leader() {
+            MessageAcceptance::Ignore
+        } else if *topic == self.topics.blocks.hash() {
+            self.accept_block(data, source)
+        } else if *topic == self.topics.transactions.hash() {
+            self.accept_transaction(data, source)
+        } else {
+            MessageAcceptance::Ignore
+        }
+    }
+
+    fn accept_block(&self, data: &[u8], source: &PeerId) -> MessageAcceptance {
+        let block = match Block::from_bytes(data) {
+            Ok(block) => block,
+            Err(_) => return MessageAcceptance::Reject,
+        };
+
+        let index = block.header.index;
+        let transactions = block.body.transactions.clone();
+        match self.blockchain.add_block(block) {
+            Ok(_) => {
+                let peer = source.to_string();
+                for transaction in transactions.iter() {
+                    self.origins
+                        .record(transaction, OriginChannel::Gossip, Some(&peer), None);
+                }
+                info!("Added new gossip block {} to the blockchain", index);
+                MessageAcceptance::Accept
+            }
+            // a block we already have, or one after blocks we are missing (the peer sync catches up)
+            Err(error)
+                if matches!(
+                    error.downcast_ref(),
+                    Some(BlockchainError::InvalidBlock(
+                        ValidationError::InvalidIndex { .. }
+                    ))
+                ) =>
+            {
+                MessageAcceptance::Ignore
+            }
+            Err(error) => {
+                warn!("Rejected gossip block {} from {}: {}", index, source, error);
+                MessageAcceptance::Reject
+            }
+        }
+    }
+
+    fn accept_transaction(&self, data: &[u8], source: &PeerId) -> MessageAcceptance {
+        let transaction: Transaction = match serde_json::from_slice(data) {
+            Ok(transaction) => transaction,
+            Err(_) => return MessageAcceptance::Reject,
+        };
+        // already received from another peer, or submitted to this node
+        let pending = self.pool.get_unconfirmed();
+        let hash = transaction_hash(&transaction);
+        if pending.iter().any(|other| transaction_hash(other) == hash) {
+            return MessageAcceptance::Ignore;
+        }
+        if let Err(error) = self
+            .blockchain
+            .validate_transaction_after(&transaction, &pending)
+        {
+            warn!("Rejected gossip transaction from {}: {}", source, error);
+            return MessageAcceptance::Reject;
+        }
+
+        let peer = source.to_string();
+        self.origins
+            .record(&transaction, OriginChannel::Gossip, Some(&peer), None);
+        match self.pool.add_transaction(transaction) {
+            true => MessageAcceptance::Accept,
+            false => MessageAcceptance::Ignore,
+        }
+    }
+}
+
+fn content_id(message: &gossipsub::Message) -> MessageId {
+    MessageId::from(hex::encode(Sha256::digest(&message.data)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        fixtures::{alice, sign},
+        PROFILE_EVENT,
+    };
+
+    #[test]
+    fn should_validate_gossip_blocks() {
+        let handler = create_handler();
+        let source = PeerId::random();
+        let previous_hash = handler.blockchain.get_last_block().header.hash;
+        let block = Block::new(1, 0, previous_hash, vec![create_transaction("{}")]);
+        let data = block.to_bytes().unwrap();
+
+        let topic = handler.topics.blocks.hash();
+        // not applied by the nodes of a cluster on standby
+        let standby = GossipHandler {
+            leader_lease: LeaderLease::new("agriblock.lease", "node-2", 10_000),
+            ..create_handler()
+        };
+        let acceptance = standby.accept(&standby.topics.blocks.hash(), &data, &source);
+        assert!(matches!(acceptance, MessageAcceptance::Ignore));
+        assert_eq!(standby.blockchain.get_last_block().header.index, 0);
+
+        // not applied during a maintenance
+        handler
+            .maintenance
+            .start(crate::cluster::MaintenanceReason::Upgrade, None);
+        let acceptance = handler.accept(&topic, &data, &source);
+        assert!(matches!(acceptance, MessageAcceptance::Ignore));
+        assert_eq!(handler.blockchain.get_last_block().header.index, 0);
+
+        handler.maintenance.end();
+        let acceptance = handler.accept(&topic, &data, &source);
+        assert!(matches!(acceptance, MessageAcceptance::Accept));
+        assert_eq!(
+            handler.blockchain.get_last_block().header.hash,
+            block.header.hash
+        );
+        // the same block again is not relayed, but the peer did nothing wrong
+        let acceptance = handler.accept_block(&data, &source);
+        assert!(matches!(acceptance, MessageAcceptance::Ignore));
+
+        let mut tampered = Block::new(2, 0, block.header.hash, vec![]);
+        tampered.header.nonce += 1;
+        let data = tampered.to_bytes().unwrap();
+        let acceptance = handler.accept_block(&data, &source);
+        assert!(matches!(acceptance, MessageAcceptance::Reject));
+
+        let acceptance = handler.accept_block(b"not a block", &source);
+        assert!(matches!(acceptance, MessageAcceptance::Reject));
+    }
+
+    #[test]
+    fn should_validate_gossip_transactions() {
+        let handler = create_handler();
+        let source = PeerId::random();
+        let transaction = create_transaction("{}");
+        let data = serde_json::to_vec(&transaction).unwrap();
+
+        let topic = handler.topics.transactions.hash();
+        let acceptance = handler.accept(&topic, &data, &source);
+        assert!(matches!(acceptance, MessageAcceptance::Accept));
+        assert_eq!(handler.pool.get_unconfirmed().len(), 1);
+        let acceptance = handler.accept_transaction(&data, &source);
+        assert!(matches!(acceptance, MessageAcceptance::Ignore));
+
+        let invalid = sign(Transaction {
+            event_type: PROFILE_EVENT.into(),
+            ..create_transaction("not a profile")
+        });
+        let data = serde_json::to_vec(&invalid).unwrap();
+        let acceptance = handler.accept_transaction(&data, &source);
+        assert!(matches!(acceptance, MessageAcceptance::Reject));
+
+        // messages of another network are not applied
+        let other_topic = IdentTopic::new("agriblock/0x00/transactions");
+        let acceptance = handler.accept(&other_topic.hash(), &data, &source);
+        assert!(matches!(acceptance, MessageAcceptance::Ignore));
+    }
+
+    fn create_handler() -> GossipHandler {
+        let blockchain = Blockchain::new(0);
+
+        GossipHandler {
+            pool: TransactionPool::new(blockchain.event_bus()),
+            origins: TransactionOrigins::new(String::new()),
+            maintenance: Maintenance::default(),
+            leader_lease: LeaderLease::new("", "", 0),
+            topics: Topics::new(&blockchain),
+            blockchain,
+        }
+    }
+
+    fn create_transaction(data: &str) -> Transaction {
+        sign(Transaction {
+            sender: alice(),
+            recipient: alice(),
+            batch_id: "WHEAT-001".to_string(),
+            data: data.into(),
+            event_type: "HARVEST".into(),
+            ..Default::default()
+        })
+    }
+}
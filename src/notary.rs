@@ -0,0 +1,215 @@
+mod timestamp_authority;
+
+use std::{fs, io::Write, path::PathBuf};
+
+use crate::{
+    model::{BlockHeader, Blockchain},
+    util::{
+        execution::{sleep_millis, Runnable},
+        Context,
+    },
+};
+use anyhow::Result;
+use timestamp_authority::{Rfc3161Authority, TimestampAuthority};
+
+// Periodically requests trusted timestamps for finalized blocks
+// The stored tokens allow to prove in court that the chain already existed at a given time
+pub struct Notary {
+    every_n_blocks: u64,
+    finality_depth: u64,
+    token_dir: PathBuf,
+    poll_ms: u64,
+    blockchain: Blockchain,
+    authority: Box<dyn TimestampAuthority>,
+}
+
+impl Runnable for Notary {
+    fn run(&self) -> Result<()> {
+        self.start()
+    }
+}
+
+impl Notary {
+    pub fn new(context: &Context) -> Notary {
+        let config = &context.config;
+        let authority = Rfc3161Authority::new(config.timestamp_authority_url.clone());
+
+        Notary {
+            every_n_blocks: config.timestamp_every_n_blocks,
+            finality_depth: config.finality_depth,
+            token_dir: PathBuf::from(&config.timestamp_token_dir),
+            poll_ms: config.peer_sync_ms,
+            blockchain: context.blockchain.clone(),
+            authority: Box::new(authority),
+        }
+    }
+
+    pub fn start(&self) -> Result<()> {
+        if self.every_n_blocks == 0 {
+            info!("Block timestamping disabled, exiting notary");
+            return Ok(());
+        }
+
+        info!("start timestamping every {} blocks", self.every_n_blocks);
+        fs::create_dir_all(&self.token_dir)?;
+
+        // after a restart, the blocks with a token already stored are skipped
+        let mut last_timestamped_index = 0;
+        loop {
+            last_timestamped_index = self.timestamp_finalized_blocks(last_timestamped_index);
+            sleep_millis(self.poll_ms);
+        }
+    }
+
+    // Timestamps all the finalized blocks that we should timestamp since the last one
+    // Returns the index of the last block that was processed, to continue from it in the next round
+    fn timestamp_finalized_blocks(&self, last_timestamped_index: u64) -> u64 {
+        let last_index = self.blockchain.get_last_block().header.index;
+        let last_finalized_index = match last_index.checked_sub(self.finality_depth) {
+            Some(index) => index,
+            None => return last_timestamped_index,
+        };
+
+        // only the hashes are needed, so we don't load the transactions of the blocks
+        let headers = self
+            .blockchain
+            .iter_headers(last_timestamped_index + 1..last_finalized_index + 1);
+        for header in headers {
+            let index = header.index;
+            if index % self.every_n_blocks != 0 || self.token_path(&header).exists() {
+                continue;
+            }
+
+            // if the authority is not available, we will retry from this block in the next round
+            if let Err(error) = self.timestamp_block(&header) {
+                error!("Could not timestamp block {}: {}", index, error);
+                return index - 1;
+            }
+            info!("Timestamped block {}", index);
+        }
+
+        last_timestamped_index.max(last_finalized_index)
+    }
+
+    fn timestamp_block(&self, header: &BlockHeader) -> Result<()> {
+        let token = self.authority.timestamp(&header.hash)?;
+
+        // a token is never overwritten, the first one is the earliest proof
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(self.token_path(header))?;
+        file.write_all(&token)?;
+
+        Ok(())
+    }
+
+    fn token_path(&self, header: &BlockHeader) -> PathBuf {
+        let file_name = format!("{}-{:#x}.tsr", header.index, header.hash);
+        self.token_dir.join(file_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::model::{Block, BlockHash};
+
+    // Timestamping authority that records the requested hashes instead of calling a real server
+    #[derive(Clone, Default)]
+    struct MockAuthority {
+        requested_hashes: Arc<Mutex<Vec<BlockHash>>>,
+    }
+
+    impl TimestampAuthority for MockAuthority {
+        fn timestamp(&self, hash: &BlockHash) -> Result<Vec<u8>> {
+            self.requested_hashes.lock().unwrap().push(*hash);
+            Ok(b"token".to_vec())
+        }
+    }
+
+    #[test]
+    fn should_timestamp_every_n_finalized_blocks() {
+        let authority = MockAuthority::default();
+        let token_dir = std::env::temp_dir().join("rust_blockchain_notary_test");
+        fs::create_dir_all(&token_dir).unwrap();
+        let notary = create_notary(authority.clone(), token_dir.clone());
+        add_empty_blocks(&notary.blockchain, 7);
+
+        // with 7 blocks and a depth of 2, only blocks 2 and 4 are both finalized and multiple of 2
+        let last_processed = notary.timestamp_finalized_blocks(0);
+        assert_eq!(last_processed, 5);
+
+        let blocks = notary.blockchain.get_all_blocks();
+        let requested_hashes = authority.requested_hashes.lock().unwrap().clone();
+        assert_eq!(
+            requested_hashes,
+            vec![blocks[2].header.hash, blocks[4].header.hash]
+        );
+
+        // the tokens are stored to be presented later
+        let file_name = format!("2-{:#x}.tsr", blocks[2].header.hash);
+        assert_eq!(fs::read(token_dir.join(file_name)).unwrap(), b"token");
+
+        // nothing new to timestamp in the next round
+        assert_eq!(notary.timestamp_finalized_blocks(last_processed), 5);
+        assert_eq!(authority.requested_hashes.lock().unwrap().len(), 2);
+
+        fs::remove_dir_all(token_dir).unwrap();
+    }
+
+    #[test]
+    fn should_not_timestamp_again_after_a_restart() {
+        let authority = MockAuthority::default();
+        let token_dir = std::env::temp_dir().join("rust_blockchain_notary_restart_test");
+        let _ = fs::remove_dir_all(&token_dir);
+        fs::create_dir_all(&token_dir).unwrap();
+        let notary = create_notary(authority.clone(), token_dir.clone());
+        add_empty_blocks(&notary.blockchain, 7);
+
+        // the token of block 2 was stored before the restart
+        let blocks = notary.blockchain.get_all_blocks();
+        let file_name = format!("2-{:#x}.tsr", blocks[2].header.hash);
+        fs::write(token_dir.join(&file_name), b"earlier token").unwrap();
+
+        // the notary starts over from the first block, but only requests the missing token
+        assert_eq!(notary.timestamp_finalized_blocks(0), 5);
+        let requested_hashes = authority.requested_hashes.lock().unwrap().clone();
+        assert_eq!(requested_hashes, vec![blocks[4].header.hash]);
+        assert_eq!(
+            fs::read(token_dir.join(file_name)).unwrap(),
+            b"earlier token"
+        );
+
+        // and a token is never overwritten
+        assert!(notary.timestamp_block(&blocks[4].header).is_err());
+
+        fs::remove_dir_all(token_dir).unwrap();
+    }
+
+    fn create_notary(authority: MockAuthority, token_dir: PathBuf) -> Notary {
+        Notary {
+            every_n_blocks: 2,
+            finality_depth: 2,
+            token_dir,
+            poll_ms: 1,
+            blockchain: Blockchain::new(0),
+            authority: Box::new(authority),
+        }
+    }
+
+    fn add_empty_blocks(blockchain: &Blockchain, amount: u64) {
+        for _ in 0..amount {
+            let last_block = blockchain.get_last_block();
+            let block = Block::new(
+                last_block.header.index + 1,
+                0,
+                last_block.header.hash,
+                Vec::new(),
+            );
+            blockchain.add_block(block).unwrap();
+        }
+    }
+}
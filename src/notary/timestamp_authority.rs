@@ -0,0 +1,165 @@
+use anyhow::{bail, Result};
+use isahc::{ReadResponseExt, Request};
+
+use crate::model::BlockHash;
+
+// DER encoding of the SHA-256 algorithm identifier (OID 2.16.840.1.101.3.4.2.1 with NULL parameters)
+const SHA256_ALGORITHM_IDENTIFIER: [u8; 15] = [
+    0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05, 0x00,
+];
+
+// A trusted third party that certifies that a block hash existed at a given time
+// Returns an opaque token that can be presented later as proof
+pub trait TimestampAuthority: Sync {
+    fn timestamp(&self, hash: &BlockHash) -> Result<Vec<u8>>;
+}
+
+// Timestamping authority that follows the RFC 3161 protocol over HTTP
+pub struct Rfc3161Authority {
+    url: String,
+}
+
+impl Rfc3161Authority {
+    pub fn new(url: String) -> Rfc3161Authority {
+        Rfc3161Authority { url }
+    }
+}
+
+impl TimestampAuthority for Rfc3161Authority {
+    fn timestamp(&self, hash: &BlockHash) -> Result<Vec<u8>> {
+        let request = Request::post(&self.url)
+            .header("Content-Type", "application/timestamp-query")
+            .body(create_timestamp_request(hash))?;
+        let mut response = isahc::send(request)?;
+
+        if !response.status().is_success() {
+            bail!("timestamp authority responded with {}", response.status());
+        }
+
+        // the whole response (TimeStampResp) is the token that proves the age of the block
+        let token = response.bytes()?;
+        check_timestamp_response(&token)?;
+        Ok(token)
+    }
+}
+
+// Checks that the DER encoded RFC 3161 "TimeStampResp" grants the timestamp, with or without modifications
+// The authority answers with HTTP 200 even when it rejects the request, with the reason in its status
+pub fn check_timestamp_response(response: &[u8]) -> Result<()> {
+    let (response, _) = read_der_element(response, 0x30)?;
+    let (status_info, time_stamp_token) = read_der_element(response, 0x30)?;
+    let (status, _) = read_der_element(status_info, 0x02)?;
+
+    // 0 is granted and 1 granted with modifications, the others are rejections or waiting
+    match status {
+        [0x00] | [0x01] if !time_stamp_token.is_empty() => Ok(()),
+        [0x00] | [0x01] => bail!("timestamp response without its token"),
+        _ => bail!(
+            "timestamp authority refused the request with status {:?}",
+            status
+        ),
+    }
+}
+
+// Creates the DER encoded RFC 3161 "TimeStampReq" for a block hash
+// Block hashes are already SHA-256 digests, so they are used directly as the message imprint
+pub fn create_timestamp_request(hash: &BlockHash) -> Vec<u8> {
+    let mut hash_bytes = [0u8; 32];
+    hash.to_big_endian(&mut hash_bytes);
+
+    let mut message_imprint = SHA256_ALGORITHM_IDENTIFIER.to_vec();
+    message_imprint.extend(der_element(0x04, &hash_bytes));
+
+    let mut request = der_element(0x02, &[0x01]); // version 1
+    request.extend(der_element(0x30, &message_imprint));
+    request.extend(der_element(0x01, &[0xff])); // ask for the certificate of the authority
+
+    der_element(0x30, &request)
+}
+
+// Encodes a DER element with a short length (all our elements are less than 128 bytes long)
+fn der_element(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut element = vec![tag, content.len() as u8];
+    element.extend(content);
+    element
+}
+
+// Reads a DER element with the expected tag, returns its content and the bytes that follow it
+fn read_der_element(bytes: &[u8], tag: u8) -> Result<(&[u8], &[u8])> {
+    match bytes {
+        [found, ..] if *found != tag => {
+            bail!("unexpected DER tag {:#x}, expected {:#x}", found, tag)
+        }
+        [_, length, rest @ ..] if *length < 0x80 => split_der_content(rest, *length as usize),
+        // long lengths are given on the next bytes, a response is far smaller than 4GB
+        [_, length, rest @ ..] if (0x81..=0x84).contains(length) => {
+            let length_bytes = (*length - 0x80) as usize;
+            if rest.len() < length_bytes {
+                bail!("truncated DER length");
+            }
+            let content_length = rest[..length_bytes]
+                .iter()
+                .fold(0usize, |length, byte| length << 8 | *byte as usize);
+            split_der_content(&rest[length_bytes..], content_length)
+        }
+        _ => bail!("invalid DER element"),
+    }
+}
+
+fn split_der_content(bytes: &[u8], length: usize) -> Result<(&[u8], &[u8])> {
+    if bytes.len() < length {
+        bail!("truncated DER element");
+    }
+    Ok(bytes.split_at(length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_create_valid_timestamp_request() {
+        let hash = BlockHash::from(0xabcdef);
+        let request = create_timestamp_request(&hash);
+
+        // SEQUENCE with the version, the message imprint and the certificate request
+        assert_eq!(request.len(), 59);
+        assert_eq!(&request[0..2], &[0x30, 57]);
+        assert_eq!(&request[2..5], &[0x02, 0x01, 0x01]);
+        assert_eq!(&request[5..7], &[0x30, 49]);
+        assert_eq!(&request[7..22], &SHA256_ALGORITHM_IDENTIFIER);
+        assert_eq!(&request[56..59], &[0x01, 0x01, 0xff]);
+
+        // the message imprint contains the block hash
+        assert_eq!(&request[22..24], &[0x04, 32]);
+        assert_eq!(&request[53..56], &[0xab, 0xcd, 0xef]);
+    }
+
+    #[test]
+    fn should_only_accept_granted_timestamp_responses() {
+        // a status of 0 (granted) followed by a token, with a long length for the whole response
+        let token = der_element(0x30, &[0x06, 0x01, 0x2a]);
+        let mut granted = der_element(0x30, &der_element(0x02, &[0x00]));
+        granted.extend(&token);
+        let mut long_response = vec![0x30, 0x81, granted.len() as u8];
+        long_response.extend(&granted);
+        assert!(check_timestamp_response(&long_response).is_ok());
+
+        // granted with modifications
+        let mut modified = der_element(0x30, &der_element(0x02, &[0x01]));
+        modified.extend(&token);
+        assert!(check_timestamp_response(&der_element(0x30, &modified)).is_ok());
+
+        // rejected (2), without any token
+        let rejected = der_element(0x30, &der_element(0x02, &[0x02]));
+        assert!(check_timestamp_response(&der_element(0x30, &rejected)).is_err());
+
+        // granted, but without its token
+        let tokenless = der_element(0x30, &der_element(0x02, &[0x00]));
+        assert!(check_timestamp_response(&der_element(0x30, &tokenless)).is_err());
+
+        // not a TimeStampResp at all, or truncated
+        assert!(check_timestamp_response(b"<html>").is_err());
+        assert!(check_timestamp_response(&long_response[..10]).is_err());
+    }
+}
@@ -1,18 +1,38 @@
-use std::panic;
+mod chain_sync;
+mod peer_list;
+mod upgrade_advisory;
+
+use std::{panic, time::Instant};
 
 use crate::{
-    model::{Block, Blockchain},
+    chaos,
+    cluster::{LeaderLease, Maintenance, MaintenanceAnnouncement, MaintenanceNotice},
+    model::{Block, Blockchain, OriginChannel, TransactionOrigins},
     util::{
         execution::{sleep_millis, Runnable},
         Context,
     },
 };
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chain_sync::{
+    download_blocks, get_headers_from_peer, select_chain, ChainCandidate, FORK_DEPTH,
+};
 use isahc::{ReadResponseExt, Request};
+use peer_list::{resolve_dns_seed, PeerVec};
+
+pub use peer_list::PeerList;
+pub use upgrade_advisory::{upgrade_advisories, UpgradeAdvisory, PROTOCOL_VERSION_HEADER};
 
 pub struct Peer {
-    peer_addresses: Vec<String>,
+    peers: PeerList,
+    static_peers: PeerVec,
+    dns_seeds: PeerVec,
     blockchain: Blockchain,
+    origins: TransactionOrigins,
+    maintenance: Maintenance,
+    leader_lease: LeaderLease,
+    // how the peers reach this node, to identify it in the announcements
+    public_address: String,
     peer_sync_ms: u64,
 }
 
@@ -25,103 +45,293 @@ impl Runnable for Peer {
 impl Peer {
     pub fn new(context: &Context) -> Peer {
         Peer {
-            peer_addresses: context.config.peers.clone(),
+            peers: context.peers.clone(),
+            static_peers: context.config.peers.clone(),
+            dns_seeds: context.config.peer_dns_seeds.clone(),
             blockchain: context.blockchain.clone(),
+            origins: context.origins.clone(),
+            maintenance: context.maintenance.clone(),
+            leader_lease: context.leader_lease.clone(),
+            public_address: context.config.public_address.clone(),
             peer_sync_ms: context.config.peer_sync_ms,
         }
     }
 
     pub fn start(&self) -> Result<()> {
-        if self.peer_addresses.is_empty() {
+        self.initialize_peers();
+
+        if self.peers.is_empty() {
             info!("No peers configured, exiting peer sync system");
             return Ok(());
         }
 
         info!(
             "start peer system with peers: {}",
-            self.peer_addresses.join(", ")
+            self.peers.get_all_peers().join(", ")
         );
 
         // At regular intervals of time, we try to sync new blocks from our peers
         let mut last_sent_block_index = self.get_last_block_index();
+        let mut announced = None;
+        let mut advised = Vec::new();
         loop {
-            self.try_receive_new_blocks();
-            self.try_send_new_blocks(last_sent_block_index);
+            self.try_exchange_peers();
+            advised = self.warn_about_upgrades(advised);
+            announced = self.try_announce_maintenance(announced);
+            // in a cluster only the leader syncs, the others catch up from the storage when they take over
+            if self.leader_lease.is_leader() {
+                self.try_receive_new_blocks();
+                self.try_send_new_blocks(last_sent_block_index);
+            }
             last_sent_block_index = self.get_last_block_index();
             sleep_millis(self.peer_sync_ms);
         }
     }
 
-    fn get_last_block_index(&self) -> usize {
-        self.blockchain.get_last_block().index as usize
+    // Collect the initial peers from the persisted list, the static configuration and the DNS seeds
+    // Peers that were reliable in previous runs will be contacted first
+    fn initialize_peers(&self) {
+        if let Err(error) = self.peers.load() {
+            error!("Could not load the persisted peers: {}", error);
+        }
+
+        for address in self.static_peers.iter() {
+            self.peers.add_peer(address);
+        }
+
+        for seed in self.dns_seeds.iter() {
+            match resolve_dns_seed(seed) {
+                Ok(addresses) => addresses.iter().for_each(|address| {
+                    self.peers.add_peer(address);
+                }),
+                Err(error) => error!("Could not resolve DNS seed {}: {}", seed, error),
+            }
+        }
+
+        self.save_peers();
     }
 
-    // Retrieve new blocks from all peers and add them to the blockchain
-    fn try_receive_new_blocks(&self) {
-        for address in self.peer_addresses.iter() {
+    // Ask all our peers for the peers they know, and add the new ones to our list
+    fn try_exchange_peers(&self) {
+        for address in self.peers.get_all_peers().iter() {
             // we don't want to panic if one peer is down or not working properly
-            let result = panic::catch_unwind(|| {
-                let new_blocks = self.get_new_blocks_from_peer(address);
+            let started_at = Instant::now();
+            let result = panic::catch_unwind(|| self.get_peers_from_peer(address));
+            self.record_result(address, started_at, result.is_ok());
 
-                if !new_blocks.is_empty() {
-                    self.add_new_blocks(&new_blocks);
+            match result {
+                Ok((peer_addresses, protocol_version)) => {
+                    self.peers
+                        .record_protocol_version(address, protocol_version);
+                    for peer_address in peer_addresses.iter() {
+                        if self.peers.add_peer(peer_address) {
+                            info!("Discovered new peer {} from {}", peer_address, address);
+                        }
+                    }
                 }
-            });
+                Err(_) => error!("Could not exchange peers with peer {}", address),
+            }
+        }
 
-            // if a peer is not working, we simply log it and ignore the error
+        // the history of the peers changes on every exchange, not only when discovering new ones
+        self.save_peers();
+    }
+
+    // Keeps track of how reliable and fast each peer is
+    fn record_result(&self, address: &str, started_at: Instant, success: bool) {
+        if success {
+            let latency_ms = started_at.elapsed().as_millis() as u64;
+            self.peers.record_success(address, latency_ms);
+        } else {
+            self.peers.record_failure(address);
+        }
+    }
+
+    fn save_peers(&self) {
+        if let Err(error) = self.peers.save() {
+            error!("Could not persist the known peers: {}", error);
+        }
+    }
+
+    fn get_last_block_index(&self) -> usize {
+        self.blockchain.get_last_block().header.index as usize
+    }
+
+    // Tell the peers that this node entered or left maintenance
+    // The notice is sent again on every sync while it lasts, for the peers that joined or restarted since
+    // Returns the last announced notice
+    fn try_announce_maintenance(
+        &self,
+        announced: Option<MaintenanceNotice>,
+    ) -> Option<MaintenanceNotice> {
+        let notice = self.maintenance.current();
+        if notice.is_none() && announced.is_none() {
+            return None;
+        }
+
+        let announcement = MaintenanceAnnouncement {
+            node: self.public_address.clone(),
+            notice: notice.clone(),
+        };
+        for address in self.peers.get_all_peers().iter() {
+            let result = panic::catch_unwind(|| {
+                Peer::send_announcement_to_peer(address, &announcement);
+            });
             if result.is_err() {
-                error!("Could not sync blocks from peer {}", address);
+                error!("Could not announce the maintenance to peer {}", address);
             }
         }
+
+        notice
     }
 
-    // Try to add a bunch of new blocks to our blockchain
-    fn add_new_blocks(&self, new_blocks: &[Block]) {
-        for block in new_blocks.iter() {
-            let result = self.blockchain.add_block(block.clone());
+    // Warns the operator about the upgrades the node needs, from the versions of the peers and the activations
+    // of the network, once per upgrade
+    // Returns the current advisories
+    fn warn_about_upgrades(&self, advised: Vec<UpgradeAdvisory>) -> Vec<UpgradeAdvisory> {
+        let advisories = upgrade_advisories(
+            &self.peers.protocol_versions(),
+            self.blockchain.activations(),
+            self.blockchain.get_last_block().header.index,
+        );
+        for advisory in advisories.iter() {
+            if !advised
+                .iter()
+                .any(|previous| previous.is_same_upgrade(advisory))
+            {
+                warn!("Upgrade advisory: {}", advisory);
+            }
+        }
 
-            // if a block is invalid, no point in trying to add the next ones
-            if result.is_err() {
-                error!("Could not add peer block {} to the blockchain", block.index);
-                return;
+        advisories
+    }
+
+    // Sync our chain with the peers: their headers first, to choose the longest valid chain,
+    // then the blocks of that chain, downloaded in parallel from the peers that have them
+    fn try_receive_new_blocks(&self) {
+        let our_height = self.blockchain.get_last_block().header.index;
+        let from = our_height.saturating_sub(FORK_DEPTH);
+
+        let mut candidates = Vec::new();
+        for address in self.peers.get_all_peers().iter() {
+            let started_at = Instant::now();
+            let headers = get_headers_from_peer(address, from);
+            self.record_result(address, started_at, headers.is_ok());
+
+            // if a peer is not working or its chain is not valid, we simply log it and ignore it
+            let candidate =
+                headers.and_then(|headers| ChainCandidate::new(&self.blockchain, address, headers));
+            match candidate {
+                Ok(Some(candidate)) => candidates.push(candidate),
+                Ok(None) => {}
+                Err(error) => error!("Could not sync headers from peer {}: {}", address, error),
             }
+        }
 
-            info!("Added new peer block {} to the blockchain", block.index);
+        if let Some(height) = candidates.iter().map(ChainCandidate::height).max() {
+            self.maintenance.record_peer_height(height);
+        }
+        // the headers are still followed during a maintenance, but no block is applied
+        if self.maintenance.is_active() {
+            return;
+        }
+
+        let chain = match select_chain(&candidates, our_height) {
+            Some(chain) => chain,
+            None => return,
+        };
+        info!(
+            "Syncing {} blocks after block {} from peer {}",
+            chain.headers.len(),
+            chain.ancestor,
+            chain.peer
+        );
+        let result = match chain.ancestor < our_height {
+            true => self.reorganize(chain, &candidates),
+            false => download_blocks(chain, &candidates, |address, blocks| {
+                self.add_new_blocks(address, &blocks)
+            }),
+        };
+        if let Err(error) = result {
+            error!("Could not sync blocks from peers: {}", error);
         }
     }
 
-    // Retrieve only the new blocks from a peer
-    fn get_new_blocks_from_peer(&self, address: &str) -> Vec<Block> {
-        // we need to know the last block index in our blockchain
-        let our_last_index = self.blockchain.get_last_block().index as usize;
+    // Replaces our blocks after the fork by the ones of a longer chain of the peers
+    // All of its blocks are downloaded first, as the chain is only replaced by a whole valid fork
+    fn reorganize(&self, chain: &ChainCandidate, candidates: &[ChainCandidate]) -> Result<()> {
+        let mut fork = Vec::new();
+        download_blocks(chain, candidates, |address, blocks| {
+            fork.extend(blocks.into_iter().map(|block| (address.to_string(), block)));
+            Ok(())
+        })?;
 
-        // we retrieve all the blocks from the peer
-        let peer_blocks = self.get_blocks_from_peer(address);
-        let peer_last_index = peer_blocks.last().unwrap().index as usize;
+        let blocks = fork.iter().map(|(_, block)| block.clone()).collect();
+        self.blockchain
+            .reorganize(chain.ancestor, blocks)
+            .map_err(|error| {
+                anyhow!(
+                    "Could not switch to the fork of peer {}: {}",
+                    chain.peer,
+                    error
+                )
+            })?;
+        for (address, block) in fork.iter() {
+            for transaction in block.body.transactions.iter() {
+                let channel = OriginChannel::PeerSync;
+                self.origins
+                    .record(transaction, channel, Some(address), None);
+            }
+        }
 
-        // Check if the peer has new blocks
-        if peer_last_index <= our_last_index {
-            return Vec::<Block>::new();
+        Ok(())
+    }
+
+    // Try to add a bunch of new blocks to our blockchain
+    // If a block is invalid, no point in trying to add the next ones
+    fn add_new_blocks(&self, address: &str, new_blocks: &[Block]) -> Result<()> {
+        for block in new_blocks.iter() {
+            self.blockchain.add_block(block.clone()).map_err(|error| {
+                anyhow!(
+                    "Could not add peer block {} to the blockchain: {}",
+                    block.header.index,
+                    error
+                )
+            })?;
+
+            for transaction in block.body.transactions.iter() {
+                let channel = OriginChannel::PeerSync;
+                self.origins
+                    .record(transaction, channel, Some(address), None);
+            }
+
+            info!(
+                "Added new peer block {} to the blockchain",
+                block.header.index
+            );
         }
 
-        // The peer do have new blocks, and we return ONLY the new ones
-        let first_new = our_last_index + 1;
-        let last_new = peer_last_index;
-        let new_blocks_range = first_new..=last_new;
-        peer_blocks.get(new_blocks_range).unwrap().to_vec()
+        Ok(())
     }
 
-    // Retrieve ALL blocks from a peer
-    fn get_blocks_from_peer(&self, address: &str) -> Vec<Block> {
-        let uri = format!("{}/blocks", address);
+    // Retrieve the list of peers known by a peer, and the version of the consensus rules it advertises
+    fn get_peers_from_peer(&self, address: &str) -> (PeerVec, Option<u32>) {
+        let uri = format!("{}/peers", address);
         let mut response = isahc::get(uri).unwrap();
 
         // check that the response is sucessful
         assert_eq!(response.status().as_u16(), 200);
 
-        // parse and return the list of blocks from the response body
+        // the nodes before the advisories don't advertise any version
+        let protocol_version = response
+            .headers()
+            .get(PROTOCOL_VERSION_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok());
+
+        // parse and return the list of peers from the response body
         let raw_body = response.text().unwrap();
-        serde_json::from_str(&raw_body).unwrap()
+        (serde_json::from_str(&raw_body).unwrap(), protocol_version)
     }
 
     // Try to broadcast all new blocks to peers since last time we broadcasted
@@ -129,18 +339,24 @@ impl Peer {
         let new_blocks = self.get_new_blocks_since(last_send_block_index);
 
         for block in new_blocks.iter() {
-            for address in self.peer_addresses.iter() {
+            for address in self.peers.get_all_peers().iter() {
                 // we don't want to panic if one peer is down or not working properly
                 let result = panic::catch_unwind(|| {
                     Peer::send_block_to_peer(address, block);
                 });
 
                 if result.is_err() {
-                    error!("Could not send block {} to peer {}", block.index, address);
+                    error!(
+                        "Could not send block {} to peer {}",
+                        block.header.index, address
+                    );
                     return;
                 }
 
-                info!("Sended new block {} to peer {}", block.index, address);
+                info!(
+                    "Sended new block {} to peer {}",
+                    block.header.index, address
+                );
             }
         }
     }
@@ -158,6 +374,7 @@ impl Peer {
 
     // Send a block to a peer using the REST API of the peer
     fn send_block_to_peer(address: &str, block: &Block) {
+        chaos::network_message(address).unwrap();
         let uri = format!("{}/blocks", address);
         let body = serde_json::to_string(&block).unwrap();
 
@@ -168,4 +385,18 @@ impl Peer {
 
         isahc::send(request).unwrap();
     }
+
+    // Send a maintenance announcement to a peer using the REST API of the peer
+    fn send_announcement_to_peer(address: &str, announcement: &MaintenanceAnnouncement) {
+        let uri = format!("{}/maintenance/announcements", address);
+        let body = serde_json::to_string(announcement).unwrap();
+
+        let request = Request::post(uri)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .unwrap();
+
+        let response = isahc::send(request).unwrap();
+        assert!(response.status().is_success());
+    }
 }
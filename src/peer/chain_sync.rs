@@ -0,0 +1,313 @@
+use std::thread;
+
+use anyhow::{anyhow, bail, Result};
+use isahc::ReadResponseExt;
+
+use crate::{
+    chaos,
+    model::{Block, BlockHash, BlockHeader, Blockchain},
+};
+
+// Our last blocks that are asked again to the peers, to find where their chains fork from ours
+// so it's also the most blocks that a reorganization can roll back
+pub const FORK_DEPTH: u64 = 100;
+// Blocks requested to a peer at once
+const BATCH_SIZE: usize = 50;
+// Batches downloaded at the same time, from different peers when possible
+const PARALLEL_BATCHES: usize = 4;
+
+// The chain of a peer, as known from its headers
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainCandidate {
+    pub peer: String,
+    // index of the last block in common with our chain
+    pub ancestor: u64,
+    // the validated headers of the peer after the common block
+    pub headers: Vec<BlockHeader>,
+}
+
+impl ChainCandidate {
+    // Builds the chain of a peer from its headers, which start at one of our blocks
+    // None if the peer sent no headers, e.g. because it's far behind us
+    pub fn new(
+        blockchain: &Blockchain,
+        peer: &str,
+        headers: Vec<BlockHeader>,
+    ) -> Result<Option<ChainCandidate>> {
+        let from = match headers.first() {
+            Some(first) => first.index,
+            None => return Ok(None),
+        };
+
+        let ours: Vec<BlockHeader> = blockchain
+            .iter_headers(from..from + headers.len() as u64)
+            .collect();
+        let common = headers
+            .iter()
+            .zip(ours.iter())
+            .take_while(|(theirs, ours)| theirs.hash == ours.hash)
+            .count();
+        if common == 0 {
+            bail!(
+                "The chain of the peer has no block in common with ours since block {}",
+                from
+            );
+        }
+
+        let ancestor = headers[common - 1].index;
+        let headers = headers[common..].to_vec();
+        blockchain.validate_headers(&headers)?;
+
+        Ok(Some(ChainCandidate {
+            peer: peer.to_string(),
+            ancestor,
+            headers,
+        }))
+    }
+
+    pub fn height(&self) -> u64 {
+        self.ancestor + self.headers.len() as u64
+    }
+
+    // Hash of the block at an index, only known after the common block
+    fn hash_at(&self, index: u64) -> Option<BlockHash> {
+        let position = index.checked_sub(self.ancestor + 1)?;
+        self.headers
+            .get(position as usize)
+            .map(|header| header.hash)
+    }
+}
+
+// Chooses the longest valid chain of the peers, as long as it's longer than ours
+// A longer chain forking from one of our blocks replaces our blocks after the fork (a reorganization)
+// On a tie, the chains that extend ours win as nothing is rolled back, then the first candidate
+// (the peers are sorted by reliability)
+pub fn select_chain(candidates: &[ChainCandidate], our_height: u64) -> Option<&ChainCandidate> {
+    candidates
+        .iter()
+        .filter(|candidate| candidate.height() > our_height)
+        .rev()
+        .max_by_key(|candidate| (candidate.height(), candidate.ancestor == our_height))
+}
+
+// Retrieve the headers of a peer from an index
+pub fn get_headers_from_peer(address: &str, from: u64) -> Result<Vec<BlockHeader>> {
+    chaos::network_message(address)?;
+    let uri = format!("{}/headers?from={}", address, from);
+    let mut response = isahc::get(uri)?;
+    if !response.status().is_success() {
+        bail!("Unexpected status {}", response.status());
+    }
+
+    Ok(serde_json::from_str(&response.text()?)?)
+}
+
+// Downloads the blocks of a chain in batches, several at the same time from the peers that have them
+// The batches are handed over in order, each time the ones downloaded together are complete
+pub fn download_blocks<F>(
+    chain: &ChainCandidate,
+    candidates: &[ChainCandidate],
+    mut apply: F,
+) -> Result<()>
+where
+    F: FnMut(&str, Vec<Block>) -> Result<()>,
+{
+    let batches: Vec<&[BlockHeader]> = chain.headers.chunks(BATCH_SIZE).collect();
+
+    for (group_number, group) in batches.chunks(PARALLEL_BATCHES).enumerate() {
+        let downloads: Vec<Result<(String, Vec<Block>)>> = thread::scope(|scope| {
+            let handles: Vec<_> = group
+                .iter()
+                .enumerate()
+                .map(|(position, batch)| {
+                    let sources = sources_for(
+                        batch,
+                        candidates,
+                        group_number * PARALLEL_BATCHES + position,
+                    );
+                    scope.spawn(move || download_from_any(&sources, batch))
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|_| Err(anyhow!("The download of a batch panicked")))
+                })
+                .collect()
+        });
+
+        for download in downloads {
+            let (address, blocks) = download?;
+            apply(&address, blocks)?;
+        }
+    }
+
+    Ok(())
+}
+
+// The peers with the same blocks as a batch, rotated so the batches are spread among them
+fn sources_for<'a>(
+    batch: &[BlockHeader],
+    candidates: &'a [ChainCandidate],
+    rotation: usize,
+) -> Vec<&'a str> {
+    let last = &batch[batch.len() - 1];
+    let mut sources: Vec<&str> = candidates
+        .iter()
+        .filter(|candidate| candidate.hash_at(last.index) == Some(last.hash))
+        .map(|candidate| candidate.peer.as_str())
+        .collect();
+
+    if !sources.is_empty() {
+        let len = sources.len();
+        sources.rotate_left(rotation % len);
+    }
+    sources
+}
+
+// Tries the peers in order until one of them sends the blocks of the batch
+fn download_from_any(sources: &[&str], batch: &[BlockHeader]) -> Result<(String, Vec<Block>)> {
+    let mut last_error = anyhow!("No peer has the blocks from {}", batch[0].index);
+    for address in sources {
+        match download_batch(address, batch) {
+            Ok(blocks) => return Ok((address.to_string(), blocks)),
+            Err(error) => last_error = error,
+        }
+    }
+
+    Err(last_error)
+}
+
+// Each block must match its validated header, so a peer cannot send other transactions
+fn download_batch(address: &str, batch: &[BlockHeader]) -> Result<Vec<Block>> {
+    chaos::network_message(address)?;
+    let uri = format!(
+        "{}/blocks?offset={}&limit={}",
+        address,
+        batch[0].index,
+        batch.len()
+    );
+    let mut response = isahc::get(uri)?;
+    if !response.status().is_success() {
+        bail!(
+            "Unexpected status {} from peer {}",
+            response.status(),
+            address
+        );
+    }
+
+    let blocks: Vec<Block> = serde_json::from_str(&response.text()?)?;
+    let matches = blocks.len() == batch.len()
+        && blocks
+            .iter()
+            .zip(batch.iter())
+            .all(|(block, header)| block.header == *header);
+    if !matches {
+        bail!("The blocks of peer {} do not match their headers", address);
+    }
+
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_find_where_the_chain_of_a_peer_forks() {
+        let blockchain = Blockchain::new(0);
+        let genesis = blockchain.get_last_block().header.clone();
+        let first = Block::new(1, 0, genesis.hash, Vec::new());
+        blockchain.add_block(first.clone()).unwrap();
+
+        // the peer has our block and one more
+        let next = Block::new(2, 0, first.header.hash, Vec::new());
+        let headers = vec![genesis.clone(), first.header.clone(), next.header.clone()];
+        let candidate = ChainCandidate::new(&blockchain, "peer", headers)
+            .unwrap()
+            .unwrap();
+        assert_eq!(candidate.ancestor, 1);
+        assert_eq!(candidate.headers, vec![next.header.clone()]);
+        assert_eq!(candidate.height(), 2);
+
+        // another peer forked after the genesis block
+        let fork = Block::new(1, 1, genesis.hash, Vec::new());
+        let fork_next = Block::new(2, 1, fork.header.hash, Vec::new());
+        let headers = vec![
+            genesis.clone(),
+            fork.header.clone(),
+            fork_next.header.clone(),
+        ];
+        let candidate = ChainCandidate::new(&blockchain, "peer", headers)
+            .unwrap()
+            .unwrap();
+        assert_eq!((candidate.ancestor, candidate.height()), (0, 2));
+
+        // but the headers after the common block must be valid
+        let mut tampered = next.header.clone();
+        tampered.nonce += 1;
+        let headers = vec![first.header.clone(), tampered];
+        assert!(ChainCandidate::new(&blockchain, "peer", headers).is_err());
+
+        // and there must be a common block
+        assert!(ChainCandidate::new(&blockchain, "peer", vec![fork.header.clone()]).is_err());
+        assert_eq!(
+            ChainCandidate::new(&blockchain, "peer", vec![]).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn should_select_the_longest_chain() {
+        let candidate = |peer: &str, ancestor, length| ChainCandidate {
+            peer: peer.to_string(),
+            ancestor,
+            headers: vec![
+                Block::new(0, 0, BlockHash::default(), Vec::new())
+                    .header
+                    .clone();
+                length
+            ],
+        };
+        let candidates = vec![
+            candidate("behind", 3, 0),
+            candidate("short", 5, 1),
+            candidate("first", 5, 3),
+            candidate("second", 5, 3),
+            candidate("fork", 2, 6),
+        ];
+
+        let selected = select_chain(&candidates, 5).unwrap();
+        assert_eq!(selected.peer, "first");
+        assert!(select_chain(&candidates[..1], 5).is_none());
+
+        // a longer fork wins, as our blocks after it can be rolled back
+        let longer_fork = candidate("longer fork", 2, 7);
+        let candidates = [candidates, vec![longer_fork]].concat();
+        assert_eq!(select_chain(&candidates, 5).unwrap().peer, "longer fork");
+    }
+
+    #[test]
+    fn should_download_from_the_peers_with_the_same_blocks() {
+        let genesis = Block::new(0, 0, BlockHash::default(), Vec::new());
+        let first = Block::new(1, 0, genesis.header.hash, Vec::new());
+        let fork = Block::new(1, 1, genesis.header.hash, Vec::new());
+        let candidate = |peer: &str, block: &Block| ChainCandidate {
+            peer: peer.to_string(),
+            ancestor: 0,
+            headers: vec![block.header.clone()],
+        };
+        let candidates = vec![
+            candidate("a", &first),
+            candidate("fork", &fork),
+            candidate("b", &first),
+        ];
+
+        let batch = [first.header.clone()];
+        assert_eq!(sources_for(&batch, &candidates, 0), vec!["a", "b"]);
+        assert_eq!(sources_for(&batch, &candidates, 1), vec!["b", "a"]);
+    }
+}
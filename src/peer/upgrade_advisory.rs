@@ -0,0 +1,192 @@
+use std::fmt;
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::model::{ProtocolActivation, PROTOCOL_VERSION};
+
+// Header of the peer exchange (GET /peers) where the nodes advertise the version of their consensus rules
+pub const PROTOCOL_VERSION_HEADER: &str = "X-Protocol-Version";
+
+// How long before the height of an unsupported version the operators are warned, about a week of blocks
+// at the default block time
+pub const ACTIVATION_WARNING_BLOCKS: u64 = 60_000;
+
+// Something the operator of the node must know to upgrade it in time
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum UpgradeAdvisory {
+    // most of the peers that advertised their version run a newer one
+    NewerPeers {
+        version: u32,
+        peers: usize,
+        known_peers: usize,
+    },
+    // the network moves to a version that this build doesn't support
+    ActivationApproaching {
+        version: u32,
+        height: u64,
+        blocks_left: u64,
+    },
+    // the network already moved to a version that this build doesn't support, the node may be on a fork
+    ActivationPassed {
+        version: u32,
+        height: u64,
+    },
+}
+
+impl UpgradeAdvisory {
+    // Whether two advisories are about the same upgrade, even if the blocks left changed since
+    pub fn is_same_upgrade(&self, other: &UpgradeAdvisory) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+            && self.version() == other.version()
+    }
+
+    pub fn version(&self) -> u32 {
+        match self {
+            UpgradeAdvisory::NewerPeers { version, .. }
+            | UpgradeAdvisory::ActivationApproaching { version, .. }
+            | UpgradeAdvisory::ActivationPassed { version, .. } => *version,
+        }
+    }
+}
+
+impl fmt::Display for UpgradeAdvisory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpgradeAdvisory::NewerPeers {
+                version,
+                peers,
+                known_peers,
+            } => write!(
+                f,
+                "{} of {} peers run the protocol version {}, this node runs {}",
+                peers, known_peers, version, PROTOCOL_VERSION
+            ),
+            UpgradeAdvisory::ActivationApproaching {
+                version,
+                height,
+                blocks_left,
+            } => write!(
+                f,
+                "The protocol version {} activates at height {} ({} blocks left), this node only supports {}",
+                version, height, blocks_left, PROTOCOL_VERSION
+            ),
+            UpgradeAdvisory::ActivationPassed { version, height } => write!(
+                f,
+                "The protocol version {} activated at height {}, this node only supports {}",
+                version, height, PROTOCOL_VERSION
+            ),
+        }
+    }
+}
+
+// The advisories for a node at a height of the chain, from the versions advertised by its peers
+// and the activations planned for its network
+pub fn upgrade_advisories(
+    peer_versions: &[u32],
+    activations: &[ProtocolActivation],
+    height: u64,
+) -> Vec<UpgradeAdvisory> {
+    let mut advisories = Vec::new();
+
+    let newer: Vec<u32> = peer_versions
+        .iter()
+        .copied()
+        .filter(|version| *version > PROTOCOL_VERSION)
+        .collect();
+    if newer.len() * 2 > peer_versions.len() {
+        advisories.push(UpgradeAdvisory::NewerPeers {
+            version: newer.iter().copied().max().unwrap_or(PROTOCOL_VERSION),
+            peers: newer.len(),
+            known_peers: peer_versions.len(),
+        });
+    }
+
+    for activation in activations
+        .iter()
+        .filter(|activation| activation.version > PROTOCOL_VERSION)
+    {
+        let blocks_left = activation.height.saturating_sub(height);
+        match blocks_left {
+            0 => advisories.push(UpgradeAdvisory::ActivationPassed {
+                version: activation.version,
+                height: activation.height,
+            }),
+            blocks_left if blocks_left <= ACTIVATION_WARNING_BLOCKS => {
+                advisories.push(UpgradeAdvisory::ActivationApproaching {
+                    version: activation.version,
+                    height: activation.height,
+                    blocks_left,
+                })
+            }
+            _ => {}
+        }
+    }
+
+    advisories
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NEXT_VERSION: u32 = PROTOCOL_VERSION + 1;
+
+    #[test]
+    fn should_warn_when_most_peers_run_a_newer_version() {
+        let advisories =
+            upgrade_advisories(&[NEXT_VERSION, NEXT_VERSION, PROTOCOL_VERSION], &[], 0);
+        assert_eq!(
+            advisories,
+            vec![UpgradeAdvisory::NewerPeers {
+                version: NEXT_VERSION,
+                peers: 2,
+                known_peers: 3
+            }]
+        );
+
+        // half of the peers is not most of them, and the older peers are not a concern
+        assert!(upgrade_advisories(&[NEXT_VERSION, PROTOCOL_VERSION], &[], 0).is_empty());
+        assert!(upgrade_advisories(&[PROTOCOL_VERSION - 1], &[], 0).is_empty());
+        assert!(upgrade_advisories(&[], &[], 0).is_empty());
+    }
+
+    #[test]
+    fn should_warn_about_the_unsupported_activations() {
+        let activations = [
+            ProtocolActivation {
+                version: PROTOCOL_VERSION,
+                height: 10,
+            },
+            ProtocolActivation {
+                version: NEXT_VERSION,
+                height: 100_000,
+            },
+        ];
+
+        assert!(upgrade_advisories(&[], &activations, 0).is_empty());
+        let approaching = upgrade_advisories(&[], &activations, 90_000);
+        assert_eq!(
+            approaching,
+            vec![UpgradeAdvisory::ActivationApproaching {
+                version: NEXT_VERSION,
+                height: 100_000,
+                blocks_left: 10_000
+            }]
+        );
+        let passed = upgrade_advisories(&[], &activations, 100_000);
+        assert!(matches!(
+            passed[..],
+            [UpgradeAdvisory::ActivationPassed {
+                height: 100_000,
+                ..
+            }]
+        ));
+
+        // the same upgrade, whatever the blocks left
+        let later = upgrade_advisories(&[], &activations, 95_000);
+        assert!(approaching[0].is_same_upgrade(&later[0]));
+        assert!(!approaching[0].is_same_upgrade(&passed[0]));
+    }
+}
@@ -0,0 +1,571 @@
+mod cluster_store;
+mod encryption;
+mod replica;
+mod sled_store;
+mod verification;
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    cluster::LeaderLease,
+    model::{
+        Block, BlockHash, BlockStats, Blockchain, ChainEvent, EventSubscription, OrphanedBlock,
+        TxPosition, COINBASE_BATCH_ID,
+    },
+    util::{
+        execution::{sleep_millis, Runnable},
+        Context,
+    },
+};
+pub use cluster_store::ClusterStore;
+pub use encryption::StorageCipher;
+pub use replica::Replica;
+pub use sled_store::SledStore;
+pub use verification::{ChainVerifier, VerificationCheckpoint, VerificationProgress};
+
+// Blocks are persisted shortly after being added, so a crash loses at most this period of blocks
+const PERSIST_POLL_MS: u64 = 100;
+// Blocks checked again by each step of the verification of the chain
+const VERIFICATION_STEP_BLOCKS: u64 = 1000;
+// Blocks sent at once in the replication stream
+pub const DEFAULT_REPLICATION_BATCH: usize = 100;
+pub const MAX_REPLICATION_BATCH: usize = 1000;
+
+// Persistent storage of the blocks of the main chain, so they survive restarts of the node
+// Blocks are appended in order of index, like in the chain, and the last ones are only removed when
+// a reorganization of the chain rolls them back
+pub trait ChainStore: Send + Sync {
+    // Appends a block with its statistics, as computed by the chain
+    fn append_block(&self, block: &Block, stats: &BlockStats) -> Result<()>;
+
+    // Removes the blocks from an index, with their hashes and statistics
+    fn truncate(&self, from: u64) -> Result<()>;
+
+    fn get_block_by_index(&self, index: u64) -> Result<Option<Block>>;
+
+    fn get_block_by_hash(&self, hash: &BlockHash) -> Result<Option<Block>>;
+
+    // The binary encoding of the blocks from an index, as stored, without decoding them
+    fn get_encoded_blocks(&self, from: u64, limit: usize) -> Result<Vec<Vec<u8>>>;
+
+    // Amount of stored blocks, including the genesis block
+    fn block_count(&self) -> Result<u64>;
+
+    // Where the events of a batch are in the stored blocks, in chain order
+    fn get_batch_positions(&self, batch_id: &str) -> Result<Vec<TxPosition>>;
+
+    // Derives the positions of the events of the batches again from the stored blocks, dropping the previous ones
+    // Returns the amount of events indexed
+    fn rebuild_batch_index(&self) -> Result<u64>;
+
+    // The statistics of the stored blocks from an index, in chain order
+    fn get_block_stats(&self, from: u64) -> Result<Vec<BlockStats>>;
+
+    // Computes the statistics of the stored blocks again, dropping the previous ones
+    // Returns the amount of blocks
+    fn rebuild_block_stats(&self) -> Result<u64>;
+
+    // The last block checked by the verification of the chain, if it was ever verified
+    fn get_checkpoint(&self) -> Result<Option<VerificationCheckpoint>>;
+
+    fn set_checkpoint(&self, checkpoint: &VerificationCheckpoint) -> Result<()>;
+
+    // Keeps a block that left the main chain, dropping the oldest ones past the retention
+    fn archive_orphaned_block(&self, orphaned: &OrphanedBlock, retention: usize) -> Result<()>;
+
+    // The archived blocks, oldest first
+    fn get_orphaned_blocks(&self) -> Result<Vec<OrphanedBlock>>;
+
+    // A store shared by the nodes of a cluster is only open on its leader, the others are always open
+    fn open(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn close(&self) {}
+}
+
+pub type SharedChainStore = Arc<dyn ChainStore>;
+
+// Adds the stored blocks that the chain is missing, validating them again like any other block
+// On startup the chain only has its genesis block. When a node takes the lead of its cluster, the blocks stored
+// by the previous leader replace the ones of the node after the last block in common, if they're a longer chain
+// (otherwise the blocks of the node are persisted over them)
+// A store of another network (e.g. other genesis or difficulty) is refused instead of being mixed with this one
+pub fn restore_chain(store: &dyn ChainStore, blockchain: &Blockchain) -> Result<u64> {
+    let count = store.block_count()?;
+    if count == 0 {
+        return Ok(0);
+    }
+
+    let genesis = store.get_block_by_index(0)?;
+    let genesis_hash = blockchain.get_block(0).map(|block| block.header.hash);
+    if genesis.map(|block| block.header.hash) != genesis_hash {
+        return Err(anyhow!("The stored chain has another genesis block"));
+    }
+    let stored_block = |index: u64| {
+        let block = store
+            .get_block_by_index(index)?
+            .ok_or_else(|| anyhow!("Missing stored block {}", index))?;
+        // a crash while appending could leave a block without its hash
+        match store.get_block_by_hash(&block.header.hash)?.is_some() {
+            true => Ok(block),
+            false => Err(anyhow!(
+                "The stored block {} cannot be found by hash",
+                index
+            )),
+        }
+    };
+
+    let height = blockchain.get_last_block().header.index;
+    let mut ancestor = height.min(count - 1);
+    while ancestor > 0
+        && store
+            .get_block_by_index(ancestor)?
+            .map(|block| block.header.hash)
+            != blockchain
+                .get_block(ancestor)
+                .map(|block| block.header.hash)
+    {
+        ancestor -= 1;
+    }
+    let restored = match (count - 1 > height, ancestor == height) {
+        (false, _) => 0,
+        (true, true) => {
+            for index in height + 1..count {
+                blockchain
+                    .add_block(stored_block(index)?)
+                    .map_err(|error| anyhow!("Invalid stored block {}: {}", index, error))?;
+            }
+            count - 1 - height
+        }
+        (true, false) => {
+            let fork = (ancestor + 1..count)
+                .map(stored_block)
+                .collect::<Result<Vec<_>>>()?;
+            blockchain.reorganize(ancestor, fork).map_err(|error| {
+                anyhow!("Invalid stored blocks after block {}: {}", ancestor, error)
+            })?;
+            count - 1 - ancestor
+        }
+    };
+    blockchain.restore_orphaned_blocks(store.get_orphaned_blocks()?);
+
+    Ok(restored)
+}
+
+// Compares the stored index of the batches with the one of the restored chain, and rebuilds it from the stored
+// blocks when they don't match, e.g. a store written by a version of the node without the index
+// Returns whether it had to be rebuilt
+pub fn check_batch_index(store: &dyn ChainStore, blockchain: &Blockchain) -> Result<bool> {
+    let index = blockchain.get_batch_index();
+    // the rewards of the miners were indexed as a batch by the previous versions
+    let batch_ids = index.batch_ids().map(String::as_str);
+    for batch_id in batch_ids.chain([COINBASE_BATCH_ID]) {
+        if store.get_batch_positions(batch_id)? != index.positions(batch_id) {
+            warn!(
+                "The stored index of the batch {} is out of date, rebuilding the index of the batches",
+                batch_id
+            );
+            let count = store.rebuild_batch_index()?;
+            info!("Indexed {} stored events of the batches", count);
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+// Loads the stored statistics of the blocks into the restored chain, rebuilding them from the stored blocks when
+// some are missing, e.g. a store written by a version of the node without them
+// Returns the amount of statistics loaded
+pub fn load_block_stats(store: &dyn ChainStore, blockchain: &Blockchain) -> Result<usize> {
+    let mut stored = store.get_block_stats(0)?;
+    if stored.len() as u64 != store.block_count()? {
+        warn!("Some statistics of the stored blocks are missing, computing them again");
+        let count = store.rebuild_block_stats()?;
+        info!("Computed the statistics of {} stored blocks", count);
+        stored = store.get_block_stats(0)?;
+    }
+
+    Ok(blockchain.load_block_stats(stored))
+}
+
+// A batch of the replication stream: the blocks from an index in their binary encoding,
+// as a bincode list of byte strings
+// Read from the store when the node has one, so serving the replicas never waits for the lock of the chain
+pub fn replication_batch(
+    store: Option<&dyn ChainStore>,
+    blockchain: &Blockchain,
+    from: u64,
+    limit: usize,
+) -> Result<Vec<u8>> {
+    let blocks = match store {
+        Some(store) => store.get_encoded_blocks(from, limit)?,
+        None => (from..from.saturating_add(limit as u64))
+            .map_while(|index| blockchain.get_block(index))
+            .map(|block| block.to_bytes())
+            .collect::<Result<_, _>>()?,
+    };
+
+    Ok(bincode::serialize(&blocks)?)
+}
+
+pub fn decode_replication_batch(bytes: &[u8]) -> Result<Vec<Block>> {
+    let blocks: Vec<Vec<u8>> = bincode::deserialize(bytes)?;
+    Ok(blocks
+        .iter()
+        .map(|block| Block::from_bytes(block))
+        .collect::<Result<_, _>>()?)
+}
+
+// Appends to the store the blocks added to the chain, whether mined or received from peers,
+// archives the ones that left the chain, and runs the steps of the verification of the chain
+// when the operator starts it
+// In a cluster, only the leader writes to the store shared by its nodes
+pub struct Storage {
+    blockchain: Blockchain,
+    store: Option<SharedChainStore>,
+    leader_lease: LeaderLease,
+    verification: ChainVerifier,
+    // the events of the blocks that left the chain
+    orphans: Mutex<EventSubscription>,
+    // the orphaned blocks that could not be archived yet, tried again on the next poll
+    pending_orphans: Mutex<VecDeque<OrphanedBlock>>,
+}
+
+impl Runnable for Storage {
+    fn run(&self) -> Result<()> {
+        self.start()
+    }
+}
+
+impl Storage {
+    pub fn new(context: &Context) -> Storage {
+        Storage {
+            blockchain: context.blockchain.clone(),
+            store: context.store.clone(),
+            leader_lease: context.leader_lease.clone(),
+            verification: context.verification.clone(),
+            orphans: Mutex::new(context.blockchain.event_bus().subscribe()),
+            pending_orphans: Mutex::default(),
+        }
+    }
+
+    pub fn start(&self) -> Result<()> {
+        match &self.store {
+            Some(_) => info!("start persisting the blocks"),
+            None => info!("No storage path, the chain is kept in memory only"),
+        }
+
+        loop {
+            // the blocks that could not be written are tried again on the next poll
+            // a leader whose lease expired in the meantime steps down instead of writing
+            if let (Some(store), true) = (&self.store, self.leader_lease.check_deadline()) {
+                if let Err(error) = persist_new_blocks(store.as_ref(), &self.blockchain) {
+                    error!("Could not persist the blocks: {}", error);
+                }
+                let events = self.orphans.lock().unwrap().drain();
+                let mut pending = self.pending_orphans.lock().unwrap();
+                if let Err(error) =
+                    archive_orphaned_blocks(store.as_ref(), &self.blockchain, events, &mut pending)
+                {
+                    error!("Could not archive the orphaned blocks: {}", error);
+                }
+            }
+            if let Err(error) = self
+                .verification
+                .step(&self.blockchain, VERIFICATION_STEP_BLOCKS)
+            {
+                error!("Could not save the progress of the verification: {}", error);
+            }
+            sleep_millis(PERSIST_POLL_MS);
+        }
+    }
+}
+
+fn persist_new_blocks(store: &dyn ChainStore, blockchain: &Blockchain) -> Result<()> {
+    // the last stored blocks may have been rolled back by a reorganization, the genesis block never is
+    let count = store.block_count()?;
+    let mut index = count;
+    while index > 1 {
+        let stored = store.get_block_by_index(index - 1)?;
+        let in_chain = blockchain.get_block(index - 1);
+        if stored.map(|block| block.header.hash) == in_chain.map(|block| block.header.hash) {
+            break;
+        }
+        index -= 1;
+    }
+    if index < count {
+        store.truncate(index)?;
+    }
+
+    while let Some((block, stats)) = blockchain.get_block_with_stats(index) {
+        store.append_block(&block, &stats)?;
+        index += 1;
+    }
+
+    Ok(())
+}
+
+// Archives the blocks that left the chain since the last poll, after the ones that could not be archived yet
+fn archive_orphaned_blocks(
+    store: &dyn ChainStore,
+    blockchain: &Blockchain,
+    events: Vec<ChainEvent>,
+    pending: &mut VecDeque<OrphanedBlock>,
+) -> Result<()> {
+    for event in events {
+        match event {
+            ChainEvent::ForkArchived(orphaned) => pending.push_back(orphaned),
+            // the chain archives the blocks rolled back without an event for each one
+            ChainEvent::ReorgOccurred(reorg) => pending.extend(
+                reorg
+                    .rolled_back
+                    .iter()
+                    .filter_map(|block| blockchain.get_orphaned_block(&block.header.hash)),
+            ),
+            _ => {}
+        }
+    }
+
+    while let Some(orphaned) = pending.front() {
+        store.archive_orphaned_block(orphaned, blockchain.orphan_limit())?;
+        pending.pop_front();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        fixtures::{alice, event, farm, mill, mine},
+        Transaction,
+    };
+
+    fn add_block(blockchain: &Blockchain) {
+        let last = blockchain.get_last_block();
+        let transaction = Transaction::coinbase(alice());
+        let block = Block::new(
+            last.header.index + 1,
+            0,
+            last.header.hash,
+            vec![transaction],
+        );
+        blockchain.add_block(block).unwrap();
+    }
+
+    #[test]
+    fn should_restore_the_persisted_chain() {
+        let path = std::env::temp_dir().join(format!("agriblock-restore-{}", std::process::id()));
+        let blockchain = Blockchain::new(0);
+        add_block(&blockchain);
+        add_block(&blockchain);
+
+        let store = SledStore::open(&path, None).unwrap();
+        persist_new_blocks(&store, &blockchain).unwrap();
+        assert_eq!(store.block_count().unwrap(), 3);
+
+        let restored = Blockchain::new(0);
+        assert_eq!(restore_chain(&store, &restored).unwrap(), 2);
+        assert_eq!(
+            restored.get_last_block().header.hash,
+            blockchain.get_last_block().header.hash
+        );
+
+        // the same blocks are refused by a chain of another network
+        assert!(restore_chain(&store, &Blockchain::new(20)).is_err());
+
+        // the blocks rolled back by a reorganization are replaced, and archived with it
+        let mut subscription = blockchain.event_bus().subscribe();
+        let genesis_hash = blockchain.get_block(0).unwrap().header.hash;
+        let first = Block::new(1, 1, genesis_hash, Vec::new());
+        let second = Block::new(2, 1, first.header.hash, Vec::new());
+        let third = Block::new(3, 1, second.header.hash, Vec::new());
+        blockchain
+            .reorganize(0, vec![first, second, third])
+            .unwrap();
+        persist_new_blocks(&store, &blockchain).unwrap();
+        let mut pending = VecDeque::new();
+        archive_orphaned_blocks(&store, &blockchain, subscription.drain(), &mut pending).unwrap();
+        assert!(pending.is_empty());
+        assert_eq!(store.block_count().unwrap(), 4);
+        let restored = Blockchain::new(0);
+        assert_eq!(restore_chain(&store, &restored).unwrap(), 3);
+        assert_eq!(
+            restored.get_last_block().header.hash,
+            blockchain.get_last_block().header.hash
+        );
+        let orphaned_blocks = restored.get_orphaned_blocks();
+        assert_eq!(orphaned_blocks.len(), 2);
+        assert_eq!(orphaned_blocks[1].fork_height, 2);
+        let reorg = orphaned_blocks[1].reorg.clone().unwrap();
+        assert_eq!((reorg.ancestor, reorg.depth), (0, 2));
+        drop(store);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn should_catch_up_with_the_stored_chain_when_taking_over() {
+        let path = std::env::temp_dir().join(format!("agriblock-take-over-{}", std::process::id()));
+        let leader = Blockchain::new(0);
+        for _ in 0..3 {
+            add_block(&leader);
+        }
+        let store = SledStore::open(&path, None).unwrap();
+        persist_new_blocks(&store, &leader).unwrap();
+
+        // a node on standby only has the genesis block
+        let standby = Blockchain::new(0);
+        assert_eq!(restore_chain(&store, &standby).unwrap(), 3);
+        assert_eq!(
+            standby.get_last_block().header.hash,
+            leader.get_last_block().header.hash
+        );
+        assert_eq!(restore_chain(&store, &standby).unwrap(), 0);
+
+        // a former leader whose last block was not followed by the cluster
+        let former = Blockchain::new(0);
+        let genesis_hash = former.get_last_block().header.hash;
+        let forked = Block::new(1, 7, genesis_hash, Vec::new());
+        former.add_block(forked.clone()).unwrap();
+        assert_eq!(restore_chain(&store, &former).unwrap(), 3);
+        assert_eq!(
+            former.get_last_block().header.hash,
+            leader.get_last_block().header.hash
+        );
+        assert!(former.get_orphaned_block(&forked.header.hash).is_some());
+
+        // a node with a longer chain keeps it, it's persisted over the stored one
+        let longer = Blockchain::new(0);
+        let mut previous_hash = longer.get_last_block().header.hash;
+        for index in 1..=4 {
+            let block = Block::new(index, 7, previous_hash, Vec::new());
+            previous_hash = block.header.hash;
+            longer.add_block(block).unwrap();
+        }
+        assert_eq!(restore_chain(&store, &longer).unwrap(), 0);
+        assert_eq!(longer.get_last_block().header.hash, previous_hash);
+
+        drop(store);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn should_rebuild_the_index_of_the_batches() {
+        let path = std::env::temp_dir().join(format!("agriblock-batches-{}", std::process::id()));
+        let blockchain = Blockchain::new(0);
+        add_block(&blockchain);
+        let harvest = event(&farm(), &mill(), "HARVEST", "WHEAT-1");
+        mine(&blockchain, &[farm().sign(harvest)]);
+        let store = SledStore::open(&path, None).unwrap();
+        persist_new_blocks(&store, &blockchain).unwrap();
+
+        // the index is kept with the blocks, without the rewards of the miners
+        let positions = blockchain.get_batch_index().positions("WHEAT-1").to_vec();
+        assert_eq!(positions, vec![(2, 0)]);
+        assert_eq!(store.get_batch_positions("WHEAT-1").unwrap(), positions);
+        assert!(store
+            .get_batch_positions(COINBASE_BATCH_ID)
+            .unwrap()
+            .is_empty());
+        assert!(!check_batch_index(&store, &blockchain).unwrap());
+
+        // and rebuilt from the stored blocks when it's lost
+        store.batches.clear().unwrap();
+        assert!(store.get_batch_positions("WHEAT-1").unwrap().is_empty());
+        assert!(check_batch_index(&store, &blockchain).unwrap());
+        assert_eq!(store.get_batch_positions("WHEAT-1").unwrap(), positions);
+
+        // the events of the blocks rolled back are removed from it
+        let genesis_hash = blockchain.get_block(0).unwrap().header.hash;
+        let first = Block::new(1, 1, genesis_hash, Vec::new());
+        let second = Block::new(2, 1, first.header.hash, Vec::new());
+        let third = Block::new(3, 1, second.header.hash, Vec::new());
+        blockchain
+            .reorganize(0, vec![first, second, third])
+            .unwrap();
+        persist_new_blocks(&store, &blockchain).unwrap();
+        assert!(store.get_batch_positions("WHEAT-1").unwrap().is_empty());
+        assert!(!check_batch_index(&store, &blockchain).unwrap());
+
+        drop(store);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn should_load_the_stored_block_stats() {
+        let path = std::env::temp_dir().join(format!("agriblock-stats-{}", std::process::id()));
+        let blockchain = Blockchain::new(0);
+        add_block(&blockchain);
+        add_block(&blockchain);
+        let store = SledStore::open(&path, None).unwrap();
+        persist_new_blocks(&store, &blockchain).unwrap();
+
+        // the statistics are stored with the blocks, as computed by the chain
+        let series = blockchain.get_block_stats(0);
+        assert_eq!(store.get_block_stats(0).unwrap(), series);
+        let restored = Blockchain::new(0);
+        restore_chain(&store, &restored).unwrap();
+        assert_eq!(load_block_stats(&store, &restored).unwrap(), 3);
+        assert_eq!(restored.get_block_stats(0), series);
+        assert_eq!(restored.get_stats_totals(), blockchain.get_stats_totals());
+
+        // and computed again from the stored blocks when they're lost
+        store.stats.clear().unwrap();
+        assert_eq!(load_block_stats(&store, &restored).unwrap(), 3);
+        assert_eq!(store.get_block_stats(1).unwrap(), series[1..]);
+
+        // the statistics of other blocks are not loaded
+        let other = Blockchain::new(0);
+        let genesis_hash = other.get_block(0).unwrap().header.hash;
+        other
+            .add_block(Block::new(1, 1, genesis_hash, Vec::new()))
+            .unwrap();
+        assert_eq!(other.load_block_stats(store.get_block_stats(0).unwrap()), 0);
+
+        // the statistics of the blocks rolled back are removed with them
+        store.truncate(2).unwrap();
+        assert_eq!(store.get_block_stats(0).unwrap(), series[..2]);
+
+        drop(store);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn should_stream_the_blocks_to_a_replica() {
+        let path = std::env::temp_dir().join(format!("agriblock-replica-{}", std::process::id()));
+        let blockchain = Blockchain::new(0);
+        add_block(&blockchain);
+        add_block(&blockchain);
+        let store = SledStore::open(&path, None).unwrap();
+        persist_new_blocks(&store, &blockchain).unwrap();
+
+        // the stored bytes and the blocks of the chain are the same stream
+        let stored = replication_batch(Some(&store), &blockchain, 1, 10).unwrap();
+        assert_eq!(stored, replication_batch(None, &blockchain, 1, 10).unwrap());
+
+        let hashes: Vec<BlockHash> = decode_replication_batch(&stored)
+            .unwrap()
+            .iter()
+            .map(|block| block.header.hash)
+            .collect();
+        let expected: Vec<BlockHash> = blockchain.get_all_blocks()[1..]
+            .iter()
+            .map(|block| block.header.hash)
+            .collect();
+        assert_eq!(hashes, expected);
+        let blocks = replication_batch(Some(&store), &blockchain, 2, 1).unwrap();
+        assert_eq!(decode_replication_batch(&blocks).unwrap().len(), 1);
+        let blocks = replication_batch(None, &blockchain, 3, 10).unwrap();
+        assert!(decode_replication_batch(&blocks).unwrap().is_empty());
+
+        drop(store);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+use std::sync::RwLock;
+
+use anyhow::{anyhow, Result};
+
+use super::{ChainStore, SledStore, StorageCipher, VerificationCheckpoint};
+use crate::model::{Block, BlockHash, BlockStats, OrphanedBlock, TxPosition};
+
+// The storage shared by the nodes of a cluster (e.g. on a network disk), only open on the leader
+// sled locks its folder for a single process, so the node opens it when it takes the lead of the cluster and
+// closes it when it loses it, for the next leader to open it
+pub struct ClusterStore {
+    path: String,
+    cipher: Option<StorageCipher>,
+    store: RwLock<Option<SledStore>>,
+}
+
+impl ClusterStore {
+    pub fn new(path: &str, cipher: Option<StorageCipher>) -> ClusterStore {
+        ClusterStore {
+            path: path.to_string(),
+            cipher,
+            store: RwLock::default(),
+        }
+    }
+
+    fn with_store<T>(&self, operation: impl FnOnce(&SledStore) -> Result<T>) -> Result<T> {
+        match self.store.read().unwrap().as_ref() {
+            Some(store) => operation(store),
+            None => Err(anyhow!(
+                "The storage is only open on the leader of the cluster"
+            )),
+        }
+    }
+}
+
+impl ChainStore for ClusterStore {
+    fn open(&self) -> Result<()> {
+        let mut store = self.store.write().unwrap();
+        if store.is_none() {
+            *store = Some(SledStore::open(&self.path, self.cipher.clone())?);
+        }
+
+        Ok(())
+    }
+
+    fn close(&self) {
+        *self.store.write().unwrap() = None;
+    }
+
+    fn append_block(&self, block: &Block, stats: &BlockStats) -> Result<()> {
+        self.with_store(|store| store.append_block(block, stats))
+    }
+
+    fn truncate(&self, from: u64) -> Result<()> {
+        self.with_store(|store| store.truncate(from))
+    }
+
+    fn get_block_by_index(&self, index: u64) -> Result<Option<Block>> {
+        self.with_store(|store| store.get_block_by_index(index))
+    }
+
+    fn get_block_by_hash(&self, hash: &BlockHash) -> Result<Option<Block>> {
+        self.with_store(|store| store.get_block_by_hash(hash))
+    }
+
+    fn get_encoded_blocks(&self, from: u64, limit: usize) -> Result<Vec<Vec<u8>>> {
+        self.with_store(|store| store.get_encoded_blocks(from, limit))
+    }
+
+    fn block_count(&self) -> Result<u64> {
+        self.with_store(|store| store.block_count())
+    }
+
+    fn get_batch_positions(&self, batch_id: &str) -> Result<Vec<TxPosition>> {
+        self.with_store(|store| store.get_batch_positions(batch_id))
+    }
+
+    fn rebuild_batch_index(&self) -> Result<u64> {
+        self.with_store(|store| store.rebuild_batch_index())
+    }
+
+    fn get_block_stats(&self, from: u64) -> Result<Vec<BlockStats>> {
+        self.with_store(|store| store.get_block_stats(from))
+    }
+
+    fn rebuild_block_stats(&self) -> Result<u64> {
+        self.with_store(|store| store.rebuild_block_stats())
+    }
+
+    fn get_checkpoint(&self) -> Result<Option<VerificationCheckpoint>> {
+        self.with_store(|store| store.get_checkpoint())
+    }
+
+    fn set_checkpoint(&self, checkpoint: &VerificationCheckpoint) -> Result<()> {
+        self.with_store(|store| store.set_checkpoint(checkpoint))
+    }
+
+    fn archive_orphaned_block(&self, orphaned: &OrphanedBlock, retention: usize) -> Result<()> {
+        self.with_store(|store| store.archive_orphaned_block(orphaned, retention))
+    }
+
+    fn get_orphaned_blocks(&self) -> Result<Vec<OrphanedBlock>> {
+        self.with_store(|store| store.get_orphaned_blocks())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Blockchain;
+
+    #[test]
+    fn should_only_be_used_once_open() {
+        let path = std::env::temp_dir().join(format!("agriblock-cluster-{}", std::process::id()));
+        let store = ClusterStore::new(path.to_str().unwrap(), None);
+        let (genesis, stats) = Blockchain::new(0).get_block_with_stats(0).unwrap();
+
+        assert!(store.append_block(&genesis, &stats).is_err());
+        store.open().unwrap();
+        store.append_block(&genesis, &stats).unwrap();
+        assert_eq!(store.block_count().unwrap(), 1);
+        store.close();
+        assert!(store.block_count().is_err());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,142 @@
+use std::process::Command;
+
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Nonce,
+};
+use anyhow::{anyhow, bail, Result};
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use sha2::Sha256;
+
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+// Derives the key of the digests from the key of the storage, so the key of AES is never used for anything else
+const DIGEST_KEY_LABEL: &[u8] = b"agriblock-storage-digest";
+
+// Encrypts the values written to the storage with AES-256-GCM, for the nodes on shared or cloud disks
+// Every value has a random nonce in front of it and is bound to its key in the database,
+// so a value cannot be moved to another key (e.g. a block to another index) without being detected
+// The keys of the database that would reveal data (e.g. the batch ids of the index of the batches) are keyed digests
+#[derive(Clone)]
+pub struct StorageCipher {
+    cipher: Aes256Gcm,
+    digest_key: [u8; KEY_LEN],
+}
+
+impl StorageCipher {
+    pub fn new(key: &[u8]) -> Result<StorageCipher> {
+        if key.len() != KEY_LEN {
+            bail!("The storage encryption key must be 32 bytes in hex");
+        }
+        let cipher = Aes256Gcm::new_from_slice(key)
+            .map_err(|_| anyhow!("Invalid storage encryption key"))?;
+        let digest_key = hmac_sha256(key, DIGEST_KEY_LABEL);
+
+        Ok(StorageCipher { cipher, digest_key })
+    }
+
+    // The key of the configuration, given in hex or printed by a command (e.g. the client of a KMS)
+    // The storage is not encrypted when there is neither
+    pub fn from_config(encryption_key: &str, key_command: &str) -> Result<Option<StorageCipher>> {
+        let key = match (encryption_key.is_empty(), key_command.is_empty()) {
+            (true, true) => return Ok(None),
+            (false, false) => {
+                bail!("Set either STORAGE_ENCRYPTION_KEY or STORAGE_KEY_COMMAND, not both")
+            }
+            (false, true) => encryption_key.to_string(),
+            (true, false) => run_key_command(key_command)?,
+        };
+        let key = hex::decode(key.trim())
+            .map_err(|_| anyhow!("The storage encryption key must be 32 bytes in hex"))?;
+
+        StorageCipher::new(&key).map(Some)
+    }
+
+    pub fn encrypt(&self, key: &[u8], value: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let payload = Payload {
+            msg: value,
+            aad: key,
+        };
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_| anyhow!("Cannot encrypt the stored value"))?;
+
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    // HMAC of a value, the same for the same value and key of the storage, but meaningless without the key
+    pub fn digest(&self, value: &[u8]) -> [u8; 32] {
+        hmac_sha256(&self.digest_key, value)
+    }
+
+    pub fn decrypt(&self, key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+        if data.len() < NONCE_LEN {
+            bail!("The stored value is not encrypted");
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: key,
+        };
+
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| anyhow!("Wrong storage encryption key, or the stored value was modified"))
+    }
+}
+
+fn hmac_sha256(key: &[u8], value: &[u8]) -> [u8; 32] {
+    // HMAC accepts keys of any length
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key");
+    mac.update(value);
+    mac.finalize().into_bytes().into()
+}
+
+// Runs the command of the configuration in a shell, the key is what it prints
+fn run_key_command(command: &str) -> Result<String> {
+    let output = Command::new("sh").arg("-c").arg(command).output()?;
+    if !output.status.success() {
+        bail!(
+            "The storage key command failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    String::from_utf8(output.stdout)
+        .map_err(|_| anyhow!("The storage key command did not print a key in hex"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_read_the_key_from_the_configuration() {
+        let key = "11".repeat(KEY_LEN);
+        assert!(StorageCipher::from_config("", "").unwrap().is_none());
+
+        let command = format!("echo {}", key);
+        let from_command = StorageCipher::from_config("", &command).unwrap().unwrap();
+        let from_key = StorageCipher::from_config(&key, "").unwrap().unwrap();
+        let encrypted = from_command.encrypt(b"key", b"value").unwrap();
+        assert_eq!(from_key.decrypt(b"key", &encrypted).unwrap(), b"value");
+
+        // the value is bound to its key
+        assert!(from_key.decrypt(b"other key", &encrypted).is_err());
+
+        // the digests only depend on the value and the key of the storage
+        assert_eq!(from_key.digest(b"batch"), from_command.digest(b"batch"));
+        assert_ne!(from_key.digest(b"batch"), from_key.digest(b"other batch"));
+        let other_key = StorageCipher::new(&[2; KEY_LEN]).unwrap();
+        assert_ne!(from_key.digest(b"batch"), other_key.digest(b"batch"));
+
+        assert!(StorageCipher::from_config("11", "").is_err());
+        assert!(StorageCipher::from_config(&key, &command).is_err());
+        assert!(StorageCipher::from_config("", "exit 1").is_err());
+    }
+}
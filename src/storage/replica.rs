@@ -0,0 +1,78 @@
+use anyhow::{anyhow, bail, Result};
+use isahc::ReadResponseExt;
+
+use super::{decode_replication_batch, MAX_REPLICATION_BATCH};
+use crate::{
+    model::Blockchain,
+    util::{
+        execution::{sleep_millis, Runnable},
+        Context,
+    },
+};
+
+// Follows the chain of a primary node through its replication stream, on a node that only serves reads
+// (e.g. the exports and analytical queries of a cooperative), so they never contend with the writes of the primary
+// The blocks are validated again like the ones of any peer, so a replica never trusts its primary blindly
+pub struct Replica {
+    primary: String,
+    blockchain: Blockchain,
+    poll_ms: u64,
+}
+
+impl Runnable for Replica {
+    fn run(&self) -> Result<()> {
+        self.start()
+    }
+}
+
+impl Replica {
+    pub fn new(context: &Context) -> Replica {
+        Replica {
+            primary: context.config.replica_of.trim_end_matches('/').to_string(),
+            blockchain: context.blockchain.clone(),
+            poll_ms: context.config.replication_poll_ms,
+        }
+    }
+
+    pub fn start(&self) -> Result<()> {
+        if self.primary.is_empty() {
+            info!("Not a read replica, exiting replication system");
+            return Ok(());
+        }
+
+        info!("start replicating the chain of {}", self.primary);
+        loop {
+            // the primary may be restarting, so the replica just tries again later
+            match self.replicate_new_blocks() {
+                Ok(0) => sleep_millis(self.poll_ms),
+                Ok(count) => info!("Replicated {} blocks from {}", count, self.primary),
+                Err(error) => {
+                    error!("Could not replicate from {}: {}", self.primary, error);
+                    sleep_millis(self.poll_ms);
+                }
+            }
+        }
+    }
+
+    // Adds the next batch of blocks of the primary, returning how many were added
+    fn replicate_new_blocks(&self) -> Result<usize> {
+        let from = self.blockchain.get_last_block().header.index + 1;
+        let uri = format!(
+            "{}/replication/blocks?from={}&limit={}",
+            self.primary, from, MAX_REPLICATION_BATCH
+        );
+        let mut response = isahc::get(uri)?;
+        if !response.status().is_success() {
+            bail!("Unexpected status {}", response.status());
+        }
+
+        let blocks = decode_replication_batch(&response.bytes()?)?;
+        for block in blocks.iter() {
+            self.blockchain
+                .add_block(block.clone())
+                .map_err(|error| anyhow!("Invalid block {}: {}", block.header.index, error))?;
+        }
+
+        Ok(blocks.len())
+    }
+}
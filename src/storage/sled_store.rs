@@ -0,0 +1,505 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use sled::{
+    transaction::{ConflictableTransactionError, TransactionError},
+    Transactional,
+};
+
+use super::{ChainStore, StorageCipher, VerificationCheckpoint};
+use crate::{
+    chaos,
+    model::{Block, BlockHash, BlockStats, OrphanedBlock, TxPosition},
+};
+
+// Key of the checkpoint of the verification in the default tree
+const CHECKPOINT_KEY: &[u8] = b"verification-checkpoint";
+// Key of a known value encrypted with the key of the storage, to refuse another key before reading any block
+const ENCRYPTION_CHECK_KEY: &[u8] = b"encryption-check";
+const ENCRYPTION_CHECK_VALUE: &[u8] = b"agriblock";
+
+// Stores the blocks in an embedded sled database, one tree with the blocks by index,
+// another one with the index of each block hash and another one with the positions of the events of each batch
+// The statistics of the blocks are in a tree by index, like the blocks, and the orphaned blocks are in a tree
+// of their own, in the order they were archived
+// With a cipher, the blocks, their statistics, the orphaned blocks and the checkpoint are encrypted, only the indexes
+// and the hashes of the blocks (which are public in the chain) are in clear. The batch ids of the index of the batches
+// are replaced by their keyed digests, so the index works without revealing them
+pub struct SledStore {
+    db: sled::Db,
+    blocks: sled::Tree,
+    hashes: sled::Tree,
+    // visible to the tests of the storage, to lose the index like a store written before it existed
+    pub(super) batches: sled::Tree,
+    // visible to the tests of the storage, like the index of the batches
+    pub(super) stats: sled::Tree,
+    orphans: sled::Tree,
+    cipher: Option<StorageCipher>,
+}
+
+impl SledStore {
+    // Opens the database in a folder, creating it if it does not exist yet
+    // A database is encrypted from its creation or never, the blocks are not encrypted again afterwards
+    pub fn open<P: AsRef<Path>>(path: P, cipher: Option<StorageCipher>) -> Result<SledStore> {
+        let db = sled::open(path)?;
+        let blocks = db.open_tree("blocks")?;
+        let hashes = db.open_tree("hashes")?;
+        let batches = db.open_tree("batches")?;
+        let stats = db.open_tree("stats")?;
+        let orphans = db.open_tree("orphans")?;
+
+        let store = SledStore {
+            db,
+            blocks,
+            hashes,
+            batches,
+            stats,
+            orphans,
+            cipher,
+        };
+        store.check_cipher()?;
+
+        Ok(store)
+    }
+
+    fn check_cipher(&self) -> Result<()> {
+        let check = self.db.get(ENCRYPTION_CHECK_KEY)?;
+        let is_empty = self.blocks.is_empty() && self.db.get(CHECKPOINT_KEY)?.is_none();
+        match (&self.cipher, check) {
+            (Some(cipher), Some(check)) => {
+                cipher.decrypt(ENCRYPTION_CHECK_KEY, &check)?;
+            }
+            (Some(cipher), None) if is_empty => {
+                let check = cipher.encrypt(ENCRYPTION_CHECK_KEY, ENCRYPTION_CHECK_VALUE)?;
+                self.db.insert(ENCRYPTION_CHECK_KEY, check)?;
+                self.db.flush()?;
+            }
+            (Some(_), None) => bail!("The storage was created without encryption"),
+            (None, Some(_)) => bail!("The storage is encrypted, its key is missing"),
+            (None, None) => {}
+        }
+
+        Ok(())
+    }
+
+    // The value as written to the database, encrypted with its key when there is a cipher
+    fn seal(&self, key: &[u8], value: Vec<u8>) -> Result<Vec<u8>> {
+        match &self.cipher {
+            Some(cipher) => cipher.encrypt(key, &value),
+            None => Ok(value),
+        }
+    }
+
+    fn unseal(&self, key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+        match &self.cipher {
+            Some(cipher) => cipher.decrypt(key, data),
+            None => Ok(data.to_vec()),
+        }
+    }
+
+    // The statistics are encrypted with their own key, so they cannot be swapped with the block of the same index
+    fn stats_key(index_key: &[u8]) -> Vec<u8> {
+        [b"stats".as_slice(), index_key].concat()
+    }
+
+    fn hash_key(hash: &BlockHash) -> Vec<u8> {
+        let mut key = vec![0; 32];
+        hash.to_big_endian(&mut key);
+        key
+    }
+
+    // The length of the batch id goes first, so the keys of a batch are never mixed with a longer id
+    // that starts the same, and big endian positions keep them in chain order
+    // With a cipher, the digest of the batch id replaces it
+    fn batch_prefix(&self, batch_id: &str) -> Vec<u8> {
+        let id = match &self.cipher {
+            Some(cipher) => cipher.digest(batch_id.as_bytes()).to_vec(),
+            None => batch_id.as_bytes().to_vec(),
+        };
+        let mut key = (id.len() as u32).to_be_bytes().to_vec();
+        key.extend_from_slice(&id);
+        key
+    }
+
+    fn batch_keys(&self, block: &Block) -> Vec<Vec<u8>> {
+        block
+            .body
+            .transactions
+            .iter()
+            .enumerate()
+            // the same events as the index of the chain, without the reward of the miner
+            .filter(|(_, transaction)| !transaction.is_coinbase())
+            .map(|(position, transaction)| {
+                let mut key = self.batch_prefix(&transaction.batch_id);
+                key.extend_from_slice(&block.header.index.to_be_bytes());
+                key.extend_from_slice(&(position as u32).to_be_bytes());
+                key
+            })
+            .collect()
+    }
+}
+
+impl ChainStore for SledStore {
+    fn append_block(&self, block: &Block, stats: &BlockStats) -> Result<()> {
+        let count = self.block_count()?;
+        if block.header.index != count {
+            bail!(
+                "Cannot store the block {}, the next index is {}",
+                block.header.index,
+                count
+            );
+        }
+
+        // big endian keys keep the blocks sorted by index
+        let index_key = block.header.index.to_be_bytes();
+        let data = self.seal(&index_key, block.to_bytes()?)?;
+        let stats_data = self.seal(&Self::stats_key(&index_key), bincode::serialize(stats)?)?;
+        let hash_key = Self::hash_key(&block.header.hash);
+        let batch_keys = self.batch_keys(block);
+        // all or none, a block stored without its hash would stop the chain from being restored
+        let result = (&self.blocks, &self.hashes, &self.batches, &self.stats).transaction(
+            |(blocks, hashes, batches, stats)| {
+                blocks.insert(&index_key, data.as_slice())?;
+                chaos::storage_write().map_err(ConflictableTransactionError::Abort)?;
+                hashes.insert(hash_key.as_slice(), &index_key)?;
+                for key in batch_keys.iter() {
+                    batches.insert(key.as_slice(), &[])?;
+                }
+                stats.insert(&index_key, stats_data.as_slice())?;
+                Ok(())
+            },
+        );
+        match result {
+            Ok(()) => {}
+            Err(TransactionError::Abort(error)) => return Err(error),
+            Err(TransactionError::Storage(error)) => return Err(error.into()),
+        }
+        // the block must be on disk before the next one is appended
+        self.db.flush()?;
+
+        Ok(())
+    }
+
+    fn truncate(&self, from: u64) -> Result<()> {
+        let mut removed = Vec::new();
+        for entry in self.blocks.range(from.to_be_bytes()..) {
+            let (index_key, data) = entry?;
+            let block = Block::from_bytes(&self.unseal(&index_key, &data)?)?;
+            removed.push((
+                index_key,
+                Self::hash_key(&block.header.hash),
+                self.batch_keys(&block),
+            ));
+        }
+
+        let result = (&self.blocks, &self.hashes, &self.batches, &self.stats).transaction(
+            |(blocks, hashes, batches, stats)| {
+                for (index_key, hash_key, batch_keys) in removed.iter() {
+                    blocks.remove(index_key)?;
+                    hashes.remove(hash_key.as_slice())?;
+                    for key in batch_keys.iter() {
+                        batches.remove(key.as_slice())?;
+                    }
+                    stats.remove(index_key)?;
+                }
+                Ok::<(), ConflictableTransactionError<anyhow::Error>>(())
+            },
+        );
+        match result {
+            Ok(()) => {}
+            Err(TransactionError::Abort(error)) => return Err(error),
+            Err(TransactionError::Storage(error)) => return Err(error.into()),
+        }
+        self.db.flush()?;
+
+        Ok(())
+    }
+
+    fn get_block_by_index(&self, index: u64) -> Result<Option<Block>> {
+        let index_key = index.to_be_bytes();
+        match self.blocks.get(index_key)? {
+            Some(data) => Ok(Some(Block::from_bytes(&self.unseal(&index_key, &data)?)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn get_block_by_hash(&self, hash: &BlockHash) -> Result<Option<Block>> {
+        match self.hashes.get(Self::hash_key(hash))? {
+            Some(index) => {
+                let index = u64::from_be_bytes(index.as_ref().try_into()?);
+                self.get_block_by_index(index)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn get_encoded_blocks(&self, from: u64, limit: usize) -> Result<Vec<Vec<u8>>> {
+        self.blocks
+            .range(from.to_be_bytes()..)
+            .take(limit)
+            .map(|entry| {
+                let (index_key, data) = entry?;
+                self.unseal(&index_key, &data)
+            })
+            .collect()
+    }
+
+    fn block_count(&self) -> Result<u64> {
+        match self.blocks.last()? {
+            Some((key, _)) => Ok(u64::from_be_bytes(key.as_ref().try_into()?) + 1),
+            None => Ok(0),
+        }
+    }
+
+    fn get_batch_positions(&self, batch_id: &str) -> Result<Vec<TxPosition>> {
+        let prefix = self.batch_prefix(batch_id);
+        self.batches
+            .scan_prefix(&prefix)
+            .keys()
+            .map(|key| {
+                let key = key?;
+                let (index, position) = key[prefix.len()..].split_at(8);
+                Ok((
+                    u64::from_be_bytes(index.try_into()?),
+                    u32::from_be_bytes(position.try_into()?) as usize,
+                ))
+            })
+            .collect()
+    }
+
+    fn rebuild_batch_index(&self) -> Result<u64> {
+        self.batches.clear()?;
+        let mut count = 0;
+        for entry in self.blocks.iter() {
+            let (index_key, data) = entry?;
+            let block = Block::from_bytes(&self.unseal(&index_key, &data)?)?;
+            for key in self.batch_keys(&block) {
+                self.batches.insert(key, &[])?;
+                count += 1;
+            }
+        }
+        self.db.flush()?;
+
+        Ok(count)
+    }
+
+    fn get_block_stats(&self, from: u64) -> Result<Vec<BlockStats>> {
+        self.stats
+            .range(from.to_be_bytes()..)
+            .map(|entry| {
+                let (index_key, data) = entry?;
+                let data = self.unseal(&Self::stats_key(&index_key), &data)?;
+                Ok(bincode::deserialize(&data)?)
+            })
+            .collect()
+    }
+
+    fn rebuild_block_stats(&self) -> Result<u64> {
+        self.stats.clear()?;
+        let mut previous: Option<Block> = None;
+        for entry in self.blocks.iter() {
+            let (index_key, data) = entry?;
+            let block = Block::from_bytes(&self.unseal(&index_key, &data)?)?;
+            let stats = BlockStats::new(&block, previous.as_ref());
+            let data = self.seal(&Self::stats_key(&index_key), bincode::serialize(&stats)?)?;
+            self.stats.insert(index_key, data)?;
+            previous = Some(block);
+        }
+        self.db.flush()?;
+
+        Ok(self.stats.len() as u64)
+    }
+
+    fn get_checkpoint(&self) -> Result<Option<VerificationCheckpoint>> {
+        match self.db.get(CHECKPOINT_KEY)? {
+            Some(data) => Ok(Some(bincode::deserialize(
+                &self.unseal(CHECKPOINT_KEY, &data)?,
+            )?)),
+            None => Ok(None),
+        }
+    }
+
+    fn set_checkpoint(&self, checkpoint: &VerificationCheckpoint) -> Result<()> {
+        chaos::storage_write()?;
+        let data = self.seal(CHECKPOINT_KEY, bincode::serialize(checkpoint)?)?;
+        self.db.insert(CHECKPOINT_KEY, data)?;
+        self.db.flush()?;
+
+        Ok(())
+    }
+
+    fn archive_orphaned_block(&self, orphaned: &OrphanedBlock, retention: usize) -> Result<()> {
+        chaos::storage_write()?;
+        let sequence = match self.orphans.last()? {
+            Some((key, _)) => u64::from_be_bytes(key.as_ref().try_into()?) + 1,
+            None => 0,
+        };
+        let sequence_key = sequence.to_be_bytes();
+        let data = self.seal(&sequence_key, orphaned.to_bytes()?)?;
+        self.orphans.insert(sequence_key, data)?;
+        while self.orphans.len() > retention {
+            self.orphans.pop_min()?;
+        }
+        self.db.flush()?;
+
+        Ok(())
+    }
+
+    fn get_orphaned_blocks(&self) -> Result<Vec<OrphanedBlock>> {
+        self.orphans
+            .iter()
+            .map(|entry| {
+                let (sequence_key, data) = entry?;
+                Ok(OrphanedBlock::from_bytes(
+                    &self.unseal(&sequence_key, &data)?,
+                )?)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{fixtures::alice, Blockchain, OrphanReason, Transaction};
+
+    // sled releases the lock of the database from a background thread, shortly after it's dropped
+    fn reopen(path: &Path, cipher: impl Fn() -> Option<StorageCipher>) -> Result<SledStore> {
+        for _ in 0..50 {
+            match SledStore::open(path, cipher()) {
+                Err(error) if error.to_string().contains("lock") => {
+                    std::thread::sleep(std::time::Duration::from_millis(100))
+                }
+                result => return result,
+            }
+        }
+        SledStore::open(path, cipher())
+    }
+
+    #[test]
+    fn should_find_the_stored_blocks() {
+        let path = std::env::temp_dir().join(format!("agriblock-sled-{}", std::process::id()));
+        let genesis = Blockchain::new(0).get_last_block();
+        let block = Block::new(1, 0, genesis.header.hash, Vec::new());
+        let stats = BlockStats::new(&block, Some(&genesis));
+
+        {
+            let store = SledStore::open(&path, None).unwrap();
+            store
+                .append_block(&genesis, &BlockStats::new(&genesis, None))
+                .unwrap();
+            store.append_block(&block, &stats).unwrap();
+            // blocks are only appended in order
+            assert!(store.append_block(&block, &stats).is_err());
+            assert_eq!(store.get_checkpoint().unwrap(), None);
+            let checkpoint = VerificationCheckpoint {
+                index: 1,
+                hash: block.header.hash,
+            };
+            store.set_checkpoint(&checkpoint).unwrap();
+        }
+
+        // the blocks are still there after opening the database again
+        let store = reopen(&path, || None).unwrap();
+        assert_eq!(store.block_count().unwrap(), 2);
+        assert_eq!(store.get_checkpoint().unwrap().unwrap().index, 1);
+        let stored = store.get_block_by_index(1).unwrap().unwrap();
+        assert_eq!(stored.header.hash, block.header.hash);
+        let stored = store
+            .get_block_by_hash(&genesis.header.hash)
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.header.index, 0);
+        assert!(store.get_block_by_index(2).unwrap().is_none());
+        let encoded = store.get_encoded_blocks(1, 10).unwrap();
+        assert_eq!(encoded, vec![block.to_bytes().unwrap()]);
+        assert_eq!(store.get_block_stats(1).unwrap(), vec![stats]);
+        assert!(store
+            .get_block_by_hash(&BlockHash::zero())
+            .unwrap()
+            .is_none());
+
+        // only the most recent orphaned blocks are kept
+        for nonce in 1..=3 {
+            let fork = Block::new(1, nonce, genesis.header.hash, Vec::new());
+            let orphaned = OrphanedBlock::new(fork, OrphanReason::StaleFork, block.header.hash);
+            store.archive_orphaned_block(&orphaned, 2).unwrap();
+        }
+        let nonces: Vec<u64> = store
+            .get_orphaned_blocks()
+            .unwrap()
+            .iter()
+            .map(|orphaned| orphaned.block.header.nonce)
+            .collect();
+        assert_eq!(nonces, vec![2, 3]);
+
+        drop(store);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn should_encrypt_the_stored_blocks() {
+        let path = std::env::temp_dir().join(format!("agriblock-encrypted-{}", std::process::id()));
+        let key = |byte: u8| move || Some(StorageCipher::new(&[byte; 32]).unwrap());
+        let genesis = Blockchain::new(0).get_last_block();
+        let transaction = Transaction {
+            recipient: alice(),
+            batch_id: "WHEAT-SECRET-001".to_string(),
+            event_type: "HARVEST".into(),
+            ..Default::default()
+        };
+        let block = Block::new(1, 0, genesis.header.hash, vec![transaction]);
+        let stats = BlockStats::new(&block, Some(&genesis));
+
+        {
+            let store = SledStore::open(&path, key(1)()).unwrap();
+            store
+                .append_block(&genesis, &BlockStats::new(&genesis, None))
+                .unwrap();
+            store.append_block(&block, &stats).unwrap();
+
+            // the payloads are not in clear on the disk
+            let stored = store.blocks.get(1u64.to_be_bytes()).unwrap().unwrap();
+            assert!(!stored
+                .windows("WHEAT-SECRET-001".len())
+                .any(|window| window == b"WHEAT-SECRET-001"));
+            // nor are the batch ids of their index
+            for key in store.batches.iter().keys() {
+                assert!(!key
+                    .unwrap()
+                    .windows("WHEAT-SECRET-001".len())
+                    .any(|window| window == b"WHEAT-SECRET-001"));
+            }
+            assert_eq!(
+                store.get_batch_positions("WHEAT-SECRET-001").unwrap(),
+                vec![(1, 0)]
+            );
+            assert_eq!(
+                store.get_encoded_blocks(1, 1).unwrap(),
+                vec![block.to_bytes().unwrap()]
+            );
+        }
+
+        // the storage cannot be opened without its key, nor with another one
+        assert!(reopen(&path, || None).is_err());
+        assert!(reopen(&path, key(2)).is_err());
+        let store = reopen(&path, key(1)).unwrap();
+        let stored = store
+            .get_block_by_hash(&block.header.hash)
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.body.transactions[0].batch_id, "WHEAT-SECRET-001");
+        drop(store);
+        std::fs::remove_dir_all(&path).unwrap();
+
+        // nor can a key be added to a storage created without one
+        let store = SledStore::open(&path, None).unwrap();
+        store
+            .append_block(&genesis, &BlockStats::new(&genesis, None))
+            .unwrap();
+        drop(store);
+        assert!(reopen(&path, key(1)).is_err());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,269 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::SharedChainStore;
+use crate::model::{BlockHash, Blockchain};
+
+// The last block checked by the verification of the chain, so the next runs start after it
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct VerificationCheckpoint {
+    pub index: u64,
+    pub hash: BlockHash,
+}
+
+#[derive(Debug, Clone, Default)]
+struct VerificationState {
+    checkpoint: Option<VerificationCheckpoint>,
+    running: bool,
+    // first block checked by the current or the last run
+    started_from: Option<u64>,
+    // the first invalid block found and why, it stops the run
+    failure: Option<(u64, String)>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct VerificationProgress {
+    pub running: bool,
+    // blocks checked so far, from the genesis
+    pub verified_blocks: u64,
+    pub total_blocks: u64,
+    // from 0 to 100
+    pub percent: f64,
+    pub started_from: Option<u64>,
+    pub invalid_block: Option<u64>,
+    pub reason: Option<String>,
+}
+
+// Verification of a long chain a few blocks at a time, which can be interrupted and resumed
+// The last block checked is saved in the storage of the node (if any) after each step, so the verification also
+// resumes after a restart, and a run once the chain is verified only checks the blocks added since then
+// Shared between the api, that starts and interrupts it, and the storage process that runs its steps
+#[derive(Clone)]
+pub struct ChainVerifier {
+    state: Arc<Mutex<VerificationState>>,
+    store: Option<SharedChainStore>,
+}
+
+impl ChainVerifier {
+    pub fn new(store: Option<SharedChainStore>) -> ChainVerifier {
+        ChainVerifier {
+            state: Arc::default(),
+            store,
+        }
+    }
+
+    // Loads the checkpoint saved by the previous runs
+    pub fn load(&self) -> Result<()> {
+        if let Some(store) = &self.store {
+            self.state.lock().unwrap().checkpoint = store.get_checkpoint()?;
+        }
+
+        Ok(())
+    }
+
+    // Starts a run from the last block checked, or resumes the one interrupted
+    pub fn start(&self, blockchain: &Blockchain) -> VerificationProgress {
+        let mut state = self.state.lock().unwrap();
+        if !state.running {
+            state.running = true;
+            state.started_from = Some(resume_index(&state, blockchain));
+            state.failure = None;
+        }
+
+        progress(&state, blockchain)
+    }
+
+    // Stops the run after the current step, the blocks checked so far are kept
+    pub fn interrupt(&self, blockchain: &Blockchain) -> VerificationProgress {
+        let mut state = self.state.lock().unwrap();
+        state.running = false;
+
+        progress(&state, blockchain)
+    }
+
+    pub fn progress(&self, blockchain: &Blockchain) -> VerificationProgress {
+        progress(&self.state.lock().unwrap(), blockchain)
+    }
+
+    // Checks at most a number of blocks of the current run, and saves how far it got
+    // The blocks are checked on a copy of the state, so the progress can be read and the run interrupted
+    // in the meantime
+    pub fn step(&self, blockchain: &Blockchain, max_blocks: u64) -> Result<()> {
+        let mut run = match self.state.lock().unwrap().clone() {
+            run if run.running => run,
+            _ => return Ok(()),
+        };
+
+        let height = blockchain.get_last_block().header.index;
+        let from = resume_index(&run, blockchain);
+        let until = height.min(from + max_blocks.max(1) - 1);
+        for header in blockchain.iter_headers(from..until + 1) {
+            if let Err(error) = blockchain.validate_block_at(header.index) {
+                warn!(
+                    "The verification found the invalid block {}: {}",
+                    header.index, error
+                );
+                run.failure = Some((header.index, error.to_string()));
+                run.running = false;
+                break;
+            }
+            run.checkpoint = Some(VerificationCheckpoint {
+                index: header.index,
+                hash: header.hash,
+            });
+        }
+        if run.running && until == height {
+            info!("Verified the chain up to block {}", height);
+            run.running = false;
+        }
+
+        // an interruption during the step is kept, along with the blocks checked until then
+        let mut state = self.state.lock().unwrap();
+        state.checkpoint = run.checkpoint;
+        state.failure = run.failure;
+        state.running &= run.running;
+
+        match (&self.store, state.checkpoint) {
+            (Some(store), Some(checkpoint)) => store.set_checkpoint(&checkpoint),
+            _ => Ok(()),
+        }
+    }
+}
+
+// The block after the checkpoint, or after the last block that the main chain still shares with the blocks
+// checked when a reorganization rolled back the checkpoint
+// The blocks rolled back are followed through the archive of the orphaned blocks, from the genesis when they
+// are not there anymore (e.g. after a restart)
+fn resume_index(state: &VerificationState, blockchain: &Blockchain) -> u64 {
+    let mut checkpoint = match state.checkpoint {
+        Some(checkpoint) => checkpoint,
+        None => return 0,
+    };
+
+    loop {
+        let on_main_chain = blockchain
+            .iter_headers(checkpoint.index..checkpoint.index + 1)
+            .next()
+            .is_some_and(|header| header.hash == checkpoint.hash);
+        if on_main_chain {
+            return checkpoint.index + 1;
+        }
+
+        match blockchain.get_orphaned_block(&checkpoint.hash) {
+            Some(orphaned) if checkpoint.index > 0 => {
+                checkpoint = VerificationCheckpoint {
+                    index: checkpoint.index - 1,
+                    hash: orphaned.block.header.previous_hash,
+                }
+            }
+            _ => return 0,
+        }
+    }
+}
+
+fn progress(state: &VerificationState, blockchain: &Blockchain) -> VerificationProgress {
+    let total_blocks = blockchain.get_last_block().header.index + 1;
+    let verified_blocks = resume_index(state, blockchain);
+
+    VerificationProgress {
+        running: state.running,
+        verified_blocks,
+        total_blocks,
+        percent: verified_blocks as f64 * 100.0 / total_blocks as f64,
+        started_from: state.started_from,
+        invalid_block: state.failure.as_ref().map(|(index, _)| *index),
+        reason: state.failure.as_ref().map(|(_, reason)| reason.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        fixtures::{event, farm},
+        Block,
+    };
+
+    #[test]
+    fn should_resume_the_verification_after_the_last_block_checked() {
+        let blockchain = Blockchain::new(0);
+        for index in 1..=4 {
+            let previous_hash = blockchain.get_last_block().header.hash;
+            let block = Block::new(index, 0, previous_hash, Vec::new());
+            blockchain.add_block(block).unwrap();
+        }
+        let verification = ChainVerifier::new(None);
+
+        // nothing is checked until a run is started
+        verification.step(&blockchain, 2).unwrap();
+        assert_eq!(verification.progress(&blockchain).verified_blocks, 0);
+
+        assert_eq!(verification.start(&blockchain).started_from, Some(0));
+        verification.step(&blockchain, 2).unwrap();
+        let progress = verification.progress(&blockchain);
+        assert!(progress.running);
+        assert_eq!(progress.verified_blocks, 2);
+        assert!((progress.percent - 40.0).abs() < f64::EPSILON);
+
+        // an interrupted run goes on from there
+        verification.interrupt(&blockchain);
+        verification.step(&blockchain, 2).unwrap();
+        assert_eq!(verification.progress(&blockchain).verified_blocks, 2);
+        assert_eq!(verification.start(&blockchain).started_from, Some(2));
+        verification.step(&blockchain, 10).unwrap();
+        let progress = verification.progress(&blockchain);
+        assert!(!progress.running);
+        assert_eq!(progress.verified_blocks, 5);
+        assert_eq!(progress.invalid_block, None);
+
+        // the next run only checks the new blocks
+        let previous_hash = blockchain.get_last_block().header.hash;
+        blockchain
+            .add_block(Block::new(5, 0, previous_hash, Vec::new()))
+            .unwrap();
+        assert_eq!(verification.start(&blockchain).started_from, Some(5));
+        verification.step(&blockchain, 10).unwrap();
+        assert_eq!(verification.progress(&blockchain).percent, 100.0);
+    }
+
+    #[test]
+    fn should_resume_from_the_common_ancestor_after_a_reorganization() {
+        let blockchain = Blockchain::new(0);
+        for index in 1..=4 {
+            let previous_hash = blockchain.get_last_block().header.hash;
+            let block = Block::new(index, 0, previous_hash, Vec::new());
+            blockchain.add_block(block).unwrap();
+        }
+        let verification = ChainVerifier::new(None);
+        verification.start(&blockchain);
+        verification.step(&blockchain, 10).unwrap();
+        assert_eq!(verification.progress(&blockchain).verified_blocks, 5);
+
+        // the blocks after the 2nd one are replaced by a longer fork
+        let mut previous_hash = blockchain.get_block(2).unwrap().header.hash;
+        let mut fork = Vec::new();
+        for index in 3..=5 {
+            let batch_id = format!("WHEAT-{}", index);
+            let block = Block::new(
+                index,
+                0,
+                previous_hash,
+                vec![farm().sign(event(&farm(), &farm(), "HARVEST", &batch_id))],
+            );
+            previous_hash = block.header.hash;
+            fork.push(block);
+        }
+        blockchain.reorganize(2, fork).unwrap();
+
+        // only the blocks of the fork are checked again
+        assert_eq!(verification.progress(&blockchain).verified_blocks, 3);
+        assert_eq!(verification.start(&blockchain).started_from, Some(3));
+        verification.step(&blockchain, 10).unwrap();
+        let progress = verification.progress(&blockchain);
+        assert_eq!(progress.verified_blocks, 6);
+        assert_eq!(progress.invalid_block, None);
+    }
+}
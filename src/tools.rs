@@ -0,0 +1,283 @@
+mod batch_history;
+mod batch_report;
+mod compare_batches;
+mod decode;
+mod forks;
+mod genesis;
+mod init;
+mod mine;
+mod output_format;
+mod picking;
+mod self_test;
+mod sign_transaction;
+mod simulate_difficulty;
+mod submit_transaction;
+mod validate_chain;
+mod verify;
+mod verify_transaction;
+mod wallet;
+mod watch_batch;
+
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use clap::{Parser, Subcommand};
+
+use crate::util::Config;
+use output_format::OutputFormat;
+
+const OTHER_COMMANDS: &str = "Other commands: batch-report <node url> <batch id>, \
+    compare-batches <node url> <batch id A> <batch id B>, decode <file|hex>, genesis [genesis file] [node url], \
+    picking <node url> <address>, selftest, sign-transaction <key file|-> <file|json>, \
+    simulate-difficulty <difficulty> <scenarios>, verify <file|hex>\n\
+    Output options of every command: --locale <locale>, --units <metric|imperial>, --date-format <format>, \
+    --output <table|json|yaml>";
+
+// The command line of the node, e.g. "agriblock start" on the server of a warehouse
+// The node is started when there is no command, so the nodes configured with a .env file keep working
+#[derive(Parser)]
+#[command(name = "agriblock", version, about = "Agricultural supply chain node", after_help = OTHER_COMMANDS)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Writes the configuration of a new node in a folder, with the storage enabled
+    Init {
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+        /// Genesis file of the network to join, copied into the folder
+        #[arg(long)]
+        genesis: Option<PathBuf>,
+    },
+    /// Runs the node with the configuration of the .env file and the environment
+    Start {
+        /// Only the processes needed to follow and extend the chain (e.g. on edge hardware)
+        #[arg(long)]
+        minimal: bool,
+    },
+    /// Asks a node to mine its pending transactions right away
+    Mine {
+        /// Address of the node, the one of the local configuration by default
+        #[arg(long)]
+        node: Option<String>,
+    },
+    /// Transactions
+    #[command(subcommand)]
+    Tx(TxCommand),
+    /// Batches
+    #[command(subcommand)]
+    Batch(BatchCommand),
+    /// Keystores of the actors, protected by the password in WALLET_PASSWORD
+    #[command(subcommand)]
+    Wallet(WalletCommand),
+    /// Chain of the node
+    #[command(subcommand)]
+    Chain(ChainCommand),
+    /// Blocks that left the main chain of a node, to investigate the events that disappeared with them
+    #[command(subcommand)]
+    Forks(ForksCommand),
+    #[command(external_subcommand)]
+    Other(Vec<String>),
+}
+
+#[derive(Subcommand)]
+enum TxCommand {
+    /// Submits a transaction, from a file or as JSON
+    Submit {
+        input: String,
+        /// Keystore of the sender, to sign the transaction before submitting it
+        #[arg(long)]
+        keystore: Option<PathBuf>,
+        #[arg(long)]
+        node: Option<String>,
+    },
+    /// Checks that a transaction is in the chain of a node with its headers only, like a light client
+    Verify {
+        /// Index of the block of the transaction
+        index: u64,
+        /// Position of the transaction in its block
+        position: usize,
+        /// The transaction that must be proven, from a file or as JSON
+        #[arg(long)]
+        transaction: Option<String>,
+        #[arg(long)]
+        node: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum BatchCommand {
+    /// Prints the events of a batch in chain order, with where each one was mined
+    History {
+        batch_id: String,
+        #[arg(long)]
+        node: Option<String>,
+    },
+    /// Prints the new events of a batch as they arrive, pending then mined, until the node stops
+    Watch {
+        batch_id: String,
+        #[arg(long)]
+        node: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum WalletCommand {
+    /// Creates the keypair of a new actor
+    New { keystore: PathBuf },
+    /// Prints the address of an actor
+    Address { keystore: PathBuf },
+    /// Signs a transaction, from a file or as JSON
+    Sign {
+        keystore: PathBuf,
+        input: String,
+        /// Normalizes the units and field names of the payload first, with the latest version
+        #[arg(long)]
+        normalize: bool,
+    },
+    /// Signs the grade given to a batch by an inspector, for a quality check graded by several of them
+    Attest {
+        keystore: PathBuf,
+        batch_id: String,
+        grade: String,
+        /// Share of the inspector in the decision
+        #[arg(long)]
+        weight: u64,
+        /// Weight that the grades must reach together, the same for all the inspectors of the check
+        #[arg(long)]
+        threshold: u64,
+    },
+    /// Signs the statement of a regulator about a block, e.g. the outcome of an inspection
+    AttestBlock {
+        keystore: PathBuf,
+        block_hash: String,
+        statement: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ChainCommand {
+    /// Validates again the stored chain of the local node, which must be stopped
+    Validate,
+}
+
+#[derive(Subcommand)]
+enum ForksCommand {
+    /// Lists the orphaned blocks, oldest first, with why each one left the chain
+    List {
+        #[arg(long)]
+        node: Option<String>,
+    },
+    /// Shows an orphaned block with its transactions
+    Show {
+        hash: String,
+        #[arg(long)]
+        node: Option<String>,
+    },
+}
+
+// How to run the node, when the command is to start it
+pub struct NodeOptions {
+    pub minimal: bool,
+}
+
+// Runs a command to operate or inspect a network, or returns the options of the node to start
+// The output options can be anywhere, e.g. "agriblock batch history --output json WHEAT-2024-001"
+pub fn run(args: &[String]) -> Result<Option<NodeOptions>> {
+    let (format, args) = OutputFormat::from_args(args)?;
+    let cli = Cli::try_parse_from(args).unwrap_or_else(|error| error.exit());
+
+    let command = match cli.command {
+        Some(command) => command,
+        None => return Ok(Some(NodeOptions { minimal: false })),
+    };
+    match command {
+        Command::Init { dir, genesis } => init::run(&dir, genesis.as_deref(), &format)?,
+        Command::Start { minimal } => return Ok(Some(NodeOptions { minimal })),
+        Command::Mine { node } => mine::run(&node_address(node)?, &format)?,
+        Command::Tx(TxCommand::Submit {
+            input,
+            keystore,
+            node,
+        }) => submit_transaction::run(&node_address(node)?, &input, keystore.as_deref(), &format)?,
+        Command::Tx(TxCommand::Verify {
+            index,
+            position,
+            transaction,
+            node,
+        }) => verify_transaction::run(
+            &node_address(node)?,
+            index,
+            position,
+            transaction.as_deref(),
+            &format,
+        )?,
+        Command::Batch(BatchCommand::History { batch_id, node }) => {
+            batch_history::run(&node_address(node)?, &batch_id, &format)?
+        }
+        Command::Batch(BatchCommand::Watch { batch_id, node }) => {
+            watch_batch::run(&node_address(node)?, &batch_id, &format)?
+        }
+        Command::Wallet(WalletCommand::New { keystore }) => wallet::new(&keystore, &format)?,
+        Command::Wallet(WalletCommand::Address { keystore }) => {
+            wallet::address(&keystore, &format)?
+        }
+        Command::Wallet(WalletCommand::Sign {
+            keystore,
+            input,
+            normalize,
+        }) => wallet::sign(&keystore, &input, normalize, &format)?,
+        Command::Wallet(WalletCommand::Attest {
+            keystore,
+            batch_id,
+            grade,
+            weight,
+            threshold,
+        }) => wallet::attest(&keystore, &batch_id, &grade, weight, threshold, &format)?,
+        Command::Wallet(WalletCommand::AttestBlock {
+            keystore,
+            block_hash,
+            statement,
+        }) => wallet::attest_block(&keystore, &block_hash, &statement, &format)?,
+        Command::Chain(ChainCommand::Validate) => validate_chain::run(&format)?,
+        Command::Forks(ForksCommand::List { node }) => forks::list(&node_address(node)?, &format)?,
+        Command::Forks(ForksCommand::Show { hash, node }) => {
+            forks::show(&node_address(node)?, &hash, &format)?
+        }
+        Command::Other(args) => run_other(&args, &format)?,
+    }
+
+    Ok(None)
+}
+
+// The commands to inspect or verify a network, e.g. "agriblock genesis http://localhost:8000"
+fn run_other(args: &[String], format: &OutputFormat) -> Result<()> {
+    match args[0].as_str() {
+        "batch-report" => batch_report::run(&args[1..], format),
+        "compare-batches" => compare_batches::run(&args[1..], format),
+        "decode" => decode::run(&args[1..], format),
+        "genesis" => genesis::run(&args[1..], format),
+        "picking" => picking::run(&args[1..], format),
+        "selftest" => self_test::run(&args[1..], format),
+        "sign-transaction" => sign_transaction::run(&args[1..], format),
+        "simulate-difficulty" => simulate_difficulty::run(&args[1..], format),
+        "verify" => verify::run(&args[1..], format),
+        command => bail!(
+            "Unknown command `{}`, see \"agriblock help\"\n{}",
+            command,
+            OTHER_COMMANDS
+        ),
+    }
+}
+
+// The node of this machine, as configured in its .env file, when no other node is given
+fn node_address(node: Option<String>) -> Result<String> {
+    let address = match node {
+        Some(node) => node,
+        None => Config::read()?.public_address,
+    };
+    Ok(address.trim_end_matches('/').to_string())
+}
@@ -0,0 +1,46 @@
+use anyhow::{anyhow, bail, Result};
+use isahc::ReadResponseExt;
+use serde::{Deserialize, Serialize};
+
+use super::output_format::OutputFormat;
+use crate::model::{BlockRef, Transaction};
+
+#[derive(Serialize, Deserialize)]
+struct BatchEvent {
+    block: BlockRef,
+    transaction: Transaction,
+}
+
+// Prints the provenance of a batch: all its events in chain order, with the block of each one
+pub fn run(address: &str, batch_id: &str, format: &OutputFormat) -> Result<()> {
+    let url = format!("{}/batches/{}/history", address, batch_id);
+    let mut response = isahc::get(url)?;
+    if !response.status().is_success() {
+        bail!(
+            "The node {} has no history for the batch {}: {}",
+            address,
+            batch_id,
+            response.text()?
+        );
+    }
+    let history: Vec<BatchEvent> = serde_json::from_str(&response.text()?)
+        .map_err(|error| anyhow!("Invalid history from the node {}: {}", address, error))?;
+
+    format.print(&history, || render_history(batch_id, &history, format))
+}
+
+fn render_history(batch_id: &str, history: &[BatchEvent], format: &OutputFormat) -> String {
+    let mut lines = vec![format!("Batch {}", batch_id)];
+    lines.extend(history.iter().map(|event| {
+        let transaction = &event.transaction;
+        format!(
+            "  {}  block {:<6} {:<16} {} -> {}",
+            format.date(event.block.timestamp),
+            event.block.index,
+            transaction.event_type.as_str(),
+            transaction.sender,
+            transaction.recipient
+        )
+    }));
+    lines.join("\n")
+}
@@ -0,0 +1,94 @@
+use anyhow::{anyhow, bail, Result};
+use isahc::ReadResponseExt;
+use serde_json::Value;
+
+use super::output_format::OutputFormat;
+use crate::analytics::BatchStatus;
+
+const USAGE: &str = "Usage: batch-report <node url> <batch id>";
+
+// Prints where a batch is now, as a report to hand to partners (e.g. a buyer or a customs agent)
+// The quantities and dates follow the output options (e.g. "--locale en-US" for pounds and US dates)
+pub fn run(args: &[String], format: &OutputFormat) -> Result<()> {
+    let (address, batch_id) = match args {
+        [address, batch_id] => (address.trim_end_matches('/'), batch_id),
+        _ => bail!(USAGE),
+    };
+
+    let mut response = isahc::get(format!("{}/batches/{}/status", address, batch_id))?;
+    if !response.status().is_success() {
+        bail!(
+            "The node {} has no status for the batch {}: {}",
+            address,
+            batch_id,
+            response.text()?
+        );
+    }
+    let status: BatchStatus = serde_json::from_str(&response.text()?)
+        .map_err(|error| anyhow!("Invalid status from the node {}: {}", address, error))?;
+
+    format.print(&status, || render_report(&status, format))
+}
+
+fn render_report(status: &BatchStatus, format: &OutputFormat) -> String {
+    let quantity = match &status.quantity {
+        Some(Value::String(quantity)) => format.quantity(quantity),
+        Some(Value::Number(quantity)) => format.quantity(&quantity.to_string()),
+        Some(other) => other.to_string(),
+        None => "-".to_string(),
+    };
+    let certifications = match status.certifications.is_empty() {
+        true => "-".to_string(),
+        false => status.certifications.join(", "),
+    };
+
+    let mut lines = vec![
+        format!("Batch {}", status.batch_id),
+        format!("  stage:          {}", status.stage),
+        format!("  custodian:      {}", status.custodian),
+        format!("  quantity:       {}", quantity),
+        format!("  certifications: {}", certifications),
+        format!("  open disputes:  {}", status.open_disputes),
+        format!("  events:         {}", status.events),
+        format!("  last event:     {}", format.date(status.last_event_at)),
+        format!("  SLA violations: {}", status.violations.len()),
+    ];
+    lines.extend(
+        status
+            .violations
+            .iter()
+            .map(|violation| format!("    - {}", violation)),
+    );
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::fixtures::alice;
+
+    #[test]
+    fn should_render_the_report_in_the_output_format() {
+        let status = BatchStatus {
+            batch_id: "WHEAT-001".to_string(),
+            stage: "STORAGE".to_string(),
+            custodian: alice(),
+            quantity: Some(Value::String("480kg".to_string())),
+            remaining_kg: Some(480.0),
+            certifications: vec!["EU-Organic".to_string()],
+            open_disputes: 0,
+            violations: vec!["Transit took 2 hours".to_string()],
+            events: 4,
+            last_event_at: 86_400_000,
+            custody_since: 0,
+            expires_at: None,
+        };
+
+        let format = OutputFormat::for_locale("en-US").unwrap();
+        let report = render_report(&status, &format);
+        assert!(report.contains("quantity:       1,058.22 lb"));
+        assert!(report.contains("last event:     01/02/1970 00:00"));
+        assert!(report.ends_with("SLA violations: 1\n    - Transit took 2 hours"));
+    }
+}
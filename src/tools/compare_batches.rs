@@ -0,0 +1,137 @@
+use anyhow::{anyhow, bail, Result};
+use isahc::ReadResponseExt;
+
+use super::output_format::OutputFormat;
+use crate::analytics::{BatchComparison, BatchStage};
+
+const USAGE: &str = "Usage: compare-batches <node url> <batch id A> <batch id B>";
+
+const MILLIS_PER_HOUR: f64 = 3_600_000.0;
+
+// Width of the columns of the stages in the text report
+const STAGE_WIDTH: usize = 16;
+const CELL_WIDTH: usize = 36;
+
+// Prints the lifecycles of two batches side by side, with what differs at each stage
+// e.g. for two shipments of the same harvest where only one of them spoiled
+pub fn run(args: &[String], format: &OutputFormat) -> Result<()> {
+    let (address, batch_a, batch_b) = match args {
+        [address, batch_a, batch_b] => (address.trim_end_matches('/'), batch_a, batch_b),
+        _ => bail!(USAGE),
+    };
+
+    let uri = format!("{}/batches/{}/comparison/{}", address, batch_a, batch_b);
+    let mut response = isahc::get(uri)?;
+    if !response.status().is_success() {
+        bail!(
+            "The node {} cannot compare the batches {} and {}: {}",
+            address,
+            batch_a,
+            batch_b,
+            response.text()?
+        );
+    }
+    let comparison: BatchComparison = serde_json::from_str(&response.text()?)
+        .map_err(|error| anyhow!("Invalid comparison from the node {}: {}", address, error))?;
+
+    format.print(&comparison, || render_report(&comparison, format))
+}
+
+fn render_report(comparison: &BatchComparison, format: &OutputFormat) -> String {
+    let hours = |ms: i64| format!("{} h", format.number(ms as f64 / MILLIS_PER_HOUR, 1));
+    let row = |stage: &str, a: &str, b: &str| {
+        format!(
+            "{:<stage_width$}{:<cell_width$}{}",
+            stage,
+            a,
+            b,
+            stage_width = STAGE_WIDTH,
+            cell_width = CELL_WIDTH
+        )
+    };
+    let cell = |stage: &Option<BatchStage>| match stage {
+        Some(stage) => {
+            let mut parts = vec![format.date(stage.at)];
+            parts.extend(stage.duration_ms.map(hours));
+            parts.extend(stage.quality.clone());
+            parts.join(", ")
+        }
+        None => "-".to_string(),
+    };
+
+    let mut lines = vec![
+        row(
+            "",
+            &format!("A: {}", comparison.batch_a),
+            &format!("B: {}", comparison.batch_b),
+        ),
+        row(
+            "total",
+            &hours(comparison.duration_a_ms),
+            &hours(comparison.duration_b_ms),
+        ),
+    ];
+    for stage in comparison.stages.iter() {
+        lines.push(row(&stage.event_type, &cell(&stage.a), &cell(&stage.b)));
+        lines.extend(
+            stage
+                .differences
+                .iter()
+                .map(|difference| format!("  ! {}", difference)),
+        );
+    }
+    lines.push(format!("Divergences: {}", comparison.divergences));
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{analytics::StageComparison, model::fixtures::alice};
+
+    #[test]
+    fn should_render_the_stages_side_by_side() {
+        let stage = |at, duration_ms, quality: Option<&str>| BatchStage {
+            event_type: "STORAGE".to_string(),
+            actor: alice(),
+            at,
+            duration_ms,
+            quality: quality.map(str::to_string),
+        };
+        let comparison = BatchComparison {
+            batch_a: "WHEAT-001-A".to_string(),
+            batch_b: "WHEAT-001-B".to_string(),
+            stages: vec![
+                StageComparison {
+                    event_type: "STORAGE".to_string(),
+                    a: Some(stage(0, Some(7_200_000), None)),
+                    b: Some(stage(0, Some(36_000_000), None)),
+                    differences: vec!["Took 2.0 hours in A and 10.0 hours in B".to_string()],
+                },
+                StageComparison {
+                    event_type: "QUALITY_CHECK".to_string(),
+                    a: None,
+                    b: Some(stage(36_000_000, None, Some("FAIL"))),
+                    differences: vec!["Only in batch B".to_string()],
+                },
+            ],
+            duration_a_ms: 7_200_000,
+            duration_b_ms: 36_000_000,
+            divergences: 2,
+        };
+
+        let format = OutputFormat::for_locale("fr").unwrap();
+        let report = render_report(&comparison, &format);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(
+            lines[0].trim_end(),
+            format!("{:16}{:36}B: WHEAT-001-B", "", "A: WHEAT-001-A")
+        );
+        assert_eq!(lines[1], format!("{:16}{:36}10,0 h", "total", "2,0 h"));
+        assert!(lines[2].starts_with("STORAGE         01/01/1970 00:00, 2,0 h"));
+        assert_eq!(lines[3], "  ! Took 2.0 hours in A and 10.0 hours in B");
+        assert!(lines[4].ends_with("01/01/1970 10:00, FAIL"));
+        assert_eq!(lines[6], "Divergences: 2");
+    }
+}
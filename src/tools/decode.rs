@@ -0,0 +1,211 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+use serde::Serialize;
+use serde_json::Value;
+
+use super::output_format::OutputFormat;
+use crate::model::{decode, Block, Encoding, Transaction};
+
+// Decoded values with their JSON payloads expanded, and the problems found in them
+#[derive(Serialize)]
+struct DecodedData {
+    decoded: Value,
+    problems: Vec<String>,
+}
+
+// Decodes raw blocks or transactions (e.g. what a peer sent) from a file or a hex string,
+// in JSON or in any of the canonical encodings, and checks that they are consistent
+// The decoded values are printed with their JSON payloads expanded, to make them readable
+pub fn run(args: &[String], format: &OutputFormat) -> Result<()> {
+    let input = args
+        .first()
+        .ok_or_else(|| anyhow!("Usage: decode <file|hex>"))?;
+    let data = read_input(input)?;
+    let mut value = decode_any(&data)?;
+
+    // a list of blocks or transactions, or a single one
+    let items = match &value {
+        Value::Array(items) => items.clone(),
+        item => vec![item.clone()],
+    };
+    let mut problems = Vec::new();
+    for (position, item) in items.into_iter().enumerate() {
+        let item_problems =
+            check_item(item).map_err(|error| anyhow!("Item {}: {}", position, error))?;
+        problems.extend(item_problems);
+    }
+
+    expand_payloads(&mut value);
+    let data = DecodedData {
+        decoded: value,
+        problems,
+    };
+    format.print(&data, || {
+        let json = serde_json::to_string_pretty(&data.decoded).unwrap_or_default();
+        match data.problems.is_empty() {
+            true => format!("{}\nThe data is consistent", json),
+            false => json,
+        }
+    })?;
+
+    if !data.problems.is_empty() {
+        bail!("Inconsistent data:\n{}", data.problems.join("\n"));
+    }
+    Ok(())
+}
+
+// The input is a file (with raw bytes or hex text) or a hex string
+pub(super) fn read_input(input: &str) -> Result<Vec<u8>> {
+    let data = match Path::new(input).is_file() {
+        true => fs::read(input)?,
+        false => input.as_bytes().to_vec(),
+    };
+
+    let text = String::from_utf8_lossy(&data);
+    let hex_text = text.trim().trim_start_matches("0x");
+    let is_hex = !hex_text.is_empty() && hex_text.chars().all(|c| c.is_ascii_hexdigit());
+    if is_hex {
+        return Ok(hex::decode(hex_text)?);
+    }
+
+    Ok(data)
+}
+
+// JSON always starts with an object or an array, the binary encoding starts with a type tag
+fn decode_any(data: &[u8]) -> Result<Value> {
+    let first = data.iter().find(|byte| !byte.is_ascii_whitespace());
+    let encoding = match first {
+        Some(b'{') | Some(b'[') => Encoding::CanonicalJson,
+        _ => Encoding::Binary,
+    };
+
+    Ok(decode(data, encoding)?)
+}
+
+// Returns the problems found in a block or a transaction
+fn check_item(item: Value) -> Result<Vec<String>> {
+    if item.get("transactions").is_some() {
+        let block: Block = serde_json::from_value(item)?;
+        return Ok(check_block(&block));
+    }
+
+    let transaction: Transaction = serde_json::from_value(item)?;
+    Ok(check_transaction(&transaction)
+        .map(|problem| vec![problem])
+        .unwrap_or_default())
+}
+
+fn check_block(block: &Block) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let calculated_root = block.calculate_merkle_root();
+    if block.header.merkle_root != calculated_root {
+        problems.push(format!(
+            "Block {}: the merkle root {:#x} does not match the transactions ({:#x})",
+            block.header.index, block.header.merkle_root, calculated_root
+        ));
+    }
+
+    let calculated_hash = block.calculate_hash();
+    if block.header.hash != calculated_hash {
+        problems.push(format!(
+            "Block {}: the hash {:#x} does not match the contents ({:#x})",
+            block.header.index, block.header.hash, calculated_hash
+        ));
+    }
+
+    for (position, transaction) in block.body.transactions.iter().enumerate() {
+        if let Some(problem) = check_transaction(transaction) {
+            problems.push(format!(
+                "Block {}, transaction {}: {}",
+                block.header.index, position, problem
+            ));
+        }
+    }
+
+    problems
+}
+
+fn check_transaction(transaction: &Transaction) -> Option<String> {
+    transaction.validate().err().map(|error| error.to_string())
+}
+
+// Replaces the payloads that are JSON documents by the documents themselves
+fn expand_payloads(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            if let Some(Value::String(data)) = fields.get("data") {
+                if let Ok(payload) = serde_json::from_str::<Value>(data) {
+                    fields.insert("data".to_string(), payload);
+                }
+            }
+            fields.values_mut().for_each(expand_payloads);
+        }
+        Value::Array(items) => items.iter_mut().for_each(expand_payloads),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::model::{
+        encode,
+        fixtures::{alice, sign},
+        BlockHash, PROFILE_EVENT,
+    };
+
+    #[test]
+    fn should_decode_any_encoding() {
+        let block = Block::new(1, 0, BlockHash::zero(), vec![]);
+        let json = serde_json::to_vec(&block).unwrap();
+        let expected = serde_json::to_value(&block).unwrap();
+
+        assert_eq!(decode_any(&json).unwrap(), expected);
+        for encoding in [Encoding::CanonicalJson, Encoding::Binary] {
+            let encoded = encode(&block, encoding).unwrap();
+            assert_eq!(decode_any(&encoded).unwrap(), expected);
+
+            // also as hex
+            let hex_input = hex::encode(&encoded);
+            let data = read_input(&hex_input).unwrap();
+            assert_eq!(decode_any(&data).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn should_find_inconsistencies() {
+        let invalid_profile = sign(Transaction {
+            sender: alice(),
+            recipient: alice(),
+            data: "not a profile".into(),
+            event_type: PROFILE_EVENT.into(),
+            ..Default::default()
+        });
+        let mut block = Block::new(1, 0, BlockHash::zero(), vec![invalid_profile]);
+        assert_eq!(
+            check_block(&block),
+            vec!["Block 1, transaction 0: Invalid profile"]
+        );
+
+        block.header.nonce += 1;
+        assert_eq!(check_block(&block).len(), 2);
+    }
+
+    #[test]
+    fn should_expand_json_payloads() {
+        let mut value = json!([
+            {"data": r#"{"crop": "wheat"}"#},
+            {"data": "500kg of wheat"},
+        ]);
+
+        expand_payloads(&mut value);
+        assert_eq!(
+            value,
+            json!([{"data": {"crop": "wheat"}}, {"data": "500kg of wheat"}])
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use anyhow::{anyhow, bail, Result};
+use isahc::ReadResponseExt;
+
+use super::output_format::OutputFormat;
+use crate::model::{OrphanReason, OrphanedBlock};
+
+// Lists the blocks that left the main chain of a node, oldest first
+pub fn list(address: &str, format: &OutputFormat) -> Result<()> {
+    let mut response = isahc::get(format!("{}/forks", address))?;
+    if !response.status().is_success() {
+        bail!(
+            "The node {} cannot list its forks: {}",
+            address,
+            response.text()?
+        );
+    }
+    let forks: Vec<OrphanedBlock> = serde_json::from_str(&response.text()?)
+        .map_err(|error| anyhow!("Invalid forks from the node {}: {}", address, error))?;
+
+    format.print(&forks, || render_list(&forks, format))
+}
+
+// Shows an orphaned block with its transactions, e.g. to find the events that "disappeared" from the chain
+pub fn show(address: &str, hash: &str, format: &OutputFormat) -> Result<()> {
+    let mut response = isahc::get(format!("{}/forks/{}", address, hash))?;
+    if !response.status().is_success() {
+        bail!(
+            "The node {} has no orphaned block {}: {}",
+            address,
+            hash,
+            response.text()?
+        );
+    }
+    let orphaned: OrphanedBlock = serde_json::from_str(&response.text()?)
+        .map_err(|error| anyhow!("Invalid fork from the node {}: {}", address, error))?;
+
+    format.print(&orphaned, || render_block(&orphaned, format))
+}
+
+fn render_list(forks: &[OrphanedBlock], format: &OutputFormat) -> String {
+    if forks.is_empty() {
+        return "No orphaned blocks".to_string();
+    }
+
+    forks
+        .iter()
+        .map(|orphaned| {
+            format!(
+                "  {}  block {:<6} {:#x}  {}, {} transactions",
+                format.date(orphaned.archived_at),
+                orphaned.fork_height,
+                orphaned.block.header.hash,
+                render_reason(orphaned),
+                orphaned.block.body.transactions.len()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn render_block(orphaned: &OrphanedBlock, format: &OutputFormat) -> String {
+    let mut lines = vec![
+        format!(
+            "Block {} {:#x}",
+            orphaned.fork_height, orphaned.block.header.hash
+        ),
+        format!(
+            "  {} on {}",
+            render_reason(orphaned),
+            format.date(orphaned.archived_at)
+        ),
+        format!(
+            "  replaced by {:#x} in the main chain",
+            orphaned.canonical_hash
+        ),
+    ];
+    lines.extend(orphaned.block.body.transactions.iter().map(|transaction| {
+        format!(
+            "  {:<16} {:<20} {} -> {}",
+            transaction.event_type.as_str(),
+            transaction.batch_id,
+            transaction.sender,
+            transaction.recipient
+        )
+    }));
+    lines.join("\n")
+}
+
+fn render_reason(orphaned: &OrphanedBlock) -> String {
+    match (&orphaned.reason, &orphaned.reorg) {
+        (OrphanReason::RolledBack, Some(reorg)) => format!(
+            "rolled back after block {} ({} blocks)",
+            reorg.ancestor, reorg.depth
+        ),
+        (OrphanReason::RolledBack, None) => "rolled back".to_string(),
+        (OrphanReason::StaleFork, _) => "stale fork".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{fixtures::alice, Block, BlockHash, ReorgSummary, Transaction};
+
+    #[test]
+    fn should_render_why_the_blocks_were_orphaned() {
+        let transaction = Transaction {
+            sender: alice(),
+            recipient: alice(),
+            batch_id: "WHEAT-001".to_string(),
+            event_type: "HARVEST".into(),
+            ..Default::default()
+        };
+        let block = Block::new(3, 0, BlockHash::zero(), vec![transaction]);
+        let mut stale = OrphanedBlock::new(block.clone(), OrphanReason::StaleFork, 1.into());
+        stale.archived_at = 0;
+        let rolled_back = OrphanedBlock {
+            reason: OrphanReason::RolledBack,
+            ..stale.clone()
+        }
+        .with_reorg(ReorgSummary {
+            ancestor: 2,
+            depth: 4,
+        });
+
+        // as read from the API of the node
+        let json = serde_json::to_string(&rolled_back).unwrap();
+        let rolled_back: OrphanedBlock = serde_json::from_str(&json).unwrap();
+
+        let format = OutputFormat::for_locale("en").unwrap();
+        let list = render_list(&[stale, rolled_back.clone()], &format);
+        let lines: Vec<&str> = list.lines().collect();
+        assert!(lines[0].ends_with("stale fork, 1 transactions"));
+        assert!(lines[1].ends_with("rolled back after block 2 (4 blocks), 1 transactions"));
+        assert_eq!(render_list(&[], &format), "No orphaned blocks");
+
+        let shown = render_block(&rolled_back, &format);
+        assert!(shown.starts_with(&format!("Block 3 {:#x}", block.header.hash)));
+        assert!(shown.contains("replaced by 0x1 in the main chain"));
+        assert!(shown.contains("HARVEST          WHEAT-001"));
+    }
+}
@@ -0,0 +1,83 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use isahc::ReadResponseExt;
+use serde::Serialize;
+
+use super::output_format::OutputFormat;
+use crate::model::{Blockchain, GenesisConfig, GenesisSummary};
+
+// Genesis of the network, and the one of the node when its address is given
+#[derive(Serialize)]
+struct GenesisCheck {
+    #[serde(flatten)]
+    expected: GenesisSummary,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    node: Option<NodeGenesis>,
+}
+
+#[derive(Serialize)]
+struct NodeGenesis {
+    address: String,
+    matches: bool,
+    #[serde(flatten)]
+    genesis: GenesisSummary,
+}
+
+// Prints the genesis hash and initial state root that every node of the network must have,
+// the ones of the default network or of a genesis file (GENESIS_FILE of the nodes)
+// If the address of a node is given, it also verifies that the node is on the same network
+pub fn run(args: &[String], format: &OutputFormat) -> Result<()> {
+    let (genesis_file, address) = match args {
+        [file, address] => (Some(file), Some(address)),
+        [arg] if Path::new(arg).is_file() => (Some(arg), None),
+        [address] => (None, Some(address)),
+        [] => (None, None),
+        _ => bail!("Usage: genesis [genesis file] [node url]"),
+    };
+    let expected = match genesis_file {
+        Some(file) => Blockchain::from_genesis(&GenesisConfig::load(file)?),
+        // the difficulty does not take part in the default genesis block
+        None => Blockchain::new(0),
+    }
+    .get_genesis_summary();
+
+    let node = match address {
+        Some(address) => {
+            let address = address.trim_end_matches('/');
+            let mut response = isahc::get(format!("{}/genesis", address))?;
+            let genesis: GenesisSummary = serde_json::from_str(&response.text()?)?;
+            Some(NodeGenesis {
+                address: address.to_string(),
+                matches: genesis == expected,
+                genesis,
+            })
+        }
+        None => None,
+    };
+
+    let check = GenesisCheck { expected, node };
+    format.print(&check, || render_check(&check))?;
+
+    match &check.node {
+        Some(node) if !node.matches => bail!(
+            "The node {} is on another network (genesis hash {:#x}, state root {:#x})",
+            node.address,
+            node.genesis.hash,
+            node.genesis.state_root
+        ),
+        _ => Ok(()),
+    }
+}
+
+fn render_check(check: &GenesisCheck) -> String {
+    let mut lines = vec![
+        format!("genesis hash: {:#x}", check.expected.hash),
+        format!("state root:   {:#x}", check.expected.state_root),
+    ];
+    if let Some(node) = check.node.as_ref().filter(|node| node.matches) {
+        lines.push(format!("The node {} matches the genesis", node.address));
+    }
+
+    lines.join("\n")
+}
@@ -0,0 +1,121 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+use serde::Serialize;
+
+use super::output_format::OutputFormat;
+use crate::model::{Blockchain, GenesisConfig, GenesisSummary};
+
+// Every setting of the node with its default value and what it does, so the operators can tune them later
+const CONFIG_TEMPLATE: &str = include_str!("../../.env.example");
+
+// Files and folders that a node on a server keeps between restarts, enabled in the written configuration
+const PERSISTED_SETTINGS: [&str; 3] = ["STORAGE_PATH", "CHANGES_FILE", "PEERS_FILE"];
+
+#[derive(Serialize)]
+struct InitializedNode {
+    config_file: String,
+    genesis_file: Option<String>,
+    genesis: GenesisSummary,
+}
+
+// Prepares a folder to run a node from, e.g. on the server of a warehouse: a .env file with the chain,
+// the change feed and the peers persisted, and a copy of the genesis file of the network if given
+// Nothing is overwritten, a folder can only be initialized once
+pub fn run(dir: &Path, genesis_file: Option<&Path>, format: &OutputFormat) -> Result<()> {
+    let config_path = dir.join(".env");
+    if config_path.exists() {
+        bail!(
+            "The node in {} is already initialized, see {}",
+            dir.display(),
+            config_path.display()
+        );
+    }
+    fs::create_dir_all(dir)?;
+
+    // an invalid genesis file would only be refused when the node starts
+    let (genesis, copied_file) = match genesis_file {
+        Some(file) => {
+            let config = GenesisConfig::load(&file.to_string_lossy())?;
+            let name = file
+                .file_name()
+                .ok_or_else(|| anyhow!("Invalid genesis file {}", file.display()))?;
+            let copy = dir.join(name);
+            if !copy.exists() {
+                fs::copy(file, &copy)?;
+            }
+            let name = name.to_string_lossy().to_string();
+            (Blockchain::from_genesis(&config), Some(name))
+        }
+        // the difficulty does not take part in the default genesis block
+        None => (Blockchain::new(0), None),
+    };
+    fs::write(&config_path, render_config(copied_file.as_deref()))?;
+
+    let node = InitializedNode {
+        config_file: config_path.display().to_string(),
+        genesis_file: copied_file,
+        genesis: genesis.get_genesis_summary(),
+    };
+    format.print(&node, || {
+        format!(
+            "Initialized a node in {} for the network with the genesis hash {:#x}\n\
+            Review its configuration in {}, then run \"agriblock start\" from that folder",
+            dir.display(),
+            node.genesis.hash,
+            node.config_file
+        )
+    })
+}
+
+// The example configuration with the persisted settings and the genesis file enabled
+fn render_config(genesis_file: Option<&str>) -> String {
+    let mut lines = vec![
+        "# Configuration of the node, written by \"agriblock init\"".to_string(),
+        "# All the values are set as environment variables when the node starts from this folder"
+            .to_string(),
+    ];
+    // the header of the template, until the first empty line, is about copying it
+    for line in CONFIG_TEMPLATE.lines().skip_while(|line| !line.is_empty()) {
+        let setting = line.trim_start_matches("# ");
+        let name = setting.split(" = ").next().unwrap_or_default();
+        let line = match (name, genesis_file) {
+            ("GENESIS_FILE", Some(file)) => format!("GENESIS_FILE = {}", file),
+            (name, _) if PERSISTED_SETTINGS.contains(&name) => setting.to_string(),
+            _ => line.to_string(),
+        };
+        lines.push(line);
+    }
+
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_enable_the_persisted_settings() {
+        let config = render_config(Some("testnet.toml"));
+        assert!(config.starts_with("# Configuration of the node"));
+        assert!(!config.contains("only an example"));
+
+        let lines: Vec<&str> = config.lines().collect();
+        for line in [
+            "STORAGE_PATH = chain-db",
+            "CHANGES_FILE = changes.jsonl",
+            "PEERS_FILE = peers.json",
+            "GENESIS_FILE = testnet.toml",
+            "PORT = 8000",
+            "# ADMIN_TOKEN = change-me",
+        ] {
+            assert!(lines.contains(&line), "{}", line);
+        }
+
+        // the default network without a genesis file
+        let config = render_config(None);
+        assert!(config
+            .lines()
+            .any(|line| line == "# GENESIS_FILE = testnet.toml"));
+    }
+}
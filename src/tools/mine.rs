@@ -0,0 +1,28 @@
+use anyhow::{anyhow, bail, Result};
+use isahc::ReadResponseExt;
+use serde::{Deserialize, Serialize};
+
+use super::output_format::OutputFormat;
+
+#[derive(Serialize, Deserialize)]
+struct MiningRequest {
+    pending_transactions: usize,
+}
+
+// Asks a node to mine its pending transactions right away, e.g. before a truck leaves the warehouse
+// The node answers before mining them, the blocks are mined in the background
+pub fn run(address: &str, format: &OutputFormat) -> Result<()> {
+    let mut response = isahc::post(format!("{}/mine", address), ())?;
+    if !response.status().is_success() {
+        bail!("The node {} cannot mine: {}", address, response.text()?);
+    }
+    let request: MiningRequest = serde_json::from_str(&response.text()?)
+        .map_err(|error| anyhow!("Invalid answer from the node {}: {}", address, error))?;
+
+    format.print(&request, || {
+        format!(
+            "The node {} will mine {} pending transactions",
+            address, request.pending_transactions
+        )
+    })
+}
@@ -0,0 +1,309 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::{
+    format::{Item, StrftimeItems},
+    TimeZone, Utc,
+};
+use serde::Serialize;
+
+// Output of the commands when no locale is given: no grouping of digits and ISO dates
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d %H:%M UTC";
+
+const SUPPORTED_LOCALES: &str = "en, en-US, fr, de, es, ur";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnitSystem {
+    Metric,
+    Imperial,
+}
+
+// What the commands print: text for people, or a document for scripts (e.g. cron jobs in packing plants)
+// The JSON and YAML documents have a stable schema and are not localized, numbers and timestamps stay raw
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputStyle {
+    Table,
+    Json,
+    Yaml,
+}
+
+// How the commands print numbers, dates and quantities, so the reports handed to farmers or customs agents
+// in other countries are readable without converting them by hand
+// e.g. "--locale fr" prints "1 234,5 kg" and "--locale en-US" prints "2,721.98 lb"
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputFormat {
+    decimal_separator: char,
+    thousands_separator: Option<char>,
+    date_format: String,
+    units: UnitSystem,
+    style: OutputStyle,
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        OutputFormat {
+            decimal_separator: '.',
+            thousands_separator: None,
+            date_format: DEFAULT_DATE_FORMAT.to_string(),
+            units: UnitSystem::Metric,
+            style: OutputStyle::Table,
+        }
+    }
+}
+
+impl OutputFormat {
+    // Conventions of a locale, falling back to the base language ("fr" for "fr-CA")
+    pub fn for_locale(locale: &str) -> Result<OutputFormat> {
+        let (decimal_separator, thousands_separator, date_format, units) = match locale {
+            "en-US" => ('.', ',', "%m/%d/%Y %H:%M", UnitSystem::Imperial),
+            "en" | "en-GB" => ('.', ',', "%d/%m/%Y %H:%M", UnitSystem::Metric),
+            "fr" => (',', ' ', "%d/%m/%Y %H:%M", UnitSystem::Metric),
+            "de" => (',', '.', "%d.%m.%Y %H:%M", UnitSystem::Metric),
+            "es" => (',', '.', "%d/%m/%Y %H:%M", UnitSystem::Metric),
+            "ur" => ('.', ',', "%d/%m/%Y %H:%M", UnitSystem::Metric),
+            _ => match locale.split_once('-') {
+                Some((language, _)) => return OutputFormat::for_locale(language),
+                None => bail!(
+                    "Unknown locale `{}`, supported locales: {}",
+                    locale,
+                    SUPPORTED_LOCALES
+                ),
+            },
+        };
+
+        Ok(OutputFormat {
+            decimal_separator,
+            thousands_separator: Some(thousands_separator),
+            date_format: date_format.to_string(),
+            units,
+            style: OutputStyle::Table,
+        })
+    }
+
+    // Takes the output options out of the arguments of a command, wherever they are:
+    // "--locale <locale>", then "--units <metric|imperial>" and "--date-format <strftime format>" override it,
+    // and "--output <table|json|yaml>" chooses between the text and a document
+    pub fn from_args(args: &[String]) -> Result<(OutputFormat, Vec<String>)> {
+        let mut format = OutputFormat::default();
+        let mut units = None;
+        let mut date_format = None;
+        let mut style = OutputStyle::Table;
+        let mut remaining = Vec::new();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| anyhow!("Missing value of the option {}", arg))
+            };
+            match arg.as_str() {
+                "--locale" => format = OutputFormat::for_locale(value()?)?,
+                "--units" => units = Some(parse_units(value()?)?),
+                "--date-format" => date_format = Some(parse_date_format(value()?)?),
+                "--output" => style = parse_style(value()?)?,
+                _ => remaining.push(arg.clone()),
+            }
+        }
+
+        format.units = units.unwrap_or(format.units);
+        format.date_format = date_format.unwrap_or(format.date_format);
+        format.style = style;
+        Ok((format, remaining))
+    }
+
+    // Prints the result of a command, as text (only built when needed) or as a JSON or YAML document
+    pub fn print<T: Serialize>(&self, result: &T, text: impl FnOnce() -> String) -> Result<()> {
+        println!("{}", self.render(result, text)?);
+        Ok(())
+    }
+
+    fn render<T: Serialize>(&self, result: &T, text: impl FnOnce() -> String) -> Result<String> {
+        match self.style {
+            OutputStyle::Table => Ok(text()),
+            OutputStyle::Json => Ok(serde_json::to_string_pretty(result)?),
+            OutputStyle::Yaml => Ok(serde_yaml::to_string(result)?.trim_end().to_string()),
+        }
+    }
+
+    pub fn number(&self, value: f64, decimals: usize) -> String {
+        let formatted = format!("{:.*}", decimals, value.abs());
+        let (integer, fraction) = match formatted.split_once('.') {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (formatted.as_str(), None),
+        };
+
+        let mut grouped = String::new();
+        for (position, digit) in integer.chars().enumerate() {
+            let remaining_digits = integer.len() - position;
+            if position > 0 && remaining_digits % 3 == 0 {
+                grouped.extend(self.thousands_separator);
+            }
+            grouped.push(digit);
+        }
+        if let Some(fraction) = fraction {
+            grouped.push(self.decimal_separator);
+            grouped.push_str(fraction);
+        }
+
+        // no "-0" when a small negative value is rounded
+        let is_zero = formatted.chars().all(|c| c == '0' || c == '.');
+        match value < 0.0 && !is_zero {
+            true => format!("-{}", grouped),
+            false => grouped,
+        }
+    }
+
+    // Timestamps in milliseconds, in UTC
+    pub fn date(&self, timestamp_ms: i64) -> String {
+        Utc.timestamp_millis(timestamp_ms)
+            .format(&self.date_format)
+            .to_string()
+    }
+
+    // Formats a quantity as reported in the payloads (e.g. "500kg"), in the unit system of the output
+    // Quantities with an unknown unit keep it, and the ones that are not a number are left as they are
+    pub fn quantity(&self, quantity: &str) -> String {
+        let quantity = quantity.trim();
+        let number_end = quantity
+            .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+            .unwrap_or(quantity.len());
+        let value: f64 = match quantity[..number_end].parse() {
+            Ok(value) => value,
+            Err(_) => return quantity.to_string(),
+        };
+        let unit = quantity[number_end..].trim();
+
+        let (value, unit) = match self.units {
+            UnitSystem::Metric => (value, unit.to_string()),
+            UnitSystem::Imperial => to_imperial(value, unit),
+        };
+        let decimals = match value.fract() == 0.0 {
+            true => 0,
+            false => 2,
+        };
+        match unit.is_empty() {
+            true => self.number(value, decimals),
+            false => format!("{} {}", self.number(value, decimals), unit),
+        }
+    }
+}
+
+fn to_imperial(value: f64, unit: &str) -> (f64, String) {
+    let (converted, imperial_unit) = match unit.to_lowercase().as_str() {
+        "kg" => (value * 2.204623, "lb"),
+        "g" => (value * 0.035274, "oz"),
+        "t" => (value * 2204.623, "lb"),
+        "l" => (value * 0.264172, "gal"),
+        "ml" => (value * 0.033814, "fl oz"),
+        "km" => (value * 0.621371, "mi"),
+        "m" => (value * 3.28084, "ft"),
+        "c" | "°c" => (value * 9.0 / 5.0 + 32.0, "°F"),
+        _ => return (value, unit.to_string()),
+    };
+
+    (converted, imperial_unit.to_string())
+}
+
+fn parse_units(units: &str) -> Result<UnitSystem> {
+    match units {
+        "metric" => Ok(UnitSystem::Metric),
+        "imperial" => Ok(UnitSystem::Imperial),
+        _ => bail!("Unknown units `{}`, expected metric or imperial", units),
+    }
+}
+
+fn parse_style(style: &str) -> Result<OutputStyle> {
+    match style {
+        "table" => Ok(OutputStyle::Table),
+        "json" => Ok(OutputStyle::Json),
+        "yaml" => Ok(OutputStyle::Yaml),
+        _ => bail!("Unknown output `{}`, expected table, json or yaml", style),
+    }
+}
+
+// An invalid format would only fail when printing the first date
+fn parse_date_format(date_format: &str) -> Result<String> {
+    if StrftimeItems::new(date_format).any(|item| item == Item::Error) {
+        bail!("Invalid date format `{}`", date_format);
+    }
+
+    Ok(date_format.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_format_numbers_for_a_locale() {
+        let french = OutputFormat::for_locale("fr-CA").unwrap();
+        assert_eq!(french.number(1234567.891, 2), "1 234 567,89");
+        assert_eq!(french.number(-950.0, 0), "-950");
+        assert_eq!(french.number(-0.001, 2), "0,00");
+
+        let default = OutputFormat::default();
+        assert_eq!(default.number(1234567.891, 1), "1234567.9");
+        assert_eq!(default.date(0), "1970-01-01 00:00 UTC");
+
+        let german = OutputFormat::for_locale("de").unwrap();
+        assert_eq!(german.date(86_400_000), "02.01.1970 00:00");
+
+        assert!(OutputFormat::for_locale("xx").is_err());
+    }
+
+    #[test]
+    fn should_convert_quantities() {
+        let american = OutputFormat::for_locale("en-US").unwrap();
+        assert_eq!(american.quantity("500kg"), "1,102.31 lb");
+        assert_eq!(american.quantity("4 °C"), "39.20 °F");
+        assert_eq!(american.quantity("12 crates"), "12 crates");
+        assert_eq!(american.quantity("a lot"), "a lot");
+
+        let french = OutputFormat::for_locale("fr").unwrap();
+        assert_eq!(french.quantity("1234.5kg"), "1 234,50 kg");
+    }
+
+    #[test]
+    fn should_take_the_options_out_of_the_arguments() {
+        let args: Vec<String> = ["20", "--locale", "en-US", "--units", "metric", "1000"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+
+        let (format, remaining) = OutputFormat::from_args(&args).unwrap();
+        assert_eq!(remaining, vec!["20", "1000"]);
+        assert_eq!(format.units, UnitSystem::Metric);
+        assert_eq!(format.number(1000.0, 0), "1,000");
+
+        let invalid = ["--date-format".to_string(), "%Q".to_string()];
+        assert!(OutputFormat::from_args(&invalid).is_err());
+        assert!(OutputFormat::from_args(&["--locale".to_string()]).is_err());
+    }
+
+    #[test]
+    fn should_render_documents_for_scripts() {
+        let args = ["--output".to_string(), "yaml".to_string()];
+        let (yaml, _) = OutputFormat::from_args(&args).unwrap();
+        let result = serde_json::json!({"batch_id": "WHEAT-001", "events": 4});
+        let text = || "Batch WHEAT-001".to_string();
+
+        assert_eq!(
+            yaml.render(&result, text).unwrap(),
+            "batch_id: WHEAT-001\nevents: 4"
+        );
+        let json = OutputFormat {
+            style: OutputStyle::Json,
+            ..OutputFormat::for_locale("fr").unwrap()
+        };
+        assert_eq!(
+            json.render(&1234.5, text).unwrap(),
+            "1234.5",
+            "the documents are not localized"
+        );
+        assert_eq!(
+            OutputFormat::default().render(&result, text).unwrap(),
+            "Batch WHEAT-001"
+        );
+
+        let invalid = ["--output".to_string(), "csv".to_string()];
+        assert!(OutputFormat::from_args(&invalid).is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::Utc;
+use isahc::ReadResponseExt;
+use serde_json::Value;
+
+use super::output_format::OutputFormat;
+use crate::analytics::{PickingBasis, PickingSuggestion};
+
+const USAGE: &str = "Usage: picking <node url> <address>";
+
+const DAY_MS: f64 = 86_400_000.0;
+
+// Prints the batches held by an actor (e.g. a warehouse) in the order to ship them,
+// with how long it holds each one and how long until it expires
+pub fn run(args: &[String], format: &OutputFormat) -> Result<()> {
+    let (address, custodian) = match args {
+        [address, custodian] => (address.trim_end_matches('/'), custodian),
+        _ => bail!(USAGE),
+    };
+
+    let url = format!("{}/custodians/{}/picking", address, custodian);
+    let mut response = isahc::get(url)?;
+    if !response.status().is_success() {
+        bail!(
+            "The node {} has no picking list for {}: {}",
+            address,
+            custodian,
+            response.text()?
+        );
+    }
+    let suggestions: Vec<PickingSuggestion> = serde_json::from_str(&response.text()?)
+        .map_err(|error| anyhow!("Invalid picking list from the node {}: {}", address, error))?;
+
+    let now = Utc::now().timestamp_millis();
+    format.print(&suggestions, || render_list(&suggestions, format, now))
+}
+
+fn render_list(suggestions: &[PickingSuggestion], format: &OutputFormat, now: i64) -> String {
+    if suggestions.is_empty() {
+        return "No batches in custody".to_string();
+    }
+
+    let days = |ms: i64| format.number(ms as f64 / DAY_MS, 1);
+    suggestions
+        .iter()
+        .map(|suggestion| {
+            let quantity = match &suggestion.quantity {
+                Some(Value::String(quantity)) => format.quantity(quantity),
+                Some(Value::Number(quantity)) => format.quantity(&quantity.to_string()),
+                Some(other) => other.to_string(),
+                None => "-".to_string(),
+            };
+            let expiry = match suggestion.expires_at {
+                Some(expires_at) if expires_at <= now => {
+                    format!("EXPIRED on {}", format.date(expires_at))
+                }
+                Some(expires_at) => format!(
+                    "expires in {} days ({})",
+                    days(expires_at - now),
+                    format.date(expires_at)
+                ),
+                None => "no shelf life".to_string(),
+            };
+            let basis = match suggestion.basis {
+                PickingBasis::Fefo => "FEFO",
+                PickingBasis::Fifo => "FIFO",
+            };
+
+            format!(
+                "{:>3}. {} [{}] {}, {}, held for {} days, {}",
+                suggestion.rank,
+                suggestion.batch_id,
+                basis,
+                suggestion.stage,
+                quantity,
+                days(now - suggestion.custody_since),
+                expiry
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_render_the_ages_and_expiries() {
+        let suggestion = |rank: usize, batch_id: &str, expires_at: Option<i64>| PickingSuggestion {
+            rank,
+            batch_id: batch_id.to_string(),
+            stage: "STORAGE".to_string(),
+            quantity: Some(Value::String("1000kg".to_string())),
+            custody_since: 0,
+            expires_at,
+            basis: match expires_at {
+                Some(_) => PickingBasis::Fefo,
+                None => PickingBasis::Fifo,
+            },
+        };
+        let suggestions = vec![
+            suggestion(1, "MILK-001", Some(86_400_000)),
+            suggestion(2, "BERRIES-001", Some(5 * 86_400_000)),
+            suggestion(3, "WHEAT-001", None),
+        ];
+
+        let format = OutputFormat::for_locale("de").unwrap();
+        let now = 2 * 86_400_000;
+        let list = render_list(&suggestions, &format, now);
+        let lines: Vec<&str> = list.lines().collect();
+        assert_eq!(
+            lines[0],
+            "  1. MILK-001 [FEFO] STORAGE, 1.000 kg, held for 2,0 days, EXPIRED on 02.01.1970 00:00"
+        );
+        assert!(lines[1].ends_with("expires in 3,0 days (06.01.1970 00:00)"));
+        assert!(lines[2].ends_with("no shelf life"));
+
+        assert_eq!(render_list(&[], &format, now), "No batches in custody");
+    }
+}
@@ -0,0 +1,271 @@
+use std::time::Instant;
+
+use anyhow::{anyhow, bail, Result};
+use ed25519_dalek::SigningKey;
+use serde::Serialize;
+
+use super::output_format::OutputFormat;
+use crate::model::{decode, encode, Address, Block, Blockchain, Encoding, Transaction};
+
+// A small chain with signed supply chain events (profiles, an SLA, a harvest, a transport and a storage),
+// mined once at REFERENCE_DIFFICULTY and bundled, so every build must accept exactly the same blocks
+const REFERENCE_CHAIN: &str = include_str!("reference_chain.json");
+const REFERENCE_DIFFICULTY: u32 = 8;
+
+// Known answers, a change of any of them means that the node is not compatible with the network anymore
+const GENESIS_HASH: &str = "0x10eef285deef7a4b7c82b22aa53589b7833df29de3814649c772bbd5c832f365";
+const REFERENCE_MERKLE_ROOT: &str =
+    "0x9cef763b77df17e8724c03b1a89f352e7740e58d55832173a20cf3c70ce6a497";
+const REFERENCE_TIP_HASH: &str = "0xbe8d5a2bd3fe21ee1c6f56e36d49d1479def5b02382758d791b7e9120dcf2f";
+
+// Signature of the profile of the reference farm (first event of the reference chain) with its key
+const REFERENCE_KEY: [u8; 32] = [7; 32];
+const REFERENCE_SIGNATURE: &str =
+    "d7963c356ccc77b00cd7d3877069cd30f540164b6fe7d6885ad06f971dd57cd8\
+    bd867bfdcfa31e3a74dc7aa7c56ff9a27dba579352e49d7b06286da5fc71b304";
+
+const MINED_BLOCKS: u64 = 3;
+const MINING_DIFFICULTY: u32 = 10;
+
+type Check = fn() -> Result<String>;
+
+const CHECKS: [(&str, Check); 5] = [
+    ("hash vectors", check_hash_vectors),
+    ("signature vectors", check_signature_vectors),
+    ("storage round-trip", check_storage_round_trip),
+    ("mining run", check_mining_run),
+    ("reference chain", check_reference_chain),
+];
+
+#[derive(Serialize)]
+struct CheckResult {
+    name: &'static str,
+    passed: bool,
+    elapsed_ms: u128,
+    // what was checked, or why it failed
+    details: String,
+}
+
+// Runs the conformance suite of the node on this machine, without any network or node running
+// Useful after building for a new platform, or before joining a network with a new version
+pub fn run(args: &[String], format: &OutputFormat) -> Result<()> {
+    if !args.is_empty() {
+        bail!("Usage: selftest");
+    }
+
+    let results: Vec<CheckResult> = CHECKS
+        .iter()
+        .map(|(name, check)| {
+            let started_at = Instant::now();
+            let result = check();
+            let elapsed_ms = started_at.elapsed().as_millis();
+            CheckResult {
+                name,
+                passed: result.is_ok(),
+                elapsed_ms,
+                details: result.unwrap_or_else(|error| error.to_string()),
+            }
+        })
+        .collect();
+    let failures = results.iter().filter(|result| !result.passed).count();
+
+    format.print(&results, || {
+        let mut lines: Vec<String> = results
+            .iter()
+            .map(|result| {
+                let status = match result.passed {
+                    true => "PASS",
+                    false => "FAIL",
+                };
+                format!(
+                    "{} {:<20} {:>6} ms  {}",
+                    status, result.name, result.elapsed_ms, result.details
+                )
+            })
+            .collect();
+        if failures == 0 {
+            lines.push(format!("All {} checks passed", CHECKS.len()));
+        }
+        lines.join("\n")
+    })?;
+
+    match failures {
+        0 => Ok(()),
+        _ => bail!("{} of {} checks failed", failures, CHECKS.len()),
+    }
+}
+
+fn reference_blocks() -> Result<Vec<Block>> {
+    serde_json::from_str(REFERENCE_CHAIN)
+        .map_err(|error| anyhow!("Invalid bundled reference chain: {}", error))
+}
+
+fn expect(name: &str, actual: String, expected: &str) -> Result<()> {
+    match actual == expected {
+        true => Ok(()),
+        false => bail!("{} is {}, expected {}", name, actual, expected),
+    }
+}
+
+fn check_hash_vectors() -> Result<String> {
+    let genesis = Blockchain::new(0).get_genesis_summary();
+    expect(
+        "the genesis hash",
+        format!("{:#x}", genesis.hash),
+        GENESIS_HASH,
+    )?;
+
+    let blocks = reference_blocks()?;
+    let block = blocks
+        .get(2)
+        .ok_or_else(|| anyhow!("Missing reference block 2"))?;
+    let merkle_root = format!("{:#x}", block.calculate_merkle_root());
+    expect(
+        "the merkle root of block 2",
+        merkle_root,
+        REFERENCE_MERKLE_ROOT,
+    )?;
+
+    let tip = blocks
+        .last()
+        .ok_or_else(|| anyhow!("Empty reference chain"))?;
+    let tip_hash = format!("{:#x}", tip.calculate_hash());
+    expect("the hash of the last block", tip_hash, REFERENCE_TIP_HASH)?;
+
+    Ok("genesis, merkle root and block hash".to_string())
+}
+
+fn check_signature_vectors() -> Result<String> {
+    let blocks = reference_blocks()?;
+    let signed = blocks
+        .get(1)
+        .and_then(|block| block.body.transactions.get(1))
+        .ok_or_else(|| anyhow!("Missing reference transaction"))?;
+
+    // ed25519 signatures are deterministic, signing again must give the same bytes
+    let mut transaction = Transaction {
+        signature: None,
+        ..signed.clone()
+    };
+    transaction.sign(&SigningKey::from_bytes(&REFERENCE_KEY))?;
+    let signature = transaction.signature.clone().unwrap_or_default();
+    expect("the signature", signature, REFERENCE_SIGNATURE)?;
+    transaction.verify()?;
+
+    // and any change after signing must be detected
+    transaction.data = format!("{} ", transaction.data.as_json()).into();
+    if transaction.verify().is_ok() {
+        bail!("A modified transaction kept a valid signature");
+    }
+
+    Ok("sign, verify and tamper detection".to_string())
+}
+
+// The chain lives in memory, so the storage formats are the encodings used to export it
+fn check_storage_round_trip() -> Result<String> {
+    let blocks = reference_blocks()?;
+    let expected = serde_json::to_value(&blocks)?;
+
+    let mut sizes = Vec::new();
+    for encoding in [Encoding::Binary, Encoding::CanonicalJson] {
+        let encoded = encode(&blocks, encoding)?;
+        if decode(&encoded, encoding)? != expected {
+            bail!("The blocks changed after a round-trip in {:?}", encoding);
+        }
+        // the encodings are deterministic, encoding twice must give the same bytes
+        if encode(&blocks, encoding)? != encoded {
+            bail!("The {:?} encoding is not deterministic", encoding);
+        }
+        sizes.push(format!("{:?} {} bytes", encoding, encoded.len()));
+    }
+
+    Ok(sizes.join(", "))
+}
+
+fn check_mining_run() -> Result<String> {
+    let blockchain = Blockchain::new(MINING_DIFFICULTY);
+    let miner = Address::from(
+        SigningKey::from_bytes(&REFERENCE_KEY)
+            .verifying_key()
+            .to_bytes(),
+    );
+
+    let started_at = Instant::now();
+    let mut attempts = 0;
+    for _ in 0..MINED_BLOCKS {
+        let last_block = blockchain.get_last_block();
+        let coinbase = Transaction::coinbase(miner.clone());
+        let mut block = Block::new(
+            last_block.header.index + 1,
+            0,
+            last_block.header.hash,
+            vec![coinbase],
+        );
+        while block.header.hash.leading_zeros() < MINING_DIFFICULTY {
+            block.header.nonce += 1;
+            block.header.hash = block.calculate_hash();
+        }
+        attempts += block.header.nonce + 1;
+        blockchain.add_block(block)?;
+    }
+
+    if !blockchain.is_valid() {
+        bail!("The mined chain is not valid");
+    }
+    let hash_rate = attempts as f64 / started_at.elapsed().as_secs_f64().max(0.001);
+    Ok(format!(
+        "{} blocks at difficulty {}, {:.0} hashes/s",
+        MINED_BLOCKS, MINING_DIFFICULTY, hash_rate
+    ))
+}
+
+fn check_reference_chain() -> Result<String> {
+    let blocks = reference_blocks()?;
+    let blockchain = Blockchain::new(REFERENCE_DIFFICULTY);
+
+    let genesis = blocks
+        .first()
+        .ok_or_else(|| anyhow!("Empty reference chain"))?;
+    if genesis.header.hash != blockchain.get_last_block().header.hash {
+        bail!("The reference chain has another genesis block");
+    }
+    for block in blocks.iter().skip(1) {
+        blockchain
+            .add_block(block.clone())
+            .map_err(|error| anyhow!("Block {} was rejected: {}", block.header.index, error))?;
+        // every event after the coinbase transaction is signed by its sender
+        for transaction in block.body.transactions.iter().skip(1) {
+            transaction.verify()?;
+        }
+    }
+
+    if !blockchain.is_valid() {
+        bail!("The reference chain is not valid");
+    }
+    Ok(format!("{} blocks accepted", blocks.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_pass_every_check() {
+        for (name, check) in CHECKS {
+            assert!(check().is_ok(), "the {} failed", name);
+        }
+    }
+
+    #[test]
+    fn should_reject_a_tampered_reference_chain() {
+        let tampered = REFERENCE_CHAIN.replacen("495kg", "950kg", 1);
+        let mut blocks: Vec<Block> = serde_json::from_str(&tampered).unwrap();
+        let blockchain = Blockchain::new(REFERENCE_DIFFICULTY);
+        let last = blocks.pop().unwrap();
+        for block in blocks.into_iter().skip(1) {
+            blockchain.add_block(block).unwrap();
+        }
+
+        assert!(blockchain.add_block(last).is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+};
+
+use anyhow::{bail, Result};
+
+use super::output_format::OutputFormat;
+use crate::{
+    model::{Transaction, NORMALIZATION_VERSION},
+    wallet::Wallet,
+};
+
+const USAGE: &str = "Usage: sign-transaction <key file|-> <file|json>";
+
+// Signs a transaction with the secret key of its sender (32 bytes in hex, e.g. from "openssl rand -hex 32")
+// and prints it ready to be submitted, so clients can sign without an ed25519 library
+// The key is read from a file, or from the standard input with "-", never from the command line where
+// it would be visible in the list of processes and in the shell history (see also "wallet sign")
+// If the key does not belong to the sender, the error tells the address of the key
+pub fn run(args: &[String], format: &OutputFormat) -> Result<()> {
+    let (key_file, input) = match args {
+        [key_file, input] => (key_file, input),
+        _ => bail!(USAGE),
+    };
+
+    let wallet = Wallet::from_secret_hex(&read_secret_key(key_file)?)?;
+    sign_and_print(&wallet, input, false, format)
+}
+
+fn read_secret_key(key_file: &str) -> Result<String> {
+    let mut secret_key = String::new();
+    match key_file {
+        "-" => io::stdin().read_to_string(&mut secret_key)?,
+        path => fs::File::open(path)?.read_to_string(&mut secret_key)?,
+    };
+    Ok(secret_key)
+}
+
+// The payload can be normalized with the latest version first, as a signed payload can't be changed anymore
+pub fn sign_and_print(
+    wallet: &Wallet,
+    input: &str,
+    normalize: bool,
+    format: &OutputFormat,
+) -> Result<()> {
+    let mut transaction = read_transaction(input)?;
+    if normalize {
+        transaction.normalize(NORMALIZATION_VERSION)?;
+    }
+    wallet.sign(&mut transaction)?;
+
+    // the text is already JSON, ready to be submitted
+    let json = serde_json::to_string_pretty(&transaction)?;
+    format.print(&transaction, || json)
+}
+
+// The transaction is read from a file or given as JSON
+pub(super) fn read_transaction(input: &str) -> Result<Transaction> {
+    let json = match Path::new(input).is_file() {
+        true => fs::read_to_string(input)?,
+        false => input.to_string(),
+    };
+    Ok(serde_json::from_str(&json)?)
+}
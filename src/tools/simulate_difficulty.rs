@@ -0,0 +1,424 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use super::output_format::OutputFormat;
+use crate::model::Difficulty;
+
+// Blocks simulated for a hash rate when the scenario doesn't say how many
+const DEFAULT_SIMULATED_BLOCKS: usize = 1000;
+
+// Difficulties around the given one that are also reported, to compare the alternatives
+const COMPARED_DIFFICULTIES: u32 = 2;
+
+const USAGE: &str =
+    "Usage: simulate-difficulty <difficulty>[:<adjustment blocks>:<target block time ms>] \
+                     <blocks file | hashes per second[:blocks]...>";
+
+// A period of time mining at the same hash rate
+#[derive(Debug, Clone, PartialEq)]
+struct Scenario {
+    hashes_per_second: f64,
+    blocks: usize,
+}
+
+// Blocks mined in a scenario
+struct SimulatedScenario {
+    // in seconds
+    intervals: Vec<f64>,
+    // difficulty of the last block of the scenario
+    difficulty: u32,
+}
+
+// Distribution of the intervals between blocks, in seconds
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct IntervalSummary {
+    mean: f64,
+    median: f64,
+    p90: f64,
+    max: f64,
+}
+
+// Result of the simulation, with the intervals of the chain when the scenario comes from its blocks
+#[derive(Serialize)]
+struct Simulation {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    observed: Option<ObservedIntervals>,
+    scenarios: Vec<ScenarioResult>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct ObservedIntervals {
+    count: usize,
+    #[serde(flatten)]
+    summary: IntervalSummary,
+}
+
+#[derive(Serialize)]
+struct ScenarioResult {
+    hashes_per_second: f64,
+    blocks: usize,
+    intervals: IntervalSummary,
+    // only with an adjustable difficulty
+    #[serde(skip_serializing_if = "Option::is_none")]
+    final_difficulty: Option<u32>,
+    expected_intervals: Vec<ExpectedInterval>,
+}
+
+// Mean interval of a difficulty at the hash rate of a scenario
+#[derive(Serialize)]
+struct ExpectedInterval {
+    difficulty: u32,
+    seconds: f64,
+}
+
+// Only the timestamps of the blocks are needed
+#[derive(Deserialize)]
+struct TimedBlock {
+    index: u64,
+    timestamp: i64,
+}
+
+// Simulates the intervals between blocks that a difficulty produces, to choose it before launching a network
+// The scenarios are hash rates (e.g. "2000000:500" is 500 blocks at 2 MH/s) or the blocks of a chain
+// (e.g. the output of GET /blocks), whose hash rate is estimated from their timestamps
+// With a fixed difficulty the intervals grow or shrink with the hash rate of the network,
+// while an adjustable one brings them back to the target block time after each change of the hash rate
+pub fn run(args: &[String], format: &OutputFormat) -> Result<()> {
+    let difficulty = parse_difficulty(args.first().ok_or_else(|| anyhow!(USAGE))?)?;
+    let inputs = &args[1..];
+    if inputs.is_empty() {
+        bail!(USAGE);
+    }
+
+    let (scenarios, observed) = match Path::new(&inputs[0]).is_file() {
+        true => {
+            let (scenarios, observed) =
+                scenarios_from_history(&fs::read_to_string(&inputs[0])?, &difficulty)?;
+            (scenarios, Some(observed))
+        }
+        false => {
+            let scenarios = inputs
+                .iter()
+                .map(|input| parse_scenario(input))
+                .collect::<Result<Vec<Scenario>>>()?;
+            (scenarios, None)
+        }
+    };
+
+    // the scenarios are mined one after the other, so an adjustable difficulty carries over between them
+    let mut rng = StdRng::from_entropy();
+    let simulated = simulate(&difficulty, &scenarios, &mut rng);
+    let results = scenarios
+        .iter()
+        .zip(simulated.iter())
+        .map(|(scenario, simulated)| {
+            let current = simulated.difficulty;
+            let first = current.saturating_sub(COMPARED_DIFFICULTIES);
+            let expected_intervals = (first..=current + COMPARED_DIFFICULTIES)
+                .map(|other| ExpectedInterval {
+                    difficulty: other,
+                    seconds: expected_interval(other, scenario.hashes_per_second),
+                })
+                .collect();
+
+            ScenarioResult {
+                hashes_per_second: scenario.hashes_per_second,
+                blocks: scenario.blocks,
+                intervals: IntervalSummary::new(&simulated.intervals),
+                final_difficulty: Some(current).filter(|_| difficulty.is_adjustable()),
+                expected_intervals,
+            }
+        })
+        .collect();
+
+    let simulation = Simulation {
+        observed,
+        scenarios: results,
+    };
+    format.print(&simulation, || render_simulation(&simulation, format))
+}
+
+fn render_simulation(simulation: &Simulation, format: &OutputFormat) -> String {
+    let mut lines = Vec::new();
+    if let Some(observed) = &simulation.observed {
+        lines.push(format!(
+            "observed {} intervals: {}",
+            format.number(observed.count as f64, 0),
+            observed.summary.describe(format)
+        ));
+    }
+
+    for result in simulation.scenarios.iter() {
+        lines.push(format!(
+            "{} H/s, {} blocks: {}",
+            format.number(result.hashes_per_second, 0),
+            format.number(result.blocks as f64, 0),
+            result.intervals.describe(format)
+        ));
+        if let Some(final_difficulty) = result.final_difficulty {
+            lines.push(format!("  difficulty at the end: {}", final_difficulty));
+        }
+
+        let alternatives: Vec<String> = result
+            .expected_intervals
+            .iter()
+            .map(|expected| {
+                format!(
+                    "{}: {}s",
+                    expected.difficulty,
+                    format.number(expected.seconds, 2)
+                )
+            })
+            .collect();
+        lines.push(format!(
+            "  expected interval by difficulty: {}",
+            alternatives.join(", ")
+        ));
+    }
+    // the intervals of a real chain are longer
+    lines.push(
+        "Not included: the time the miner waits for transactions (BLOCK_INTERVAL_*_MS)".to_string(),
+    );
+
+    lines.join("\n")
+}
+
+// A difficulty, optionally followed by the amount of blocks between adjustments and the target block time
+fn parse_difficulty(input: &str) -> Result<Difficulty> {
+    let invalid = || {
+        anyhow!(
+            "Invalid difficulty `{}`, expected <leading zero bits>[:<adjustment blocks>:<target block time ms>]",
+            input
+        )
+    };
+
+    let parts: Vec<&str> = input.split(':').collect();
+    match parts[..] {
+        [initial] => Ok(Difficulty::fixed(initial.parse().map_err(|_| invalid())?)),
+        [initial, adjustment_blocks, target_ms] => Ok(Difficulty::new(
+            initial.parse().map_err(|_| invalid())?,
+            adjustment_blocks.parse().map_err(|_| invalid())?,
+            target_ms.parse().map_err(|_| invalid())?,
+        )),
+        _ => Err(invalid()),
+    }
+}
+
+// A hash rate, optionally followed by the amount of blocks mined at it
+fn parse_scenario(input: &str) -> Result<Scenario> {
+    let invalid = || {
+        anyhow!(
+            "Invalid scenario `{}`, expected <hashes per second>[:blocks]",
+            input
+        )
+    };
+
+    let (rate, blocks) = match input.split_once(':') {
+        Some((rate, blocks)) => (rate, blocks.parse().map_err(|_| invalid())?),
+        None => (input, DEFAULT_SIMULATED_BLOCKS),
+    };
+    let hashes_per_second: f64 = rate.parse().map_err(|_| invalid())?;
+    if hashes_per_second <= 0.0 || !hashes_per_second.is_finite() || blocks == 0 {
+        return Err(invalid());
+    }
+
+    Ok(Scenario {
+        hashes_per_second,
+        blocks,
+    })
+}
+
+// Estimates the hash rate that mined the blocks, as the work of their difficulties over the time they took
+// The intervals also include the time the miner waited for transactions, so the real hash rate is higher
+fn scenarios_from_history(
+    raw_blocks: &str,
+    difficulty: &Difficulty,
+) -> Result<(Vec<Scenario>, ObservedIntervals)> {
+    let mut blocks: Vec<TimedBlock> = serde_json::from_str(raw_blocks)?;
+    // the genesis block has a fixed timestamp
+    blocks.retain(|block| block.index > 0);
+    blocks.sort_by_key(|block| block.index);
+
+    let intervals: Vec<f64> = blocks
+        .windows(2)
+        .map(|pair| (pair[1].timestamp - pair[0].timestamp).max(0) as f64 / 1000.0)
+        .collect();
+    if intervals.is_empty() {
+        bail!("At least three blocks (including the genesis) are needed to measure intervals");
+    }
+
+    let observed = ObservedIntervals {
+        count: intervals.len(),
+        summary: IntervalSummary::new(&intervals),
+    };
+
+    // the difficulty of each block depends on the timestamps of the previous ones
+    let timestamp_of = |index: u64| {
+        blocks
+            .iter()
+            .find(|block| block.index == index)
+            .map_or(0, |block| block.timestamp)
+    };
+    let work: f64 = blocks
+        .iter()
+        .skip(1)
+        .map(|block| expected_hashes(difficulty.at(block.index, timestamp_of)))
+        .sum();
+
+    // at least a millisecond, a chain mined instantly would have an infinite hash rate
+    let elapsed = (observed.summary.mean * intervals.len() as f64).max(0.001);
+    let scenario = Scenario {
+        hashes_per_second: work / elapsed,
+        blocks: intervals.len(),
+    };
+    Ok((vec![scenario], observed))
+}
+
+// A hash meets the difficulty with a probability of 2^-difficulty
+fn expected_hashes(difficulty: u32) -> f64 {
+    2f64.powi(difficulty as i32)
+}
+
+fn expected_interval(difficulty: u32, hashes_per_second: f64) -> f64 {
+    expected_hashes(difficulty) / hashes_per_second
+}
+
+// Each hash is an independent trial, so the time to find a block is exponentially distributed
+// The difficulty of each block is derived from the simulated timestamps, as the nodes do with the real ones
+fn simulate(
+    difficulty: &Difficulty,
+    scenarios: &[Scenario],
+    rng: &mut impl Rng,
+) -> Vec<SimulatedScenario> {
+    // in milliseconds, starting with the genesis block
+    let mut timestamps = vec![0.0];
+
+    scenarios
+        .iter()
+        .map(|scenario| {
+            let mut current = difficulty.initial();
+            let intervals = (0..scenario.blocks)
+                .map(|_| {
+                    let index = timestamps.len() as u64;
+                    current = difficulty.at(index, |previous| timestamps[previous as usize] as i64);
+                    let mean = expected_interval(current, scenario.hashes_per_second);
+
+                    // never zero, to keep the logarithm finite
+                    let uniform: f64 = 1.0 - rng.gen::<f64>();
+                    let interval = -uniform.ln() * mean;
+                    timestamps.push(timestamps.last().unwrap() + interval * 1000.0);
+                    interval
+                })
+                .collect();
+
+            SimulatedScenario {
+                intervals,
+                difficulty: current,
+            }
+        })
+        .collect()
+}
+
+impl IntervalSummary {
+    fn new(intervals: &[f64]) -> IntervalSummary {
+        let mut sorted = intervals.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let percentile = |p: f64| sorted[((sorted.len() - 1) as f64 * p).round() as usize];
+
+        IntervalSummary {
+            mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+            median: percentile(0.5),
+            p90: percentile(0.9),
+            max: *sorted.last().unwrap(),
+        }
+    }
+
+    fn describe(&self, format: &OutputFormat) -> String {
+        format!(
+            "mean {}s, median {}s, p90 {}s, max {}s",
+            format.number(self.mean, 2),
+            format.number(self.median, 2),
+            format.number(self.p90, 2),
+            format.number(self.max, 2)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_scenarios() {
+        let scenario = parse_scenario("2000000:500").unwrap();
+        assert_eq!(scenario.hashes_per_second, 2_000_000.0);
+        assert_eq!(scenario.blocks, 500);
+
+        assert_eq!(
+            parse_scenario("1e6").unwrap().blocks,
+            DEFAULT_SIMULATED_BLOCKS
+        );
+        assert!(parse_scenario("fast").is_err());
+        assert!(parse_scenario("-5:10").is_err());
+        assert!(parse_scenario("1000:0").is_err());
+    }
+
+    #[test]
+    fn should_simulate_the_expected_intervals() {
+        // 2^20 hashes per block at 2^20 H/s is one block per second
+        let scenario = Scenario {
+            hashes_per_second: 1048576.0,
+            blocks: 20000,
+        };
+        let mut rng = StdRng::seed_from_u64(42);
+
+        let simulated = simulate(&Difficulty::fixed(20), &[scenario], &mut rng);
+        let summary = IntervalSummary::new(&simulated[0].intervals);
+        assert!((summary.mean - 1.0).abs() < 0.05);
+        // the median of an exponential distribution is ln(2) times its mean
+        assert!((summary.median - 2f64.ln()).abs() < 0.05);
+        assert!(summary.p90 > summary.median && summary.max > summary.p90);
+    }
+
+    #[test]
+    fn should_estimate_the_hash_rate_of_a_chain() {
+        let blocks = r#"[
+            {"index": 0, "timestamp": 0},
+            {"index": 1, "timestamp": 10000},
+            {"index": 2, "timestamp": 12000},
+            {"index": 3, "timestamp": 16000}
+        ]"#;
+
+        let (scenarios, observed) = scenarios_from_history(blocks, &Difficulty::fixed(10)).unwrap();
+        // a mean interval of 3 seconds
+        assert_eq!(scenarios[0].blocks, 2);
+        assert!((scenarios[0].hashes_per_second - 1024.0 / 3.0).abs() < 1e-9);
+        assert_eq!(observed.count, 2);
+        assert_eq!(observed.summary.max, 4.0);
+
+        let genesis_only = r#"[{"index": 0, "timestamp": 0}]"#;
+        assert!(scenarios_from_history(genesis_only, &Difficulty::fixed(10)).is_err());
+    }
+
+    #[test]
+    fn should_bring_the_intervals_back_to_the_target() {
+        // 2^20 H/s, so a difficulty of 20 is a block per second, but the target is 4 seconds
+        let difficulty = parse_difficulty("20:10:4000").unwrap();
+        let scenario = Scenario {
+            hashes_per_second: 1048576.0,
+            blocks: 2000,
+        };
+        let mut rng = StdRng::seed_from_u64(42);
+
+        let simulated = simulate(&difficulty, &[scenario], &mut rng);
+        assert_eq!(simulated[0].difficulty, 22);
+        let last_intervals = &simulated[0].intervals[1000..];
+        let summary = IntervalSummary::new(last_intervals);
+        assert!((summary.mean - 4.0).abs() < 1.0);
+
+        assert!(parse_difficulty("20:10").is_err());
+    }
+}
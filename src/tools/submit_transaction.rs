@@ -0,0 +1,63 @@
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use isahc::{ReadResponseExt, Request, RequestExt};
+use serde::{Deserialize, Serialize};
+
+use super::{output_format::OutputFormat, sign_transaction::read_transaction, wallet};
+use crate::{model::transaction_hash, wallet::Wallet};
+
+#[derive(Serialize, Deserialize)]
+struct TransactionSubmission {
+    // hash of the transaction, to follow it once mined (computed here when the node doesn't return it)
+    #[serde(default)]
+    hash: String,
+    added: bool,
+    warnings: Vec<String>,
+}
+
+// Submits a transaction to a node, signed first with the keystore of its sender if given
+// The warnings of the node (e.g. a probable duplicate) are printed, the transaction is accepted anyway
+pub fn run(
+    address: &str,
+    input: &str,
+    keystore: Option<&Path>,
+    format: &OutputFormat,
+) -> Result<()> {
+    let mut transaction = read_transaction(input)?;
+    if let Some(keystore) = keystore {
+        let wallet = Wallet::load(keystore, &wallet::read_password()?)?;
+        wallet.sign(&mut transaction)?;
+    }
+
+    let mut response = Request::post(format!("{}/transactions", address))
+        .header("Content-Type", "application/json")
+        .body(serde_json::to_vec(&transaction)?)?
+        .send()?;
+    if !response.status().is_success() {
+        bail!(
+            "The node {} refused the transaction: {}",
+            address,
+            response.text()?
+        );
+    }
+    let mut submission: TransactionSubmission = serde_json::from_str(&response.text()?)
+        .map_err(|error| anyhow!("Invalid answer from the node {}: {}", address, error))?;
+    if submission.hash.is_empty() {
+        submission.hash = format!("{:#x}", transaction_hash(&transaction));
+    }
+
+    format.print(&submission, || {
+        let mut lines = vec![match submission.added {
+            true => format!("Submitted the transaction {}", submission.hash),
+            false => format!("The transaction {} was already pending", submission.hash),
+        }];
+        lines.extend(
+            submission
+                .warnings
+                .iter()
+                .map(|warning| format!("  warning: {}", warning)),
+        );
+        lines.join("\n")
+    })
+}
@@ -0,0 +1,61 @@
+use anyhow::{anyhow, bail, Result};
+use serde::Serialize;
+
+use super::output_format::OutputFormat;
+use crate::{
+    storage::{self, SledStore, StorageCipher},
+    util::Config,
+};
+
+#[derive(Serialize)]
+struct ChainValidation {
+    storage: String,
+    // the genesis block and the valid blocks that follow it
+    valid_blocks: u64,
+    valid: bool,
+    // why the next block is not valid, if any
+    reason: Option<String>,
+}
+
+// Validates again every stored block of the local node with the rules of its configuration (.env file),
+// like the node does when it starts, e.g. after restoring a backup or moving the storage to another disk
+pub fn run(format: &OutputFormat) -> Result<()> {
+    let config = Config::read()?;
+    if config.storage_path.is_empty() {
+        bail!("The node has no storage to validate, see STORAGE_PATH");
+    }
+    // sled locks its folder, so it cannot be read while the node runs
+    let cipher =
+        StorageCipher::from_config(&config.storage_encryption_key, &config.storage_key_command)?;
+    let store = SledStore::open(&config.storage_path, cipher).map_err(|error| {
+        anyhow!(
+            "Cannot open the storage at {}, is the node running? {}",
+            config.storage_path,
+            error
+        )
+    })?;
+
+    let blockchain = crate::create_blockchain(&config)?;
+    let result = storage::restore_chain(&store, &blockchain);
+    let validation = ChainValidation {
+        storage: config.storage_path.clone(),
+        valid_blocks: blockchain.get_last_block().header.index + 1,
+        valid: result.is_ok(),
+        reason: result.err().map(|error| error.to_string()),
+    };
+    format.print(&validation, || match &validation.reason {
+        None => format!(
+            "The chain stored in {} is valid ({} blocks)",
+            validation.storage, validation.valid_blocks
+        ),
+        Some(reason) => format!(
+            "Only the first {} blocks stored in {} are valid: {}",
+            validation.valid_blocks, validation.storage, reason
+        ),
+    })?;
+
+    match validation.valid {
+        true => Ok(()),
+        false => bail!("The stored chain is not valid"),
+    }
+}
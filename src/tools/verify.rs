@@ -0,0 +1,124 @@
+use anyhow::{anyhow, bail, Result};
+use serde::Serialize;
+use serde_json::Value;
+
+use super::{decode::read_input, output_format::OutputFormat};
+use crate::verify::{
+    verify_batch_bundle, verify_block, verify_transaction_inclusion, BatchBundle, InclusionProof,
+    VerifyError,
+};
+
+#[derive(Serialize)]
+struct Verification {
+    // "block", "inclusion proof" or "batch bundle"
+    kind: &'static str,
+    valid: bool,
+    // what was verified, or why it's not valid
+    details: String,
+}
+
+// Verifies offline what a consumer app would: a block in binary (e.g. from the storage of a node),
+// or the JSON of an inclusion proof (GET /blocks/{index}/proofs/{position}) or of a batch bundle
+// (GET /batches/{batch_id}/bundle), from a file or a hex string
+pub fn run(args: &[String], format: &OutputFormat) -> Result<()> {
+    let input = args
+        .first()
+        .ok_or_else(|| anyhow!("Usage: verify <file|hex>"))?;
+    let data = read_input(input)?;
+
+    let (kind, result) = verify_any(&data)?;
+    let verification = Verification {
+        kind,
+        valid: result.is_ok(),
+        details: result.unwrap_or_else(|error| error.to_string()),
+    };
+    format.print(&verification, || {
+        let status = match verification.valid {
+            true => "Valid",
+            false => "Invalid",
+        };
+        format!("{} {}: {}", status, verification.kind, verification.details)
+    })?;
+
+    match verification.valid {
+        true => Ok(()),
+        false => bail!("The {} is not valid", kind),
+    }
+}
+
+// JSON is a proof or a bundle, anything else is the binary encoding of a block
+fn verify_any(data: &[u8]) -> Result<(&'static str, Result<String, VerifyError>)> {
+    let json: Value = match serde_json::from_slice(data) {
+        Ok(json @ Value::Object(_)) => json,
+        _ => {
+            let result = verify_block(data).map(|block| {
+                format!(
+                    "block {} with {} transactions",
+                    block.header.index,
+                    block.body.transactions.len()
+                )
+            });
+            return Ok(("block", result));
+        }
+    };
+
+    if json.get("events").is_some() {
+        let bundle: BatchBundle = serde_json::from_value(json)?;
+        let result = verify_batch_bundle(&bundle).map(|_| {
+            format!(
+                "{} events of batch {}",
+                bundle.events.len(),
+                bundle.batch_id
+            )
+        });
+        return Ok(("batch bundle", result));
+    }
+
+    let inclusion: InclusionProof = serde_json::from_value(json)?;
+    let result = verify_transaction_inclusion(&inclusion).map(|hash| {
+        format!(
+            "transaction {:#x} in block {}",
+            hash, inclusion.header.index
+        )
+    });
+    Ok(("inclusion proof", result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{fixtures::alice, Block, BlockHash, Transaction};
+
+    #[test]
+    fn should_detect_what_to_verify() {
+        let transaction = Transaction {
+            sender: alice(),
+            recipient: alice(),
+            batch_id: "WHEAT-1".to_string(),
+            event_type: "HARVEST".into(),
+            ..Default::default()
+        };
+        let block = Block::new(1, 0, BlockHash::zero(), vec![transaction]);
+
+        let (kind, result) = verify_any(&block.to_bytes().unwrap()).unwrap();
+        assert_eq!(kind, "block");
+        assert_eq!(result, Ok("block 1 with 1 transactions".to_string()));
+
+        let inclusion = InclusionProof {
+            header: block.header.clone(),
+            proof: block.proof_for(0).unwrap(),
+        };
+        let json = serde_json::to_vec(&inclusion).unwrap();
+        let (kind, result) = verify_any(&json).unwrap();
+        assert_eq!(kind, "inclusion proof");
+        assert!(result.is_ok());
+
+        let bundle = serde_json::json!({"batch_id": "WHEAT-1", "events": []});
+        let (kind, result) = verify_any(&serde_json::to_vec(&bundle).unwrap()).unwrap();
+        assert_eq!(kind, "batch bundle");
+        assert_eq!(result, Ok("0 events of batch WHEAT-1".to_string()));
+
+        // JSON that is neither a proof nor a bundle
+        assert!(verify_any(br#"{"index": 1}"#).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+use anyhow::{anyhow, bail, Result};
+use isahc::ReadResponseExt;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{output_format::OutputFormat, sign_transaction::read_transaction};
+use crate::{
+    model::{transaction_hash, BlockHeader},
+    util::Config,
+    verify::{InclusionProof, LightClient},
+};
+
+#[derive(Serialize)]
+struct TransactionVerification {
+    block: u64,
+    position: usize,
+    // hash of the transaction proven to be in the block
+    transaction_hash: Option<String>,
+    // the headers checked from the genesis of the network
+    headers: u64,
+    valid: bool,
+    // why the transaction is not proven, if so
+    reason: Option<String>,
+}
+
+// Verifies that a transaction is in the chain of a node like a light client does (e.g. from the block and
+// the position of an event printed on the QR code of a batch): only the headers up to its block are downloaded
+// and checked from the genesis and the difficulty of the local configuration, then the inclusion proof
+// of the transaction against the merkle root of its header
+// With the transaction (from a file or as JSON), the proof must be about that exact transaction
+pub fn run(
+    address: &str,
+    index: u64,
+    position: usize,
+    transaction: Option<&str>,
+    format: &OutputFormat,
+) -> Result<()> {
+    let expected = transaction.map(read_transaction).transpose()?;
+    let config = Config::read()?;
+    let blockchain = crate::create_blockchain(&config)?;
+    let genesis = blockchain.get_block(0).unwrap().header;
+    let mut client = LightClient::new(genesis, blockchain.difficulty());
+
+    let headers: Vec<BlockHeader> = fetch(address, &format!("headers?from=1&to={}", index + 1))?;
+    let inclusion: InclusionProof =
+        fetch(address, &format!("blocks/{}/proofs/{}", index, position))?;
+    let result = client
+        .add_headers(&headers)
+        .and_then(|_| client.verify_inclusion(index, &inclusion.proof))
+        .map_err(|error| error.to_string())
+        .and_then(|hash| match &expected {
+            Some(expected) if transaction_hash(expected) != hash => Err(format!(
+                "The proof is about the transaction {:#x}, not the given one",
+                hash
+            )),
+            _ => Ok(hash),
+        });
+
+    let verification = TransactionVerification {
+        block: index,
+        position,
+        transaction_hash: result.as_ref().ok().map(|hash| format!("{:#x}", hash)),
+        headers: client.height(),
+        valid: result.is_ok(),
+        reason: result.err(),
+    };
+    format.print(&verification, || match &verification.reason {
+        None => format!(
+            "The transaction {} is in block {} of the chain ({} headers checked)",
+            verification.transaction_hash.as_deref().unwrap_or_default(),
+            index,
+            verification.headers
+        ),
+        Some(reason) => format!(
+            "The transaction {} of block {} is not proven: {}",
+            position, index, reason
+        ),
+    })?;
+
+    match verification.valid {
+        true => Ok(()),
+        false => bail!("The transaction is not in the chain of {}", address),
+    }
+}
+
+fn fetch<T: DeserializeOwned>(address: &str, path: &str) -> Result<T> {
+    let mut response = isahc::get(format!("{}/{}", address, path))?;
+    if !response.status().is_success() {
+        bail!(
+            "The node {} refused GET /{}: {}",
+            address,
+            path,
+            response.text()?
+        );
+    }
+
+    serde_json::from_str(&response.text()?)
+        .map_err(|error| anyhow!("Invalid answer from the node {}: {}", address, error))
+}
@@ -0,0 +1,89 @@
+use std::{env, path::Path, str::FromStr};
+
+use anyhow::{anyhow, bail, Result};
+use serde::Serialize;
+
+use super::{output_format::OutputFormat, sign_transaction::sign_and_print};
+use crate::{
+    model::{Address, BlockHash},
+    wallet::Wallet,
+};
+
+// The password is not an argument, so it's not visible in the list of processes or in the shell history
+const PASSWORD_VAR: &str = "WALLET_PASSWORD";
+
+#[derive(Serialize)]
+struct WalletInfo {
+    address: Address,
+    keystore: String,
+}
+
+// Manages the keypair of an actor in an encrypted keystore file
+// e.g. "WALLET_PASSWORD=... agriblock wallet new farm.json" prints the address of the new actor
+pub fn new(keystore: &Path, format: &OutputFormat) -> Result<()> {
+    let password = read_password()?;
+    if keystore.exists() {
+        bail!("The keystore {} already exists", keystore.display());
+    }
+    let wallet = Wallet::generate();
+    wallet.save(keystore, &password)?;
+    print_info(&wallet, keystore, format)
+}
+
+pub fn address(keystore: &Path, format: &OutputFormat) -> Result<()> {
+    let wallet = Wallet::load(keystore, &read_password()?)?;
+    print_info(&wallet, keystore, format)
+}
+
+// Signs a transaction with the key of the keystore, see sign-transaction
+pub fn sign(keystore: &Path, input: &str, normalize: bool, format: &OutputFormat) -> Result<()> {
+    let wallet = Wallet::load(keystore, &read_password()?)?;
+    sign_and_print(&wallet, input, normalize, format)
+}
+
+// Signs the grade of an inspector for a quality check graded by several of them, to hand to the party that submits it
+pub fn attest(
+    keystore: &Path,
+    batch_id: &str,
+    grade: &str,
+    weight: u64,
+    threshold: u64,
+    format: &OutputFormat,
+) -> Result<()> {
+    let wallet = Wallet::load(keystore, &read_password()?)?;
+    let attestation = wallet.attest_quality(batch_id, grade, weight, threshold);
+
+    // the text is already JSON, ready to be added to the attestations of the check
+    let json = serde_json::to_string_pretty(&attestation)?;
+    format.print(&attestation, || json)
+}
+
+// Signs the statement of a regulator about a block, to submit to POST /blocks/{hash}/attestations
+pub fn attest_block(
+    keystore: &Path,
+    block_hash: &str,
+    statement: &str,
+    format: &OutputFormat,
+) -> Result<()> {
+    let block_hash = BlockHash::from_str(block_hash)
+        .map_err(|_| anyhow!("Invalid block hash {}", block_hash))?;
+    let wallet = Wallet::load(keystore, &read_password()?)?;
+    let attestation = wallet.attest_block(block_hash, statement);
+
+    // the text is already JSON, ready to be submitted
+    let json = serde_json::to_string_pretty(&attestation)?;
+    format.print(&attestation, || json)
+}
+
+pub(super) fn read_password() -> Result<String> {
+    env::var(PASSWORD_VAR)
+        .map_err(|_| anyhow!("The password of the keystore must be in {}", PASSWORD_VAR))
+}
+
+fn print_info(wallet: &Wallet, keystore: &Path, format: &OutputFormat) -> Result<()> {
+    let info = WalletInfo {
+        address: wallet.address(),
+        keystore: keystore.display().to_string(),
+    };
+    format.print(&info, || info.address.to_string())
+}
@@ -0,0 +1,222 @@
+use std::net::TcpStream;
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use tungstenite::{stream::MaybeTlsStream, Message, WebSocket};
+
+use super::output_format::OutputFormat;
+use crate::model::{BlockRef, Transaction};
+
+// A change of the events of a watched batch, as the node pushes them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum BatchUpdate {
+    // the event was accepted in the pool of the node
+    Pending {
+        hash: String,
+        transaction: Transaction,
+    },
+    // the event was mined in a block, or mined again in another block after a reorganization
+    Mined {
+        hash: String,
+        transaction: Transaction,
+        block: BlockRef,
+    },
+    // the blocks after the ancestor were replaced, the events mined in them are pending again until mined again
+    Reorganized {
+        ancestor: u64,
+        rolled_back: u64,
+    },
+}
+
+// The notifications of the WebSocket of the node that concern the watched batch, the others are skipped
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
+enum Notification {
+    TransactionPending {
+        hash: String,
+        transaction: Transaction,
+    },
+    TransactionMined {
+        hash: String,
+        transaction: Transaction,
+        block: BlockRef,
+    },
+    ChainReorganized {
+        ancestor: u64,
+        rolled_back: u64,
+    },
+    Error {
+        message: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+// The new events of a batch on a node, with the changes of their status, followed through its WebSocket (/ws)
+// Each update is read as it arrives, so iterating blocks until the node pushes the next one
+pub struct BatchWatch {
+    socket: WebSocket<MaybeTlsStream<TcpStream>>,
+}
+
+// Starts following a batch on a node, e.g. watch_batch("http://localhost:8000", "WHEAT-001")
+// Only the events after the subscription are pushed, the previous ones are in the history of the batch
+pub fn watch_batch(address: &str, batch_id: &str) -> Result<BatchWatch> {
+    let url = format!("{}/ws?batch_id={}", websocket_address(address)?, batch_id);
+    let (socket, _) = tungstenite::connect(url)
+        .map_err(|error| anyhow!("Cannot follow the batch on the node {}: {}", address, error))?;
+
+    Ok(BatchWatch { socket })
+}
+
+impl Iterator for BatchWatch {
+    type Item = Result<BatchUpdate>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let text = match self.socket.read() {
+                Ok(Message::Text(text)) => text,
+                Ok(Message::Close(_)) => return None,
+                Ok(_) => continue,
+                Err(tungstenite::Error::ConnectionClosed) => return None,
+                Err(error) => return Some(Err(error.into())),
+            };
+            match parse_update(&text) {
+                Ok(Some(update)) => return Some(Ok(update)),
+                Ok(None) => continue,
+                Err(error) => return Some(Err(error)),
+            }
+        }
+    }
+}
+
+// Prints the updates of a batch as they arrive, until the node closes the connection
+pub fn run(address: &str, batch_id: &str, format: &OutputFormat) -> Result<()> {
+    let watch = watch_batch(address, batch_id)?;
+    // printed once subscribed, so the events from then on are all printed
+    eprintln!("Watching the batch {} on the node {}", batch_id, address);
+    for update in watch {
+        let update = update?;
+        format.print(&update, || render_update(&update, format))?;
+    }
+
+    Ok(())
+}
+
+fn parse_update(text: &str) -> Result<Option<BatchUpdate>> {
+    let notification: Notification = serde_json::from_str(text)
+        .map_err(|error| anyhow!("Invalid notification of the node: {}", error))?;
+
+    let update = match notification {
+        Notification::TransactionPending { hash, transaction } => {
+            BatchUpdate::Pending { hash, transaction }
+        }
+        Notification::TransactionMined {
+            hash,
+            transaction,
+            block,
+        } => BatchUpdate::Mined {
+            hash,
+            transaction,
+            block,
+        },
+        Notification::ChainReorganized {
+            ancestor,
+            rolled_back,
+        } => BatchUpdate::Reorganized {
+            ancestor,
+            rolled_back,
+        },
+        Notification::Error { message } => bail!("The node refused the subscription: {}", message),
+        Notification::Other => return Ok(None),
+    };
+    Ok(Some(update))
+}
+
+// The WebSocket of a node from its HTTP address
+fn websocket_address(address: &str) -> Result<String> {
+    match address.split_once("://") {
+        Some(("http", rest)) => Ok(format!("ws://{}", rest)),
+        Some(("https", rest)) => Ok(format!("wss://{}", rest)),
+        _ => bail!("The address of the node must start with http:// or https://"),
+    }
+}
+
+fn render_update(update: &BatchUpdate, format: &OutputFormat) -> String {
+    match update {
+        BatchUpdate::Pending { hash, transaction } => {
+            format!("pending   {:<16} {}", transaction.event_type.as_str(), hash)
+        }
+        BatchUpdate::Mined {
+            hash,
+            transaction,
+            block,
+        } => format!(
+            "mined     {:<16} {}  block {} at {}",
+            transaction.event_type.as_str(),
+            hash,
+            block.index,
+            format.date(block.timestamp)
+        ),
+        BatchUpdate::Reorganized {
+            ancestor,
+            rolled_back,
+        } => format!(
+            "reorganized after block {}, {} blocks rolled back",
+            ancestor, rolled_back
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_follow_the_status_of_the_events() {
+        let transaction = Transaction {
+            batch_id: "WHEAT-1".to_string(),
+            event_type: "HARVEST".into(),
+            ..Default::default()
+        };
+        let pending = serde_json::json!({
+            "type": "TRANSACTION_PENDING",
+            "hash": "0x1",
+            "transaction": transaction,
+        });
+        let update = parse_update(&pending.to_string()).unwrap();
+        assert!(matches!(
+            update,
+            Some(BatchUpdate::Pending { hash, transaction }) if hash == "0x1" && transaction.batch_id == "WHEAT-1"
+        ));
+
+        let reorganized =
+            r#"{"type": "CHAIN_REORGANIZED", "ancestor": 4, "rolled_back": 2, "headers": []}"#;
+        assert!(matches!(
+            parse_update(reorganized).unwrap(),
+            Some(BatchUpdate::Reorganized {
+                ancestor: 4,
+                rolled_back: 2
+            })
+        ));
+
+        // the other notifications don't concern the batch
+        let block = r#"{"type": "BLOCK_ADDED", "header": {}}"#;
+        assert!(parse_update(block).unwrap().is_none());
+        let error = r#"{"type": "ERROR", "message": "At most 100 batches can be followed"}"#;
+        assert!(parse_update(error).is_err());
+    }
+
+    #[test]
+    fn should_reach_the_websocket_of_the_node() {
+        assert_eq!(
+            websocket_address("http://localhost:8000").unwrap(),
+            "ws://localhost:8000"
+        );
+        assert_eq!(
+            websocket_address("https://node.example.org").unwrap(),
+            "wss://node.example.org"
+        );
+        assert!(websocket_address("localhost:8000").is_err());
+    }
+}
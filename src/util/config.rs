@@ -1,10 +1,11 @@
 extern crate dotenv;
 
+use anyhow::{bail, Result};
 use dotenv::dotenv;
 use std::env;
 use std::str::FromStr;
 
-use crate::model::Address;
+use crate::model::{Address, QuantityStrictness};
 
 type StringVec = Vec<String>;
 
@@ -13,41 +14,250 @@ type StringVec = Vec<String>;
 pub struct Config {
     // Networking settings
     pub port: u16,
+    pub public_address: String,
+    pub query_cache_size: usize,
+    pub duplicate_window_ms: u64,
+
+    // Audit settings
+    pub origins_file: String,
+    pub origins_record_ip: bool,
+    pub admin_token: String,
+    pub node_key_file: String,
+    pub regulator_keys: StringVec,
+    pub attestations_file: String,
+
+    // Storage settings
+    pub storage_path: String,
+    pub orphan_archive_limit: usize,
+    pub storage_encryption_key: String,
+    pub storage_key_command: String,
+    pub changes_file: String,
+    pub saved_queries_file: String,
+    pub replica_of: String,
+    pub replication_poll_ms: u64,
 
     // Peer settings
     pub peers: StringVec,
+    pub peer_dns_seeds: StringVec,
+    pub peer_allowlist: StringVec,
+    pub peers_file: String,
     pub peer_sync_ms: u64,
 
+    // Gossip network settings
+    pub p2p_listen_address: String,
+    // not read by the builds without the gossip network
+    #[cfg_attr(not(feature = "gossip"), allow(dead_code))]
+    pub p2p_bootstrap: StringVec,
+    #[cfg_attr(not(feature = "gossip"), allow(dead_code))]
+    pub p2p_mdns: bool,
+
     // Miner settings
     pub max_blocks: u64,
     pub max_nonce: u64,
+    pub max_hash_rate: u64,
+    pub mining_duty_cycle: u64,
     pub difficulty: u32,
+    pub difficulty_adjustment_blocks: u64,
+    pub target_block_time_ms: u64,
+    pub genesis_file: String,
+    pub max_custody_events: usize,
+    pub custody_events_window_ms: u64,
+    pub quantity_strictness: QuantityStrictness,
     pub tx_waiting_ms: u64,
+    pub block_interval_min_ms: u64,
+    pub block_interval_max_ms: u64,
+    pub block_interval_full_pool: u64,
+    pub max_transactions_per_block: usize,
+    pub pool_max_age_ms: u64,
     pub miner_address: Address,
+
+    // Cluster settings
+    pub cluster_lease_file: String,
+    pub cluster_node_id: String,
+    pub cluster_lease_ms: u64,
+
+    // Timestamping settings
+    pub finality_depth: u64,
+    pub timestamp_every_n_blocks: u64,
+    pub timestamp_authority_url: String,
+    pub timestamp_token_dir: String,
+
+    // Analytics settings
+    pub anomaly_window_ms: u64,
+    pub anomaly_alert_score: f64,
+
+    // Public statistics settings
+    pub public_stats_min_contributors: usize,
+    pub public_stats_epsilon: f64,
+    pub public_stats_max_contribution: f64,
+    pub public_stats_epoch_blocks: u64,
+    pub public_stats_salt: String,
+
+    // Testnet settings
+    pub testnet: bool,
+    pub faucet_registrar_key: String,
 }
 
 // The implementation reads the values from environment variables
 // If a value is missing then it enforces a default value
 impl Config {
     // Parse and return configuration values from environment variables
-    pub fn read() -> Config {
+    // Fails when the values contradict each other, instead of the node misbehaving once started
+    pub fn read() -> Result<Config> {
         dotenv().ok();
 
-        Config {
+        let port = Config::read_envvar::<u16>("PORT", 8000);
+        let default_public_address = format!("http://localhost:{}", port);
+        let public_address =
+            Config::read_envvar::<String>("PUBLIC_ADDRESS", default_public_address);
+
+        let config = Config {
             // Networking settings
-            port: Config::read_envvar::<u16>("PORT", 8000),
+            port,
+            public_address,
+            query_cache_size: Config::read_envvar::<usize>("QUERY_CACHE_SIZE", 1000),
+            duplicate_window_ms: Config::read_envvar::<u64>("DUPLICATE_WINDOW_MS", 600_000),
+
+            // Audit settings
+            origins_file: Config::read_envvar::<String>("ORIGINS_FILE", String::default()),
+            origins_record_ip: Config::read_envvar::<bool>("ORIGINS_RECORD_IP", false),
+            admin_token: Config::read_envvar::<String>("ADMIN_TOKEN", String::default()),
+            node_key_file: Config::read_envvar::<String>("NODE_KEY_FILE", String::default()),
+            regulator_keys: Config::read_vec_envvar("REGULATOR_KEYS", ",", StringVec::default()),
+            attestations_file: Config::read_envvar::<String>(
+                "ATTESTATIONS_FILE",
+                String::default(),
+            ),
+
+            // Storage settings
+            storage_path: Config::read_envvar::<String>("STORAGE_PATH", String::default()),
+            orphan_archive_limit: Config::read_envvar::<usize>("ORPHAN_ARCHIVE_LIMIT", 1000),
+            storage_encryption_key: Config::read_envvar::<String>(
+                "STORAGE_ENCRYPTION_KEY",
+                String::default(),
+            ),
+            storage_key_command: Config::read_envvar::<String>(
+                "STORAGE_KEY_COMMAND",
+                String::default(),
+            ),
+            changes_file: Config::read_envvar::<String>("CHANGES_FILE", String::default()),
+            saved_queries_file: Config::read_envvar::<String>(
+                "SAVED_QUERIES_FILE",
+                String::default(),
+            ),
+            replica_of: Config::read_envvar::<String>("REPLICA_OF", String::default()),
+            replication_poll_ms: Config::read_envvar::<u64>("REPLICATION_POLL_MS", 1000),
 
             // Peer settings
             peers: Config::read_vec_envvar("PEERS", ",", StringVec::default()),
+            peer_dns_seeds: Config::read_vec_envvar("PEER_DNS_SEEDS", ",", StringVec::default()),
+            peer_allowlist: Config::read_vec_envvar("PEER_ALLOWLIST", ",", StringVec::default()),
+            peers_file: Config::read_envvar::<String>("PEERS_FILE", String::default()),
             peer_sync_ms: Config::read_envvar::<u64>("PEER_SYNC_MS", 10000),
 
+            // Gossip network settings
+            p2p_listen_address: Config::read_envvar::<String>(
+                "P2P_LISTEN_ADDRESS",
+                String::default(), // no gossip network
+            ),
+            p2p_bootstrap: Config::read_vec_envvar("P2P_BOOTSTRAP", ",", StringVec::default()),
+            p2p_mdns: Config::read_envvar::<bool>("P2P_MDNS", true),
+
             // Miner settings
             max_blocks: Config::read_envvar::<u64>("MAX_BLOCKS", 0), // unlimited blocks
             max_nonce: Config::read_envvar::<u64>("MAX_NONCE", 1_000_000),
+            max_hash_rate: Config::read_envvar::<u64>("MAX_HASH_RATE", 0), // unlimited
+            mining_duty_cycle: Config::read_envvar::<u64>("MINING_DUTY_CYCLE", 100),
             difficulty: Config::read_envvar::<u32>("DIFFICULTY", 10),
+            difficulty_adjustment_blocks: Config::read_envvar::<u64>(
+                "DIFFICULTY_ADJUSTMENT_BLOCKS",
+                0, // fixed difficulty
+            ),
+            target_block_time_ms: Config::read_envvar::<u64>("TARGET_BLOCK_TIME_MS", 10000),
+            genesis_file: Config::read_envvar::<String>("GENESIS_FILE", String::default()), // default network
+            max_custody_events: Config::read_envvar::<usize>("MAX_CUSTODY_EVENTS", 0), // unlimited
+            custody_events_window_ms: Config::read_envvar::<u64>(
+                "CUSTODY_EVENTS_WINDOW_MS",
+                60_000,
+            ),
+            quantity_strictness: Config::read_envvar::<QuantityStrictness>(
+                "QUANTITY_STRICTNESS",
+                QuantityStrictness::Flag,
+            ),
             tx_waiting_ms: Config::read_envvar::<u64>("TRANSACTION_WAITING_MS", 10000),
+            block_interval_min_ms: Config::read_envvar::<u64>("BLOCK_INTERVAL_MIN_MS", 0),
+            block_interval_max_ms: Config::read_envvar::<u64>("BLOCK_INTERVAL_MAX_MS", 0),
+            block_interval_full_pool: Config::read_envvar::<u64>("BLOCK_INTERVAL_FULL_POOL", 100),
+            max_transactions_per_block: Config::read_envvar::<usize>(
+                "MAX_TRANSACTIONS_PER_BLOCK",
+                0, // all the pending transactions
+            ),
+            pool_max_age_ms: Config::read_envvar::<u64>("POOL_MAX_AGE_MS", 0),
             miner_address: Config::read_envvar::<Address>("MINER_ADDRESS", Address::default()),
+
+            // Cluster settings
+            cluster_lease_file: Config::read_envvar::<String>(
+                "CLUSTER_LEASE_FILE",
+                String::default(),
+            ),
+            cluster_node_id: Config::read_envvar::<String>("CLUSTER_NODE_ID", String::default()),
+            cluster_lease_ms: Config::read_envvar::<u64>("CLUSTER_LEASE_MS", 10_000),
+
+            // Timestamping settings
+            finality_depth: Config::read_envvar::<u64>("FINALITY_DEPTH", 6),
+            timestamp_every_n_blocks: Config::read_envvar::<u64>("TIMESTAMP_EVERY_N_BLOCKS", 0),
+            timestamp_authority_url: Config::read_envvar::<String>(
+                "TIMESTAMP_AUTHORITY_URL",
+                "http://timestamp.digicert.com".to_string(),
+            ),
+            timestamp_token_dir: Config::read_envvar::<String>(
+                "TIMESTAMP_TOKEN_DIR",
+                "timestamps".to_string(),
+            ),
+
+            // Analytics settings
+            anomaly_window_ms: Config::read_envvar::<u64>("ANOMALY_WINDOW_MS", 60_000),
+            anomaly_alert_score: Config::read_envvar::<f64>("ANOMALY_ALERT_SCORE", 0.9),
+
+            // Public statistics settings
+            public_stats_min_contributors: Config::read_envvar::<usize>(
+                "PUBLIC_STATS_MIN_CONTRIBUTORS",
+                3,
+            ),
+            public_stats_epsilon: Config::read_envvar::<f64>("PUBLIC_STATS_EPSILON", 1.0),
+            public_stats_max_contribution: Config::read_envvar::<f64>(
+                "PUBLIC_STATS_MAX_CONTRIBUTION",
+                10_000.0,
+            ),
+            public_stats_epoch_blocks: Config::read_envvar::<u64>("PUBLIC_STATS_EPOCH_BLOCKS", 100),
+            public_stats_salt: Config::read_envvar::<String>(
+                "PUBLIC_STATS_SALT",
+                String::default(),
+            ),
+
+            // Testnet settings
+            testnet: Config::read_envvar::<bool>("TESTNET", false),
+            faucet_registrar_key: Config::read_envvar::<String>(
+                "FAUCET_REGISTRAR_KEY",
+                String::default(),
+            ),
+        };
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        // the interval shortens from the max to the min as the pool fills up
+        if self.block_interval_min_ms > self.block_interval_max_ms {
+            bail!(
+                "BLOCK_INTERVAL_MIN_MS ({}) cannot be greater than BLOCK_INTERVAL_MAX_MS ({})",
+                self.block_interval_min_ms,
+                self.block_interval_max_ms
+            );
         }
+
+        Ok(())
     }
 
     // Parses a singular value from a environment variable, accepting a default value if missing
@@ -82,7 +292,7 @@ mod tests {
         env::set_var(var_name, real_value.to_string());
 
         // read the present var, should NOT return the default value but the real one
-        let default_value = 8000 as u16;
+        let default_value = 8000_u16;
         let value = Config::read_envvar::<u16>(var_name, default_value);
 
         assert_eq!(value, real_value);
@@ -91,11 +301,25 @@ mod tests {
         env::remove_var(var_name);
     }
 
+    #[test]
+    fn reject_block_interval_min_above_max() {
+        // the intervals are changed on the config itself, setting the vars would break the other tests reading it
+        let mut config = Config::read().unwrap();
+        config.block_interval_min_ms = 5000;
+        config.block_interval_max_ms = 1000;
+
+        let error = config.validate().err().unwrap().to_string();
+        assert!(error.contains("BLOCK_INTERVAL_MIN_MS (5000)"));
+
+        config.block_interval_max_ms = 5000;
+        assert!(config.validate().is_ok());
+    }
+
     #[test]
     fn read_present_vec_envvar() {
         let var_name = "PRESENT_VEC_ENVVAR";
         let value = "FOO,BAR";
-        env::set_var(var_name, value.to_string());
+        env::set_var(var_name, value);
 
         // read the present var, should NOT return the default value but the real one
         let default_value = StringVec::default();
@@ -116,7 +340,7 @@ mod tests {
         env::remove_var(var_name);
 
         // read the non present var, should return the default value
-        let default_value = 8000 as u16;
+        let default_value = 8000_u16;
         let value = Config::read_envvar::<u16>(var_name, default_value);
         assert_eq!(value, default_value);
 
@@ -132,7 +356,7 @@ mod tests {
         let var_name = "INVALID=VAR=NAME";
 
         // read the invalid var, should return the default value
-        let default_value = 8000 as u16;
+        let default_value = 8000_u16;
         let value = Config::read_envvar::<u16>(var_name, default_value);
         assert_eq!(value, default_value);
 
@@ -143,7 +367,7 @@ mod tests {
     }
 
     // All credit for this function to https://stackoverflow.com/a/58175659
-    fn do_vecs_match<T: PartialEq>(a: &Vec<T>, b: &Vec<T>) -> bool {
+    fn do_vecs_match<T: PartialEq>(a: &[T], b: &[T]) -> bool {
         let matching = a.iter().zip(b.iter()).filter(|&(a, b)| a == b).count();
         matching == a.len() && matching == b.len()
     }
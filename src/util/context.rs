@@ -1,8 +1,28 @@
+use std::sync::Arc;
+
 use super::Config;
-use crate::model::{Blockchain, TransactionPool};
+use crate::{
+    analytics::AnomalyScores,
+    cluster::{LeaderLease, Maintenance},
+    model::{BlockAttestations, Blockchain, ChangeFeed, TransactionOrigins, TransactionPool},
+    peer::PeerList,
+    storage::{ChainVerifier, SharedChainStore},
+    wallet::Wallet,
+};
 
 pub struct Context {
     pub config: Config,
     pub blockchain: Blockchain,
     pub pool: TransactionPool,
+    pub origins: TransactionOrigins,
+    pub attestations: BlockAttestations,
+    pub changes: ChangeFeed,
+    pub peers: PeerList,
+    pub anomaly_scores: AnomalyScores,
+    pub leader_lease: LeaderLease,
+    pub maintenance: Maintenance,
+    pub store: Option<SharedChainStore>,
+    pub verification: ChainVerifier,
+    // signs the transactions built by the node itself, e.g. the lots it allocates
+    pub node_wallet: Arc<Wallet>,
 }
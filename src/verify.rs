@@ -0,0 +1,319 @@
+mod light_client;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use crate::model::{
+    transaction_hash, Block, BlockHeader, EncodingError, MerkleProof, Transaction,
+    TransactionError, TxHash, ValidationError,
+};
+
+pub use light_client::LightClient;
+
+// Verify-only facade for the apps of the consumers (e.g. scanning the QR code of a batch in a shop)
+// It only checks data against itself with the hashing, merkle and signature primitives of the chain:
+// no chain state, storage or network, so it's the only part of the node that such apps need to link
+// The headers still have to be trusted by other means, e.g. compared with the ones of a few nodes,
+// or followed from the genesis with a LightClient
+//
+// Stability: the signatures of these functions, the JSON of InclusionProof and BatchBundle, and the meaning
+// of the errors don't change within a minor version. New error variants can be added, so match them with a
+// wildcard. A change of the hashes of the chain is a new network, announced as a major version
+
+// Why some data doesn't prove what it claims
+#[derive(Error, PartialEq, Debug)]
+pub enum VerifyError {
+    #[error("Invalid encoding: {0}")]
+    InvalidEncoding(#[from] EncodingError),
+
+    #[error("The hash of block {0} does not match its contents")]
+    InvalidHash(u64),
+
+    #[error("The merkle root of block {0} does not match its transactions")]
+    InvalidMerkleRoot(u64),
+
+    #[error("Transaction {position} has an invalid signature: {error}")]
+    InvalidSignature {
+        position: usize,
+        error: TransactionError,
+    },
+
+    #[error("The proof does not lead to the merkle root of block {0}")]
+    InvalidProof(u64),
+
+    #[error("Event {0} does not match the transaction of its proof")]
+    TransactionMismatch(usize),
+
+    #[error("Event {0} is about another batch")]
+    WrongBatch(usize),
+
+    #[error("Event {0} is before the previous one in the chain")]
+    OutOfOrder(usize),
+
+    #[error("The header of block {index} does not follow the chain: {error}")]
+    InvalidHeader { index: u64, error: ValidationError },
+
+    #[error("Block {0} is not in the headers of the chain")]
+    UnknownBlock(u64),
+}
+
+// What a light client needs to check that a transaction is in the chain without the whole block
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct InclusionProof {
+    pub header: BlockHeader,
+    pub proof: MerkleProof,
+}
+
+// An event of a batch with the proof that it's in a block of the chain
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct BundledEvent {
+    pub transaction: Transaction,
+    pub inclusion: InclusionProof,
+}
+
+// The whole history of a batch that can be checked offline, in chain order
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct BatchBundle {
+    pub batch_id: String,
+    pub events: Vec<BundledEvent>,
+}
+
+// Decodes a block in the binary format of the storage and the gossip network, and checks its hash,
+// its merkle root and the signatures of its transactions
+// The difficulty and the link to the previous block depend on the chain, so they are not checked
+pub fn verify_block(bytes: &[u8]) -> Result<Block, VerifyError> {
+    let block = Block::from_bytes(bytes)?;
+
+    if block.header.hash != block.calculate_hash() {
+        return Err(VerifyError::InvalidHash(block.header.index));
+    }
+    if block.header.merkle_root != block.calculate_merkle_root() {
+        return Err(VerifyError::InvalidMerkleRoot(block.header.index));
+    }
+    // unsigned transactions are accepted, like the chain does
+    for (position, transaction) in block.body.transactions.iter().enumerate() {
+        if transaction.signature.is_some() {
+            transaction
+                .verify()
+                .map_err(|error| VerifyError::InvalidSignature { position, error })?;
+        }
+    }
+
+    Ok(block)
+}
+
+// Checks that the proof leads to the merkle root of a header with a valid hash,
+// and returns the hash of the transaction that it proves
+pub fn verify_transaction_inclusion(inclusion: &InclusionProof) -> Result<TxHash, VerifyError> {
+    let header = &inclusion.header;
+    if header.hash != header.calculate_hash() {
+        return Err(VerifyError::InvalidHash(header.index));
+    }
+    let proof = &inclusion.proof;
+    if !MerkleProof::verify(&header.merkle_root, &proof.transaction_hash, &proof.path) {
+        return Err(VerifyError::InvalidProof(header.index));
+    }
+
+    Ok(proof.transaction_hash)
+}
+
+// Checks every event of a batch: that it's about the batch, in chain order, signed by its sender when
+// it has a signature, and included in its block
+pub fn verify_batch_bundle(bundle: &BatchBundle) -> Result<(), VerifyError> {
+    let mut last_index = 0;
+    for (position, event) in bundle.events.iter().enumerate() {
+        let transaction = &event.transaction;
+        if transaction.batch_id != bundle.batch_id {
+            return Err(VerifyError::WrongBatch(position));
+        }
+
+        let index = event.inclusion.header.index;
+        if index < last_index {
+            return Err(VerifyError::OutOfOrder(position));
+        }
+        last_index = index;
+
+        if transaction.signature.is_some() {
+            transaction
+                .verify()
+                .map_err(|error| VerifyError::InvalidSignature { position, error })?;
+        }
+
+        let proven = verify_transaction_inclusion(&event.inclusion)?;
+        if proven != transaction_hash(transaction) {
+            return Err(VerifyError::TransactionMismatch(position));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::SigningKey;
+
+    use super::*;
+    use crate::model::{fixtures::bob, Address, BlockHash};
+
+    const KEY: [u8; 32] = [7; 32];
+
+    #[test]
+    fn should_verify_a_block_from_its_bytes() {
+        let block = create_block(1, BlockHash::from(1), "WHEAT-1");
+        let verified = verify_block(&block.to_bytes().unwrap()).unwrap();
+        assert_eq!(verified.header.hash, block.header.hash);
+
+        let mut tampered = block.clone();
+        tampered.header.nonce += 1;
+        assert_eq!(
+            verify_block(&tampered.to_bytes().unwrap()).err(),
+            Some(VerifyError::InvalidHash(1))
+        );
+
+        // the hash only covers the transactions through the merkle root
+        let mut tampered = block.clone();
+        tampered.body.transactions[1].batch_id = "WHEAT-2".to_string();
+        assert_eq!(
+            verify_block(&tampered.to_bytes().unwrap()).err(),
+            Some(VerifyError::InvalidMerkleRoot(1))
+        );
+
+        // a signature made by another key, in a block with a matching merkle root and hash
+        let mut forged = block.body.transactions.clone();
+        forged[0].sender = bob();
+        let forged = Block::new(1, 0, BlockHash::from(1), forged);
+        assert!(matches!(
+            verify_block(&forged.to_bytes().unwrap()),
+            Err(VerifyError::InvalidSignature { position: 0, .. })
+        ));
+
+        assert_eq!(
+            verify_block(b"not a block").err(),
+            Some(VerifyError::InvalidEncoding(EncodingError::InvalidData))
+        );
+    }
+
+    #[test]
+    fn should_verify_the_inclusion_of_a_transaction() {
+        let block = create_block(1, BlockHash::from(1), "WHEAT-1");
+        for position in 0..block.body.transactions.len() {
+            let inclusion = create_inclusion(&block, position);
+            assert_eq!(
+                verify_transaction_inclusion(&inclusion),
+                Ok(transaction_hash(&block.body.transactions[position]))
+            );
+        }
+
+        // a proof of another transaction
+        let mut inclusion = create_inclusion(&block, 0);
+        inclusion.proof.transaction_hash = TxHash::from(42);
+        assert_eq!(
+            verify_transaction_inclusion(&inclusion),
+            Err(VerifyError::InvalidProof(1))
+        );
+
+        // a header whose merkle root was replaced by the one of the proof
+        let mut inclusion = create_inclusion(&block, 0);
+        inclusion.header.merkle_root = TxHash::from(42);
+        inclusion.proof.transaction_hash = TxHash::from(42);
+        inclusion.proof.path.clear();
+        assert_eq!(
+            verify_transaction_inclusion(&inclusion),
+            Err(VerifyError::InvalidHash(1))
+        );
+    }
+
+    #[test]
+    fn should_verify_the_events_of_a_batch() {
+        let first = create_block(1, BlockHash::from(1), "WHEAT-1");
+        let second = create_block(2, first.header.hash, "WHEAT-1");
+        let bundle = BatchBundle {
+            batch_id: "WHEAT-1".to_string(),
+            events: vec![
+                create_event(&first, 0),
+                create_event(&first, 1),
+                create_event(&second, 0),
+            ],
+        };
+        assert_eq!(verify_batch_bundle(&bundle), Ok(()));
+
+        let check = |change: fn(&mut BatchBundle)| {
+            let mut tampered = bundle.clone();
+            change(&mut tampered);
+            verify_batch_bundle(&tampered)
+        };
+        assert_eq!(
+            check(|bundle| bundle.batch_id = "WHEAT-2".to_string()),
+            Err(VerifyError::WrongBatch(0))
+        );
+        assert_eq!(
+            check(|bundle| bundle.events.swap(1, 2)),
+            Err(VerifyError::OutOfOrder(2))
+        );
+        // the unsigned event is changed, so its signature doesn't protect it
+        assert_eq!(
+            check(|bundle| bundle.events[1].transaction.data = "{}".into()),
+            Err(VerifyError::TransactionMismatch(1))
+        );
+        assert!(matches!(
+            check(|bundle| bundle.events[0].transaction.data = "{}".into()),
+            Err(VerifyError::InvalidSignature { position: 0, .. })
+        ));
+        assert_eq!(
+            check(|bundle| bundle.events[2].inclusion.header.nonce += 1),
+            Err(VerifyError::InvalidHash(2))
+        );
+
+        // and a proof of another event of the batch
+        assert_eq!(
+            check(|bundle| {
+                bundle.events[1].inclusion = bundle.events[0].inclusion.clone();
+            }),
+            Err(VerifyError::TransactionMismatch(1))
+        );
+
+        // a batch without events proves nothing, but nothing is wrong with it
+        let empty = BatchBundle {
+            batch_id: "WHEAT-1".to_string(),
+            events: Vec::new(),
+        };
+        assert_eq!(verify_batch_bundle(&empty), Ok(()));
+    }
+
+    // A block with a signed event and an unsigned one of a batch
+    fn create_block(index: u64, previous_hash: BlockHash, batch_id: &str) -> Block {
+        let key = SigningKey::from_bytes(&KEY);
+        let farm = Address::from(key.verifying_key().to_bytes());
+        let mut signed = Transaction {
+            sender: farm.clone(),
+            recipient: farm.clone(),
+            data: r#"{"temperature": 4.5}"#.into(),
+            batch_id: batch_id.to_string(),
+            event_type: "STORAGE".into(),
+            ..Default::default()
+        };
+        signed.sign(&key).unwrap();
+        let unsigned = Transaction {
+            signature: None,
+            recipient: bob(),
+            ..signed.clone()
+        };
+
+        Block::new(index, 0, previous_hash, vec![signed, unsigned])
+    }
+
+    fn create_inclusion(block: &Block, position: usize) -> InclusionProof {
+        InclusionProof {
+            header: block.header.clone(),
+            proof: block.proof_for(position).unwrap(),
+        }
+    }
+
+    fn create_event(block: &Block, position: usize) -> BundledEvent {
+        BundledEvent {
+            transaction: block.body.transactions[position].clone(),
+            inclusion: create_inclusion(block, position),
+        }
+    }
+}
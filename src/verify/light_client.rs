@@ -0,0 +1,163 @@
+use super::VerifyError;
+use crate::{
+    chaos,
+    model::{BlockHeader, BlockValidator, Difficulty, DifficultyPeriods, MerkleProof, TxHash},
+};
+
+// Verifier of the transactions of a chain that only keeps the headers of its blocks, e.g. in the app of a shop
+// that checks the harvest of a batch when its QR code is scanned, without the whole chain
+// The headers are checked like a node does (index, link, timestamp, hash and difficulty) from a genesis
+// that the client trusts, so a full node can prove that a transaction is in a block but cannot make one up
+// Only one chain is followed: headers that don't extend the known ones are refused
+#[derive(Debug, Clone)]
+pub struct LightClient {
+    difficulties: DifficultyPeriods,
+    // from the genesis, the index of each header is its position
+    headers: Vec<BlockHeader>,
+}
+
+impl LightClient {
+    // Starts from the genesis of the network, which must come from a trusted source (e.g. its genesis file)
+    pub fn new(genesis: BlockHeader, difficulty: Difficulty) -> LightClient {
+        LightClient {
+            difficulties: DifficultyPeriods::new(difficulty),
+            headers: vec![genesis],
+        }
+    }
+
+    // Index of the last known header
+    pub fn height(&self) -> u64 {
+        self.headers.len() as u64 - 1
+    }
+
+    pub fn header(&self, index: u64) -> Option<&BlockHeader> {
+        self.headers.get(index as usize)
+    }
+
+    // Appends the headers that follow the known ones (e.g. from GET /headers of a node), and returns the new height
+    // The known headers sent again are skipped, and the headers before an invalid one are kept
+    pub fn add_headers(&mut self, headers: &[BlockHeader]) -> Result<u64, VerifyError> {
+        let validator = BlockValidator::at(chaos::now_millis());
+        for header in headers {
+            if self.header(header.index) == Some(header) {
+                continue;
+            }
+
+            let previous = self.headers.last().unwrap();
+            // the difficulty depends on the timestamps of the previous headers, which are all known
+            validator
+                .validate_header(previous, header, self.difficulties.at(header.index))
+                .map_err(|error| VerifyError::InvalidHeader {
+                    index: header.index,
+                    error,
+                })?;
+            self.headers.push(header.clone());
+            let headers = &self.headers;
+            self.difficulties
+                .apply(header.index, |index| headers[index as usize].timestamp);
+        }
+
+        Ok(self.height())
+    }
+
+    // Checks the proof given by a full node that a transaction is in a known block, against the merkle root
+    // of the header of the client, and returns the hash of the transaction that it proves
+    pub fn verify_inclusion(&self, index: u64, proof: &MerkleProof) -> Result<TxHash, VerifyError> {
+        let header = self.header(index).ok_or(VerifyError::UnknownBlock(index))?;
+        match MerkleProof::verify(&header.merkle_root, &proof.transaction_hash, &proof.path) {
+            true => Ok(proof.transaction_hash),
+            false => Err(VerifyError::InvalidProof(index)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        fixtures::alice, transaction_hash, Block, BlockHash, Transaction, ValidationError,
+    };
+
+    #[test]
+    fn should_only_follow_the_headers_of_the_chain() {
+        let blocks = create_chain(3);
+        let mut client = LightClient::new(blocks[0].header.clone(), Difficulty::fixed(0));
+        assert_eq!(client.height(), 0);
+
+        let headers: Vec<BlockHeader> = blocks.iter().map(|block| block.header.clone()).collect();
+        assert_eq!(client.add_headers(&headers[..2]), Ok(1));
+        // the known headers are skipped
+        assert_eq!(client.add_headers(&headers), Ok(2));
+        assert_eq!(client.header(2), Some(&headers[2]));
+
+        // a competing block, or one with a hash too easy for the difficulty
+        let competing = Block::new(2, 7, blocks[1].header.hash, Vec::new())
+            .header
+            .clone();
+        assert!(matches!(
+            client.add_headers(&[competing]),
+            Err(VerifyError::InvalidHeader {
+                index: 2,
+                error: ValidationError::InvalidIndex { .. }
+            })
+        ));
+        let mut client = LightClient::new(blocks[0].header.clone(), Difficulty::fixed(255));
+        assert!(matches!(
+            client.add_headers(&headers),
+            Err(VerifyError::InvalidHeader {
+                index: 1,
+                error: ValidationError::InvalidDifficulty { .. }
+            })
+        ));
+        assert_eq!(client.height(), 0);
+    }
+
+    #[test]
+    fn should_verify_the_transactions_of_the_known_blocks() {
+        let blocks = create_chain(2);
+        let mut client = LightClient::new(blocks[0].header.clone(), Difficulty::fixed(0));
+        let headers: Vec<BlockHeader> = blocks.iter().map(|block| block.header.clone()).collect();
+        client.add_headers(&headers).unwrap();
+
+        let proof = blocks[1].proof_for(1).unwrap();
+        assert_eq!(
+            client.verify_inclusion(1, &proof),
+            Ok(transaction_hash(&blocks[1].body.transactions[1]))
+        );
+        assert_eq!(
+            client.verify_inclusion(0, &proof),
+            Err(VerifyError::InvalidProof(0))
+        );
+        assert_eq!(
+            client.verify_inclusion(2, &proof),
+            Err(VerifyError::UnknownBlock(2))
+        );
+
+        // a full node cannot prove a transaction that is not in the block
+        let mut forged = proof.clone();
+        forged.transaction_hash = TxHash::from(42);
+        assert_eq!(
+            client.verify_inclusion(1, &forged),
+            Err(VerifyError::InvalidProof(1))
+        );
+    }
+
+    // A genesis block followed by blocks of two harvests each
+    fn create_chain(length: u64) -> Vec<Block> {
+        let mut blocks = vec![Block::new(0, 0, BlockHash::zero(), Vec::new())];
+        for index in 1..length {
+            let harvests = (0..2)
+                .map(|position| Transaction {
+                    sender: alice(),
+                    recipient: alice(),
+                    batch_id: format!("WHEAT-{}-{}", index, position),
+                    event_type: "HARVEST".into(),
+                    ..Default::default()
+                })
+                .collect();
+            let previous_hash = blocks.last().unwrap().header.hash;
+            blocks.push(Block::new(index, 0, previous_hash, harvests));
+        }
+        blocks
+    }
+}
@@ -0,0 +1,340 @@
+use std::{fs, io::Write, path::Path};
+
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Nonce,
+};
+use anyhow::Result;
+use ed25519_dalek::SigningKey;
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::model::{
+    Address, BlockAttestation, BlockHash, QualityAttestation, Transaction, TransactionError,
+};
+
+const KEYSTORE_VERSION: u32 = 1;
+const KDF: &str = "scrypt";
+const CIPHER: &str = "aes-256-gcm";
+
+// Cost of the key derivation: 2^15 iterations and 32 MiB of memory, about a tenth of a second on a laptop
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+
+#[derive(Error, PartialEq, Debug)]
+pub enum WalletError {
+    #[error("The secret key must be 32 bytes in hex")]
+    InvalidSecretKey,
+
+    #[error("Wrong password, or the keystore was modified")]
+    WrongPassword,
+
+    #[error("Unsupported keystore: {0}")]
+    UnsupportedKeystore(String),
+}
+
+// An ed25519 keypair of an actor, its address is the public key
+#[derive(Clone)]
+pub struct Wallet {
+    key: SigningKey,
+}
+
+impl Wallet {
+    // Generates a new keypair from the random generator of the operating system
+    pub fn generate() -> Wallet {
+        let mut secret = [0u8; KEY_LEN];
+        OsRng.fill_bytes(&mut secret);
+        Wallet::from_secret(secret)
+    }
+
+    pub fn from_secret(secret: [u8; KEY_LEN]) -> Wallet {
+        Wallet {
+            key: SigningKey::from_bytes(&secret),
+        }
+    }
+
+    // e.g. from "openssl rand -hex 32"
+    pub fn from_secret_hex(secret: &str) -> Result<Wallet, WalletError> {
+        hex::decode(secret.trim())
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(Wallet::from_secret)
+            .ok_or(WalletError::InvalidSecretKey)
+    }
+
+    pub fn address(&self) -> Address {
+        Address::from(self.key.verifying_key().to_bytes())
+    }
+
+    // The reverse of from_secret_hex, to hand the keypair over to its actor
+    pub fn secret_hex(&self) -> String {
+        hex::encode(self.key.to_bytes())
+    }
+
+    // Only the transactions sent by the address of the wallet can be signed
+    pub fn sign(&self, transaction: &mut Transaction) -> Result<(), TransactionError> {
+        transaction.sign(&self.key)
+    }
+
+    // The grade given to a batch by the inspector of the wallet, for a check graded by several inspectors
+    pub fn attest_quality(
+        &self,
+        batch_id: &str,
+        grade: &str,
+        weight: u64,
+        threshold: u64,
+    ) -> QualityAttestation {
+        QualityAttestation::sign(&self.key, batch_id, grade, weight, threshold)
+    }
+
+    // The statement of the regulator of the wallet about a block, signed now
+    pub fn attest_block(&self, block_hash: BlockHash, statement: &str) -> BlockAttestation {
+        let signed_at = chrono::Utc::now().timestamp_millis();
+        BlockAttestation::sign(&self.key, block_hash, statement, signed_at)
+    }
+
+    // Writes the keypair to a keystore file, with the secret key encrypted with the password
+    pub fn save(&self, path: &Path, password: &str) -> Result<()> {
+        let keystore = Keystore::encrypt(self, password, SCRYPT_LOG_N)?;
+        fs::write(path, serde_json::to_string_pretty(&keystore)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path, password: &str) -> Result<Wallet> {
+        let keystore: Keystore = serde_json::from_str(&fs::read_to_string(path)?)?;
+        Ok(keystore.decrypt(password)?)
+    }
+
+    // The keypair of a file with the secret key in hex, e.g. the key of a node, generated on the first start
+    // Only the owner of the file can read it, like a private ssh key
+    pub fn open_secret_file(path: &Path) -> Result<Wallet> {
+        if path.exists() {
+            return Ok(Wallet::from_secret_hex(&fs::read_to_string(path)?)?);
+        }
+
+        let wallet = Wallet::generate();
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(path)?
+            .write_all(wallet.secret_hex().as_bytes())?;
+        Ok(wallet)
+    }
+}
+
+// Parameters of the key derivation, stored so the cost can be raised without breaking the older keystores
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct KdfParams {
+    log_n: u8,
+    r: u32,
+    p: u32,
+    salt: String,
+}
+
+// The file of a wallet: its address in clear, and its secret key encrypted with a key derived from a password
+// The address is authenticated with the secret key, so it cannot be changed to impersonate another actor
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Keystore {
+    version: u32,
+    address: Address,
+    kdf: String,
+    kdf_params: KdfParams,
+    cipher: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+impl Keystore {
+    fn encrypt(wallet: &Wallet, password: &str, log_n: u8) -> Result<Keystore> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+
+        let kdf_params = KdfParams {
+            log_n,
+            r: SCRYPT_R,
+            p: SCRYPT_P,
+            salt: hex::encode(salt),
+        };
+        let cipher = Keystore::cipher(password, &kdf_params)?;
+        let address = wallet.address();
+        let payload = Payload {
+            msg: wallet.key.as_bytes(),
+            aad: address.as_bytes(),
+        };
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_| WalletError::UnsupportedKeystore("cannot encrypt".to_string()))?;
+
+        Ok(Keystore {
+            version: KEYSTORE_VERSION,
+            address,
+            kdf: KDF.to_string(),
+            kdf_params,
+            cipher: CIPHER.to_string(),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+
+    fn decrypt(&self, password: &str) -> Result<Wallet, WalletError> {
+        if self.version != KEYSTORE_VERSION || self.kdf != KDF || self.cipher != CIPHER {
+            let description = format!(
+                "version {} with {} and {}",
+                self.version, self.kdf, self.cipher
+            );
+            return Err(WalletError::UnsupportedKeystore(description));
+        }
+
+        let invalid = |field: &str| WalletError::UnsupportedKeystore(format!("invalid {}", field));
+        let nonce: [u8; NONCE_LEN] = hex::decode(&self.nonce)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| invalid("nonce"))?;
+        let ciphertext = hex::decode(&self.ciphertext).map_err(|_| invalid("ciphertext"))?;
+
+        let cipher = Keystore::cipher(password, &self.kdf_params)?;
+        let payload = Payload {
+            msg: &ciphertext,
+            aad: self.address.as_bytes(),
+        };
+        let secret: [u8; KEY_LEN] = cipher
+            .decrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_| WalletError::WrongPassword)?
+            .try_into()
+            .map_err(|_| invalid("secret key"))?;
+
+        Ok(Wallet::from_secret(secret))
+    }
+
+    fn cipher(password: &str, params: &KdfParams) -> Result<Aes256Gcm, WalletError> {
+        let invalid = || WalletError::UnsupportedKeystore("invalid scrypt parameters".to_string());
+        let salt = hex::decode(&params.salt).map_err(|_| invalid())?;
+        let scrypt_params = scrypt::Params::new(params.log_n, params.r, params.p, KEY_LEN)
+            .map_err(|_| invalid())?;
+
+        let mut key = [0u8; KEY_LEN];
+        scrypt::scrypt(password.as_bytes(), &salt, &scrypt_params, &mut key)
+            .map_err(|_| invalid())?;
+        Aes256Gcm::new_from_slice(&key).map_err(|_| invalid())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // much cheaper than the default, the tests don't need to resist brute force
+    const TEST_LOG_N: u8 = 4;
+
+    #[test]
+    fn should_derive_the_address_from_the_public_key() {
+        let wallet = Wallet::from_secret([7; 32]);
+        let key = SigningKey::from_bytes(&[7; 32]);
+        assert_eq!(
+            wallet.address(),
+            Address::from(key.verifying_key().to_bytes())
+        );
+
+        let from_hex = Wallet::from_secret_hex(&hex::encode([7; 32])).unwrap();
+        assert_eq!(from_hex.address(), wallet.address());
+        assert_eq!(from_hex.secret_hex(), hex::encode([7; 32]));
+        assert_eq!(
+            Wallet::from_secret_hex("07").err(),
+            Some(WalletError::InvalidSecretKey)
+        );
+        assert_ne!(Wallet::generate().address(), Wallet::generate().address());
+    }
+
+    #[test]
+    fn should_sign_the_transactions_of_its_address() {
+        let wallet = Wallet::generate();
+        let mut transaction = Transaction {
+            sender: wallet.address(),
+            recipient: wallet.address(),
+            event_type: "HARVEST".into(),
+            ..Default::default()
+        };
+        wallet.sign(&mut transaction).unwrap();
+        assert_eq!(transaction.verify(), Ok(()));
+
+        transaction.sender = Wallet::generate().address();
+        assert!(wallet.sign(&mut transaction).is_err());
+    }
+
+    #[test]
+    fn should_encrypt_the_secret_key_with_the_password() {
+        let wallet = Wallet::generate();
+        let keystore = Keystore::encrypt(&wallet, "correct horse", TEST_LOG_N).unwrap();
+        assert_eq!(keystore.address, wallet.address());
+        assert!(!keystore
+            .ciphertext
+            .contains(&hex::encode(wallet.key.as_bytes())));
+
+        let decrypted = keystore.decrypt("correct horse").unwrap();
+        assert_eq!(decrypted.address(), wallet.address());
+        assert_eq!(
+            keystore.decrypt("battery staple").err(),
+            Some(WalletError::WrongPassword)
+        );
+
+        // the address cannot be replaced by another one
+        let mut tampered = keystore.clone();
+        tampered.address = Wallet::generate().address();
+        assert_eq!(
+            tampered.decrypt("correct horse").err(),
+            Some(WalletError::WrongPassword)
+        );
+
+        let mut unsupported = keystore;
+        unsupported.version = 2;
+        assert!(matches!(
+            unsupported.decrypt("correct horse"),
+            Err(WalletError::UnsupportedKeystore(_))
+        ));
+    }
+
+    #[test]
+    fn should_persist_the_keystore() {
+        let path = std::env::temp_dir().join(format!("agriblock-wallet-{}", std::process::id()));
+        let wallet = Wallet::generate();
+        wallet.save(&path, "correct horse").unwrap();
+
+        let loaded = Wallet::load(&path, "correct horse").unwrap();
+        assert_eq!(loaded.address(), wallet.address());
+        let error = Wallet::load(&path, "battery staple").err().unwrap();
+        assert_eq!(
+            error.downcast_ref::<WalletError>(),
+            Some(&WalletError::WrongPassword)
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn should_keep_the_secret_file_of_the_node() {
+        let path = std::env::temp_dir().join(format!("agriblock-node-key-{}", std::process::id()));
+        let wallet = Wallet::open_secret_file(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), wallet.secret_hex());
+
+        // the same key on the next start
+        let reopened = Wallet::open_secret_file(&path).unwrap();
+        assert_eq!(reopened.address(), wallet.address());
+
+        fs::write(&path, "07").unwrap();
+        let error = Wallet::open_secret_file(&path).err().unwrap();
+        assert_eq!(
+            error.downcast_ref::<WalletError>(),
+            Some(&WalletError::InvalidSecretKey)
+        );
+        fs::remove_file(path).unwrap();
+    }
+}
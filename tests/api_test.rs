@@ -1,9 +1,12 @@
 mod common;
 
+use isahc::ReadResponseExt;
 use serial_test::serial;
+use sha2::Digest;
 
 use crate::common::{
-    Api, Block, BlockHash, ServerBuilder, Transaction, ALICE, BOB, MINER_ADDRESS,
+    address_of, sign_transaction, Api, Block, BlockHash, Server, ServerBuilder, Transaction, ALICE,
+    ALICE_KEY, BOB, BOB_KEY,
 };
 
 #[test]
@@ -37,9 +40,11 @@ fn test_should_let_add_transactions() {
     // the sender must the mining address,
     // as it should have funds from the coinbase reward of the genesis block
     let transaction = Transaction {
-        sender: MINER_ADDRESS.to_string(),
+        sender: ALICE.to_string(),
         recipient: BOB.to_string(),
-        data: "Test transaction data".to_string(),
+        data: r#"{"crop": "wheat", "quantity": "500kg", "quality": "Grade A"}"#.to_string(),
+        batch_id: "WHEAT-2024-001".to_string(),
+        event_type: "HARVEST".to_string(),
     };
     let res = node.add_transaction(&transaction);
     assert_eq!(res.status().as_u16(), 200);
@@ -112,3 +117,1853 @@ fn test_should_not_let_add_invalid_block() {
     let res = node.add_block(&invalid_block);
     assert_eq!(res.status().as_u16(), 400);
 }
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_archive_competing_blocks() {
+    let node = ServerBuilder::new().start();
+    let genesis_block = node.get_last_block();
+
+    // the first block for height 1 is added to the chain
+    let res = node.add_valid_block();
+    assert_eq!(res.status().as_u16(), 200);
+
+    // a competing block for the same height is rejected...
+    let competing_block = Block {
+        index: genesis_block.index + 1,
+        timestamp: 1,
+        nonce: 0,
+        previous_hash: genesis_block.hash,
+        hash: BlockHash::default(),
+        transactions: [].to_vec(),
+    };
+    let res = node.add_block(&competing_block);
+    assert_eq!(res.status().as_u16(), 400);
+
+    // ...but archived for later investigation
+    let forks = node.get_forks();
+    assert_eq!(forks.len(), 1);
+    assert_eq!(forks[0].reason, "STALE_FORK");
+    assert_eq!(forks[0].fork_height, 1);
+    assert_eq!(forks[0].canonical_hash, node.get_last_block().hash);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_publish_profiles() {
+    let mut node = ServerBuilder::new().start();
+    let secret_key = [7u8; 32];
+    let alice = address_of(&secret_key);
+
+    // nobody has published a profile yet
+    let res = node.get_profile(&alice);
+    assert_eq!(res.status().as_u16(), 404);
+
+    // an actor cannot publish a profile on behalf of others
+    let forged_profile = Transaction {
+        sender: BOB.to_string(),
+        recipient: alice.clone(),
+        data: r#"{"display_name": "Alice Farm"}"#.to_string(),
+        batch_id: String::new(),
+        event_type: "PROFILE".to_string(),
+    };
+    let res = node.add_transaction(&forged_profile);
+    assert_eq!(res.status().as_u16(), 400);
+
+    // nor without its signature
+    let profile = Transaction {
+        sender: alice.clone(),
+        recipient: alice.clone(),
+        data: r#"{"display_name": "Alice Farm", "roles": ["FARMER"]}"#.to_string(),
+        batch_id: String::new(),
+        event_type: "PROFILE".to_string(),
+    };
+    let res = node.add_transaction(&profile);
+    assert_eq!(res.status().as_u16(), 400);
+
+    // but it can publish its own signed profile
+    let signed = sign_transaction(&secret_key, &serde_json::to_string(&profile).unwrap());
+    let res = node.add_raw_transaction(&signed);
+    assert_eq!(res.status().as_u16(), 200);
+    node.wait_for_mining();
+
+    // and it's visible to everybody
+    let mut res = node.get_profile(&alice);
+    assert_eq!(res.status().as_u16(), 200);
+    let profile: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(profile["display_name"], "Alice Farm");
+    assert_eq!(profile["roles"][0], "FARMER");
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_cache_frequent_queries() {
+    let node = ServerBuilder::new().start();
+
+    // the first query calculates the response, the second one is served from the cache
+    let first_blocks = node.get_blocks();
+    let second_blocks = node.get_blocks();
+    assert_eq!(first_blocks, second_blocks);
+
+    let metrics = node.get_metrics();
+    assert!(metrics.contains("query_cache_hits_total 1"));
+    assert!(metrics.contains("query_cache_misses_total 1"));
+
+    // adding a new block invalidates the cache
+    node.add_valid_block();
+    let blocks = node.get_blocks();
+    assert_eq!(blocks.len(), first_blocks.len() + 1);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_export_chain_statistics() {
+    let node = ServerBuilder::new().start();
+
+    // the statistics of new blocks are available as soon as they are added
+    node.add_valid_block();
+
+    let metrics = node.get_metrics();
+    assert!(metrics.contains("chain_blocks 2"));
+    assert!(metrics.contains("chain_transactions{event_type=\"INITIALIZATION\"} 1"));
+    // also counted from the events of the chain
+    assert!(metrics.contains("events_blocks_applied_total 1"));
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_select_parts_of_the_payloads() {
+    let node = ServerBuilder::new().start();
+    node.add_valid_block();
+
+    // only the selected part of the payload is returned
+    let mut res = node.get_transactions("event_type=INITIALIZATION&path=$.event");
+    assert_eq!(res.status().as_u16(), 200);
+    let selections: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(selections[0]["batch_id"], "SYSTEM-INIT");
+    assert_eq!(selections[0]["value"], "system_initialization");
+
+    // invalid expressions are rejected
+    let res = node.get_transactions("path=event");
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_provision_actors_on_testnet() {
+    let profile = serde_json::json!({"display_name": "QA Farm", "roles": ["FARMER"]});
+
+    // the faucet is not available outside of test networks
+    let node = ServerBuilder::new().start();
+    let res = node.provision_actor(&profile);
+    assert_eq!(res.status().as_u16(), 404);
+    drop(node);
+
+    // on testnet, a new actor is registered with the chosen roles
+    let mut node = ServerBuilder::new().testnet().start();
+    let mut res = node.provision_actor(&profile);
+    assert_eq!(res.status().as_u16(), 200);
+    let actor: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    let address = actor["address"].as_str().unwrap().to_string();
+    // the actor gets the secret key of its address, to sign its own events
+    assert_eq!(actor["secret_key"].as_str().unwrap().len(), 64);
+
+    node.wait_for_mining();
+    let mut res = node.get_profile(&address);
+    assert_eq!(res.status().as_u16(), 200);
+    let profile: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(profile["roles"][0], "FARMER");
+
+    // and the faucet, the registrar of the new chain, grants the roles in the registry
+    // the registration may only be mined with one of the next blocks
+    for _ in 0..5 {
+        if !node.get_registry()["actors"].as_array().unwrap().is_empty() {
+            break;
+        }
+        node.wait_for_mining();
+    }
+    let registry = node.get_registry();
+    assert!(registry["registrar"].is_string());
+    assert_eq!(registry["actors"][0]["address"], address);
+    assert_eq!(registry["actors"][0]["roles"][0], "FARMER");
+
+    // a role unknown to the registry can't be granted
+    let profile = serde_json::json!({"display_name": "QA Pilot", "roles": ["PILOT"]});
+    let res = node.provision_actor(&profile);
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_warn_about_duplicated_transactions() {
+    let node = ServerBuilder::new().start();
+    let transaction = Transaction {
+        sender: ALICE.to_string(),
+        recipient: BOB.to_string(),
+        data: r#"{"crop": "rice", "quantity": "200kg"}"#.to_string(),
+        batch_id: "RICE-2024-007".to_string(),
+        event_type: "HARVEST".to_string(),
+    };
+    let res = node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 200);
+
+    // a batch is harvested only once, so the duplicate is a later event
+    let transaction = Transaction {
+        data: r#"{"vehicle": "TRUCK-12"}"#.to_string(),
+        event_type: "TRANSPORT".to_string(),
+        ..transaction
+    };
+    let mut res = node.add_transaction(&transaction);
+    let body: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert!(body["warnings"].as_array().unwrap().is_empty());
+    let hash = body["hash"].as_str().unwrap().to_string();
+    assert!(hash.starts_with("0x"));
+
+    // the same event recorded again, told apart by its nonce, is accepted but flagged
+    let mut with_nonce = serde_json::to_value(&transaction).unwrap();
+    with_nonce["nonce"] = serde_json::json!(1);
+    let signed = sign_transaction(&ALICE_KEY, &with_nonce.to_string());
+    let mut res = node.add_raw_transaction(&signed);
+    assert_eq!(res.status().as_u16(), 200);
+    let body: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(body["added"], true);
+    assert_eq!(body["warnings"].as_array().unwrap().len(), 1);
+    assert_ne!(body["hash"], hash.as_str());
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_refuse_the_transactions_already_mined() {
+    let mut node = ServerBuilder::new().start();
+    let transaction = Transaction {
+        sender: ALICE.to_string(),
+        recipient: BOB.to_string(),
+        data: r#"{"crop": "rice", "quantity": "200kg"}"#.to_string(),
+        batch_id: "RICE-2024-008".to_string(),
+        event_type: "HARVEST".to_string(),
+    };
+    let res = node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 200);
+    node.wait_for_mining();
+    // the block is logged right before it's added to the chain
+    std::thread::sleep(std::time::Duration::from_millis(200));
+
+    // the same transaction submitted again cannot be mined twice
+    let mut res = node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 400);
+    let body: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(body["code"], "INVALID_TRANSACTION");
+    assert!(body["message"]
+        .as_str()
+        .unwrap()
+        .contains("already in the chain"));
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_describe_the_api_and_its_errors() {
+    let node = ServerBuilder::new().start();
+
+    // the OpenAPI document lists the routes of the node
+    let document = node.get_openapi();
+    assert!(document["openapi"].as_str().unwrap().starts_with("3."));
+    assert!(document["paths"]["/transactions"]["post"].is_object());
+
+    // and all errors share the same typed body
+    let mut res = node.get_profile("not-an-address");
+    assert_eq!(res.status().as_u16(), 400);
+    let body: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(body["code"], "INVALID_ADDRESS");
+    assert_eq!(body["message"], "Invalid address");
+
+    let mut res = node.get_transactions("path=event");
+    assert_eq!(res.status().as_u16(), 400);
+    let body: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(body["code"], "INVALID_PATH");
+    assert!(body["details"]["position"].is_u64());
+
+    // even for requests that cannot be parsed
+    let mut res = node.provision_actor(&serde_json::json!({"unknown": true}));
+    assert_eq!(res.status().as_u16(), 400);
+    let body: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(body["code"], "INVALID_REQUEST");
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_allocate_unique_lots() {
+    let mut node = ServerBuilder::new().start();
+    let request = serde_json::json!({"sender": ALICE, "prefix": "WHEAT", "season": "2024"});
+
+    let mut res = node.allocate_lot(&request);
+    assert_eq!(res.status().as_u16(), 200);
+    let lot: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(lot["lot_id"], "WHEAT-2024-0001");
+
+    // the next allocation gets the next sequence, before and after mining the first one
+    let mut res = node.allocate_lot(&request);
+    let lot: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(lot["lot_id"], "WHEAT-2024-0002");
+
+    node.wait_for_mining();
+    let mut res = node.allocate_lot(&request);
+    let lot: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(lot["lot_id"], "WHEAT-2024-0003");
+
+    // the allocations are recorded on chain, signed by the node for their owner
+    let mut res = node.get_transactions("event_type=LOT");
+    let allocations: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(allocations[0]["batch_id"], "WHEAT-2024-0001");
+    assert_eq!(allocations[0]["recipient"], ALICE);
+    assert!(allocations[0]["signature"].is_string());
+
+    let invalid_request = serde_json::json!({"sender": ALICE, "prefix": "wheat", "season": "2024"});
+    let res = node.allocate_lot(&invalid_request);
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_verify_the_genesis_of_a_node() {
+    let node = ServerBuilder::new().start();
+    let address = format!("http://localhost:{}", node.config.port);
+
+    let output = std::process::Command::new(assert_cmd::cargo::cargo_bin("agriblock"))
+        .args(["genesis", &address])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let genesis_hash = format!("genesis hash: {:#x}", node.get_blocks()[0].hash);
+    assert!(stdout.contains(&genesis_hash));
+    assert!(stdout.contains("matches the genesis"));
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_start_another_network_from_a_genesis_file() {
+    let genesis_file = std::env::temp_dir().join("agriblock-test-genesis.json");
+    let secret_key = [7u8; 32];
+    let farm = address_of(&secret_key);
+    let genesis = format!(
+        r#"{{"chain_id": "agriblock-testnet", "timestamp": 1700000000000,
+            "actors": [{{"address": "{}", "roles": ["FARMER"]}}]}}"#,
+        farm
+    );
+    std::fs::write(&genesis_file, genesis).unwrap();
+    let genesis_file = genesis_file.to_str().unwrap();
+    let mut node = ServerBuilder::new().genesis_file(genesis_file).start();
+    let address = format!("http://localhost:{}", node.config.port);
+
+    // the parameters of the network and its actors are in the genesis block
+    let genesis_block = node.get_blocks()[0].clone();
+    assert_eq!(genesis_block.timestamp, 1_700_000_000_000);
+    assert_eq!(genesis_block.transactions[0].event_type, "GENESIS");
+    assert_eq!(genesis_block.transactions[1].recipient, farm);
+
+    let genesis_of = |args: &[&str]| {
+        std::process::Command::new(assert_cmd::cargo::cargo_bin("agriblock"))
+            .arg("genesis")
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    assert!(!genesis_of(&[&address]).status.success());
+    assert!(genesis_of(&[genesis_file, &address]).status.success());
+
+    // the roles of the registered actors are only granted to their keys
+    let transaction = Transaction {
+        sender: farm.clone(),
+        recipient: farm,
+        data: r#"{"crop": "wheat"}"#.to_string(),
+        batch_id: "WHEAT-2024-001".to_string(),
+        event_type: "HARVEST".to_string(),
+    };
+    assert_eq!(node.add_transaction(&transaction).status().as_u16(), 400);
+    let signed = sign_transaction(&secret_key, &serde_json::to_string(&transaction).unwrap());
+    assert_eq!(node.add_raw_transaction(&signed).status().as_u16(), 200);
+    node.wait_for_mining();
+    assert_eq!(node.get_last_block().previous_hash, genesis_block.hash);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_operate_a_node_with_the_cli() {
+    let mut node = ServerBuilder::new().start();
+    let address = format!("http://localhost:{}", node.config.port);
+    let agriblock = |args: &[&str]| {
+        std::process::Command::new(assert_cmd::cargo::cargo_bin("agriblock"))
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let transaction = Transaction {
+        sender: ALICE.to_string(),
+        recipient: BOB.to_string(),
+        data: r#"{"crop": "wheat", "quantity": "500kg"}"#.to_string(),
+        batch_id: "WHEAT-2024-001".to_string(),
+        event_type: "HARVEST".to_string(),
+    };
+    let json = sign_transaction(&ALICE_KEY, &serde_json::to_string(&transaction).unwrap());
+    let output = agriblock(&["tx", "submit", "--node", &address, &json]);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .starts_with("Submitted the transaction 0x"));
+    node.wait_for_mining();
+
+    let output = agriblock(&[
+        "batch",
+        "history",
+        "--node",
+        &address,
+        "--output",
+        "json",
+        "WHEAT-2024-001",
+    ]);
+    assert!(output.status.success());
+    let history: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(history[0]["transaction"]["event_type"], "HARVEST");
+    assert_eq!(history[0]["block"]["index"], 1);
+
+    // the transaction was already mined
+    let output = agriblock(&["mine", "--node", &address]);
+    assert!(!output.status.success());
+    assert!(
+        !agriblock(&["batch", "history", "--node", &address, "WHEAT-2024-002"])
+            .status
+            .success()
+    );
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_watch_a_batch_with_the_cli() {
+    use std::io::{BufRead, BufReader};
+
+    let mut node = ServerBuilder::new().start();
+    let address = format!("http://localhost:{}", node.config.port);
+    let mut watch = std::process::Command::new(assert_cmd::cargo::cargo_bin("agriblock"))
+        .args(["batch", "watch", "--node", &address, "WHEAT-2024-001"])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    // the lines are read in the background, so a missing update fails the test instead of blocking it
+    let (sender, lines) = std::sync::mpsc::channel();
+    for output in [
+        Box::new(watch.stdout.take().unwrap()) as Box<dyn std::io::Read + Send>,
+        Box::new(watch.stderr.take().unwrap()),
+    ] {
+        let sender = sender.clone();
+        std::thread::spawn(move || {
+            for line in BufReader::new(output).lines() {
+                let _ = sender.send(line.unwrap());
+            }
+        });
+    }
+    let next_line = || {
+        lines
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .unwrap()
+    };
+    assert!(next_line().starts_with("Watching the batch WHEAT-2024-001"));
+
+    let transaction = Transaction {
+        sender: ALICE.to_string(),
+        recipient: BOB.to_string(),
+        data: r#"{"crop": "wheat", "quantity": "500kg"}"#.to_string(),
+        batch_id: "WHEAT-2024-001".to_string(),
+        event_type: "HARVEST".to_string(),
+    };
+    assert_eq!(node.add_transaction(&transaction).status().as_u16(), 200);
+    assert!(next_line().starts_with("pending   HARVEST"));
+    node.wait_for_mining();
+    let mined = next_line();
+    assert!(mined.starts_with("mined     HARVEST"));
+    assert!(mined.contains("block 1"));
+
+    watch.kill().unwrap();
+    watch.wait().unwrap();
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_require_signed_transactions() {
+    let mut node = ServerBuilder::new().start();
+
+    let secret_key = [7u8; 32];
+    let transaction = Transaction {
+        sender: address_of(&secret_key),
+        recipient: BOB.to_string(),
+        data: r#"{"crop": "wheat", "quantity": "500kg"}"#.to_string(),
+        batch_id: "WHEAT-2024-001".to_string(),
+        event_type: "HARVEST".to_string(),
+    };
+    let res = node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 400);
+
+    let signed = sign_transaction(&secret_key, &serde_json::to_string(&transaction).unwrap());
+
+    // a signature is only valid for the exact transaction that was signed
+    let tampered = signed.replace("500kg", "900kg");
+    let res = node.add_raw_transaction(&tampered);
+    assert_eq!(res.status().as_u16(), 400);
+
+    let res = node.add_raw_transaction(&signed);
+    assert_eq!(res.status().as_u16(), 200);
+    node.wait_for_mining();
+    assert_eq!(node.get_last_block().transactions[1], transaction);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_include_the_pre_signed_transactions_within_their_window() {
+    let mut node = ServerBuilder::new().start();
+
+    // the device of the worker signs the event in advance, for the blocks 2 and 3 only
+    let secret_key = [7u8; 32];
+    let worker = address_of(&secret_key);
+    let transaction = serde_json::json!({
+        "sender": worker,
+        "recipient": BOB,
+        "data": r#"{"crop": "wheat", "quantity": "500kg"}"#,
+        "batch_id": "WHEAT-2024-001",
+        "event_type": "HARVEST",
+        "validity": {"not_before_height": 2, "not_after_height": 3},
+    });
+    let signed = sign_transaction(&secret_key, &transaction.to_string());
+
+    // too early, the next block is the first one
+    let mut res = node.add_raw_transaction(&signed);
+    assert_eq!(res.status().as_u16(), 400);
+    assert!(res.text().unwrap().contains("not valid before block 2"));
+
+    let res = node.add_transaction(&Transaction {
+        sender: ALICE.to_string(),
+        recipient: BOB.to_string(),
+        data: "{}".to_string(),
+        batch_id: "CORN-2024-001".to_string(),
+        event_type: "HARVEST".to_string(),
+    });
+    assert_eq!(res.status().as_u16(), 200);
+    node.wait_for_mining();
+    // the block is logged right before it's added to the chain
+    std::thread::sleep(std::time::Duration::from_millis(200));
+
+    // the coordinator cannot widen the window that the worker signed
+    let widened = signed.replace("\"not_after_height\": 3", "\"not_after_height\": 30");
+    assert_ne!(widened, signed);
+    let res = node.add_raw_transaction(&widened);
+    assert_eq!(res.status().as_u16(), 400);
+
+    let res = node.add_raw_transaction(&signed);
+    assert_eq!(res.status().as_u16(), 200);
+    node.wait_for_mining();
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    let mut res = node.get_transactions("batch_id=WHEAT-2024-001");
+    let transactions: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(transactions[0]["validity"]["not_before_height"], 2);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_report_a_batch_in_the_output_format() {
+    let mut node = ServerBuilder::new().start();
+    let address = format!("http://localhost:{}", node.config.port);
+
+    let transaction = Transaction {
+        sender: ALICE.to_string(),
+        recipient: ALICE.to_string(),
+        data: r#"{"crop": "wheat", "quantity": "1500kg"}"#.to_string(),
+        batch_id: "WHEAT-2024-001".to_string(),
+        event_type: "HARVEST".to_string(),
+    };
+    let res = node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 200);
+    node.wait_for_mining();
+
+    let output = std::process::Command::new(assert_cmd::cargo::cargo_bin("agriblock"))
+        .args([
+            "batch-report",
+            "--locale",
+            "en-US",
+            &address,
+            "WHEAT-2024-001",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("stage:          HARVEST"));
+    assert!(stdout.contains("quantity:       3,306.93 lb"));
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_verify_a_transaction_with_the_headers_only() {
+    let mut node = ServerBuilder::new().start();
+    let address = format!("http://localhost:{}", node.config.port);
+    let transaction = Transaction {
+        sender: ALICE.to_string(),
+        recipient: BOB.to_string(),
+        data: r#"{"crop": "wheat", "quantity": "500kg"}"#.to_string(),
+        batch_id: "WHEAT-2024-001".to_string(),
+        event_type: "HARVEST".to_string(),
+    };
+    let res = node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 200);
+    node.wait_for_mining();
+    // the block is logged right before it's added to the chain
+    std::thread::sleep(std::time::Duration::from_millis(200));
+
+    let verify = |position: &str| {
+        std::process::Command::new(assert_cmd::cargo::cargo_bin("agriblock"))
+            .args(["tx", "verify", "1", position, "--node", &address])
+            .args([
+                "--transaction",
+                &sign_transaction(&ALICE_KEY, &serde_json::to_string(&transaction).unwrap()),
+            ])
+            .args(["--output", "json"])
+            .env("DIFFICULTY", node.config.difficulty.to_string())
+            .output()
+            .unwrap()
+    };
+    let output = verify("1");
+    assert!(output.status.success());
+    let verification: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(verification["headers"], 1);
+
+    // the proof of another transaction of the block doesn't prove this one
+    let output = verify("0");
+    assert!(!output.status.success());
+    let verification: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(verification["valid"], false);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_deliver_documents_in_chunks() {
+    let node = ServerBuilder::new().start();
+
+    // a certificate too large for a single transaction
+    let content = "certified organic ".repeat(2000);
+    let request = serde_json::json!({
+        "recipient": BOB,
+        "batch_id": "WHEAT-001",
+        "name": "organic.txt",
+        "content": content,
+    });
+    let mut res = node.add_document(&request);
+    assert_eq!(res.status().as_u16(), 200);
+    let submitted: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(submitted["chunks"], 3);
+
+    let hash = submitted["hash"].as_str().unwrap();
+    let document = wait_for_document(&node, hash, |document| document["content"].is_string());
+    assert_eq!(document["content"], content);
+    assert_eq!(document["name"], "organic.txt");
+
+    // a document with a missing chunk is not delivered
+    let content = "first part, second part";
+    let hash = hex::encode(sha2::Sha256::digest(content.as_bytes()));
+    let manifest = serde_json::json!({"hash": hash, "size": content.len(), "chunks": 2});
+    let chunk = serde_json::json!({"document": hash, "index": 0, "content": "first part, "});
+    for (event_type, data) in [("DOCUMENT", manifest), ("CHUNK", chunk)] {
+        let transaction = Transaction {
+            sender: ALICE.to_string(),
+            recipient: BOB.to_string(),
+            data: data.to_string(),
+            batch_id: "WHEAT-001".to_string(),
+            event_type: event_type.to_string(),
+        };
+        assert_eq!(node.add_transaction(&transaction).status().as_u16(), 200);
+    }
+
+    let error = wait_for_document(&node, &hash, |error| {
+        error["details"]["missing_chunks"] == serde_json::json!([1])
+    });
+    assert_eq!(error["code"], "INCOMPLETE_DOCUMENT");
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_flag_documents_reused_in_other_batches() {
+    let node = ServerBuilder::new().start();
+    let address = format!("http://localhost:{}", node.config.port);
+
+    let lab_report = |batch_id: &str| {
+        serde_json::json!({
+            "recipient": BOB,
+            "batch_id": batch_id,
+            "name": "lab-report.txt",
+            "content": "aflatoxin below 4 ppb",
+        })
+    };
+    let mut res = node.add_document(&lab_report("WHEAT-001"));
+    let submitted: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert!(submitted["warnings"].as_array().unwrap().is_empty());
+    let hash = submitted["hash"].as_str().unwrap().to_string();
+    wait_for_document(&node, &hash, |document| document["content"].is_string());
+
+    // the same report submitted for another harvest is accepted, but flagged
+    let mut res = node.add_document(&lab_report("WHEAT-002"));
+    assert_eq!(res.status().as_u16(), 200);
+    let submitted: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    let warnings = submitted["warnings"].as_array().unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].as_str().unwrap().contains("WHEAT-001"));
+
+    // and shows up in the audit once mined
+    let url = format!("{}/fingerprints/reused", address);
+    let mut reused = serde_json::Value::Null;
+    for _ in 0..50 {
+        let mut res = isahc::get(&url).unwrap();
+        reused = serde_json::from_str(&res.text().unwrap()).unwrap();
+        if !reused.as_array().unwrap().is_empty() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    assert_eq!(reused[0]["hash"], hash.as_str());
+    assert_eq!(
+        reused[0]["batches"],
+        serde_json::json!(["WHEAT-001", "WHEAT-002"])
+    );
+
+    let mut res = isahc::get(format!("{}/fingerprints/{}", address, hash)).unwrap();
+    let occurrences: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(occurrences.as_array().unwrap().len(), 2);
+    assert_eq!(occurrences[0]["event_type"], "DOCUMENT");
+    let res = isahc::get(format!("{}/fingerprints/{}", address, "00".repeat(32))).unwrap();
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+// Polls a document until the response satisfies a condition, as its chunks may be mined in several blocks
+fn wait_for_document<P>(node: &Server, hash: &str, condition: P) -> serde_json::Value
+where
+    P: Fn(&serde_json::Value) -> bool,
+{
+    for _ in 0..50 {
+        let mut res = node.get_document(hash);
+        let body: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+        if condition(&body) {
+            return body;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+
+    panic!("The document {} did not reach the expected state", hash);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_record_the_origin_of_transactions() {
+    let node = ServerBuilder::new().admin_token("secret").start();
+    let transaction = Transaction {
+        sender: ALICE.to_string(),
+        recipient: BOB.to_string(),
+        data: r#"{"crop": "barley", "quantity": "80kg"}"#.to_string(),
+        batch_id: "BARLEY-2024-003".to_string(),
+        event_type: "HARVEST".to_string(),
+    };
+    node.add_transaction(&transaction);
+
+    // only the operator can investigate the origins
+    let res = node.get_origins("", "wrong");
+    assert_eq!(res.status().as_u16(), 401);
+
+    let mut res = node.get_origins("batch_id=BARLEY-2024-003", "secret");
+    assert_eq!(res.status().as_u16(), 200);
+    let origins: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    let origins = origins.as_array().unwrap();
+    assert_eq!(origins.len(), 1);
+    assert_eq!(origins[0]["channel"], "API");
+    assert_eq!(origins[0]["sender"], ALICE);
+    // the addresses of the clients are not recorded by default
+    assert!(origins[0]["ip"].is_null());
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_pause_the_node_during_a_maintenance() {
+    let node = ServerBuilder::new().admin_token("secret").start();
+    let transaction = Transaction {
+        sender: ALICE.to_string(),
+        recipient: BOB.to_string(),
+        data: r#"{"crop": "oats", "quantity": "40kg"}"#.to_string(),
+        batch_id: "OATS-2024-001".to_string(),
+        event_type: "HARVEST".to_string(),
+    };
+    let request = serde_json::json!({"reason": "UPGRADE", "message": "Rolling out v2.1"});
+
+    // only the operator can pause the node
+    let res = node.start_maintenance(&request, "wrong");
+    assert_eq!(res.status().as_u16(), 401);
+    let res = node.start_maintenance(&request, "secret");
+    assert_eq!(res.status().as_u16(), 200);
+
+    // nothing new is accepted, but the queries are still served
+    let mut res = node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 503);
+    let error: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(error["code"], "MAINTENANCE");
+    assert_eq!(error["details"]["reason"], "UPGRADE");
+    assert_eq!(node.add_valid_block().status().as_u16(), 503);
+    assert_eq!(node.get_blocks().len(), 1);
+    let status = node.get_maintenance();
+    assert_eq!(status["maintenance"]["message"], "Rolling out v2.1");
+
+    // only the known peers can announce their maintenance
+    let announcement = serde_json::json!({
+        "node": "http://localhost:9000",
+        "notice": {"reason": "UPGRADE", "message": null, "since": 0}
+    });
+    let res = node.announce_maintenance(&announcement);
+    assert_eq!(res.status().as_u16(), 400);
+
+    let res = node.end_maintenance("secret");
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(node.add_transaction(&transaction).status().as_u16(), 200);
+    assert!(node.get_maintenance()["maintenance"].is_null());
+    let res = node.end_maintenance("secret");
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_keep_the_results_of_the_saved_queries() {
+    let mut node = ServerBuilder::new().admin_token("secret").start();
+    let event = |batch_id: &str, event_type: &str, data: &str| Transaction {
+        sender: ALICE.to_string(),
+        recipient: BOB.to_string(),
+        data: data.to_string(),
+        batch_id: batch_id.to_string(),
+        event_type: event_type.to_string(),
+    };
+    node.add_transaction(&event(
+        "OATS-1",
+        "HARVEST",
+        r#"{"crop": "oats", "organic": true}"#,
+    ));
+    node.add_transaction(&event(
+        "OATS-2",
+        "HARVEST",
+        r#"{"crop": "oats", "organic": false}"#,
+    ));
+    node.wait_for_mining();
+    // the block is logged right before it's added to the chain
+    std::thread::sleep(std::time::Duration::from_millis(200));
+
+    let query = serde_json::json!({
+        "name": "uncertified-organic",
+        "filter": {"event_type": "HARVEST", "path": "$.organic", "equals": true},
+        "except": {"event_type": "CERTIFICATION"}
+    });
+    assert_eq!(node.save_query(&query, "wrong").status().as_u16(), 401);
+    let mut res = node.save_query(&query, "secret");
+    assert_eq!(res.status().as_u16(), 201);
+    let results: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(results["batch_ids"], serde_json::json!(["OATS-1"]));
+
+    // the results follow the new blocks
+    node.add_transaction(&event(
+        "OATS-1",
+        "CERTIFICATION",
+        r#"{"body": "EU organic"}"#,
+    ));
+    node.add_transaction(&event(
+        "OATS-3",
+        "HARVEST",
+        r#"{"crop": "oats", "organic": true}"#,
+    ));
+    node.wait_for_mining();
+    // the block is logged right before it's added to the chain
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    let mut res = node.get_query_results("uncertified-organic");
+    assert_eq!(res.status().as_u16(), 200);
+    let results: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(results["batch_ids"], serde_json::json!(["OATS-3"]));
+
+    let invalid = serde_json::json!({"name": "broken", "filter": {"path": "$.organic["}});
+    let mut res = node.save_query(&invalid, "secret");
+    assert_eq!(res.status().as_u16(), 400);
+    let error: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(error["code"], "INVALID_PATH");
+
+    let res = node.remove_query("uncertified-organic", "secret");
+    assert_eq!(res.status().as_u16(), 204);
+    let res = node.get_query_results("uncertified-organic");
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_get_the_history_of_a_batch() {
+    let mut node = ServerBuilder::new().start();
+    let address = format!("http://localhost:{}", node.config.port);
+
+    for event_type in ["HARVEST", "TRANSPORT"] {
+        let transaction = Transaction {
+            sender: ALICE.to_string(),
+            recipient: BOB.to_string(),
+            data: r#"{"crop": "wheat"}"#.to_string(),
+            batch_id: "WHEAT-2024-001".to_string(),
+            event_type: event_type.to_string(),
+        };
+        let res = node.add_transaction(&transaction);
+        assert_eq!(res.status().as_u16(), 200);
+    }
+    node.wait_for_mining();
+
+    // the events may be mined in more than one block
+    let url = format!("{}/batches/WHEAT-2024-001/history", address);
+    let mut history = serde_json::Value::Null;
+    for _ in 0..20 {
+        let mut res = isahc::get(&url).unwrap();
+        assert_eq!(res.status().as_u16(), 200);
+        history = serde_json::from_str(&res.text().unwrap()).unwrap();
+        if history.as_array().unwrap().len() == 2 {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    let events = history.as_array().unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0]["transaction"]["event_type"], "HARVEST");
+    assert_eq!(events[1]["transaction"]["event_type"], "TRANSPORT");
+
+    // the position of an event is enough to get its inclusion proof
+    let block = &events[0]["block"];
+    let res = isahc::get(format!(
+        "{}/blocks/{}/proofs/{}",
+        address, block["index"], block["position"]
+    ))
+    .unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+
+    // and the batch is now on its way
+    let mut res = isahc::get(format!("{}/batches/WHEAT-2024-001/state", address)).unwrap();
+    let state: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(state["state"], "IN_TRANSIT");
+
+    let res = isahc::get(format!("{}/batches/CORN-2024-001/history", address)).unwrap();
+    assert_eq!(res.status().as_u16(), 404);
+    let res = isahc::get(format!("{}/batches/CORN-2024-001/state", address)).unwrap();
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_match_the_planned_events_of_a_batch() {
+    let mut node = ServerBuilder::new().start();
+    let address = format!("http://localhost:{}", node.config.port);
+
+    let events = [
+        (
+            "PLANNED",
+            r#"{"event_type": "TRANSPORT", "due": 4102444800000}"#,
+        ),
+        ("HARVEST", r#"{"crop": "wheat"}"#),
+        ("TRANSPORT", r#"{"crop": "wheat"}"#),
+    ];
+    for (event_type, data) in events {
+        let transaction = Transaction {
+            sender: ALICE.to_string(),
+            recipient: BOB.to_string(),
+            data: data.to_string(),
+            batch_id: "WHEAT-2024-001".to_string(),
+            event_type: event_type.to_string(),
+        };
+        let res = node.add_transaction(&transaction);
+        assert_eq!(res.status().as_u16(), 200);
+    }
+    node.wait_for_mining();
+
+    // the events may be mined in more than one block
+    let url = format!("{}/batches/WHEAT-2024-001/plans", address);
+    let mut plans = serde_json::Value::Null;
+    for _ in 0..20 {
+        let mut res = isahc::get(&url).unwrap();
+        assert_eq!(res.status().as_u16(), 200);
+        plans = serde_json::from_str(&res.text().unwrap()).unwrap();
+        if plans[0]["status"] == "FULFILLED" {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    assert_eq!(plans.as_array().unwrap().len(), 1);
+    assert_eq!(plans[0]["status"], "FULFILLED");
+    assert_eq!(plans[0]["planned"]["event_type"], "TRANSPORT");
+
+    // the plan is not part of the history of the batch
+    let mut res = isahc::get(format!("{}/batches/WHEAT-2024-001/history", address)).unwrap();
+    let history: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(history.as_array().unwrap().len(), 2);
+
+    let mut res = isahc::get(format!("{}/plans/overdue", address)).unwrap();
+    let overdue: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert!(overdue.as_array().unwrap().is_empty());
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_verify_the_bundle_of_a_batch() {
+    let mut node = ServerBuilder::new().start();
+    let address = format!("http://localhost:{}", node.config.port);
+
+    for event_type in ["HARVEST", "STORAGE"] {
+        let transaction = Transaction {
+            sender: ALICE.to_string(),
+            recipient: BOB.to_string(),
+            data: r#"{"crop": "wheat"}"#.to_string(),
+            batch_id: "WHEAT-2024-001".to_string(),
+            event_type: event_type.to_string(),
+        };
+        let res = node.add_transaction(&transaction);
+        assert_eq!(res.status().as_u16(), 200);
+    }
+    node.wait_for_mining();
+
+    // the events may be mined in more than one block
+    let url = format!("{}/batches/WHEAT-2024-001/bundle", address);
+    let mut bundle = serde_json::Value::Null;
+    for _ in 0..20 {
+        let mut res = isahc::get(&url).unwrap();
+        assert_eq!(res.status().as_u16(), 200);
+        bundle = serde_json::from_str(&res.text().unwrap()).unwrap();
+        if bundle["events"].as_array().unwrap().len() == 2 {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    assert_eq!(bundle["events"].as_array().unwrap().len(), 2);
+    assert!(bundle["events"][0]["inclusion"]["header"]["hash"].is_string());
+
+    // the bundle can be checked offline, without the node
+    let verify = |bundle: &str| {
+        std::process::Command::new(assert_cmd::cargo::cargo_bin("agriblock"))
+            .args(["verify", bundle])
+            .output()
+            .unwrap()
+    };
+    let output = verify(&bundle.to_string());
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Valid batch bundle: 2 events of batch WHEAT-2024-001"));
+
+    let tampered = bundle.to_string().replace("wheat", "maize");
+    assert!(!verify(&tampered).status.success());
+
+    let res = isahc::get(format!("{}/batches/CORN-2024-001/bundle", address)).unwrap();
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_compare_two_batches() {
+    // all the events are mined together on request
+    let mut node = ServerBuilder::new().block_interval_ms(60_000).start();
+    let address = format!("http://localhost:{}", node.config.port);
+
+    for (batch_id, result) in [("WHEAT-2024-001-A", "PASS"), ("WHEAT-2024-001-B", "FAIL")] {
+        for (event_type, data) in [
+            ("HARVEST", r#"{"crop": "wheat"}"#.to_string()),
+            ("QUALITY_CHECK", format!(r#"{{"result": "{}"}}"#, result)),
+        ] {
+            let transaction = Transaction {
+                sender: ALICE.to_string(),
+                recipient: ALICE.to_string(),
+                data,
+                batch_id: batch_id.to_string(),
+                event_type: event_type.to_string(),
+            };
+            let res = node.add_transaction(&transaction);
+            assert_eq!(res.status().as_u16(), 200);
+        }
+    }
+    node.request_mining();
+    node.wait_for_mining();
+
+    let url = format!(
+        "{}/batches/WHEAT-2024-001-A/comparison/WHEAT-2024-001-B",
+        address
+    );
+    // the mined block may take a moment to be added
+    let mut res = isahc::get(&url).unwrap();
+    for _ in 0..20 {
+        if res.status().as_u16() == 200 {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        res = isahc::get(&url).unwrap();
+    }
+    assert_eq!(res.status().as_u16(), 200);
+    let comparison: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(comparison["stages"].as_array().unwrap().len(), 2);
+    assert_eq!(comparison["divergences"], 1);
+    assert_eq!(
+        comparison["stages"][1]["differences"][0],
+        "Quality PASS in A and FAIL in B"
+    );
+
+    let url = format!(
+        "{}/batches/WHEAT-2024-001-A/comparison/CORN-2024-001",
+        address
+    );
+    let res = isahc::get(url).unwrap();
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_summarize_the_sensor_readings_of_a_batch() {
+    // all the readings are mined together on request
+    let mut node = ServerBuilder::new().block_interval_ms(60_000).start();
+    let address = format!("http://localhost:{}", node.config.port);
+
+    // a reading every 30 minutes in a cold room, the third one too warm
+    for (minutes, temperature) in [(0, 4.0), (30, 5.0), (60, 9.5), (90, 6.0)] {
+        let transaction = Transaction {
+            sender: ALICE.to_string(),
+            recipient: ALICE.to_string(),
+            data: format!(
+                r#"{{"temperature": {}, "recorded_at": {}}}"#,
+                temperature,
+                minutes * 60_000
+            ),
+            batch_id: "APPLES-2024-002".to_string(),
+            event_type: "SENSOR_READING".to_string(),
+        };
+        let res = node.add_transaction(&transaction);
+        assert_eq!(res.status().as_u16(), 200);
+    }
+    node.request_mining();
+    node.wait_for_mining();
+
+    let url = format!("{}/batches/APPLES-2024-002/sensors?max=8", address);
+    // the mined block may take a moment to be added
+    let mut res = isahc::get(&url).unwrap();
+    for _ in 0..20 {
+        if res.status().as_u16() == 200 {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        res = isahc::get(&url).unwrap();
+    }
+    assert_eq!(res.status().as_u16(), 200);
+    let trend: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(trend["readings"], 4);
+    let buckets = trend["buckets"].as_array().unwrap();
+    assert_eq!(buckets.len(), 2);
+    assert_eq!(buckets[0]["avg"], 4.5);
+    assert_eq!(buckets[1]["max"], 9.5);
+    assert_eq!(trend["violations"][0]["start"], 3_600_000);
+
+    let res = isahc::get(format!(
+        "{}/batches/APPLES-2024-002/sensors?metric=pressure",
+        address
+    ))
+    .unwrap();
+    assert_eq!(res.status().as_u16(), 400);
+    let res = isahc::get(format!(
+        "{}/batches/APPLES-2024-002/sensors?metric=humidity",
+        address
+    ))
+    .unwrap();
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_get_the_thread_of_a_dispute() {
+    let mut node = ServerBuilder::new().start();
+    let address = format!("http://localhost:{}", node.config.port);
+
+    let dispute = Transaction {
+        sender: ALICE.to_string(),
+        recipient: BOB.to_string(),
+        data: r#"{"reason": "short delivery"}"#.to_string(),
+        batch_id: "RICE-2024-009".to_string(),
+        event_type: "DISPUTE".to_string(),
+    };
+    let res = node.add_transaction(&dispute);
+    assert_eq!(res.status().as_u16(), 200);
+    node.wait_for_mining();
+
+    // the hash of the dispute comes with its inclusion proof
+    let block = node.get_last_block();
+    let position = block
+        .transactions
+        .iter()
+        .position(|tx| *tx == dispute)
+        .unwrap();
+    let mut res = isahc::get(format!(
+        "{}/blocks/{}/proofs/{}",
+        address, block.index, position
+    ))
+    .unwrap();
+    let proof: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    let dispute_hash = proof["proof"]["transaction_hash"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    // a response must reference a transaction in the chain
+    let resolution = |reference: &str| {
+        let transaction = serde_json::json!({
+            "sender": BOB,
+            "recipient": ALICE,
+            "data": r#"{"refund": "20kg"}"#,
+            "batch_id": "RICE-2024-009",
+            "event_type": "DISPUTE_RESOLVED",
+            "in_response_to": reference,
+        });
+        sign_transaction(&BOB_KEY, &transaction.to_string())
+    };
+    let res = node.add_raw_transaction(&resolution("0x1234"));
+    assert_eq!(res.status().as_u16(), 400);
+    let res = node.add_raw_transaction(&resolution(&dispute_hash));
+    assert_eq!(res.status().as_u16(), 200);
+
+    // the response may be mined in a later block
+    let url = format!("{}/transactions/{}/thread", address, dispute_hash);
+    let mut thread = serde_json::Value::Null;
+    for _ in 0..20 {
+        let mut res = isahc::get(&url).unwrap();
+        assert_eq!(res.status().as_u16(), 200);
+        thread = serde_json::from_str(&res.text().unwrap()).unwrap();
+        if thread.as_array().unwrap().len() == 2 {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    let events = thread.as_array().unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0]["hash"], dispute_hash.as_str());
+    assert_eq!(events[1]["transaction"]["event_type"], "DISPUTE_RESOLVED");
+    assert_eq!(
+        events[1]["transaction"]["in_response_to"],
+        dispute_hash.as_str()
+    );
+
+    // the thread is the same from any of its transactions
+    let resolution_url = format!(
+        "{}/transactions/{}/thread",
+        address,
+        events[1]["hash"].as_str().unwrap()
+    );
+    let mut res = isahc::get(resolution_url).unwrap();
+    let from_resolution: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(from_resolution, thread);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_mine_on_request() {
+    // without the request, the node would wait a minute for more transactions
+    let mut node = ServerBuilder::new().block_interval_ms(60_000).start();
+    let address = format!("http://localhost:{}", node.config.port);
+
+    let res = node.request_mining();
+    assert_eq!(res.status().as_u16(), 409);
+
+    let transaction = Transaction {
+        sender: ALICE.to_string(),
+        recipient: BOB.to_string(),
+        data: r#"{"crop": "barley"}"#.to_string(),
+        batch_id: "BARLEY-2024-010".to_string(),
+        event_type: "HARVEST".to_string(),
+    };
+    let res = node.add_transaction(&transaction);
+    assert_eq!(res.status().as_u16(), 200);
+    let mut res = node.request_mining();
+    assert_eq!(res.status().as_u16(), 202);
+    let request: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(request["pending_transactions"], 1);
+    node.wait_for_mining();
+
+    // the block can be fetched by its hash
+    let block = node.get_last_block();
+    assert_eq!(block.transactions[1], transaction);
+    let mut res = isahc::get(format!("{}/blocks/{:#x}", address, block.hash)).unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    let fetched: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(fetched["index"], block.index);
+
+    // and the chain in pages, with the total amount of blocks
+    let mut res = isahc::get(format!("{}/blocks?offset=1&limit=1", address)).unwrap();
+    assert_eq!(res.headers()["X-Total-Count"], "2");
+    let page: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(page.as_array().unwrap().len(), 1);
+    assert_eq!(page[0]["index"], 1);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_suggest_the_batches_to_ship_first() {
+    let mut node = ServerBuilder::new().start();
+    let address = format!("http://localhost:{}", node.config.port);
+
+    for (batch_id, shelf_life) in [
+        ("WHEAT-2024-001", ""),
+        ("MILK-2024-001", r#", "shelf_life_days": 7"#),
+    ] {
+        let harvest = Transaction {
+            sender: ALICE.to_string(),
+            recipient: ALICE.to_string(),
+            data: format!(r#"{{"quantity": "100kg"{}}}"#, shelf_life),
+            batch_id: batch_id.to_string(),
+            event_type: "HARVEST".to_string(),
+        };
+        let transport = Transaction {
+            recipient: BOB.to_string(),
+            data: "{}".to_string(),
+            event_type: "TRANSPORT".to_string(),
+            ..harvest.clone()
+        };
+        for transaction in [harvest, transport] {
+            let res = node.add_transaction(&transaction);
+            assert_eq!(res.status().as_u16(), 200);
+        }
+    }
+    node.wait_for_mining();
+
+    // the events may be mined in more than one block
+    let url = format!("{}/custodians/{}/picking", address, BOB);
+    let mut suggestions = serde_json::Value::Null;
+    for _ in 0..20 {
+        let mut res = isahc::get(&url).unwrap();
+        assert_eq!(res.status().as_u16(), 200);
+        suggestions = serde_json::from_str(&res.text().unwrap()).unwrap();
+        if suggestions.as_array().unwrap().len() == 2 {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    let ranked: Vec<(&str, &str)> = suggestions
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|suggestion| {
+            let batch_id = suggestion["batch_id"].as_str().unwrap();
+            (batch_id, suggestion["basis"].as_str().unwrap())
+        })
+        .collect();
+    // the milk expires, so it goes first even if the wheat arrived before
+    assert_eq!(
+        ranked,
+        vec![("MILK-2024-001", "FEFO"), ("WHEAT-2024-001", "FIFO")]
+    );
+
+    let output = std::process::Command::new(assert_cmd::cargo::cargo_bin("agriblock"))
+        .args(["picking", &address, BOB])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("  1. MILK-2024-001 [FEFO] TRANSPORT, 100 kg"));
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_keep_the_blocks_after_a_restart() {
+    let path = std::env::temp_dir().join(format!("agriblock-storage-{}", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_dir_all(path);
+
+    let (mined_blocks, difficulty) = {
+        let mut node = ServerBuilder::new().storage_path(path).start();
+        let transaction = Transaction {
+            sender: ALICE.to_string(),
+            recipient: BOB.to_string(),
+            data: r#"{"crop": "wheat", "quantity": "500kg"}"#.to_string(),
+            batch_id: "WHEAT-2024-001".to_string(),
+            event_type: "HARVEST".to_string(),
+        };
+        let res = node.add_transaction(&transaction);
+        assert_eq!(res.status().as_u16(), 200);
+        node.wait_for_mining();
+        // give the node some time to persist the block before stopping it
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        (node.get_blocks(), node.config.difficulty)
+    };
+    assert!(mined_blocks.len() >= 2);
+
+    // the operators can validate the storage of a stopped node
+    let output = std::process::Command::new(assert_cmd::cargo::cargo_bin("agriblock"))
+        .args(["chain", "validate", "--output", "json"])
+        .env("STORAGE_PATH", path)
+        .env("DIFFICULTY", difficulty.to_string())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let validation: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(validation["valid_blocks"], mined_blocks.len() as u64);
+
+    // the restarted node continues from the stored blocks instead of the genesis block
+    let node = ServerBuilder::new().storage_path(path).start();
+    let blocks = node.get_blocks();
+    assert!(blocks.len() >= mined_blocks.len());
+    assert_eq!(blocks[..mined_blocks.len()], mined_blocks[..]);
+
+    drop(node);
+    std::fs::remove_dir_all(path).unwrap();
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_resume_the_verification_of_the_chain_after_a_restart() {
+    let path = std::env::temp_dir().join(format!("agriblock-verification-{}", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_dir_all(path);
+    let wait_for_verification = |node: &Server| {
+        let mut progress = serde_json::Value::Null;
+        for _ in 0..20 {
+            let mut res = node.get_verification_progress("secret");
+            progress = serde_json::from_str(&res.text().unwrap()).unwrap();
+            if progress["running"] == false {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        progress
+    };
+
+    {
+        let node = ServerBuilder::new()
+            .storage_path(path)
+            .admin_token("secret")
+            .start();
+        node.add_valid_block();
+        node.add_valid_block();
+        assert_eq!(node.start_verification("wrong").status().as_u16(), 401);
+
+        let mut res = node.start_verification("secret");
+        assert_eq!(res.status().as_u16(), 200);
+        let progress: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+        assert_eq!(progress["started_from"], 0);
+        let progress = wait_for_verification(&node);
+        assert_eq!(progress["verified_blocks"], 3);
+        assert_eq!(progress["percent"], 100.0);
+        assert!(progress["invalid_block"].is_null());
+        // give the node some time to persist the blocks before stopping it
+        std::thread::sleep(std::time::Duration::from_millis(500));
+    }
+
+    // the blocks checked before the restart are not checked again
+    let node = ServerBuilder::new()
+        .storage_path(path)
+        .admin_token("secret")
+        .start();
+    let mut res = node.get_verification_progress("secret");
+    let progress: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(progress["verified_blocks"], 3);
+    node.add_valid_block();
+    let mut res = node.start_verification("secret");
+    let progress: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(progress["started_from"], 3);
+    assert_eq!(wait_for_verification(&node)["verified_blocks"], 4);
+
+    drop(node);
+    std::fs::remove_dir_all(path).unwrap();
+}
+
+#[test]
+#[serial]
+#[cfg(all(unix, feature = "chaos"))]
+fn test_should_persist_every_block_despite_the_storage_failures() {
+    let path = std::env::temp_dir().join(format!("agriblock-chaos-{}", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_dir_all(path);
+
+    let blocks = {
+        let node = ServerBuilder::new()
+            .storage_path(path)
+            .chaos("CHAOS_STORAGE_FAILURE_RATE", "0.5")
+            .start();
+        for _ in 0..5 {
+            node.add_valid_block();
+        }
+        // the failed writes are tried again on the next polls
+        std::thread::sleep(std::time::Duration::from_millis(3000));
+        node.get_blocks()
+    };
+    assert_eq!(blocks.len(), 6);
+
+    // no block was lost or stored without its hash, the chain is restored as it was
+    let node = ServerBuilder::new().storage_path(path).start();
+    assert_eq!(node.get_blocks(), blocks);
+
+    drop(node);
+    std::fs::remove_dir_all(path).unwrap();
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_follow_the_changes_with_a_cursor() {
+    let node = ServerBuilder::new().start();
+    let address = format!("http://localhost:{}", node.config.port);
+    let get_changes = |query: &str| {
+        let mut res = isahc::get(format!("{}/changes{}", address, query)).unwrap();
+        let body: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+        (res.status().as_u16(), body)
+    };
+
+    // the genesis block is not a change
+    let (_, page) = get_changes("");
+    assert_eq!(page["changes"].as_array().unwrap().len(), 0);
+    assert_eq!(page["next_cursor"], 0);
+
+    node.add_valid_block();
+    let (status, page) = get_changes("");
+    assert_eq!(status, 200);
+    let change = &page["changes"][0];
+    assert_eq!(change["cursor"], 1);
+    assert_eq!(change["kind"], "BLOCK_APPLIED");
+    assert_eq!(change["index"], 1);
+    assert_eq!(change["block"]["hash"], change["hash"]);
+    assert_eq!(page["next_cursor"], 1);
+
+    // only the changes after the cursor
+    node.add_valid_block();
+    let (_, page) = get_changes("?cursor=1");
+    let changes = page["changes"].as_array().unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0]["index"], 2);
+    assert_eq!(page["next_cursor"], 2);
+
+    let (_, page) = get_changes("?cursor=2");
+    assert_eq!(page["changes"].as_array().unwrap().len(), 0);
+    assert_eq!(page["next_cursor"], 2);
+    let (_, page) = get_changes("?cursor=0&limit=1");
+    assert_eq!(page["next_cursor"], 1);
+
+    let (status, error) = get_changes("?cursor=10");
+    assert_eq!(status, 400);
+    assert_eq!(error["code"], "INVALID_CURSOR");
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_push_the_events_of_the_followed_batches() {
+    let mut node = ServerBuilder::new().start();
+    let uri = format!(
+        "ws://localhost:{}/ws?batch_id=WHEAT-2024-001",
+        node.config.port
+    );
+    let (mut socket, _) = tungstenite::connect(uri).unwrap();
+    if let tungstenite::stream::MaybeTlsStream::Plain(stream) = socket.get_ref() {
+        let timeout = std::time::Duration::from_secs(5);
+        stream.set_read_timeout(Some(timeout)).unwrap();
+    }
+    let mut next_notification = || {
+        let message = socket.read().unwrap();
+        serde_json::from_str::<serde_json::Value>(message.to_text().unwrap()).unwrap()
+    };
+
+    let corn = Transaction {
+        sender: ALICE.to_string(),
+        recipient: BOB.to_string(),
+        data: r#"{"crop": "corn", "quantity": "200kg"}"#.to_string(),
+        batch_id: "CORN-2024-001".to_string(),
+        event_type: "HARVEST".to_string(),
+    };
+    let wheat = Transaction {
+        batch_id: "WHEAT-2024-001".to_string(),
+        ..corn.clone()
+    };
+    assert_eq!(node.add_transaction(&wheat).status().as_u16(), 200);
+    let pending = next_notification();
+    assert_eq!(pending["type"], "TRANSACTION_PENDING");
+    assert_eq!(pending["transaction"]["batch_id"], "WHEAT-2024-001");
+
+    node.wait_for_mining();
+    let block = next_notification();
+    assert_eq!(block["type"], "BLOCK_ADDED");
+    let mined = next_notification();
+    assert_eq!(mined["type"], "TRANSACTION_MINED");
+    assert_eq!(mined["hash"], pending["hash"]);
+    assert_eq!(mined["block"]["hash"], block["header"]["hash"]);
+
+    // only the transactions of the followed batch are pushed, but every block is
+    assert_eq!(node.add_transaction(&corn).status().as_u16(), 200);
+    let block = next_notification();
+    assert_eq!(block["type"], "BLOCK_ADDED");
+    assert_eq!(block["header"]["index"], 2);
+
+    // the client changes the batches it follows
+    let request = r#"{"action": "subscribe", "batch_id": "CORN-2024-001"}"#;
+    socket.send(tungstenite::Message::text(request)).unwrap();
+    let message = socket.read().unwrap();
+    let subscribed: serde_json::Value = serde_json::from_str(message.to_text().unwrap()).unwrap();
+    assert_eq!(subscribed["type"], "SUBSCRIBED");
+    assert_eq!(
+        subscribed["batch_ids"],
+        serde_json::json!(["CORN-2024-001", "WHEAT-2024-001"])
+    );
+
+    // plain HTTP requests are not subscriptions
+    let res = isahc::get(format!("http://localhost:{}/ws", node.config.port)).unwrap();
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_attach_the_cold_chain_compliance_of_a_batch() {
+    let mut node = ServerBuilder::new().start();
+    let address = format!("http://localhost:{}", node.config.port);
+    let event = |event_type: &str, data: &str| Transaction {
+        sender: ALICE.to_string(),
+        recipient: BOB.to_string(),
+        data: data.to_string(),
+        batch_id: "MILK-1".to_string(),
+        event_type: event_type.to_string(),
+    };
+
+    let readings = r#"{"readings": [
+        {"device_id": "TRUCK-7", "recorded_at": 1000, "temperature": 4.5},
+        {"device_id": "TRUCK-7", "recorded_at": 121000, "temperature": 9.5}
+    ]}"#;
+    let res = node.add_transaction(&event("SENSOR_READINGS", readings));
+    assert_eq!(res.status().as_u16(), 200);
+    node.wait_for_mining();
+
+    let res = isahc::get(format!("{}/batches/MILK-1/compliance", address)).unwrap();
+    assert_eq!(res.status().as_u16(), 400);
+    let url = format!("{}/batches/MILK-1/compliance?profile=dairy", address);
+    let mut res = isahc::get(&url).unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    let report_json = res.text().unwrap();
+    let report: serde_json::Value = serde_json::from_str(&report_json).unwrap();
+    assert_eq!(report["readings"], 2);
+    assert_eq!(report["compliant"], false);
+    assert_eq!(report["violations"][0]["peak"], 9.5);
+
+    // the report is attached as it is, but can't be forged
+    let mut forged = report.clone();
+    forged["compliant"] = serde_json::Value::Bool(true);
+    let res = node.add_transaction(&event("COMPLIANCE_REPORT", &forged.to_string()));
+    assert_eq!(res.status().as_u16(), 400);
+    let res = node.add_transaction(&event("COMPLIANCE_REPORT", &report_json));
+    assert_eq!(res.status().as_u16(), 200);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_normalize_the_payloads_before_signing_them() {
+    // the keystore of the farm, normalizing its payloads as it signs them
+    let keystore = std::env::temp_dir().join(format!("agriblock-farm-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&keystore);
+    let wallet = |args: &[&str]| {
+        let output = std::process::Command::new(assert_cmd::cargo::cargo_bin("agriblock"))
+            .arg("wallet")
+            .args(args)
+            .env("WALLET_PASSWORD", "harvests")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let farm = wallet(&["new", keystore.to_str().unwrap()]);
+    let mut node = ServerBuilder::new().start();
+    let address = format!("http://localhost:{}", node.config.port);
+
+    let mut res = isahc::get(format!("{}/normalization", address)).unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    let versions: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(versions["latest"], 1);
+    assert_eq!(versions["versions"][0]["transforms"][1]["name"], "units");
+
+    let harvest = Transaction {
+        sender: farm.trim().to_string(),
+        recipient: BOB.to_string(),
+        data: r#"{"crop": "corn", "qty": "1.5t", "temp": "39.2F"}"#.to_string(),
+        batch_id: "CORN-1".to_string(),
+        event_type: "HARVEST".to_string(),
+    };
+    let signed = wallet(&[
+        "sign",
+        keystore.to_str().unwrap(),
+        &serde_json::to_string(&harvest).unwrap(),
+        "--normalize",
+    ]);
+    let res = node.add_raw_transaction(&signed);
+    assert_eq!(res.status().as_u16(), 200);
+    node.wait_for_mining();
+    // the block is logged right before it's added to the chain
+    std::thread::sleep(std::time::Duration::from_millis(200));
+
+    // the version is recorded with the normalized payload
+    let mut res = node.get_transactions("event_type=HARVEST");
+    let transactions: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(transactions[0]["normalization"], 1);
+    let data: serde_json::Value =
+        serde_json::from_str(transactions[0]["data"].as_str().unwrap()).unwrap();
+    assert_eq!(
+        data,
+        serde_json::json!({"crop": "corn", "quantity_kg": 1500.0, "temperature": 4.0})
+    );
+
+    // a payload that doesn't match the version recorded with it is refused, even once signed
+    let forged = serde_json::json!({
+        "sender": farm.trim(),
+        "recipient": BOB,
+        "data": r#"{"qty": "2t"}"#,
+        "batch_id": "CORN-2",
+        "event_type": "HARVEST",
+        "normalization": 1,
+    });
+    let signed = wallet(&["sign", keystore.to_str().unwrap(), &forged.to_string()]);
+    let res = node.add_raw_transaction(&signed);
+    assert_eq!(res.status().as_u16(), 400);
+    std::fs::remove_file(keystore).unwrap();
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_keep_the_batches_of_each_namespace_apart() {
+    let mut node = ServerBuilder::new().start();
+
+    // the integration of a consortium signs its events with the namespace of their batches...
+    let harvest = Transaction {
+        sender: ALICE.to_string(),
+        recipient: BOB.to_string(),
+        data: "{}".to_string(),
+        batch_id: "acme:WHEAT-2024-001".to_string(),
+        event_type: "HARVEST".to_string(),
+    };
+    let res = node.add_namespace_transaction("acme", &harvest);
+    assert_eq!(res.status().as_u16(), 200);
+
+    // ...and cannot submit them without it, or into the batches of another one
+    let unqualified_harvest = Transaction {
+        batch_id: "WHEAT-2024-001".to_string(),
+        ..harvest.clone()
+    };
+    let res = node.add_namespace_transaction("acme", &unqualified_harvest);
+    assert_eq!(res.status().as_u16(), 400);
+    let foreign_harvest = Transaction {
+        batch_id: "globex:WHEAT-2024-001".to_string(),
+        ..harvest.clone()
+    };
+    let mut res = node.add_namespace_transaction("acme", &foreign_harvest);
+    assert_eq!(res.status().as_u16(), 400);
+    assert!(res.text().unwrap().contains("FOREIGN_NAMESPACE"));
+    let mut res = node.add_namespace_transaction("ACME", &harvest);
+    assert_eq!(res.status().as_u16(), 400);
+    assert!(res.text().unwrap().contains("INVALID_NAMESPACE"));
+
+    node.wait_for_mining();
+    // the block is logged right before it's added to the chain
+    std::thread::sleep(std::time::Duration::from_millis(200));
+
+    // the batch is only found in its namespace
+    let mut res = node.get_namespace_batch_history("acme", "WHEAT-2024-001");
+    assert_eq!(res.status().as_u16(), 200);
+    let history: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(history[0]["transaction"]["batch_id"], "acme:WHEAT-2024-001");
+    let res = node.get_namespace_batch_history("globex", "WHEAT-2024-001");
+    assert_eq!(res.status().as_u16(), 404);
+    let res = node.get_namespace_batch_history("globex", "acme:WHEAT-2024-001");
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_attach_the_attestations_of_the_regulators_to_the_blocks() {
+    // the keystore of the regulator, whose address is trusted by the node
+    let keystore =
+        std::env::temp_dir().join(format!("agriblock-regulator-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&keystore);
+    let wallet = |args: &[&str]| {
+        let output = std::process::Command::new(assert_cmd::cargo::cargo_bin("agriblock"))
+            .arg("wallet")
+            .args(args)
+            .env("WALLET_PASSWORD", "inspections")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let regulator = wallet(&["new", keystore.to_str().unwrap()]);
+    let node = ServerBuilder::new().regulator(regulator.trim()).start();
+
+    let block = node.get_last_block();
+    let attestation = wallet(&[
+        "attest-block",
+        keystore.to_str().unwrap(),
+        &format!("{:#x}", block.hash),
+        "Inspection 2024-117 of the silo, compliant",
+    ]);
+    let uri = format!(
+        "http://localhost:{}/blocks/{:#x}/attestations",
+        node.config.port, block.hash
+    );
+    let post = |body: &str| {
+        let request = isahc::Request::post(&uri)
+            .header("Content-Type", "application/json")
+            .body(body.to_string())
+            .unwrap();
+        isahc::send(request).unwrap()
+    };
+    let res = post(&attestation);
+    assert_eq!(res.status().as_u16(), 200);
+
+    // the statement cannot be altered
+    let altered = attestation.replace("compliant", "not compliant");
+    let mut res = post(&altered);
+    assert_eq!(res.status().as_u16(), 400);
+    assert!(res.text().unwrap().contains("INVALID_ATTESTATION"));
+
+    // the attestation is found with the header of the block, which didn't change
+    let mut res = isahc::get(&uri).unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    let attested: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(attested["attestations"].as_array().unwrap().len(), 1);
+    assert_eq!(attested["attestations"][0]["regulator"], regulator.trim());
+    assert_eq!(node.get_last_block(), block);
+    std::fs::remove_file(keystore).unwrap();
+}
@@ -1,3 +1,5 @@
+use std::io::Write;
+
 use ethereum_types::U256;
 use isahc::{Body, ReadResponseExt, Request, Response};
 use serde::{Deserialize, Serialize};
@@ -21,20 +23,65 @@ pub struct Transaction {
     pub sender: String,
     pub recipient: String,
     pub data: String,
+    pub batch_id: String,
+    pub event_type: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct OrphanedBlock {
+    pub block: Block,
+    pub reason: String,
+    pub fork_height: u64,
+    pub canonical_hash: BlockHash,
+    pub archived_at: i64,
 }
 
+// Every transaction of the chain is signed by its sender, so the sample actors come with their secret key
 #[allow(dead_code)]
-pub const ALICE: &str = "f780b958227ff0bf5795ede8f9f7eaac67e7e06666b043a400026cbd421ce28e";
+pub const ALICE: &str = "8a88e3dd7409f195fd52db2d3cba5d72ca6709bf1d94121bf3748801b40f6f5c";
+pub const ALICE_KEY: [u8; 32] = [1; 32];
 
 #[allow(dead_code)]
-pub const BOB: &str = "51df097c03c0a6e64e54a6fce90cb6968adebd85955917ed438e3d3c05f2f00f";
+pub const BOB: &str = "8139770ea87d175f56a35466c34c7ecccb8d8a91b4ee37a25df60f5b8fc9b394";
+pub const BOB_KEY: [u8; 32] = [2; 32];
 
+#[allow(dead_code)]
 pub trait Api {
     fn get_blocks(&self) -> Vec<Block>;
     fn get_last_block(&self) -> Block;
     fn add_block(&self, block: &Block) -> Response<Body>;
     fn add_valid_block(&self) -> Response<Body>;
     fn add_transaction(&self, transaction: &Transaction) -> Response<Body>;
+    fn add_raw_transaction(&self, body: &str) -> Response<Body>;
+    fn request_mining(&self) -> Response<Body>;
+    fn get_transactions(&self, query: &str) -> Response<Body>;
+    fn add_namespace_transaction(
+        &self,
+        namespace: &str,
+        transaction: &Transaction,
+    ) -> Response<Body>;
+    fn get_namespace_batch_history(&self, namespace: &str, batch_id: &str) -> Response<Body>;
+    fn get_peers(&self) -> Vec<String>;
+    fn get_forks(&self) -> Vec<OrphanedBlock>;
+    fn get_profile(&self, address: &str) -> Response<Body>;
+    fn provision_actor(&self, profile: &serde_json::Value) -> Response<Body>;
+    fn get_registry(&self) -> serde_json::Value;
+    fn allocate_lot(&self, request: &serde_json::Value) -> Response<Body>;
+    fn add_document(&self, request: &serde_json::Value) -> Response<Body>;
+    fn get_document(&self, hash: &str) -> Response<Body>;
+    fn get_origins(&self, query: &str, token: &str) -> Response<Body>;
+    fn get_maintenance(&self) -> serde_json::Value;
+    fn get_upgrades(&self, token: &str) -> Response<Body>;
+    fn start_maintenance(&self, request: &serde_json::Value, token: &str) -> Response<Body>;
+    fn end_maintenance(&self, token: &str) -> Response<Body>;
+    fn get_verification_progress(&self, token: &str) -> Response<Body>;
+    fn start_verification(&self, token: &str) -> Response<Body>;
+    fn save_query(&self, query: &serde_json::Value, token: &str) -> Response<Body>;
+    fn remove_query(&self, name: &str, token: &str) -> Response<Body>;
+    fn get_query_results(&self, name: &str) -> Response<Body>;
+    fn announce_maintenance(&self, announcement: &serde_json::Value) -> Response<Body>;
+    fn get_metrics(&self) -> String;
+    fn get_openapi(&self) -> serde_json::Value;
 }
 
 impl Api for Server {
@@ -59,10 +106,14 @@ impl Api for Server {
 
     fn add_valid_block(&self) -> Response<Body> {
         let last_block = self.get_last_block();
+        // a transaction is only mined once, so each block has its own
         let transaction = Transaction {
             sender: ALICE.to_string(),
             recipient: ALICE.to_string(),
-            data: r#"{"event": "system_initialization"}"#.to_string(),
+            data: format!(
+                r#"{{"event": "system_initialization", "block": {}}}"#,
+                last_block.index + 1
+            ),
             batch_id: "SYSTEM-INIT".to_string(),
             event_type: "INITIALIZATION".to_string(),
         };
@@ -81,20 +132,282 @@ impl Api for Server {
     }
 
     fn add_block(&self, block: &Block) -> Response<Body> {
-        // send the request to the REST API
+        // send the request to the REST API, with the transactions of the sample actors signed
         let uri = format!("{}/blocks", get_base_url(self));
-        let body = serde_json::to_string(&block).unwrap();
+        let mut body = serde_json::to_value(block).unwrap();
+        for transaction in body["transactions"].as_array_mut().unwrap() {
+            *transaction = serde_json::from_str(&sign_as_sender(&transaction.to_string())).unwrap();
+        }
 
-        post_request(uri, body)
+        post_request(uri, body.to_string())
     }
 
     fn add_transaction(&self, transaction: &Transaction) -> Response<Body> {
         // send the request to the REST API
         let uri = format!("{}/transactions", get_base_url(self));
-        let body = serde_json::to_string(&transaction).unwrap();
+        let body = sign_as_sender(&serde_json::to_string(&transaction).unwrap());
 
         post_request(uri, body)
     }
+
+    fn request_mining(&self) -> Response<Body> {
+        let uri = format!("{}/mine", get_base_url(self));
+
+        post_request(uri, String::new())
+    }
+
+    // for the fields that the test transactions don't have (e.g. signatures)
+    fn add_raw_transaction(&self, body: &str) -> Response<Body> {
+        let uri = format!("{}/transactions", get_base_url(self));
+
+        post_request(uri, body.to_string())
+    }
+
+    fn get_transactions(&self, query: &str) -> Response<Body> {
+        let uri = format!("{}/transactions?{}", get_base_url(self), query);
+        isahc::get(uri).unwrap()
+    }
+
+    fn add_namespace_transaction(
+        &self,
+        namespace: &str,
+        transaction: &Transaction,
+    ) -> Response<Body> {
+        let uri = format!("{}/ns/{}/transactions", get_base_url(self), namespace);
+        let body = sign_as_sender(&serde_json::to_string(&transaction).unwrap());
+
+        post_request(uri, body)
+    }
+
+    fn get_namespace_batch_history(&self, namespace: &str, batch_id: &str) -> Response<Body> {
+        let uri = format!(
+            "{}/ns/{}/batches/{}/history",
+            get_base_url(self),
+            namespace,
+            batch_id
+        );
+        isahc::get(uri).unwrap()
+    }
+
+    fn get_peers(&self) -> Vec<String> {
+        // list the peers by querying the REST API
+        let uri = format!("{}/peers", get_base_url(self));
+        let mut response = isahc::get(uri).unwrap();
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        // parse the list of peers from the response body
+        let raw_body = response.text().unwrap();
+        serde_json::from_str(&raw_body).unwrap()
+    }
+
+    fn get_forks(&self) -> Vec<OrphanedBlock> {
+        // list the orphaned blocks by querying the REST API
+        let uri = format!("{}/forks", get_base_url(self));
+        let mut response = isahc::get(uri).unwrap();
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        // parse the list of orphaned blocks from the response body
+        let raw_body = response.text().unwrap();
+        serde_json::from_str(&raw_body).unwrap()
+    }
+
+    fn get_profile(&self, address: &str) -> Response<Body> {
+        let uri = format!("{}/profiles/{}", get_base_url(self), address);
+        isahc::get(uri).unwrap()
+    }
+
+    fn provision_actor(&self, profile: &serde_json::Value) -> Response<Body> {
+        let uri = format!("{}/faucet/actors", get_base_url(self));
+        post_request(uri, profile.to_string())
+    }
+
+    fn get_registry(&self) -> serde_json::Value {
+        let uri = format!("{}/registry", get_base_url(self));
+        let mut response = isahc::get(uri).unwrap();
+        serde_json::from_str(&response.text().unwrap()).unwrap()
+    }
+
+    fn allocate_lot(&self, request: &serde_json::Value) -> Response<Body> {
+        let uri = format!("{}/lots", get_base_url(self));
+        post_request(uri, request.to_string())
+    }
+
+    fn add_document(&self, request: &serde_json::Value) -> Response<Body> {
+        let uri = format!("{}/documents", get_base_url(self));
+        post_request(uri, request.to_string())
+    }
+
+    fn get_document(&self, hash: &str) -> Response<Body> {
+        let uri = format!("{}/documents/{}", get_base_url(self), hash);
+        isahc::get(uri).unwrap()
+    }
+
+    fn get_origins(&self, query: &str, token: &str) -> Response<Body> {
+        let uri = format!("{}/admin/origins?{}", get_base_url(self), query);
+        let request = Request::get(uri)
+            .header("Authorization", format!("Bearer {}", token))
+            .body(())
+            .unwrap();
+
+        isahc::send(request).unwrap()
+    }
+
+    fn get_upgrades(&self, token: &str) -> Response<Body> {
+        let uri = format!("{}/admin/upgrades", get_base_url(self));
+        let request = Request::get(uri)
+            .header("Authorization", format!("Bearer {}", token))
+            .body(())
+            .unwrap();
+
+        isahc::send(request).unwrap()
+    }
+
+    fn get_maintenance(&self) -> serde_json::Value {
+        let uri = format!("{}/maintenance", get_base_url(self));
+        let mut response = isahc::get(uri).unwrap();
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        let raw_body = response.text().unwrap();
+        serde_json::from_str(&raw_body).unwrap()
+    }
+
+    fn start_maintenance(&self, request: &serde_json::Value, token: &str) -> Response<Body> {
+        let uri = format!("{}/admin/maintenance", get_base_url(self));
+        let request = Request::post(uri)
+            .header("Content-Type", "application/json")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(request.to_string())
+            .unwrap();
+
+        isahc::send(request).unwrap()
+    }
+
+    fn end_maintenance(&self, token: &str) -> Response<Body> {
+        let uri = format!("{}/admin/maintenance", get_base_url(self));
+        let request = Request::delete(uri)
+            .header("Authorization", format!("Bearer {}", token))
+            .body(())
+            .unwrap();
+
+        isahc::send(request).unwrap()
+    }
+
+    fn get_verification_progress(&self, token: &str) -> Response<Body> {
+        let uri = format!("{}/admin/verification", get_base_url(self));
+        let request = Request::get(uri)
+            .header("Authorization", format!("Bearer {}", token))
+            .body(())
+            .unwrap();
+
+        isahc::send(request).unwrap()
+    }
+
+    fn start_verification(&self, token: &str) -> Response<Body> {
+        let uri = format!("{}/admin/verification", get_base_url(self));
+        let request = Request::post(uri)
+            .header("Authorization", format!("Bearer {}", token))
+            .body(())
+            .unwrap();
+
+        isahc::send(request).unwrap()
+    }
+
+    fn save_query(&self, query: &serde_json::Value, token: &str) -> Response<Body> {
+        let uri = format!("{}/admin/queries", get_base_url(self));
+        let request = Request::post(uri)
+            .header("Content-Type", "application/json")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(query.to_string())
+            .unwrap();
+
+        isahc::send(request).unwrap()
+    }
+
+    fn remove_query(&self, name: &str, token: &str) -> Response<Body> {
+        let uri = format!("{}/admin/queries/{}", get_base_url(self), name);
+        let request = Request::delete(uri)
+            .header("Authorization", format!("Bearer {}", token))
+            .body(())
+            .unwrap();
+
+        isahc::send(request).unwrap()
+    }
+
+    fn get_query_results(&self, name: &str) -> Response<Body> {
+        let uri = format!("{}/queries/{}", get_base_url(self), name);
+        isahc::get(uri).unwrap()
+    }
+
+    fn announce_maintenance(&self, announcement: &serde_json::Value) -> Response<Body> {
+        let uri = format!("{}/maintenance/announcements", get_base_url(self));
+        post_request(uri, announcement.to_string())
+    }
+
+    fn get_metrics(&self) -> String {
+        let uri = format!("{}/metrics", get_base_url(self));
+        let mut response = isahc::get(uri).unwrap();
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        response.text().unwrap()
+    }
+
+    fn get_openapi(&self) -> serde_json::Value {
+        let uri = format!("{}/openapi.json", get_base_url(self));
+        let mut response = isahc::get(uri).unwrap();
+
+        // check that the response is sucessful
+        assert_eq!(response.status().as_u16(), 200);
+
+        let raw_body = response.text().unwrap();
+        serde_json::from_str(&raw_body).unwrap()
+    }
+}
+
+// The address of the actor of a secret key, its ed25519 public key in hex
+#[allow(dead_code)]
+pub fn address_of(secret_key: &[u8; 32]) -> String {
+    hex::encode(
+        ed25519_dalek::SigningKey::from_bytes(secret_key)
+            .verifying_key()
+            .to_bytes(),
+    )
+}
+
+// Signs a transaction with the sign-transaction command, like a client without an ed25519 library
+// The secret key goes through the standard input, returns the signed transaction as JSON
+#[allow(dead_code)]
+pub fn sign_transaction(secret_key: &[u8; 32], transaction: &str) -> String {
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("agriblock"))
+        .args(["sign-transaction", "-", transaction])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(hex::encode(secret_key).as_bytes()).unwrap();
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    String::from_utf8(output.stdout).unwrap()
+}
+
+// Signs a transaction of ALICE or BOB with their key, the ones of other senders are left unsigned
+fn sign_as_sender(transaction: &str) -> String {
+    let value: serde_json::Value = serde_json::from_str(transaction).unwrap();
+    match value["sender"].as_str() {
+        Some(ALICE) => sign_transaction(&ALICE_KEY, transaction),
+        Some(BOB) => sign_transaction(&BOB_KEY, transaction),
+        _ => transaction.to_string(),
+    }
 }
 
 fn get_base_url(server: &Server) -> String {
@@ -13,8 +13,11 @@ use nix::{
     unistd::Pid,
 };
 
+use super::Api;
+
 pub const MINER_ADDRESS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
 
+#[allow(dead_code)]
 pub struct Config {
     pub port: u16,
     pub peers: Vec<String>,
@@ -24,6 +27,18 @@ pub struct Config {
     pub difficulty: u32,
     pub tx_waiting_ms: u64,
     pub miner_address: String,
+    pub testnet: bool,
+    pub admin_token: String,
+    pub regulator_keys: Vec<String>,
+    pub storage_path: String,
+    pub genesis_file: String,
+    pub block_interval_ms: u64,
+    pub p2p_listen_address: String,
+    pub p2p_bootstrap: Vec<String>,
+    pub minimal: bool,
+    pub replica_of: String,
+    // faults injected by the builds with the chaos feature, as CHAOS_* variables
+    pub chaos: Vec<(String, String)>,
 }
 
 pub struct ServerBuilder {
@@ -38,14 +53,30 @@ impl ServerBuilder {
             port: 8000,
             // not to high to avoid waiting too much, not too shot to spam it
             peer_sync_ms: 10,
-            // set difficulty higher to prevent instant mining
-            difficulty: 30,
+            // no difficulty, so blocks can be added and mined instantly
+            difficulty: 0,
             // not to high to avoid waiting, not too shot to spam it
             tx_waiting_ms: 10,
             peers: Vec::<String>::new(),
             max_blocks: 0, // unlimited blocks
             max_nonce: 0,  // unlimited nonce
             miner_address: MINER_ADDRESS.to_string(),
+            testnet: false,
+            admin_token: String::new(),
+            regulator_keys: Vec::new(),
+            storage_path: String::new(),
+            // the default network
+            genesis_file: String::new(),
+            // blocks are mined as soon as there are transactions
+            block_interval_ms: 0,
+            // no gossip network
+            p2p_listen_address: String::new(),
+            p2p_bootstrap: Vec::<String>::new(),
+            minimal: false,
+            // not a read replica
+            replica_of: String::new(),
+            // no fault injected
+            chaos: Vec::new(),
         };
 
         ServerBuilder { config }
@@ -67,6 +98,64 @@ impl ServerBuilder {
         self
     }
 
+    pub fn testnet(mut self) -> ServerBuilder {
+        self.config.testnet = true;
+        self
+    }
+
+    pub fn admin_token(mut self, token: &str) -> ServerBuilder {
+        self.config.admin_token = token.to_string();
+        self
+    }
+
+    pub fn regulator(mut self, address: &str) -> ServerBuilder {
+        self.config.regulator_keys.push(address.to_string());
+        self
+    }
+
+    pub fn storage_path(mut self, path: &str) -> ServerBuilder {
+        self.config.storage_path = path.to_string();
+        self
+    }
+
+    pub fn genesis_file(mut self, path: &str) -> ServerBuilder {
+        self.config.genesis_file = path.to_string();
+        self
+    }
+
+    pub fn block_interval_ms(mut self, interval_ms: u64) -> ServerBuilder {
+        self.config.block_interval_ms = interval_ms;
+        self
+    }
+
+    pub fn gossip(mut self, port: u16) -> ServerBuilder {
+        self.config.p2p_listen_address = format!("/ip4/127.0.0.1/tcp/{}", port);
+        self
+    }
+
+    pub fn gossip_bootstrap(mut self, port: u16) -> ServerBuilder {
+        let address = format!("/ip4/127.0.0.1/tcp/{}", port);
+        self.config.p2p_bootstrap.push(address);
+        self
+    }
+
+    pub fn minimal(mut self) -> ServerBuilder {
+        self.config.minimal = true;
+        self
+    }
+
+    pub fn replica_of(mut self, port: u16) -> ServerBuilder {
+        self.config.replica_of = format!("http://localhost:{}", port);
+        self
+    }
+
+    pub fn chaos(mut self, variable: &str, value: &str) -> ServerBuilder {
+        self.config
+            .chaos
+            .push((variable.to_string(), value.to_string()));
+        self
+    }
+
     pub fn start(self) -> Server {
         Server::new(self.config)
     }
@@ -101,13 +190,41 @@ impl Server {
 
     // start the blockchain application in the background
     fn start_process(config: &Config) -> Child {
-        Command::new(cargo_bin("rust_blockchain"))
+        let args = match config.minimal {
+            true => vec!["start", "--minimal"],
+            false => vec!["start"],
+        };
+        Command::new(cargo_bin("agriblock"))
+            .args(args)
             .env("PORT", config.port.to_string())
             .env("PEERS", config.peers.join(","))
             .env("DIFFICULTY", config.difficulty.to_string())
             .env("TRANSACTION_WAITING_MS", config.tx_waiting_ms.to_string())
             .env("PEER_SYNC_MS", config.peer_sync_ms.to_string())
             .env("MINER_ADDRESS", config.miner_address.clone())
+            .env("TESTNET", config.testnet.to_string())
+            .env("ADMIN_TOKEN", config.admin_token.clone())
+            .env("REGULATOR_KEYS", config.regulator_keys.join(","))
+            .env("STORAGE_PATH", config.storage_path.clone())
+            .env("GENESIS_FILE", config.genesis_file.clone())
+            .env(
+                "BLOCK_INTERVAL_MIN_MS",
+                config.block_interval_ms.to_string(),
+            )
+            .env(
+                "BLOCK_INTERVAL_MAX_MS",
+                config.block_interval_ms.to_string(),
+            )
+            .env("P2P_LISTEN_ADDRESS", config.p2p_listen_address.clone())
+            .env("P2P_BOOTSTRAP", config.p2p_bootstrap.join(","))
+            .env("REPLICA_OF", config.replica_of.clone())
+            .env("REPLICATION_POLL_MS", "10")
+            .envs(config.chaos.iter().cloned())
+            // the nodes of other tests must not be discovered
+            .env("P2P_MDNS", "false")
+            // unavailable peers make the node panic (and recover) on every sync,
+            // printing backtraces would slow it down too much
+            .env("RUST_BACKTRACE", "0")
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()
@@ -139,8 +256,35 @@ impl Server {
     }
 
     // block the execution until we mine a new block
+    // the miner logs the block right before adding it, so we also wait for it to be in the chain
     pub fn wait_for_mining(&mut self) {
-        self.wait_for_log_message("valid block found for index");
+        let message = "valid block found for index ";
+        self.wait_for_log_message(message);
+        let index: u64 = self
+            .output
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find_map(|line| line.split(message).nth(1))
+            .and_then(|index| index.trim().parse().ok())
+            .unwrap();
+
+        self.wait_for_height(index);
+    }
+
+    // block the execution until the chain reaches a height, however the blocks get there
+    pub fn wait_for_height(&self, index: u64) {
+        let start = Instant::now();
+        while self.get_last_block().index < index {
+            assert!(
+                start.elapsed() < Duration::from_secs(20),
+                "The node on port {} did not reach the block {}",
+                self.config.port,
+                index
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
     }
 
     // block the execution until we sync a new block
@@ -148,18 +292,68 @@ impl Server {
         self.wait_for_log_message("Added new peer block");
     }
 
+    // block the execution until we refuse the chain or the blocks of a peer
+    pub fn wait_for_rejected_peer_sync(&mut self) {
+        self.wait_for_log_message("Could not sync");
+    }
+
+    // block the execution until we sync the block at an index
+    pub fn wait_for_peer_block(&mut self, index: u64) {
+        self.wait_for_log_message(&format!("Added new peer block {} ", index));
+    }
+
+    // block the execution until we fail to send our new blocks to a peer, e.g. because it's not up yet
+    pub fn wait_for_failed_block_send(&mut self) {
+        self.wait_for_log_message("Could not send block");
+    }
+
+    // block the execution until we switch to the longer fork of a peer
+    pub fn wait_for_reorganization(&mut self) {
+        self.wait_for_log_message("Reorganized the chain");
+    }
+
+    // block the execution until a read replica adds blocks of its primary
+    pub fn wait_for_replication(&mut self) {
+        self.wait_for_log_message("Replicated");
+    }
+
+    // block the execution until we discover a new peer from our peers
+    pub fn wait_for_peer_discovery(&mut self) {
+        self.wait_for_log_message("Discovered new peer");
+    }
+
+    // block the execution until a gossip peer is ready to receive our blocks
+    pub fn wait_for_gossip_peer(&mut self) {
+        self.wait_for_log_message("subscribed to the blocks");
+    }
+
+    // block the execution until we receive a new block from the gossip network
+    pub fn wait_for_gossip_block(&mut self) {
+        self.wait_for_log_message("Added new gossip block");
+    }
+
+    // block the execution until a peer tells us that it entered maintenance
+    pub fn wait_for_maintenance_announcement(&mut self) {
+        self.wait_for_log_message("is in maintenance");
+    }
+
     // block the execution until we receive a new block via api
     pub fn wait_to_receive_block_in_api(&mut self) {
         self.wait_for_log_message("Received new block");
     }
 
+    // check if a message was already printed, e.g. to know that a process didn't start
+    pub fn has_log_message(&mut self, message: &str) -> bool {
+        self.search_message_in_output(message)
+    }
+
     // block the execution until a message is contained in the process output
-    // or until a max time has passed
+    // the test fails if it doesn't appear in time, instead of going on and racing with the node
     fn wait_for_log_message(&mut self, message: &str) {
         // time interval to check for new output messages
         let wait_time = Duration::from_millis(50);
         // max time that we are going to wait for the message to appear
-        let max_wait_time = Duration::from_millis(500);
+        let max_wait_time = Duration::from_secs(20);
 
         let start = Instant::now();
         while Instant::now() < start + max_wait_time {
@@ -169,6 +363,13 @@ impl Server {
             }
             thread::sleep(wait_time);
         }
+        // the last lines of the node tell what it was doing instead
+        let output = self.output.lock().unwrap();
+        let last_lines = output[output.len().saturating_sub(20)..].join("\n");
+        panic!(
+            "The node on port {} did not log `{}` within {:?}, its last lines:\n{}",
+            self.config.port, message, max_wait_time, last_lines
+        );
     }
 
     fn search_message_in_output(&mut self, message: &str) -> bool {
@@ -1,6 +1,7 @@
 mod common;
 
-use crate::common::{Api, ServerBuilder};
+use crate::common::{Api, Block, BlockHash, Server, ServerBuilder};
+use isahc::ReadResponseExt;
 use serial_test::serial;
 
 #[test]
@@ -41,7 +42,7 @@ fn test_should_not_receive_new_invalid_blocks() {
     // This new node will keep asking for new blocks to the leader node
     // But we will require a much higher difficulty, so it should not accept blocks from the leader
     let mut follower_node = ServerBuilder::new()
-        .difficulty(1)
+        .difficulty(30)
         .port(8001)
         .peer(8000)
         .start();
@@ -50,7 +51,7 @@ fn test_should_not_receive_new_invalid_blocks() {
     leader_node.add_valid_block();
 
     // the follower node should eventually ask and receive the new block
-    follower_node.wait_for_peer_sync();
+    follower_node.wait_for_rejected_peer_sync();
 
     // but the block should not be added as the difficulty will not match
     assert_eq!(follower_node.get_blocks().len(), 1);
@@ -108,3 +109,271 @@ fn test_should_send_new_blocks() {
     let last_follower_block = leader_node.get_last_block();
     assert_eq!(last_follower_block, last_leader_block);
 }
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_sync_the_longest_chain_of_the_peers() {
+    // Two nodes that are ahead of us, one more than the other
+    let shorter_node = ServerBuilder::new().port(8000).start();
+    let longer_node = ServerBuilder::new().port(8001).start();
+    shorter_node.add_valid_block();
+    longer_node.add_valid_block();
+    longer_node.add_valid_block();
+
+    // A new node asks both of them for their headers, then downloads the blocks of the longest chain
+    let mut new_node = ServerBuilder::new()
+        .port(8002)
+        .peer(8000)
+        .peer(8001)
+        .start();
+    new_node.wait_for_peer_block(2);
+
+    assert_eq!(new_node.get_blocks().len(), 3);
+    assert_eq!(new_node.get_last_block(), longer_node.get_last_block());
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_switch_to_a_longer_fork_of_a_peer() {
+    // Our node adds a block before its peer is up...
+    let mut our_node = ServerBuilder::new().port(8000).peer(8001).start();
+    let genesis_block = our_node.get_last_block();
+    let our_block = Block {
+        index: 1,
+        timestamp: 1,
+        nonce: 0,
+        previous_hash: genesis_block.hash,
+        hash: BlockHash::default(),
+        transactions: [].to_vec(),
+    };
+    assert_eq!(our_node.add_block(&our_block).status().as_u16(), 200);
+    let rolled_back_block = our_node.get_last_block();
+    // the block is only sent once, the peer would build on it if it was up already
+    our_node.wait_for_failed_block_send();
+
+    // ...which has a longer chain from the genesis
+    let peer_node = ServerBuilder::new().port(8001).start();
+    peer_node.add_valid_block();
+    peer_node.add_valid_block();
+
+    // our block is rolled back, and the blocks of the peer applied instead
+    our_node.wait_for_reorganization();
+    assert_eq!(our_node.get_blocks().len(), 3);
+    assert_eq!(our_node.get_last_block(), peer_node.get_last_block());
+    let forks = our_node.get_forks();
+    assert_eq!(forks.len(), 1);
+    assert_eq!(forks[0].reason, "ROLLED_BACK");
+    assert_eq!(forks[0].block, rolled_back_block);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_discover_peers_from_peers() {
+    // The first node does not know anybody
+    let _first_node = ServerBuilder::new().port(8000).start();
+
+    // The second node only knows the first one
+    let _second_node = ServerBuilder::new().port(8001).peer(8000).start();
+
+    // The third node only knows the second one...
+    let mut third_node = ServerBuilder::new().port(8002).peer(8001).start();
+
+    // ...but it should eventually learn about the first node from the second one
+    third_node.wait_for_peer_discovery();
+    let peers = third_node.get_peers();
+    assert_eq!(peers.len(), 2);
+    assert!(peers.contains(&"http://localhost:8000".to_string()));
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_gossip_new_blocks() {
+    // the nodes don't sync over the API, they only find each other in the gossip network
+    let leader_node = ServerBuilder::new().port(8000).gossip(9000).start();
+    let mut follower_node = ServerBuilder::new()
+        .port(8001)
+        .gossip(9001)
+        .gossip_bootstrap(9000)
+        .start();
+    follower_node.wait_for_gossip_peer();
+
+    // a new block in the leader node is broadcast as soon as it's added
+    leader_node.add_valid_block();
+    follower_node.wait_for_gossip_block();
+
+    assert_eq!(follower_node.get_blocks().len(), 2);
+    assert_eq!(follower_node.get_last_block(), leader_node.get_last_block());
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_sync_blocks_in_minimal_mode() {
+    let leader_node = ServerBuilder::new().port(8000).start();
+
+    // a minimal node (e.g. on edge hardware) only follows the chain through the peer system
+    let mut minimal_node = ServerBuilder::new()
+        .port(8001)
+        .peer(8000)
+        .gossip(9001)
+        .minimal()
+        .start();
+    assert!(minimal_node.has_log_message("Minimal mode"));
+
+    leader_node.add_valid_block();
+    minimal_node.wait_for_peer_sync();
+    assert_eq!(minimal_node.get_last_block(), leader_node.get_last_block());
+
+    // even with a listen address, the gossip network was not started
+    assert!(!minimal_node.has_log_message("Gossip network listening"));
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_announce_the_maintenance_to_the_peers() {
+    let paused_node = ServerBuilder::new()
+        .port(8000)
+        .peer(8001)
+        .admin_token("secret")
+        .start();
+    let mut other_node = ServerBuilder::new().port(8001).peer(8000).start();
+
+    let request = serde_json::json!({"reason": "MIGRATION"});
+    let res = paused_node.start_maintenance(&request, "secret");
+    assert_eq!(res.status().as_u16(), 200);
+    other_node.wait_for_maintenance_announcement();
+    let peers = &other_node.get_maintenance()["peers"];
+    assert_eq!(peers["http://localhost:8000"]["reason"], "MIGRATION");
+
+    // the paused node follows the headers of its peers, but doesn't apply their blocks
+    other_node.add_valid_block();
+    wait_for_peer_height(&paused_node, 1);
+    assert_eq!(paused_node.get_blocks().len(), 1);
+
+    // and catches up once the maintenance ends, by syncing the block or when the peer sends it again
+    paused_node.end_maintenance("secret");
+    paused_node.wait_for_height(1);
+    assert_eq!(paused_node.get_blocks().len(), 2);
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_record_the_protocol_version_of_the_peers() {
+    let leader_node = ServerBuilder::new().port(8000).start();
+    let mut follower_node = ServerBuilder::new()
+        .port(8001)
+        .peer(8000)
+        .admin_token("secret")
+        .start();
+    // the peers are exchanged, with their versions, before the blocks are synced
+    leader_node.add_valid_block();
+    follower_node.wait_for_peer_sync();
+
+    // only the operator sees the versions
+    assert_eq!(follower_node.get_upgrades("wrong").status().as_u16(), 401);
+    let mut res = follower_node.get_upgrades("secret");
+    assert_eq!(res.status().as_u16(), 200);
+    let report: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(report["protocol_version"], 1);
+    assert_eq!(report["peers"][0]["address"], "http://localhost:8000");
+    assert_eq!(report["peers"][0]["protocol_version"], 1);
+
+    // the peers run the same version, so there is nothing to upgrade
+    assert_eq!(report["advisories"], serde_json::json!([]));
+}
+
+#[test]
+#[serial]
+#[cfg(unix)]
+fn test_should_follow_the_primary_from_a_read_replica() {
+    let path = std::env::temp_dir().join(format!("agriblock-primary-{}", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_dir_all(path);
+
+    // the replication stream of the primary is read from its storage
+    let primary = ServerBuilder::new().port(8000).storage_path(path).start();
+    let mut replica = ServerBuilder::new().port(8001).replica_of(8000).start();
+    assert!(replica.has_log_message("Read replica of http://localhost:8000"));
+
+    primary.add_valid_block();
+    replica.wait_for_replication();
+    assert_eq!(replica.get_blocks(), primary.get_blocks());
+
+    // the writes go to the primary
+    let mut res = replica.add_valid_block();
+    assert_eq!(res.status().as_u16(), 503);
+    let error: serde_json::Value = serde_json::from_str(&res.text().unwrap()).unwrap();
+    assert_eq!(error["code"], "READ_ONLY");
+    assert_eq!(error["details"]["primary"], "http://localhost:8000");
+    assert_eq!(replica.request_mining().status().as_u16(), 503);
+
+    drop(primary);
+    std::fs::remove_dir_all(path).unwrap();
+}
+
+fn wait_for_peer_height(server: &Server, height: u64) {
+    for _ in 0..50 {
+        if server.get_maintenance()["peer_height"] == height {
+            return;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+
+    panic!("The peers of the node did not reach the height {}", height);
+}
+
+#[test]
+#[serial]
+#[cfg(all(unix, feature = "chaos"))]
+fn test_should_sync_despite_the_lost_and_delayed_messages() {
+    let leader_node = ServerBuilder::new().port(8000).start();
+    let mut follower_node = ServerBuilder::new()
+        .port(8001)
+        .peer(8000)
+        .chaos("CHAOS_NETWORK_DROP_RATE", "0.5")
+        .chaos("CHAOS_NETWORK_DELAY_MS", "20")
+        .start();
+
+    for _ in 0..3 {
+        leader_node.add_valid_block();
+    }
+
+    // the lost requests are made again on the next syncs
+    let started_at = std::time::Instant::now();
+    while follower_node.get_blocks().len() < 4 && started_at.elapsed().as_secs() < 10 {
+        follower_node.wait_for_peer_block(3);
+    }
+    assert_eq!(follower_node.get_blocks(), leader_node.get_blocks());
+}
+
+#[test]
+#[serial]
+#[cfg(all(unix, feature = "chaos"))]
+fn test_should_not_receive_the_blocks_of_a_node_with_a_clock_ahead() {
+    // 10 minutes ahead, more than the drift tolerated between nodes
+    let mut leader_node = ServerBuilder::new()
+        .port(8000)
+        .chaos("CHAOS_CLOCK_SKEW_MS", "600000")
+        .start();
+    let mut follower_node = ServerBuilder::new().port(8001).peer(8000).start();
+
+    let transaction = common::Transaction {
+        sender: common::ALICE.to_string(),
+        recipient: common::BOB.to_string(),
+        data: r#"{"crop": "wheat"}"#.to_string(),
+        batch_id: "WHEAT-1".to_string(),
+        event_type: "HARVEST".to_string(),
+    };
+    leader_node.add_transaction(&transaction);
+    leader_node.wait_for_mining();
+
+    follower_node.wait_for_rejected_peer_sync();
+    assert_eq!(follower_node.get_blocks().len(), 1);
+    assert!(follower_node.has_log_message("is in the future"));
+}